@@ -1,12 +1,41 @@
 use std::path::PathBuf;
 
+const PROTO_FILE: &str = "proto/readings.proto";
+
+/// Element count of all repeated fields.
+const MAX_LEN: usize = 12;
+
+/// Capacity of the string fields, the backend has to reject longer values.
+const MAX_BYTES: &[(&str, usize)] = &[
+    (".bt.solar.StartupEvent.imei", 16),
+    (".bt.solar.StartupEvent.imsi", 16),
+    (".bt.solar.StartupEvent.iccid", 24),
+    (".bt.solar.StartupEvent.firmware_revision", 64),
+    (".bt.solar.CrashEvent.message", 96),
+    (".bt.solar.LinkQualityEvent.operator", 24),
+    (".bt.solar.CommandAuditEvent.command", 16),
+    (".bt.solar.CommandAuditEvent.parameters", 32),
+    (".bt.solar.CommandAuditEvent.result", 24),
+];
+
+/// The env vars that configure the build.
+const CONFIG_KEYS: &[&str] = &["SOLAR_BACKEND_BASE_URL", "SOLAR_BACKEND_TOKEN"];
+
+/// FNV-1a, identifies the proto file the firmware was built with.
+fn fingerprint(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+}
+
 fn main() {
     let mut generator = micropb_gen::Generator::new();
     generator.use_container_heapless();
-    generator.configure(".", micropb_gen::Config::new().max_len(12));
+    generator.configure(".", micropb_gen::Config::new().max_len(MAX_LEN as u32));
+    for (field, max_bytes) in MAX_BYTES {
+        generator.configure(field, micropb_gen::Config::new().max_bytes(*max_bytes as u32));
+    }
     // Compile example.proto into a Rust module
     generator
-        .compile_protos(&["proto/readings.proto"], std::env::var("OUT_DIR").unwrap() + "/generated_proto.rs")
+        .compile_protos(&[PROTO_FILE], std::env::var("OUT_DIR").unwrap() + "/generated_proto.rs")
         .unwrap();
     println!("cargo:rerun-if-changed=proto");
 
@@ -19,6 +48,22 @@ fn main() {
     let out_dir_path = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
     let out_file_path = out_dir_path.join("consts.rs");
 
+    let proto_fingerprint = fingerprint(&std::fs::read(PROTO_FILE).unwrap());
+    let max_bytes: String = MAX_BYTES.iter().map(|(field, max_bytes)| format!("(\"{field}\", {max_bytes}), ")).collect();
+    let config_keys: String = CONFIG_KEYS.iter().map(|key| format!("\"{key}\", ")).collect();
+    std::fs::write(
+        out_dir_path.join("schema.rs"),
+        format!(
+            "
+            // generated by build.rs
+            pub const PROTO_FINGERPRINT: u32 = {proto_fingerprint:#010x};
+            pub const MAX_LEN: usize = {MAX_LEN};
+            pub const MAX_BYTES: &[(&str, usize)] = &[{max_bytes}];
+            pub const CONFIG_KEYS: &[&str] = &[{config_keys}];"
+        ),
+    )
+    .unwrap();
+
     std::fs::write(
         out_file_path,
         format!(
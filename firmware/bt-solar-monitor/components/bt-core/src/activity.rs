@@ -0,0 +1,294 @@
+//! Coordination of the host links (USB shell, BLE) with the time critical UART work.
+//!
+//! The VE.Direct UART has no receive buffer beyond the DMA transfer of its reader, a busy
+//! shell or a burst of verbose log frames on the same executor delays the next read until
+//! the UART overruns. The coordination works with hints in [`ACTIVITY`]:
+//!
+//! - a host link holds a [`HostBusy`] while it moves data, `debug!` and `trace!` are
+//!   suppressed meanwhile, see [`Activity::verbose`],
+//! - the UART paths hold a [`UartBusy`] while a VE.Direct frame or an AT command is in
+//!   progress, the host links defer their transfers with [`Activity::wait_uart_idle`],
+//! - the board wraps its UARTs into a [`Monitored`] stream, which counts the overruns per
+//!   path and whether a host was busy at the time. The shell `status` shows the counters.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{ErrorType, Read, Write};
+
+pub static ACTIVITY: Activity = Activity::new();
+
+/// The longest a host transfer waits for the UART work in progress.
+pub const MAX_HOST_DEFERRAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UartPath {
+    /// The charger, see `sensor::ve_direct`.
+    VeDirect,
+    /// The cellular module, see `at`.
+    Modem,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UartOverruns {
+    pub ve_direct: u32,
+    pub modem: u32,
+    /// Of the VE.Direct overruns, the ones while a host link was busy.
+    pub ve_direct_host_busy: u32,
+    /// Of the modem overruns, the ones while a host link was busy.
+    pub modem_host_busy: u32,
+}
+
+impl UartOverruns {
+    pub const fn new() -> Self {
+        Self {
+            ve_direct: 0,
+            modem: 0,
+            ve_direct_host_busy: 0,
+            modem_host_busy: 0,
+        }
+    }
+}
+
+pub struct Activity {
+    hosts: Mutex<CriticalSectionRawMutex, Cell<u8>>,
+    uarts: Mutex<CriticalSectionRawMutex, Cell<u8>>,
+    uart_idle: Signal<CriticalSectionRawMutex, ()>,
+    overruns: Mutex<CriticalSectionRawMutex, Cell<UartOverruns>>,
+}
+
+impl Activity {
+    pub const fn new() -> Self {
+        Self {
+            hosts: Mutex::new(Cell::new(0)),
+            uarts: Mutex::new(Cell::new(0)),
+            uart_idle: Signal::new(),
+            overruns: Mutex::new(Cell::new(UartOverruns::new())),
+        }
+    }
+
+    /// Marks a host link busy until the guard is dropped.
+    pub fn host_busy(&self) -> HostBusy<'_> {
+        self.hosts.lock(|hosts| hosts.set(hosts.get().saturating_add(1)));
+        HostBusy { activity: self }
+    }
+
+    pub fn is_host_busy(&self) -> bool {
+        self.hosts.lock(|hosts| hosts.get() > 0)
+    }
+
+    /// Whether the verbose log frames are written, not while a host link is busy.
+    pub fn verbose(&self) -> bool {
+        !self.is_host_busy()
+    }
+
+    /// Marks a UART path busy until the guard is dropped.
+    pub fn uart_busy(&self) -> UartBusy<'_> {
+        self.uarts.lock(|uarts| uarts.set(uarts.get().saturating_add(1)));
+        UartBusy { activity: self }
+    }
+
+    pub fn is_uart_busy(&self) -> bool {
+        self.uarts.lock(|uarts| uarts.get() > 0)
+    }
+
+    /// Waits until no UART work is in progress, at most [`MAX_HOST_DEFERRAL`].
+    pub async fn wait_uart_idle(&self) {
+        let _ = with_timeout(MAX_HOST_DEFERRAL, async {
+            while self.is_uart_busy() {
+                self.uart_idle.wait().await;
+            }
+        })
+        .await;
+    }
+
+    pub fn count_overrun(&self, path: UartPath) {
+        let host_busy = self.is_host_busy() as u32;
+        self.overruns.lock(|cell| {
+            let mut overruns = cell.get();
+            match path {
+                UartPath::VeDirect => {
+                    overruns.ve_direct = overruns.ve_direct.wrapping_add(1);
+                    overruns.ve_direct_host_busy = overruns.ve_direct_host_busy.wrapping_add(host_busy);
+                }
+                UartPath::Modem => {
+                    overruns.modem = overruns.modem.wrapping_add(1);
+                    overruns.modem_host_busy = overruns.modem_host_busy.wrapping_add(host_busy);
+                }
+            }
+            cell.set(overruns);
+        });
+    }
+
+    pub fn overruns(&self) -> UartOverruns {
+        self.overruns.lock(|cell| cell.get())
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A host link moves data until dropped.
+pub struct HostBusy<'a> {
+    activity: &'a Activity,
+}
+
+impl Drop for HostBusy<'_> {
+    fn drop(&mut self) {
+        self.activity.hosts.lock(|hosts| hosts.set(hosts.get().saturating_sub(1)));
+    }
+}
+
+/// A UART path has a frame or a command in progress until dropped.
+pub struct UartBusy<'a> {
+    activity: &'a Activity,
+}
+
+impl Drop for UartBusy<'_> {
+    fn drop(&mut self) {
+        let uarts = self.activity.uarts.lock(|uarts| {
+            uarts.set(uarts.get().saturating_sub(1));
+            uarts.get()
+        });
+        if uarts == 0 {
+            self.activity.uart_idle.signal(());
+        }
+    }
+}
+
+/// A UART of the board that counts its overruns in [`ACTIVITY`].
+pub struct Monitored<S: ErrorType> {
+    stream: S,
+    path: UartPath,
+    is_overrun: fn(&S::Error) -> bool,
+    activity: &'static Activity,
+}
+
+impl<S: ErrorType> Monitored<S> {
+    /// `is_overrun` tells the overruns apart from the other read errors of the driver.
+    pub fn new(stream: S, path: UartPath, is_overrun: fn(&S::Error) -> bool) -> Self {
+        Self {
+            stream,
+            path,
+            is_overrun,
+            activity: &ACTIVITY,
+        }
+    }
+}
+
+impl<S: ErrorType> ErrorType for Monitored<S> {
+    type Error = S::Error;
+}
+
+impl<S: Read> Read for Monitored<S> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let result = self.stream.read(buf).await;
+        if result.as_ref().is_err_and(|error| (self.is_overrun)(error)) {
+            self.activity.count_overrun(self.path);
+        }
+        result
+    }
+}
+
+impl<S: Write> Write for Monitored<S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.stream.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.stream.flush().await
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use embedded_io_async::ErrorKind;
+
+    use super::*;
+
+    /// Fails every other read, like a UART that overruns.
+    struct Overrunning {
+        reads: usize,
+    }
+
+    impl ErrorType for Overrunning {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Overrunning {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.reads += 1;
+            if self.reads.is_multiple_of(2) {
+                return Err(ErrorKind::OutOfMemory);
+            }
+            buf[0] = b'V';
+            Ok(1)
+        }
+    }
+
+    fn monitored(activity: &'static Activity) -> Monitored<Overrunning> {
+        Monitored {
+            activity,
+            ..Monitored::new(Overrunning { reads: 0 }, UartPath::VeDirect, |error| *error == ErrorKind::OutOfMemory)
+        }
+    }
+
+    #[tokio::test]
+    async fn check_overruns_with_host_busy() {
+        static ACTIVITY: Activity = Activity::new();
+        let mut uart = monitored(&ACTIVITY);
+        let mut buf = [0u8; 1];
+        for _ in 0..4 {
+            let _ = uart.read(&mut buf).await;
+        }
+        {
+            let _shell = ACTIVITY.host_busy();
+            assert!(!ACTIVITY.verbose());
+            for _ in 0..2 {
+                let _ = uart.read(&mut buf).await;
+            }
+        }
+        assert!(ACTIVITY.verbose());
+        let overruns = ACTIVITY.overruns();
+        assert_eq!((overruns.ve_direct, overruns.ve_direct_host_busy), (3, 1));
+        assert_eq!((overruns.modem, overruns.modem_host_busy), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn check_host_defers_to_uart() {
+        let activity = Activity::new();
+        // idle, no wait at all
+        let started = embassy_time::Instant::now();
+        activity.wait_uart_idle().await;
+        assert!(started.elapsed() < MAX_HOST_DEFERRAL);
+
+        let frame = activity.uart_busy();
+        let (_, waited) = embassy_futures::join::join(
+            async {
+                embassy_time::Timer::after_millis(10).await;
+                drop(frame);
+            },
+            async {
+                activity.wait_uart_idle().await;
+                started.elapsed()
+            },
+        )
+        .await;
+        assert!(waited >= Duration::from_millis(10) && waited < MAX_HOST_DEFERRAL, "{:?}", waited);
+
+        // a stuck frame delays the host by the deferral at most
+        let _stuck = activity.uart_busy();
+        let started = embassy_time::Instant::now();
+        activity.wait_uart_idle().await;
+        assert!(started.elapsed() >= MAX_HOST_DEFERRAL);
+    }
+}
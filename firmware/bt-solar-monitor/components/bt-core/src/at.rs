@@ -1,29 +1,60 @@
 #![allow(async_fn_in_trait)]
 
+pub mod bridge;
+pub mod capabilities;
+pub mod dns;
+pub mod fs;
+pub mod gnss;
 pub mod http;
+pub mod identification;
 pub mod network;
+pub mod ntp;
 pub mod packet_domain;
+pub mod quectel;
+pub mod quirks;
+pub mod recorder;
 pub mod serial_interface;
+pub mod ssl;
+pub mod stats;
 pub mod status_control;
+pub mod trace;
 
-use core::mem::{MaybeUninit, replace};
-use embassy_futures::select::select;
-use embassy_sync::{
-    blocking_mutex::raw::NoopRawMutex,
-    channel::{Channel, Receiver, Sender},
-    mutex::Mutex,
+use core::{
+    cell::Cell,
+    mem::{MaybeUninit, replace},
 };
-use embassy_time::{Duration, with_timeout};
+use embassy_futures::select::{Either, Either4, select, select4};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
 use embedded_io_async::{Read, Write};
 use heapless::{CapacityError, String, Vec};
 
-use crate::{LoggingMutexGuard, debug, error, info, trace, warn};
+use crate::{
+    LoggingMutexGuard,
+    activity::ACTIVITY,
+    at::{
+        recorder::{AtRecorder, Direction},
+        stats::AtStats,
+        trace::AT_TRACE,
+    },
+    debug, error, info,
+    timeouts::Timeouts,
+    trace, warn,
+    watchdog::{FEED_INTERVAL, WatchdogHandle},
+};
 
 pub const ERROR_STRING_SIZE: usize = 64;
-const CHANNEL_SIZE: usize = 2;
+pub const MAX_AT_CLIENTS: usize = 3;
+// every client has at most an acquire and a release in flight
+const CHANNEL_SIZE: usize = 2 * MAX_AT_CLIENTS;
 const AT_BUFFER_SIZE: usize = 256;
 const MAX_RESPONSE_LINES: usize = 4;
-pub const MAX_READ_BUFFER_SIZE: usize = AT_BUFFER_SIZE * MAX_RESPONSE_LINES;
+/// A longer `AT+HTTPREAD` is split into back to back reads of this size.
+pub const HTTP_READ_CHUNK_SIZE: usize = 512;
+/// Silence on the line after which a cancelled transfer is considered drained.
+const ABORT_QUIET_TIME: Duration = Duration::from_millis(100);
+/// Silence before and after the `+++` escape sequence of the data mode.
+const ESCAPE_GUARD_TIME: Duration = Duration::from_millis(1100);
 
 #[derive(Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -32,7 +63,20 @@ pub enum AtError {
     FormatError,
     CapacityError,
     EnumParseError(String<ERROR_STRING_SIZE>),
-    ResponseLineCountMismatch { expected: usize, actual: usize },
+    ResponseLineCountMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// A low priority transfer was aborted in favour of a more urgent request.
+    Cancelled,
+    /// The runner was stopped with [`shutdown`], no more requests are served.
+    Shutdown,
+    /// Reading from or writing to the stream failed.
+    Uart,
+    /// Timed out after lines that were neither the response nor a final result.
+    UnexpectedResponse,
+    /// The module lost the network during an HTTP transfer, `+HTTP_NONET_EVENT`.
+    NoNetwork,
     Error,
 }
 
@@ -56,25 +100,38 @@ impl From<CapacityError> for AtError {
     }
 }
 
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Line {
+    Text(String<AT_BUFFER_SIZE>),
+    /// Line that is not valid UTF-8, passed on raw instead of being dropped.
+    Binary(Vec<u8, AT_BUFFER_SIZE>),
+}
+
 #[derive(Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct AtCommandRequest {
     command: String<AT_BUFFER_SIZE>,
-    timeout: Duration,
+    timeout: Option<Duration>,
     urc_prefix: Option<String<AT_BUFFER_SIZE>>,
+    priority: AtPriority,
+    /// The data goes after a `>` prompt instead of `CONNECT`.
+    write_prompt: bool,
 }
 
 impl AtCommandRequest {
     fn new(command: String<AT_BUFFER_SIZE>) -> Self {
         AtCommandRequest {
             command,
-            timeout: Duration::from_secs(5),
+            timeout: None,
             urc_prefix: None,
+            priority: AtPriority::Normal,
+            write_prompt: false,
         }
     }
 
     fn with_timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = timeout;
+        self.timeout = Some(timeout);
         self
     }
 
@@ -83,9 +140,47 @@ impl AtCommandRequest {
         self
     }
 
+    fn with_priority(mut self, priority: AtPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// [`AtCommandRequest::send_with_data`] waits for the `>` prompt of the SIMCom file writes.
+    fn with_write_prompt(mut self) -> Self {
+        self.write_prompt = true;
+        self
+    }
+
     async fn send<'ch, Ctr: AtController>(self, client: &impl AtClient<'ch, Ctr>) -> Result<AtCommandResponse, AtError> {
         debug!("AT.Req> {:?}", self);
-        let response = client.use_controller(async |ctr| ctr.handle_command(&self).await).await;
+        let response = client
+            .use_controller_with_priority(self.priority, async |ctr| ctr.handle_command(&self).await)
+            .await;
+        debug!("AT.Rsp> {:?}", response);
+        response
+    }
+
+    /// Sends the command, writes `data` after the `CONNECT` (or `>`) prompt and then waits for the final result.
+    async fn send_with_data<'ch, Ctr: AtController>(self, client: &impl AtClient<'ch, Ctr>, data: &[&[u8]]) -> Result<AtCommandResponse, AtError> {
+        debug!("AT.Req> {:?} with data", self);
+        let response = client
+            .use_controller_with_priority(self.priority, async |ctr| ctr.handle_data_write(&self, data).await)
+            .await;
+        debug!("AT.Rsp> {:?}", response);
+        response
+    }
+
+    /// Sends the command and reads `len` bytes after the `CONNECT` prompt, bytes beyond `buf` are discarded.
+    async fn send_reading_data<'ch, Ctr: AtController>(
+        self,
+        client: &impl AtClient<'ch, Ctr>,
+        len: usize,
+        buf: &mut [u8],
+    ) -> Result<(usize, AtCommandResponse), AtError> {
+        debug!("AT.Req> {:?} reading {} bytes", self, len);
+        let response = client
+            .use_controller_with_priority(self.priority, async |ctr| ctr.handle_data_read(&self, len, buf).await)
+            .await;
         debug!("AT.Rsp> {:?}", response);
         response
     }
@@ -113,12 +208,33 @@ impl AtCommandResponse {
         }
     }
 
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| line.as_str())
+    }
+
     pub fn line(&self, n: usize) -> Result<&str, AtError> {
         self.lines.get(n).map(|s| s.as_str()).ok_or(AtError::ResponseLineCountMismatch {
             expected: n + 1,
             actual: self.lines.len(),
         })
     }
+
+    /// The lines starting with `prefix`, without it, e.g. the contexts of `AT+CGDCONT?`.
+    pub fn prefixed<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.lines().filter_map(move |line| line.strip_prefix(prefix))
+    }
+
+    /// The first line starting with `prefix`, without it, e.g. `find_prefixed("+CREG: ")`.
+    ///
+    /// Echoes and URCs before the information response are skipped.
+    pub fn find_prefixed(&self, prefix: &str) -> Result<&str, AtError> {
+        self.lines().find_map(|line| line.strip_prefix(prefix)).ok_or(AtError::Error)
+    }
+
+    /// Parses the first line starting with `prefix`, for responses of a single value like `+CSCLK: 1`.
+    pub fn parse_prefixed<T: core::str::FromStr>(&self, prefix: &str) -> Result<T, AtError> {
+        self.find_prefixed(prefix)?.trim().parse().map_err(|_| AtError::Error)
+    }
 }
 
 impl Default for AtCommandResponse {
@@ -127,45 +243,94 @@ impl Default for AtCommandResponse {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+/// Order in which waiting clients get the AT controller.
+///
+/// A running [`AtPriority::Low`] transfer is cancelled as soon as a more urgent request arrives.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-enum AtRequestMessage {
-    AcquireAtController,
-    ReleaseAtController,
+pub enum AtPriority {
+    /// Long transfers that may be cancelled, e.g. reading a HTTP body.
+    Low,
+    Normal,
+    /// Checks that must not wait behind a transfer, e.g. the alive check.
+    High,
 }
 
 #[derive(Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-enum AtResponseMessage {
-    Ok,
+enum AtRequestMessage {
+    AcquireAtController { client: usize, priority: AtPriority },
+}
+
+/// Request queue of the runner and the per client grant and preempt signals.
+///
+/// The releases bypass the queue: a release comes from a `Drop` and cannot wait for a free slot.
+/// Each client counts its releases, the runner matches them with the oldest acquire of the client,
+/// granted, pending or still queued.
+struct Arbitration {
+    requests: Channel<NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>,
+    grants: [Signal<NoopRawMutex, ()>; MAX_AT_CLIENTS],
+    preempts: [Signal<NoopRawMutex, ()>; MAX_AT_CLIENTS],
+    releases: [Cell<usize>; MAX_AT_CLIENTS],
+    /// Wakes the runner after a release.
+    released: Signal<NoopRawMutex, ()>,
+    clients: Cell<usize>,
+    /// Set once the runner returned.
+    stopped: Cell<bool>,
+}
+
+impl Arbitration {
+    fn new() -> Self {
+        Self {
+            requests: Channel::new(),
+            grants: [const { Signal::new() }; MAX_AT_CLIENTS],
+            preempts: [const { Signal::new() }; MAX_AT_CLIENTS],
+            releases: [const { Cell::new(0) }; MAX_AT_CLIENTS],
+            released: Signal::new(),
+            clients: Cell::new(0),
+            stopped: Cell::new(false),
+        }
+    }
 }
 
 pub struct State<Stream: Read + Write> {
-    tx_channel: Channel<NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>,
-    rx_channel: Channel<NoopRawMutex, Result<AtResponseMessage, AtError>, CHANNEL_SIZE>,
+    arbitration: Arbitration,
     at_controller: MaybeUninit<Mutex<NoopRawMutex, AtControllerImpl<Stream>>>,
+    recording: bool,
 }
 
 impl<Stream: Read + Write> State<Stream> {
     pub fn new() -> Self {
         Self {
-            tx_channel: Channel::<NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>::new(),
-            rx_channel: Channel::<NoopRawMutex, Result<AtResponseMessage, AtError>, CHANNEL_SIZE>::new(),
+            arbitration: Arbitration::new(),
             at_controller: MaybeUninit::uninit(),
+            recording: false,
         }
     }
+
+    /// Records the AT traffic of the controller, see [`AtControllerImpl::with_recorder`].
+    pub fn with_recorder(mut self) -> Self {
+        self.recording = true;
+        self
+    }
 }
 
 pub fn new<'a, Stream: Read + Write>(
     state: &'a mut State<Stream>,
     stream: Stream,
+    timeouts: Timeouts,
 ) -> (crate::at::Runner<'a, AtControllerImpl<Stream>>, AtClientImpl<'a, AtControllerImpl<Stream>>) {
-    let at_client = Mutex::new(crate::at::AtControllerImpl::new(stream));
+    let mut at_controller = crate::at::AtControllerImpl::new(stream, timeouts);
+    if state.recording {
+        at_controller = at_controller.with_recorder();
+    }
+    let at_client = Mutex::new(at_controller);
     state.at_controller.write(at_client);
     let ctr: &Mutex<NoopRawMutex, AtControllerImpl<Stream>> = unsafe { &*state.at_controller.as_ptr() };
     let handle = AtControllerHandle { inner: ctr };
-    let runner = crate::at::Runner::new(handle, state.tx_channel.receiver(), state.rx_channel.sender());
-    let client = AtClientImpl::new(state.tx_channel.sender(), state.rx_channel.receiver(), handle);
+    let state: &'a State<Stream> = state;
+    let runner = crate::at::Runner::new(handle, &state.arbitration);
+    let client = AtClientImpl::new(&state.arbitration, handle).expect("first client");
     (runner, client)
 }
 
@@ -184,122 +349,354 @@ macro_rules! at_request {
 }
 
 pub async fn at<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<(), AtError> {
-    at_request!("AT").with_timeout(Duration::from_millis(200)).send(client).await?;
+    at_request!("AT")
+        .with_timeout(Duration::from_millis(200))
+        .with_priority(AtPriority::High)
+        .send(client)
+        .await?;
     Ok(())
 }
 
+/// Sends `command` as it is, the response lines are returned without parsing, e.g. for a remote diagnosis.
+pub async fn passthrough<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, command: &str, timeout: Duration) -> Result<AtCommandResponse, AtError> {
+    AtCommandRequest::new(command.try_into()?).with_timeout(timeout).send(client).await
+}
+
+/// Why the module did not answer [`ping`] with `OK`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PingError {
+    /// Nothing came back, the module is off, asleep or hangs.
+    NoResponse,
+    /// Something came back, but not `OK`: a wrong baud rate, a module still booting or in the data mode.
+    Garbage,
+    /// The UART itself failed.
+    Uart,
+}
+
+impl From<AtError> for PingError {
+    fn from(err: AtError) -> Self {
+        match err {
+            AtError::Timeout | AtError::Cancelled | AtError::Shutdown => PingError::NoResponse,
+            AtError::Uart => PingError::Uart,
+            _ => PingError::Garbage,
+        }
+    }
+}
+
+/// Sends `AT` like [`at`], returns the round trip time including the wait for the controller.
+pub async fn ping<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<Duration, PingError> {
+    let start = Instant::now();
+    at(client).await?;
+    Ok(start.elapsed())
+}
+
+/// Request to stop the AT runner, see [`shutdown`].
+pub struct Shutdown {
+    requested: Signal<NoopRawMutex, ()>,
+    done: Signal<NoopRawMutex, ()>,
+}
+
+impl Shutdown {
+    pub const fn new() -> Self {
+        Self {
+            requested: Signal::new(),
+            done: Signal::new(),
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stops the runner, returns once the transfer in flight finished and the runner released the controller.
+///
+/// The waiting requests and all requests afterwards fail with [`AtError::Shutdown`].
+pub async fn shutdown(shutdown: &Shutdown) {
+    shutdown.done.reset();
+    shutdown.requested.signal(());
+    shutdown.done.wait().await;
+}
+
 pub struct Runner<'ch, Ctr: AtController> {
-    receiver: Receiver<'ch, NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>,
-    sender: Sender<'ch, NoopRawMutex, Result<AtResponseMessage, AtError>, CHANNEL_SIZE>,
+    arbitration: &'ch Arbitration,
     at_controller: AtControllerHandle<'ch, Ctr>,
+    pending: Vec<(AtPriority, usize), MAX_AT_CLIENTS>,
+    owner: Option<(AtPriority, usize)>,
+    watchdog: WatchdogHandle<'ch>,
+    shutdown: Option<&'ch Shutdown>,
 }
 
 impl<'ch, Ctr: AtController> Runner<'ch, Ctr> {
-    fn new(
-        at_controller: AtControllerHandle<'ch, Ctr>,
-        receiver: Receiver<'ch, NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>,
-        sender: Sender<'ch, NoopRawMutex, Result<AtResponseMessage, AtError>, CHANNEL_SIZE>,
-    ) -> Self {
+    fn new(at_controller: AtControllerHandle<'ch, Ctr>, arbitration: &'ch Arbitration) -> Self {
         Self {
-            receiver,
-            sender,
+            arbitration,
             at_controller,
+            pending: Vec::new(),
+            owner: None,
+            watchdog: WatchdogHandle::default(),
+            shutdown: None,
         }
     }
 
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'ch>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    /// Returns on a [`shutdown`] request.
+    pub fn with_shutdown(mut self, shutdown: &'ch Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
     pub async fn run(mut self) {
-        #[derive(Debug, Eq, PartialEq)]
-        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-        enum State {
-            UrcPoll,
-            AtControllerAcquired,
+        let mut stopping = false;
+        loop {
+            self.watchdog.feed();
+            self.handle_releases();
+            if stopping && self.owner.is_none() {
+                self.stop();
+                return;
+            }
+            if !stopping
+                && self.owner.is_none()
+                && let Some((priority, client)) = self.next_pending()
+            {
+                trace!("AT runner loop: grant client {} ({:?})", client, priority);
+                self.owner = Some((priority, client));
+                self.arbitration.grants[client].signal(());
+            }
+            let shutdown = self.shutdown.filter(|_| !stopping);
+            let shutdown_requested = async move {
+                match shutdown {
+                    Some(shutdown) => shutdown.requested.wait().await,
+                    None => core::future::pending().await,
+                }
+            };
+            let request = if self.owner.is_none() {
+                let next = {
+                    let mut ctr = self.at_controller.inner("urc_poll").await;
+                    select4(self.next_request(), ctr.poll_urc(), Timer::after(FEED_INTERVAL), shutdown_requested).await
+                };
+                match next {
+                    Either4::First(Some(request)) => request,
+                    Either4::First(None) => continue,
+                    Either4::Second(urc) => {
+                        self.handle_urc(urc).await;
+                        continue;
+                    }
+                    Either4::Third(()) => continue,
+                    Either4::Fourth(()) => {
+                        stopping = true;
+                        continue;
+                    }
+                }
+            } else {
+                match select(with_timeout(FEED_INTERVAL, self.next_request()), shutdown_requested).await {
+                    Either::First(Ok(Some(request))) => request,
+                    Either::First(Ok(None) | Err(_)) => continue,
+                    Either::Second(()) => {
+                        info!("AT runner shutdown requested => waiting for client {:?}", self.owner);
+                        stopping = true;
+                        continue;
+                    }
+                }
+            };
+            trace!("AT runner loop: handle {:?}", request);
+            self.handle_request(request);
         }
+    }
 
-        let mut state = State::UrcPoll;
-        loop {
-            trace!("AT runner loop: enter {:?}", state);
-            match state {
-                State::UrcPoll => {
-                    let next = {
-                        let mut ctr = self.at_controller.inner("urc_poll").await;
-                        select(self.receiver.receive(), ctr.poll_urc()).await
-                    };
-                    trace!("AT runner loop: handle {:?}", next);
-                    match next {
-                        embassy_futures::select::Either::First(request) => match request {
-                            AtRequestMessage::AcquireAtController => {
-                                state = State::AtControllerAcquired;
-                                self.sender.send(Ok(AtResponseMessage::Ok)).await;
-                            }
-                            AtRequestMessage::ReleaseAtController => {
-                                warn!("ReleaseAtController while not acquired");
-                                self.sender.send(Ok(AtResponseMessage::Ok)).await;
-                            }
-                        },
-                        embassy_futures::select::Either::Second(urc) => self.handle_urc(urc).await,
-                    };
+    /// The next acquire, `None` after a release.
+    async fn next_request(&self) -> Option<AtRequestMessage> {
+        match select(self.arbitration.requests.receive(), self.arbitration.released.wait()).await {
+            Either::First(request) => Some(request),
+            Either::Second(()) => None,
+        }
+    }
+
+    /// Fails the waiting clients and reports the shutdown as done.
+    ///
+    /// Every client gets its grant, also one still blocked in sending its request to the full
+    /// queue: the drain makes room for it and it finds the grant once the request is sent.
+    fn stop(&mut self) {
+        self.arbitration.stopped.set(true);
+        while let Ok(request) = self.arbitration.requests.try_receive() {
+            self.handle_request(request);
+        }
+        self.handle_releases();
+        self.pending.clear();
+        for grant in &self.arbitration.grants[..self.arbitration.clients.get()] {
+            grant.signal(());
+        }
+        info!("AT runner stopped");
+        if let Some(shutdown) = self.shutdown {
+            shutdown.done.signal(());
+        }
+    }
+
+    fn handle_request(&mut self, request: AtRequestMessage) {
+        // the releases of the owner and the pending acquires come first, they are older than this acquire
+        self.handle_releases();
+        match request {
+            AtRequestMessage::AcquireAtController { client, priority } => {
+                if self.take_release(client) {
+                    // cancelled before the runner saw the acquire
+                    return;
                 }
-                State::AtControllerAcquired => {
-                    let next = self.receiver.receive().await;
-                    trace!("AT runner loop: handle {:?}", next);
-                    match next {
-                        AtRequestMessage::AcquireAtController => {
-                            warn!("AcquireAtController while already acquired");
-                            self.sender.send(Ok(AtResponseMessage::Ok)).await;
-                        }
-                        AtRequestMessage::ReleaseAtController => {
-                            state = State::UrcPoll;
-                            self.sender.send(Ok(AtResponseMessage::Ok)).await;
-                        }
-                    };
+                if let Some((owner_priority, owner)) = self.owner
+                    && owner_priority == AtPriority::Low
+                    && priority > owner_priority
+                {
+                    info!("AT client {} ({:?}) preempts low priority client {}", client, priority, owner);
+                    self.arbitration.preempts[owner].signal(());
+                }
+                if self.pending.push((priority, client)).is_err() {
+                    error!("AcquireAtController of client {} while already waiting", client);
+                }
+            }
+        }
+    }
+
+    /// Matches the releases with the owner and the pending acquires, the rest waits for the queued acquires.
+    fn handle_releases(&mut self) {
+        for client in 0..MAX_AT_CLIENTS {
+            if self.arbitration.releases[client].get() == 0 {
+                continue;
+            }
+            if self.owner.is_some_and(|(_, owner)| owner == client) && self.take_release(client) {
+                self.owner = None;
+            }
+            // cancelled while waiting for the grant
+            while let Some(index) = self.pending.iter().position(|(_, pending)| *pending == client) {
+                if !self.take_release(client) {
+                    break;
                 }
+                self.pending.remove(index);
             }
-            trace!("AT runner loop: exit");
         }
     }
 
-    async fn handle_urc(&mut self, urc: String<AT_BUFFER_SIZE>) {
-        info!("Handling URC: {}", urc.as_str());
+    fn take_release(&self, client: usize) -> bool {
+        let releases = &self.arbitration.releases[client];
+        if releases.get() == 0 {
+            return false;
+        }
+        releases.set(releases.get() - 1);
+        true
+    }
+
+    /// Highest priority first, the longest waiting first within a priority.
+    fn next_pending(&mut self) -> Option<(AtPriority, usize)> {
+        let index = (0..self.pending.len()).max_by_key(|&i| (self.pending[i].0, core::cmp::Reverse(i)))?;
+        Some(self.pending.remove(index))
+    }
+
+    async fn handle_urc(&mut self, urc: Line) {
+        match urc {
+            Line::Text(urc) => info!("Handling URC: {}", urc.as_str()),
+            Line::Binary(data) => warn!("Handling binary URC: {}", crate::fmt::Bytes(&data)),
+        }
     }
 }
 
 pub trait AtClient<'ch, Ctr: AtController> {
-    async fn use_controller<'a, F, R>(&'a self, f: F) -> R
+    async fn use_controller<'a, F, R>(&'a self, f: F) -> Result<R, AtError>
     where
-        F: AsyncFnMut(&mut Ctr) -> R + 'a,
+        F: AsyncFnMut(&mut Ctr) -> Result<R, AtError> + 'a,
+        Ctr: 'a,
+    {
+        self.use_controller_with_priority(AtPriority::Normal, f).await
+    }
+
+    async fn use_controller_with_priority<'a, F, R>(&'a self, priority: AtPriority, f: F) -> Result<R, AtError>
+    where
+        F: AsyncFnMut(&mut Ctr) -> Result<R, AtError> + 'a,
         Ctr: 'a;
 }
 
 pub struct AtClientImpl<'ch, Ctr: AtController> {
-    tx: Sender<'ch, NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>,
-    rx: Receiver<'ch, NoopRawMutex, Result<AtResponseMessage, AtError>, CHANNEL_SIZE>,
+    arbitration: &'ch Arbitration,
+    id: usize,
     at_controller: AtControllerHandle<'ch, Ctr>,
 }
 
 impl<'ch, Ctr: AtController> AtClientImpl<'ch, Ctr> {
-    fn new(
-        tx: Sender<'ch, NoopRawMutex, AtRequestMessage, CHANNEL_SIZE>,
-        rx: Receiver<'ch, NoopRawMutex, Result<AtResponseMessage, AtError>, CHANNEL_SIZE>,
-        at_controller: AtControllerHandle<'ch, Ctr>,
-    ) -> Self {
-        Self { tx, rx, at_controller }
+    fn new(arbitration: &'ch Arbitration, at_controller: AtControllerHandle<'ch, Ctr>) -> Option<Self> {
+        let id = arbitration.clients.get();
+        if id >= MAX_AT_CLIENTS {
+            return None;
+        }
+        arbitration.clients.set(id + 1);
+        Some(Self {
+            arbitration,
+            id,
+            at_controller,
+        })
+    }
+
+    /// Another client of the same module, `None` once [`MAX_AT_CLIENTS`] exist.
+    pub fn try_clone(&self) -> Option<Self> {
+        Self::new(self.arbitration, self.at_controller)
     }
 }
 
 impl<'ch, Ctr: AtController> AtClient<'ch, Ctr> for AtClientImpl<'ch, Ctr> {
-    async fn use_controller<'a, F, R>(&'a self, mut f: F) -> R
+    async fn use_controller_with_priority<'a, F, R>(&'a self, priority: AtPriority, mut f: F) -> Result<R, AtError>
     where
-        F: AsyncFnMut(&mut Ctr) -> R + 'a,
+        F: AsyncFnMut(&mut Ctr) -> Result<R, AtError> + 'a,
         Ctr: 'a,
     {
-        self.tx.send(AtRequestMessage::AcquireAtController).await;
-        let _ = self.rx.receive().await;
+        if self.arbitration.stopped.get() {
+            return Err(AtError::Shutdown);
+        }
+        let grant = &self.arbitration.grants[self.id];
+        let preempt = &self.arbitration.preempts[self.id];
+        grant.reset();
+        preempt.reset();
+        self.arbitration
+            .requests
+            .send(AtRequestMessage::AcquireAtController { client: self.id, priority })
+            .await;
+        let _release = ReleaseGuard {
+            arbitration: self.arbitration,
+            client: self.id,
+        };
+        grant.wait().await;
+        if self.arbitration.stopped.get() {
+            return Err(AtError::Shutdown);
+        }
         let mut ctr = self.at_controller.inner("at_rx").await;
-        let response = f(&mut ctr).await;
-        drop(ctr);
-        self.tx.send(AtRequestMessage::ReleaseAtController).await;
-        let _ = self.rx.receive().await;
-        response
+        if priority != AtPriority::Low {
+            return f(&mut ctr).await;
+        }
+        let result = select(f(&mut ctr), preempt.wait()).await;
+        match result {
+            Either::First(response) => response,
+            Either::Second(()) => {
+                warn!("AT client {} transfer preempted", self.id);
+                ctr.abort().await;
+                Err(AtError::Cancelled)
+            }
+        }
+    }
+}
+
+/// Releases the controller also if the client future is dropped.
+struct ReleaseGuard<'ch> {
+    arbitration: &'ch Arbitration,
+    client: usize,
+}
+
+impl Drop for ReleaseGuard<'_> {
+    fn drop(&mut self) {
+        let releases = &self.arbitration.releases[self.client];
+        releases.set(releases.get() + 1);
+        self.arbitration.released.signal(());
     }
 }
 
@@ -321,52 +718,77 @@ impl<'ch, Ctr: AtController> AtControllerHandle<'ch, Ctr> {
 
 pub trait AtController {
     async fn handle_command(&mut self, cmd: &AtCommandRequest) -> Result<AtCommandResponse, AtError>;
-    async fn handle_http_read(&mut self, buf: &mut [u8], offset: usize) -> Result<(), AtError>;
+    /// Reads the body of the last HTTP action from `offset` into `buf`, returns the number of bytes read.
+    ///
+    /// Less than `buf.len()` where the body ends early or the network is lost after some of the data.
+    async fn handle_http_read(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, AtError>;
     async fn handle_http_write(&mut self, buf: &[u8]) -> Result<(), AtError>;
-    async fn poll_urc(&mut self) -> String<AT_BUFFER_SIZE>;
+    /// Reads the response header of the last HTTP action into `buf`, returns the number of bytes stored.
+    async fn handle_http_head(&mut self, buf: &mut [u8]) -> Result<usize, AtError>;
+    async fn handle_data_write(&mut self, cmd: &AtCommandRequest, data: &[&[u8]]) -> Result<AtCommandResponse, AtError>;
+    async fn handle_data_read(&mut self, cmd: &AtCommandRequest, len: usize, buf: &mut [u8]) -> Result<(usize, AtCommandResponse), AtError>;
+    async fn poll_urc(&mut self) -> Line;
+    /// Drops the rest of a cancelled transfer so the next command starts on a clean line.
+    async fn abort(&mut self);
 }
 
 pub struct AtControllerImpl<S: Read + Write> {
     stream: S,
     line_buffer: heapless::Vec<u8, AT_BUFFER_SIZE>,
+    binary_line_count: u32,
+    timeouts: Timeouts,
+    recorder: Option<AtRecorder>,
+    stats: AtStats,
 }
 
 impl<S: Read + Write> AtController for AtControllerImpl<S> {
     async fn handle_command(&mut self, cmd: &AtCommandRequest) -> Result<AtCommandResponse, AtError> {
-        if let Err(_e) = self.stream.write_all(cmd.command.as_bytes()).await {
-            error!("Failed to send command: {}", cmd.command);
-            return Err(AtError::Error);
-        }
-        if let Err(_e) = self.stream.write_all(b"\r\n").await {
-            error!("Failed to send command: {}", cmd.command);
-            return Err(AtError::Error);
-        }
-        info!("UART.TX> {}", cmd.command);
-        let mut response = AtCommandResponse::default();
-        self.read_response_lines(cmd.command.as_str(), cmd.timeout, &mut response.lines).await?;
+        let started = Instant::now();
+        let _command = ACTIVITY.uart_busy();
+        let result = self.command(cmd).await;
+        self.dump_on_error(cmd.command.as_str(), started, result)
+    }
 
-        if let Some(prefix) = &cmd.urc_prefix {
-            self.read_line_until_urc(prefix.as_str(), cmd.timeout, &mut response.lines).await?;
-        }
-        debug!("'{}' => completed with {:?}", cmd.command, response);
-        Ok(response)
+    async fn handle_data_write(&mut self, cmd: &AtCommandRequest, data: &[&[u8]]) -> Result<AtCommandResponse, AtError> {
+        let started = Instant::now();
+        let _command = ACTIVITY.uart_busy();
+        let result = self.data_write(cmd, data).await;
+        self.dump_on_error(cmd.command.as_str(), started, result)
     }
 
-    async fn handle_http_read(&mut self, buf: &mut [u8], offset: usize) -> Result<(), AtError> {
-        self.http_read(buf, offset).await?;
-        Ok(())
+    async fn handle_data_read(&mut self, cmd: &AtCommandRequest, len: usize, buf: &mut [u8]) -> Result<(usize, AtCommandResponse), AtError> {
+        let started = Instant::now();
+        let _command = ACTIVITY.uart_busy();
+        let result = self.data_read(cmd, len, buf).await;
+        self.dump_on_error(cmd.command.as_str(), started, result)
+    }
+
+    async fn handle_http_read(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, AtError> {
+        let started = Instant::now();
+        let result = self.http_read(buf, offset).await;
+        self.dump_on_error("AT+HTTPREAD", started, result)
     }
 
     async fn handle_http_write(&mut self, buf: &[u8]) -> Result<(), AtError> {
-        self.http_write(buf).await?;
+        let started = Instant::now();
+        let result = self.http_write(buf).await;
+        self.dump_on_error("AT+HTTPDATA", started, result)?;
         Ok(())
     }
 
-    async fn poll_urc(&mut self) -> String<AT_BUFFER_SIZE> {
+    async fn handle_http_head(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        let started = Instant::now();
+        let result = self.http_head(buf).await;
+        self.dump_on_error("AT+HTTPHEAD", started, result)
+    }
+
+    async fn poll_urc(&mut self) -> Line {
         loop {
             match self.read_line().await {
                 Ok(urc_line) => {
-                    debug!("URC.RX> {}", urc_line.as_str());
+                    debug!("URC.RX> {:?}", urc_line);
+                    self.stats.urcs = self.stats.urcs.wrapping_add(1);
+                    crate::health::HEALTH.set_at_stats(self.stats);
                     return urc_line;
                 }
                 Err(_) => {
@@ -375,58 +797,430 @@ impl<S: Read + Write> AtController for AtControllerImpl<S> {
             }
         }
     }
+
+    async fn abort(&mut self) {
+        let mut discard = [0u8; 32];
+        let mut discarded = 0;
+        while let Ok(Ok(n)) = with_timeout(ABORT_QUIET_TIME, self.stream.read(&mut discard)).await {
+            discarded += n;
+        }
+        self.stats.received(discarded);
+        self.line_buffer.clear();
+        warn!("Aborted transfer => discarded {} bytes", discarded);
+    }
 }
 
 impl<S: Read + Write> AtControllerImpl<S> {
-    pub fn new(stream: S) -> Self {
+    pub fn new(stream: S, timeouts: Timeouts) -> Self {
         Self {
             stream,
             line_buffer: heapless::Vec::new(),
+            binary_line_count: 0,
+            timeouts,
+            recorder: None,
+            stats: AtStats::new(),
         }
     }
 
-    async fn http_read(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, AtError> {
-        let cmd = heapless::format!(AT_BUFFER_SIZE; "AT+HTTPREAD={},{}", offset, buf.len())?;
-        self.stream.write_all(cmd.as_bytes()).await.map_err(|_| AtError::Error)?;
-        self.stream.write_all(b"\r\n").await.map_err(|_| AtError::Error)?;
+    /// Snapshot of the traffic counters since the reset.
+    pub fn stats(&self) -> AtStats {
+        self.stats
+    }
+
+    /// Keeps the last lines sent and received, they are logged when a command fails.
+    pub fn with_recorder(mut self) -> Self {
+        self.recorder = Some(AtRecorder::new());
+        self
+    }
+
+    pub fn recorder(&self) -> Option<&AtRecorder> {
+        self.recorder.as_ref()
+    }
+
+    fn record(&mut self, direction: Direction, line: &str) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(direction, line);
+        }
+    }
+
+    /// Completes a command: publishes the stats, traces it and dumps the recorder if it failed.
+    fn dump_on_error<T>(&self, command: &str, started: Instant, result: Result<T, AtError>) -> Result<T, AtError> {
+        crate::health::HEALTH.set_at_stats(self.stats);
+        if self.recorder.is_some() {
+            AT_TRACE.record(command, started, &result);
+        }
+        if let (Err(_e), Some(recorder)) = (&result, &self.recorder) {
+            warn!("'{}' => failed with {:?} => dump AT recorder", command, _e);
+            recorder.dump();
+        }
+        result
+    }
+
+    async fn command(&mut self, cmd: &AtCommandRequest) -> Result<AtCommandResponse, AtError> {
+        self.write_command(cmd).await?;
+        let timeout = cmd.timeout.unwrap_or(self.timeouts.at_command);
+        let mut response = AtCommandResponse::default();
+        self.read_response_lines(cmd.command.as_str(), timeout, &mut response.lines).await?;
+
+        if let Some(prefix) = &cmd.urc_prefix {
+            self.read_line_until_urc(prefix.as_str(), timeout, &mut response.lines).await?;
+        }
+        debug!("'{}' => completed with {:?}", cmd.command, response);
+        Ok(response)
+    }
+
+    async fn data_write(&mut self, cmd: &AtCommandRequest, data: &[&[u8]]) -> Result<AtCommandResponse, AtError> {
+        self.write_command(cmd).await?;
+        let timeout = cmd.timeout.unwrap_or(self.timeouts.at_command);
+        let mut response = AtCommandResponse::default();
+        if cmd.write_prompt {
+            self.read_until_prompt(cmd.command.as_str(), timeout, &mut response.lines).await?;
+        } else {
+            self.read_until_connect(cmd.command.as_str(), timeout, &mut response.lines).await?;
+        }
+        for chunk in data {
+            self.write(chunk).await?;
+        }
+        self.read_response_lines(cmd.command.as_str(), timeout, &mut response.lines).await?;
+        if let Some(prefix) = &cmd.urc_prefix {
+            self.read_line_until_urc(prefix.as_str(), timeout, &mut response.lines).await?;
+        }
+        debug!("'{}' => completed with {:?}", cmd.command, response);
+        Ok(response)
+    }
+
+    async fn data_read(&mut self, cmd: &AtCommandRequest, len: usize, buf: &mut [u8]) -> Result<(usize, AtCommandResponse), AtError> {
+        self.write_command(cmd).await?;
+        let timeout = cmd.timeout.unwrap_or(self.timeouts.at_command);
+        let mut response = AtCommandResponse::default();
+        self.read_until_connect(cmd.command.as_str(), timeout, &mut response.lines).await?;
+        let stored = self.read_data(cmd.command.as_str(), len, buf, timeout).await?;
+        self.read_response_lines(cmd.command.as_str(), timeout, &mut response.lines).await?;
+        if let Some(prefix) = &cmd.urc_prefix {
+            self.read_line_until_urc(prefix.as_str(), timeout, &mut response.lines).await?;
+        }
+        debug!("'{}' => completed with {:?}", cmd.command, response);
+        Ok((stored, response))
+    }
+
+    /// Dials into the data mode with `cmd` (`ATD*99#`) and runs `session` on the raw stream, e.g. PPP.
+    ///
+    /// Escapes back to the command mode with `+++` and hangs up once the session returned.
+    pub async fn data_mode<R>(&mut self, cmd: &AtCommandRequest, session: impl AsyncFnOnce(&mut S) -> R) -> Result<R, AtError> {
+        let started = Instant::now();
+        self.write_command(cmd).await?;
+        let timeout = cmd.timeout.unwrap_or(self.timeouts.at_command);
+        let connected = with_timeout(timeout, async {
+            loop {
+                let line = self.read_text_line().await?;
+                if line.starts_with("CONNECT") {
+                    break Ok(());
+                } else if line == "ERROR" || line == "NO CARRIER" || line.starts_with("+CME ERROR") {
+                    warn!("'{}' => {}", cmd.command, line.as_str());
+                    break Err(AtError::Error);
+                }
+            }
+        })
+        .await;
+        self.dump_on_error(cmd.command.as_str(), started, connected.unwrap_or(Err(AtError::Timeout)))?;
+        info!("'{}' => data mode", cmd.command);
+        let result = session(&mut self.stream).await;
+        if let Err(e) = self.escape_data_mode().await {
+            self.abort().await;
+            return Err(e);
+        }
+        let hang_up = AtCommandRequest::new("ATH".try_into()?);
+        self.handle_command(&hang_up).await?;
+        Ok(result)
+    }
+
+    /// Hands the raw stream to `session`, see [`bridge::bridge`].
+    ///
+    /// Drops the partial line before and the unread rest after it, the next command starts on a clean line.
+    pub async fn bridge<R>(&mut self, session: impl AsyncFnOnce(&mut S) -> R) -> R {
+        self.line_buffer.clear();
+        let result = session(&mut self.stream).await;
+        self.abort().await;
+        result
+    }
+
+    async fn escape_data_mode(&mut self) -> Result<(), AtError> {
+        Timer::after(ESCAPE_GUARD_TIME).await;
+        self.write(b"+++").await?;
+        Timer::after(ESCAPE_GUARD_TIME).await;
+        self.line_buffer.clear();
+        let escaped = with_timeout(self.timeouts.at_command, async {
+            loop {
+                // the frames still in flight are no text lines
+                match self.read_line().await {
+                    Ok(Line::Text(line)) if line == "OK" || line == "NO CARRIER" => break,
+                    Ok(_) => {}
+                    Err(_) => self.line_buffer.clear(),
+                }
+            }
+        })
+        .await;
+        match escaped {
+            Ok(()) => {
+                info!("data mode => command mode");
+                Ok(())
+            }
+            Err(_) => {
+                error!("no answer to the data mode escape");
+                Err(AtError::Timeout)
+            }
+        }
+    }
+
+    /// Number of received lines that were not valid UTF-8.
+    pub fn binary_line_count(&self) -> u32 {
+        self.binary_line_count
+    }
 
+    /// Reads `buf` in chunks of [`HTTP_READ_CHUNK_SIZE`]. The read of the next chunk is sent as
+    /// soon as the data of the previous one is in, the module answers it right after the
+    /// `+HTTPREAD: 0` trailer instead of a round trip later.
+    ///
+    /// The module splits the data of a chunk into one or more `+HTTPREAD: <len>` segments and
+    /// ends it with `+HTTPREAD: 0`, a trailer before the chunk is complete is the end of the
+    /// body. A `+HTTP_NONET_EVENT` ends the read with the bytes received so far.
+    async fn http_read(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, AtError> {
+        let mut chunks = buf.chunks_mut(HTTP_READ_CHUNK_SIZE).peekable();
+        let Some(first) = chunks.peek() else {
+            return Ok(0);
+        };
+        let mut cmd = self.request_http_read(offset, first.len()).await?;
+        let mut read = 0;
         let mut lines = heapless::Vec::new();
-        self.read_response_lines(cmd.as_str(), Duration::from_secs(10), &mut lines).await?;
-        lines.clear();
-        let start_tag = heapless::format!(AT_BUFFER_SIZE; "+HTTPREAD: {}", buf.len())?;
-        self.read_line_until_urc(start_tag.as_str(), Duration::from_secs(120), &mut lines).await?;
-        self.stream.read_exact(buf).await.map_err(|_| AtError::Error)?;
-        self.read_line_until_urc("+HTTPREAD: 0", Duration::from_secs(120), &mut lines).await?;
-        Ok(buf.len())
+        while let Some(chunk) = chunks.next() {
+            lines.clear();
+            self.read_response_lines(cmd.as_str(), self.timeouts.http_command, &mut lines).await?;
+            let mut received = 0;
+            while received < chunk.len() {
+                match self.read_http_read_tag().await? {
+                    Some(0) => {
+                        warn!("'{}' => body ended after {} bytes", cmd.as_str(), received);
+                        return Ok(read + received);
+                    }
+                    Some(len) => received += self.read_data(cmd.as_str(), len, &mut chunk[received..], self.timeouts.http_read).await?,
+                    None => return self.http_read_no_network(read + received).await,
+                }
+            }
+            read += received;
+            if let Some(next) = chunks.peek() {
+                cmd = self.request_http_read(offset + read, next.len()).await?;
+            }
+            loop {
+                match self.read_http_read_tag().await? {
+                    Some(0) => break,
+                    // more than asked for, dropped
+                    Some(len) => {
+                        self.read_data(cmd.as_str(), len, &mut [], self.timeouts.http_read).await?;
+                    }
+                    None => return self.http_read_no_network(read).await,
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    /// The length of the next `+HTTPREAD: <len>` segment, `None` for a `+HTTP_NONET_EVENT`.
+    async fn read_http_read_tag(&mut self) -> Result<Option<usize>, AtError> {
+        with_timeout(self.timeouts.http_read, async {
+            loop {
+                let line = self.read_text_line().await?;
+                if let Some(len) = line.strip_prefix("+HTTPREAD: ") {
+                    break len.trim().parse().map(Some).map_err(|_| AtError::Error);
+                }
+                if line.starts_with("+HTTP_NONET_EVENT") {
+                    break Ok(None);
+                }
+                debug!("Skipping '{}' while waiting for +HTTPREAD", line.as_str());
+            }
+        })
+        .await
+        .map_err(|_| AtError::Timeout)?
+    }
+
+    /// The bytes read before the network was lost count, the pipelined read still in flight is dropped.
+    async fn http_read_no_network(&mut self, read: usize) -> Result<usize, AtError> {
+        warn!("'AT+HTTPREAD' => network lost after {} bytes", read);
+        self.abort().await;
+        if read > 0 { Ok(read) } else { Err(AtError::NoNetwork) }
+    }
+
+    async fn request_http_read(&mut self, offset: usize, len: usize) -> Result<String<AT_BUFFER_SIZE>, AtError> {
+        let cmd = heapless::format!(AT_BUFFER_SIZE; "AT+HTTPREAD={},{}", offset, len)?;
+        self.record(Direction::Tx, &cmd);
+        self.write_line(cmd.as_bytes()).await?;
+        Ok(cmd)
     }
 
     async fn http_write(&mut self, buf: &[u8]) -> Result<usize, AtError> {
         let cmd = heapless::format!(AT_BUFFER_SIZE; "AT+HTTPDATA={},{}", &buf.len(), 60)?;
-        self.stream.write_all(cmd.as_bytes()).await.map_err(|_| AtError::Error)?;
-        self.stream.write_all(b"\r\n").await.map_err(|_| AtError::Error)?;
+        self.record(Direction::Tx, &cmd);
+        self.write_line(cmd.as_bytes()).await?;
 
         let mut lines = heapless::Vec::new();
-        self.read_response_lines(cmd.as_str(), Duration::from_secs(10), &mut lines).await?;
+        self.read_response_lines(cmd.as_str(), self.timeouts.http_command, &mut lines).await?;
         lines.clear();
-        self.stream.write_all(buf).await.map_err(|_| AtError::Error)?;
-        self.read_response_lines("", Duration::from_secs(10), &mut lines).await?;
+        self.write(buf).await?;
+        self.read_response_lines("", self.timeouts.http_command, &mut lines).await?;
         Ok(buf.len())
     }
 
+    // AT+HTTPHEAD
+    // +HTTPHEAD: <data_len>
+    // <data>
+    // OK
+    async fn http_head(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        let cmd = "AT+HTTPHEAD";
+        self.record(Direction::Tx, cmd);
+        self.write_line(cmd.as_bytes()).await?;
+
+        let mut lines = heapless::Vec::new();
+        self.read_line_until_urc("+HTTPHEAD: ", self.timeouts.http_command, &mut lines).await?;
+        let tag_line = lines.last().ok_or(AtError::Error)?;
+        let len: usize = tag_line["+HTTPHEAD: ".len()..].trim().parse().map_err(|_| AtError::Error)?;
+        let stored = self.read_data(cmd, len, buf, self.timeouts.http_read).await?;
+        lines.clear();
+        self.read_response_lines(cmd, self.timeouts.http_command, &mut lines).await?;
+        Ok(stored)
+    }
+
+    /// Reads `len` raw bytes, the bytes beyond `buf` are discarded.
+    async fn read_data(&mut self, command: &str, len: usize, buf: &mut [u8], timeout: Duration) -> Result<usize, AtError> {
+        let stored = core::cmp::min(len, buf.len());
+        with_timeout(timeout, async {
+            self.stream.read_exact(&mut buf[..stored]).await.map_err(|_| AtError::Uart)?;
+            self.stats.received(stored);
+            let mut discard = [0u8; 32];
+            let mut remaining = len - stored;
+            while remaining > 0 {
+                let n = core::cmp::min(remaining, discard.len());
+                self.stream.read_exact(&mut discard[..n]).await.map_err(|_| AtError::Uart)?;
+                self.stats.received(n);
+                remaining -= n;
+            }
+            Ok::<(), AtError>(())
+        })
+        .await
+        .map_err(|_| AtError::Timeout)??;
+        if stored < len {
+            warn!("'{}' => discarded {} of {} data bytes", command, len - stored, len);
+        }
+        Ok(stored)
+    }
+
+    async fn write_command(&mut self, cmd: &AtCommandRequest) -> Result<(), AtError> {
+        self.record(Direction::Tx, cmd.command.as_str());
+        if let Err(e) = self.write_line(cmd.command.as_bytes()).await {
+            error!("Failed to send command: {}", cmd.command);
+            return Err(e);
+        }
+        info!("UART.TX> {}", cmd.command);
+        Ok(())
+    }
+
+    /// Writes a command line, counts as a command.
+    async fn write_line(&mut self, line: &[u8]) -> Result<(), AtError> {
+        self.stats.commands = self.stats.commands.wrapping_add(1);
+        self.write(line).await?;
+        self.write(b"\r\n").await
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), AtError> {
+        self.stream.write_all(data).await.map_err(|_| AtError::Uart)?;
+        self.stats.sent(data.len());
+        Ok(())
+    }
+
+    async fn read_until_connect(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        lines: &mut Vec<String<AT_BUFFER_SIZE>, MAX_RESPONSE_LINES>,
+    ) -> Result<(), AtError> {
+        match with_timeout(timeout, async {
+            loop {
+                let line = self.read_text_line().await?;
+                if line == "CONNECT" {
+                    break Ok(());
+                } else if line == "ERROR" || line.starts_with("+CME ERROR") {
+                    warn!("{} => error while waiting for data prompt", line.as_str());
+                    break Err(AtError::Error);
+                } else if line != command {
+                    lines.push(line).map_err(|_| AtError::CapacityError)?;
+                }
+            }
+        })
+        .await
+        {
+            Ok(result) => result,
+            Err(_e) => {
+                error!("'{}' => timeout waiting for CONNECT", command);
+                Err(AtError::Timeout)
+            }
+        }
+    }
+
+    /// The `>` prompt has no line end, it is only taken at the start of a line.
+    async fn read_until_prompt(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        lines: &mut Vec<String<AT_BUFFER_SIZE>, MAX_RESPONSE_LINES>,
+    ) -> Result<(), AtError> {
+        match with_timeout(timeout, async {
+            loop {
+                if self.line_buffer.is_empty() {
+                    let mut char_buf = [0u8; 1];
+                    let n = self.stream.read(&mut char_buf).await.map_err(|_| AtError::Uart)?;
+                    self.stats.received(n);
+                    match char_buf[0] {
+                        b'>' => {
+                            self.record(Direction::Rx, ">");
+                            break Ok(());
+                        }
+                        b'\r' | b'\n' => continue,
+                        c => self.line_buffer.push(c).map_err(|_| AtError::CapacityError)?,
+                    }
+                }
+                let line = self.read_text_line().await?;
+                if line == "ERROR" || line.starts_with("+CME ERROR") {
+                    warn!("{} => error while waiting for data prompt", line.as_str());
+                    break Err(AtError::Error);
+                } else if line != command {
+                    lines.push(line).map_err(|_| AtError::CapacityError)?;
+                }
+            }
+        })
+        .await
+        {
+            Ok(result) => result,
+            Err(_e) => {
+                error!("'{}' => timeout waiting for the data prompt", command);
+                Err(AtError::Timeout)
+            }
+        }
+    }
+
     async fn read_response_lines(
         &mut self,
         command: &str,
         timeout: Duration,
         lines: &mut Vec<String<AT_BUFFER_SIZE>, MAX_RESPONSE_LINES>,
     ) -> Result<(), AtError> {
+        let binary_line_count = self.binary_line_count;
         match with_timeout(timeout, async {
             loop {
-                let line = self.read_line().await?;
+                let line = self.read_text_line().await?;
                 if line == "OK" || line == "DOWNLOAD" {
                     debug!("{} => success => {} response lines", line, lines.len());
                     break Ok(());
-                } else if line == "ERROR" {
-                    warn!("ERROR => error => {} response lines", lines.len());
+                } else if line == "ERROR" || line.starts_with("+CME ERROR") {
+                    warn!("{} => error => {} response lines", line, lines.len());
+                    self.stats.errors = self.stats.errors.wrapping_add(1);
                     break Err(AtError::Error);
                 } else {
                     if line == command {
@@ -448,8 +1242,13 @@ impl<S: Read + Write> AtControllerImpl<S> {
                 error!("'{}' => error", command);
                 Err(e)
             }
+            Err(_e) if !lines.is_empty() || !self.line_buffer.is_empty() || self.binary_line_count != binary_line_count => {
+                error!("'{}' => timeout after an unexpected response", command);
+                Err(AtError::UnexpectedResponse)
+            }
             Err(_e) => {
                 error!("'{}' => timeout", command);
+                self.stats.timeouts = self.stats.timeouts.wrapping_add(1);
                 Err(AtError::Timeout)
             }
         }
@@ -463,7 +1262,7 @@ impl<S: Read + Write> AtControllerImpl<S> {
     ) -> Result<(), AtError> {
         match with_timeout(timeout, async {
             loop {
-                let line = self.read_line().await?;
+                let line = self.read_text_line().await?;
                 let prefix_match = line.starts_with(prefix);
                 lines.push(line).map_err(|_| AtError::CapacityError)?;
                 if prefix_match {
@@ -489,12 +1288,22 @@ impl<S: Read + Write> AtControllerImpl<S> {
         }
     }
 
-    async fn read_line(&mut self) -> Result<String<AT_BUFFER_SIZE>, AtError> {
+    async fn read_text_line(&mut self) -> Result<String<AT_BUFFER_SIZE>, AtError> {
+        loop {
+            match self.read_line().await? {
+                Line::Text(line) => return Ok(line),
+                Line::Binary(data) => warn!("Skipping binary line while waiting for response: {}", crate::fmt::Bytes(&data)),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<Line, AtError> {
         let mut have_cr = false;
         loop {
             let mut char_buf = [0u8; 1];
             match self.stream.read(&mut char_buf).await {
-                Ok(_) => {
+                Ok(n) => {
+                    self.stats.received(n);
                     if char_buf[0] == b'\r' {
                         have_cr = true;
                         continue;
@@ -506,20 +1315,28 @@ impl<S: Read + Write> AtControllerImpl<S> {
                         have_cr = false;
                         trace!("UART.RX line of lenght {}", self.line_buffer.len());
                         if !self.line_buffer.is_empty() {
-                            match String::from_utf8(replace(&mut self.line_buffer, heapless::Vec::new())) {
-                                Ok(line) => {
-                                    debug!("UART.RX> {}", line.as_str());
-                                    return Ok(line);
+                            let data = replace(&mut self.line_buffer, heapless::Vec::new());
+                            if core::str::from_utf8(&data).is_err() {
+                                self.binary_line_count = self.binary_line_count.wrapping_add(1);
+                                error!("Invalid UTF-8 sequence in line of {} bytes (count {})", data.len(), self.binary_line_count);
+                                if let Ok(note) = heapless::format!(32; "<{} binary bytes>", data.len()) {
+                                    self.record(Direction::Rx, note.as_str());
                                 }
-                                Err(_) => error!("Invalid UTF-8 sequence"),
+                                return Ok(Line::Binary(data));
                             }
-                            self.line_buffer.clear();
+                            let line = String::from_utf8(data).map_err(|_| AtError::Error)?;
+                            debug!("UART.RX> {}", line.as_str());
+                            self.record(Direction::Rx, line.as_str());
+                            return Ok(Line::Text(line));
                         }
                     } else {
                         self.line_buffer.push(char_buf[0]).map_err(|_| AtError::CapacityError)?;
                     }
                 }
-                Err(_e) => warn!("Read error"),
+                Err(_e) => {
+                    warn!("Read error");
+                    return Err(AtError::Uart);
+                }
             };
         }
     }
@@ -543,18 +1360,31 @@ pub mod mocks {
             let request = self.request.downcast_ref::<AtCommandRequest>().unwrap();
             assert_eq!(cmd, request);
 
-            let response = self.response.take().unwrap().downcast::<AtCommandResponse>().map(|r| *r).unwrap();
-            Ok(response)
+            let response = self.response.take().unwrap();
+            match response.downcast::<AtError>() {
+                Ok(error) => Err(*error),
+                Err(response) => Ok(response.downcast::<AtCommandResponse>().map(|r| *r).unwrap()),
+            }
         }
-        async fn handle_http_read(&mut self, _buf: &mut [u8], _offset: usize) -> Result<(), AtError> {
+        async fn handle_http_read(&mut self, _buf: &mut [u8], _offset: usize) -> Result<usize, AtError> {
             Err(AtError::Error)
         }
         async fn handle_http_write(&mut self, _buf: &[u8]) -> Result<(), AtError> {
             Err(AtError::Error)
         }
-        async fn poll_urc(&mut self) -> String<AT_BUFFER_SIZE> {
-            String::new()
+        async fn handle_http_head(&mut self, _buf: &mut [u8]) -> Result<usize, AtError> {
+            Err(AtError::Error)
         }
+        async fn handle_data_write(&mut self, _cmd: &AtCommandRequest, _data: &[&[u8]]) -> Result<AtCommandResponse, AtError> {
+            Err(AtError::Error)
+        }
+        async fn handle_data_read(&mut self, _cmd: &AtCommandRequest, _len: usize, _buf: &mut [u8]) -> Result<(usize, AtCommandResponse), AtError> {
+            Err(AtError::Error)
+        }
+        async fn poll_urc(&mut self) -> Line {
+            Line::Text(String::new())
+        }
+        async fn abort(&mut self) {}
     }
 
     pub struct AtClientMock {
@@ -573,9 +1403,9 @@ pub mod mocks {
     }
 
     impl<'ch> AtClient<'ch, AtControllerMock> for AtClientMock {
-        async fn use_controller<'a, F, R>(&'a self, mut f: F) -> R
+        async fn use_controller_with_priority<'a, F, R>(&'a self, _priority: AtPriority, mut f: F) -> Result<R, AtError>
         where
-            F: AsyncFnMut(&mut AtControllerMock) -> R + 'a,
+            F: AsyncFnMut(&mut AtControllerMock) -> Result<R, AtError> + 'a,
             AtControllerMock: 'a,
         {
             let mut ctr = self.controller.lock().await;
@@ -584,11 +1414,553 @@ pub mod mocks {
     }
 
     pub fn mock_request(command: &str, response_lines: &[&str]) -> AtClientMock {
+        mock_response(AtCommandRequest::new(command.try_into().unwrap()), response_lines)
+    }
+
+    pub fn mock_response(request: AtCommandRequest, response_lines: &[&str]) -> AtClientMock {
         let mut lines = heapless::Vec::<heapless::String<AT_BUFFER_SIZE>, MAX_RESPONSE_LINES>::new();
         for line in response_lines {
             lines.push(heapless::String::<AT_BUFFER_SIZE>::try_from(*line).unwrap()).unwrap();
         }
 
-        AtClientMock::new(Box::new(AtCommandRequest::new(command.try_into().unwrap())), Box::new(AtCommandResponse::new(lines)))
+        AtClientMock::new(Box::new(request), Box::new(AtCommandResponse::new(lines)))
+    }
+
+    pub fn mock_error(command: &str, error: AtError) -> AtClientMock {
+        AtClientMock::new(Box::new(AtCommandRequest::new(command.try_into().unwrap())), Box::new(error))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::vec::Vec as StdVec;
+
+    struct ScriptStream {
+        input: StdVec<u8>,
+        pos: usize,
+        output: StdVec<u8>,
+        /// The input position at every write.
+        write_positions: StdVec<usize>,
+    }
+
+    impl ScriptStream {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: input.to_vec(),
+                pos: 0,
+                output: StdVec::new(),
+                write_positions: StdVec::new(),
+            }
+        }
+    }
+
+    impl embedded_io_async::ErrorType for ScriptStream {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for ScriptStream {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.pos >= self.input.len() {
+                core::future::pending::<()>().await;
+            }
+            let n = core::cmp::min(buf.len(), self.input.len() - self.pos);
+            buf[..n].copy_from_slice(&self.input[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for ScriptStream {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output.extend_from_slice(buf);
+            self.write_positions.push(self.pos);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_response_prefixed_lines() -> Result<(), AtError> {
+        let response = AtCommandResponse::new(Vec::from_iter(
+            ["+CGDCONT: 1,\"IP\",\"gprs.swisscom.ch\"", "+CGDCONT: 2,\"IPV6\",\"ims\"", "+CSCLK: 1"].map(|line| line.try_into().unwrap()),
+        ));
+        let contexts: StdVec<&str> = response.prefixed("+CGDCONT: ").collect();
+        assert_eq!(contexts, ["1,\"IP\",\"gprs.swisscom.ch\"", "2,\"IPV6\",\"ims\""]);
+        assert_eq!(response.find_prefixed("+CGDCONT: ")?, "1,\"IP\",\"gprs.swisscom.ch\"");
+        assert_eq!(response.find_prefixed("+CREG: "), Err(AtError::Error));
+        assert_eq!(response.parse_prefixed::<u32>("+CSCLK: ")?, 1);
+        assert_eq!(response.parse_prefixed::<u32>("+CGDCONT: "), Err(AtError::Error));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_binary_line_is_returned() {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"+URC: 1\r\n\xff\xfe\x01\r\n"), Timeouts::default());
+        assert_eq!(ctr.poll_urc().await, Line::Text("+URC: 1".try_into().unwrap()));
+        assert_eq!(ctr.poll_urc().await, Line::Binary(Vec::from_slice(&[0xff, 0xfe, 0x01]).unwrap()));
+        assert_eq!(ctr.binary_line_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_binary_line_skipped_in_response() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"AT+CSQ\r\n\xc3\x28\r\n+CSQ: 20,99\r\nOK\r\n"), Timeouts::default());
+        let response = ctr.handle_command(&AtCommandRequest::new("AT+CSQ".try_into()?)).await?;
+        assert_eq!(response.line(0)?, "+CSQ: 20,99");
+        response.ensure_lines(1)?;
+        assert_eq!(ctr.binary_line_count(), 1);
+        assert_eq!(ctr.stream.output.as_slice(), b"AT+CSQ\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stats() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"AT+CSQ\r\n+CSQ: 20,99\r\nOK\r\nERROR\r\n+CPIN: READY\r\n"), Timeouts::default());
+        ctr.handle_command(&AtCommandRequest::new("AT+CSQ".try_into()?)).await?;
+        assert_eq!(ctr.handle_command(&AtCommandRequest::new("AT+CFUN=1".try_into()?)).await, Err(AtError::Error));
+        ctr.poll_urc().await;
+        let timeout = AtCommandRequest::new("AT".try_into()?).with_timeout(Duration::from_millis(50));
+        assert_eq!(ctr.handle_command(&timeout).await, Err(AtError::Timeout));
+        assert_eq!(
+            ctr.stats(),
+            AtStats {
+                commands: 3,
+                timeouts: 1,
+                errors: 1,
+                urcs: 1,
+                bytes_rx: 46,
+                bytes_tx: 23,
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_response() -> Result<(), AtError> {
+        let request = AtCommandRequest::new("AT".try_into()?).with_timeout(Duration::from_millis(50));
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"\x00\xf8\xf8\r\n"), Timeouts::default());
+        assert_eq!(ctr.handle_command(&request).await, Err(AtError::UnexpectedResponse));
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"RDY"), Timeouts::default());
+        assert_eq!(ctr.handle_command(&request).await, Err(AtError::UnexpectedResponse));
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b""), Timeouts::default());
+        assert_eq!(ctr.handle_command(&request).await, Err(AtError::Timeout));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let request = || {
+            AtCommandRequest::new("AT".try_into().unwrap())
+                .with_timeout(Duration::from_millis(200))
+                .with_priority(AtPriority::High)
+        };
+        assert!(ping(&mocks::mock_response(request(), &[])).await.is_ok());
+        let failure = |error: AtError| async { ping(&mocks::AtClientMock::new(std::boxed::Box::new(request()), std::boxed::Box::new(error))).await };
+        assert_eq!(failure(AtError::Timeout).await, Err(PingError::NoResponse));
+        assert_eq!(failure(AtError::UnexpectedResponse).await, Err(PingError::Garbage));
+        assert_eq!(failure(AtError::Error).await, Err(PingError::Garbage));
+        assert_eq!(failure(AtError::Uart).await, Err(PingError::Uart));
+    }
+
+    #[tokio::test]
+    async fn test_data_mode() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(
+            ScriptStream::new(b"ATD*99#\r\r\nCONNECT 150000000\r\n~\xff\x7d\x23~~\xff\x03\r\nOK\r\nATH\r\r\nOK\r\n"),
+            Timeouts::default(),
+        );
+        let frame = ctr
+            .data_mode(&AtCommandRequest::new("ATD*99#".try_into()?), async |stream: &mut ScriptStream| {
+                let mut frame = [0u8; 5];
+                stream.read_exact(&mut frame).await.unwrap();
+                stream.write_all(b"~\x01~").await.unwrap();
+                frame
+            })
+            .await?;
+        assert_eq!(&frame, b"~\xff\x7d\x23~");
+        assert_eq!(ctr.stream.output.as_slice(), b"ATD*99#\r\n~\x01~+++ATH\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_data_mode_no_carrier() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"ATD*99#\r\r\nNO CARRIER\r\n"), Timeouts::default());
+        let result = ctr
+            .data_mode(&AtCommandRequest::new("ATD*99#".try_into()?), async |_: &mut ScriptStream| ())
+            .await;
+        assert_eq!(result, Err(AtError::Error));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_data_write() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"CONNECT\r\n\r\nOK\r\n"), Timeouts::default());
+        let response = ctr
+            .handle_data_write(&AtCommandRequest::new("AT+QHTTPURL=10,80".try_into()?), &[b"http://", b"abc"])
+            .await?;
+        response.ensure_lines(0)?;
+        assert_eq!(ctr.stream.output.as_slice(), b"AT+QHTTPURL=10,80\r\nhttp://abc");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_data_read_discards_overflow() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"CONNECT\r\nhello world\r\nOK\r\n\r\n+QHTTPREAD: 0\r\n"), Timeouts::default());
+        let request = AtCommandRequest::new("AT+QHTTPREAD=80".try_into()?).with_urc_prefix("+QHTTPREAD: ".try_into()?);
+        let mut buf = [0u8; 5];
+        let (n, response) = ctr.handle_data_read(&request, 11, &mut buf).await?;
+        assert_eq!(n, 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(response.line(0)?, "+QHTTPREAD: 0");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_head() -> Result<(), AtError> {
+        const HEADER: &[u8] = b"HTTP/1.1 200 OK\r\nETag: \"42\"\r\n\r\n";
+        let mut script = StdVec::new();
+        script.extend_from_slice(format!("AT+HTTPHEAD\r\n+HTTPHEAD: {}\r\n", HEADER.len()).as_bytes());
+        script.extend_from_slice(HEADER);
+        script.extend_from_slice(b"\r\nOK\r\n");
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(&script), Timeouts::default());
+        let mut buf = [0u8; 64];
+        let n = ctr.handle_http_head(&mut buf).await?;
+        assert_eq!(&buf[..n], HEADER);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_read_pipelined() -> Result<(), AtError> {
+        let data: StdVec<u8> = (0..HTTP_READ_CHUNK_SIZE + 100).map(|i| i as u8).collect();
+        let mut script = StdVec::new();
+        script.extend_from_slice(format!("AT+HTTPREAD=0,{0}\r\r\nOK\r\n\r\n+HTTPREAD: {0}\r\n", HTTP_READ_CHUNK_SIZE).as_bytes());
+        script.extend_from_slice(&data[..HTTP_READ_CHUNK_SIZE]);
+        let first_data_end = script.len();
+        script.extend_from_slice(format!("\r\n+HTTPREAD: 0\r\nAT+HTTPREAD={},100\r\r\nOK\r\n\r\n+HTTPREAD: 100\r\n", HTTP_READ_CHUNK_SIZE).as_bytes());
+        script.extend_from_slice(&data[HTTP_READ_CHUNK_SIZE..]);
+        script.extend_from_slice(b"\r\n+HTTPREAD: 0\r\n");
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(&script), Timeouts::default());
+        let mut buf = std::vec![0u8; data.len()];
+        assert_eq!(ctr.handle_http_read(&mut buf, 0).await, Ok(data.len()));
+        assert_eq!(buf, data);
+        assert_eq!(ctr.stream.pos, script.len());
+        assert_eq!(ctr.stream.output, format!("AT+HTTPREAD=0,{0}\r\nAT+HTTPREAD={0},100\r\n", HTTP_READ_CHUNK_SIZE).as_bytes());
+        // the second read went out right after the data, before the trailer of the first
+        assert_eq!(ctr.stream.write_positions[2], first_data_end);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_read_segments() -> Result<(), AtError> {
+        // one read answered in two segments
+        let script = b"AT+HTTPREAD=0,10\r\r\nOK\r\n\r\n+HTTPREAD: 4\r\n0123\r\n+HTTPREAD: 6\r\n456789\r\n+HTTPREAD: 0\r\n";
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(script), Timeouts::default());
+        let mut buf = [0u8; 10];
+        assert_eq!(ctr.handle_http_read(&mut buf, 0).await, Ok(10));
+        assert_eq!(&buf, b"0123456789");
+        assert_eq!(ctr.stream.pos, script.len());
+
+        // the body ends before the requested length
+        let script = b"AT+HTTPREAD=0,10\r\r\nOK\r\n\r\n+HTTPREAD: 4\r\n0123\r\n+HTTPREAD: 0\r\n";
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(script), Timeouts::default());
+        let mut buf = [0u8; 10];
+        assert_eq!(ctr.handle_http_read(&mut buf, 0).await, Ok(4));
+        assert_eq!(&buf[..4], b"0123");
+        assert_eq!(ctr.stream.pos, script.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_read_no_network() -> Result<(), AtError> {
+        let script = b"AT+HTTPREAD=0,10\r\r\nOK\r\n\r\n+HTTPREAD: 4\r\n0123\r\n+HTTP_NONET_EVENT\r\n";
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(script), Timeouts::default());
+        let mut buf = [0u8; 10];
+        assert_eq!(ctr.handle_http_read(&mut buf, 0).await, Ok(4));
+
+        let script = b"AT+HTTPREAD=0,10\r\r\nOK\r\n\r\n+HTTP_NONET_EVENT\r\n";
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(script), Timeouts::default());
+        assert_eq!(ctr.handle_http_read(&mut buf, 0).await, Err(AtError::NoNetwork));
+        Ok(())
+    }
+
+    /// One exchange of a [`Transcript`].
+    enum Step {
+        /// A command with an optional URC prefix to wait for and the expected response lines.
+        Command(&'static str, Option<&'static str>, &'static [&'static str]),
+        /// An `AT+HTTPDATA` upload of the data.
+        HttpWrite(&'static [u8]),
+        /// An `AT+HTTPREAD` of the expected data from offset 0.
+        HttpRead(&'static [u8]),
+        /// An unsolicited line while idle.
+        Urc(&'static str),
+    }
+
+    /// Bytes received from the module in one flow together with the exchanges that consume them.
+    struct Transcript {
+        name: &'static str,
+        rx: &'static [u8],
+        steps: &'static [Step],
+    }
+
+    // Modelled after A7670E sessions with echo enabled, note the `\r\r\n` after the echo.
+    const TRANSCRIPTS: &[Transcript] = &[
+        Transcript {
+            name: "power on",
+            rx: b"\r\nRDY\r\n\r\n*ATREADY: 1\r\n\r\n+CPIN: READY\r\n\r\nSMS DONE\r\n\r\nPB DONE\r\nAT\r\r\nOK\r\nAT+CTZU=1\r\r\nOK\r\nAT+CGMR\r\r\n+CGMR: A131B01A7670M7\r\n\r\nOK\r\n",
+            steps: &[
+                Step::Urc("RDY"),
+                Step::Urc("*ATREADY: 1"),
+                Step::Urc("+CPIN: READY"),
+                Step::Urc("SMS DONE"),
+                Step::Urc("PB DONE"),
+                Step::Command("AT", None, &[]),
+                Step::Command("AT+CTZU=1", None, &[]),
+                Step::Command("AT+CGMR", None, &["+CGMR: A131B01A7670M7"]),
+            ],
+        },
+        Transcript {
+            name: "registration",
+            rx: b"AT+CGDCONT=1,\"IP\",\"gprs.swisscom.ch\"\r\r\nOK\r\nAT+CEREG?\r\r\n+CEREG: 0,2\r\n\r\nOK\r\n\r\n+CGEV: EPS PDN ACT 1\r\nAT+CEREG?\r\r\n+CEREG: 0,1\r\n\r\nOK\r\nAT+CCLK?\r\r\n+CCLK: \"25/12/30,15:22:22+04\"\r\n\r\nOK\r\n",
+            steps: &[
+                Step::Command("AT+CGDCONT=1,\"IP\",\"gprs.swisscom.ch\"", None, &[]),
+                Step::Command("AT+CEREG?", None, &["+CEREG: 0,2"]),
+                Step::Urc("+CGEV: EPS PDN ACT 1"),
+                Step::Command("AT+CEREG?", None, &["+CEREG: 0,1"]),
+                Step::Command("AT+CCLK?", None, &["+CCLK: \"25/12/30,15:22:22+04\""]),
+            ],
+        },
+        Transcript {
+            name: "urc interleaved with response",
+            rx: b"AT+CSQ\r\r\n\r\n+CGEV: NW MODIFY 1,4\r\n+CSQ: 21,99\r\n\r\nOK\r\n",
+            steps: &[Step::Command("AT+CSQ", None, &["+CGEV: NW MODIFY 1,4", "+CSQ: 21,99"])],
+        },
+        Transcript {
+            name: "http get",
+            rx: b"AT+HTTPINIT\r\r\nOK\r\nAT+HTTPPARA=\"URL\",\"http://example.com/a\"\r\r\nOK\r\nAT+HTTPACTION=0\r\r\nOK\r\n\r\n+HTTPACTION: 0,200,5\r\nAT+HTTPREAD=0,5\r\r\nOK\r\n\r\n+HTTPREAD: 5\r\nhello\r\n+HTTPREAD: 0\r\n",
+            steps: &[
+                Step::Command("AT+HTTPINIT", None, &[]),
+                Step::Command("AT+HTTPPARA=\"URL\",\"http://example.com/a\"", None, &[]),
+                Step::Command("AT+HTTPACTION=0", Some("+HTTPACTION: "), &["+HTTPACTION: 0,200,5"]),
+                Step::HttpRead(b"hello"),
+            ],
+        },
+        Transcript {
+            name: "http post",
+            rx: b"AT+HTTPDATA=4,60\r\r\nDOWNLOAD\r\n\r\nOK\r\nAT+HTTPACTION=1\r\r\nOK\r\n\r\n+HTTPACTION: 1,201,0\r\n",
+            steps: &[
+                Step::HttpWrite(b"\x08\x01\x10\x02"),
+                Step::Command("AT+HTTPACTION=1", Some("+HTTPACTION: "), &["+HTTPACTION: 1,201,0"]),
+            ],
+        },
+        Transcript {
+            name: "sleep",
+            rx: b"AT+HTTPTERM\r\r\nOK\r\nAT+CSCLK=1\r\r\nOK\r\nAT+CSCLK?\r\r\n+CSCLK: 1\r\n\r\nOK\r\n",
+            steps: &[
+                Step::Command("AT+HTTPTERM", None, &[]),
+                Step::Command("AT+CSCLK=1", None, &[]),
+                Step::Command("AT+CSCLK?", None, &["+CSCLK: 1"]),
+            ],
+        },
+    ];
+
+    async fn replay(transcript: &Transcript) -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(transcript.rx), Timeouts::default());
+        let mut tx = StdVec::new();
+        for step in transcript.steps {
+            match step {
+                Step::Command(command, urc_prefix, lines) => {
+                    let mut request = AtCommandRequest::new((*command).try_into()?);
+                    if let Some(prefix) = urc_prefix {
+                        request = request.with_urc_prefix((*prefix).try_into()?);
+                    }
+                    let response = ctr.handle_command(&request).await?;
+                    assert_eq!(response.lines, *lines, "{}: {}", transcript.name, command);
+                    tx.extend_from_slice(format!("{}\r\n", command).as_bytes());
+                }
+                Step::HttpWrite(data) => {
+                    ctr.handle_http_write(data).await?;
+                    tx.extend_from_slice(format!("AT+HTTPDATA={},60\r\n", data.len()).as_bytes());
+                    tx.extend_from_slice(data);
+                }
+                Step::HttpRead(data) => {
+                    let mut buf = std::vec![0u8; data.len()];
+                    assert_eq!(ctr.handle_http_read(&mut buf, 0).await?, data.len(), "{}: http read", transcript.name);
+                    assert_eq!(buf, *data, "{}: http read", transcript.name);
+                    tx.extend_from_slice(format!("AT+HTTPREAD=0,{}\r\n", data.len()).as_bytes());
+                }
+                Step::Urc(urc) => {
+                    assert_eq!(ctr.poll_urc().await, Line::Text((*urc).try_into()?), "{}", transcript.name);
+                }
+            }
+        }
+        assert_eq!(ctr.stream.pos, transcript.rx.len(), "{}: unconsumed input", transcript.name);
+        assert_eq!(ctr.stream.output, tx, "{}: sent bytes", transcript.name);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transcripts() -> Result<(), AtError> {
+        for transcript in TRANSCRIPTS {
+            replay(transcript).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transcript_error() -> Result<(), AtError> {
+        let mut ctr = AtControllerImpl::new(ScriptStream::new(b"AT+HTTPACTION=0\r\r\nERROR\r\n"), Timeouts::default());
+        let request = AtCommandRequest::new("AT+HTTPACTION=0".try_into()?).with_urc_prefix("+HTTPACTION: ".try_into()?);
+        assert_eq!(ctr.handle_command(&request).await, Err(AtError::Error));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recorder_keeps_failed_transaction() -> Result<(), AtError> {
+        let mut ctr =
+            AtControllerImpl::new(ScriptStream::new(b"AT+CSQ\r\r\n+CSQ: 20,99\r\nOK\r\nAT+CREG?\r\r\nERROR\r\n"), Timeouts::default()).with_recorder();
+        ctr.handle_command(&AtCommandRequest::new("AT+CSQ".try_into()?)).await?;
+        assert_eq!(ctr.handle_command(&AtCommandRequest::new("AT+CREG?".try_into()?)).await, Err(AtError::Error));
+
+        let recorded: StdVec<(Direction, &str)> = ctr.recorder().unwrap().lines().map(|r| (r.direction, r.line.as_str())).collect();
+        assert_eq!(
+            recorded,
+            [
+                (Direction::Tx, "AT+CSQ"),
+                (Direction::Rx, "AT+CSQ"),
+                (Direction::Rx, "+CSQ: 20,99"),
+                (Direction::Rx, "OK"),
+                (Direction::Tx, "AT+CREG?"),
+                (Direction::Rx, "AT+CREG?"),
+                (Direction::Rx, "ERROR"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_low_priority_transfer_is_preempted() {
+        let mut state = State::new();
+        let (runner, low) = new(&mut state, ScriptStream::new(b""), Timeouts::default());
+        let high = low.try_clone().unwrap();
+        let clients = async {
+            embassy_futures::join::join(
+                low.use_controller_with_priority(AtPriority::Low, async |_ctr| {
+                    core::future::pending::<()>().await;
+                    Ok(())
+                }),
+                async {
+                    Timer::after_millis(10).await;
+                    high.use_controller_with_priority(AtPriority::High, async |_ctr| Ok(42)).await
+                },
+            )
+            .await
+        };
+        let Either::Second((low_result, high_result)) = select(runner.run(), clients).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(low_result, Err(AtError::Cancelled));
+        assert_eq!(high_result, Ok(42));
+    }
+
+    #[tokio::test]
+    async fn test_runner_with_simulated_modem() {
+        let (stream, modem) = crate::tests::mock_stream::mock_stream();
+        let script = crate::tests::mock_stream::ModemSimulator::new()
+            .urc("+CPIN: READY")
+            .expect("AT+CSQ")
+            .delay(Duration::from_millis(20))
+            .respond(&["+CSQ: 20,99", "", "OK"])
+            .urc("+CGEV: NW MODIFY 1,4")
+            .expect("AT")
+            .ok();
+        let mut state = State::new();
+        let (runner, client) = new(&mut state, stream, Timeouts::default());
+        let commands = async {
+            Timer::after_millis(10).await;
+            let signal = status_control::query_signal_quality(&client).await;
+            (signal, at(&client).await)
+        };
+        let Either::Second((_, (signal, alive))) = select(runner.run(), embassy_futures::join::join(script.run(modem), commands)).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(signal, Ok((status_control::Rssi::from_dbm(-73), 99)));
+        assert_eq!(alive, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_request() {
+        let (stream, modem) = crate::tests::mock_stream::mock_stream();
+        let script = crate::tests::mock_stream::ModemSimulator::new()
+            .expect("AT+CSQ")
+            .delay(Duration::from_millis(50))
+            .respond(&["+CSQ: 20,99", "", "OK"]);
+        let mut state = State::new();
+        let stop = Shutdown::new();
+        let (runner, client) = new(&mut state, stream, Timeouts::default());
+        let runner = runner.with_shutdown(&stop);
+        let other = client.try_clone().unwrap();
+        let work = async {
+            let stopping = async {
+                Timer::after_millis(10).await;
+                shutdown(&stop).await;
+            };
+            let (signal, ()) = embassy_futures::join::join(status_control::query_signal_quality(&client), stopping).await;
+            (signal, at(&other).await)
+        };
+        let ((), (_modem, (signal, after))) = embassy_futures::join::join(runner.run(), embassy_futures::join::join(script.run(modem), work)).await;
+        assert_eq!(signal, Ok((status_control::Rssi::from_dbm(-73), 99)));
+        assert_eq!(after, Err(AtError::Shutdown));
+    }
+
+    #[tokio::test]
+    async fn test_release_with_full_request_queue() {
+        let mut state = State::new();
+        let (runner, a) = new(&mut state, ScriptStream::new(b""), Timeouts::default());
+        let b = a.try_clone().unwrap();
+        let c = a.try_clone().unwrap();
+        let cancelled = async |client: &AtClientImpl<'_, _>, millis| {
+            with_timeout(Duration::from_millis(millis), client.use_controller(async |_ctr| Ok(())))
+                .await
+                .is_err()
+        };
+        // the runner is not polled yet, the acquires and releases pile up
+        assert!(cancelled(&a, 1).await);
+        assert!(cancelled(&b, 1).await);
+        let (a_cancelled, c_cancelled) = embassy_futures::join::join(cancelled(&a, 5), cancelled(&c, 10)).await;
+        assert!(a_cancelled && c_cancelled);
+        let served = with_timeout(Duration::from_secs(1), b.use_controller(async |_ctr| Ok(42)));
+        let Either::Second(result) = select(runner.run(), served).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result, Ok(Ok(42)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_with_client_blocked_in_send() {
+        let mut state = State::new();
+        let stop = Shutdown::new();
+        let (runner, a) = new(&mut state, ScriptStream::new(b""), Timeouts::default());
+        let mut runner = runner.with_shutdown(&stop);
+        let b = a.try_clone().unwrap();
+        // cancelled requests fill the queue, the runner is not polled
+        for _ in 0..CHANNEL_SIZE {
+            assert!(with_timeout(Duration::from_millis(1), a.use_controller(async |_ctr| Ok(()))).await.is_err());
+        }
+        assert!(a.arbitration.requests.is_full());
+        let blocked = with_timeout(Duration::from_secs(1), b.use_controller(async |_ctr| Ok(())));
+        let stopping = async {
+            Timer::after_millis(10).await;
+            runner.stop();
+        };
+        let (result, ()) = embassy_futures::join::join(blocked, stopping).await;
+        assert_eq!(result, Ok(Err(AtError::Shutdown)));
+        assert_eq!(at(&a).await, Err(AtError::Shutdown));
+    }
+
+    #[tokio::test]
+    async fn test_clients_are_limited() {
+        let mut state = State::<ScriptStream>::new();
+        let (_runner, client) = new(&mut state, ScriptStream::new(b""), Timeouts::default());
+        let clients: StdVec<_> = (1..MAX_AT_CLIENTS).map(|_| client.try_clone().unwrap()).collect();
+        assert_eq!(clients.len(), MAX_AT_CLIENTS - 1);
+        assert!(client.try_clone().is_none());
     }
 }
@@ -0,0 +1,123 @@
+//! Bridge of the module UART to a host port, to drive the module from a PC for diagnostics.
+//!
+//! [`bridge`] holds the AT controller with a high priority grant, so the runner and the
+//! other clients wait, and pipes the raw bytes between the host port (e.g. the USB CDC
+//! port of the board) and the module. The host ends it with [`BRIDGE_ESCAPE`]. The module
+//! may be left in any state by the PC, the cloud runner recovers it like after an error.
+
+use embassy_futures::select::{Either, select};
+use embedded_io_async::{Read, Write};
+
+use crate::{
+    at::{AtClient, AtControllerImpl, AtError, AtPriority},
+    info,
+};
+
+/// Ctrl-], like telnet, it is no byte of an AT command.
+pub const BRIDGE_ESCAPE: u8 = 0x1d;
+const BRIDGE_BUFFER_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BridgeEnd {
+    /// The host sent [`BRIDGE_ESCAPE`].
+    Escape,
+    /// Reading from or writing to the host failed, e.g. the terminal closed.
+    Host,
+    /// Reading from or writing to the module failed.
+    Module,
+}
+
+/// Pipes the bytes between `host` and `module` until the host escapes or a side fails.
+pub async fn pipe<H: Read + Write, M: Read + Write>(host: &mut H, module: &mut M) -> BridgeEnd {
+    let mut from_host = [0u8; BRIDGE_BUFFER_SIZE];
+    let mut from_module = [0u8; BRIDGE_BUFFER_SIZE];
+    loop {
+        // both reads are buffered by the drivers, the one not selected loses nothing
+        match select(host.read(&mut from_host), module.read(&mut from_module)).await {
+            Either::First(Ok(len)) => {
+                let (bytes, escaped) = match from_host[..len].iter().position(|byte| *byte == BRIDGE_ESCAPE) {
+                    Some(escape) => (&from_host[..escape], true),
+                    None => (&from_host[..len], false),
+                };
+                if module.write_all(bytes).await.is_err() {
+                    return BridgeEnd::Module;
+                }
+                if escaped {
+                    return BridgeEnd::Escape;
+                }
+            }
+            Either::First(Err(_)) => return BridgeEnd::Host,
+            Either::Second(Ok(len)) => {
+                if host.write_all(&from_module[..len]).await.is_err() {
+                    return BridgeEnd::Host;
+                }
+            }
+            Either::Second(Err(_)) => return BridgeEnd::Module,
+        }
+    }
+}
+
+/// Bridges the module UART to `host` until the bridge ends, see the module documentation.
+pub async fn bridge<'ch, S: Read + Write, H: Read + Write>(client: &impl AtClient<'ch, AtControllerImpl<S>>, host: &mut H) -> Result<BridgeEnd, AtError> {
+    info!("AT bridge> started");
+    let end = client
+        .use_controller_with_priority(AtPriority::High, async |ctr| Ok(ctr.bridge(async |module: &mut S| pipe(host, module).await).await))
+        .await?;
+    info!("AT bridge> ended with {:?}", end);
+    Ok(end)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Returns the chunks one per read, each after yielding the given times so the other side gets a turn.
+    struct ChunkStream {
+        chunks: std::collections::VecDeque<(usize, &'static [u8])>,
+        output: std::vec::Vec<u8>,
+    }
+
+    impl ChunkStream {
+        fn new(chunks: &[(usize, &'static [u8])]) -> Self {
+            Self {
+                chunks: chunks.iter().copied().collect(),
+                output: std::vec::Vec::new(),
+            }
+        }
+    }
+
+    impl embedded_io_async::ErrorType for ChunkStream {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for ChunkStream {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let Some(&(yields, _)) = self.chunks.front() else {
+                return core::future::pending().await;
+            };
+            for _ in 0..yields {
+                embassy_futures::yield_now().await;
+            }
+            let (_, chunk) = self.chunks.pop_front().unwrap();
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for ChunkStream {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[tokio::test]
+    async fn check_pipe_until_escape() {
+        let mut host = ChunkStream::new(&[(1, b"AT+CSQ\r"), (3, b"ATI\r\x1dAT+CFUN=0\r")]);
+        let mut module = ChunkStream::new(&[(2, b"\r\n+CSQ: 21,3\r\n\r\nOK\r\n")]);
+        assert_eq!(pipe(&mut host, &mut module).await, BridgeEnd::Escape);
+        assert_eq!(module.output, b"AT+CSQ\rATI\r");
+        assert_eq!(host.output, b"\r\n+CSQ: 21,3\r\n\r\nOK\r\n");
+    }
+}
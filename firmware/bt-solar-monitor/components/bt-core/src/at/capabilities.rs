@@ -0,0 +1,155 @@
+use crate::{
+    at::{AtClient, AtController, AtError},
+    at_request, info, warn,
+};
+
+/// Bitmap of optional AT features supported by the module firmware.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    /// `AT+HTTPPOSTFILE` for posting files staged on the module file system.
+    pub const HTTP_POST_FILE: Capabilities = Capabilities(1 << 0);
+    /// `AT+CNTP` network time protocol client.
+    pub const NTP: Capabilities = Capabilities(1 << 1);
+    /// `AT+CMQTT*` MQTT client.
+    pub const MQTT: Capabilities = Capabilities(1 << 2);
+    /// `AT+CSCLK` UART sleep mode.
+    pub const SLEEP: Capabilities = Capabilities(1 << 3);
+    /// `AT+CSSLCFG` SSL contexts for HTTPS.
+    pub const SSL: Capabilities = Capabilities(1 << 4);
+
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+const PROBES: [(&str, Capabilities); 5] = [
+    ("AT+HTTPPOSTFILE", Capabilities::HTTP_POST_FILE),
+    ("AT+CNTP", Capabilities::NTP),
+    ("AT+CMQTTSTART", Capabilities::MQTT),
+    ("AT+CSCLK", Capabilities::SLEEP),
+    ("AT+CSSLCFG", Capabilities::SSL),
+];
+
+/// Outcome of a single `AT+<x>=?` probe.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Support {
+    Supported,
+    Unsupported,
+    /// The probe failed for another reason, e.g. a timeout, the command may still work.
+    Unknown,
+}
+
+// AT+<x>=?
+// OK => supported, ERROR or +CME ERROR => not supported, anything else => unknown
+pub async fn is_supported<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, command: &str) -> Support {
+    let test = async || at_request!("{}=?", command).send(client).await;
+    match test().await {
+        Ok(_) => Support::Supported,
+        Err(AtError::Error) => Support::Unsupported,
+        Err(e) => {
+            warn!("probing {} failed: {:?}", command, e);
+            Support::Unknown
+        }
+    }
+}
+
+/// Probes every command on its own, a capability is only left out when the module rejects it.
+///
+/// A failed probe keeps the capability, the feature then fails at its first use instead of
+/// being disabled until the next probe.
+pub async fn probe<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Capabilities {
+    let mut capabilities = Capabilities::NONE;
+    for (command, capability) in PROBES {
+        match is_supported(client, command).await {
+            Support::Supported => capabilities.insert(capability),
+            Support::Unsupported => info!("{} not supported by module", command),
+            Support::Unknown => {
+                info!("{} unknown => assume supported", command);
+                capabilities.insert(capability);
+            }
+        }
+    }
+    capabilities
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        at::mocks::{mock_error, mock_request},
+        tests::mock_stream::{ModemSimulator, mock_stream},
+        timeouts::Timeouts,
+    };
+    use embassy_futures::{
+        join::join,
+        select::{Either, select},
+    };
+    use embassy_time::Duration;
+
+    #[test]
+    fn test_capabilities_bitmap() {
+        let mut capabilities = Capabilities::NONE;
+        assert!(!capabilities.contains(Capabilities::NTP));
+        capabilities.insert(Capabilities::NTP);
+        capabilities.insert(Capabilities::MQTT);
+        assert!(capabilities.contains(Capabilities::NTP));
+        assert!(capabilities.contains(Capabilities::MQTT));
+        assert!(!capabilities.contains(Capabilities::HTTP_POST_FILE));
+        assert_eq!(capabilities.bits(), 0b110);
+    }
+
+    #[tokio::test]
+    async fn test_is_supported() {
+        let mock = mock_request("AT+CNTP=?", &["+CNTP: 255,(-96~96)"]);
+        assert_eq!(is_supported(&mock, "AT+CNTP").await, Support::Supported);
+
+        let mock = mock_error("AT+CMQTTSTART=?", AtError::Error);
+        assert_eq!(is_supported(&mock, "AT+CMQTTSTART").await, Support::Unsupported);
+
+        let mock = mock_error("AT+HTTPPOSTFILE=?", AtError::Timeout);
+        assert_eq!(is_supported(&mock, "AT+HTTPPOSTFILE").await, Support::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_probe_with_timeout() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new()
+            .expect("AT+HTTPPOSTFILE=?")
+            .respond(&["", "+CME ERROR: operation not supported"])
+            .expect("AT+CNTP=?")
+            // no answer => timeout
+            .expect("AT+CMQTTSTART=?")
+            .respond(&["", "ERROR"])
+            .expect("AT+CSCLK=?")
+            .respond(&["", "+CSCLK: (0-2)", "", "OK"])
+            .expect("AT+CSSLCFG=?")
+            .respond(&["", "OK"]);
+        let timeouts = Timeouts {
+            at_command: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, timeouts);
+        let Either::Second((_, capabilities)) = select(runner.run(), join(script.run(modem), probe(&client))).await else {
+            unreachable!("runner never returns");
+        };
+        assert!(!capabilities.contains(Capabilities::HTTP_POST_FILE));
+        assert!(capabilities.contains(Capabilities::NTP));
+        assert!(!capabilities.contains(Capabilities::MQTT));
+        assert!(capabilities.contains(Capabilities::SLEEP));
+        assert!(capabilities.contains(Capabilities::SSL));
+    }
+}
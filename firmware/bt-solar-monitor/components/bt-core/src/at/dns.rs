@@ -0,0 +1,112 @@
+use core::net::IpAddr;
+
+use embassy_time::Duration;
+use heapless::Vec;
+use nom::{
+    Parser,
+    bytes::complete::{tag, take_until},
+    sequence::delimited,
+};
+
+use crate::{
+    at::{AtClient, AtController, AtError, packet_domain::parse_ip_address},
+    at_request, warn,
+};
+
+const MAX_DNS_ADDRESSES: usize = 2;
+
+fn quoted(input: &str) -> nom::IResult<&str, &str> {
+    delimited(tag("\""), take_until("\""), tag("\"")).parse(input)
+}
+
+// +CDNSGIP: 1,"www.google.com","142.250.74.36"
+// +CDNSGIP: 0,10
+fn parse_dns_response(input: &str) -> nom::IResult<&str, Vec<&str, MAX_DNS_ADDRESSES>> {
+    let (mut remaining, (_, status)) = (tag("+CDNSGIP: "), nom::character::complete::u32).parse(input)?;
+    let mut addresses = Vec::new();
+    if status != 1 {
+        return Ok((remaining, addresses));
+    }
+    (remaining, _) = (tag(","), quoted).parse(remaining)?;
+    while let Ok((next, (_, ip))) = (tag(","), quoted).parse(remaining) {
+        let _ = addresses.push(ip);
+        remaining = next;
+    }
+    Ok((remaining, addresses))
+}
+
+// AT+CDNSGIP=<domain name>
+// OK
+// +CDNSGIP: 1,<domain name>,<IP address>[,<IP address2>]
+// +CDNSGIP: 0,<dns error code>
+/// Resolves `host` with the DNS client of the module, `Ok(None)` if the name could not be resolved.
+///
+/// With `prefer_ipv6` an IPv6 address is returned if the module reports one.
+pub async fn resolve_host<'ch, Ctr: AtController>(
+    client: &impl AtClient<'ch, Ctr>,
+    host: &str,
+    timeout: Duration,
+    prefer_ipv6: bool,
+) -> Result<Option<IpAddr>, AtError> {
+    let response = at_request!("AT+CDNSGIP=\"{}\"", host)
+        .with_timeout(timeout)
+        .with_urc_prefix("+CDNSGIP: ".try_into()?)
+        .send(client)
+        .await?;
+    let line = response.lines.iter().find(|l| l.starts_with("+CDNSGIP: ")).ok_or(AtError::Error)?;
+    let (_, addresses) = parse_dns_response(line.as_str())?;
+    let mut addresses = addresses.iter().filter_map(|address| {
+        let ip = parse_ip_address(address);
+        if ip.is_none() {
+            warn!("invalid ip address '{}' for {}", address, host);
+        }
+        ip
+    });
+    let first = addresses.next();
+    match first {
+        Some(ip) if ip.is_ipv6() != prefer_ipv6 => Ok(Some(addresses.find(|a| a.is_ipv6() == prefer_ipv6).unwrap_or(ip))),
+        other => Ok(other),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::mock_response;
+    use core::net::Ipv4Addr;
+
+    #[test]
+    fn test_parse_dns_response() {
+        let (_, ip) = parse_dns_response("+CDNSGIP: 1,\"www.google.com\",\"142.250.74.36\"").unwrap();
+        assert_eq!(ip.as_slice(), &["142.250.74.36"]);
+
+        let (_, ip) = parse_dns_response("+CDNSGIP: 1,\"example.com\",\"93.184.215.14\",\"2606:2800:21f:cb07:6820:80da:af6b:8b2c\"").unwrap();
+        assert_eq!(ip.as_slice(), &["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"]);
+
+        let (_, ip) = parse_dns_response("+CDNSGIP: 0,10").unwrap();
+        assert!(ip.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_host() -> Result<(), AtError> {
+        let request = at_request!("AT+CDNSGIP=\"www.google.com\"")
+            .with_timeout(Duration::from_secs(30))
+            .with_urc_prefix("+CDNSGIP: ".try_into()?);
+        let mock = mock_response(request, &["+CDNSGIP: 1,\"www.google.com\",\"142.250.74.36\""]);
+        let ip = resolve_host(&mock, "www.google.com", Duration::from_secs(30), false).await?;
+        assert_eq!(ip, Some(IpAddr::V4(Ipv4Addr::new(142, 250, 74, 36))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_host_prefer_ipv6() -> Result<(), AtError> {
+        let request = at_request!("AT+CDNSGIP=\"example.com\"")
+            .with_timeout(Duration::from_secs(30))
+            .with_urc_prefix("+CDNSGIP: ".try_into()?);
+        let line = "+CDNSGIP: 1,\"example.com\",\"93.184.215.14\",\"2606:2800:21f:cb07:6820:80da:af6b:8b2c\"";
+        let mock = mock_response(request, &[line]);
+        let ip = resolve_host(&mock, "example.com", Duration::from_secs(30), true).await?;
+        assert_eq!(ip, Some("2606:2800:21f:cb07:6820:80da:af6b:8b2c".parse().unwrap()));
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use nom::{Parser, bytes::complete::tag};
+
+use crate::{
+    at::{AtClient, AtController, AtError},
+    at_request, warn,
+};
+
+/// Longest chunk of one `AT+FSWRITE`, the module takes up to 10240 bytes.
+pub const MAX_WRITE_SIZE: usize = 1024;
+
+/// How [`open`] treats an existing file.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OpenMode {
+    /// Opens the file, creates it if it does not exist.
+    Open = 0,
+    /// Creates the file, an existing one is truncated.
+    Create = 1,
+    ReadOnly = 2,
+}
+
+/// Handle of an open file, see [`open`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FileHandle(u32);
+
+// AT+FSOPEN=<filepath>,<mode>
+// +FSOPEN: <fh>
+// OK
+/// `path` includes the drive, e.g. `C:/upload.bin`.
+pub async fn open<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, path: &str, mode: OpenMode) -> Result<FileHandle, AtError> {
+    let response = at_request!("AT+FSOPEN={},{}", path, mode as u8).send(client).await?;
+    let (_, handle) = nom::character::complete::u32.parse(response.find_prefixed("+FSOPEN: ")?)?;
+    Ok(FileHandle(handle))
+}
+
+// AT+FSWRITE=<fh>,<size>
+// >
+// <data>
+// +FSWRITE: <size>,<unsent_size>
+// OK
+/// Appends `data`, at most [`MAX_WRITE_SIZE`] bytes, at the position of the file.
+pub async fn write<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, handle: FileHandle, data: &[u8]) -> Result<(), AtError> {
+    let response = at_request!("AT+FSWRITE={},{}", handle.0, data.len())
+        .with_write_prompt()
+        .send_with_data(client, &[data])
+        .await?;
+    let (_, (written, _, unsent)) =
+        (nom::character::complete::usize, tag(","), nom::character::complete::usize).parse(response.find_prefixed("+FSWRITE: ")?)?;
+    if written != data.len() || unsent != 0 {
+        warn!("AT+FSWRITE => {} of {} bytes written", written, data.len());
+        return Err(AtError::Error);
+    }
+    Ok(())
+}
+
+// AT+FSCLOSE=<fh>
+pub async fn close<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, handle: FileHandle) -> Result<(), AtError> {
+    at_request!("AT+FSCLOSE={}", handle.0).send(client).await?;
+    Ok(())
+}
+
+// AT+FSDEL=<filename>
+pub async fn delete<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, path: &str) -> Result<(), AtError> {
+    at_request!("AT+FSDEL={}", path).send(client).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::mock_request;
+
+    #[tokio::test]
+    async fn test_open() -> Result<(), AtError> {
+        let mock = mock_request("AT+FSOPEN=C:/upload.bin,1", &["+FSOPEN: 1"]);
+        assert_eq!(open(&mock, "C:/upload.bin", OpenMode::Create).await?, FileHandle(1));
+        Ok(())
+    }
+}
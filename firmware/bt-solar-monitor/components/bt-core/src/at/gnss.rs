@@ -0,0 +1,95 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use heapless::Vec;
+use nom::{Parser, bytes::complete::tag};
+
+use crate::{
+    at::{AtClient, AtController, AtError},
+    at_request,
+};
+
+const MAX_GNSS_INFO_FIELDS: usize = 20;
+
+/// A GNSS fix, coordinates in decimal degrees (north and east positive).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GnssPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level.
+    pub altitude: f32,
+    /// UTC time of the fix.
+    pub fix_time: NaiveDateTime,
+}
+
+// AT+CGNSSPWR=<on/off>
+/// The engine needs a while until the first fix, `AT+CGNSSINFO` reports none until then.
+pub async fn set_power<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, on: bool) -> Result<(), AtError> {
+    at_request!("AT+CGNSSPWR={}", if on { 1 } else { 0 }).send(client).await?;
+    Ok(())
+}
+
+// AT+CGNSSINFO
+// +CGNSSINFO: <mode>,<GPS-SVs>,<GLONASS-SVs>,<BEIDOU-SVs>,<lat>,<N/S>,<log>,<E/W>,<date>,<UTC-time>,<alt>,<speed>,<course>,<PDOP>,<HDOP>,<VDOP>
+// +CGNSSINFO: <mode>,<GPS-SVs>,<GLONASS-SVs>,<GALILEO-SVs>,<BEIDOU-SVs>,<lat>,...
+// +CGNSSINFO: ,,,,,,,,
+/// The current position, `None` without a fix.
+pub async fn query_position<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<Option<GnssPosition>, AtError> {
+    let response = at_request!("AT+CGNSSINFO").send(client).await?;
+    parse_gnss_info(response.line(0)?)
+}
+
+/// The number of satellite count fields differs between firmware versions, the fields are located from the `N`/`S` indicator.
+fn parse_gnss_info(line: &str) -> Result<Option<GnssPosition>, AtError> {
+    let (fields, _) = tag("+CGNSSINFO: ").parse(line)?;
+    let fields: Vec<&str, MAX_GNSS_INFO_FIELDS> = fields.split(',').map(str::trim).take(MAX_GNSS_INFO_FIELDS).collect();
+    let mode = fields.first().copied().unwrap_or_default();
+    if mode.is_empty() || mode == "0" {
+        return Ok(None);
+    }
+    let ns = fields.iter().skip(1).position(|f| *f == "N" || *f == "S").ok_or(AtError::Error)? + 1;
+    let field = |offset: usize| fields.get(ns - 1 + offset).copied().ok_or(AtError::Error);
+    let latitude = signed_degrees(field(0)?, field(1)?, "S")?;
+    let longitude = signed_degrees(field(2)?, field(3)?, "W")?;
+    let date = NaiveDate::parse_from_str(field(4)?, "%d%m%y").map_err(|_| AtError::Error)?;
+    let time = NaiveTime::parse_from_str(field(5)?, "%H%M%S%.f").map_err(|_| AtError::Error)?;
+    let altitude = field(6)?.parse().unwrap_or_default();
+    Ok(Some(GnssPosition {
+        latitude,
+        longitude,
+        altitude,
+        fix_time: date.and_time(time),
+    }))
+}
+
+fn signed_degrees(value: &str, hemisphere: &str, negative: &str) -> Result<f64, AtError> {
+    let degrees: f64 = value.parse().map_err(|_| AtError::Error)?;
+    Ok(if hemisphere == negative { -degrees } else { degrees })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::mock_request;
+
+    #[test]
+    fn test_parse_gnss_info() {
+        let position = parse_gnss_info("+CGNSSINFO: 3,12,,04,00,47.3769351,N,8.5417010,E,300625,154212.00,412.6,0.000,,1.30,0.80,1.03")
+            .unwrap()
+            .unwrap();
+        approx::assert_abs_diff_eq!(position.latitude, 47.3769351);
+        approx::assert_abs_diff_eq!(position.longitude, 8.5417010);
+        approx::assert_abs_diff_eq!(position.altitude, 412.6);
+        assert_eq!(position.fix_time, NaiveDateTime::parse_from_str("2025-06-30 15:42:12", "%Y-%m-%d %H:%M:%S").unwrap());
+
+        let position = parse_gnss_info("+CGNSSINFO: 2,06,03,00,33.8688,S,151.2093,E,011225,010203.0,58.0,0.0,255.0,1.1,0.8,0.7")
+            .unwrap()
+            .unwrap();
+        approx::assert_abs_diff_eq!(position.latitude, -33.8688);
+    }
+
+    #[tokio::test]
+    async fn test_query_position_without_fix() -> Result<(), AtError> {
+        let mock = mock_request("AT+CGNSSINFO", &["+CGNSSINFO: ,,,,,,,,"]);
+        assert_eq!(query_position(&mock).await?, None);
+        Ok(())
+    }
+}
@@ -9,15 +9,28 @@ pub enum HttpAction {
     Post = 1,
     Head = 2,
     Delete = 3,
+    Put = 4,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct HttpStatusCode(u32);
 
 impl HttpStatusCode {
+    /// Reported by the module instead of a server status, e.g. for a server the CA file does not verify.
+    pub const SSL_HANDSHAKE_FAILED: HttpStatusCode = HttpStatusCode(715);
+
+    pub const fn new(code: u32) -> Self {
+        Self(code)
+    }
+
     pub fn is_ok(&self) -> bool {
         self.0 >= 200 && self.0 < 300
     }
+
+    /// 4xx, retrying the same request will not help.
+    pub fn is_client_error(&self) -> bool {
+        self.0 >= 400 && self.0 < 500
+    }
 }
 
 impl core::fmt::Display for HttpStatusCode {
@@ -48,6 +61,19 @@ pub async fn set_url<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, u
     Ok(())
 }
 
+// AT+HTTPPARA="SSLCFG",<ssl_ctx_id>
+/// The SSL context of the `https://` requests, configure it with [`crate::at::ssl`].
+///
+/// Some firmware revisions want the id `quoted`, see [`crate::at::quirks::Quirks::QUOTED_SSL_CONTEXT`].
+pub async fn set_ssl_context<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context: u8, quoted: bool) -> Result<(), AtError> {
+    if quoted {
+        at_request!("AT+HTTPPARA=\"SSLCFG\",\"{}\"", context).send(client).await?;
+    } else {
+        at_request!("AT+HTTPPARA=\"SSLCFG\",{}", context).send(client).await?;
+    }
+    Ok(())
+}
+
 pub async fn set_header<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, header: &str, value: &str) -> Result<(), AtError> {
     at_request!("AT+HTTPPARA=\"USERDATA\",\"{}: {}\"", header, value).send(client).await?;
     Ok(())
@@ -58,9 +84,90 @@ pub async fn action<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, ac
         .with_urc_prefix("+HTTPACTION: ".try_into()?)
         .send(client)
         .await?;
-    let (_, (_, _action, _, status_code, _, data_len)) =
-        (tag("+HTTPACTION: "), nom::character::complete::u32, tag(","), nom::character::complete::u32, tag(","), nom::character::complete::usize)
-            .parse(response.line(0)?)?;
+    let (_, (_action, _, status_code, _, data_len)) =
+        (nom::character::complete::u32, tag(","), nom::character::complete::u32, tag(","), nom::character::complete::usize)
+            .parse(response.find_prefixed("+HTTPACTION: ")?)?;
 
     Ok((HttpStatusCode(status_code), data_len))
 }
+
+// AT+HTTPPOSTFILE=<filename>,<path>,<method>
+// OK
+// +HTTPPOSTFILE: <statuscode>,<datalen>
+/// Sends the file `name` of the local drive `C:` as the body, the response is read like the one of [`action`].
+pub async fn post_file<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, name: &str, action: HttpAction) -> Result<(HttpStatusCode, usize), AtError> {
+    let response = at_request!("AT+HTTPPOSTFILE=\"{}\",1,{}", name, action as u32)
+        .with_urc_prefix("+HTTPPOSTFILE: ".try_into()?)
+        .send(client)
+        .await?;
+    let (_, (status_code, _, data_len)) =
+        (nom::character::complete::u32, tag(","), nom::character::complete::usize).parse(response.find_prefixed("+HTTPPOSTFILE: ")?)?;
+    Ok((HttpStatusCode(status_code), data_len))
+}
+
+// AT+HTTPHEAD
+/// Reads the raw response header of the last action into `buf`, see [`HttpHeaders`].
+pub async fn read_head<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, buf: &mut [u8]) -> Result<usize, AtError> {
+    client.use_controller(async |ctr| ctr.handle_http_head(buf).await).await
+}
+
+/// Header fields of a raw HTTP response header, the status line is skipped.
+#[derive(Debug, Clone)]
+pub struct HttpHeaders<'a> {
+    lines: core::str::Split<'a, &'static str>,
+}
+
+impl<'a> HttpHeaders<'a> {
+    pub fn parse(raw: &'a str) -> Self {
+        Self { lines: raw.split("\r\n") }
+    }
+
+    /// Value of the first field called `name`, compared case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.clone().find(|(field, _)| field.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+}
+
+impl<'a> Iterator for HttpHeaders<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?;
+            if let Some((name, value)) = line.split_once(':') {
+                return Some((name.trim(), value.trim()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::mock_response;
+
+    #[tokio::test]
+    async fn test_action() -> Result<(), AtError> {
+        let mock = mock_response(at_request!("AT+HTTPACTION=4").with_urc_prefix("+HTTPACTION: ".try_into()?), &["+HTTPACTION: 4,204,0"]);
+        assert_eq!(action(&mock, HttpAction::Put).await?, (HttpStatusCode::new(204), 0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_file() -> Result<(), AtError> {
+        let mock = mock_response(at_request!("AT+HTTPPOSTFILE=\"upload.bin\",1,1").with_urc_prefix("+HTTPPOSTFILE: ".try_into()?), &["+HTTPPOSTFILE: 201,17"]);
+        assert_eq!(post_file(&mock, "upload.bin", HttpAction::Post).await?, (HttpStatusCode::new(201), 17));
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers() {
+        let raw = "HTTP/1.1 200 OK\r\nContent-Type: application/x-protobuf\r\nETag: \"v42\"\r\nRetry-After:120\r\n\r\n";
+        let headers = HttpHeaders::parse(raw);
+        assert_eq!(headers.clone().count(), 3);
+        assert_eq!(headers.get("content-type"), Some("application/x-protobuf"));
+        assert_eq!(headers.get("ETag"), Some("\"v42\""));
+        assert_eq!(headers.get("Retry-After"), Some("120"));
+        assert_eq!(headers.get("Location"), None);
+    }
+}
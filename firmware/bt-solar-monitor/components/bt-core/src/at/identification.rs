@@ -0,0 +1,112 @@
+use heapless::String;
+use nom::{
+    Parser,
+    bytes::complete::tag,
+    character::complete::{alphanumeric1, digit1},
+    combinator::opt,
+};
+
+use crate::{
+    at::{AtClient, AtController, AtError},
+    at_request,
+};
+
+pub const IDENTIFICATION_STRING_SIZE: usize = 64;
+/// 15 digits.
+pub const IMEI_SIZE: usize = 16;
+/// Up to 15 digits.
+pub const IMSI_SIZE: usize = 16;
+/// 19 or 20 digits, some SIMs pad with a trailing `F`.
+pub const ICCID_SIZE: usize = 24;
+
+/// Identity of the module and the SIM, for the inventory in the backend.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ModuleIdentity {
+    pub imei: String<IMEI_SIZE>,
+    pub imsi: String<IMSI_SIZE>,
+    pub iccid: String<ICCID_SIZE>,
+    pub firmware_revision: String<IDENTIFICATION_STRING_SIZE>,
+}
+
+// AT+CGMR
+// +CGMR: A011B07A7670M7
+pub async fn query_firmware_revision<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<String<IDENTIFICATION_STRING_SIZE>, AtError> {
+    let response = at_request!("AT+CGMR").send(client).await?;
+    Ok(response.find_prefixed("+CGMR: ")?.try_into()?)
+}
+
+// AT+CGSN
+// 864663060123456
+/// The IMEI, some firmware revisions answer with a `+CGSN: ` prefix.
+pub async fn query_imei<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<String<IMEI_SIZE>, AtError> {
+    let response = at_request!("AT+CGSN").send(client).await?;
+    let (_, (_, imei)) = (opt(tag("+CGSN: ")), digit1).parse(response.line(0)?)?;
+    Ok(imei.try_into()?)
+}
+
+// AT+CIMI
+// 228012345678901
+/// The IMSI of the SIM, fails without a SIM.
+pub async fn query_imsi<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<String<IMSI_SIZE>, AtError> {
+    let response = at_request!("AT+CIMI").send(client).await?;
+    let (_, imsi) = digit1.parse(response.line(0)?)?;
+    Ok(imsi.try_into()?)
+}
+
+// AT+CICCID
+// +ICCID: 89410123456789012345
+/// The ICCID of the SIM, fails without a SIM.
+pub async fn query_iccid<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<String<ICCID_SIZE>, AtError> {
+    let response = at_request!("AT+CICCID").send(client).await?;
+    let (_, iccid) = alphanumeric1.parse(response.find_prefixed("+ICCID: ")?)?;
+    Ok(iccid.try_into()?)
+}
+
+pub async fn query_identity<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<ModuleIdentity, AtError> {
+    Ok(ModuleIdentity {
+        imei: query_imei(client).await?,
+        imsi: query_imsi(client).await?,
+        iccid: query_iccid(client).await?,
+        firmware_revision: query_firmware_revision(client).await?,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::{mock_error, mock_request};
+
+    #[tokio::test]
+    async fn test_query_firmware_revision() -> Result<(), AtError> {
+        let mock = mock_request("AT+CGMR", &["+CGMR: A011B07A7670M7"]);
+        let revision = query_firmware_revision(&mock).await?;
+        assert_eq!(revision.as_str(), "A011B07A7670M7");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_imei() -> Result<(), AtError> {
+        let mock = mock_request("AT+CGSN", &["864663060123456"]);
+        assert_eq!(query_imei(&mock).await?.as_str(), "864663060123456");
+        let mock = mock_request("AT+CGSN", &["+CGSN: 864663060123456"]);
+        assert_eq!(query_imei(&mock).await?.as_str(), "864663060123456");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_imsi() -> Result<(), AtError> {
+        let mock = mock_request("AT+CIMI", &["228012345678901"]);
+        assert_eq!(query_imsi(&mock).await?.as_str(), "228012345678901");
+        let mock = mock_error("AT+CIMI", AtError::Error);
+        assert_eq!(query_imsi(&mock).await, Err(AtError::Error));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_iccid() -> Result<(), AtError> {
+        let mock = mock_request("AT+CICCID", &["+ICCID: 8941012345678901234F"]);
+        assert_eq!(query_iccid(&mock).await?.as_str(), "8941012345678901234F");
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-use heapless::format;
+use embassy_time::Duration;
+use heapless::{String, format};
 
 use crate::{
     at::{AtClient, AtController, AtError},
@@ -6,6 +7,8 @@ use crate::{
 };
 use nom::{Parser, bytes::complete::tag};
 
+// the names of the AT command manual
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum NetworkRegistrationUrcConfig {
@@ -29,7 +32,7 @@ impl TryFrom<u32> for NetworkRegistrationUrcConfig {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum NetworkRegistrationState {
     /// 0 not registered, ME is not currently searching a new operator to register to.
@@ -71,10 +74,219 @@ pub async fn get_network_registration<'ch, Ctr: AtController>(
     ctr: &impl AtClient<'ch, Ctr>,
 ) -> Result<(NetworkRegistrationUrcConfig, NetworkRegistrationState), AtError> {
     let response = at_request!("AT+CREG?").send(ctr).await?;
-    let (_, (_, n, _, stat)) = (tag("+CREG: "), nom::character::complete::u32, tag(","), nom::character::complete::u32).parse(response.line(0)?)?;
+    let (_, (n, _, stat)) = (nom::character::complete::u32, tag(","), nom::character::complete::u32).parse(response.find_prefixed("+CREG: ")?)?;
     Ok((n.try_into()?, stat.try_into()?))
 }
 
+// +CEREG: <n>,<stat>[,<tac>,<ci>,<AcT>]
+// +CEREG: 0,5
+/// EPS (LTE / LTE-M / NB-IoT) registration, modules on LTE-M only report their state here.
+pub async fn get_eps_network_registration<'ch, Ctr: AtController>(
+    ctr: &impl AtClient<'ch, Ctr>,
+) -> Result<(NetworkRegistrationUrcConfig, NetworkRegistrationState), AtError> {
+    let response = at_request!("AT+CEREG?").send(ctr).await?;
+    parse_eps_network_registration(response.line(0)?)
+}
+
+/// Parses a `+CEREG: <n>,<stat>` line, also for modules answering without the AT client.
+pub fn parse_eps_network_registration(line: &str) -> Result<(NetworkRegistrationUrcConfig, NetworkRegistrationState), AtError> {
+    let (_, (_, n, _, stat)) = (tag("+CEREG: "), nom::character::complete::u32, tag(","), nom::character::complete::u32).parse(line)?;
+    Ok((n.try_into()?, stat.try_into()?))
+}
+
+pub const OPERATOR_SIZE: usize = 24;
+/// A manual operator selection searches the network first.
+pub const OPERATOR_SELECTION_TIMEOUT: Duration = Duration::from_secs(180);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OperatorSelectionMode {
+    /// 0 automatic, the module selects the operator.
+    Automatic = 0,
+    /// 1 manual, only the given operator.
+    Manual = 1,
+    /// 2 deregister from the network.
+    Deregister = 2,
+    /// 4 manual, automatic if the given operator is not available.
+    ManualAutomatic = 4,
+}
+
+impl TryFrom<u32> for OperatorSelectionMode {
+    type Error = AtError;
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(OperatorSelectionMode::Automatic),
+            1 => Ok(OperatorSelectionMode::Manual),
+            2 => Ok(OperatorSelectionMode::Deregister),
+            4 => Ok(OperatorSelectionMode::ManualAutomatic),
+            _ => Err(AtError::EnumParseError(format!("Invalid OperatorSelectionMode value: {}", value).unwrap_or_default())),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AccessTechnology {
+    /// 0 GSM.
+    Gsm = 0,
+    /// 2 UTRAN.
+    Utran = 2,
+    /// 7 E-UTRAN, LTE and LTE-M.
+    EUtran = 7,
+    /// 9 E-UTRAN NB-S1, NB-IoT.
+    NbIot = 9,
+}
+
+impl TryFrom<u32> for AccessTechnology {
+    type Error = AtError;
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AccessTechnology::Gsm),
+            2 => Ok(AccessTechnology::Utran),
+            7 => Ok(AccessTechnology::EUtran),
+            9 => Ok(AccessTechnology::NbIot),
+            _ => Err(AtError::EnumParseError(format!("Invalid AccessTechnology value: {}", value).unwrap_or_default())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OperatorSelection {
+    pub mode: OperatorSelectionMode,
+    /// In the format of the last `AT+COPS` write, the long name by default. `None` while not registered.
+    pub operator: Option<String<OPERATOR_SIZE>>,
+    pub access_technology: Option<AccessTechnology>,
+}
+
+// AT+COPS?
+// +COPS: <mode>[,<format>,<oper>[,<AcT>]]
+// +COPS: 0,0,"Swisscom",7
+/// The selection mode and the selected operator. Longer names are truncated.
+pub async fn query_operator_selection<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<OperatorSelection, AtError> {
+    let response = at_request!("AT+COPS?").send(ctr).await?;
+    parse_operator_selection(response.line(0)?)
+}
+
+/// Parses a `+COPS: <mode>[,<format>,<oper>[,<AcT>]]` line. Longer names are truncated.
+pub fn parse_operator_selection(line: &str) -> Result<OperatorSelection, AtError> {
+    let (fields, (_, mode)) = (tag("+COPS: "), nom::character::complete::u32).parse(line)?;
+    let mut selection = OperatorSelection {
+        mode: mode.try_into()?,
+        operator: None,
+        access_technology: None,
+    };
+    let Some((_, quoted)) = fields.split_once('"') else {
+        return Ok(selection);
+    };
+    let (name, rest) = quoted.split_once('"').unwrap_or((quoted, ""));
+    let mut operator = String::new();
+    for c in name.chars() {
+        if operator.push(c).is_err() {
+            break;
+        }
+    }
+    selection.operator = Some(operator);
+    if let Some(act) = rest.strip_prefix(',') {
+        let (_, act) = nom::character::complete::u32.parse(act)?;
+        selection.access_technology = Some(act.try_into()?);
+    }
+    Ok(selection)
+}
+
+/// The name of the selected operator, `None` while not registered. Longer names are truncated.
+pub async fn query_operator<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<Option<String<OPERATOR_SIZE>>, AtError> {
+    Ok(query_operator_selection(ctr).await?.operator)
+}
+
+// AT+COPS=<mode>,2,"<plmn>"[,<AcT>]
+// AT+COPS=1,2,"22801",7
+/// Pins the module to the operator with the numeric `plmn` (MCC and MNC), with `fallback` it
+/// selects automatically while the operator is not available.
+pub async fn select_operator<'ch, Ctr: AtController>(
+    ctr: &impl AtClient<'ch, Ctr>,
+    plmn: &str,
+    access_technology: Option<AccessTechnology>,
+    fallback: bool,
+) -> Result<(), AtError> {
+    let mode = if fallback {
+        OperatorSelectionMode::ManualAutomatic
+    } else {
+        OperatorSelectionMode::Manual
+    };
+    let request = match access_technology {
+        Some(act) => at_request!("AT+COPS={},2,\"{}\",{}", mode as u32, plmn, act as u32),
+        None => at_request!("AT+COPS={},2,\"{}\"", mode as u32, plmn),
+    };
+    request.with_timeout(OPERATOR_SELECTION_TIMEOUT).send(ctr).await?;
+    Ok(())
+}
+
+/// LTE bands as the bit mask of `AT+CNBP`, band `n` is bit `n - 1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LteBands(pub u64);
+
+impl LteBands {
+    pub const fn from_bands(bands: &[u8]) -> Self {
+        let mut mask = 0;
+        let mut i = 0;
+        while i < bands.len() {
+            mask |= 1 << (bands[i] - 1);
+            i += 1;
+        }
+        Self(mask)
+    }
+
+    pub fn contains(&self, band: u8) -> bool {
+        (1..=64).contains(&band) && self.0 & (1 << (band - 1)) != 0
+    }
+}
+
+/// The preferred bands of a SIMCom module.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BandPreference {
+    /// GSM and WCDMA bands, the mask of the SIMCom AT manual.
+    pub gsm_wcdma: u64,
+    pub lte: LteBands,
+    /// TD-SCDMA bands, only reported by some modules.
+    pub tds: Option<u64>,
+}
+
+fn hex_mask(field: &str) -> Result<u64, AtError> {
+    let digits = field
+        .trim()
+        .strip_prefix("0x")
+        .or_else(|| field.trim().strip_prefix("0X"))
+        .ok_or(AtError::Error)?;
+    u64::from_str_radix(digits, 16).map_err(|_| AtError::Error)
+}
+
+// AT+CNBP?
+// +CNBP: <mode>,<lte_mode>[,<tds_mode>]
+// +CNBP: 0x0002000000680380,0x00000000000800C5,0x0000000000000021
+pub async fn query_band_preference<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<BandPreference, AtError> {
+    let response = at_request!("AT+CNBP?").send(ctr).await?;
+    let mut masks = response.find_prefixed("+CNBP: ")?.split(',');
+    Ok(BandPreference {
+        gsm_wcdma: hex_mask(masks.next().ok_or(AtError::Error)?)?,
+        lte: LteBands(hex_mask(masks.next().ok_or(AtError::Error)?)?),
+        tds: masks.next().map(hex_mask).transpose()?,
+    })
+}
+
+// AT+CNBP=<mode>,<lte_mode>[,<tds_mode>]
+/// Stored in the module NVM, only write changes.
+pub async fn set_band_preference<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>, bands: &BandPreference) -> Result<(), AtError> {
+    let request = match bands.tds {
+        Some(tds) => at_request!("AT+CNBP=0x{:016X},0x{:016X},0x{:016X}", bands.gsm_wcdma, bands.lte.0, tds),
+        None => at_request!("AT+CNBP=0x{:016X},0x{:016X}", bands.gsm_wcdma, bands.lte.0),
+    };
+    request.send(ctr).await?;
+    Ok(())
+}
+
 // AT+CTZU=<on/off>
 //
 pub async fn set_automatic_time_and_time_zone_update<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>, enable: bool) -> Result<(), AtError> {
@@ -85,7 +297,7 @@ pub async fn set_automatic_time_and_time_zone_update<'ch, Ctr: AtController>(ctr
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::at::mocks::mock_request;
+    use crate::at::mocks::{mock_request, mock_response};
 
     #[tokio::test]
     async fn test_network_registration() -> Result<(), AtError> {
@@ -106,4 +318,67 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_query_operator() -> Result<(), AtError> {
+        let mock = mock_request("AT+COPS?", &["+COPS: 0,0,\"Swisscom\",7"]);
+        assert_eq!(query_operator(&mock).await?.as_deref(), Some("Swisscom"));
+
+        let mock = mock_request("AT+COPS?", &["+COPS: 0"]);
+        assert_eq!(query_operator(&mock).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_operator_selection() -> Result<(), AtError> {
+        let mock = mock_request("AT+COPS?", &["+COPS: 1,2,\"22801\",7"]);
+        let selection = query_operator_selection(&mock).await?;
+        assert_eq!(selection.mode, OperatorSelectionMode::Manual);
+        assert_eq!(selection.operator.as_deref(), Some("22801"));
+        assert_eq!(selection.access_technology, Some(AccessTechnology::EUtran));
+
+        let mock = mock_request("AT+COPS?", &["+COPS: 4"]);
+        let selection = query_operator_selection(&mock).await?;
+        assert_eq!(selection.mode, OperatorSelectionMode::ManualAutomatic);
+        assert_eq!(selection.operator, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_select_operator() -> Result<(), AtError> {
+        let mock = mock_response(at_request!("AT+COPS=1,2,\"22801\",7").with_timeout(OPERATOR_SELECTION_TIMEOUT), &[]);
+        select_operator(&mock, "22801", Some(AccessTechnology::EUtran), false).await?;
+
+        let mock = mock_response(at_request!("AT+COPS=4,2,\"22801\"").with_timeout(OPERATOR_SELECTION_TIMEOUT), &[]);
+        select_operator(&mock, "22801", None, true).await
+    }
+
+    #[tokio::test]
+    async fn test_band_preference() -> Result<(), AtError> {
+        let mock = mock_request("AT+CNBP?", &["+CNBP: 0x0002000000680380,0x00000000000800C5,0x0000000000000021"]);
+        let bands = query_band_preference(&mock).await?;
+        assert_eq!(bands.gsm_wcdma, 0x0002_0000_0068_0380);
+        assert!(bands.lte.contains(1) && bands.lte.contains(3) && bands.lte.contains(20));
+        assert!(!bands.lte.contains(2) && !bands.lte.contains(0) && !bands.lte.contains(65));
+        assert_eq!(bands.tds, Some(0x21));
+
+        let mock = mock_request("AT+CNBP?", &["+CNBP: 0x0000000000000000,0x0000000000000004"]);
+        assert_eq!(query_band_preference(&mock).await?.tds, None);
+
+        let locked = BandPreference {
+            lte: LteBands::from_bands(&[3, 8, 20]),
+            ..bands
+        };
+        let mock = mock_request("AT+CNBP=0x0002000000680380,0x0000000000080084,0x0000000000000021", &[]);
+        set_band_preference(&mock, &locked).await
+    }
+
+    #[tokio::test]
+    async fn test_eps_network_registration() -> Result<(), AtError> {
+        let mock = mock_request("AT+CEREG?", &["+CEREG: 0,5"]);
+        let (n, stat) = get_eps_network_registration(&mock).await?;
+        assert_eq!(n, NetworkRegistrationUrcConfig::UrcDisabled);
+        assert_eq!(stat, NetworkRegistrationState::RegisteredRoaming);
+        Ok(())
+    }
 }
@@ -0,0 +1,60 @@
+use embassy_time::Duration;
+
+use crate::{
+    at::{AtClient, AtController, AtError},
+    at_request, warn,
+};
+
+// AT+CNTP="<host>",<timezone>
+/// Configures the NTP server, `timezone` in quarters of an hour, 0 keeps the RTC in UTC.
+pub async fn set_server<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, host: &str, timezone: i32) -> Result<(), AtError> {
+    at_request!("AT+CNTP=\"{}\",{}", host, timezone).send(client).await?;
+    Ok(())
+}
+
+// AT+CNTP
+// OK
+// +CNTP: <err>
+/// Synchronizes the module RTC with the configured NTP server, read the result with `AT+CCLK?`.
+pub async fn sync<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, timeout: Duration) -> Result<(), AtError> {
+    let response = at_request!("AT+CNTP")
+        .with_timeout(timeout)
+        .with_urc_prefix("+CNTP: ".try_into()?)
+        .send(client)
+        .await?;
+    let err: u32 = response.parse_prefixed("+CNTP: ")?;
+    if err != 0 {
+        // 1 unknown, 2 wrong parameter, 3 wrong date and time calculated, 4 network, 5 time zone, 6 timeout
+        warn!("NTP sync failed with error {}", err);
+        return Err(AtError::Error);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::{mock_request, mock_response};
+
+    #[tokio::test]
+    async fn test_set_server() -> Result<(), AtError> {
+        let mock = mock_request("AT+CNTP=\"pool.ntp.org\",0", &[]);
+        set_server(&mock, "pool.ntp.org", 0).await
+    }
+
+    #[tokio::test]
+    async fn test_sync() -> Result<(), AtError> {
+        let request = at_request!("AT+CNTP")
+            .with_timeout(Duration::from_secs(30))
+            .with_urc_prefix("+CNTP: ".try_into()?);
+        let mock = mock_response(request, &["+CNTP: 0"]);
+        sync(&mock, Duration::from_secs(30)).await?;
+
+        let request = at_request!("AT+CNTP")
+            .with_timeout(Duration::from_secs(30))
+            .with_urc_prefix("+CNTP: ".try_into()?);
+        let mock = mock_response(request, &["+CNTP: 4"]);
+        assert_eq!(sync(&mock, Duration::from_secs(30)).await, Err(AtError::Error));
+        Ok(())
+    }
+}
@@ -1,9 +1,182 @@
+use core::net::{IpAddr, Ipv6Addr};
+
+use embassy_time::Duration;
+use embedded_io_async::{Read, Write};
+use heapless::{String, Vec};
+use nom::{Parser, bytes::complete::tag, combinator::rest};
+
 use crate::{
-    at::{AtClient, AtController, AtError},
-    at_request,
+    at::{AtClient, AtController, AtControllerImpl, AtError},
+    at_request, warn,
 };
 
-pub async fn set_apn<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, apn: &str) -> Result<(), AtError> {
-    at_request!("AT+CGDCONT=1,\"IP\",\"{}\"", apn).send(client).await?;
+pub const MAX_PDP_ADDRESSES: usize = 2;
+pub const CREDENTIAL_SIZE: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PdpType {
+    #[default]
+    Ip,
+    Ipv6,
+    Ipv4v6,
+}
+
+impl PdpType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PdpType::Ip => "IP",
+            PdpType::Ipv6 => "IPV6",
+            PdpType::Ipv4v6 => "IPV4V6",
+        }
+    }
+
+    /// Whether IPv6 addresses should be preferred for this context type.
+    pub fn prefers_ipv6(&self) -> bool {
+        *self == PdpType::Ipv6
+    }
+}
+
+/// Authentication of the PDP context, the codes of `AT+CGAUTH`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthProtocol {
+    #[default]
+    None = 0,
+    Pap = 1,
+    Chap = 2,
+}
+
+/// Username and password some SIMs require for the APN, without them no authentication.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PdpAuth {
+    pub protocol: AuthProtocol,
+    pub username: String<CREDENTIAL_SIZE>,
+    pub password: String<CREDENTIAL_SIZE>,
+}
+
+impl PdpAuth {
+    pub fn new(protocol: AuthProtocol, username: &str, password: &str) -> Option<Self> {
+        Some(Self {
+            protocol,
+            username: username.try_into().ok()?,
+            password: password.try_into().ok()?,
+        })
+    }
+}
+
+// AT+CGDCONT=<cid>,<PDP_type>,<APN>
+pub async fn set_apn<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, apn: &str, pdp_type: PdpType) -> Result<(), AtError> {
+    at_request!("AT+CGDCONT=1,\"{}\",\"{}\"", pdp_type.as_str(), apn).send(client).await?;
+    Ok(())
+}
+
+// AT+CGAUTH=<cid>,<auth_type>[,<passwd>,<user>]
+/// The SIMCom order, the password before the username.
+pub async fn set_auth<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, auth: &PdpAuth) -> Result<(), AtError> {
+    match auth.protocol {
+        AuthProtocol::None => at_request!("AT+CGAUTH=1,0").send(client).await?,
+        protocol => {
+            at_request!("AT+CGAUTH=1,{},\"{}\",\"{}\"", protocol as u8, auth.password, auth.username)
+                .send(client)
+                .await?
+        }
+    };
     Ok(())
 }
+
+// ATD*99#
+/// Dials into the data mode of the default context and runs `session` on the stream, see [`AtControllerImpl::data_mode`].
+pub async fn dial_data_mode<S: Read + Write, R>(
+    ctr: &mut AtControllerImpl<S>,
+    timeout: Duration,
+    session: impl AsyncFnOnce(&mut S) -> R,
+) -> Result<R, AtError> {
+    ctr.data_mode(&at_request!("ATD*99#").with_timeout(timeout), session).await
+}
+
+/// Parses an address as reported by the module, IPv6 either in colon notation or as 16 dot separated octets.
+pub fn parse_ip_address(input: &str) -> Option<IpAddr> {
+    let input = input.trim().trim_matches('"');
+    if let Ok(ip) = input.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    let mut octets = [0u8; 16];
+    let mut count = 0;
+    for part in input.split('.') {
+        if count == octets.len() {
+            return None;
+        }
+        octets[count] = part.parse().ok()?;
+        count += 1;
+    }
+    (count == octets.len()).then(|| IpAddr::V6(Ipv6Addr::from(octets)))
+}
+
+// AT+CGPADDR=1
+// +CGPADDR: 1,10.71.155.118
+// +CGPADDR: 1,10.71.155.118,36.9.128.0.0.0.0.0.0.0.0.0.0.0.0.1
+pub async fn get_pdp_addresses<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<Vec<IpAddr, MAX_PDP_ADDRESSES>, AtError> {
+    let response = at_request!("AT+CGPADDR=1").send(client).await?;
+    let (_, (_cid, _, addresses)) = (nom::character::complete::u32, tag(","), rest).parse(response.find_prefixed("+CGPADDR: ")?)?;
+    let mut result = Vec::new();
+    for address in addresses.split(',') {
+        match parse_ip_address(address) {
+            Some(ip) => result.push(ip).map_err(|_| AtError::CapacityError)?,
+            None => warn!("invalid pdp address '{}'", address),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::mock_request;
+    use core::net::Ipv4Addr;
+
+    #[test]
+    fn test_parse_ip_address() {
+        assert_eq!(parse_ip_address("10.71.155.118"), Some(IpAddr::V4(Ipv4Addr::new(10, 71, 155, 118))));
+        assert_eq!(parse_ip_address("\"2001:db8::1\""), Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))));
+        assert_eq!(parse_ip_address("32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1"), Some(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))));
+        assert_eq!(parse_ip_address("1.2.3"), None);
+        assert_eq!(parse_ip_address("32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1.7"), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_pdp_addresses() -> Result<(), AtError> {
+        let mock = mock_request("AT+CGPADDR=1", &["+CGPADDR: 1,10.71.155.118"]);
+        let addresses = get_pdp_addresses(&mock).await?;
+        assert_eq!(addresses.as_slice(), &[IpAddr::V4(Ipv4Addr::new(10, 71, 155, 118))]);
+
+        let mock = mock_request("AT+CGPADDR=1", &["+CGPADDR: 1,10.71.155.118,32.1.13.184.0.0.0.0.0.0.0.0.0.0.0.1"]);
+        let addresses = get_pdp_addresses(&mock).await?;
+        assert_eq!(
+            addresses.as_slice(),
+            &[
+                IpAddr::V4(Ipv4Addr::new(10, 71, 155, 118)),
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_apn() -> Result<(), AtError> {
+        let mock = mock_request("AT+CGDCONT=1,\"IPV4V6\",\"gprs.swisscom.ch\"", &[]);
+        set_apn(&mock, "gprs.swisscom.ch", PdpType::Ipv4v6).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_auth() -> Result<(), AtError> {
+        let mock = mock_request("AT+CGAUTH=1,2,\"secret\",\"solar\"", &[]);
+        set_auth(&mock, &PdpAuth::new(AuthProtocol::Chap, "solar", "secret").unwrap()).await?;
+        let mock = mock_request("AT+CGAUTH=1,0", &[]);
+        set_auth(&mock, &PdpAuth::default()).await?;
+        assert_eq!(PdpAuth::new(AuthProtocol::Pap, "a-username-longer-than-32-characters", ""), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Quectel specific commands (BG95 / BG96): TCP/IP context, HTTP(S) client and sleep.
+
+use embassy_time::Duration;
+use nom::{Parser, bytes::complete::tag};
+
+use crate::{
+    at::{
+        AtClient, AtController, AtError, AtPriority,
+        http::HttpStatusCode,
+        packet_domain::{PdpAuth, PdpType},
+    },
+    at_request, warn,
+};
+
+/// Time the module takes at most to answer `AT+QIACT`.
+const CONTEXT_ACTIVATION_TIMEOUT: Duration = Duration::from_secs(150);
+
+fn context_type(pdp_type: PdpType) -> u32 {
+    match pdp_type {
+        PdpType::Ip => 1,
+        PdpType::Ipv6 => 2,
+        PdpType::Ipv4v6 => 3,
+    }
+}
+
+// AT+QICSGP=<contextID>,<context_type>,<APN>,<username>,<password>,<authentication>
+/// The authentication codes are the ones of `AT+CGAUTH`.
+pub async fn configure_context<'ch, Ctr: AtController>(
+    client: &impl AtClient<'ch, Ctr>,
+    context_id: u8,
+    apn: &str,
+    pdp_type: PdpType,
+    auth: &PdpAuth,
+) -> Result<(), AtError> {
+    at_request!("AT+QICSGP={},{},\"{}\",\"{}\",\"{}\",{}", context_id, context_type(pdp_type), apn, auth.username, auth.password, auth.protocol as u8)
+        .send(client)
+        .await?;
+    Ok(())
+}
+
+// AT+QIACT=<contextID>
+pub async fn activate_context<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context_id: u8) -> Result<(), AtError> {
+    at_request!("AT+QIACT={}", context_id)
+        .with_timeout(CONTEXT_ACTIVATION_TIMEOUT)
+        .send(client)
+        .await?;
+    Ok(())
+}
+
+// AT+QIACT?
+// +QIACT: <contextID>,<context_state>,<context_type>[,<IP_address>]
+pub async fn is_context_active<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context_id: u8) -> Result<bool, AtError> {
+    let response = at_request!("AT+QIACT?").send(client).await?;
+    for line in response.prefixed("+QIACT: ") {
+        let (_, (id, _, state)) = (nom::character::complete::u8, tag(","), nom::character::complete::u8).parse(line)?;
+        if id == context_id {
+            return Ok(state == 1);
+        }
+    }
+    Ok(false)
+}
+
+// AT+QHTTPCFG="contextid",<contextID>
+pub async fn http_set_context<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context_id: u8) -> Result<(), AtError> {
+    at_request!("AT+QHTTPCFG=\"contextid\",{}", context_id).send(client).await?;
+    Ok(())
+}
+
+// AT+QHTTPCFG="requestheader",<0/1>
+/// With the request header enabled the POST data has to start with the complete HTTP request header.
+pub async fn http_set_request_header<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, enable: bool) -> Result<(), AtError> {
+    at_request!("AT+QHTTPCFG=\"requestheader\",{}", if enable { 1 } else { 0 }).send(client).await?;
+    Ok(())
+}
+
+// AT+QHTTPURL=<URL_length>,<timeout>
+// CONNECT
+// <URL>
+// OK
+pub async fn http_set_url<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, scheme: &str, url: &str, timeout: Duration) -> Result<(), AtError> {
+    at_request!("AT+QHTTPURL={},{}", scheme.len() + url.len(), timeout.as_secs())
+        .with_timeout(timeout)
+        .send_with_data(client, &[scheme.as_bytes(), url.as_bytes()])
+        .await?;
+    Ok(())
+}
+
+// +QHTTPPOST: <err>[,<httprspcode>[,<content_length>]]
+fn parse_http_result<'a>(prefix: &'static str, input: &'a str) -> nom::IResult<&'a str, (u32, Option<u32>, Option<usize>)> {
+    let (input, (_, err)) = (tag(prefix), nom::character::complete::u32).parse(input)?;
+    let (input, status) = nom::combinator::opt((tag(","), nom::character::complete::u32).map(|(_, s)| s)).parse(input)?;
+    let (input, len) = nom::combinator::opt((tag(","), nom::character::complete::usize).map(|(_, l)| l)).parse(input)?;
+    Ok((input, (err, status, len)))
+}
+
+// AT+QHTTPPOST=<data_length>,<input_time>,<rsptime>
+// CONNECT
+// <data>
+// OK
+// +QHTTPPOST: <err>[,<httprspcode>[,<content_length>]]
+/// POSTs the concatenated `data`, returns the status and the length of the response body (0 if the server did not send one).
+pub async fn http_post<'ch, Ctr: AtController>(
+    client: &impl AtClient<'ch, Ctr>,
+    data: &[&[u8]],
+    timeout: Duration,
+) -> Result<(HttpStatusCode, usize), AtError> {
+    let len: usize = data.iter().map(|chunk| chunk.len()).sum();
+    let response = at_request!("AT+QHTTPPOST={},{},{}", len, timeout.as_secs(), timeout.as_secs())
+        .with_timeout(timeout)
+        .with_urc_prefix("+QHTTPPOST: ".try_into()?)
+        .send_with_data(client, data)
+        .await?;
+    let line = response.lines.iter().find(|l| l.starts_with("+QHTTPPOST: ")).ok_or(AtError::Error)?;
+    match parse_http_result("+QHTTPPOST: ", line.as_str())? {
+        (_, (0, Some(status), len)) => Ok((HttpStatusCode::new(status), len.unwrap_or(0))),
+        (_, (err, _, _)) => {
+            warn!("HTTP POST failed with error {}", err);
+            Err(AtError::Error)
+        }
+    }
+}
+
+// AT+QHTTPREAD=<wait_time>
+// CONNECT
+// <data>
+// OK
+// +QHTTPREAD: <err>
+/// Reads the `len` bytes of the response body, returns the number of bytes stored in `buf`.
+pub async fn http_read<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, len: usize, buf: &mut [u8], timeout: Duration) -> Result<usize, AtError> {
+    let (n, response) = at_request!("AT+QHTTPREAD={}", timeout.as_secs())
+        .with_timeout(timeout)
+        .with_priority(AtPriority::Low)
+        .with_urc_prefix("+QHTTPREAD: ".try_into()?)
+        .send_reading_data(client, len, buf)
+        .await?;
+    let line = response.lines.iter().find(|l| l.starts_with("+QHTTPREAD: ")).ok_or(AtError::Error)?;
+    match parse_http_result("+QHTTPREAD: ", line.as_str())? {
+        (_, (0, _, _)) => Ok(n),
+        (_, (err, _, _)) => {
+            warn!("HTTP read failed with error {}", err);
+            Err(AtError::Error)
+        }
+    }
+}
+
+// AT+QSCLK=<n>
+pub async fn set_sleep_enabled<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, enable: bool) -> Result<(), AtError> {
+    at_request!("AT+QSCLK={}", if enable { 1 } else { 0 }).send(client).await?;
+    Ok(())
+}
+
+// AT+QPOWD=1
+// OK
+// POWERED DOWN
+pub async fn power_down<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<(), AtError> {
+    at_request!("AT+QPOWD=1")
+        .with_timeout(Duration::from_secs(60))
+        .with_urc_prefix("POWERED DOWN".try_into()?)
+        .send(client)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::{mock_request, mock_response};
+
+    #[tokio::test]
+    async fn test_configure_context() -> Result<(), AtError> {
+        let mock = mock_request("AT+QICSGP=1,3,\"gprs.swisscom.ch\",\"\",\"\",0", &[]);
+        configure_context(&mock, 1, "gprs.swisscom.ch", PdpType::Ipv4v6, &PdpAuth::default()).await?;
+
+        let auth = PdpAuth::new(crate::at::packet_domain::AuthProtocol::Pap, "solar", "secret").unwrap();
+        let mock = mock_request("AT+QICSGP=1,1,\"iot.private\",\"solar\",\"secret\",1", &[]);
+        configure_context(&mock, 1, "iot.private", PdpType::Ip, &auth).await
+    }
+
+    #[tokio::test]
+    async fn test_is_context_active() -> Result<(), AtError> {
+        let mock = mock_request("AT+QIACT?", &["+QIACT: 1,1,1,\"10.7.157.1\""]);
+        assert!(is_context_active(&mock, 1).await?);
+
+        let mock = mock_request("AT+QIACT?", &[]);
+        assert!(!is_context_active(&mock, 1).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_activate_context() -> Result<(), AtError> {
+        let mock = mock_response(at_request!("AT+QIACT=1").with_timeout(CONTEXT_ACTIVATION_TIMEOUT), &[]);
+        activate_context(&mock, 1).await
+    }
+
+    #[test]
+    fn test_parse_http_result() {
+        let (_, result) = parse_http_result("+QHTTPPOST: ", "+QHTTPPOST: 0,200,177").unwrap();
+        assert_eq!(result, (0, Some(200), Some(177)));
+
+        let (_, result) = parse_http_result("+QHTTPPOST: ", "+QHTTPPOST: 0,204").unwrap();
+        assert_eq!(result, (0, Some(204), None));
+
+        let (_, result) = parse_http_result("+QHTTPREAD: ", "+QHTTPREAD: 702").unwrap();
+        assert_eq!(result, (702, None, None));
+    }
+}
@@ -0,0 +1,279 @@
+//! Workarounds for the firmware revisions of the module, updatable without a device firmware release.
+//!
+//! The firmware revisions of a module family differ in details, e.g. the quoting of the
+//! `AT+HTTPPARA` values or whether `AT+CSCLK` is supported. A [`QuirkTable`] maps the
+//! revision prefixes (`AT+CGMR`) to the [`Quirks`] to apply, the module resolves it with
+//! the revision probed at startup. The table is kept in the config store under
+//! [`QUIRK_TABLE`], the board loads it into [`AT_QUIRKS`] at boot. The backend replaces
+//! it with `"at_quirks":"<prefix>=<bits>,..."` in a response body, e.g.
+//! `"at_quirks":"A011B07=2,A110=0"`, [`persist_quirks`] stores the download.
+
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use heapless::{String, Vec};
+
+use crate::{
+    info,
+    storage::{ConfigKey, ConfigStore, ConfigValue, KeyValueStore},
+    warn,
+};
+
+pub const QUIRK_PREFIX_SIZE: usize = 16;
+/// Rules of the config, they fit one config value.
+pub const MAX_QUIRK_RULES: usize = 4;
+
+pub const QUIRK_TABLE: ConfigKey<QuirkTable> = ConfigKey::new("at", "quirks");
+
+pub static AT_QUIRKS: AtQuirks = AtQuirks::new();
+
+const AT_QUIRKS_KEY: &str = "\"at_quirks\":\"";
+
+/// Bitmap of the workarounds for a firmware revision.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Quirks(u32);
+
+impl Quirks {
+    pub const NONE: Quirks = Quirks(0);
+    /// `AT+HTTPPARA="SSLCFG"` takes the context id quoted, `"0"`.
+    pub const QUOTED_SSL_CONTEXT: Quirks = Quirks(1 << 0);
+    /// No `AT+CSCLK`, the module stays awake instead of sleeping.
+    pub const NO_SLEEP_MODE: Quirks = Quirks(1 << 1);
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Quirks(bits)
+    }
+
+    pub fn contains(&self, other: Quirks) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Quirks) {
+        self.0 |= other.0;
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+/// The quirks of the revisions starting with `prefix`, an empty prefix matches every revision.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QuirkRule {
+    pub prefix: String<QUIRK_PREFIX_SIZE>,
+    pub quirks: Quirks,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QuirkTable {
+    rules: Vec<QuirkRule, MAX_QUIRK_RULES>,
+}
+
+impl QuirkTable {
+    pub const fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn rules(&self) -> &[QuirkRule] {
+        &self.rules
+    }
+
+    /// Adds a rule after the others, `None` if the table is full.
+    pub fn push(&mut self, rule: QuirkRule) -> Option<()> {
+        self.rules.push(rule).ok()
+    }
+
+    /// The quirks of the first rule matching `revision`, none without a match.
+    pub fn resolve(&self, revision: &str) -> Quirks {
+        self.rules
+            .iter()
+            .find(|rule| revision.starts_with(rule.prefix.as_str()))
+            .map_or(Quirks::NONE, |rule| rule.quirks)
+    }
+
+    /// The table of `<prefix>=<bits>,...`, `None` if a rule is malformed or there are too many.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut table = QuirkTable::new();
+        for rule in text.split(',').filter(|rule| !rule.is_empty()) {
+            let (prefix, bits) = rule.split_once('=')?;
+            table.push(QuirkRule {
+                prefix: prefix.try_into().ok()?,
+                quirks: Quirks(bits.parse().ok()?),
+            })?;
+        }
+        Some(table)
+    }
+
+    /// The table asked for in a response body, `None` if there is none or it is malformed.
+    pub fn from_response(body: &str) -> Option<Self> {
+        let start = body.find(AT_QUIRKS_KEY)? + AT_QUIRKS_KEY.len();
+        let end = body[start..].find('"')?;
+        QuirkTable::parse(&body[start..start + end])
+    }
+}
+
+/// Per rule: the quirk bits little endian, the prefix length, the prefix.
+impl ConfigValue for QuirkTable {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        for rule in &self.rules {
+            let prefix = rule.prefix.as_bytes();
+            let entry = buffer.get_mut(len..len + 5 + prefix.len())?;
+            entry[..4].copy_from_slice(&rule.quirks.bits().to_le_bytes());
+            entry[4] = prefix.len() as u8;
+            entry[5..].copy_from_slice(prefix);
+            len += entry.len();
+        }
+        Some(len)
+    }
+
+    fn decode(mut data: &[u8]) -> Option<Self> {
+        let mut table = QuirkTable::new();
+        while let [b0, b1, b2, b3, len, rest @ ..] = data {
+            let prefix = rest.get(..*len as usize)?;
+            table.push(QuirkRule {
+                prefix: String::from_utf8(Vec::from_slice(prefix).ok()?).ok()?,
+                quirks: Quirks(u32::from_le_bytes([*b0, *b1, *b2, *b3])),
+            })?;
+            data = &rest[*len as usize..];
+        }
+        data.is_empty().then_some(table)
+    }
+}
+
+/// The table the module resolves its quirks with, and the downloads to store.
+pub struct AtQuirks {
+    table: Mutex<CriticalSectionRawMutex, RefCell<QuirkTable>>,
+    downloaded: Signal<CriticalSectionRawMutex, QuirkTable>,
+}
+
+impl AtQuirks {
+    pub const fn new() -> Self {
+        Self {
+            table: Mutex::new(RefCell::new(QuirkTable::new())),
+            downloaded: Signal::new(),
+        }
+    }
+
+    /// The stored table, set by the board at boot.
+    pub fn load(&self, table: QuirkTable) {
+        self.table.lock(|cell| *cell.borrow_mut() = table);
+    }
+
+    /// A table of the backend, applies from the next module startup and is stored by [`persist_quirks`].
+    pub fn download(&self, table: QuirkTable) {
+        info!("AT quirks> downloaded {} rules", table.rules().len());
+        self.load(table.clone());
+        self.downloaded.signal(table);
+    }
+
+    pub fn resolve(&self, revision: &str) -> Quirks {
+        self.table.lock(|cell| cell.borrow().resolve(revision))
+    }
+
+    pub fn table(&self) -> QuirkTable {
+        self.table.lock(|cell| cell.borrow().clone())
+    }
+
+    async fn wait_download(&self) -> QuirkTable {
+        self.downloaded.wait().await
+    }
+}
+
+impl Default for AtQuirks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Loads the stored table into [`AT_QUIRKS`], at boot before the module starts.
+pub async fn load_quirks<S: KeyValueStore>(config: &mut ConfigStore<S>) {
+    AT_QUIRKS.load(config.get_or(QUIRK_TABLE, QuirkTable::new()).await);
+}
+
+/// The table the module resolves its quirks with, the stored one or the last download.
+pub fn active_quirks() -> QuirkTable {
+    AT_QUIRKS.table()
+}
+
+/// Stores the tables downloaded into [`AT_QUIRKS`], runs forever.
+pub async fn persist_quirks<S: KeyValueStore>(mut config: ConfigStore<S>) {
+    loop {
+        let table = AT_QUIRKS.wait_download().await;
+        if let Err(e) = config.set(QUIRK_TABLE, &table).await {
+            warn!("Failed to store the AT quirks: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn table() -> QuirkTable {
+        QuirkTable::parse("A011B07=3,A011=1,=0").unwrap()
+    }
+
+    #[test]
+    fn check_resolve() {
+        let table = table();
+        assert_eq!(table.rules().len(), 3);
+        // the first match wins, the empty prefix clears the quirks of the other revisions
+        assert_eq!(table.resolve("A011B07A7670M7"), Quirks::from_bits(3));
+        assert!(table.resolve("A011B07A7670M7").contains(Quirks::NO_SLEEP_MODE));
+        assert_eq!(table.resolve("A011B03A7670M7"), Quirks::QUOTED_SSL_CONTEXT);
+        assert_eq!(table.resolve("A110B01A7672M7"), Quirks::NONE);
+        assert_eq!(QuirkTable::new().resolve("A011B07A7670M7"), Quirks::NONE);
+    }
+
+    #[test]
+    fn check_parse() {
+        assert_eq!(QuirkTable::parse(""), Some(QuirkTable::new()));
+        assert_eq!(QuirkTable::parse("A011B07"), None);
+        assert_eq!(QuirkTable::parse("A011B07=x"), None);
+        assert_eq!(QuirkTable::parse("A011B07A7670M7-TOO-LONG=1"), None);
+        assert_eq!(QuirkTable::parse("A=1,B=1,C=1,D=1,E=1"), None);
+
+        let body = r#"{"accepted_proto_version":12,"at_quirks":"A011B07=3,A011=1,=0"}"#;
+        assert_eq!(QuirkTable::from_response(body), Some(table()));
+        assert_eq!(QuirkTable::from_response(r#"{"at_quirks":"A011B07"}"#), None);
+        assert_eq!(QuirkTable::from_response(r#"{"run_at":"AT+CSQ"}"#), None);
+    }
+
+    #[test]
+    fn check_config_value() {
+        let mut buffer = [0u8; crate::storage::CONFIG_VALUE_SIZE];
+        let table = table();
+        let len = table.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..12], &[3, 0, 0, 0, 7, b'A', b'0', b'1', b'1', b'B', b'0', b'7']);
+        assert_eq!(QuirkTable::decode(&buffer[..len]), Some(table));
+        assert_eq!(QuirkTable::decode(&buffer[..len - 1]), None);
+
+        // the largest table fits one config value
+        let mut full = QuirkTable::new();
+        while full
+            .push(QuirkRule {
+                prefix: "A011B07A7670M7XY".try_into().unwrap(),
+                quirks: Quirks::from_bits(u32::MAX),
+            })
+            .is_some()
+        {}
+        assert!(full.encode(&mut buffer).is_some());
+    }
+
+    #[tokio::test]
+    async fn check_download() {
+        let quirks = AtQuirks::new();
+        quirks.load(table());
+        assert_eq!(quirks.resolve("A011B03"), Quirks::QUOTED_SSL_CONTEXT);
+        quirks.download(QuirkTable::parse("A011=2").unwrap());
+        assert_eq!(quirks.resolve("A011B03"), Quirks::NO_SLEEP_MODE);
+        assert_eq!(quirks.wait_download().await, QuirkTable::parse("A011=2").unwrap());
+    }
+}
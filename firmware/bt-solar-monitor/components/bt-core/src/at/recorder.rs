@@ -0,0 +1,139 @@
+//! Black box of the AT traffic.
+//!
+//! Keeps the last [`RECORDED_LINES`] lines sent to and received from the module, so
+//! a failing command can be traced in the field without an attached probe.
+
+use core::fmt::Write;
+
+use embassy_time::Instant;
+use heapless::{Deque, String};
+
+use crate::warn;
+
+pub const RECORDED_LINES: usize = 16;
+/// Longer lines are truncated, the start of a line is what identifies it.
+pub const RECORDED_LINE_SIZE: usize = 64;
+/// A dumped line with the timestamp and direction prefix.
+const DUMP_LINE_SIZE: usize = RECORDED_LINE_SIZE + 32;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Tx => "TX",
+            Direction::Rx => "RX",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecordedLine {
+    pub at: Instant,
+    pub direction: Direction,
+    pub line: String<RECORDED_LINE_SIZE>,
+}
+
+/// Ring buffer of the last recorded lines, the oldest line is dropped when it is full.
+#[derive(Debug, Default)]
+pub struct AtRecorder {
+    lines: Deque<RecordedLine, RECORDED_LINES>,
+}
+
+impl AtRecorder {
+    pub fn new() -> Self {
+        Self { lines: Deque::new() }
+    }
+
+    pub fn record(&mut self, direction: Direction, line: &str) {
+        self.record_at(Instant::now(), direction, line);
+    }
+
+    fn record_at(&mut self, at: Instant, direction: Direction, line: &str) {
+        if self.lines.is_full() {
+            self.lines.pop_front();
+        }
+        let mut truncated = String::new();
+        for c in line.chars() {
+            if truncated.push(c).is_err() {
+                break;
+            }
+        }
+        let _ = self.lines.push_back(RecordedLine {
+            at,
+            direction,
+            line: truncated,
+        });
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &RecordedLine> {
+        self.lines.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Logs the recorded lines, oldest first.
+    pub fn dump(&self) {
+        warn!("AT.Rec> last {} lines", self.lines.len());
+        for recorded in self.lines.iter() {
+            warn!("AT.Rec> [{}] {} {}", recorded.at.as_millis(), recorded.direction.as_str(), recorded.line.as_str());
+        }
+    }
+
+    /// Writes the recorded lines as text, one per line, e.g. as payload of a diagnostic upload.
+    ///
+    /// Stops at the first line that does not fit into `out`, without writing a part of it.
+    pub fn write_dump(&self, out: &mut impl Write) -> core::fmt::Result {
+        for recorded in self.lines.iter() {
+            let mut line = String::<DUMP_LINE_SIZE>::new();
+            writeln!(line, "[{}] {} {}", recorded.at.as_millis(), recorded.direction.as_str(), recorded.line.as_str())?;
+            out.write_str(line.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_ring_drops_oldest() {
+        let mut recorder = AtRecorder::new();
+        for i in 0..RECORDED_LINES as u64 + 2 {
+            recorder.record_at(Instant::from_millis(i), Direction::Rx, heapless::format!(8; "L{}", i).unwrap().as_str());
+        }
+        assert_eq!(recorder.len(), RECORDED_LINES);
+        assert_eq!(recorder.lines().next().unwrap().line.as_str(), "L2");
+        assert_eq!(recorder.lines().last().unwrap().at, Instant::from_millis(RECORDED_LINES as u64 + 1));
+    }
+
+    #[test]
+    fn check_write_dump() {
+        let mut recorder = AtRecorder::new();
+        recorder.record_at(Instant::from_millis(1200), Direction::Tx, "AT+CSQ");
+        recorder.record_at(Instant::from_millis(1250), Direction::Rx, "ERROR");
+        recorder.record_at(Instant::from_millis(1300), Direction::Rx, &"x".repeat(100));
+        assert_eq!(recorder.lines().last().unwrap().line.len(), RECORDED_LINE_SIZE);
+
+        let mut out = String::<64>::new();
+        assert!(recorder.write_dump(&mut out).is_err());
+        assert_eq!(out.as_str(), "[1200] TX AT+CSQ\n[1250] RX ERROR\n");
+    }
+}
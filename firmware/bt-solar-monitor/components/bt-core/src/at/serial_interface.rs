@@ -1,5 +1,4 @@
 use heapless::format;
-use nom::{Parser, bytes::complete::tag};
 
 use crate::{
     at::{AtClient, AtController, AtError},
@@ -33,8 +32,7 @@ pub async fn set_sleep_mode<'ch, Ctr: AtController>(client: &impl AtClient<'ch,
 
 pub async fn read_sleep_mode<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>) -> Result<SleepMode, AtError> {
     let response = at_request!("AT+CSCLK?").send(client).await?;
-    let (_, (_, mode)) = (tag("+CSCLK: "), nom::character::complete::u32).parse(response.line(0)?)?;
-    mode.try_into()
+    response.parse_prefixed::<u32>("+CSCLK: ")?.try_into()
 }
 
 #[cfg(test)]
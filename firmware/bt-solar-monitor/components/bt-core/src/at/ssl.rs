@@ -0,0 +1,72 @@
+use crate::{
+    at::{AtClient, AtController, AtError},
+    at_request,
+};
+
+/// The SSL context the HTTP(S) service of the module uses, see [`crate::at::http::set_ssl_context`].
+pub const HTTP_SSL_CONTEXT: u8 = 0;
+
+/// Which certificates the module verifies in the handshake, only [`AuthMode::Server`] is used.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuthMode {
+    /// The default of the module, any server is accepted.
+    None = 0,
+    /// The server certificate has to verify against the `cacert` of the context.
+    Server = 1,
+    ServerAndClient = 2,
+}
+
+// AT+CSSLCFG="authmode",<ssl_ctx_id>,<authmode>
+pub async fn set_auth_mode<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context: u8, mode: AuthMode) -> Result<(), AtError> {
+    at_request!("AT+CSSLCFG=\"authmode\",{},{}", context, mode as u8).send(client).await?;
+    Ok(())
+}
+
+// AT+CSSLCFG="cacert",<ssl_ctx_id>,<ca_file>
+/// `file` has to be downloaded to the module before, e.g. with `AT+CCERTDOWN`.
+pub async fn set_ca_cert<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context: u8, file: &str) -> Result<(), AtError> {
+    at_request!("AT+CSSLCFG=\"cacert\",{},\"{}\"", context, file).send(client).await?;
+    Ok(())
+}
+
+// AT+CSSLCFG="enableSNI",<ssl_ctx_id>,<enableSNI_flag>
+pub async fn set_sni<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, context: u8, enable: bool) -> Result<(), AtError> {
+    at_request!("AT+CSSLCFG=\"enableSNI\",{},{}", context, enable as u8).send(client).await?;
+    Ok(())
+}
+
+// AT+CCERTLIST
+// +CCERTLIST: <file_name>
+// OK
+/// Whether `file` is one of the certificates downloaded to the module.
+pub async fn has_cert<'ch, Ctr: AtController>(client: &impl AtClient<'ch, Ctr>, file: &str) -> Result<bool, AtError> {
+    let response = at_request!("AT+CCERTLIST").send(client).await?;
+    Ok(response.prefixed("+CCERTLIST: ").any(|name| name.trim_matches('"') == file))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::mocks::{mock_error, mock_request};
+
+    #[tokio::test]
+    async fn test_configure() -> Result<(), AtError> {
+        set_auth_mode(&mock_request("AT+CSSLCFG=\"authmode\",0,1", &[]), HTTP_SSL_CONTEXT, AuthMode::Server).await?;
+        set_ca_cert(&mock_request("AT+CSSLCFG=\"cacert\",0,\"pin-0123456789abcdef.pem\"", &[]), 0, "pin-0123456789abcdef.pem").await?;
+        set_sni(&mock_request("AT+CSSLCFG=\"enableSNI\",0,1", &[]), 0, true).await?;
+        let mock = mock_error("AT+CSSLCFG=\"authmode\",0,2", AtError::Error);
+        assert_eq!(set_auth_mode(&mock, 0, AuthMode::ServerAndClient).await, Err(AtError::Error));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_has_cert() -> Result<(), AtError> {
+        let mock = mock_request("AT+CCERTLIST", &["+CCERTLIST: \"ca.pem\"", "+CCERTLIST: \"pin-0123456789abcdef.pem\""]);
+        assert!(has_cert(&mock, "pin-0123456789abcdef.pem").await?);
+        let mock = mock_request("AT+CCERTLIST", &["+CCERTLIST: \"ca.pem\""]);
+        assert!(!has_cert(&mock, "pin-0123456789abcdef.pem").await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+//! Traffic counters of the AT controller, to spot a degrading UART link in the field.
+//!
+//! [`super::AtControllerImpl`] counts since the reset and publishes a snapshot to
+//! [`crate::health::HEALTH`] after every command, the health event reports it. A link
+//! that picks up noise shows as timeouts and `ERROR`s growing faster than the commands.
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AtStats {
+    /// Commands written, including the HTTP transfers.
+    pub commands: u32,
+    /// Commands without a response.
+    pub timeouts: u32,
+    /// Commands answered with `ERROR`.
+    pub errors: u32,
+    /// Unsolicited lines received while idle.
+    pub urcs: u32,
+    pub bytes_rx: u32,
+    pub bytes_tx: u32,
+}
+
+impl AtStats {
+    pub const fn new() -> Self {
+        Self {
+            commands: 0,
+            timeouts: 0,
+            errors: 0,
+            urcs: 0,
+            bytes_rx: 0,
+            bytes_tx: 0,
+        }
+    }
+
+    pub(crate) fn received(&mut self, bytes: usize) {
+        self.bytes_rx = self.bytes_rx.wrapping_add(bytes as u32);
+    }
+
+    pub(crate) fn sent(&mut self, bytes: usize) {
+        self.bytes_tx = self.bytes_tx.wrapping_add(bytes as u32);
+    }
+}
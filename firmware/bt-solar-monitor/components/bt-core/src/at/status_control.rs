@@ -6,6 +6,7 @@ use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use heapless::format;
 use nom::{Parser, branch::alt, bytes::complete::tag};
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Rssi(i32);
 
 impl core::fmt::Display for Rssi {
@@ -21,6 +22,12 @@ impl defmt::Format for Rssi {
     }
 }
 
+impl Rssi {
+    pub fn from_dbm(dbm: i32) -> Self {
+        Self(dbm)
+    }
+}
+
 impl From<Rssi> for i32 {
     fn from(value: Rssi) -> Self {
         value.0
@@ -31,7 +38,7 @@ impl From<Rssi> for i32 {
 // +CSQ: <rssi>,<ber>
 pub async fn query_signal_quality<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<(Rssi, u32), AtError> {
     let response = at_request!("AT+CSQ").send(ctr).await?;
-    let (_, (_, raw_rssi, _, raw_ber)) = (tag("+CSQ: "), nom::character::complete::i32, tag(","), nom::character::complete::u32).parse(response.line(0)?)?;
+    let (_, (raw_rssi, _, raw_ber)) = (nom::character::complete::i32, tag(","), nom::character::complete::u32).parse(response.find_prefixed("+CSQ: ")?)?;
     let rssi = match raw_rssi {
         0..=31 => Rssi(-113 + (raw_rssi * 2)),
         99 => return Err(AtError::EnumParseError("Signal strength not known or not detectable".try_into()?)),
@@ -40,6 +47,14 @@ pub async fn query_signal_quality<'ch, Ctr: AtController>(ctr: &impl AtClient<'c
     Ok((rssi, raw_ber))
 }
 
+// AT+CPIN?
+// +CPIN: READY
+/// `true` once the SIM is unlocked, `false` while it waits for a PIN or PUK.
+pub async fn query_sim_ready<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<bool, AtError> {
+    let response = at_request!("AT+CPIN?").send(ctr).await?;
+    Ok(response.find_prefixed("+CPIN: ")?.trim() == "READY")
+}
+
 // AT+CPOF
 pub async fn power_down<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<(), AtError> {
     at_request!("AT+CPOF").send(ctr).await?;
@@ -85,15 +100,56 @@ fn parse_rtc_date_time(input: &str) -> nom::IResult<&str, NaiveDateTime> {
 // +CCLK: "25/11/24,21:19:07+04"
 pub async fn query_real_time_clock<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>) -> Result<NaiveDateTime, AtError> {
     let response = at_request!("AT+CCLK?").send(ctr).await?;
-    let (_, (_, date_time, _)) = (tag("+CCLK: \""), parse_rtc_date_time, tag("\"")).parse(response.line(0)?)?;
+    parse_real_time_clock(response.line(0)?)
+}
+
+/// Parses a `+CCLK: "yy/MM/dd,hh:mm:ss±zz"` line into UTC.
+pub fn parse_real_time_clock(line: &str) -> Result<NaiveDateTime, AtError> {
+    let (_, (_, date_time, _)) = (tag("+CCLK: \""), parse_rtc_date_time, tag("\"")).parse(line)?;
     Ok(date_time)
 }
 
+// AT+CCLK="25/11/24,21:19:07+00"
+/// Sets the module RTC to `utc`, the time zone is written as +00.
+pub async fn set_real_time_clock<'ch, Ctr: AtController>(ctr: &impl AtClient<'ch, Ctr>, utc: &NaiveDateTime) -> Result<(), AtError> {
+    use chrono::{Datelike, Timelike};
+
+    if !(2000..2100).contains(&utc.year()) {
+        return Err(AtError::Error);
+    }
+    at_request!("AT+CCLK=\"{:02}/{:02}/{:02},{:02}:{:02}:{:02}+00\"", utc.year() - 2000, utc.month(), utc.day(), utc.hour(), utc.minute(), utc.second())
+        .send(ctr)
+        .await?;
+    Ok(())
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::at::mocks::mock_request;
     use chrono::{Datelike, Timelike};
 
+    #[tokio::test]
+    async fn test_set_real_time_clock() -> Result<(), AtError> {
+        let utc = NaiveDateTime::parse_from_str("2025-11-24 07:09:03", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mock = mock_request("AT+CCLK=\"25/11/24,07:09:03+00\"", &[]);
+        set_real_time_clock(&mock, &utc).await?;
+
+        let mock = mock_request("AT+CCLK?", &[]);
+        let future = NaiveDateTime::parse_from_str("2125-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(set_real_time_clock(&mock, &future).await, Err(AtError::Error));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sim_ready() -> Result<(), AtError> {
+        let mock = mock_request("AT+CPIN?", &["+CPIN: READY"]);
+        assert!(query_sim_ready(&mock).await?);
+        let mock = mock_request("AT+CPIN?", &["+CPIN: SIM PIN"]);
+        assert!(!query_sim_ready(&mock).await?);
+        Ok(())
+    }
+
     #[test]
     fn test_parse_rtc_date_time() {
         let input = "25/11/24,21:19:07+00";
@@ -0,0 +1,163 @@
+//! Trace of the completed AT commands, for the `attrace` shell command.
+//!
+//! Where the [`super::recorder::AtRecorder`] keeps the raw lines, the trace keeps one entry
+//! per command with its result and duration. A recording controller adds every command to
+//! [`AT_TRACE`], the shell dumps it as CSV so a field tech can capture a failing sequence
+//! over the USB port without RTT tooling.
+
+use core::{cell::RefCell, fmt::Write};
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, String};
+
+use crate::at::{AtError, recorder::RECORDED_LINE_SIZE};
+
+pub const TRACED_COMMANDS: usize = 32;
+/// Header of the CSV dump.
+pub const CSV_HEADER: &str = "timestamp_ms,command,result,duration_ms";
+/// A CSV line, the command quoted with room for escaped quotes.
+const CSV_LINE_SIZE: usize = 2 * RECORDED_LINE_SIZE + 48;
+
+pub static AT_TRACE: AtTrace = AtTrace::new();
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TracedCommand {
+    pub at: Instant,
+    /// Truncated like the recorded lines.
+    pub command: String<RECORDED_LINE_SIZE>,
+    /// `OK` or the name of the [`AtError`].
+    pub result: &'static str,
+    pub duration: Duration,
+}
+
+/// Ring of the last [`TRACED_COMMANDS`] commands, the oldest one is dropped when it is full.
+pub struct AtTrace {
+    commands: Mutex<CriticalSectionRawMutex, RefCell<Deque<TracedCommand, TRACED_COMMANDS>>>,
+}
+
+impl AtTrace {
+    pub const fn new() -> Self {
+        Self {
+            commands: Mutex::new(RefCell::new(Deque::new())),
+        }
+    }
+
+    pub(crate) fn record<T>(&self, command: &str, started: Instant, result: &Result<T, AtError>) {
+        let mut truncated = String::new();
+        for c in command.chars() {
+            if truncated.push(c).is_err() {
+                break;
+            }
+        }
+        let traced = TracedCommand {
+            at: started,
+            command: truncated,
+            result: result.as_ref().map_or_else(result_name, |_| "OK"),
+            duration: Instant::now().saturating_duration_since(started),
+        };
+        self.commands.lock(|commands| {
+            let mut commands = commands.borrow_mut();
+            if commands.is_full() {
+                commands.pop_front();
+            }
+            let _ = commands.push_back(traced);
+        });
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.commands.lock(|commands| commands.borrow().len())
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.commands.lock(|commands| commands.borrow_mut().clear());
+    }
+
+    /// Writes the [`CSV_HEADER`] and the traced commands, oldest first.
+    ///
+    /// The ring stays locked while writing, so `out` should be a buffer and not the port.
+    pub fn write_csv(&self, out: &mut impl Write) -> core::fmt::Result {
+        writeln!(out, "{}", CSV_HEADER)?;
+        self.commands.lock(|commands| {
+            for traced in commands.borrow().iter() {
+                let mut line = String::<CSV_LINE_SIZE>::new();
+                write!(line, "{},\"", traced.at.as_millis())?;
+                for c in traced.command.chars() {
+                    if c == '"' {
+                        line.push('"').map_err(|_| core::fmt::Error)?;
+                    }
+                    line.push(c).map_err(|_| core::fmt::Error)?;
+                }
+                writeln!(line, "\",{},{}", traced.result, traced.duration.as_millis())?;
+                out.write_str(line.as_str())?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Default for AtTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn result_name(error: &AtError) -> &'static str {
+    match error {
+        AtError::Timeout => "Timeout",
+        AtError::FormatError => "FormatError",
+        AtError::CapacityError => "CapacityError",
+        AtError::EnumParseError(_) => "EnumParseError",
+        AtError::ResponseLineCountMismatch { .. } => "ResponseLineCountMismatch",
+        AtError::Cancelled => "Cancelled",
+        AtError::Shutdown => "Shutdown",
+        AtError::Uart => "Uart",
+        AtError::UnexpectedResponse => "UnexpectedResponse",
+        AtError::NoNetwork => "NoNetwork",
+        AtError::Error => "ERROR",
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_csv() {
+        let trace = AtTrace::new();
+        let started = Instant::now();
+        trace.record(r#"AT+HTTPPARA="URL","http://example.com/a""#, started, &Ok(()));
+        trace.record::<()>("AT+CREG?", started, &Err(AtError::Error));
+        assert_eq!(trace.len(), 2);
+
+        let mut out = std::string::String::new();
+        trace.write_csv(&mut out).unwrap();
+        let lines: std::vec::Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        let started = started.as_millis();
+        assert!(lines[1].starts_with(&std::format!(r#"{},"AT+HTTPPARA=""URL"",""http://example.com/a""",OK,"#, started)));
+        assert!(lines[2].starts_with(&std::format!(r#"{},"AT+CREG?",ERROR,"#, started)));
+
+        trace.clear();
+        assert!(trace.is_empty());
+    }
+
+    #[test]
+    fn check_ring_drops_oldest() {
+        let trace = AtTrace::new();
+        for i in 0..TRACED_COMMANDS + 2 {
+            trace.record(heapless::format!(16; "AT+C{}", i).unwrap().as_str(), Instant::now(), &Ok(()));
+        }
+        assert_eq!(trace.len(), TRACED_COMMANDS);
+        let mut out = std::string::String::new();
+        trace.write_csv(&mut out).unwrap();
+        assert!(out.lines().nth(1).unwrap().contains("\"AT+C2\""));
+    }
+}
@@ -0,0 +1,408 @@
+//! Audit log of the executed commands.
+//!
+//! Command executors hand every accepted command with its origin, parameters
+//! and result to [`Audit::record`]. The [`AuditRunner`] numbers the entries,
+//! appends them to the [`AuditStore`] in flash and offers the oldest entry not
+//! yet uploaded to the cloud runner. The cloud runner uploads it as
+//! `CommandAuditEvent` and acknowledges it, only then the next one is offered.
+
+#![allow(async_fn_in_trait)]
+
+use core::fmt::Write;
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use embassy_time::Instant;
+use heapless::{String, Vec};
+use micropb::{MessageDecode, MessageEncode, PbDecoder, PbEncoder};
+
+use crate::{info, proto::bt_::solar_::CommandAuditEvent, time::UtcTime, warn};
+
+pub const AUDIT_COMMAND_SIZE: usize = 16;
+pub const AUDIT_PARAMETERS_SIZE: usize = 32;
+pub const AUDIT_RESULT_SIZE: usize = 24;
+pub const AUDIT_ENTRY_SIZE: usize = CommandAuditEvent::MAX_SIZE.expect("Size known at compile time");
+const AUDIT_QUEUE_SIZE: usize = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandOrigin {
+    /// The firmware itself, e.g. the board code.
+    Local = 0,
+    /// A command sent by the backend.
+    Backend = 1,
+    /// A rule evaluated on the device.
+    Rule = 2,
+}
+
+impl From<u32> for CommandOrigin {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => CommandOrigin::Backend,
+            2 => CommandOrigin::Rule,
+            _ => CommandOrigin::Local,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AuditEntry {
+    /// Assigned by the runner, monotonic per device.
+    pub sequence: u32,
+    /// Unix timestamp of the execution, 0 before the first time sync.
+    pub timestamp: i64,
+    pub uptime_seconds: u32,
+    pub origin: CommandOrigin,
+    pub command: String<AUDIT_COMMAND_SIZE>,
+    pub parameters: String<AUDIT_PARAMETERS_SIZE>,
+    pub success: bool,
+    /// The error of a failed command.
+    pub result: String<AUDIT_RESULT_SIZE>,
+}
+
+impl AuditEntry {
+    /// Encoding for the store, the same as the uploaded `CommandAuditEvent`.
+    pub fn encode(&self) -> Option<Vec<u8, AUDIT_ENTRY_SIZE>> {
+        let mut buffer = micropb::heapless::Vec::<u8, AUDIT_ENTRY_SIZE>::new();
+        let mut encoder = PbEncoder::new(&mut buffer);
+        self.to_event().encode(&mut encoder).ok()?;
+        Vec::from_slice(buffer.as_slice()).ok()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut event = CommandAuditEvent::default();
+        let mut decoder = PbDecoder::new(data);
+        event.decode(&mut decoder, data.len()).ok()?;
+        Some(Self {
+            sequence: event.sequence,
+            timestamp: event.timestamp,
+            uptime_seconds: event.uptime_seconds,
+            origin: event.origin.into(),
+            command: event.command.as_str().try_into().ok()?,
+            parameters: event.parameters.as_str().try_into().ok()?,
+            success: event.success,
+            result: event.result.as_str().try_into().ok()?,
+        })
+    }
+
+    pub(crate) fn to_event(&self) -> CommandAuditEvent {
+        let mut event = CommandAuditEvent {
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            uptime_seconds: self.uptime_seconds,
+            origin: self.origin as u32,
+            success: self.success,
+            ..Default::default()
+        };
+        // the generated strings have the same capacities
+        let _ = event.command.push_str(self.command.as_str());
+        let _ = event.parameters.push_str(self.parameters.as_str());
+        let _ = event.result.push_str(self.result.as_str());
+        event
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AuditError {
+    /// Reading or writing the flash failed.
+    Storage,
+    /// A stored entry does not decode.
+    Corrupt,
+}
+
+/// Position of the log, persisted with every change.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AuditCursors {
+    /// Sequence number of the next appended entry.
+    pub next: u32,
+    /// Sequence number of the oldest entry not yet uploaded.
+    pub upload: u32,
+}
+
+/// Flash backed log, the board decides how many entries it keeps.
+pub trait AuditStore {
+    /// The persisted cursors, the defaults for an empty log.
+    async fn cursors(&mut self) -> Result<AuditCursors, AuditError>;
+    async fn store_cursors(&mut self, cursors: &AuditCursors) -> Result<(), AuditError>;
+    /// Persists `entry`, once the log is full it replaces the oldest one.
+    async fn append(&mut self, entry: &AuditEntry) -> Result<(), AuditError>;
+    /// The entry with `sequence`, `None` if it was replaced already.
+    async fn read(&mut self, sequence: u32) -> Result<Option<AuditEntry>, AuditError>;
+}
+
+/// Link between the command executors, the [`AuditRunner`] and the cloud runner.
+pub struct Audit {
+    records: Channel<NoopRawMutex, AuditEntry, AUDIT_QUEUE_SIZE>,
+    offered: Signal<NoopRawMutex, AuditEntry>,
+    uploaded: Signal<NoopRawMutex, u32>,
+}
+
+impl Audit {
+    pub fn new() -> Self {
+        Self {
+            records: Channel::new(),
+            offered: Signal::new(),
+            uploaded: Signal::new(),
+        }
+    }
+
+    pub fn runner<S: AuditStore>(&self, store: S) -> AuditRunner<'_, S> {
+        AuditRunner {
+            audit: self,
+            store,
+            cursors: AuditCursors::default(),
+            offered: None,
+        }
+    }
+
+    /// Records an executed command, longer strings are truncated.
+    pub async fn record<E: core::fmt::Debug>(&self, origin: CommandOrigin, command: &str, parameters: &str, result: Result<(), E>) {
+        let mut entry = AuditEntry {
+            sequence: 0,
+            timestamp: UtcTime::now().await.map_or(0, |now| now.and_utc().timestamp()),
+            uptime_seconds: Instant::now().as_secs() as u32,
+            origin,
+            command: truncated(command),
+            parameters: truncated(parameters),
+            success: result.is_ok(),
+            result: String::new(),
+        };
+        if let Err(e) = result {
+            // a too long error is cut at the formatting step that does not fit
+            let _ = write!(entry.result, "{:?}", e);
+        }
+        self.records.send(entry).await;
+    }
+
+    pub(crate) fn take_offered(&self) -> Option<AuditEntry> {
+        self.offered.try_take()
+    }
+
+    pub(crate) async fn wait_offered(&self) -> AuditEntry {
+        self.offered.wait().await
+    }
+
+    /// Confirms the upload of the offered entry with `sequence`.
+    pub(crate) fn acknowledge(&self, sequence: u32) {
+        self.uploaded.signal(sequence);
+    }
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn truncated<const N: usize>(value: &str) -> String<N> {
+    let mut truncated = String::new();
+    for c in value.chars() {
+        if truncated.push(c).is_err() {
+            break;
+        }
+    }
+    truncated
+}
+
+pub struct AuditRunner<'a, S: AuditStore> {
+    audit: &'a Audit,
+    store: S,
+    cursors: AuditCursors,
+    /// Sequence number of the entry offered to the cloud runner.
+    offered: Option<u32>,
+}
+
+impl<'a, S: AuditStore> AuditRunner<'a, S> {
+    pub async fn run(mut self) {
+        self.load().await;
+        loop {
+            self.once().await;
+        }
+    }
+
+    async fn load(&mut self) {
+        match self.store.cursors().await {
+            Ok(cursors) => self.cursors = cursors,
+            Err(e) => warn!("Audit> loading the cursors failed: {:?}", e),
+        }
+        info!("Audit> {} entries, {} to upload", self.cursors.next, self.cursors.next - self.cursors.upload);
+    }
+
+    async fn once(&mut self) {
+        if self.offered.is_none() {
+            self.offer().await;
+        }
+        match select(self.audit.records.receive(), self.audit.uploaded.wait()).await {
+            Either::First(mut entry) => {
+                entry.sequence = self.cursors.next;
+                info!("Audit> #{} {:?} {} {}", entry.sequence, entry.origin, entry.command.as_str(), entry.parameters.as_str());
+                if let Err(e) = self.store.append(&entry).await {
+                    warn!("Audit> appending #{} failed: {:?}", entry.sequence, e);
+                }
+                self.cursors.next += 1;
+                self.store_cursors().await;
+            }
+            Either::Second(sequence) => {
+                if self.offered == Some(sequence) {
+                    self.offered = None;
+                    self.cursors.upload = sequence + 1;
+                    self.store_cursors().await;
+                }
+            }
+        }
+    }
+
+    /// Offers the oldest entry not yet uploaded, entries already replaced in the store are skipped.
+    async fn offer(&mut self) {
+        while self.cursors.upload < self.cursors.next {
+            match self.store.read(self.cursors.upload).await {
+                Ok(Some(entry)) => {
+                    self.offered = Some(entry.sequence);
+                    self.audit.offered.signal(entry);
+                    return;
+                }
+                Ok(None) | Err(AuditError::Corrupt) => {
+                    warn!("Audit> #{} lost before the upload", self.cursors.upload);
+                    self.cursors.upload += 1;
+                }
+                Err(e) => {
+                    warn!("Audit> reading #{} failed: {:?}", self.cursors.upload, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn store_cursors(&mut self) {
+        if let Err(e) = self.store.store_cursors(&self.cursors).await {
+            warn!("Audit> storing the cursors failed: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Keeps the last `capacity` entries like a ring in flash.
+    #[derive(Default)]
+    pub(crate) struct RamStore {
+        entries: std::vec::Vec<std::vec::Vec<u8>>,
+        capacity: usize,
+        pub(crate) cursors: AuditCursors,
+    }
+
+    impl RamStore {
+        pub(crate) fn new(capacity: usize) -> Self {
+            Self {
+                capacity,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl AuditStore for &mut RamStore {
+        async fn cursors(&mut self) -> Result<AuditCursors, AuditError> {
+            Ok(self.cursors)
+        }
+
+        async fn store_cursors(&mut self, cursors: &AuditCursors) -> Result<(), AuditError> {
+            self.cursors = *cursors;
+            Ok(())
+        }
+
+        async fn append(&mut self, entry: &AuditEntry) -> Result<(), AuditError> {
+            if self.entries.len() == self.capacity {
+                self.entries.remove(0);
+            }
+            self.entries.push(entry.encode().ok_or(AuditError::Corrupt)?.to_vec());
+            Ok(())
+        }
+
+        async fn read(&mut self, sequence: u32) -> Result<Option<AuditEntry>, AuditError> {
+            Ok(self
+                .entries
+                .iter()
+                .filter_map(|data| AuditEntry::decode(data))
+                .find(|entry| entry.sequence == sequence))
+        }
+    }
+
+    #[test]
+    fn check_encode_decode() {
+        let entry = AuditEntry {
+            sequence: 7,
+            timestamp: 1_767_107_342,
+            uptime_seconds: 3600,
+            origin: CommandOrigin::Backend,
+            command: "load".try_into().unwrap(),
+            parameters: "off".try_into().unwrap(),
+            success: false,
+            result: "Timeout".try_into().unwrap(),
+        };
+        assert_eq!(AuditEntry::decode(&entry.encode().unwrap()), Some(entry));
+    }
+
+    #[test]
+    fn check_truncated() {
+        assert_eq!(truncated::<4>("load").as_str(), "load");
+        assert_eq!(truncated::<4>("switch").as_str(), "swit");
+        assert_eq!(truncated::<3>("aé").as_str(), "aé");
+        assert_eq!(truncated::<2>("aé").as_str(), "a");
+    }
+
+    #[tokio::test]
+    async fn check_record_and_upload() {
+        let audit = Audit::new();
+        let mut store = RamStore::new(8);
+        let mut runner = audit.runner(&mut store);
+        runner.load().await;
+
+        audit.record::<()>(CommandOrigin::Backend, "load", "on", Ok(())).await;
+        audit.record(CommandOrigin::Rule, "load", "off", Err("Timeout")).await;
+        runner.once().await;
+        runner.once().await;
+        assert_eq!(runner.cursors, AuditCursors { next: 2, upload: 0 });
+
+        let entry = audit.take_offered().unwrap();
+        assert_eq!(entry.sequence, 0);
+        assert_eq!(entry.parameters.as_str(), "on");
+        assert!(entry.success);
+        audit.acknowledge(0);
+        runner.once().await;
+        assert_eq!(runner.cursors, AuditCursors { next: 2, upload: 1 });
+
+        runner.offer().await;
+        let entry = audit.take_offered().unwrap();
+        assert_eq!(entry.sequence, 1);
+        assert_eq!(entry.origin, CommandOrigin::Rule);
+        assert!(!entry.success);
+        assert_eq!(entry.result.as_str(), "\"Timeout\"");
+    }
+
+    #[tokio::test]
+    async fn check_replaced_entries_are_skipped() {
+        let audit = Audit::new();
+        let mut store = RamStore::new(2);
+        let mut runner = audit.runner(&mut store);
+        for parameters in ["1", "2", "3"] {
+            audit.record::<()>(CommandOrigin::Local, "load", parameters, Ok(())).await;
+            runner.once().await;
+        }
+        // #0 was offered with the second record and is still in flight
+        assert_eq!(audit.take_offered().unwrap().sequence, 0);
+        audit.acknowledge(0);
+        runner.once().await;
+        runner.offer().await;
+        assert_eq!(audit.take_offered().unwrap().sequence, 1);
+
+        runner.offered = None;
+        runner.cursors.upload = 0;
+        runner.offer().await;
+        assert_eq!(runner.cursors.upload, 1);
+        assert_eq!(audit.take_offered().unwrap().sequence, 1);
+    }
+}
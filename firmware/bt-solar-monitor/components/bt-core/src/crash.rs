@@ -0,0 +1,171 @@
+//! Crash information that survives the reset after a panic or hard fault.
+//!
+//! The fault handler can neither allocate nor wait for the flash, it only
+//! fills a [`CrashRecord`] kept in retained (`.uninit`) RAM. On the next boot
+//! the board takes the record as [`CrashReport`], persists it and hands it to
+//! the cloud runner which uploads it as `CrashEvent`.
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+use micropb::{MessageDecode, MessageEncode, PbDecoder, PbEncoder};
+
+use crate::proto::bt_::solar_::CrashEvent;
+
+pub const CRASH_MESSAGE_SIZE: usize = 96;
+pub const CRASH_REPORT_SIZE: usize = CrashEvent::MAX_SIZE.expect("Size known at compile time");
+const CRASH_RECORD_MAGIC: u32 = 0xC0A5_4ED1;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CrashReport {
+    pub uptime_seconds: u32,
+    /// Program counter of the fault, 0 for a panic.
+    pub pc: u32,
+    pub message: String<CRASH_MESSAGE_SIZE>,
+}
+
+impl CrashReport {
+    /// Encoding for the key value store, the same as the uploaded `CrashEvent`.
+    pub fn encode(&self) -> Option<Vec<u8, CRASH_REPORT_SIZE>> {
+        let mut buffer = micropb::heapless::Vec::<u8, CRASH_REPORT_SIZE>::new();
+        let mut encoder = PbEncoder::new(&mut buffer);
+        self.to_event().encode(&mut encoder).ok()?;
+        Vec::from_slice(buffer.as_slice()).ok()
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut event = CrashEvent::default();
+        let mut decoder = PbDecoder::new(data);
+        event.decode(&mut decoder, data.len()).ok()?;
+        Some(Self {
+            uptime_seconds: event.uptime_seconds,
+            pc: event.pc,
+            message: event.message.as_str().try_into().ok()?,
+        })
+    }
+
+    pub(crate) fn to_event(&self) -> CrashEvent {
+        let mut message = micropb::heapless::String::new();
+        let _ = message.push_str(self.message.as_str());
+        CrashEvent {
+            uptime_seconds: self.uptime_seconds,
+            pc: self.pc,
+            message,
+        }
+    }
+}
+
+/// Crash record placed in retained RAM by the board, any bit pattern is a valid (empty or garbage) record.
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    uptime_seconds: u32,
+    pc: u32,
+    len: u32,
+    message: [u8; CRASH_MESSAGE_SIZE],
+}
+
+impl CrashRecord {
+    pub const fn new() -> Self {
+        Self {
+            magic: 0,
+            uptime_seconds: 0,
+            pc: 0,
+            len: 0,
+            message: [0; CRASH_MESSAGE_SIZE],
+        }
+    }
+
+    /// Records a crash, the message is truncated to [`CRASH_MESSAGE_SIZE`] bytes.
+    pub fn record(&mut self, uptime_seconds: u32, pc: u32, message: core::fmt::Arguments<'_>) {
+        let mut writer = TruncatingWriter {
+            buffer: &mut self.message,
+            len: 0,
+        };
+        let _ = writer.write_fmt(message);
+        self.len = writer.len as u32;
+        self.uptime_seconds = uptime_seconds;
+        self.pc = pc;
+        self.magic = CRASH_RECORD_MAGIC;
+    }
+
+    /// Takes the recorded crash, if any, and clears the record.
+    pub fn take(&mut self) -> Option<CrashReport> {
+        if self.magic != CRASH_RECORD_MAGIC {
+            return None;
+        }
+        self.magic = 0;
+        let message = self.message.get(..self.len as usize)?;
+        Some(CrashReport {
+            uptime_seconds: self.uptime_seconds,
+            pc: self.pc,
+            message: core::str::from_utf8(message).ok()?.try_into().ok()?,
+        })
+    }
+}
+
+impl Default for CrashRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TruncatingWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let mut utf8 = [0u8; 4];
+            let encoded = c.encode_utf8(&mut utf8).as_bytes();
+            if self.len + encoded.len() > self.buffer.len() {
+                return Err(core::fmt::Error);
+            }
+            self.buffer[self.len..self.len + encoded.len()].copy_from_slice(encoded);
+            self.len += encoded.len();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_take() {
+        let mut record = CrashRecord::new();
+        assert_eq!(record.take(), None);
+
+        record.record(42, 0x1234, format_args!("panicked at {}:{}", "src/main.rs", 7));
+        let report = record.take().unwrap();
+        assert_eq!(report.uptime_seconds, 42);
+        assert_eq!(report.pc, 0x1234);
+        assert_eq!(report.message.as_str(), "panicked at src/main.rs:7");
+        assert_eq!(record.take(), None);
+    }
+
+    #[test]
+    fn test_record_truncates() {
+        let mut record = CrashRecord::new();
+        let long = "äöü".repeat(CRASH_MESSAGE_SIZE);
+        record.record(1, 0, format_args!("{}", long));
+        let report = record.take().unwrap();
+        assert_eq!(report.message.len(), CRASH_MESSAGE_SIZE);
+        assert!(report.message.starts_with("äöü"));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let report = CrashReport {
+            uptime_seconds: 3600,
+            pc: 0x0002_a4f0,
+            message: "HardFault".try_into().unwrap(),
+        };
+        let encoded = report.encode().unwrap();
+        assert_eq!(CrashReport::decode(&encoded), Some(report));
+    }
+}
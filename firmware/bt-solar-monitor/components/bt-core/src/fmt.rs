@@ -7,13 +7,14 @@ use core::fmt::{Debug, Display, LowerHex};
 #[cfg(all(feature = "defmt", feature = "log"))]
 compile_error!("You may not enable both `defmt` and `log` features.");
 
+// The host tests assert and panic with std types without `defmt::Format`, they use core.
 #[collapse_debuginfo(yes)]
 macro_rules! assert {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::assert!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::assert!($($x)*);
         }
     };
@@ -23,9 +24,9 @@ macro_rules! assert {
 macro_rules! assert_eq {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::assert_eq!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::assert_eq!($($x)*);
         }
     };
@@ -35,9 +36,9 @@ macro_rules! assert_eq {
 macro_rules! assert_ne {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::assert_ne!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::assert_ne!($($x)*);
         }
     };
@@ -47,9 +48,9 @@ macro_rules! assert_ne {
 macro_rules! debug_assert {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::debug_assert!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::debug_assert!($($x)*);
         }
     };
@@ -59,9 +60,9 @@ macro_rules! debug_assert {
 macro_rules! debug_assert_eq {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::debug_assert_eq!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::debug_assert_eq!($($x)*);
         }
     };
@@ -71,9 +72,9 @@ macro_rules! debug_assert_eq {
 macro_rules! debug_assert_ne {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::debug_assert_ne!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::debug_assert_ne!($($x)*);
         }
     };
@@ -83,9 +84,9 @@ macro_rules! debug_assert_ne {
 macro_rules! todo {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::todo!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::todo!($($x)*);
         }
     };
@@ -95,9 +96,9 @@ macro_rules! todo {
 macro_rules! unreachable {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::unreachable!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::unreachable!($($x)*);
         }
     };
@@ -107,9 +108,9 @@ macro_rules! unreachable {
 macro_rules! panic {
     ($($x:tt)*) => {
         {
-            #[cfg(not(feature = "defmt"))]
+            #[cfg(any(test, not(feature = "defmt")))]
             ::core::panic!($($x)*);
-            #[cfg(feature = "defmt")]
+            #[cfg(all(not(test), feature = "defmt"))]
             ::defmt::panic!($($x)*);
         }
     };
@@ -120,11 +121,16 @@ macro_rules! panic {
 macro_rules! trace {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
-            #[cfg(feature = "log")]
-            ::log::trace!($s $(, $x)*);
-            #[cfg(feature = "defmt")]
-            ::defmt::trace!($s $(, $x)*);
-            #[cfg(not(any(feature = "log", feature="defmt")))]
+            // suppressed while a host link is busy, see the `activity` module
+            #[cfg(all(feature = "log", not(feature = "release-log")))]
+            if $crate::fmt::verbose() {
+                ::log::trace!($s $(, $x)*);
+            }
+            #[cfg(all(feature = "defmt", not(feature = "release-log")))]
+            if $crate::fmt::verbose() {
+                ::defmt::trace!($s $(, $x)*);
+            }
+            #[cfg(any(feature = "release-log", not(any(feature = "log", feature="defmt"))))]
             let _ = ($( & $x ),*);
         }
     };
@@ -135,11 +141,16 @@ macro_rules! trace {
 macro_rules! debug {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
-            #[cfg(feature = "log")]
-            ::log::debug!($s $(, $x)*);
-            #[cfg(feature = "defmt")]
-            ::defmt::debug!($s $(, $x)*);
-            #[cfg(not(any(feature = "log", feature="defmt")))]
+            // suppressed while a host link is busy, see the `activity` module
+            #[cfg(all(feature = "log", not(feature = "release-log")))]
+            if $crate::fmt::verbose() {
+                ::log::debug!($s $(, $x)*);
+            }
+            #[cfg(all(feature = "defmt", not(feature = "release-log")))]
+            if $crate::fmt::verbose() {
+                ::defmt::debug!($s $(, $x)*);
+            }
+            #[cfg(any(feature = "release-log", not(any(feature = "log", feature="defmt"))))]
             let _ = ($( & $x ),*);
         }
     };
@@ -167,8 +178,12 @@ macro_rules! warn {
         {
             #[cfg(feature = "log")]
             ::log::warn!($s $(, $x)*);
+            // the frame is kept in the log ring of the board, if it has one
             #[cfg(feature = "defmt")]
-            ::defmt::warn!($s $(, $x)*);
+            {
+                let _capture = $crate::fmt::capture_log();
+                ::defmt::warn!($s $(, $x)*);
+            }
             #[cfg(not(any(feature = "log", feature="defmt")))]
             let _ = ($( & $x ),*);
         }
@@ -182,8 +197,12 @@ macro_rules! error {
         {
             #[cfg(feature = "log")]
             ::log::error!($s $(, $x)*);
+            // the frame is kept in the log ring of the board, if it has one
             #[cfg(feature = "defmt")]
-            ::defmt::error!($s $(, $x)*);
+            {
+                let _capture = $crate::fmt::capture_log();
+                ::defmt::error!($s $(, $x)*);
+            }
             #[cfg(not(any(feature = "log", feature="defmt")))]
             let _ = ($( & $x ),*);
         }
@@ -221,6 +240,18 @@ macro_rules! unwrap {
     }
 }
 
+/// For the `trace!` and `debug!` macros, see [`crate::activity::Activity::verbose`].
+#[doc(hidden)]
+pub fn verbose() -> bool {
+    crate::activity::ACTIVITY.verbose()
+}
+
+/// For the `warn!` and `error!` macros, see [`crate::log_ring::LogRing::capture`].
+#[doc(hidden)]
+pub fn capture_log() -> crate::log_ring::Capture<'static> {
+    crate::log_ring::LOG_RING.capture()
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct NoneError;
 
@@ -287,26 +318,121 @@ pub trait FormatRequirement {}
 
 pub struct FormatableNaiveDateTime<'a>(pub &'a NaiveDateTime);
 
+fn write_date_time(f: &mut core::fmt::Formatter<'_>, date_time: &NaiveDateTime, separator: char) -> core::fmt::Result {
+    use chrono::Datelike;
+    use chrono::Timelike;
+
+    write!(
+        f,
+        "{:04}-{:02}-{:02}{}{:02}:{:02}:{:02}",
+        date_time.year(),
+        date_time.month(),
+        date_time.day(),
+        separator,
+        date_time.hour(),
+        date_time.minute(),
+        date_time.second()
+    )
+}
+
 //#[cfg(feature = "log")]
 impl Display for FormatableNaiveDateTime<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        use chrono::Datelike;
-        use chrono::Timelike;
-
-        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.0.year(), self.0.month(), self.0.day(), self.0.hour(), self.0.minute(), self.0.second())
+        write_date_time(f, self.0, ' ')
     }
 }
 
 #[cfg(feature = "defmt")]
 impl<'a> defmt::Format for FormatableNaiveDateTime<'a> {
     fn format(&self, fmt: defmt::Formatter) {
-        use chrono::Datelike;
-        use chrono::Timelike;
+        defmt::write!(fmt, "{}", defmt::Display2Format(self));
+    }
+}
+
+/// `2025-11-30T12:30:21Z`
+pub const ISO_8601_SIZE: usize = 20;
+
+/// UTC date time in ISO-8601 notation, e.g. `2025-11-30T12:30:21Z`.
+pub struct Iso8601<'a>(pub &'a NaiveDateTime);
+
+impl Display for Iso8601<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_date_time(f, self.0, 'T')?;
+        f.write_str("Z")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<'a> defmt::Format for Iso8601<'a> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}", defmt::Display2Format(self));
+    }
+}
+
+impl Iso8601<'_> {
+    /// Renders into a fixed buffer, e.g. for protocol fields.
+    pub fn to_buffer(&self) -> heapless::String<ISO_8601_SIZE> {
+        use core::fmt::Write;
+
+        let mut buffer = heapless::String::new();
+        // years up to 9999 always fit
+        let _ = write!(buffer, "{}", self);
+        buffer
+    }
+}
+
+/// Duration with a unit, `250 ms`, `4.500 s` or `1h 05m 20s`.
+pub struct FormatableDuration(pub embassy_time::Duration);
 
-        defmt::write!(fmt, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.0.year(), self.0.month(), self.0.day(), self.0.hour(), self.0.minute(), self.0.second());
+impl Display for FormatableDuration {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let millis = self.0.as_millis();
+        let secs = self.0.as_secs();
+        if millis < 1000 {
+            write!(f, "{} ms", millis)
+        } else if secs < 60 {
+            write!(f, "{}.{:03} s", secs, millis % 1000)
+        } else if secs < 3600 {
+            write!(f, "{}m {:02}s", secs / 60, secs % 60)
+        } else {
+            write!(f, "{}h {:02}m {:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for FormatableDuration {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}", defmt::Display2Format(self));
     }
 }
 
+macro_rules! unit_display {
+    ($name:ident, $precision:literal, $unit:literal) => {
+        #[doc = concat!("Value in ", $unit, " printed with its unit.")]
+        #[derive(Debug, Copy, Clone, PartialEq)]
+        pub struct $name(pub f32);
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, concat!("{:.", $precision, "} ", $unit), self.0)
+            }
+        }
+
+        #[cfg(feature = "defmt")]
+        impl defmt::Format for $name {
+            fn format(&self, fmt: defmt::Formatter) {
+                defmt::write!(fmt, "{}", defmt::Display2Format(self));
+            }
+        }
+    };
+}
+
+unit_display!(Voltage, 2, "V");
+unit_display!(Current, 2, "A");
+unit_display!(Power, 1, "W");
+unit_display!(Energy, 2, "kWh");
+
 /*
 #[cfg(feature = "defmt")]
 impl<T: fmt::Display + ?Sized> fmt::Display for Display2Format<'_, T> {
@@ -327,3 +453,34 @@ impl<T: fmt::Display + ?Sized> Format for Display2Format<'_, T> {
     }
 }
 */
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use embassy_time::Duration;
+    use std::format;
+
+    #[test]
+    fn test_date_time() {
+        let date_time = NaiveDateTime::parse_from_str("2025-11-30 12:30:21", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(format!("{}", FormatableNaiveDateTime(&date_time)), "2025-11-30 12:30:21");
+        assert_eq!(Iso8601(&date_time).to_buffer().as_str(), "2025-11-30T12:30:21Z");
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(format!("{}", FormatableDuration(Duration::from_millis(250))), "250 ms");
+        assert_eq!(format!("{}", FormatableDuration(Duration::from_millis(4500))), "4.500 s");
+        assert_eq!(format!("{}", FormatableDuration(Duration::from_secs(5 * 60 + 2))), "5m 02s");
+        assert_eq!(format!("{}", FormatableDuration(Duration::from_secs(3600 + 5 * 60 + 20))), "1h 05m 20s");
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(format!("{}", Voltage(12.345)), "12.35 V");
+        assert_eq!(format!("{}", Current(-0.5)), "-0.50 A");
+        assert_eq!(format!("{}", Power(42.04)), "42.0 W");
+        assert_eq!(format!("{}", Energy(1.2)), "1.20 kWh");
+    }
+}
@@ -0,0 +1,130 @@
+//! Health counters of the firmware, for monitoring the fleet and not just the solar data.
+//!
+//! The drivers count their failures in [`HEALTH`], the cloud runner uploads them as a
+//! `HealthEvent` together with the uptime, the reset reason and the stack watermark of the
+//! board, see `cloud::Runner::with_health_report`. The counters start over with every reset.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+use crate::{at::stats::AtStats, proto::bt_::solar_::HealthEvent};
+
+pub static HEALTH: Health = Health::new();
+
+/// Cause of the last reset of the MCU, as far as the board can tell.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetReason {
+    #[default]
+    Unknown = 0,
+    PowerOn = 1,
+    /// The reset pin, e.g. the debugger.
+    Pin = 2,
+    Watchdog = 3,
+    /// A software reset, e.g. after a panic or a firmware update.
+    SoftReset = 4,
+    /// The CPU locked up in a fault.
+    Lockup = 5,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HealthCounters {
+    /// Resets of the cellular module after an error.
+    pub modem_restarts: u32,
+    /// VE.Direct frames and HEX messages with an invalid checksum.
+    pub checksum_errors: u32,
+    /// Published by the AT controller.
+    pub at: AtStats,
+}
+
+pub struct Health {
+    counters: Mutex<CriticalSectionRawMutex, Cell<HealthCounters>>,
+    reset_reason: Mutex<CriticalSectionRawMutex, Cell<ResetReason>>,
+}
+
+impl Health {
+    pub const fn new() -> Self {
+        Self {
+            counters: Mutex::new(Cell::new(HealthCounters {
+                modem_restarts: 0,
+                checksum_errors: 0,
+                at: AtStats::new(),
+            })),
+            reset_reason: Mutex::new(Cell::new(ResetReason::Unknown)),
+        }
+    }
+
+    /// Set by the board at boot.
+    pub fn set_reset_reason(&self, reason: ResetReason) {
+        self.reset_reason.lock(|cell| cell.set(reason));
+    }
+
+    pub fn reset_reason(&self) -> ResetReason {
+        self.reset_reason.lock(|cell| cell.get())
+    }
+
+    pub fn counters(&self) -> HealthCounters {
+        self.counters.lock(|cell| cell.get())
+    }
+
+    pub(crate) fn count(&self, update: impl FnOnce(&mut HealthCounters)) {
+        self.counters.lock(|cell| {
+            let mut counters = cell.get();
+            update(&mut counters);
+            cell.set(counters);
+        });
+    }
+
+    pub(crate) fn set_at_stats(&self, stats: AtStats) {
+        self.count(|counters| counters.at = stats);
+    }
+
+    pub fn to_event(&self, uptime_seconds: u32, stack_free_bytes: u32) -> HealthEvent {
+        let counters = self.counters();
+        HealthEvent {
+            uptime_seconds,
+            stack_free_bytes,
+            reset_reason: self.reset_reason() as u32,
+            modem_restarts: counters.modem_restarts,
+            at_errors: counters.at.errors,
+            at_timeouts: counters.at.timeouts,
+            checksum_errors: counters.checksum_errors,
+            at_commands: counters.at.commands,
+            at_urcs: counters.at.urcs,
+            at_bytes_rx: counters.at.bytes_rx,
+            at_bytes_tx: counters.at.bytes_tx,
+        }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_event() {
+        let health = Health::new();
+        health.set_reset_reason(ResetReason::Watchdog);
+        health.set_at_stats(AtStats {
+            commands: 40,
+            errors: 2,
+            bytes_tx: 512,
+            ..AtStats::new()
+        });
+        health.count(|counters| counters.modem_restarts += 1);
+        let event = health.to_event(3_600, 1_024);
+        assert_eq!(event.uptime_seconds, 3_600);
+        assert_eq!(event.stack_free_bytes, 1_024);
+        assert_eq!(event.reset_reason, 3);
+        assert_eq!((event.modem_restarts, event.at_errors, event.at_timeouts, event.checksum_errors), (1, 2, 0, 0));
+        assert_eq!((event.at_commands, event.at_bytes_tx), (40, 512));
+    }
+}
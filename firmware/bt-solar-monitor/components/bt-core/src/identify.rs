@@ -0,0 +1,106 @@
+//! Physical identification of a device, e.g. among identical boxes mounted side by side.
+//!
+//! The `identify` command of the shell or the backend (`"identify":<minutes>` in a response
+//! body) starts [`IDENTIFY`], the board flashes its status LED with [`BLINK_PATTERN`] while
+//! it is active. The shell greets with [`write_banner`], the IMEI set by the cloud runner
+//! tells which box answered.
+
+use core::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+};
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant};
+use heapless::String;
+
+use crate::at::identification::IMEI_SIZE;
+
+pub const DEFAULT_IDENTIFY_DURATION: Duration = Duration::from_secs(5 * 60);
+/// A longer identification is cut to this one, the LED should not stay busy for a forgotten command.
+pub const MAX_IDENTIFY_DURATION: Duration = Duration::from_secs(60 * 60);
+/// On and off times in milliseconds, starting with on: three flashes and a long one.
+pub const BLINK_PATTERN: [u64; 8] = [60, 60, 60, 60, 60, 60, 600, 600];
+
+pub static IDENTIFY: Identify = Identify::new();
+
+pub struct Identify {
+    until: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>>,
+    device_id: Mutex<CriticalSectionRawMutex, RefCell<String<IMEI_SIZE>>>,
+}
+
+impl Identify {
+    pub const fn new() -> Self {
+        Self {
+            until: Mutex::new(Cell::new(None)),
+            device_id: Mutex::new(RefCell::new(String::new())),
+        }
+    }
+
+    /// Identifies the device for `duration`, at most [`MAX_IDENTIFY_DURATION`].
+    pub fn start(&self, duration: Duration) {
+        let duration = duration.min(MAX_IDENTIFY_DURATION);
+        info!("Identify for {} s", duration.as_secs());
+        self.until.lock(|until| until.set(Some(Instant::now() + duration)));
+    }
+
+    pub fn stop(&self) {
+        self.until.lock(|until| until.set(None));
+    }
+
+    pub fn is_active(&self, now: Instant) -> bool {
+        self.until.lock(|until| until.get().is_some_and(|until| now < until))
+    }
+
+    /// Set once the cloud runner knows the module.
+    pub fn set_device_id(&self, device_id: &str) {
+        self.device_id.lock(|id| {
+            let mut id = id.borrow_mut();
+            id.clear();
+            let _ = id.push_str(device_id);
+        });
+    }
+
+    /// Empty until the identity of the module is known.
+    pub fn device_id(&self) -> String<IMEI_SIZE> {
+        self.device_id.lock(|id| id.borrow().clone())
+    }
+}
+
+impl Default for Identify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the greeting of the shell: the firmware, the IMEI and the uptime.
+pub fn write_banner(out: &mut impl Write, firmware: &str) -> core::fmt::Result {
+    let device_id = IDENTIFY.device_id();
+    let device_id = if device_id.is_empty() { "unknown" } else { device_id.as_str() };
+    write!(out, "bt-solar-monitor {}\r\nid {}\r\nup {} s\r\n", firmware, device_id, Instant::now().as_secs())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_duration_bounded() {
+        let identify = Identify::new();
+        assert!(!identify.is_active(Instant::now()));
+        identify.start(Duration::from_secs(24 * 60 * 60));
+        let now = Instant::now();
+        assert!(identify.is_active(now));
+        assert!(!identify.is_active(now + MAX_IDENTIFY_DURATION + Duration::from_secs(1)));
+        identify.stop();
+        assert!(!identify.is_active(now));
+    }
+
+    #[test]
+    fn check_banner() {
+        let mut out = std::string::String::new();
+        write_banner(&mut out, "0.4.2").unwrap();
+        assert!(out.starts_with("bt-solar-monitor 0.4.2\r\nid "), "{}", out);
+        assert!(out.ends_with(" s\r\n"), "{}", out);
+    }
+}
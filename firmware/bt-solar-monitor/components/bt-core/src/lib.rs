@@ -5,12 +5,32 @@ use embassy_sync::{
     mutex::{Mutex, MutexGuard},
 };
 
-pub mod at;
+pub mod prelude;
+
+// The apps import from `prelude`, the modules behind it are private. `fmt` is
+// public for the exported logging macros only.
+mod activity;
+mod at;
+mod audit;
+mod crash;
+#[doc(hidden)]
 pub mod fmt;
-pub mod net;
-pub mod sensor;
-pub mod solar_monitor;
-pub mod time;
+mod health;
+mod identify;
+mod log_ring;
+mod net;
+mod ota;
+mod poll_stats;
+mod power;
+mod schema;
+mod sensor;
+mod shared_uart;
+mod shell;
+mod solar_monitor;
+mod storage;
+mod time;
+mod timeouts;
+mod watchdog;
 
 mod proto {
     #![allow(clippy::all)]
@@ -64,6 +84,7 @@ impl<'a, M: RawMutex, T: ?Sized> Drop for LoggingMutexGuard<'a, M, T> {
 
 #[cfg(test)]
 pub mod tests {
+    pub mod mock_stream;
 
     #[cfg(feature = "log")]
     #[cfg_attr(feature = "log", ctor::ctor)]
@@ -0,0 +1,450 @@
+//! Warnings and errors of the installed devices, where no probe reads the RTT log.
+//!
+//! A board built with a log ring has a defmt global logger that hands the encoded frames
+//! to [`LOG_RING`]. Only the frames of the `warn!` and `error!` macros of [`crate::fmt`]
+//! are kept, the macros mark them with [`LogRing::capture`]. The frames stay encoded,
+//! the backend decodes them with the ELF of the firmware version.
+//!
+//! The [`LogRunner`] moves the captured frames in chunks to the [`LogStore`], a circular
+//! region in flash, so they survive resets. It defers the chunks not yet uploaded to
+//! the [`UploadScheduler`] as `Log` uploads, periodically or once requested with
+//! [`LogRing::request_upload`].
+
+#![allow(async_fn_in_trait)]
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{Deque, Vec};
+
+use crate::solar_monitor::scheduler::{LOW_PRIORITY_PAYLOAD_SIZE, UploadClass, UploadScheduler};
+
+/// A chunk in the store, one deferred upload.
+pub const LOG_CHUNK_SIZE: usize = LOW_PRIORITY_PAYLOAD_SIZE;
+/// Longest captured frame, a longer one is dropped.
+pub const LOG_FRAME_SIZE: usize = 96;
+const LOG_RAM_SIZE: usize = 2 * LOG_CHUNK_SIZE;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+pub static LOG_RING: LogRing = LogRing::new();
+
+/// Encoded frame bytes of the defmt global logger of the board, see [`LogRing::write`].
+pub fn log_frame(bytes: &[u8]) {
+    LOG_RING.write(bytes);
+}
+
+/// The runner of [`LOG_RING`], see [`LogRing::runner`].
+pub fn new<'a, S: LogStore>(store: S, scheduler: &'a UploadScheduler) -> LogRunner<'a, S> {
+    LOG_RING.runner(store, scheduler)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogError {
+    /// Reading or writing the flash failed.
+    Storage,
+    /// A stored chunk is larger than the buffer.
+    Corrupt,
+}
+
+/// Position of the chunks in the store, persisted with every change.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogCursors {
+    /// Sequence number of the next appended chunk.
+    pub next: u32,
+    /// Sequence number of the oldest chunk not yet uploaded.
+    pub upload: u32,
+}
+
+/// Flash region of the chunks, the board decides how many it keeps.
+pub trait LogStore {
+    /// The persisted cursors, the defaults for an empty region.
+    async fn cursors(&mut self) -> Result<LogCursors, LogError>;
+    async fn store_cursors(&mut self, cursors: &LogCursors) -> Result<(), LogError>;
+    /// Persists chunk `sequence`, once the region is full it replaces the oldest one.
+    async fn append(&mut self, sequence: u32, chunk: &[u8]) -> Result<(), LogError>;
+    /// Reads chunk `sequence` into `buffer`, `None` if it was replaced already.
+    async fn read(&mut self, sequence: u32, buffer: &mut [u8]) -> Result<Option<usize>, LogError>;
+}
+
+struct RingState {
+    capturing: bool,
+    /// The frame being captured, `None` once it got too long.
+    frame: Option<Vec<u8, LOG_FRAME_SIZE>>,
+    /// Frames with a one byte length prefix, the oldest are dropped for new ones.
+    frames: Deque<u8, LOG_RAM_SIZE>,
+    upload_requested: bool,
+}
+
+/// RAM ring of the captured frames, fed by the global logger.
+pub struct LogRing {
+    state: Mutex<CriticalSectionRawMutex, RefCell<RingState>>,
+}
+
+/// Ends the capture of the frame on drop, see [`LogRing::capture`].
+pub struct Capture<'a> {
+    ring: &'a LogRing,
+}
+
+impl Drop for Capture<'_> {
+    fn drop(&mut self) {
+        self.ring.end_capture();
+    }
+}
+
+impl LogRing {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(RingState {
+                capturing: false,
+                frame: None,
+                frames: Deque::new(),
+                upload_requested: false,
+            })),
+        }
+    }
+
+    /// Keeps the bytes written until the returned guard is dropped as one frame.
+    pub fn capture(&self) -> Capture<'_> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.capturing = true;
+            state.frame = Some(Vec::new());
+        });
+        Capture { ring: self }
+    }
+
+    /// Encoded frame bytes of the global logger, ignored outside of a capture.
+    pub fn write(&self, bytes: &[u8]) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if !state.capturing {
+                return;
+            }
+            if let Some(frame) = state.frame.as_mut()
+                && frame.extend_from_slice(bytes).is_err()
+            {
+                state.frame = None;
+            }
+        });
+    }
+
+    fn end_capture(&self) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.capturing = false;
+            let Some(frame) = state.frame.take().filter(|frame| !frame.is_empty()) else {
+                return;
+            };
+            while LOG_RAM_SIZE - state.frames.len() < frame.len() + 1 {
+                let len = state.frames.pop_front().unwrap_or_default();
+                for _ in 0..len {
+                    state.frames.pop_front();
+                }
+            }
+            let _ = state.frames.push_back(frame.len() as u8);
+            for byte in frame {
+                let _ = state.frames.push_back(byte);
+            }
+        });
+    }
+
+    /// Moves the oldest whole frames that fit into `chunk`, `false` if there were none.
+    pub fn take_chunk(&self, chunk: &mut Vec<u8, LOG_CHUNK_SIZE>) -> bool {
+        chunk.clear();
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            while let Some(&len) = state.frames.front() {
+                if chunk.len() + 1 + len as usize > LOG_CHUNK_SIZE {
+                    break;
+                }
+                for _ in 0..=len {
+                    let _ = chunk.push(state.frames.pop_front().unwrap_or_default());
+                }
+            }
+        });
+        !chunk.is_empty()
+    }
+
+    /// Writes the frames not yet moved to the store as hex, one line per frame, oldest first.
+    ///
+    /// The frames stay in the ring, decode them with the ELF of the firmware version.
+    pub fn write_hex(&self, out: &mut impl core::fmt::Write) -> core::fmt::Result {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            let mut bytes = state.frames.iter();
+            while let Some(&len) = bytes.next() {
+                for byte in bytes.by_ref().take(len as usize) {
+                    write!(out, "{:02x}", byte)?;
+                }
+                out.write_str("\r\n")?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Uploads the stored chunks with the next run of the [`LogRunner`], e.g. for a command of the backend.
+    pub fn request_upload(&self) {
+        self.state.lock(|state| state.borrow_mut().upload_requested = true);
+    }
+
+    pub(crate) fn take_upload_request(&self) -> bool {
+        self.state.lock(|state| core::mem::take(&mut state.borrow_mut().upload_requested))
+    }
+
+    pub fn runner<'a, S: LogStore>(&'a self, store: S, scheduler: &'a UploadScheduler) -> LogRunner<'a, S> {
+        LogRunner {
+            ring: self,
+            store,
+            scheduler,
+            cursors: LogCursors::default(),
+            upload_interval: None,
+            next_upload: Instant::now(),
+        }
+    }
+}
+
+impl Default for LogRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct LogRunner<'a, S: LogStore> {
+    ring: &'a LogRing,
+    store: S,
+    scheduler: &'a UploadScheduler,
+    cursors: LogCursors,
+    upload_interval: Option<Duration>,
+    next_upload: Instant,
+}
+
+impl<'a, S: LogStore> LogRunner<'a, S> {
+    /// Also uploads the new chunks every `interval`, without only on request.
+    pub fn with_upload_interval(mut self, interval: Duration) -> Self {
+        self.upload_interval = Some(interval);
+        self.next_upload = Instant::now() + interval;
+        self
+    }
+
+    pub async fn run(mut self) {
+        self.load().await;
+        loop {
+            Timer::after(FLUSH_INTERVAL).await;
+            self.once(Instant::now()).await;
+        }
+    }
+
+    async fn load(&mut self) {
+        match self.store.cursors().await {
+            Ok(cursors) => self.cursors = cursors,
+            Err(e) => warn!("Log> loading the cursors failed: {:?}", e),
+        }
+        info!("Log> {} chunks, {} to upload", self.cursors.next, self.cursors.next - self.cursors.upload);
+    }
+
+    async fn once(&mut self, now: Instant) {
+        self.flush().await;
+        let due = self.upload_interval.is_some_and(|_| now >= self.next_upload);
+        let requested = self.ring.take_upload_request();
+        if requested || due {
+            if let Some(interval) = self.upload_interval {
+                self.next_upload = now + interval;
+            }
+            // the periodic upload piggy-backs, the one asked for goes out right away
+            self.upload(!requested).await;
+        }
+    }
+
+    /// Appends the captured frames to the store.
+    async fn flush(&mut self) {
+        let mut chunk = Vec::new();
+        let mut appended = false;
+        while self.ring.take_chunk(&mut chunk) {
+            if let Err(e) = self.store.append(self.cursors.next, &chunk).await {
+                warn!("Log> appending chunk #{} failed: {:?}", self.cursors.next, e);
+                continue;
+            }
+            self.cursors.next += 1;
+            appended = true;
+        }
+        if appended {
+            self.store_cursors().await;
+        }
+    }
+
+    /// Defers the chunks not yet uploaded while the scheduler has room, chunks already replaced are skipped.
+    async fn upload(&mut self, piggy_back: bool) {
+        let mut buffer = [0u8; LOG_CHUNK_SIZE];
+        let start = self.cursors.upload;
+        while self.cursors.upload < self.cursors.next && self.scheduler.has_room() {
+            match self.store.read(self.cursors.upload, &mut buffer).await {
+                Ok(Some(len)) => {
+                    let _ = self.scheduler.enqueue(UploadClass::Log, &buffer[..len], piggy_back);
+                }
+                Ok(None) | Err(LogError::Corrupt) => warn!("Log> chunk #{} lost before the upload", self.cursors.upload),
+                Err(e) => {
+                    warn!("Log> reading chunk #{} failed: {:?}", self.cursors.upload, e);
+                    break;
+                }
+            }
+            self.cursors.upload += 1;
+        }
+        if self.cursors.upload != start {
+            info!("Log> deferred chunks #{} to #{}", start, self.cursors.upload - 1);
+            self.store_cursors().await;
+        }
+    }
+
+    async fn store_cursors(&mut self) {
+        if let Err(e) = self.store.store_cursors(&self.cursors).await {
+            warn!("Log> storing the cursors failed: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Keeps the last `capacity` chunks like the circular region in flash.
+    #[derive(Default)]
+    struct RamStore {
+        chunks: std::vec::Vec<(u32, std::vec::Vec<u8>)>,
+        capacity: usize,
+        cursors: LogCursors,
+    }
+
+    impl LogStore for &mut RamStore {
+        async fn cursors(&mut self) -> Result<LogCursors, LogError> {
+            Ok(self.cursors)
+        }
+
+        async fn store_cursors(&mut self, cursors: &LogCursors) -> Result<(), LogError> {
+            self.cursors = *cursors;
+            Ok(())
+        }
+
+        async fn append(&mut self, sequence: u32, chunk: &[u8]) -> Result<(), LogError> {
+            if self.chunks.len() == self.capacity {
+                self.chunks.remove(0);
+            }
+            self.chunks.push((sequence, chunk.into()));
+            Ok(())
+        }
+
+        async fn read(&mut self, sequence: u32, buffer: &mut [u8]) -> Result<Option<usize>, LogError> {
+            let Some((_, chunk)) = self.chunks.iter().find(|(s, _)| *s == sequence) else {
+                return Ok(None);
+            };
+            buffer.get_mut(..chunk.len()).ok_or(LogError::Corrupt)?.copy_from_slice(chunk);
+            Ok(Some(chunk.len()))
+        }
+    }
+
+    fn log(ring: &LogRing, frame: &[u8]) {
+        let _capture = ring.capture();
+        // the encoder writes a frame in pieces
+        for part in frame.chunks(3) {
+            ring.write(part);
+        }
+    }
+
+    #[test]
+    fn check_capture() {
+        let ring = LogRing::new();
+        ring.write(&[1, 2, 3]);
+        log(&ring, &[4, 5, 6, 7]);
+        ring.write(&[8]);
+        log(&ring, &[0; LOG_FRAME_SIZE + 1]);
+        log(&ring, &[9]);
+
+        let mut hex = std::string::String::new();
+        ring.write_hex(&mut hex).unwrap();
+        assert_eq!(hex, "04050607\r\n09\r\n");
+
+        let mut chunk = Vec::new();
+        assert!(ring.take_chunk(&mut chunk));
+        assert_eq!(chunk.as_slice(), &[4, 4, 5, 6, 7, 1, 9]);
+        assert!(!ring.take_chunk(&mut chunk));
+    }
+
+    #[test]
+    fn check_oldest_frames_dropped() {
+        let ring = LogRing::new();
+        for i in 0..12u8 {
+            log(&ring, &[i; 63]);
+        }
+        // 64 bytes per frame, the RAM keeps the last 8
+        let mut chunk = Vec::new();
+        assert!(ring.take_chunk(&mut chunk));
+        assert_eq!(chunk.len(), 4 * 64);
+        assert_eq!((chunk[0], chunk[1]), (63, 4));
+        assert!(ring.take_chunk(&mut chunk));
+        assert_eq!(chunk[1], 8);
+        assert!(!ring.take_chunk(&mut chunk));
+    }
+
+    #[tokio::test]
+    async fn check_flush_and_upload() {
+        let ring = LogRing::new();
+        let scheduler = UploadScheduler::default();
+        let mut store = RamStore {
+            capacity: 8,
+            ..Default::default()
+        };
+        let mut runner = ring.runner(&mut store, &scheduler);
+        runner.load().await;
+
+        for i in 0..6u8 {
+            log(&ring, &[i; 63]);
+            if i % 2 == 1 {
+                runner.once(Instant::now()).await;
+            }
+        }
+        assert_eq!(runner.cursors, LogCursors { next: 3, upload: 0 });
+        assert!(scheduler.is_empty());
+
+        ring.request_upload();
+        runner.once(Instant::now()).await;
+        assert_eq!(runner.cursors, LogCursors { next: 3, upload: 3 });
+        // asked for, the chunks do not wait for the next wake burst
+        assert!(scheduler.is_overdue(Instant::now()));
+        let upload = scheduler.take().unwrap();
+        assert_eq!(upload.class, UploadClass::Log);
+        assert_eq!((upload.data.len(), upload.data[1]), (128, 0));
+        assert_eq!(store.cursors, LogCursors { next: 3, upload: 3 });
+    }
+
+    #[tokio::test]
+    async fn check_upload_waits_for_room() {
+        let ring = LogRing::new();
+        let scheduler = UploadScheduler::default();
+        let mut store = RamStore {
+            capacity: 2,
+            ..Default::default()
+        };
+        let mut runner = ring.runner(&mut store, &scheduler).with_upload_interval(Duration::from_secs(60));
+        for i in 0..6u8 {
+            log(&ring, &[i; 63]);
+            runner.once(Instant::now()).await;
+        }
+        // only the last 2 chunks are still in the store
+        runner.once(Instant::now() + Duration::from_secs(61)).await;
+        assert_eq!(runner.cursors, LogCursors { next: 6, upload: 6 });
+        assert!(!scheduler.is_overdue(Instant::now()));
+        assert_eq!(scheduler.take().unwrap().data[1], 4);
+        assert_eq!(scheduler.take().unwrap().data[1], 5);
+        assert!(scheduler.take().is_none());
+
+        for _ in 0..4 {
+            scheduler.defer(UploadClass::Metrics, &[0]).unwrap();
+        }
+        log(&ring, &[6; 63]);
+        ring.request_upload();
+        runner.once(Instant::now()).await;
+        assert_eq!(runner.cursors, LogCursors { next: 7, upload: 6 });
+    }
+}
@@ -1 +1,4 @@
 pub mod cellular;
+pub(crate) mod dns;
+pub mod http;
+pub mod tls;
@@ -1,7 +1,216 @@
 #![allow(async_fn_in_trait)]
 
-use crate::at::AtError;
+use core::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use embassy_time::Duration;
+use heapless::String;
+
+use crate::{
+    at::{
+        AtCommandResponse, AtError,
+        gnss::GnssPosition,
+        http::HttpStatusCode,
+        identification::ModuleIdentity,
+        network::{AccessTechnology, LteBands, NetworkRegistrationState, OPERATOR_SIZE},
+        packet_domain::{PdpAuth, PdpType},
+        status_control::Rssi,
+    },
+    storage::{ConfigKey, ConfigValue},
+};
+pub mod power_cycles;
+pub mod ppp;
+pub mod quectel_bg9x;
 pub mod sim_com_a67;
+pub mod sleep;
+
+/// Operations the cloud client needs from a cellular module.
+///
+/// Implemented by the module drivers below `net::cellular` so the cloud logic
+/// does not depend on a specific module.
+pub trait CellularModem {
+    /// Power the module (off and) on until it answers.
+    async fn power_cycle(&mut self) -> Result<(), CellularError>;
+    /// Takes over a module that is still on after a reset of the MCU, `false` when it needs a power cycle.
+    async fn resume(&mut self) -> Result<bool, CellularError> {
+        Ok(false)
+    }
+    /// Power the module off, [`CellularModem::power_cycle`] powers it on again.
+    async fn power_down(&mut self) -> Result<(), CellularError>;
+    /// Hard reset the module via its reset line.
+    async fn reset(&mut self) -> Result<(), CellularError>;
+    /// Configure the APN with its authentication and wait for the network registration.
+    async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError>;
+    /// How long the steps of the last [`CellularModem::startup_network`] took, none for a module that does not tell.
+    fn network_phases(&self) -> NetworkPhases {
+        NetworkPhases::default()
+    }
+    async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError>;
+    /// Synchronize the module RTC with the NTP `server` and return the new time in UTC.
+    async fn sync_network_time(&mut self, server: &str) -> Result<NaiveDateTime, CellularError>;
+    /// Write `utc` into the module RTC.
+    async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError>;
+    async fn query_signal_quality(&self) -> Result<Rssi, CellularError>;
+    /// Signal quality, registration state and operator in one sample.
+    async fn query_link_quality(&self) -> Result<LinkQuality, CellularError>;
+    /// IMEI, IMSI, ICCID and firmware revision, for the inventory in the backend.
+    async fn query_identity(&self) -> Result<ModuleIdentity, CellularError> {
+        Err(CellularError::Unsupported)
+    }
+    /// The GNSS position, `None` until the module has a fix.
+    async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError>;
+    /// Enter the low power mode while staying registered.
+    async fn sleep(&mut self) -> Result<(), CellularError>;
+    /// Leave the low power mode and wait for the network registration.
+    async fn wake_up(&mut self) -> Result<(), CellularError>;
+    /// POST `body` to `url`, returns the status and the number of response body bytes read into `response`.
+    async fn http_post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError>;
+    /// GET `url` and stream the response body into `sink`, returns the status and the number of body bytes streamed.
+    ///
+    /// The body is only streamed for a successful status.
+    async fn http_get(&mut self, url: &str, headers: &[(&str, &str)], sink: &mut impl HttpBodySink) -> Result<(HttpStatusCode, usize), CellularError>;
+    /// The status of `url` without the body, a GET with the body dropped where the module has no HEAD.
+    async fn http_head(&mut self, url: &str, headers: &[(&str, &str)]) -> Result<HttpStatusCode, CellularError> {
+        Ok(self.http_get(url, headers, &mut DiscardSink).await?.0)
+    }
+    /// Resolves `host` with the DNS of the network.
+    async fn resolve(&mut self, _host: &str) -> Result<IpAddr, CellularError> {
+        Err(CellularError::Unsupported)
+    }
+    /// Runs an arbitrary AT command, see [`crate::solar_monitor::remote_at`].
+    async fn run_at_command(&mut self, _command: &str, _timeout: Duration) -> Result<AtCommandResponse, CellularError> {
+        Err(CellularError::Unsupported)
+    }
+}
+
+/// Steps of [`CellularModem::startup_network`], for the startup report of the cloud client.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NetworkPhases {
+    /// Until the SIM is unlocked.
+    pub sim_ready: Option<Duration>,
+    /// From the APN configuration until registered.
+    pub registration: Option<Duration>,
+    /// Until the PDP context is active, for a module that activates it explicitly.
+    pub context_activation: Option<Duration>,
+}
+
+/// Pins the module to the home network, e.g. for deployments near a border.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NetworkLock {
+    /// Numeric MCC and MNC of the operator, e.g. `22801`.
+    pub plmn: Option<String<6>>,
+    pub access_technology: Option<AccessTechnology>,
+    /// Select automatically while the operator is not available.
+    pub fallback: bool,
+    pub lte_bands: Option<LteBands>,
+}
+
+pub const TLS_PIN: ConfigKey<TlsPin> = ConfigKey::new("cloud", "tls_pin");
+
+/// SHA-256 of the public key the backend has to present, for uploads over untrusted carrier
+/// networks (`openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`).
+///
+/// The PPP path checks the pin itself: [`crate::net::tls`] hashes the `subjectPublicKeyInfo`
+/// of the server certificate in the handshake and only accepts the CertificateVerify signed
+/// by that key. The A67 HTTP client does not hand the certificate to the firmware, there the
+/// module verifies the server against its own certificate, downloaded by the installer as
+/// [`TlsPin::cert_file`] with `AT+CCERTDOWN`, and the firmware refuses to talk HTTPS while the
+/// module does not list that file. With a pin plain `http://` URLs and servers presenting
+/// another key fail with [`CellularError::TlsRejected`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TlsPin {
+    /// SHA-256 of the DER encoded `subjectPublicKeyInfo` of the server certificate.
+    pub sha256: [u8; 32],
+}
+
+impl TlsPin {
+    /// `pin-<first 8 bytes of the hash in hex>.pem`.
+    pub fn cert_file(&self) -> String<24> {
+        let mut name = String::new();
+        let _ = name.push_str("pin-");
+        for byte in &self.sha256[..8] {
+            let _ = core::fmt::write(&mut name, format_args!("{:02x}", byte));
+        }
+        let _ = name.push_str(".pem");
+        name
+    }
+
+    /// Whether the public key of the DER encoded X.509 `certificate` hashes to the pin.
+    pub fn matches(&self, certificate: &[u8]) -> bool {
+        use sha2::Digest;
+        match crate::net::tls::subject_public_key_info(certificate) {
+            Some(key_info) => sha2::Sha256::digest(key_info).as_slice() == self.sha256,
+            None => false,
+        }
+    }
+}
+
+impl ConfigValue for TlsPin {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.sha256.as_slice().encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        Some(Self { sha256: data.try_into().ok()? })
+    }
+}
+
+/// A sample of the radio link, see [`CellularModem::query_link_quality`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkQuality {
+    pub rssi: Rssi,
+    /// RXQUAL class 0..7, 99 if not known.
+    pub ber: u32,
+    pub registration: NetworkRegistrationState,
+    /// Empty while not registered.
+    pub operator: String<OPERATOR_SIZE>,
+}
+
+/// Receives a response body chunk by chunk, in order.
+pub trait HttpBodySink {
+    async fn write(&mut self, data: &[u8]) -> Result<(), CellularError>;
+}
+
+/// Collects a small response body into a buffer.
+pub struct BufferSink<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> BufferSink<'b> {
+    pub fn new(buffer: &'b mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+
+    #[cfg(test)]
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+impl HttpBodySink for BufferSink<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), CellularError> {
+        let end = self.len + data.len();
+        if end > self.buffer.len() {
+            return Err(CellularError::BufferOverflow);
+        }
+        self.buffer[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Drops the response body, see [`CellularModem::http_head`].
+struct DiscardSink;
+
+impl HttpBodySink for DiscardSink {
+    async fn write(&mut self, _data: &[u8]) -> Result<(), CellularError> {
+        Ok(())
+    }
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum CellularError {
@@ -9,6 +218,14 @@ pub enum CellularError {
     AtError(AtError),
     GpioError,
     Encoding(),
+    HttpStatus(HttpStatusCode),
+    HostNotFound,
+    BufferOverflow,
+    Unsupported,
+    /// The socket failed or closed early.
+    Connection,
+    /// The server did not present the key of the [`TlsPin`] or the URL was not `https://`.
+    TlsRejected,
 }
 
 #[cfg(feature = "defmt")]
@@ -19,6 +236,12 @@ impl defmt::Format for CellularError {
             CellularError::AtError(e) => defmt::write!(f, "AtError({:?})", e),
             CellularError::GpioError => defmt::write!(f, "GpioError"),
             CellularError::Encoding() => defmt::write!(f, "Encoding Error"),
+            CellularError::HttpStatus(status) => defmt::write!(f, "HttpStatus({})", status),
+            CellularError::HostNotFound => defmt::write!(f, "HostNotFound"),
+            CellularError::BufferOverflow => defmt::write!(f, "BufferOverflow"),
+            CellularError::Unsupported => defmt::write!(f, "Unsupported"),
+            CellularError::Connection => defmt::write!(f, "Connection"),
+            CellularError::TlsRejected => defmt::write!(f, "TlsRejected"),
         }
     }
 }
@@ -42,6 +265,30 @@ impl embedded_io_async::Error for CellularError {
             CellularError::AtError(_) => embedded_io_async::ErrorKind::Other,
             CellularError::GpioError => embedded_io_async::ErrorKind::Other,
             CellularError::Encoding() => embedded_io_async::ErrorKind::Other,
+            CellularError::HttpStatus(_) => embedded_io_async::ErrorKind::Other,
+            CellularError::HostNotFound => embedded_io_async::ErrorKind::AddrNotAvailable,
+            CellularError::BufferOverflow => embedded_io_async::ErrorKind::OutOfMemory,
+            CellularError::Unsupported => embedded_io_async::ErrorKind::Unsupported,
+            CellularError::Connection => embedded_io_async::ErrorKind::ConnectionReset,
+            CellularError::TlsRejected => embedded_io_async::ErrorKind::PermissionDenied,
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_tls_pin() {
+        let pin = TlsPin {
+            sha256: core::array::from_fn(|i| 0x10 + i as u8),
+        };
+        assert_eq!(pin.cert_file().as_str(), "pin-1011121314151617.pem");
+        let mut buffer = [0u8; 64];
+        let len = pin.encode(&mut buffer).unwrap();
+        assert_eq!(len, 32);
+        assert_eq!(TlsPin::decode(&buffer[..len]), Some(pin));
+        assert_eq!(TlsPin::decode(&buffer[..31]), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! Power cycles of the module across resets of the MCU.
+//!
+//! A firmware in a reset loop (watchdog, crash) would otherwise pulse PWRKEY on every
+//! boot, some modules do not take that well. The board keeps a [`PowerCycleRecord`] in
+//! retained (`.uninit`) RAM like the crash record. The cloud runner takes over a module
+//! that is still on with [`super::CellularModem::resume`] and keeps a minimum interval
+//! between the power cycles, see [`PowerCycleRecord::wait_before_cycle`].
+
+use embassy_time::Duration;
+
+const POWER_CYCLE_RECORD_MAGIC: u32 = 0x90C7_C1E5;
+
+/// Power cycle record placed in retained RAM by the board, any bit pattern is a valid (empty or garbage) record.
+#[repr(C)]
+pub struct PowerCycleRecord {
+    magic: u32,
+    /// Seconds since the last power cycle, as of the last update.
+    since_cycle: u32,
+    /// Uptime of the current run at the last update.
+    updated_at: u32,
+    /// Non zero while the module is on since the last power cycle.
+    modem_on: u32,
+}
+
+impl PowerCycleRecord {
+    pub const fn new() -> Self {
+        Self {
+            magic: 0,
+            since_cycle: 0,
+            updated_at: 0,
+            modem_on: 0,
+        }
+    }
+
+    /// Takes over the record of the previous run after a reset, a garbage record (power loss) is cleared.
+    pub fn boot(&mut self) {
+        if self.magic != POWER_CYCLE_RECORD_MAGIC {
+            *self = Self::new();
+        }
+        // the uptime starts over, the seconds up to the last update are in `since_cycle`
+        self.updated_at = 0;
+    }
+
+    /// Adds the uptime since the last update, the time spent in the reset itself is lost.
+    pub fn update(&mut self, uptime_seconds: u32) {
+        self.since_cycle = self.since_cycle.saturating_add(uptime_seconds.saturating_sub(self.updated_at));
+        self.updated_at = uptime_seconds;
+    }
+
+    /// Records a power cycle, the module is on afterwards.
+    pub fn cycled(&mut self, uptime_seconds: u32) {
+        self.since_cycle = 0;
+        self.updated_at = uptime_seconds;
+        self.modem_on = 1;
+        self.magic = POWER_CYCLE_RECORD_MAGIC;
+    }
+
+    pub fn powered_down(&mut self) {
+        self.modem_on = 0;
+    }
+
+    /// Whether the module was on at the last update, e.g. before a reset of the MCU.
+    pub fn modem_on(&self) -> bool {
+        self.magic == POWER_CYCLE_RECORD_MAGIC && self.modem_on != 0
+    }
+
+    /// Time since the last power cycle, `None` without one.
+    pub fn since_cycle(&mut self, uptime_seconds: u32) -> Option<Duration> {
+        if self.magic != POWER_CYCLE_RECORD_MAGIC {
+            return None;
+        }
+        self.update(uptime_seconds);
+        Some(Duration::from_secs(self.since_cycle as u64))
+    }
+
+    /// Time left until the next power cycle keeps `min_interval` to the last one.
+    pub fn wait_before_cycle(&mut self, uptime_seconds: u32, min_interval: Duration) -> Duration {
+        match self.since_cycle(uptime_seconds) {
+            Some(since) if since < min_interval => min_interval - since,
+            _ => Duration::from_secs(0),
+        }
+    }
+}
+
+impl Default for PowerCycleRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_interval_across_resets() {
+        let mut record = PowerCycleRecord::new();
+        record.boot();
+        assert!(!record.modem_on());
+        assert_eq!(record.wait_before_cycle(5, Duration::from_secs(60)), Duration::from_secs(0));
+
+        record.cycled(10);
+        record.update(25);
+        // reset 15s after the power cycle
+        record.boot();
+        assert!(record.modem_on());
+        assert_eq!(record.since_cycle(20), Some(Duration::from_secs(35)));
+        assert_eq!(record.wait_before_cycle(30, Duration::from_secs(60)), Duration::from_secs(15));
+        assert_eq!(record.wait_before_cycle(45, Duration::from_secs(60)), Duration::from_secs(0));
+
+        record.powered_down();
+        record.boot();
+        assert!(!record.modem_on());
+        assert_eq!(record.since_cycle(0), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn check_garbage_record() {
+        let mut record = PowerCycleRecord {
+            magic: 0xdead_beef,
+            since_cycle: 3,
+            updated_at: 7,
+            modem_on: 1,
+        };
+        record.boot();
+        assert!(!record.modem_on());
+        assert_eq!(record.since_cycle(100), None);
+    }
+}
@@ -0,0 +1,546 @@
+//! IP over PPP in the data mode of the SIMCom A67, as alternative to its AT HTTP client.
+//!
+//! For every request the module dials into the data mode (`ATD*99#`), the app runs PPP and
+//! an IP stack (embassy-net-ppp and embassy-net) on the UART, the request goes through the
+//! [`HttpTransport`] on that stack. Afterwards the module escapes back to the command mode, the
+//! AT commands of the other [`CellularModem`] operations work as before.
+//!
+//! [`SocketTransport`] writes the requests to a plain TCP socket with the HTTP helper of
+//! [`crate::net::http`], [`PinnedTlsTransport`] wraps the socket in TLS pinned to the backend
+//! key, see [`crate::net::tls`]. An app with an HTTP client on the stack (reqwless) brings its
+//! own transport.
+
+use core::net::IpAddr;
+
+use chrono::NaiveDateTime;
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, with_timeout};
+use embedded_hal::digital::OutputPin;
+use embedded_io_async::{Read, Write};
+use embedded_tls::{TlsConfig, TlsConnection, TlsContext};
+use rand_chacha::{ChaCha8Rng, rand_core::SeedableRng};
+
+use crate::{
+    at::{
+        AtCommandResponse, AtControllerImpl,
+        gnss::GnssPosition,
+        http::HttpStatusCode,
+        identification::ModuleIdentity,
+        packet_domain::{PdpAuth, PdpType},
+        status_control::Rssi,
+    },
+    net::{
+        cellular::{BufferSink, CellularError, CellularModem, HttpBodySink, LinkQuality, NetworkPhases, TlsPin, sim_com_a67::SimComCellularModule},
+        http::{HttpResponse, Url, write_request},
+        tls::{PinnedProvider, Socket07, TlsStream, tls_error},
+    },
+    timeouts::Timeouts,
+};
+
+/// Chunks of the response body handed to the sink.
+const BODY_CHUNK_SIZE: usize = 256;
+
+/// The PPP protocol on the data mode stream `S`.
+pub trait PppLink<S> {
+    /// Runs the link until it fails, brings the IP stack up once PPP negotiated an address.
+    async fn run(&mut self, stream: &mut S) -> CellularError;
+}
+
+/// HTTP requests over the IP stack on the PPP link.
+pub trait HttpTransport {
+    /// Waits until the stack has an address.
+    async fn wait_up(&self);
+    /// Sends the request, the body goes to `sink` for a successful status or with `body_on_error`.
+    async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        sink: &mut impl HttpBodySink,
+        body_on_error: bool,
+    ) -> Result<(HttpStatusCode, usize), CellularError>;
+}
+
+/// TCP connections of the IP stack on the PPP link.
+pub trait TcpConnector {
+    type Socket<'s>: Read + Write
+    where
+        Self: 's;
+
+    /// Waits until the stack has an address.
+    async fn wait_up(&self);
+    /// Resolves `host` and connects to it.
+    async fn connect(&mut self, host: &str, port: u16) -> Result<Self::Socket<'_>, CellularError>;
+}
+
+/// Plain HTTP on the sockets of a [`TcpConnector`], `https://` URLs are not supported.
+pub struct SocketTransport<C: TcpConnector> {
+    connector: C,
+}
+
+impl<C: TcpConnector> SocketTransport<C> {
+    pub fn new(connector: C) -> Self {
+        Self { connector }
+    }
+}
+
+impl<C: TcpConnector> HttpTransport for SocketTransport<C> {
+    async fn wait_up(&self) {
+        self.connector.wait_up().await
+    }
+
+    async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        sink: &mut impl HttpBodySink,
+        body_on_error: bool,
+    ) -> Result<(HttpStatusCode, usize), CellularError> {
+        let url = Url::parse(url).ok_or(CellularError::Encoding())?;
+        if url.https {
+            warn!("HTTPS not supported on a plain socket");
+            return Err(CellularError::Unsupported);
+        }
+        exchange(&mut self.connector, method, &url, headers, body, sink, body_on_error).await
+    }
+}
+
+/// HTTPS on the sockets of a [`TcpConnector`], only to the server presenting the key of the
+/// [`TlsPin`]. Plain `http://` URLs fail with [`CellularError::TlsRejected`].
+pub struct PinnedTlsTransport<'b, C: TcpConnector> {
+    connector: C,
+    pin: TlsPin,
+    rng: ChaCha8Rng,
+    read_buffer: &'b mut [u8],
+    write_buffer: &'b mut [u8],
+}
+
+impl<'b, C: TcpConnector> PinnedTlsTransport<'b, C> {
+    /// `seed` has to come from a hardware RNG, the buffers see [`crate::net::tls::TLS_READ_BUFFER_SIZE`].
+    pub fn new(connector: C, pin: TlsPin, seed: [u8; 32], read_buffer: &'b mut [u8], write_buffer: &'b mut [u8]) -> Self {
+        Self {
+            connector,
+            pin,
+            rng: ChaCha8Rng::from_seed(seed),
+            read_buffer,
+            write_buffer,
+        }
+    }
+}
+
+impl<C: TcpConnector> HttpTransport for PinnedTlsTransport<'_, C> {
+    async fn wait_up(&self) {
+        self.connector.wait_up().await
+    }
+
+    async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        sink: &mut impl HttpBodySink,
+        body_on_error: bool,
+    ) -> Result<(HttpStatusCode, usize), CellularError> {
+        let url = Url::parse(url).ok_or(CellularError::Encoding())?;
+        if !url.https {
+            warn!("TLS pin set => rejected http://{}{}", url.host, url.path);
+            return Err(CellularError::TlsRejected);
+        }
+        let socket = self.connector.connect(url.host, url.port).await?;
+        let mut connection = TlsConnection::new(Socket07(socket), self.read_buffer, self.write_buffer);
+        let config = TlsConfig::new().with_server_name(url.host);
+        connection
+            .open(TlsContext::new(&config, PinnedProvider::new(&mut self.rng, &self.pin)))
+            .await
+            .map_err(tls_error)?;
+        transfer(&mut TlsStream(connection), method, &url, headers, body, sink, body_on_error).await
+    }
+}
+
+pub struct PppCellularModule<'ch, Output: OutputPin, S: Read + Write, L: PppLink<S>, T: HttpTransport> {
+    module: SimComCellularModule<'ch, Output, AtControllerImpl<S>>,
+    link: L,
+    transport: T,
+    timeouts: Timeouts,
+}
+
+impl<'ch, Output: OutputPin, S: Read + Write, L: PppLink<S>, T: HttpTransport> PppCellularModule<'ch, Output, S, L, T> {
+    pub fn new(module: SimComCellularModule<'ch, Output, AtControllerImpl<S>>, link: L, transport: T, timeouts: Timeouts) -> Self {
+        Self {
+            module,
+            link,
+            transport,
+            timeouts,
+        }
+    }
+
+    /// Sends the request through the transport in a data mode session.
+    async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        sink: &mut impl HttpBodySink,
+        body_on_error: bool,
+    ) -> Result<(HttpStatusCode, usize), CellularError> {
+        let (link, transport, timeout) = (&mut self.link, &mut self.transport, self.timeouts.http_read);
+        self.module
+            .data_mode(async |stream| {
+                let exchange = with_timeout(timeout, async {
+                    transport.wait_up().await;
+                    transport.request(method, url, headers, body, sink, body_on_error).await
+                });
+                match select(link.run(stream), exchange).await {
+                    Either::First(e) => {
+                        warn!("PPP link failed: {:?}", e);
+                        Err(e)
+                    }
+                    Either::Second(result) => result?,
+                }
+            })
+            .await?
+    }
+}
+
+async fn exchange<C: TcpConnector>(
+    connector: &mut C,
+    method: &str,
+    url: &Url<'_>,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    sink: &mut impl HttpBodySink,
+    body_on_error: bool,
+) -> Result<(HttpStatusCode, usize), CellularError> {
+    let mut socket = connector.connect(url.host, url.port).await?;
+    transfer(&mut socket, method, url, headers, body, sink, body_on_error).await
+}
+
+/// The request and response on a connected `stream`.
+async fn transfer<S: Read + Write>(
+    stream: &mut S,
+    method: &str,
+    url: &Url<'_>,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    sink: &mut impl HttpBodySink,
+    body_on_error: bool,
+) -> Result<(HttpStatusCode, usize), CellularError> {
+    write_request(stream, method, url, headers, body).await?;
+    let mut response = HttpResponse::read(stream).await?;
+    let status = response.status();
+    if !status.is_ok() && !body_on_error {
+        return Ok((status, 0));
+    }
+    let mut chunk = [0u8; BODY_CHUNK_SIZE];
+    let mut len = 0;
+    loop {
+        match response.read_body(&mut chunk).await? {
+            0 => return Ok((status, len)),
+            n => {
+                sink.write(&chunk[..n]).await?;
+                len += n;
+            }
+        }
+    }
+}
+
+impl<'ch, Output: OutputPin, S: Read + Write, L: PppLink<S>, T: HttpTransport> CellularModem for PppCellularModule<'ch, Output, S, L, T> {
+    async fn power_cycle(&mut self) -> Result<(), CellularError> {
+        CellularModem::power_cycle(&mut self.module).await
+    }
+
+    async fn resume(&mut self) -> Result<bool, CellularError> {
+        CellularModem::resume(&mut self.module).await
+    }
+
+    async fn power_down(&mut self) -> Result<(), CellularError> {
+        CellularModem::power_down(&mut self.module).await
+    }
+
+    async fn reset(&mut self) -> Result<(), CellularError> {
+        CellularModem::reset(&mut self.module).await
+    }
+
+    async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError> {
+        CellularModem::startup_network(&mut self.module, apn, pdp_type, auth).await
+    }
+
+    fn network_phases(&self) -> NetworkPhases {
+        CellularModem::network_phases(&self.module)
+    }
+
+    async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError> {
+        CellularModem::query_real_time_clock(&self.module).await
+    }
+
+    async fn sync_network_time(&mut self, server: &str) -> Result<NaiveDateTime, CellularError> {
+        CellularModem::sync_network_time(&mut self.module, server).await
+    }
+
+    async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError> {
+        CellularModem::set_real_time_clock(&self.module, utc).await
+    }
+
+    async fn query_signal_quality(&self) -> Result<Rssi, CellularError> {
+        CellularModem::query_signal_quality(&self.module).await
+    }
+
+    async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+        CellularModem::query_link_quality(&self.module).await
+    }
+
+    async fn query_identity(&self) -> Result<ModuleIdentity, CellularError> {
+        CellularModem::query_identity(&self.module).await
+    }
+
+    async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError> {
+        CellularModem::query_position(&mut self.module).await
+    }
+
+    async fn sleep(&mut self) -> Result<(), CellularError> {
+        CellularModem::sleep(&mut self.module).await
+    }
+
+    async fn wake_up(&mut self) -> Result<(), CellularError> {
+        CellularModem::wake_up(&mut self.module).await
+    }
+
+    async fn http_post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError> {
+        let mut sink = BufferSink::new(response);
+        self.request("POST", url, headers, Some(body), &mut sink, true).await
+    }
+
+    async fn http_get(&mut self, url: &str, headers: &[(&str, &str)], sink: &mut impl HttpBodySink) -> Result<(HttpStatusCode, usize), CellularError> {
+        self.request("GET", url, headers, None, sink, false).await
+    }
+
+    /// The AT DNS of the module in the command mode, the IP stack resolves on its own in the data mode.
+    async fn resolve(&mut self, host: &str) -> Result<IpAddr, CellularError> {
+        CellularModem::resolve(&mut self.module, host).await
+    }
+
+    async fn run_at_command(&mut self, command: &str, timeout: Duration) -> Result<AtCommandResponse, CellularError> {
+        CellularModem::run_at_command(&mut self.module, command, timeout).await
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use embedded_io_adapters::tokio_1::FromTokio;
+    use embedded_io_async::{ErrorKind, ErrorType};
+    use std::{string::String, sync::Arc, vec::Vec as StdVec};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        task::JoinHandle,
+    };
+    use tokio_rustls::{
+        TlsAcceptor,
+        rustls::{
+            ServerConfig,
+            pki_types::{CertificateDer, PrivateKeyDer},
+        },
+    };
+
+    use super::*;
+    use crate::net::tls::{
+        TLS_READ_BUFFER_SIZE, TLS_WRITE_BUFFER_SIZE,
+        tests::{SERVER_CERT, SERVER_KEY, SERVER_PIN},
+    };
+
+    /// Connects to a server answering `response`, records the requests.
+    struct Server {
+        response: &'static [u8],
+        connected: StdVec<(String, u16)>,
+        request: StdVec<u8>,
+    }
+
+    struct Socket<'s> {
+        server: &'s mut Server,
+        read: usize,
+    }
+
+    impl ErrorType for Socket<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Socket<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let rest = &self.server.response[self.read..];
+            let n = rest.len().min(buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for Socket<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.server.request.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    impl TcpConnector for Server {
+        type Socket<'s> = Socket<'s>;
+
+        async fn wait_up(&self) {}
+
+        async fn connect(&mut self, host: &str, port: u16) -> Result<Self::Socket<'_>, CellularError> {
+            self.connected.push((host.into(), port));
+            Ok(Socket { server: self, read: 0 })
+        }
+    }
+
+    fn new_server(response: &'static [u8]) -> Server {
+        Server {
+            response,
+            connected: StdVec::new(),
+            request: StdVec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_exchange() {
+        let mut server = new_server(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok");
+        let url = Url::parse("localhost:8000/api/v2/solar/reading").unwrap();
+        let mut buffer = [0u8; 8];
+        let mut sink = BufferSink::new(&mut buffer);
+        let result = exchange(&mut server, "POST", &url, &[], Some(b"\x08\x01"), &mut sink, true).await;
+        assert_eq!(result, Ok((HttpStatusCode::new(201), 2)));
+        assert_eq!(sink.data(), b"ok");
+        assert_eq!(server.connected, [("localhost".into(), 8000)]);
+        assert!(server.request.starts_with(b"POST /api/v2/solar/reading HTTP/1.0\r\n"));
+    }
+
+    #[tokio::test]
+    async fn check_https_unsupported() {
+        let mut transport = SocketTransport::new(new_server(b""));
+        let mut buffer = [0u8; 8];
+        let mut sink = BufferSink::new(&mut buffer);
+        let result = transport
+            .request("GET", "https://solar.bittailor.ch/firmware", &[], None, &mut sink, false)
+            .await;
+        assert_eq!(result, Err(CellularError::Unsupported));
+        assert!(transport.connector.connected.is_empty());
+    }
+
+    /// Connects to the TLS server on `127.0.0.1:port`, whatever the host.
+    struct LocalConnector {
+        port: u16,
+    }
+
+    impl TcpConnector for LocalConnector {
+        type Socket<'s> = FromTokio<TcpStream>;
+
+        async fn wait_up(&self) {}
+
+        async fn connect(&mut self, _host: &str, _port: u16) -> Result<Self::Socket<'_>, CellularError> {
+            let stream = TcpStream::connect(("127.0.0.1", self.port)).await.map_err(|_| CellularError::Connection)?;
+            Ok(FromTokio::new(stream))
+        }
+    }
+
+    /// Answers one request with `response` over TLS with the test certificate, returns the request.
+    async fn serve_tls(response: &'static [u8]) -> (u16, JoinHandle<std::io::Result<StdVec<u8>>>) {
+        let config = ServerConfig::builder_with_provider(Arc::new(tokio_rustls::rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![CertificateDer::from(SERVER_CERT)], PrivateKeyDer::Pkcs8(SERVER_KEY.into()))
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let mut stream = TlsAcceptor::from(Arc::new(config)).accept(stream).await?;
+            let mut request = StdVec::new();
+            let mut chunk = [0u8; 256];
+            while !request_complete(&request) {
+                match stream.read(&mut chunk).await? {
+                    0 => break,
+                    n => request.extend_from_slice(&chunk[..n]),
+                }
+            }
+            stream.write_all(response).await?;
+            stream.shutdown().await?;
+            Ok(request)
+        });
+        (port, server)
+    }
+
+    fn request_complete(request: &[u8]) -> bool {
+        let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return false;
+        };
+        let head = core::str::from_utf8(&request[..end]).unwrap();
+        let len = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |len| len.parse::<usize>().unwrap());
+        request.len() >= end + 4 + len
+    }
+
+    async fn pinned_upload(port: u16, pin: TlsPin, url: &str) -> (Result<(HttpStatusCode, usize), CellularError>, StdVec<u8>) {
+        let (mut read_buffer, mut write_buffer) = (vec![0u8; TLS_READ_BUFFER_SIZE], vec![0u8; TLS_WRITE_BUFFER_SIZE]);
+        let mut transport = PinnedTlsTransport::new(LocalConnector { port }, pin, [7; 32], &mut read_buffer, &mut write_buffer);
+        let mut buffer = [0u8; 16];
+        let mut sink = BufferSink::new(&mut buffer);
+        let result = transport
+            .request("POST", url, &[("Content-Type", "application/x-protobuf")], Some(b"\x08\x01"), &mut sink, true)
+            .await;
+        let data = sink.data().to_vec();
+        (result, data)
+    }
+
+    #[tokio::test]
+    async fn check_pinned_upload() {
+        let (port, server) = serve_tls(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok").await;
+        let (result, body) = pinned_upload(port, SERVER_PIN, "https://localhost/api/v2/solar/reading").await;
+        assert_eq!(result, Ok((HttpStatusCode::new(201), 2)));
+        assert_eq!(body, b"ok");
+        let request = server.await.unwrap().unwrap();
+        assert!(request.starts_with(b"POST /api/v2/solar/reading HTTP/1.0\r\nHost: localhost\r\n"));
+        assert!(request.ends_with(b"\r\n\r\n\x08\x01"));
+    }
+
+    #[tokio::test]
+    async fn check_pinned_upload_other_key() {
+        let (port, server) = serve_tls(b"HTTP/1.1 201 Created\r\n\r\n").await;
+        let mut pin = SERVER_PIN;
+        pin.sha256[31] ^= 1;
+        let (result, _) = pinned_upload(port, pin, "https://localhost/api/v2/solar/reading").await;
+        assert_eq!(result, Err(CellularError::TlsRejected));
+        // the server never sees a request
+        assert!(server.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn check_pinned_plain_http() {
+        let (result, _) = pinned_upload(1, SERVER_PIN, "http://localhost/api/v2/solar/reading").await;
+        assert_eq!(result, Err(CellularError::TlsRejected));
+    }
+
+    #[tokio::test]
+    async fn check_error_body() {
+        let url = Url::parse("localhost:8000/firmware").unwrap();
+        let mut buffer = [0u8; 16];
+
+        let mut server = new_server(b"HTTP/1.1 404 Not Found\r\n\r\nnot found");
+        let mut sink = BufferSink::new(&mut buffer);
+        let result = exchange(&mut server, "GET", &url, &[], None, &mut sink, false).await;
+        assert_eq!(result, Ok((HttpStatusCode::new(404), 0)));
+        assert_eq!(sink.data(), b"");
+
+        let mut server = new_server(b"HTTP/1.1 404 Not Found\r\n\r\nnot found");
+        let mut sink = BufferSink::new(&mut buffer);
+        let result = exchange(&mut server, "POST", &url, &[], Some(b""), &mut sink, true).await;
+        assert_eq!(result, Ok((HttpStatusCode::new(404), 9)));
+        assert_eq!(sink.data(), b"not found");
+    }
+}
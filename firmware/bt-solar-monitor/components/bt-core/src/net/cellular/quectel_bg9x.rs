@@ -0,0 +1,363 @@
+//! Driver for the Quectel BG95 / BG96 LTE-M modules.
+//!
+//! Uses the module's own TCP/IP context (`+QIACT`) and HTTP client (`+QHTTP*`).
+//! Custom headers are only supported by sending the complete request header
+//! together with the body, so [`CellularModem::http_post`] builds it itself.
+
+use core::fmt::Write;
+
+use chrono::NaiveDateTime;
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant, Timer, WithTimeout, with_timeout};
+use embedded_hal::digital::OutputPin;
+use heapless::String;
+
+use crate::{
+    at::{
+        AtCommandResponse, AtController, PingError,
+        gnss::GnssPosition,
+        http::HttpStatusCode,
+        network::NetworkRegistrationState,
+        packet_domain::{PdpAuth, PdpType},
+        status_control::Rssi,
+    },
+    net::cellular::{CellularError, CellularModem, HttpBodySink, LinkQuality, NetworkPhases},
+    timeouts::Timeouts,
+};
+
+const CONTEXT_ID: u8 = 1;
+const HTTP_HEADER_SIZE: usize = 512;
+/// PWRKEY low time to turn the module on (500 ms min).
+const POWER_KEY_PULSE: Duration = Duration::from_millis(750);
+/// RESET_N low time to reset the module (150 ms min, 460 ms max).
+const RESET_PULSE: Duration = Duration::from_millis(300);
+
+pub struct QuectelCellularModule<'ch, Output: OutputPin, Ctr: AtController> {
+    at_client: crate::at::AtClientImpl<'ch, Ctr>,
+    pwrkey: Output,
+    reset: Output,
+    http_configured: bool,
+    timeouts: Timeouts,
+    network_phases: NetworkPhases,
+}
+
+impl<'ch, Output: OutputPin, Ctr: AtController> QuectelCellularModule<'ch, Output, Ctr> {
+    pub fn new(at_client: crate::at::AtClientImpl<'ch, Ctr>, pwrkey: Output, reset: Output, timeouts: Timeouts) -> Self {
+        QuectelCellularModule {
+            at_client,
+            pwrkey,
+            reset,
+            http_configured: false,
+            timeouts,
+            network_phases: NetworkPhases::default(),
+        }
+    }
+
+    /// Pings the module with `AT`, the round trip time or why it did not answer.
+    pub async fn is_alive(&self) -> Result<Duration, PingError> {
+        crate::at::ping(&self.at_client).await
+    }
+
+    pub async fn power_cycle(&mut self) -> Result<(), CellularError> {
+        match self.is_alive().await {
+            Ok(_) | Err(PingError::Garbage) => {
+                info!("still on => first power_down ...");
+                self.power_down().await?;
+                Timer::after_secs(1).await; // Just some 'safety' delay
+            }
+            Err(e) => debug!("not alive ({:?}) => power on", e),
+        }
+        self.power_on().await
+    }
+
+    pub async fn power_on(&mut self) -> Result<(), CellularError> {
+        self.http_configured = false;
+        info!("power on ...");
+        self.pwrkey.set_low().map_err(|_| CellularError::GpioError {})?;
+        Timer::after(POWER_KEY_PULSE).await;
+        self.pwrkey.set_high().map_err(|_| CellularError::GpioError {})?;
+        info!("... wait {}s to startup ...", self.timeouts.modem_boot.as_secs());
+        Timer::after(self.timeouts.modem_boot).await;
+        info!("... check AT ...");
+        self.ensure_at(self.timeouts.modem_at_ready).await?;
+        info!("... power on done");
+        self.initialize().await
+    }
+
+    /// Takes over the module without PWRKEY if it still answers.
+    pub async fn resume(&mut self) -> Result<bool, CellularError> {
+        if let Err(e) = self.is_alive().await {
+            info!("not alive ({:?}) => no resume", e);
+            return Ok(false);
+        }
+        info!("still on => resume ...");
+        self.http_configured = false;
+        self.initialize().await?;
+        Ok(true)
+    }
+
+    async fn initialize(&mut self) -> Result<(), CellularError> {
+        crate::at::network::set_automatic_time_and_time_zone_update(&self.at_client, true).await?;
+        match crate::at::identification::query_firmware_revision(&self.at_client).await {
+            Ok(revision) => info!("module firmware revision: {}", revision.as_str()),
+            Err(e) => warn!("failed to query firmware revision: {:?}", e),
+        }
+        Ok(())
+    }
+
+    pub async fn power_down(&mut self) -> Result<(), CellularError> {
+        self.http_configured = false;
+        crate::at::quectel::power_down(&self.at_client).await?;
+        Timer::after_secs(1).await; // Power off - power on buffer time
+        Ok(())
+    }
+
+    pub async fn reset(&mut self) -> Result<(), CellularError> {
+        info!("reset ...");
+        self.http_configured = false;
+        self.reset.set_low().map_err(|_| CellularError::GpioError {})?;
+        Timer::after(RESET_PULSE).await;
+        self.reset.set_high().map_err(|_| CellularError::GpioError {})?;
+        info!("... wait a bit for module to start ...");
+        Timer::after(self.timeouts.modem_boot).await;
+        info!("... reset done");
+        Ok(())
+    }
+
+    async fn ensure_at(&self, timeout: Duration) -> Result<(), CellularError> {
+        async { while crate::at::at(&self.at_client).await.is_err() {} }
+            .with_timeout(timeout)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError> {
+        let start = Instant::now();
+        self.wait_sim_ready().await?;
+        let sim_ready = Instant::now();
+        crate::at::quectel::configure_context(&self.at_client, CONTEXT_ID, apn, pdp_type, auth).await?;
+        while !self.is_registered().await? {
+            warn!("Not registered to network yet, waiting...");
+            Timer::after_secs(1).await;
+            info!("... retrying ...");
+        }
+        let registered = Instant::now();
+        self.ensure_context().await?;
+        self.network_phases = NetworkPhases {
+            sim_ready: Some(sim_ready - start),
+            registration: Some(registered - sim_ready),
+            context_activation: Some(Instant::now() - registered),
+        };
+        let _rtc = self.query_real_time_clock().await?;
+        Ok(())
+    }
+
+    async fn wait_sim_ready(&self) -> Result<(), CellularError> {
+        while !crate::at::status_control::query_sim_ready(&self.at_client).await? {
+            warn!("SIM not ready yet, waiting...");
+            Timer::after_secs(1).await;
+        }
+        Ok(())
+    }
+
+    async fn is_registered(&self) -> Result<bool, CellularError> {
+        let (_, state) = crate::at::network::get_eps_network_registration(&self.at_client).await?;
+        Ok(matches!(state, NetworkRegistrationState::Registered | NetworkRegistrationState::RegisteredRoaming))
+    }
+
+    async fn ensure_context(&mut self) -> Result<(), CellularError> {
+        if !crate::at::quectel::is_context_active(&self.at_client, CONTEXT_ID).await? {
+            info!("activate context {} ...", CONTEXT_ID);
+            crate::at::quectel::activate_context(&self.at_client, CONTEXT_ID).await?;
+        }
+        if !self.http_configured {
+            crate::at::quectel::http_set_context(&self.at_client, CONTEXT_ID).await?;
+            crate::at::quectel::http_set_request_header(&self.at_client, true).await?;
+            self.http_configured = true;
+        }
+        Ok(())
+    }
+
+    pub async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError> {
+        crate::at::status_control::query_real_time_clock(&self.at_client).await.map_err(Into::into)
+    }
+
+    pub async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError> {
+        crate::at::status_control::set_real_time_clock(&self.at_client, utc).await.map_err(Into::into)
+    }
+
+    pub async fn query_signal_quality(&self) -> Result<Rssi, CellularError> {
+        crate::at::status_control::query_signal_quality(&self.at_client)
+            .await
+            .map(|(rssi, _)| rssi)
+            .map_err(Into::into)
+    }
+
+    pub async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+        let (rssi, ber) = crate::at::status_control::query_signal_quality(&self.at_client).await?;
+        let (_, registration) = crate::at::network::get_eps_network_registration(&self.at_client).await?;
+        let operator = crate::at::network::query_operator(&self.at_client).await?.unwrap_or_default();
+        Ok(LinkQuality {
+            rssi,
+            ber,
+            registration,
+            operator,
+        })
+    }
+
+    pub async fn wake_up(&self) -> Result<(), CellularError> {
+        with_timeout(self.timeouts.modem_wake_up, async {
+            while let Err(e) = self.is_alive().await {
+                warn!("LTE module not alive ({:?}), retrying...", e);
+                Timer::after_millis(5).await;
+                yield_now().await;
+            }
+            while !self.is_registered().await? {
+                warn!("Not registered to network yet, waiting...");
+                Timer::after_secs(2).await;
+                info!("... retrying ...");
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    pub async fn post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError> {
+        self.ensure_context().await?;
+        let (scheme, url) = split_scheme(url);
+        let (host, path) = split_host(url);
+        let header = request_header("POST", host, path, headers, body.len())?;
+        crate::at::quectel::http_set_url(&self.at_client, scheme, url, self.timeouts.http_command).await?;
+        let (status, len) = crate::at::quectel::http_post(&self.at_client, &[header.as_bytes(), body], self.timeouts.http_read).await?;
+        let read = if len > 0 {
+            crate::at::quectel::http_read(&self.at_client, len, response, self.timeouts.http_read).await?
+        } else {
+            0
+        };
+        Ok((status, read))
+    }
+}
+
+impl<'ch, Output: OutputPin, Ctr: AtController> CellularModem for QuectelCellularModule<'ch, Output, Ctr> {
+    async fn power_cycle(&mut self) -> Result<(), CellularError> {
+        QuectelCellularModule::power_cycle(self).await
+    }
+
+    async fn resume(&mut self) -> Result<bool, CellularError> {
+        QuectelCellularModule::resume(self).await
+    }
+
+    async fn power_down(&mut self) -> Result<(), CellularError> {
+        QuectelCellularModule::power_down(self).await
+    }
+
+    async fn reset(&mut self) -> Result<(), CellularError> {
+        QuectelCellularModule::reset(self).await
+    }
+
+    async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError> {
+        QuectelCellularModule::startup_network(self, apn, pdp_type, auth).await
+    }
+
+    fn network_phases(&self) -> NetworkPhases {
+        self.network_phases
+    }
+
+    async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError> {
+        QuectelCellularModule::query_real_time_clock(self).await
+    }
+
+    async fn sync_network_time(&mut self, _server: &str) -> Result<NaiveDateTime, CellularError> {
+        warn!("NTP sync not supported by the Quectel driver");
+        Err(CellularError::Unsupported)
+    }
+
+    async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError> {
+        QuectelCellularModule::set_real_time_clock(self, utc).await
+    }
+
+    async fn query_signal_quality(&self) -> Result<Rssi, CellularError> {
+        QuectelCellularModule::query_signal_quality(self).await
+    }
+
+    async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+        QuectelCellularModule::query_link_quality(self).await
+    }
+
+    async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError> {
+        warn!("GNSS not supported by the Quectel driver");
+        Err(CellularError::Unsupported)
+    }
+
+    async fn sleep(&mut self) -> Result<(), CellularError> {
+        crate::at::quectel::set_sleep_enabled(&self.at_client, true).await.map_err(Into::into)
+    }
+
+    async fn wake_up(&mut self) -> Result<(), CellularError> {
+        QuectelCellularModule::wake_up(self).await
+    }
+
+    async fn http_post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError> {
+        self.post(url, headers, body, response).await
+    }
+
+    async fn run_at_command(&mut self, command: &str, timeout: Duration) -> Result<AtCommandResponse, CellularError> {
+        Ok(crate::at::passthrough(&self.at_client, command, timeout).await?)
+    }
+
+    /// `AT+QHTTPREAD` delivers the whole body in one piece, streaming large bodies is not supported.
+    async fn http_get(&mut self, _url: &str, _headers: &[(&str, &str)], _sink: &mut impl HttpBodySink) -> Result<(HttpStatusCode, usize), CellularError> {
+        warn!("HTTP GET streaming not supported by the Quectel driver");
+        Err(CellularError::Unsupported)
+    }
+}
+
+/// Splits off the scheme, the backend URLs come without one and default to `http://`.
+fn split_scheme(url: &str) -> (&'static str, &str) {
+    if let Some(rest) = url.strip_prefix("https://") {
+        ("https://", rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        ("http://", rest)
+    } else {
+        ("http://", url)
+    }
+}
+
+fn split_host(url: &str) -> (&str, &str) {
+    match url.find('/') {
+        Some(index) => (&url[..index], &url[index..]),
+        None => (url, "/"),
+    }
+}
+
+fn request_header(method: &str, host: &str, path: &str, headers: &[(&str, &str)], content_length: usize) -> Result<String<HTTP_HEADER_SIZE>, CellularError> {
+    let mut header = String::new();
+    let mut write = || -> core::fmt::Result {
+        write!(header, "{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, host)?;
+        for (name, value) in headers {
+            write!(header, "{}: {}\r\n", name, value)?;
+        }
+        write!(header, "Content-Length: {}\r\n\r\n", content_length)
+    };
+    write().map_err(|_| CellularError::Encoding())?;
+    Ok(header)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_url() {
+        assert_eq!(split_scheme("localhost:8000/api/v2/solar/reading"), ("http://", "localhost:8000/api/v2/solar/reading"));
+        assert_eq!(split_scheme("https://example.com"), ("https://", "example.com"));
+        assert_eq!(split_host("localhost:8000/api/v2/solar/reading"), ("localhost:8000", "/api/v2/solar/reading"));
+        assert_eq!(split_host("example.com"), ("example.com", "/"));
+    }
+
+    #[test]
+    fn test_request_header() {
+        let header = request_header("POST", "example.com", "/api", &[("Authorization", "Bearer token")], 12).unwrap();
+        assert_eq!(header.as_str(), "POST /api HTTP/1.1\r\nHost: example.com\r\nAuthorization: Bearer token\r\nContent-Length: 12\r\n\r\n");
+    }
+}
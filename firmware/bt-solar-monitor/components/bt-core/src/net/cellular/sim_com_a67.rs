@@ -1,14 +1,34 @@
-use core::str::{self};
+use core::{
+    net::IpAddr,
+    str::{self},
+};
 
 use chrono::NaiveDateTime;
 use embassy_futures::yield_now;
-use embassy_time::{Duration, Timer, WithTimeout, with_timeout};
+use embassy_time::{Duration, Instant, Timer, WithTimeout, with_timeout};
 use embedded_hal::digital::OutputPin;
-use embedded_io_async::Read;
+use embedded_io_async::{Read, Write};
 
 use crate::{
-    at::{AtClient, AtController, http::HttpStatusCode, network::NetworkRegistrationState, serial_interface::SleepMode, status_control::Rssi},
-    net::cellular::CellularError,
+    at::{
+        AtClient, AtCommandResponse, AtController, AtControllerImpl, AtError, AtPriority, PingError,
+        capabilities::Capabilities,
+        fs::{FileHandle, OpenMode},
+        gnss::GnssPosition,
+        http::{HttpAction, HttpHeaders, HttpStatusCode},
+        identification::ModuleIdentity,
+        network::{BandPreference, NetworkRegistrationState},
+        packet_domain::{PdpAuth, PdpType, dial_data_mode},
+        quirks::{AT_QUIRKS, Quirks},
+        serial_interface::SleepMode,
+        ssl::{AuthMode, HTTP_SSL_CONTEXT},
+        status_control::Rssi,
+    },
+    net::{
+        cellular::{CellularError, CellularModem, HttpBodySink, LinkQuality, NetworkLock, NetworkPhases, TlsPin},
+        dns::DnsCache,
+    },
+    timeouts::Timeouts,
 };
 
 pub struct SimComCellularModule<'ch, Output: OutputPin, Ctr: AtController> {
@@ -16,58 +36,191 @@ pub struct SimComCellularModule<'ch, Output: OutputPin, Ctr: AtController> {
     pwrkey: Output,
     reset: Output,
     http_initialized: bool,
+    gnss_powered: bool,
+    capabilities: Capabilities,
+    quirks: Quirks,
+    dns_cache: DnsCache<DNS_CACHE_SIZE>,
+    pdp_type: PdpType,
+    timeouts: Timeouts,
+    http_read_retries: u32,
+    network_lock: Option<NetworkLock>,
+    tls_pin: Option<TlsPin>,
+    network_phases: NetworkPhases,
 }
 
+const DNS_CACHE_SIZE: usize = 4;
+const DEFAULT_HTTP_READ_RETRIES: u32 = 2;
+/// Two pipelined `AT+HTTPREAD` chunks per body read.
+const HTTP_GET_BUFFER_SIZE: usize = 2 * crate::at::HTTP_READ_CHUNK_SIZE;
+
 impl<'ch, Output: OutputPin, Ctr: AtController> SimComCellularModule<'ch, Output, Ctr> {
-    pub fn new(at_client: crate::at::AtClientImpl<'ch, Ctr>, pwrkey: Output, reset: Output) -> Self {
+    pub fn new(at_client: crate::at::AtClientImpl<'ch, Ctr>, pwrkey: Output, reset: Output, timeouts: Timeouts) -> Self {
         SimComCellularModule {
             at_client,
             pwrkey,
             reset,
             http_initialized: false,
+            gnss_powered: false,
+            capabilities: Capabilities::NONE,
+            quirks: Quirks::NONE,
+            dns_cache: DnsCache::default(),
+            pdp_type: PdpType::Ip,
+            timeouts,
+            http_read_retries: DEFAULT_HTTP_READ_RETRIES,
+            network_lock: None,
+            tls_pin: None,
+            network_phases: NetworkPhases::default(),
         }
     }
 
-    pub async fn is_alive(&self) -> bool {
-        crate::at::at(&self.at_client).await.is_ok()
+    /// Number of times a failed `AT+HTTPREAD` chunk is retried before the body read gives up.
+    pub fn with_http_read_retries(mut self, retries: u32) -> Self {
+        self.http_read_retries = retries;
+        self
+    }
+
+    /// Applies the operator and band lock before every network registration.
+    pub fn with_network_lock(mut self, lock: NetworkLock) -> Self {
+        self.network_lock = Some(lock);
+        self
+    }
+
+    /// Only talks HTTPS to servers presenting the pinned key, see [`TlsPin`].
+    pub fn with_tls_pin(mut self, pin: TlsPin) -> Self {
+        self.tls_pin = Some(pin);
+        self
+    }
+
+    /// Pings the module with `AT`, the round trip time or why it did not answer.
+    pub async fn is_alive(&self) -> Result<Duration, PingError> {
+        crate::at::ping(&self.at_client).await
     }
 
     pub async fn power_cycle(&mut self) -> Result<(), CellularError> {
-        if self.is_alive().await {
-            info!("still on => first power_down ...");
-            self.power_down().await?;
-            Timer::after_secs(1).await; // Just some 'safety' delay
+        match self.is_alive().await {
+            Ok(_) | Err(PingError::Garbage) => {
+                info!("still on => first power_down ...");
+                self.power_down().await?;
+                Timer::after_secs(1).await; // Just some 'safety' delay
+            }
+            Err(e) => debug!("not alive ({:?}) => power on", e),
         }
         self.power_on().await
     }
 
     pub async fn power_on(&mut self) -> Result<(), CellularError> {
         self.http_initialized = false;
+        self.gnss_powered = false;
         info!("power on ...");
         self.pwrkey.set_low().map_err(|_| CellularError::GpioError {})?;
         Timer::after_millis(50).await;
         self.pwrkey.set_high().map_err(|_| CellularError::GpioError {})?;
-        info!("... wait 8s to startup ...");
-        Timer::after_secs(8).await;
+        info!("... wait {}s to startup ...", self.timeouts.modem_boot.as_secs());
+        Timer::after(self.timeouts.modem_boot).await;
         info!("... check AT ...");
-        self.ensure_at(Duration::from_secs(10)).await?;
+        self.ensure_at(self.timeouts.modem_at_ready).await?;
         info!("... power on done");
+        self.initialize().await
+    }
+
+    /// Takes over the module without PWRKEY if it still answers, the state of the previous run is dropped.
+    pub async fn resume(&mut self) -> Result<bool, CellularError> {
+        if let Err(e) = self.is_alive().await {
+            info!("not alive ({:?}) => no resume", e);
+            return Ok(false);
+        }
+        info!("still on => resume ...");
+        self.gnss_powered = false;
+        // the HTTP service of the previous run would fail the next AT+HTTPINIT
+        if crate::at::http::term(&self.at_client).await.is_ok() {
+            debug!("terminated the HTTP service of the previous run");
+        }
+        self.http_initialized = false;
+        self.initialize().await?;
+        Ok(true)
+    }
+
+    async fn initialize(&mut self) -> Result<(), CellularError> {
         crate::at::network::set_automatic_time_and_time_zone_update(&self.at_client, true).await?;
+        self.probe_capabilities().await;
         Ok(())
     }
 
-    pub async fn startup_network(&mut self, apn: &str) -> Result<(), CellularError> {
-        self.set_apn(apn).await?;
+    async fn probe_capabilities(&mut self) {
+        self.quirks = match crate::at::identification::query_firmware_revision(&self.at_client).await {
+            Ok(revision) => {
+                info!("module firmware revision: {}", revision.as_str());
+                AT_QUIRKS.resolve(revision.as_str())
+            }
+            Err(e) => {
+                warn!("failed to query firmware revision: {:?}", e);
+                Quirks::NONE
+            }
+        };
+        info!("module quirks: {:?}", self.quirks);
+        self.capabilities = crate::at::capabilities::probe(&self.at_client).await;
+        info!("module capabilities: {:?}", self.capabilities);
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// The workarounds for the firmware revision of the module, see [`crate::at::quirks`].
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError> {
+        let start = Instant::now();
+        self.wait_sim_ready().await?;
+        let sim_ready = Instant::now();
+        self.apply_network_lock().await?;
+        self.set_apn(apn, pdp_type).await?;
+        crate::at::packet_domain::set_auth(&self.at_client, auth).await?;
 
         while self.read_network_registration().await?.1 != NetworkRegistrationState::Registered {
             warn!("Not registered to network yet, waiting...");
             Timer::after_secs(1).await;
             info!("... retrying ...");
         }
+        // the module activates the default PDN context with the registration
+        self.network_phases = NetworkPhases {
+            sim_ready: Some(sim_ready - start),
+            registration: Some(Instant::now() - sim_ready),
+            context_activation: None,
+        };
         let _rtc = self.query_real_time_clock().await?;
         Ok(())
     }
 
+    async fn wait_sim_ready(&self) -> Result<(), CellularError> {
+        while !crate::at::status_control::query_sim_ready(&self.at_client).await? {
+            warn!("SIM not ready yet, waiting...");
+            Timer::after_secs(1).await;
+        }
+        Ok(())
+    }
+
+    async fn apply_network_lock(&self) -> Result<(), CellularError> {
+        let Some(lock) = &self.network_lock else {
+            return Ok(());
+        };
+        if let Some(lte) = lock.lte_bands {
+            let bands = crate::at::network::query_band_preference(&self.at_client).await?;
+            // the preference is stored in the module NVM
+            if bands.lte != lte {
+                info!("lock LTE bands {:#x}", lte.0);
+                crate::at::network::set_band_preference(&self.at_client, &BandPreference { lte, ..bands }).await?;
+            }
+        }
+        if let Some(plmn) = &lock.plmn {
+            info!("lock operator {}", plmn.as_str());
+            crate::at::network::select_operator(&self.at_client, plmn, lock.access_technology, lock.fallback).await?;
+        }
+        Ok(())
+    }
+
     pub async fn power_down(&self) -> Result<(), CellularError> {
         crate::at::status_control::power_down(&self.at_client).await?;
         Timer::after_secs(2).await; // Power off time
@@ -93,8 +246,15 @@ impl<'ch, Output: OutputPin, Ctr: AtController> SimComCellularModule<'ch, Output
             .map_err(Into::into)
     }
 
-    pub async fn set_apn(&self, apn: &str) -> Result<(), CellularError> {
-        crate::at::packet_domain::set_apn(&self.at_client, apn).await.map_err(Into::into)
+    pub async fn set_apn(&mut self, apn: &str, pdp_type: PdpType) -> Result<(), CellularError> {
+        crate::at::packet_domain::set_apn(&self.at_client, apn, pdp_type).await?;
+        self.pdp_type = pdp_type;
+        self.dns_cache.clear();
+        Ok(())
+    }
+
+    pub async fn query_pdp_addresses(&self) -> Result<heapless::Vec<IpAddr, { crate::at::packet_domain::MAX_PDP_ADDRESSES }>, CellularError> {
+        crate::at::packet_domain::get_pdp_addresses(&self.at_client).await.map_err(Into::into)
     }
 
     pub async fn read_network_registration(
@@ -107,6 +267,20 @@ impl<'ch, Output: OutputPin, Ctr: AtController> SimComCellularModule<'ch, Output
         crate::at::status_control::query_real_time_clock(&self.at_client).await.map_err(Into::into)
     }
 
+    /// `CellularError::Unsupported` without `AT+CNTP`, the caller falls back to the module RTC.
+    pub async fn sync_network_time(&self, server: &str) -> Result<NaiveDateTime, CellularError> {
+        if !self.capabilities.contains(Capabilities::NTP) {
+            return Err(CellularError::Unsupported);
+        }
+        crate::at::ntp::set_server(&self.at_client, server, 0).await?;
+        crate::at::ntp::sync(&self.at_client, self.timeouts.ntp_sync).await?;
+        self.query_real_time_clock().await
+    }
+
+    pub async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError> {
+        crate::at::status_control::set_real_time_clock(&self.at_client, utc).await.map_err(Into::into)
+    }
+
     // AT+CSCLK
     pub async fn read_sleep_mode(&self) -> Result<SleepMode, CellularError> {
         crate::at::serial_interface::read_sleep_mode(&self.at_client).await.map_err(Into::into)
@@ -121,10 +295,10 @@ impl<'ch, Output: OutputPin, Ctr: AtController> SimComCellularModule<'ch, Output
     }
 
     pub async fn wake_up(&self) -> Result<(), CellularError> {
-        with_timeout(Duration::from_secs(30), async {
-            self.is_alive().await;
-            while !self.is_alive().await {
-                warn!("LTE module not alive, retrying...");
+        with_timeout(self.timeouts.modem_wake_up, async {
+            let _ = self.is_alive().await;
+            while let Err(e) = self.is_alive().await {
+                warn!("LTE module not alive ({:?}), retrying...", e);
                 Timer::after_millis(5).await;
                 yield_now().await;
             }
@@ -145,22 +319,230 @@ impl<'ch, Output: OutputPin, Ctr: AtController> SimComCellularModule<'ch, Output
             .map_err(Into::into)
     }
 
+    pub async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+        let (rssi, ber) = crate::at::status_control::query_signal_quality(&self.at_client).await?;
+        let (_, registration) = self.read_network_registration().await?;
+        let operator = crate::at::network::query_operator(&self.at_client).await?.unwrap_or_default();
+        Ok(LinkQuality {
+            rssi,
+            ber,
+            registration,
+            operator,
+        })
+    }
+
+    pub async fn query_identity(&self) -> Result<ModuleIdentity, CellularError> {
+        Ok(crate::at::identification::query_identity(&self.at_client).await?)
+    }
+
+    /// Turns the GNSS engine on when needed and off again after a fix to save power.
+    pub async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError> {
+        if !self.gnss_powered {
+            crate::at::gnss::set_power(&self.at_client, true).await?;
+            self.gnss_powered = true;
+        }
+        let position = crate::at::gnss::query_position(&self.at_client).await?;
+        if position.is_some() {
+            crate::at::gnss::set_power(&self.at_client, false).await?;
+            self.gnss_powered = false;
+        }
+        Ok(position)
+    }
+
+    pub async fn resolve(&mut self, host: &str) -> Result<IpAddr, CellularError> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(ip);
+        }
+        let address = match self.dns_cache.lookup(host, Instant::now()) {
+            Some(address) => address,
+            None => {
+                let address = crate::at::dns::resolve_host(&self.at_client, host, self.timeouts.dns_lookup, self.pdp_type.prefers_ipv6()).await?;
+                self.dns_cache.insert(host, address, Instant::now());
+                address
+            }
+        };
+        address.ok_or(CellularError::HostNotFound)
+    }
+
     pub async fn request(&mut self) -> Result<HttpRequest<'_, '_, Ctr>, CellularError> {
         if !self.http_initialized {
             crate::at::http::init(&self.at_client).await?;
+            if let Some(pin) = &self.tls_pin {
+                self.apply_tls_pin(pin).await?;
+            }
             self.http_initialized = true;
         }
-        HttpRequest::new(&self.at_client).await
+        HttpRequest::new(&self.at_client, self.http_read_retries, self.tls_pin.is_some(), self.capabilities.contains(Capabilities::HTTP_POST_FILE)).await
+    }
+
+    /// The SSL context verifies the server against the pinned certificate, the module keeps it until the next `AT+HTTPINIT`.
+    async fn apply_tls_pin(&self, pin: &TlsPin) -> Result<(), CellularError> {
+        if !self.capabilities.contains(Capabilities::SSL) {
+            warn!("no AT+CSSLCFG => no HTTPS");
+            return Err(CellularError::Unsupported);
+        }
+        let cert_file = pin.cert_file();
+        if !crate::at::ssl::has_cert(&self.at_client, &cert_file).await? {
+            warn!("TLS pin set but {} not on the module => no HTTPS", cert_file.as_str());
+            return Err(CellularError::TlsRejected);
+        }
+        info!("TLS pinned to {}", cert_file.as_str());
+        crate::at::ssl::set_auth_mode(&self.at_client, HTTP_SSL_CONTEXT, AuthMode::Server).await?;
+        crate::at::ssl::set_ca_cert(&self.at_client, HTTP_SSL_CONTEXT, &cert_file).await?;
+        crate::at::ssl::set_sni(&self.at_client, HTTP_SSL_CONTEXT, true).await?;
+        crate::at::http::set_ssl_context(&self.at_client, HTTP_SSL_CONTEXT, self.quirks.contains(Quirks::QUOTED_SSL_CONTEXT)).await?;
+        Ok(())
     }
 }
 
+impl<'ch, Output: OutputPin, S: Read + Write> SimComCellularModule<'ch, Output, AtControllerImpl<S>> {
+    /// Runs `session` on the UART in the PPP data mode (`ATD*99#`), the other AT clients wait meanwhile.
+    pub async fn data_mode<R>(&self, session: impl AsyncFnOnce(&mut S) -> R) -> Result<R, CellularError> {
+        let mut session = Some(session);
+        let timeout = self.timeouts.network_registration;
+        self.at_client
+            .use_controller(async |ctr| {
+                let session = session.take().ok_or(AtError::Error)?;
+                dial_data_mode(ctr, timeout, session).await
+            })
+            .await
+            .map_err(Into::into)
+    }
+}
+
+impl<'ch, Output: OutputPin, Ctr: AtController> CellularModem for SimComCellularModule<'ch, Output, Ctr> {
+    async fn power_cycle(&mut self) -> Result<(), CellularError> {
+        SimComCellularModule::power_cycle(self).await
+    }
+
+    async fn resume(&mut self) -> Result<bool, CellularError> {
+        SimComCellularModule::resume(self).await
+    }
+
+    async fn power_down(&mut self) -> Result<(), CellularError> {
+        SimComCellularModule::power_down(self).await
+    }
+
+    async fn reset(&mut self) -> Result<(), CellularError> {
+        SimComCellularModule::reset(self).await
+    }
+
+    async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError> {
+        SimComCellularModule::startup_network(self, apn, pdp_type, auth).await
+    }
+
+    fn network_phases(&self) -> NetworkPhases {
+        self.network_phases
+    }
+
+    async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError> {
+        SimComCellularModule::query_real_time_clock(self).await
+    }
+
+    async fn sync_network_time(&mut self, server: &str) -> Result<NaiveDateTime, CellularError> {
+        SimComCellularModule::sync_network_time(self, server).await
+    }
+
+    async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError> {
+        SimComCellularModule::set_real_time_clock(self, utc).await
+    }
+
+    async fn query_signal_quality(&self) -> Result<Rssi, CellularError> {
+        SimComCellularModule::query_signal_quality(self).await
+    }
+
+    async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+        SimComCellularModule::query_link_quality(self).await
+    }
+
+    async fn query_identity(&self) -> Result<ModuleIdentity, CellularError> {
+        SimComCellularModule::query_identity(self).await
+    }
+
+    async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError> {
+        SimComCellularModule::query_position(self).await
+    }
+
+    async fn sleep(&mut self) -> Result<(), CellularError> {
+        if self.quirks.contains(Quirks::NO_SLEEP_MODE) {
+            debug!("no AT+CSCLK => module stays awake");
+            return Ok(());
+        }
+        self.set_sleep_mode(SleepMode::RxSleep).await
+    }
+
+    async fn wake_up(&mut self) -> Result<(), CellularError> {
+        SimComCellularModule::wake_up(self).await
+    }
+
+    async fn http_post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError> {
+        let request = self.request().await?;
+        for (header, value) in headers {
+            request.set_header(header, value).await?;
+        }
+        let mut http_response = request.post(url, body).await?;
+        let len = http_response.body().read_to_end(response).await?;
+        Ok((http_response.status(), len))
+    }
+
+    async fn http_get(&mut self, url: &str, headers: &[(&str, &str)], sink: &mut impl HttpBodySink) -> Result<(HttpStatusCode, usize), CellularError> {
+        let request = self.request().await?;
+        for (header, value) in headers {
+            request.set_header(header, value).await?;
+        }
+        let mut http_response = request.get(url).await?;
+        if !http_response.status().is_ok() {
+            return Ok((http_response.status(), 0));
+        }
+        let mut chunk = [0u8; HTTP_GET_BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let n = http_response.body().read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            sink.write(&chunk[..n]).await?;
+            total += n;
+        }
+        Ok((http_response.status(), total))
+    }
+
+    async fn http_head(&mut self, url: &str, headers: &[(&str, &str)]) -> Result<HttpStatusCode, CellularError> {
+        let request = self.request().await?;
+        for (header, value) in headers {
+            request.set_header(header, value).await?;
+        }
+        Ok(request.head(url).await?.status())
+    }
+
+    async fn resolve(&mut self, host: &str) -> Result<IpAddr, CellularError> {
+        SimComCellularModule::resolve(self, host).await
+    }
+
+    async fn run_at_command(&mut self, command: &str, timeout: Duration) -> Result<AtCommandResponse, CellularError> {
+        Ok(crate::at::passthrough(&self.at_client, command, timeout).await?)
+    }
+}
+
+/// File of the module the streamed request bodies are staged in, see [`HttpRequest::body`].
+const BODY_FILE_NAME: &str = "upload.bin";
+const BODY_FILE_PATH: &str = "C:/upload.bin";
+
 pub struct HttpRequest<'m, 'ch, Ctr: AtController> {
     at_client: &'m crate::at::AtClientImpl<'ch, Ctr>,
+    read_retries: u32,
+    https_only: bool,
+    post_file: bool,
 }
 
 impl<'m, 'ch, Ctr: AtController> HttpRequest<'m, 'ch, Ctr> {
-    async fn new(at_client: &'m crate::at::AtClientImpl<'ch, Ctr>) -> Result<Self, CellularError> {
-        Ok(Self { at_client })
+    async fn new(at_client: &'m crate::at::AtClientImpl<'ch, Ctr>, read_retries: u32, https_only: bool, post_file: bool) -> Result<Self, CellularError> {
+        Ok(Self {
+            at_client,
+            read_retries,
+            https_only,
+            post_file,
+        })
     }
 
     pub async fn set_header(&self, header: &str, value: &str) -> Result<&HttpRequest<'m, 'ch, Ctr>, CellularError> {
@@ -169,26 +551,126 @@ impl<'m, 'ch, Ctr: AtController> HttpRequest<'m, 'ch, Ctr> {
     }
 
     pub async fn get(&self, url: &str) -> Result<HttpResponse<'_, '_, Ctr>, CellularError> {
-        crate::at::http::set_url(self.at_client, url).await?;
-        crate::at::http::action(self.at_client, crate::at::http::HttpAction::Get)
-            .await
-            .map_err(Into::into)
-            .map(|(status, len)| HttpResponse {
-                status,
-                body: HttpResponseBody::new(self.at_client, len),
-            })
+        self.send(HttpAction::Get, url, None).await
     }
 
+    /// The body goes in one `AT+HTTPDATA`, a second one replaces the body instead of appending to it.
     pub async fn post(&self, url: &str, body: &[u8]) -> Result<HttpResponse<'_, '_, Ctr>, CellularError> {
+        self.send(HttpAction::Post, url, Some(body)).await
+    }
+
+    pub async fn put(&self, url: &str, body: &[u8]) -> Result<HttpResponse<'_, '_, Ctr>, CellularError> {
+        self.send(HttpAction::Put, url, Some(body)).await
+    }
+
+    pub async fn delete(&self, url: &str) -> Result<HttpResponse<'_, '_, Ctr>, CellularError> {
+        self.send(HttpAction::Delete, url, None).await
+    }
+
+    /// The response carries no body, read its fields with [`HttpResponse::headers`].
+    pub async fn head(&self, url: &str) -> Result<HttpResponse<'_, '_, Ctr>, CellularError> {
+        self.send(HttpAction::Head, url, None).await
+    }
+
+    /// Streams a body of any size into a file of the module, [`HttpRequestBody::post`] sends it.
+    ///
+    /// `CellularError::Unsupported` without `AT+HTTPPOSTFILE`, the body has to go to [`HttpRequest::post`] then.
+    pub async fn body(&self) -> Result<HttpRequestBody<'_, 'm, 'ch, Ctr>, CellularError> {
+        if !self.post_file {
+            warn!("no AT+HTTPPOSTFILE => no streamed body");
+            return Err(CellularError::Unsupported);
+        }
+        let handle = crate::at::fs::open(self.at_client, BODY_FILE_PATH, OpenMode::Create).await?;
+        Ok(HttpRequestBody { request: self, handle, len: 0 })
+    }
+
+    fn check_url(&self, url: &str) -> Result<(), CellularError> {
+        if self.https_only && !url.starts_with("https://") {
+            warn!("TLS pin set => rejected {}", url);
+            return Err(CellularError::TlsRejected);
+        }
+        Ok(())
+    }
+
+    fn response(&self, url: &str, status: HttpStatusCode, len: usize) -> Result<HttpResponse<'m, 'ch, Ctr>, CellularError> {
+        if self.https_only && status == HttpStatusCode::SSL_HANDSHAKE_FAILED {
+            warn!("TLS handshake with {} failed => server without the pinned key", url);
+            return Err(CellularError::TlsRejected);
+        }
+        Ok(HttpResponse {
+            status,
+            body: HttpResponseBody::new(self.at_client, len, self.read_retries),
+        })
+    }
+
+    async fn send(&self, action: HttpAction, url: &str, body: Option<&[u8]>) -> Result<HttpResponse<'_, '_, Ctr>, CellularError> {
+        self.check_url(url)?;
         crate::at::http::set_url(self.at_client, url).await?;
-        self.at_client.use_controller(async |ctr| ctr.handle_http_write(body).await).await?;
-        crate::at::http::action(self.at_client, crate::at::http::HttpAction::Post)
-            .await
-            .map_err(Into::into)
-            .map(|(status, len)| HttpResponse {
-                status,
-                body: HttpResponseBody::new(self.at_client, len),
-            })
+        if let Some(body) = body {
+            self.at_client.use_controller(async |ctr| ctr.handle_http_write(body).await).await?;
+        }
+        let (status, len) = crate::at::http::action(self.at_client, action).await?;
+        self.response(url, status, len)
+    }
+}
+
+/// Request body staged chunk by chunk with `AT+FSWRITE`, see [`HttpRequest::body`].
+pub struct HttpRequestBody<'r, 'm, 'ch, Ctr: AtController> {
+    request: &'r HttpRequest<'m, 'ch, Ctr>,
+    handle: FileHandle,
+    len: usize,
+}
+
+impl<'r, 'm, 'ch, Ctr: AtController> HttpRequestBody<'r, 'm, 'ch, Ctr> {
+    /// Bytes written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// POSTs the written body to `url`, the file is deleted afterwards.
+    pub async fn post(self, url: &str) -> Result<HttpResponse<'m, 'ch, Ctr>, CellularError> {
+        self.send(HttpAction::Post, url).await
+    }
+
+    pub async fn put(self, url: &str) -> Result<HttpResponse<'m, 'ch, Ctr>, CellularError> {
+        self.send(HttpAction::Put, url).await
+    }
+
+    async fn send(self, action: HttpAction, url: &str) -> Result<HttpResponse<'m, 'ch, Ctr>, CellularError> {
+        let at_client = self.request.at_client;
+        crate::at::fs::close(at_client, self.handle).await?;
+        let result = async {
+            self.request.check_url(url)?;
+            crate::at::http::set_url(at_client, url).await?;
+            Ok::<_, CellularError>(crate::at::http::post_file(at_client, BODY_FILE_NAME, action).await?)
+        }
+        .await;
+        if let Err(e) = crate::at::fs::delete(at_client, BODY_FILE_PATH).await {
+            warn!("deleting the body file failed: {:?}", e);
+        }
+        let (status, len) = result?;
+        self.request.response(url, status, len)
+    }
+}
+
+impl<'r, 'm, 'ch, Ctr: AtController> embedded_io_async::ErrorType for HttpRequestBody<'r, 'm, 'ch, Ctr> {
+    type Error = CellularError;
+}
+
+impl<'r, 'm, 'ch, Ctr: AtController> Write for HttpRequestBody<'r, 'm, 'ch, Ctr> {
+    /// Writes up to [`crate::at::fs::MAX_WRITE_SIZE`] bytes with one `AT+FSWRITE`.
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let chunk = &buf[..core::cmp::min(buf.len(), crate::at::fs::MAX_WRITE_SIZE)];
+        if chunk.is_empty() {
+            return Ok(0);
+        }
+        crate::at::fs::write(self.request.at_client, self.handle, chunk).await?;
+        self.len += chunk.len();
+        Ok(chunk.len())
     }
 }
 
@@ -205,17 +687,31 @@ impl<'m, 'ch, Ctr: AtController> HttpResponse<'m, 'ch, Ctr> {
     pub fn body(&mut self) -> &mut HttpResponseBody<'m, 'ch, Ctr> {
         &mut self.body
     }
+
+    /// Reads the response header into `buf` and iterates its fields.
+    pub async fn headers<'b>(&self, buf: &'b mut [u8]) -> Result<HttpHeaders<'b>, CellularError> {
+        let n = crate::at::http::read_head(self.body.at_client, buf).await?;
+        let raw = str::from_utf8(&buf[..n]).map_err(|_| CellularError::Encoding())?;
+        Ok(HttpHeaders::parse(raw))
+    }
 }
 
 pub struct HttpResponseBody<'m, 'ch, Ctr: AtController> {
     at_client: &'m crate::at::AtClientImpl<'ch, Ctr>,
     len: usize,
+    /// Offset up to which the body was read successfully.
     pos: usize,
+    retries: u32,
 }
 
 impl<'m, 'ch, Ctr: AtController> HttpResponseBody<'m, 'ch, Ctr> {
-    fn new(at_client: &'m crate::at::AtClientImpl<'ch, Ctr>, len: usize) -> Self {
-        Self { at_client, len, pos: 0 }
+    fn new(at_client: &'m crate::at::AtClientImpl<'ch, Ctr>, len: usize, retries: u32) -> Self {
+        Self {
+            at_client,
+            len,
+            pos: 0,
+            retries,
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -257,14 +753,243 @@ impl<'m, 'ch, Ctr: AtController> Read for HttpResponseBody<'m, 'ch, Ctr> {
             return Ok(0);
         }
         let len = core::cmp::min(remaining, buf.len());
-        self.at_client
-            .use_controller(async |ctr| ctr.handle_http_read(&mut buf[0..len], self.pos).await)
-            .await?;
-        self.pos += len;
-        Ok(len)
+        let mut attempt = 0;
+        let read = loop {
+            let result = self
+                .at_client
+                .use_controller_with_priority(AtPriority::Low, async |ctr| {
+                    if attempt > 0 {
+                        // drop what is left of the failed chunk
+                        ctr.abort().await;
+                    }
+                    ctr.handle_http_read(&mut buf[0..len], self.pos).await
+                })
+                .await;
+            match result {
+                Ok(read) => break read,
+                // a retry does not bring the network back
+                Err(e) if e != AtError::Cancelled && e != AtError::NoNetwork && attempt < self.retries => {
+                    attempt += 1;
+                    warn!("HTTPREAD at {} failed with {:?} => retry {}/{}", self.pos, e, attempt, self.retries);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if read < len {
+            warn!("HTTPREAD at {} => {} of {} bytes", self.pos, read, len);
+        }
+        if read == 0 {
+            // the body is shorter than announced
+            self.len = self.pos;
+        }
+        self.pos += read;
+        Ok(read)
     }
 }
 
 impl<'m, 'ch, Ctr: AtController> embedded_io_async::ErrorType for HttpResponseBody<'m, 'ch, Ctr> {
     type Error = CellularError;
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        at::{network::NetworkRegistrationState, status_control::Rssi},
+        tests::mock_stream::{MockPin, ModemSimulator, mock_stream},
+    };
+    use embassy_futures::{
+        join::join,
+        select::{Either, select},
+    };
+
+    #[tokio::test]
+    async fn test_query_link_quality() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new()
+            .expect("AT+CSQ")
+            .respond(&["", "+CSQ: 21,3", "", "OK"])
+            .expect("AT+CREG?")
+            .respond(&["", "+CREG: 0,5", "", "OK"])
+            .expect("AT+COPS?")
+            .respond(&["", "+COPS: 0,0,\"Swisscom\",7", "", "OK"]);
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default());
+        let Either::Second((_, quality)) = select(runner.run(), join(script.run(modem), module.query_link_quality())).await else {
+            unreachable!("runner never returns");
+        };
+        let quality = quality.unwrap();
+        assert_eq!(quality.rssi, Rssi::from_dbm(-71));
+        assert_eq!(quality.ber, 3);
+        assert_eq!(quality.registration, NetworkRegistrationState::RegisteredRoaming);
+        assert_eq!(quality.operator.as_str(), "Swisscom");
+    }
+
+    #[tokio::test]
+    async fn test_tls_pin() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new()
+            .expect("AT+HTTPINIT")
+            .ok()
+            .expect("AT+CCERTLIST")
+            .respond(&["", "+CCERTLIST: \"pin-a1b2c3d4e5f60718.pem\"", "", "OK"])
+            .expect("AT+CSSLCFG=\"authmode\",0,1")
+            .ok()
+            .expect("AT+CSSLCFG=\"cacert\",0,\"pin-a1b2c3d4e5f60718.pem\"")
+            .ok()
+            .expect("AT+CSSLCFG=\"enableSNI\",0,1")
+            .ok()
+            .expect("AT+HTTPPARA=\"SSLCFG\",0")
+            .ok()
+            .expect("AT+HTTPPARA=\"URL\",\"https://evil.example.com/api\"")
+            .ok()
+            .expect("AT+HTTPACTION=0")
+            .ok()
+            .urc("+HTTPACTION: 0,715,0");
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let mut sha256 = [0u8; 32];
+        sha256[..8].copy_from_slice(&[0xa1, 0xb2, 0xc3, 0xd4, 0xe5, 0xf6, 0x07, 0x18]);
+        let mut module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default()).with_tls_pin(TlsPin { sha256 });
+        module.capabilities = Capabilities::SSL;
+        let requests = async {
+            let request = module.request().await?;
+            assert_eq!(request.get("http://solar.bittailor.ch/api").await.err(), Some(CellularError::TlsRejected));
+            request.get("https://evil.example.com/api").await.map(|_| ())
+        };
+        let Either::Second((_, result)) = select(runner.run(), join(script.run(modem), requests)).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result, Err(CellularError::TlsRejected));
+    }
+
+    #[tokio::test]
+    async fn test_tls_pin_without_cert_file() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new()
+            .expect("AT+HTTPINIT")
+            .ok()
+            .expect("AT+CCERTLIST")
+            .respond(&["", "+CCERTLIST: \"ca.pem\"", "", "OK"]);
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let mut module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default()).with_tls_pin(TlsPin { sha256: [0; 32] });
+        module.capabilities = Capabilities::SSL;
+        let Either::Second((_, result)) = select(runner.run(), join(script.run(modem), module.request())).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result.err(), Some(CellularError::TlsRejected));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body() {
+        let body: std::vec::Vec<u8> = (0..1500u32).map(|i| i as u8).collect();
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new()
+            .expect("AT+HTTPINIT")
+            .ok()
+            .expect("AT+FSOPEN=C:/upload.bin,1")
+            .respond(&["+FSOPEN: 1", "", "OK"])
+            .expect("AT+FSWRITE=1,1024")
+            .prompt()
+            .expect_data(&body[..1024])
+            .respond(&["", "+FSWRITE: 1024,0", "", "OK"])
+            .expect("AT+FSWRITE=1,476")
+            .prompt()
+            .expect_data(&body[1024..])
+            .respond(&["", "+FSWRITE: 476,0", "", "OK"])
+            .expect("AT+FSCLOSE=1")
+            .ok()
+            .expect("AT+HTTPPARA=\"URL\",\"http://solar.bittailor.ch/api\"")
+            .ok()
+            .expect("AT+HTTPPOSTFILE=\"upload.bin\",1,1")
+            .ok()
+            .urc("+HTTPPOSTFILE: 201,0")
+            .expect("AT+FSDEL=C:/upload.bin")
+            .ok();
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let mut module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default());
+        module.capabilities = Capabilities::HTTP_POST_FILE;
+        let requests = async {
+            let request = module.request().await?;
+            let mut writer = request.body().await?;
+            writer.write_all(&body).await?;
+            assert_eq!(writer.len(), 1500);
+            writer.post("http://solar.bittailor.ch/api").await.map(|response| response.status())
+        };
+        let Either::Second((_, result)) = select(runner.run(), join(script.run(modem), requests)).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result, Ok(HttpStatusCode::new(201)));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_unsupported() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new().expect("AT+HTTPINIT").ok();
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let mut module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default());
+        let requests = async {
+            // no AT+FSOPEN without AT+HTTPPOSTFILE
+            let request = module.request().await?;
+            request.body().await.map(|_| ())
+        };
+        let Either::Second((_, result)) = select(runner.run(), join(script.run(modem), requests)).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result, Err(CellularError::Unsupported));
+    }
+
+    #[tokio::test]
+    async fn test_quirks() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new()
+            .expect("AT+HTTPINIT")
+            .ok()
+            .expect("AT+CCERTLIST")
+            .respond(&["", "+CCERTLIST: \"pin-0000000000000000.pem\"", "", "OK"])
+            .expect("AT+CSSLCFG=\"authmode\",0,1")
+            .ok()
+            .expect("AT+CSSLCFG=\"cacert\",0,\"pin-0000000000000000.pem\"")
+            .ok()
+            .expect("AT+CSSLCFG=\"enableSNI\",0,1")
+            .ok()
+            .expect("AT+HTTPPARA=\"SSLCFG\",\"0\"")
+            .ok();
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let mut module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default()).with_tls_pin(TlsPin { sha256: [0; 32] });
+        module.quirks = Quirks::from_bits(Quirks::QUOTED_SSL_CONTEXT.bits() | Quirks::NO_SLEEP_MODE.bits());
+        module.capabilities = Capabilities::SSL;
+        let requests = async {
+            module.request().await?;
+            // no AT+CSCLK, the script has no more commands
+            CellularModem::sleep(&mut module).await
+        };
+        let Either::Second((_, result)) = select(runner.run(), join(script.run(modem), requests)).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_capability_gating() {
+        let (stream, modem) = mock_stream();
+        let script = ModemSimulator::new().expect("AT+HTTPINIT").ok();
+        let mut state = crate::at::State::new();
+        let (runner, client) = crate::at::new(&mut state, stream, Timeouts::default());
+        let mut module = SimComCellularModule::new(client, MockPin, MockPin, Timeouts::default()).with_tls_pin(TlsPin { sha256: [0; 32] });
+        let requests = async {
+            // neither AT+CNTP nor AT+CSSLCFG reach the module
+            assert_eq!(module.sync_network_time("pool.ntp.org").await, Err(CellularError::Unsupported));
+            module.request().await.map(|_| ())
+        };
+        let Either::Second((_, result)) = select(runner.run(), join(script.run(modem), requests)).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(result, Err(CellularError::Unsupported));
+    }
+}
@@ -0,0 +1,105 @@
+//! Scoped wake ups of a sleeping module.
+//!
+//! A sleeping module wakes up for a heartbeat or a command and should sleep again right
+//! after, a wake up without the matching sleep keeps the module drawing its idle current
+//! until the next upload. [`ModemSleep`] keeps the sleep policy of the module and whether it
+//! is awake, [`ModemScope::with_modem_awake`] runs a piece of work with the module awake and
+//! restores the policy on the way out, also after an error. A scope dropped halfway (a
+//! timeout, a `select`) cannot sleep in `Drop`, it leaves the module awake against the
+//! policy and the next scope or [`ModemSleep::restore`] puts it back to sleep.
+
+use super::{CellularError, CellularModem};
+
+/// Whether the module sleeps outside of the [`ModemScope::with_modem_awake`] scopes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SleepPolicy {
+    #[default]
+    Awake,
+    Asleep,
+}
+
+#[derive(Debug)]
+pub struct ModemSleep {
+    policy: SleepPolicy,
+    /// The module answers, as far as known: set once a wake up completed, cleared once a sleep did.
+    awake: bool,
+}
+
+impl ModemSleep {
+    /// A module that was just powered on, awake.
+    pub const fn new() -> Self {
+        Self {
+            policy: SleepPolicy::Awake,
+            awake: true,
+        }
+    }
+
+    /// The module was powered on or reset, it is awake.
+    pub fn powered_on(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Puts the module to sleep, also between the scopes.
+    pub async fn sleep(&mut self, modem: &mut impl CellularModem) -> Result<(), CellularError> {
+        self.policy = SleepPolicy::Asleep;
+        self.restore(modem).await
+    }
+
+    /// Wakes the module up for good.
+    pub async fn wake_up(&mut self, modem: &mut impl CellularModem) -> Result<(), CellularError> {
+        self.policy = SleepPolicy::Awake;
+        self.restore(modem).await
+    }
+
+    /// Brings the module into the state of its policy, e.g. after a cancelled scope.
+    pub async fn restore(&mut self, modem: &mut impl CellularModem) -> Result<(), CellularError> {
+        match (self.policy, self.awake) {
+            (SleepPolicy::Asleep, true) => {
+                modem.sleep().await?;
+                self.awake = false;
+            }
+            (SleepPolicy::Awake, false) => {
+                modem.wake_up().await?;
+                self.awake = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn enter(&mut self, modem: &mut impl CellularModem) -> Result<(), CellularError> {
+        if !self.awake {
+            modem.wake_up().await?;
+            self.awake = true;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ModemSleep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Owner of a module and its [`ModemSleep`], e.g. the cloud controller.
+pub trait ModemScope: Sized {
+    type Modem: CellularModem;
+
+    fn modem_sleep(&mut self) -> (&mut Self::Modem, &mut ModemSleep);
+
+    /// Runs `work` with the module awake, afterwards the module sleeps again if its policy says so.
+    ///
+    /// The error of `work` wins over the one of the sleep.
+    async fn with_modem_awake<R>(&mut self, work: impl AsyncFnOnce(&mut Self) -> Result<R, CellularError>) -> Result<R, CellularError> {
+        let (modem, sleep) = self.modem_sleep();
+        sleep.enter(modem).await?;
+        let result = work(self).await;
+        let (modem, sleep) = self.modem_sleep();
+        let restored = sleep.restore(modem).await;
+        let value = result?;
+        restored?;
+        Ok(value)
+    }
+}
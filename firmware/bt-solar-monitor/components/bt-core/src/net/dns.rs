@@ -0,0 +1,110 @@
+use core::net::IpAddr;
+
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+
+pub const MAX_HOST_NAME_SIZE: usize = 64;
+
+struct DnsCacheEntry {
+    host: String<MAX_HOST_NAME_SIZE>,
+    address: Option<IpAddr>,
+    expires: Instant,
+}
+
+/// Tiny DNS cache keeping positive (resolved) and negative (not found) answers.
+///
+/// The modem does not report the record TTL, so fixed TTLs are used. When full,
+/// the entry closest to expiry is replaced.
+pub struct DnsCache<const N: usize> {
+    entries: Vec<DnsCacheEntry, N>,
+    positive_ttl: Duration,
+    negative_ttl: Duration,
+}
+
+impl<const N: usize> DnsCache<N> {
+    pub const fn new(positive_ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            entries: Vec::new(),
+            positive_ttl,
+            negative_ttl,
+        }
+    }
+
+    /// `Some(Some(ip))` for a cached address, `Some(None)` for a cached failure, `None` if unknown or expired.
+    pub fn lookup(&mut self, host: &str, now: Instant) -> Option<Option<IpAddr>> {
+        self.entries.retain(|e| e.expires > now);
+        self.entries.iter().find(|e| e.host == host).map(|e| e.address)
+    }
+
+    pub fn insert(&mut self, host: &str, address: Option<IpAddr>, now: Instant) {
+        let Ok(host) = String::try_from(host) else {
+            warn!("host name too long for dns cache");
+            return;
+        };
+        let ttl = if address.is_some() { self.positive_ttl } else { self.negative_ttl };
+        let entry = DnsCacheEntry {
+            host,
+            address,
+            expires: now + ttl,
+        };
+        self.entries.retain(|e| e.host != entry.host && e.expires > now);
+        if self.entries.is_full()
+            && let Some(oldest) = self.entries.iter().enumerate().min_by_key(|(_, e)| e.expires).map(|(i, _)| i)
+        {
+            self.entries.swap_remove(oldest);
+        }
+        let _ = self.entries.push(entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<const N: usize> Default for DnsCache<N> {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10 * 60), Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use core::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    #[test]
+    fn check_positive_and_negative_ttl() {
+        let mut cache = DnsCache::<2>::new(Duration::from_secs(60), Duration::from_secs(5));
+        let now = Instant::from_secs(100);
+        assert_eq!(cache.lookup("a.example", now), None);
+
+        cache.insert("a.example", Some(IP), now);
+        cache.insert("b.example", None, now);
+        assert_eq!(cache.lookup("a.example", now), Some(Some(IP)));
+        assert_eq!(cache.lookup("b.example", now), Some(None));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(cache.lookup("a.example", later), Some(Some(IP)));
+        assert_eq!(cache.lookup("b.example", later), None);
+
+        let much_later = now + Duration::from_secs(61);
+        assert_eq!(cache.lookup("a.example", much_later), None);
+    }
+
+    #[test]
+    fn check_replace_when_full() {
+        let mut cache = DnsCache::<2>::new(Duration::from_secs(60), Duration::from_secs(5));
+        let now = Instant::from_secs(100);
+        cache.insert("a.example", Some(IP), now);
+        cache.insert("b.example", None, now);
+        cache.insert("c.example", Some(IP), now);
+        assert_eq!(cache.lookup("a.example", now), Some(Some(IP)));
+        assert_eq!(cache.lookup("b.example", now), None);
+        assert_eq!(cache.lookup("c.example", now), Some(Some(IP)));
+
+        cache.insert("a.example", None, now);
+        assert_eq!(cache.lookup("a.example", now), Some(None));
+    }
+}
@@ -0,0 +1,275 @@
+//! HTTP over a socket, for modules without an HTTP client of their own (the nRF91 modem).
+//!
+//! The requests are HTTP/1.0: the server closes the connection after the response and
+//! never answers chunked, the body ends at its `Content-Length` or with the connection.
+
+use core::fmt::Write as _;
+
+use embedded_io_async::{Read, Write};
+use heapless::{String, Vec};
+
+use crate::{
+    at::http::{HttpHeaders, HttpStatusCode},
+    net::cellular::CellularError,
+};
+
+/// Status line and headers of a response.
+const RESPONSE_HEAD_SIZE: usize = 512;
+const REQUEST_LINE_SIZE: usize = 256;
+
+/// The parts of an `http://` or `https://` URL, the backend URLs come without a scheme and default to `http://`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Url<'u> {
+    pub https: bool,
+    pub host: &'u str,
+    pub port: u16,
+    /// With the query, `/` for an empty path.
+    pub path: &'u str,
+}
+
+impl<'u> Url<'u> {
+    pub fn parse(url: &'u str) -> Option<Self> {
+        let (https, rest) = match url.split_once("://") {
+            Some(("https", rest)) => (true, rest),
+            Some(("http", rest)) => (false, rest),
+            Some(_) => return None,
+            None => (false, url),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, if https { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self { https, host, port, path })
+    }
+}
+
+/// Writes the request, a `body` is sent with its `Content-Length`.
+pub async fn write_request<W: Write>(stream: &mut W, method: &str, url: &Url<'_>, headers: &[(&str, &str)], body: Option<&[u8]>) -> Result<(), CellularError> {
+    let mut line = String::<REQUEST_LINE_SIZE>::new();
+    write!(line, "{} {} HTTP/1.0\r\nHost: {}\r\n", method, url.path, url.host).map_err(|_| CellularError::BufferOverflow)?;
+    write_all(stream, line.as_bytes()).await?;
+    for (name, value) in headers {
+        line.clear();
+        write!(line, "{}: {}\r\n", name, value).map_err(|_| CellularError::BufferOverflow)?;
+        write_all(stream, line.as_bytes()).await?;
+    }
+    if let Some(body) = body {
+        line.clear();
+        write!(line, "Content-Length: {}\r\n", body.len()).map_err(|_| CellularError::BufferOverflow)?;
+        write_all(stream, line.as_bytes()).await?;
+    }
+    write_all(stream, b"\r\n").await?;
+    if let Some(body) = body {
+        write_all(stream, body).await?;
+    }
+    stream.flush().await.map_err(|_| CellularError::Connection)
+}
+
+async fn write_all<W: Write>(stream: &mut W, data: &[u8]) -> Result<(), CellularError> {
+    stream.write_all(data).await.map_err(|_| CellularError::Connection)
+}
+
+/// A response being read, see [`HttpResponse::read`].
+pub struct HttpResponse<'s, R: Read> {
+    stream: &'s mut R,
+    status: HttpStatusCode,
+    /// Body bytes not yet read, `None` without `Content-Length`.
+    remaining: Option<usize>,
+    /// The body bytes read along with the head.
+    buffered: Vec<u8, RESPONSE_HEAD_SIZE>,
+    offset: usize,
+}
+
+impl<'s, R: Read> HttpResponse<'s, R> {
+    /// Reads the status line and the headers.
+    pub async fn read(stream: &'s mut R) -> Result<Self, CellularError> {
+        let mut head = Vec::<u8, RESPONSE_HEAD_SIZE>::new();
+        let end = loop {
+            if let Some(index) = head.windows(4).position(|window| window == b"\r\n\r\n") {
+                break index;
+            }
+            let len = head.len();
+            if head.resize(RESPONSE_HEAD_SIZE, 0).is_err() || len == RESPONSE_HEAD_SIZE {
+                return Err(CellularError::BufferOverflow);
+            }
+            let n = stream.read(&mut head[len..]).await.map_err(|_| CellularError::Connection)?;
+            head.truncate(len + n);
+            if n == 0 {
+                return Err(CellularError::Connection);
+            }
+        };
+        let raw = core::str::from_utf8(&head[..end]).map_err(|_| CellularError::Encoding())?;
+        let status = raw
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .filter(|_| raw.starts_with("HTTP/"))
+            .ok_or(CellularError::Encoding())?;
+        let remaining = match HttpHeaders::parse(raw).get("Content-Length") {
+            Some(len) => Some(len.parse::<usize>().map_err(|_| CellularError::Encoding())?),
+            None => None,
+        };
+        let buffered = Vec::from_slice(&head[end + 4..]).map_err(|_| CellularError::BufferOverflow)?;
+        debug!("HTTP> {} ({:?} body bytes)", status, remaining);
+        Ok(Self {
+            stream,
+            status: HttpStatusCode::new(status),
+            remaining,
+            buffered,
+            offset: 0,
+        })
+    }
+
+    pub fn status(&self) -> HttpStatusCode {
+        self.status
+    }
+
+    /// Reads the next body bytes into `buffer`, 0 at the end of the body.
+    pub async fn read_body(&mut self, buffer: &mut [u8]) -> Result<usize, CellularError> {
+        let limit = self.remaining.map_or(buffer.len(), |remaining| remaining.min(buffer.len()));
+        if limit == 0 {
+            return Ok(0);
+        }
+        let n = if self.offset < self.buffered.len() {
+            let n = limit.min(self.buffered.len() - self.offset);
+            buffer[..n].copy_from_slice(&self.buffered[self.offset..self.offset + n]);
+            self.offset += n;
+            n
+        } else {
+            let n = self.stream.read(&mut buffer[..limit]).await.map_err(|_| CellularError::Connection)?;
+            if n == 0 && self.remaining.is_some() {
+                // closed before the announced length
+                return Err(CellularError::Connection);
+            }
+            n
+        };
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= n;
+        }
+        Ok(n)
+    }
+
+    /// Reads the whole body into `buffer`, a longer body fails.
+    pub async fn read_to_end(&mut self, buffer: &mut [u8]) -> Result<usize, CellularError> {
+        let mut len = 0;
+        loop {
+            if len == buffer.len() {
+                let mut probe = [0u8; 1];
+                return match self.read_body(&mut probe).await? {
+                    0 => Ok(len),
+                    _ => Err(CellularError::BufferOverflow),
+                };
+            }
+            match self.read_body(&mut buffer[len..]).await? {
+                0 => return Ok(len),
+                n => len += n,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use embedded_io_async::{ErrorKind, ErrorType};
+    use std::vec::Vec as StdVec;
+
+    use super::*;
+
+    /// Records the request and answers with `response` in chunks of `chunk` bytes, then reports the end of the stream.
+    struct Server<'r> {
+        request: StdVec<u8>,
+        response: &'r [u8],
+        chunk: usize,
+    }
+
+    impl<'r> Server<'r> {
+        fn new(response: &'r [u8], chunk: usize) -> Self {
+            Self {
+                request: StdVec::new(),
+                response,
+                chunk,
+            }
+        }
+    }
+
+    impl ErrorType for Server<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Server<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = self.chunk.min(buf.len()).min(self.response.len());
+            buf[..n].copy_from_slice(&self.response[..n]);
+            self.response = &self.response[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for Server<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.request.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn check_url() {
+        let url = Url::parse("https://solar.bittailor.ch/api/v2/solar/reading").unwrap();
+        assert_eq!((url.https, url.host, url.port, url.path), (true, "solar.bittailor.ch", 443, "/api/v2/solar/reading"));
+        let url = Url::parse("http://192.168.1.10:8080").unwrap();
+        assert_eq!((url.https, url.host, url.port, url.path), (false, "192.168.1.10", 8080, "/"));
+        let url = Url::parse("localhost:8000/api/v2/solar/reading").unwrap();
+        assert_eq!((url.https, url.host, url.port, url.path), (false, "localhost", 8000, "/api/v2/solar/reading"));
+        assert_eq!(Url::parse("ftp://example.com/"), None);
+        assert_eq!(Url::parse("https:///path"), None);
+        assert_eq!(Url::parse("http://example.com:port/"), None);
+    }
+
+    #[tokio::test]
+    async fn check_post() {
+        let mut server = Server::new(b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 28\r\n\r\n{\"accepted_proto_version\":5}", 7);
+        let url = Url::parse("https://solar.bittailor.ch/api/v2/solar/reading").unwrap();
+        write_request(&mut server, "POST", &url, &[("Authorization", "Bearer 42")], Some(&[0x08, 0x01]))
+            .await
+            .unwrap();
+        assert_eq!(
+            server.request,
+            b"POST /api/v2/solar/reading HTTP/1.0\r\nHost: solar.bittailor.ch\r\nAuthorization: Bearer 42\r\nContent-Length: 2\r\n\r\n\x08\x01"
+        );
+
+        let mut response = HttpResponse::read(&mut server).await.unwrap();
+        assert_eq!(response.status(), HttpStatusCode::new(200));
+        let mut body = [0u8; 64];
+        let len = response.read_to_end(&mut body).await.unwrap();
+        assert_eq!(&body[..len], b"{\"accepted_proto_version\":5}");
+    }
+
+    #[tokio::test]
+    async fn check_body_until_closed() {
+        let mut server = Server::new(b"HTTP/1.0 404 Not Found\r\n\r\nno such device", 512);
+        let mut response = HttpResponse::read(&mut server).await.unwrap();
+        assert_eq!(response.status(), HttpStatusCode::new(404));
+        let mut body = [0u8; 8];
+        assert_eq!(response.read_to_end(&mut body).await, Err(CellularError::BufferOverflow));
+    }
+
+    #[tokio::test]
+    async fn check_truncated_response() {
+        let mut server = Server::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort", 512);
+        let mut response = HttpResponse::read(&mut server).await.unwrap();
+        let mut body = [0u8; 16];
+        assert_eq!(response.read_to_end(&mut body).await, Err(CellularError::Connection));
+
+        let mut server = Server::new(b"HTTP/1.1 200 OK\r\nContent-", 512);
+        assert!(matches!(HttpResponse::read(&mut server).await, Err(CellularError::Connection)));
+        let mut server = Server::new(b"garbage\r\n\r\n", 512);
+        assert!(matches!(HttpResponse::read(&mut server).await, Err(CellularError::Encoding())));
+    }
+}
@@ -0,0 +1,281 @@
+//! TLS 1.3 with public key pinning, for HTTPS on the sockets of the PPP link.
+//!
+//! There is no CA bundle on the device: [`PinnedVerifier`] accepts the server if the
+//! `subjectPublicKeyInfo` of its certificate hashes to the [`TlsPin`] and the CertificateVerify
+//! of the handshake is signed by that key. Names and validity of the certificate are not
+//! checked, the key is the identity. Only P-256 keys (`EcdsaSecp256r1Sha256`) are supported.
+
+use embedded_io_async::{Read, Write};
+use embedded_io_async_07 as io07;
+use embedded_tls::{
+    Aes128GcmSha256, CertificateEntryRef, CertificateRef, CertificateVerifyRef, CryptoProvider, CryptoRngCore, SignatureScheme, TlsConnection, TlsError,
+    TlsVerifier,
+};
+use heapless::Vec;
+use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+use rand_chacha::ChaCha8Rng;
+use sha2::{Digest, Sha256};
+
+use crate::net::cellular::{CellularError, TlsPin};
+
+/// Record buffer for reading, a TLS record is up to 16 KiB plus overhead.
+pub const TLS_READ_BUFFER_SIZE: usize = 16640;
+/// Record buffer for writing, large requests are split into several records.
+pub const TLS_WRITE_BUFFER_SIZE: usize = 4096;
+
+/// Uncompressed SEC1 point of a P-256 key.
+const P256_KEY_SIZE: usize = 65;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_VERSION: u8 = 0xa0;
+
+/// A DER element and the data after it.
+struct DerElement<'d> {
+    tag: u8,
+    content: &'d [u8],
+    /// Tag, length and content.
+    encoded: &'d [u8],
+    rest: &'d [u8],
+}
+
+/// Splits off the DER element at the start of `data`.
+fn der_element(data: &[u8]) -> Option<DerElement<'_>> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 3 || rest.len() < count {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at(count);
+        (bytes.iter().fold(0usize, |len, &byte| (len << 8) | byte as usize), rest)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header = data.len() - rest.len();
+    Some(DerElement {
+        tag,
+        content: &rest[..len],
+        encoded: &data[..header + len],
+        rest: &rest[len..],
+    })
+}
+
+/// Skips the element at the start of `data` if it has the `tag`.
+fn skip(data: &[u8], tag: u8) -> Option<&[u8]> {
+    der_element(data).filter(|element| element.tag == tag).map(|element| element.rest)
+}
+
+/// The content of the SEQUENCE at the start of `data`.
+fn sequence(data: &[u8]) -> Option<&[u8]> {
+    der_element(data).filter(|element| element.tag == TAG_SEQUENCE).map(|element| element.content)
+}
+
+/// The DER encoded `subjectPublicKeyInfo` of an X.509 certificate, what the pin hashes.
+pub(crate) fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let certificate = sequence(certificate)?;
+    let mut rest = sequence(certificate)?;
+    if rest.first() == Some(&TAG_VERSION) {
+        rest = skip(rest, TAG_VERSION)?;
+    }
+    rest = skip(rest, TAG_INTEGER)?;
+    // signature algorithm, issuer, validity and subject
+    for _ in 0..4 {
+        rest = skip(rest, TAG_SEQUENCE)?;
+    }
+    der_element(rest).filter(|element| element.tag == TAG_SEQUENCE).map(|element| element.encoded)
+}
+
+/// The key bits of a `subjectPublicKeyInfo`, the SEC1 point for EC keys.
+fn public_key(key_info: &[u8]) -> Option<&[u8]> {
+    let rest = skip(sequence(key_info)?, TAG_SEQUENCE)?;
+    match der_element(rest)? {
+        DerElement {
+            tag: TAG_BIT_STRING,
+            content: [0, key @ ..],
+            ..
+        } => Some(key),
+        _ => None,
+    }
+}
+
+/// Accepts the server presenting the pinned key, see the module documentation.
+pub struct PinnedVerifier<'p> {
+    pin: &'p TlsPin,
+    public_key: Option<Vec<u8, P256_KEY_SIZE>>,
+    transcript: Option<Sha256>,
+}
+
+impl<'p> PinnedVerifier<'p> {
+    pub fn new(pin: &'p TlsPin) -> Self {
+        Self {
+            pin,
+            public_key: None,
+            transcript: None,
+        }
+    }
+}
+
+impl TlsVerifier<Aes128GcmSha256> for PinnedVerifier<'_> {
+    /// The pin replaces the name check, the host only goes out as SNI.
+    fn set_hostname_verification(&mut self, _hostname: &str) -> Result<(), TlsError> {
+        Ok(())
+    }
+
+    fn verify_certificate(&mut self, transcript: &Sha256, cert: CertificateRef) -> Result<(), TlsError> {
+        let Some(CertificateEntryRef::X509(certificate)) = cert.entries.first() else {
+            warn!("TLS server without an X.509 certificate");
+            return Err(TlsError::InvalidCertificate);
+        };
+        if !self.pin.matches(certificate) {
+            warn!("TLS server key does not match the pin");
+            return Err(TlsError::InvalidCertificate);
+        }
+        let key = subject_public_key_info(certificate).and_then(public_key).ok_or(TlsError::InvalidCertificate)?;
+        self.public_key = Some(Vec::from_slice(key).map_err(|_| TlsError::InvalidCertificate)?);
+        self.transcript = Some(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerifyRef) -> Result<(), TlsError> {
+        let (Some(key), Some(transcript)) = (self.public_key.take(), self.transcript.take()) else {
+            return Err(TlsError::InvalidCertificate);
+        };
+        if verify.signature_scheme != SignatureScheme::EcdsaSecp256r1Sha256 {
+            warn!("TLS signature scheme not supported");
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        // RFC 8446 4.4.3: 64 spaces, the context string and the transcript hash
+        let mut message: Vec<u8, 130> = Vec::new();
+        message.resize(64, 0x20).map_err(|_| TlsError::EncodeError)?;
+        message
+            .extend_from_slice(b"TLS 1.3, server CertificateVerify\x00")
+            .map_err(|_| TlsError::EncodeError)?;
+        message.extend_from_slice(&transcript.finalize()).map_err(|_| TlsError::EncodeError)?;
+        let key = VerifyingKey::from_sec1_bytes(&key).map_err(|_| TlsError::InvalidCertificate)?;
+        let signature = Signature::from_der(verify.signature).map_err(|_| TlsError::InvalidSignature)?;
+        key.verify(&message, &signature).map_err(|_| TlsError::InvalidSignature)
+    }
+}
+
+/// The crypto of a handshake: the random numbers and the [`PinnedVerifier`].
+pub struct PinnedProvider<'a> {
+    rng: &'a mut ChaCha8Rng,
+    verifier: PinnedVerifier<'a>,
+}
+
+impl<'a> PinnedProvider<'a> {
+    pub fn new(rng: &'a mut ChaCha8Rng, pin: &'a TlsPin) -> Self {
+        Self {
+            rng,
+            verifier: PinnedVerifier::new(pin),
+        }
+    }
+}
+
+impl CryptoProvider for PinnedProvider<'_> {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = &'static [u8];
+
+    fn rng(&mut self) -> impl CryptoRngCore {
+        &mut *self.rng
+    }
+
+    fn verifier(&mut self) -> Result<&mut impl TlsVerifier<Aes128GcmSha256>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+}
+
+/// The error of a handshake or record, the verifier rejections become [`CellularError::TlsRejected`].
+pub fn tls_error(error: TlsError) -> CellularError {
+    match error {
+        TlsError::InvalidCertificate | TlsError::InvalidSignature | TlsError::InvalidSignatureScheme => CellularError::TlsRejected,
+        _ => {
+            warn!("TLS failed: {:?}", error);
+            CellularError::Connection
+        }
+    }
+}
+
+/// A socket of embedded-io-async 0.6 for embedded-tls, which is on 0.7.
+pub struct Socket07<S>(pub S);
+
+impl<S> io07::ErrorType for Socket07<S> {
+    type Error = io07::ErrorKind;
+}
+
+impl<S: Read> io07::Read for Socket07<S> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf).await.map_err(|_| io07::ErrorKind::ConnectionReset)
+    }
+}
+
+impl<S: Write> io07::Write for Socket07<S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.write(buf).await.map_err(|_| io07::ErrorKind::ConnectionReset)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush().await.map_err(|_| io07::ErrorKind::ConnectionReset)
+    }
+}
+
+/// An open [`TlsConnection`] as stream of embedded-io-async 0.6, for [`crate::net::http`].
+pub struct TlsStream<'a, S: Read + Write>(pub TlsConnection<'a, Socket07<S>, Aes128GcmSha256>);
+
+impl<S: Read + Write> embedded_io_async::ErrorType for TlsStream<'_, S> {
+    type Error = CellularError;
+}
+
+impl<S: Read + Write> Read for TlsStream<'_, S> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        io07::Read::read(&mut self.0, buf).await.map_err(tls_error)
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<'_, S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        io07::Write::write(&mut self.0, buf).await.map_err(tls_error)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        io07::Write::flush(&mut self.0).await.map_err(tls_error)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub const SERVER_CERT: &[u8] = include_bytes!("../tests/tls/server-cert.der");
+    pub const SERVER_KEY: &[u8] = include_bytes!("../tests/tls/server-key.der");
+    /// `openssl x509 -inform der -in server-cert.der -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`
+    pub const SERVER_PIN: TlsPin = TlsPin {
+        sha256: [
+            0x16, 0xa7, 0x49, 0x23, 0x74, 0x41, 0xa7, 0x85, 0x0e, 0x17, 0xc7, 0xe6, 0xe0, 0x5b, 0x0b, 0x82, 0xa0, 0xc5, 0x7d, 0x1e, 0xca, 0xf7, 0x12, 0xc6,
+            0xa5, 0x34, 0x24, 0xaa, 0x7c, 0xb4, 0xf3, 0x79,
+        ],
+    };
+
+    #[test]
+    fn check_pin_matches() {
+        assert!(SERVER_PIN.matches(SERVER_CERT));
+        let mut other = SERVER_PIN.clone();
+        other.sha256[0] ^= 1;
+        assert!(!other.matches(SERVER_CERT));
+        assert!(!SERVER_PIN.matches(&SERVER_CERT[..100]));
+    }
+
+    #[test]
+    fn check_public_key() {
+        let key = subject_public_key_info(SERVER_CERT).and_then(public_key).unwrap();
+        assert_eq!(key.len(), P256_KEY_SIZE);
+        assert_eq!(key[0], 0x04);
+        assert!(VerifyingKey::from_sec1_bytes(key).is_ok());
+    }
+}
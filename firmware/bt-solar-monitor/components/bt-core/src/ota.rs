@@ -0,0 +1,516 @@
+//! Firmware update over the cellular link.
+//!
+//! The cloud runner polls the manifest endpoint, a JSON object like
+//! `{"version":8,"size":412160,"sha256":"29ce…","signature":"3045…","url":"http://…/8"}`.
+//! If the backend offers a newer version it streams the image as [`OtaMessage`]s to the
+//! [`OtaRunner`], which writes it into the secondary [`FirmwareSlot`] (the DFU partition of the
+//! bootloader), reads it back, checks the hash and the signature and marks the slot for the
+//! swap. The board resets once [`OtaRunner::run`] returns and the bootloader swaps the images.
+//!
+//! The signature is ECDSA P-256 with SHA-256 over the statement `<version>:<size>:<sha256>`
+//! (decimal, decimal, lowercase hex), DER encoded in hex, by the release key whose public half
+//! is stored under [`FIRMWARE_KEY`], see `just sign_ota_nrf`. Signing the version along keeps an
+//! older image from being offered as a newer one.
+//!
+//! A swapped in firmware confirms itself with [`Ota::confirm`] once it reached the backend, the
+//! runner then marks the slot booted. If it resets before, the bootloader swaps the previous
+//! firmware back.
+
+#![allow(async_fn_in_trait)]
+
+use core::{cell::Cell, fmt::Write as _};
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+use heapless::{String, Vec};
+use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    net::cellular::{CellularError, HttpBodySink},
+    solar_monitor::cloud::{number_after, string_after},
+    storage::{ConfigKey, ConfigValue},
+    watchdog::WatchdogHandle,
+};
+
+pub const FIRMWARE_KEY: ConfigKey<FirmwareKey> = ConfigKey::new("ota", "firmware_key");
+
+pub const OTA_CHUNK_SIZE: usize = 512;
+pub const OTA_URL_SIZE: usize = 128;
+/// The JSON of a [`FirmwareManifest`] with the longest URL and signature.
+pub const FIRMWARE_MANIFEST_SIZE: usize = 512;
+/// Longest DER encoded P-256 signature.
+const SIGNATURE_SIZE: usize = 72;
+/// `<version>:<size>:<sha256>`.
+const STATEMENT_SIZE: usize = 10 + 1 + 10 + 1 + 64;
+/// Uncompressed SEC1 point of a P-256 key.
+const KEY_SIZE: usize = 65;
+const OTA_QUEUE_SIZE: usize = 2;
+
+const VERSION_KEY: &str = "\"version\":";
+const SIZE_KEY: &str = "\"size\":";
+const SHA256_KEY: &str = "\"sha256\":\"";
+const SIGNATURE_KEY: &str = "\"signature\":\"";
+const URL_KEY: &str = "\"url\":\"";
+
+/// The public key of the release signatures, an uncompressed SEC1 point
+/// (`openssl ec -pubout -outform der | tail -c 65`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareKey {
+    pub sec1: [u8; KEY_SIZE],
+}
+
+impl ConfigValue for FirmwareKey {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.sec1.as_slice().encode(buffer)
+    }
+
+    /// Only a point on the curve.
+    fn decode(data: &[u8]) -> Option<Self> {
+        VerifyingKey::from_sec1_bytes(data).ok()?;
+        Some(Self { sec1: data.try_into().ok()? })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareManifest {
+    pub version: u32,
+    pub size: u32,
+    pub sha256: [u8; 32],
+    /// DER encoded, see the module documentation.
+    pub signature: Vec<u8, SIGNATURE_SIZE>,
+    pub url: String<OTA_URL_SIZE>,
+}
+
+impl FirmwareManifest {
+    /// The manifest in a response body, `None` if a field is missing or malformed.
+    pub fn parse(body: &str) -> Option<Self> {
+        Some(Self {
+            version: number_after(body, VERSION_KEY)?,
+            size: number_after(body, SIZE_KEY)?,
+            sha256: hex::<32>(string_after(body, SHA256_KEY)?)?.into_array().ok()?,
+            signature: hex(string_after(body, SIGNATURE_KEY)?)?,
+            url: string_after(body, URL_KEY)?.try_into().ok()?,
+        })
+    }
+
+    /// What the release key signed.
+    fn statement(&self) -> String<STATEMENT_SIZE> {
+        let mut statement = String::new();
+        let _ = write!(statement, "{}:{}:", self.version, self.size);
+        for byte in self.sha256 {
+            let _ = write!(statement, "{:02x}", byte);
+        }
+        statement
+    }
+
+    fn verify(&self, key: &FirmwareKey) -> Result<(), OtaError> {
+        let key = VerifyingKey::from_sec1_bytes(&key.sec1).map_err(|_| OtaError::InvalidSignature)?;
+        let signature = Signature::from_der(&self.signature).map_err(|_| OtaError::InvalidSignature)?;
+        key.verify(self.statement().as_bytes(), &signature).map_err(|_| OtaError::InvalidSignature)
+    }
+}
+
+/// The bytes of a hex string, `None` for odd lengths, other characters or more than `N` bytes.
+fn hex<const N: usize>(text: &str) -> Option<Vec<u8, N>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    for pair in text.as_bytes().chunks(2) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        bytes.push(u8::from_str_radix(pair, 16).ok()?).ok()?;
+    }
+    Some(bytes)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OtaError {
+    /// Writing or reading the slot or the bootloader state failed.
+    Flash,
+    /// The image does not fit into the slot.
+    TooLarge,
+    SizeMismatch,
+    /// The image read back from the slot does not hash to the manifest.
+    ChecksumMismatch,
+    /// The manifest is not signed by the [`FirmwareKey`].
+    InvalidSignature,
+    /// The download was aborted by the cloud runner.
+    Aborted,
+    InvalidManifest,
+    Timeout,
+    Download(CellularError),
+}
+
+impl From<CellularError> for OtaError {
+    fn from(err: CellularError) -> Self {
+        OtaError::Download(err)
+    }
+}
+
+/// Secondary flash slot the new image is written to, with the state the bootloader reads.
+pub trait FirmwareSlot {
+    /// Largest image in bytes, the size of the primary slot it is swapped into.
+    fn capacity(&self) -> u32;
+    /// Writes the next part of the image, erasing the sectors as it goes.
+    async fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), OtaError>;
+    async fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), OtaError>;
+    /// Marks the slot to be swapped in by the bootloader on the next reset.
+    async fn mark_updated(&mut self) -> Result<(), OtaError>;
+    /// Keeps the running firmware, otherwise the bootloader swaps the previous one back on the next reset.
+    async fn mark_booted(&mut self) -> Result<(), OtaError>;
+}
+
+// The channel slots have the size of a chunk anyway.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OtaMessage {
+    Begin(FirmwareManifest),
+    Chunk(Vec<u8, OTA_CHUNK_SIZE>),
+    End,
+    Abort,
+}
+
+/// Link between the cloud runner (downloading) and the [`OtaRunner`] (writing the slot).
+pub struct Ota {
+    key: FirmwareKey,
+    messages: Channel<NoopRawMutex, OtaMessage, OTA_QUEUE_SIZE>,
+    result: Signal<NoopRawMutex, Result<(), OtaError>>,
+    confirmed: Signal<NoopRawMutex, ()>,
+    booted: Cell<bool>,
+}
+
+impl Ota {
+    /// Updates signed by `key`, see the module documentation.
+    pub fn new(key: FirmwareKey) -> Self {
+        Self {
+            key,
+            messages: Channel::new(),
+            result: Signal::new(),
+            confirmed: Signal::new(),
+            booted: Cell::new(false),
+        }
+    }
+
+    pub fn runner<S: FirmwareSlot>(&self, slot: S) -> OtaRunner<'_, S> {
+        OtaRunner { ota: self, slot }
+    }
+
+    /// The running firmware works, called by the cloud runner after the first successful upload.
+    pub(crate) fn confirm(&self) {
+        if !self.booted.get() {
+            self.confirmed.signal(());
+        }
+    }
+
+    /// Whether the runner marked the running firmware booted, updates are only accepted after.
+    pub(crate) fn booted(&self) -> bool {
+        self.booted.get()
+    }
+
+    pub(crate) async fn send(&self, message: OtaMessage) {
+        self.messages.send(message).await;
+    }
+
+    pub(crate) fn reset_result(&self) {
+        self.result.reset();
+    }
+
+    /// Waits for the result of the update started by the last [`OtaMessage::Begin`].
+    pub(crate) async fn wait_result(&self) -> Result<(), OtaError> {
+        self.result.wait().await
+    }
+}
+
+pub struct OtaRunner<'a, S: FirmwareSlot> {
+    ota: &'a Ota,
+    slot: S,
+}
+
+impl<'a, S: FirmwareSlot> OtaRunner<'a, S> {
+    /// Confirms the running firmware, then applies updates until one is verified and marked for the swap.
+    pub async fn run(mut self) {
+        self.ota.confirmed.wait().await;
+        if let Err(e) = self.slot.mark_booted().await {
+            warn!("OTA> marking the firmware booted failed: {:?}", e);
+            return core::future::pending().await;
+        }
+        info!("OTA> firmware confirmed");
+        self.ota.booted.set(true);
+        loop {
+            let result = self.update().await;
+            let done = result.is_ok();
+            match &result {
+                Ok(()) => info!("OTA> image verified, swap on next reset"),
+                Err(e) => warn!("OTA> update failed: {:?}", e),
+            }
+            self.ota.result.signal(result);
+            if done {
+                return;
+            }
+        }
+    }
+
+    async fn update(&mut self) -> Result<(), OtaError> {
+        let manifest = loop {
+            if let OtaMessage::Begin(manifest) = self.ota.messages.receive().await {
+                break manifest;
+            }
+        };
+        info!("OTA> receiving firmware {} ({} bytes)", manifest.version, manifest.size);
+        if manifest.size > self.slot.capacity() {
+            return Err(OtaError::TooLarge);
+        }
+        let mut offset = 0u32;
+        loop {
+            match self.ota.messages.receive().await {
+                OtaMessage::Chunk(data) => {
+                    if offset + data.len() as u32 > manifest.size {
+                        return Err(OtaError::SizeMismatch);
+                    }
+                    self.slot.write(offset, &data).await?;
+                    offset += data.len() as u32;
+                }
+                OtaMessage::End => break,
+                OtaMessage::Abort => return Err(OtaError::Aborted),
+                OtaMessage::Begin(_) => return Err(OtaError::Aborted),
+            }
+        }
+        if offset != manifest.size {
+            return Err(OtaError::SizeMismatch);
+        }
+        self.verify(&manifest).await?;
+        self.slot.mark_updated().await
+    }
+
+    /// Hashes the image read back from the slot, so flash write errors are caught as well, and
+    /// checks the signature of the manifest.
+    async fn verify(&mut self, manifest: &FirmwareManifest) -> Result<(), OtaError> {
+        let mut sha256 = Sha256::new();
+        let mut buf = [0u8; OTA_CHUNK_SIZE];
+        let mut offset = 0u32;
+        while offset < manifest.size {
+            let len = core::cmp::min(OTA_CHUNK_SIZE as u32, manifest.size - offset) as usize;
+            self.slot.read(offset, &mut buf[..len]).await?;
+            sha256.update(&buf[..len]);
+            offset += len as u32;
+        }
+        if sha256.finalize().as_slice() != manifest.sha256 {
+            return Err(OtaError::ChecksumMismatch);
+        }
+        manifest.verify(&self.ota.key)
+    }
+}
+
+/// Cuts the streamed response body into [`OtaMessage::Chunk`]s.
+pub(crate) struct ImageSink<'a> {
+    ota: &'a Ota,
+    chunk: Vec<u8, OTA_CHUNK_SIZE>,
+    watchdog: WatchdogHandle<'a>,
+}
+
+impl<'a> ImageSink<'a> {
+    pub(crate) fn new(ota: &'a Ota, watchdog: WatchdogHandle<'a>) -> Self {
+        Self {
+            ota,
+            chunk: Vec::new(),
+            watchdog,
+        }
+    }
+
+    /// Sends the last partial chunk.
+    pub(crate) async fn flush(&mut self) {
+        if !self.chunk.is_empty() {
+            let chunk = core::mem::take(&mut self.chunk);
+            self.ota.send(OtaMessage::Chunk(chunk)).await;
+        }
+    }
+}
+
+impl HttpBodySink for ImageSink<'_> {
+    async fn write(&mut self, mut data: &[u8]) -> Result<(), CellularError> {
+        while !data.is_empty() {
+            let n = core::cmp::min(OTA_CHUNK_SIZE - self.chunk.len(), data.len());
+            let _ = self.chunk.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.chunk.is_full() {
+                self.flush().await;
+                self.watchdog.feed();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use embassy_futures::join::join;
+
+    /// `openssl ecparam -name prime256v1 -genkey`, the public key of the fixtures.
+    const KEY: &str = "04833515155fac4a9568de1ca70d5832bc9db4f9fda8bc9c729a2461cc198c9d36dfe26037ac07606ea0bd26b1c43503d3db2f9a0637669c4d6ac184c19332de03";
+    /// `image(1300)` as firmware 8, signed with `openssl dgst -sha256 -sign`.
+    const MANIFEST: &str = concat!(
+        r#"{"version":8,"size":1300,"#,
+        r#""sha256":"29ce70e9f9046ea286bda508ba6a0f322be3bb01c64616f8bd9d4743bf63a0c9","#,
+        r#""signature":"3045022036474fe052008346923c25ee32b51b908704b56f5bc9884bddae11364627a249022100c01feb0d2a3bb4cc666dcc4fd92cac42bbea812122ac2c8fe512e2fb95d318b1","#,
+        r#""url":"http://example.com/api/v2/solar/firmware/8"}"#
+    );
+
+    struct RamSlot {
+        data: std::vec::Vec<u8>,
+        capacity: u32,
+        updated: bool,
+        booted: bool,
+    }
+
+    impl RamSlot {
+        fn new(capacity: u32) -> Self {
+            Self {
+                data: std::vec![0xFF; capacity as usize],
+                capacity,
+                updated: false,
+                booted: false,
+            }
+        }
+    }
+
+    impl FirmwareSlot for &mut RamSlot {
+        fn capacity(&self) -> u32 {
+            self.capacity
+        }
+
+        async fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), OtaError> {
+            self.data[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        async fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), OtaError> {
+            buf.copy_from_slice(&self.data[offset as usize..offset as usize + buf.len()]);
+            Ok(())
+        }
+
+        async fn mark_updated(&mut self) -> Result<(), OtaError> {
+            self.updated = true;
+            Ok(())
+        }
+
+        async fn mark_booted(&mut self) -> Result<(), OtaError> {
+            self.booted = true;
+            Ok(())
+        }
+    }
+
+    fn key() -> FirmwareKey {
+        FirmwareKey::decode(&hex::<KEY_SIZE>(KEY).unwrap()).unwrap()
+    }
+
+    fn manifest() -> FirmwareManifest {
+        FirmwareManifest::parse(MANIFEST).unwrap()
+    }
+
+    fn image(len: usize) -> std::vec::Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    async fn stream(ota: &Ota, manifest: FirmwareManifest, image: &[u8]) {
+        ota.reset_result();
+        ota.send(OtaMessage::Begin(manifest)).await;
+        let mut sink = ImageSink::new(ota, WatchdogHandle::default());
+        for part in image.chunks(300) {
+            sink.write(part).await.unwrap();
+        }
+        sink.flush().await;
+        ota.send(OtaMessage::End).await;
+    }
+
+    async fn update(slot: &mut RamSlot, manifest: FirmwareManifest, image: &[u8]) -> Result<(), OtaError> {
+        let ota = Ota::new(key());
+        let mut runner = ota.runner(slot);
+        let (result, _) = join(runner.update(), stream(&ota, manifest, image)).await;
+        result
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = manifest();
+        assert_eq!(manifest.version, 8);
+        assert_eq!(manifest.size, 1300);
+        assert_eq!(manifest.sha256[..2], [0x29, 0xce]);
+        assert_eq!(manifest.signature.len(), 71);
+        assert_eq!(manifest.url.as_str(), "http://example.com/api/v2/solar/firmware/8");
+        assert_eq!(FirmwareManifest::parse(&MANIFEST.replace("\"sha256\":\"29", "\"sha256\":\"2")), None);
+        assert_eq!(FirmwareManifest::parse(&MANIFEST.replace("\"url\"", "\"link\"")), None);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let mut manifest = manifest();
+        assert_eq!(manifest.verify(&key()), Ok(()));
+        manifest.version = 9;
+        assert_eq!(manifest.verify(&key()), Err(OtaError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_decode_key() {
+        let mut sec1 = hex::<KEY_SIZE>(KEY).unwrap();
+        assert!(FirmwareKey::decode(&sec1).is_some());
+        sec1[64] ^= 1;
+        assert_eq!(FirmwareKey::decode(&sec1), None);
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let ota = Ota::new(key());
+        let mut slot = RamSlot::new(4096);
+        let data = image(1300);
+        let (_, result) = join(ota.runner(&mut slot).run(), async {
+            ota.confirm();
+            stream(&ota, manifest(), &data).await;
+            ota.wait_result().await
+        })
+        .await;
+        assert_eq!(result, Ok(()));
+        assert!(ota.booted());
+        assert_eq!(slot.data[..1300], data);
+        assert!(slot.booted);
+        assert!(slot.updated);
+    }
+
+    #[tokio::test]
+    async fn test_update_checksum_mismatch() {
+        let mut slot = RamSlot::new(4096);
+        let mut data = image(1300);
+        data[700] ^= 1;
+        assert_eq!(update(&mut slot, manifest(), &data).await, Err(OtaError::ChecksumMismatch));
+        assert!(!slot.updated);
+    }
+
+    #[tokio::test]
+    async fn test_update_other_version() {
+        let mut slot = RamSlot::new(4096);
+        let mut manifest = manifest();
+        manifest.version = 9;
+        assert_eq!(update(&mut slot, manifest, &image(1300)).await, Err(OtaError::InvalidSignature));
+        assert!(!slot.updated);
+    }
+
+    #[tokio::test]
+    async fn test_update_size_mismatch() {
+        let mut slot = RamSlot::new(4096);
+        assert_eq!(update(&mut slot, manifest(), &image(1200)).await, Err(OtaError::SizeMismatch));
+        assert!(!slot.updated);
+    }
+
+    #[tokio::test]
+    async fn test_update_too_large() {
+        let ota = Ota::new(key());
+        let mut slot = RamSlot::new(1024);
+        let mut runner = ota.runner(&mut slot);
+        let (result, _) = join(runner.update(), ota.send(OtaMessage::Begin(manifest()))).await;
+        assert_eq!(result, Err(OtaError::TooLarge));
+    }
+}
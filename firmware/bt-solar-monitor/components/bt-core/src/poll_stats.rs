@@ -0,0 +1,188 @@
+//! Poll durations of the executor tasks, to find long synchronous sections.
+//!
+//! A poll that runs for long (protobuf encode, flash erase) starves the other tasks of the
+//! executor, the UART tasks lose bytes. The app forwards the task hooks of the embassy-executor
+//! `trace` feature to [`POLL_STATS`], the cloud runner reports the tasks whose longest poll
+//! exceeded the limit. Task ids are the addresses of the task storage, see the map file of the
+//! build. A poll preempted by an interrupt executor counts the interrupt time as well.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::proto::bt_::solar_::{PollStatsEvent, TaskPollStats};
+
+/// Tasks beyond are not tracked.
+pub const MAX_TRACKED_TASKS: usize = 16;
+
+pub static POLL_STATS: PollStats = PollStats::new();
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TaskStats {
+    pub task_id: u32,
+    pub polls: u32,
+    pub longest_poll: Duration,
+    pub busy: Duration,
+    /// Start of the running poll.
+    started: Option<Instant>,
+}
+
+struct Stats {
+    tasks: Vec<TaskStats, MAX_TRACKED_TASKS>,
+    since: Option<Instant>,
+}
+
+/// Hook of the embassy-executor `trace` feature, a poll of `task_id` starts.
+pub fn task_exec_begin(task_id: u32) {
+    POLL_STATS.exec_begin(task_id, Instant::now());
+}
+
+/// Hook of the embassy-executor `trace` feature, the poll of `task_id` ended.
+pub fn task_exec_end(task_id: u32) {
+    POLL_STATS.exec_end(task_id, Instant::now());
+}
+
+pub struct PollStats {
+    stats: Mutex<CriticalSectionRawMutex, RefCell<Stats>>,
+}
+
+impl PollStats {
+    pub const fn new() -> Self {
+        Self {
+            stats: Mutex::new(RefCell::new(Stats {
+                tasks: Vec::new(),
+                since: None,
+            })),
+        }
+    }
+
+    pub fn exec_begin(&self, task_id: u32, now: Instant) {
+        self.stats.lock(|stats| {
+            let mut stats = stats.borrow_mut();
+            stats.since.get_or_insert(now);
+            if let Some(task) = Self::task(&mut stats.tasks, task_id) {
+                task.started = Some(now);
+            }
+        });
+    }
+
+    pub fn exec_end(&self, task_id: u32, now: Instant) {
+        self.stats.lock(|stats| {
+            if let Some(task) = Self::task(&mut stats.borrow_mut().tasks, task_id)
+                && let Some(started) = task.started.take()
+            {
+                let poll = now.saturating_duration_since(started);
+                task.polls += 1;
+                task.longest_poll = task.longest_poll.max(poll);
+                task.busy += poll;
+            }
+        });
+    }
+
+    fn task(tasks: &mut Vec<TaskStats, MAX_TRACKED_TASKS>, task_id: u32) -> Option<&mut TaskStats> {
+        match tasks.iter().position(|task| task.task_id == task_id) {
+            Some(index) => tasks.get_mut(index),
+            None => {
+                tasks.push(TaskStats { task_id, ..Default::default() }).ok()?;
+                tasks.last_mut()
+            }
+        }
+    }
+
+    /// The tasks with a poll longer than `limit` since the last report, longest poll first.
+    ///
+    /// Starts a new period, `None` without offenders.
+    pub fn take_report(&self, limit: Duration, now: Instant) -> Option<PollStatsEvent> {
+        self.stats.lock(|stats| {
+            let mut stats = stats.borrow_mut();
+            let since = stats.since.replace(now)?;
+            let mut offenders: Vec<TaskStats, MAX_TRACKED_TASKS> = stats.tasks.iter().filter(|task| task.longest_poll > limit).copied().collect();
+            for task in stats.tasks.iter_mut() {
+                *task = TaskStats {
+                    task_id: task.task_id,
+                    started: task.started,
+                    ..Default::default()
+                };
+            }
+            if offenders.is_empty() {
+                return None;
+            }
+            offenders.sort_unstable_by_key(|task| core::cmp::Reverse(task.longest_poll));
+            let mut event = PollStatsEvent {
+                period_seconds: now.saturating_duration_since(since).as_secs() as u32,
+                limit_us: limit.as_micros() as u32,
+                ..Default::default()
+            };
+            for task in offenders.iter() {
+                warn!("Task {:#x} polled for up to {} us", task.task_id, task.longest_poll.as_micros());
+                if event
+                    .offenders
+                    .push(TaskPollStats {
+                        task_id: task.task_id,
+                        polls: task.polls,
+                        longest_poll_us: task.longest_poll.as_micros() as u32,
+                        busy_ms: task.busy.as_millis() as u32,
+                    })
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Some(event)
+        })
+    }
+}
+
+impl Default for PollStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn poll(stats: &PollStats, task_id: u32, at: u64, millis: u64) {
+        stats.exec_begin(task_id, Instant::from_millis(at));
+        stats.exec_end(task_id, Instant::from_millis(at + millis));
+    }
+
+    #[test]
+    fn check_report() {
+        let stats = PollStats::new();
+        assert!(stats.take_report(Duration::from_millis(5), Instant::from_millis(1_000)).is_none());
+
+        poll(&stats, 0x2000_0100, 1_000, 1);
+        poll(&stats, 0x2000_0200, 1_010, 12);
+        poll(&stats, 0x2000_0100, 1_030, 2);
+        poll(&stats, 0x2000_0300, 1_040, 40);
+        poll(&stats, 0x2000_0200, 1_090, 3);
+        let event = stats.take_report(Duration::from_millis(5), Instant::from_millis(61_000)).unwrap();
+        assert_eq!(event.period_seconds, 60);
+        assert_eq!(event.limit_us, 5_000);
+        let offenders: std::vec::Vec<_> = event
+            .offenders
+            .iter()
+            .map(|task| (task.task_id, task.polls, task.longest_poll_us, task.busy_ms))
+            .collect();
+        assert_eq!(offenders, [(0x2000_0300, 1, 40_000, 40), (0x2000_0200, 2, 12_000, 15)]);
+
+        // a new period
+        poll(&stats, 0x2000_0100, 62_000, 2);
+        assert!(stats.take_report(Duration::from_millis(5), Instant::from_millis(70_000)).is_none());
+    }
+
+    #[test]
+    fn check_task_limit() {
+        let stats = PollStats::new();
+        for task_id in 0..MAX_TRACKED_TASKS as u32 + 2 {
+            poll(&stats, task_id, 0, 10);
+        }
+        let event = stats.take_report(Duration::from_millis(5), Instant::from_millis(100)).unwrap();
+        assert_eq!(event.offenders.len(), crate::schema::MAX_LEN);
+    }
+}
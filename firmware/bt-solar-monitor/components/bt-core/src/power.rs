@@ -0,0 +1,174 @@
+//! Power management between the averaging windows.
+//!
+//! The nRF enters System ON idle on its own whenever the executor has nothing
+//! to do, the VE.Direct UART RX interrupt wakes it again. What keeps the
+//! average current up are the modem and the board peripherals (LEDs, UART
+//! clocks). Runners that need the device awake hold a [`WakeLock`], the board
+//! task follows [`PowerManager::run`] and switches its peripherals off while
+//! nobody holds one.
+//!
+//! The idle windows, the modem asleep between the upload bursts, are also the time for
+//! the flash maintenance, see [`PowerManager::wait_idle`].
+
+use core::cell::Cell;
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::{Duration, with_timeout};
+
+use crate::solar_monitor::Flush;
+
+const POWER_DOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerState {
+    /// At least one wake lock is held.
+    Active,
+    /// Nobody needs the device, only the averaging keeps running.
+    Idle,
+}
+
+pub struct PowerManager {
+    locks: Cell<u32>,
+    changed: Signal<NoopRawMutex, PowerState>,
+    idle: Signal<NoopRawMutex, ()>,
+}
+
+impl PowerManager {
+    pub fn new() -> Self {
+        Self {
+            locks: Cell::new(0),
+            changed: Signal::new(),
+            idle: Signal::new(),
+        }
+    }
+
+    pub fn handle(&self) -> PowerHandle<'_> {
+        PowerHandle { manager: Some(self) }
+    }
+
+    pub fn state(&self) -> PowerState {
+        if self.locks.get() > 0 { PowerState::Active } else { PowerState::Idle }
+    }
+
+    /// Waits for the next change of the power state.
+    pub async fn wait_for_change(&self) -> PowerState {
+        self.changed.wait().await
+    }
+
+    /// Waits until nobody holds a wake lock, for the flash maintenance of one runner.
+    pub async fn wait_idle(&self) {
+        while self.state() == PowerState::Active {
+            self.idle.wait().await;
+        }
+    }
+
+    /// Calls `on_change` with the current state and then on every change.
+    pub async fn run(&self, mut on_change: impl FnMut(PowerState)) {
+        let mut state = self.state();
+        on_change(state);
+        loop {
+            let next = self.wait_for_change().await;
+            if next != state {
+                info!("Power> {:?}", next);
+                state = next;
+                on_change(state);
+            }
+        }
+    }
+
+    /// Prepares a planned power down or restart, the partial upload batch is queued first.
+    pub async fn prepare_power_down(&self, flush: &Flush) {
+        prepare_power_down(flush).await
+    }
+
+    fn acquire(&self) {
+        let locks = self.locks.get();
+        self.locks.set(locks + 1);
+        if locks == 0 {
+            self.changed.signal(PowerState::Active);
+        }
+    }
+
+    fn release(&self) {
+        let locks = self.locks.get().saturating_sub(1);
+        self.locks.set(locks);
+        if locks == 0 {
+            self.changed.signal(PowerState::Idle);
+            self.idle.signal(());
+        }
+    }
+}
+
+impl Default for PowerManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn prepare_power_down(flush: &Flush) {
+    info!("Power> preparing power down");
+    if with_timeout(POWER_DOWN_FLUSH_TIMEOUT, crate::solar_monitor::flush(flush)).await.is_err() {
+        warn!("Power> upload flush timed out");
+    }
+}
+
+/// Access to the power manager for a runner, the default handle is not managed.
+#[derive(Copy, Clone, Default)]
+pub struct PowerHandle<'a> {
+    manager: Option<&'a PowerManager>,
+}
+
+impl<'a> PowerHandle<'a> {
+    pub fn acquire(&self) -> WakeLock<'a> {
+        if let Some(manager) = self.manager {
+            manager.acquire();
+        }
+        WakeLock { manager: self.manager }
+    }
+
+    /// See [`PowerManager::prepare_power_down`], also without a manager.
+    pub async fn prepare_power_down(&self, flush: &Flush) {
+        prepare_power_down(flush).await
+    }
+}
+
+/// Keeps the device awake until dropped.
+pub struct WakeLock<'a> {
+    manager: Option<&'a PowerManager>,
+}
+
+impl Drop for WakeLock<'_> {
+    fn drop(&mut self) {
+        if let Some(manager) = self.manager {
+            manager.release();
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wake_locks() {
+        let manager = PowerManager::new();
+        let handle = manager.handle();
+        assert_eq!(manager.state(), PowerState::Idle);
+
+        let first = handle.acquire();
+        assert_eq!(manager.wait_for_change().await, PowerState::Active);
+        let second = handle.acquire();
+        drop(first);
+        assert_eq!(manager.state(), PowerState::Active);
+        drop(second);
+        assert_eq!(manager.state(), PowerState::Idle);
+        assert_eq!(manager.wait_for_change().await, PowerState::Idle);
+    }
+
+    #[test]
+    fn test_unmanaged_handle() {
+        let lock = PowerHandle::default().acquire();
+        drop(lock);
+    }
+}
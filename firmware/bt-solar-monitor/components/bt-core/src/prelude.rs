@@ -0,0 +1,142 @@
+//! The stable surface for the application crates.
+//!
+//! Apps import from here, the modules behind it are private and may move. The
+//! top level has the types, traits and error types the boards configure and
+//! implement, with the keys of their settings in the config store. [`tasks`] has
+//! the task constructors, [`driver`] the few helpers for a modem driver and the
+//! hooks of the executor and the global logger. Only the logging macros (`info!`,
+//! `warn!`, ...) and the build `config` stay at the crate root.
+
+pub use crate::{
+    activity::{ACTIVITY, Monitored, UartPath},
+    at::{
+        AtClient, AtClientImpl, AtCommandResponse, AtController, AtControllerImpl, AtError, AtPriority, PingError,
+        bridge::BridgeEnd,
+        gnss::GnssPosition,
+        http::HttpStatusCode,
+        identification::ModuleIdentity,
+        network::{AccessTechnology, LteBands, NetworkRegistrationState},
+        packet_domain::{AuthProtocol, PdpAuth, PdpType},
+        quirks::{QuirkRule, QuirkTable, Quirks, active_quirks, load_quirks},
+        serial_interface::SleepMode,
+        status_control::Rssi,
+    },
+    audit::{AUDIT_ENTRY_SIZE, Audit, AuditCursors, AuditEntry, AuditError, AuditStore, CommandOrigin},
+    crash::{CRASH_REPORT_SIZE, CrashRecord, CrashReport},
+    fmt::FormatableDuration,
+    health::{HEALTH, ResetReason},
+    identify::{BLINK_PATTERN, IDENTIFY},
+    log_ring::{LOG_CHUNK_SIZE, LogCursors, LogError, LogStore},
+    net::{
+        cellular::{
+            CellularError, CellularModem, HttpBodySink, LinkQuality, NetworkLock, TLS_PIN, TlsPin,
+            power_cycles::PowerCycleRecord,
+            ppp::{HttpTransport, PinnedTlsTransport, PppCellularModule, PppLink, SocketTransport, TcpConnector},
+            quectel_bg9x::QuectelCellularModule,
+            sim_com_a67::SimComCellularModule,
+        },
+        http::{HttpResponse, Url},
+        tls::{TLS_READ_BUFFER_SIZE, TLS_WRITE_BUFFER_SIZE},
+    },
+    ota::{FIRMWARE_KEY, FirmwareKey, FirmwareSlot, Ota, OtaError, OtaRunner},
+    power::{PowerHandle, PowerManager, PowerState, WakeLock},
+    sensor::{
+        analog::{ANALOG_CALIBRATION, AnalogCalibration, AnalogInput, ChannelCalibration},
+        filter::{Field, Filter, ReadingFilter},
+        pulse::PulseInput,
+        temperature::{TemperatureChip, TemperatureError, TemperatureSensor},
+        ve_direct::{
+            battery_monitor::BatteryReading,
+            charger_error::ChargerErrors,
+            hex::{HexError, LoadOutput, LoadSwitch},
+            history::DailyHistory,
+        },
+    },
+    shell::{
+        SHELL_PIN, ShellError, ShellPolicy,
+        console::{Console, ShellBackend},
+    },
+    solar_monitor::{
+        Flush,
+        apn::{APN_PROFILES, ApnProfile, ApnProfiles},
+        battery::{AlarmPreset, BatteryAlarms, BatteryPolicy, CHEMISTRY, Chemistry},
+        cbor::{UPLOAD_ENCODING, UploadEncoding},
+        cloud::PRIVACY_MODE,
+        envelope::{ENVELOPE_KEY, EnvelopeKey},
+        flush,
+        retry::RetryPolicy,
+        scheduler::{StalenessLimits, UploadScheduler, UploadWindow},
+        sensors::SensorSource,
+        soc::{SocConfig, SocCurve, SocEstimator},
+        upload::{BATCH_POLICY, BatchPolicy, UploadStatus},
+        upload_queue::{UploadQueue, UploadQueueError, UploadStore},
+    },
+    storage::{CONFIG_MIGRATIONS, ConfigKey, ConfigStore, KeyValueStore, StorageError},
+    time::{TimeQuality, UtcTime, restore_time},
+    timeouts::Timeouts,
+    watchdog::{Watchdog, WatchdogHandle},
+};
+
+/// The task constructors, each module has a `State` (where needed), `new` and `Runner`.
+pub mod tasks {
+    pub use crate::{at::quirks::persist_quirks, time::persist_time};
+
+    pub mod at {
+        pub use crate::at::{Runner, Shutdown, State, bridge::bridge, new, passthrough, shutdown};
+    }
+
+    pub mod cloud {
+        pub use crate::solar_monitor::cloud::{Runner, new};
+    }
+
+    pub mod upload {
+        pub use crate::solar_monitor::upload::{Runner, new};
+    }
+
+    pub mod metrics {
+        pub use crate::solar_monitor::metrics::{Runner, new};
+    }
+
+    pub mod log_ring {
+        pub use crate::log_ring::{LogRunner as Runner, new};
+    }
+
+    pub mod ve_direct {
+        pub use crate::sensor::ve_direct::{Runner, State, new};
+    }
+
+    pub mod analog {
+        pub use crate::sensor::analog::{Runner, State, new};
+    }
+
+    pub mod pulse {
+        pub use crate::sensor::pulse::{Runner, State, new};
+    }
+
+    pub mod temperature {
+        pub use crate::sensor::temperature::{Runner, State, new};
+    }
+
+    pub mod battery_monitor {
+        pub use crate::sensor::ve_direct::battery_monitor::{Runner, State, new};
+    }
+
+    /// With the [`shared_uart::SharedUart`] handing out the [`shared_uart::Port`]s of the devices.
+    pub mod shared_uart {
+        pub use crate::shared_uart::{LineDiscipline, Port, Runner, SharedLine, SharedUart, SharedUartError, State, any_line, new, nmea};
+    }
+}
+
+/// For the modem drivers of the boards, e.g. on the modem firmware of the nRF9160, and the
+/// hooks of the embassy-executor `trace` feature and the defmt global logger.
+pub mod driver {
+    pub use crate::{
+        at::{
+            network::{parse_eps_network_registration, parse_operator_selection},
+            status_control::parse_real_time_clock,
+        },
+        log_ring::log_frame,
+        net::http::write_request,
+        poll_stats::{task_exec_begin, task_exec_end},
+    };
+}
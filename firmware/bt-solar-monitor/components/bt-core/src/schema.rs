@@ -0,0 +1,154 @@
+//! The upload schema the firmware is built with, for the compatibility checks with the backend.
+//!
+//! [`write_manifest`] writes it as JSON. `schema.json` next to the `Cargo.toml` is the
+//! checked in copy for the backend, a host test fails as soon as it is out of date, the
+//! manifest is only built for the host tests.
+
+#[cfg(test)]
+use core::fmt::Write;
+
+#[cfg(test)]
+use micropb::MessageEncode;
+
+#[cfg(test)]
+use crate::proto::bt_::solar_::{
+    BatteryMonitorReading, ChargerErrorEvent, CommandAuditEvent, CrashEvent, HealthEvent, HistoryDayEvent, LinkQualityEvent, OfflineEvent, OnlineEvent,
+    PollStatsEvent, PositionEvent, Reading, Spread, StartupEvent, SystemEvent, TaskPollStats, Upload, UploadEntry,
+};
+
+#[cfg(test)]
+mod wire_fixtures;
+
+#[cfg(test)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/schema.rs"));
+}
+
+#[cfg(test)]
+pub use generated::{CONFIG_KEYS, MAX_BYTES, MAX_LEN, PROTO_FINGERPRINT};
+
+/// Bumped with every change of the proto file the backend has to know about.
+///
+/// Sent in the `schema_version` of every `Upload` and `SystemEvent` and as the
+/// `X-Proto-Version` header. Fields are only added, so the backend can decode
+/// older versions and the firmware ignores what a newer backend would expect.
+pub const SCHEMA_VERSION: u32 = 17;
+
+/// Encoded size limit of the messages, `None` for unbounded ones.
+#[cfg(test)]
+pub const MAX_SIZES: &[(&str, Option<usize>)] = &[
+    (".bt.solar.Reading", Reading::MAX_SIZE),
+    (".bt.solar.Spread", Spread::MAX_SIZE),
+    (".bt.solar.BatteryMonitorReading", BatteryMonitorReading::MAX_SIZE),
+    (".bt.solar.UploadEntry", UploadEntry::MAX_SIZE),
+    (".bt.solar.Upload", Upload::MAX_SIZE),
+    (".bt.solar.SystemEvent", SystemEvent::MAX_SIZE),
+    (".bt.solar.StartupEvent", StartupEvent::MAX_SIZE),
+    (".bt.solar.OnlineEvent", OnlineEvent::MAX_SIZE),
+    (".bt.solar.OfflineEvent", OfflineEvent::MAX_SIZE),
+    (".bt.solar.CrashEvent", CrashEvent::MAX_SIZE),
+    (".bt.solar.PositionEvent", PositionEvent::MAX_SIZE),
+    (".bt.solar.LinkQualityEvent", LinkQualityEvent::MAX_SIZE),
+    (".bt.solar.CommandAuditEvent", CommandAuditEvent::MAX_SIZE),
+    (".bt.solar.ChargerErrorEvent", ChargerErrorEvent::MAX_SIZE),
+    (".bt.solar.TaskPollStats", TaskPollStats::MAX_SIZE),
+    (".bt.solar.PollStatsEvent", PollStatsEvent::MAX_SIZE),
+    (".bt.solar.HealthEvent", HealthEvent::MAX_SIZE),
+    (".bt.solar.HistoryDayEvent", HistoryDayEvent::MAX_SIZE),
+];
+
+#[cfg(test)]
+pub fn write_manifest(out: &mut impl Write) -> core::fmt::Result {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"schema_version\": {},", SCHEMA_VERSION)?;
+    writeln!(out, "  \"proto_fingerprint\": \"{:#010x}\",", PROTO_FINGERPRINT)?;
+    writeln!(out, "  \"max_len\": {},", MAX_LEN)?;
+    writeln!(out, "  \"max_bytes\": {{")?;
+    for (index, (field, max_bytes)) in MAX_BYTES.iter().enumerate() {
+        writeln!(out, "    \"{}\": {}{}", field, max_bytes, separator(index, MAX_BYTES.len()))?;
+    }
+    writeln!(out, "  }},")?;
+    writeln!(out, "  \"max_sizes\": {{")?;
+    for (index, (message, max_size)) in MAX_SIZES.iter().enumerate() {
+        match max_size {
+            Some(max_size) => write!(out, "    \"{}\": {}", message, max_size)?,
+            None => write!(out, "    \"{}\": null", message)?,
+        }
+        writeln!(out, "{}", separator(index, MAX_SIZES.len()))?;
+    }
+    writeln!(out, "  }},")?;
+    writeln!(out, "  \"config_keys\": [")?;
+    for (index, key) in CONFIG_KEYS.iter().enumerate() {
+        writeln!(out, "    \"{}\"{}", key, separator(index, CONFIG_KEYS.len()))?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+fn separator(index: usize, len: usize) -> &'static str {
+    if index + 1 < len { "," } else { "" }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use micropb::{MessageDecode, PbEncoder};
+    use std::{string::String as StdString, vec::Vec as StdVec};
+
+    fn encode(message: &impl MessageEncode) -> StdVec<u8> {
+        let mut buffer = StdVec::new();
+        message.encode(&mut PbEncoder::new(&mut buffer)).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn check_schema_json_is_current() {
+        let mut manifest = StdString::new();
+        write_manifest(&mut manifest).unwrap();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/schema.json");
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(path, &manifest).unwrap();
+        }
+        let checked_in = std::fs::read_to_string(path).unwrap_or_default();
+        assert_eq!(checked_in, manifest, "schema.json is out of date, run the tests with UPDATE_SCHEMA=1");
+    }
+
+    #[test]
+    fn check_decode_without_version() {
+        // an upload of the firmware before versioning
+        let old = encode(&Upload {
+            start_timestamp: 1_764_505_800,
+            ..Default::default()
+        });
+        let mut upload = Upload::default();
+        upload.decode_from_bytes(&old).unwrap();
+        assert_eq!(upload.schema_version, 0);
+        assert_eq!(upload.start_timestamp, 1_764_505_800);
+    }
+
+    #[test]
+    fn check_decode_ignores_unknown_fields() {
+        let mut newer = encode(&SystemEvent {
+            timestamp: 1_764_505_800,
+            schema_version: SCHEMA_VERSION + 1,
+            ..Default::default()
+        });
+        // a varint field 100 and a length delimited field 101 of a later schema
+        newer.extend_from_slice(&[0xa0, 0x06, 0x2a, 0xaa, 0x06, 0x03, b'n', b'e', b'w']);
+        let mut event = SystemEvent::default();
+        event.decode_from_bytes(&newer).unwrap();
+        assert_eq!(event.timestamp, 1_764_505_800);
+        assert_eq!(event.schema_version, SCHEMA_VERSION + 1);
+        assert!(event.event.is_none());
+    }
+
+    #[test]
+    fn check_limits_match_the_firmware() {
+        let max_size = |name: &str| MAX_SIZES.iter().find(|(message, _)| *message == name).and_then(|(_, size)| *size);
+        assert_eq!(max_size(".bt.solar.CommandAuditEvent"), Some(crate::audit::AUDIT_ENTRY_SIZE));
+        assert_eq!(max_size(".bt.solar.CrashEvent"), Some(crate::crash::CRASH_REPORT_SIZE));
+        assert!(MAX_BYTES.contains(&(".bt.solar.LinkQualityEvent.operator", crate::at::network::OPERATOR_SIZE)));
+        assert!(CONFIG_KEYS.contains(&"SOLAR_BACKEND_BASE_URL"));
+    }
+}
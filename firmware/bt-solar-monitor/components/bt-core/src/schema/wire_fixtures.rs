@@ -0,0 +1,368 @@
+//! Example payloads of the firmware encoder for the backend decoders.
+//!
+//! The test writes every example to `target/wire-fixtures/<name>.bin` with an annotated
+//! `<name>.txt` next to it: offset, field, wire type and value of every field, the nested
+//! messages indented. The field names are taken from the proto file, a field the encoder
+//! writes but the proto file does not know fails the test.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::PathBuf,
+    string::{String as StdString, ToString},
+    vec::Vec as StdVec,
+};
+
+use micropb::{MessageDecode, MessageEncode, PbEncoder};
+
+use crate::{
+    proto::bt_::solar_::{
+        BatteryMonitorReading, ChargerErrorEvent, CommandAuditEvent, CrashEvent, HealthEvent, HistoryDayEvent, LinkQualityEvent, OfflineEvent, OnlineEvent,
+        PositionEvent, Reading, Spread, StartupEvent, SystemEvent, SystemEvent_::Event, Upload, UploadEntry,
+    },
+    schema::SCHEMA_VERSION,
+};
+
+const PROTO: &str = include_str!("../../proto/readings.proto");
+const TIMESTAMP: i64 = 1_764_505_800;
+
+/// Field number => (name, type) per message name.
+type Schema = HashMap<StdString, HashMap<u64, (StdString, StdString)>>;
+
+fn parse_schema(proto: &str) -> Schema {
+    let mut schema = Schema::new();
+    let mut message = None;
+    for line in proto.lines() {
+        let line = line.split("//").next().unwrap().trim();
+        let tokens: StdVec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["message", name, ..] => message = Some(name.to_string()),
+            [.., typ, name, "=", number] if message.is_some() => {
+                let number = number.trim_end_matches(';').parse().unwrap();
+                let fields = schema.entry(message.clone().unwrap()).or_default();
+                fields.insert(number, (name.to_string(), typ.to_string()));
+            }
+            _ => {}
+        }
+    }
+    schema
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = data[*offset];
+        *offset += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+    panic!("varint longer than 10 bytes at {:#06x}", *offset);
+}
+
+fn hex(data: &[u8]) -> StdString {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect::<StdVec<_>>().join(" ")
+}
+
+/// Annotates the fields of `data`, a `message` at `base` of the payload.
+fn annotate(schema: &Schema, message: &str, data: &[u8], base: usize, depth: usize, out: &mut StdString) {
+    let fields = &schema[message];
+    let indent = "  ".repeat(depth);
+    let mut offset = 0;
+    while offset < data.len() {
+        let start = offset;
+        let tag = read_varint(data, &mut offset);
+        let (number, wire_type) = (tag >> 3, tag & 0x7);
+        let (name, typ) = fields
+            .get(&number)
+            .unwrap_or_else(|| panic!("field {} of {} at {:#06x} not in the proto file", number, message, base + start));
+        let value = match wire_type {
+            0 => {
+                let raw = read_varint(data, &mut offset);
+                match typ.as_str() {
+                    "int32" | "int64" => (raw as i64).to_string(),
+                    "bool" => (raw != 0).to_string(),
+                    _ => raw.to_string(),
+                }
+            }
+            1 => {
+                let bytes: [u8; 8] = data[offset..offset + 8].try_into().unwrap();
+                offset += 8;
+                f64::from_le_bytes(bytes).to_string()
+            }
+            5 => {
+                let bytes: [u8; 4] = data[offset..offset + 4].try_into().unwrap();
+                offset += 4;
+                f32::from_le_bytes(bytes).to_string()
+            }
+            2 => {
+                let len = read_varint(data, &mut offset) as usize;
+                let body = &data[offset..offset + len];
+                let header = &data[start..offset];
+                offset += len;
+                if schema.contains_key(typ.as_str()) {
+                    writeln!(out, "{:04x}  {}{}: {} ({} bytes)  [{}]", base + start, indent, name, typ, len, hex(header)).unwrap();
+                    annotate(schema, typ, body, base + start + header.len(), depth + 1, out);
+                    continue;
+                }
+                format!("{:?}", core::str::from_utf8(body).unwrap())
+            }
+            _ => panic!("wire type {} of {}.{} at {:#06x}", wire_type, message, name, base + start),
+        };
+        writeln!(out, "{:04x}  {}{} = {}  [field {}, wire type {}: {}]", base + start, indent, name, value, number, wire_type, hex(&data[start..offset]))
+            .unwrap();
+    }
+}
+
+fn encode(message: &impl MessageEncode) -> StdVec<u8> {
+    let mut buffer = StdVec::new();
+    message.encode(&mut PbEncoder::new(&mut buffer)).unwrap();
+    buffer
+}
+
+fn event(event: Event) -> SystemEvent {
+    SystemEvent {
+        timestamp: TIMESTAMP,
+        schema_version: SCHEMA_VERSION,
+        event: Some(event),
+    }
+}
+
+fn examples() -> StdVec<(&'static str, &'static str, StdVec<u8>)> {
+    let reading = Reading {
+        battery_voltage: 12_850,
+        battery_current: -1_200,
+        panel_voltage: 18_420,
+        panel_power: 45,
+        load_current: 800,
+        yield_today: 230,
+        yield_total: 154_000,
+        max_power_today: 61,
+        charger_state: 3,
+        mppt_mode: 2,
+        error_code: 0,
+        load_on: true,
+        ..Default::default()
+    }
+    .init_panel_power_deciwatt(452);
+    // an average of several readings with the spread of the panel power and the battery temperature
+    let averaged = reading
+        .clone()
+        .init_panel_power_spread(
+            Spread {
+                min: 12,
+                max: 61,
+                ..Default::default()
+            }
+            .init_stddev(14),
+        )
+        .init_battery_temperature(-550)
+        .init_auxiliary_1(24_310)
+        .init_pulse_count(42);
+    let upload = Upload {
+        start_timestamp: TIMESTAMP,
+        schema_version: SCHEMA_VERSION,
+        approximate_time: true,
+        entries: [
+            UploadEntry::default()
+                .init_offset_in_seconds(0)
+                .init_reading(reading.clone())
+                .init_battery_monitor(BatteryMonitorReading {
+                    voltage: 12_840,
+                    current: -1_150,
+                    state_of_charge: 815,
+                    time_to_go: -1,
+                    ..Default::default()
+                }),
+            UploadEntry::default()
+                .init_offset_in_seconds(300)
+                .init_reading(averaged)
+                .init_estimated_state_of_charge(640),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    let mut startup = StartupEvent {
+        uptime_seconds: 12,
+        rssi: -71,
+        reset_reason: 3,
+        modem_boot_ms: 5_210,
+        sim_ready_ms: 840,
+        registration_ms: 17_350,
+        time_sync_ms: 1_120,
+        first_upload_ms: 3_460,
+        ..Default::default()
+    };
+    startup.imei.push_str("864663060123456").unwrap();
+    startup.imsi.push_str("228012345678901").unwrap();
+    startup.iccid.push_str("89410123456789012345").unwrap();
+    startup.firmware_revision.push_str("A011B07A7670M7").unwrap();
+    let mut crash = CrashEvent {
+        uptime_seconds: 86_400,
+        pc: 0x0002_3f1c,
+        ..Default::default()
+    };
+    crash.message.push_str("panicked at upload.rs:42").unwrap();
+    let mut link_quality = LinkQualityEvent {
+        period_seconds: 3_600,
+        samples: 60,
+        rssi_min: -93,
+        rssi_avg: -81,
+        rssi_max: -71,
+        ber_avg: 0.5,
+        ber_max: 2,
+        registration_state: 1,
+        ..Default::default()
+    };
+    link_quality.operator.push_str("Swisscom").unwrap();
+    let mut audit = CommandAuditEvent {
+        sequence: 17,
+        timestamp: TIMESTAMP,
+        uptime_seconds: 3_600,
+        origin: 1,
+        success: true,
+        ..Default::default()
+    };
+    audit.command.push_str("load").unwrap();
+    audit.parameters.push_str("off").unwrap();
+    std::vec![
+        ("upload", "Upload", encode(&upload)),
+        ("startup_event", "SystemEvent", encode(&event(Event::StartupEvent(startup)))),
+        (
+            "online_event",
+            "SystemEvent",
+            encode(&event(Event::OnlineEvent(OnlineEvent {
+                uptime_seconds: 3_600,
+                rssi: -71
+            })))
+        ),
+        (
+            "offline_event",
+            "SystemEvent",
+            encode(&event(Event::OfflineEvent(OfflineEvent {
+                uptime_seconds: 3_700,
+                rssi: -75,
+                upload_overflows: 2,
+            }))),
+        ),
+        ("crash_event", "SystemEvent", encode(&event(Event::CrashEvent(crash)))),
+        (
+            "position_event",
+            "SystemEvent",
+            encode(&event(Event::PositionEvent(PositionEvent {
+                latitude: 47.3769,
+                longitude: 8.5417,
+                altitude: 408.5,
+                fix_timestamp: TIMESTAMP - 30,
+            }))),
+        ),
+        ("link_quality_event", "SystemEvent", encode(&event(Event::LinkQualityEvent(link_quality)))),
+        ("command_audit_event", "SystemEvent", encode(&event(Event::CommandAuditEvent(audit)))),
+        (
+            "health_event",
+            "SystemEvent",
+            encode(&event(Event::HealthEvent(HealthEvent {
+                uptime_seconds: 86_400,
+                stack_free_bytes: 3_072,
+                reset_reason: 3,
+                modem_restarts: 1,
+                at_errors: 4,
+                at_timeouts: 2,
+                checksum_errors: 7,
+                at_commands: 1_234,
+                at_urcs: 56,
+                at_bytes_rx: 98_765,
+                at_bytes_tx: 43_210,
+            }))),
+        ),
+        (
+            "charger_error_event",
+            "SystemEvent",
+            encode(&event(Event::ChargerErrorEvent(ChargerErrorEvent {
+                code: 17,
+                previous_code: 0,
+                uptime_seconds: 7_200,
+            }))),
+        ),
+        (
+            "history_day_event",
+            "SystemEvent",
+            encode(&event(Event::HistoryDayEvent(HistoryDayEvent {
+                day_sequence: 412,
+                days_ago: 1,
+                solar_yield: 1_230,
+                consumed: 120,
+                max_battery_voltage: 14_320,
+                min_battery_voltage: 12_180,
+                max_power: 312,
+                max_battery_current: 18_700,
+                max_panel_voltage: 43_210,
+                bulk_minutes: 95,
+                absorption_minutes: 60,
+                float_minutes: 240,
+                errors: [17].into_iter().collect(),
+            }))),
+        ),
+    ]
+}
+
+fn fixture_dir() -> PathBuf {
+    let target = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../target"), PathBuf::from);
+    target.join("wire-fixtures")
+}
+
+#[test]
+fn write_wire_fixtures() {
+    let schema = parse_schema(PROTO);
+    let dir = fixture_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, message, payload) in examples() {
+        let mut annotated = format!("{} ({}), {} bytes, schema version {}\n", name, message, payload.len(), SCHEMA_VERSION);
+        annotate(&schema, message, &payload, 0, 0, &mut annotated);
+        std::fs::write(dir.join(format!("{}.bin", name)), &payload).unwrap();
+        std::fs::write(dir.join(format!("{}.txt", name)), annotated).unwrap();
+    }
+}
+
+#[test]
+fn check_examples_round_trip() {
+    for (name, message, payload) in examples() {
+        let reencoded = match message {
+            "Upload" => {
+                let mut upload = Upload::default();
+                upload.decode_from_bytes(&payload).unwrap();
+                encode(&upload)
+            }
+            _ => {
+                let mut event = SystemEvent::default();
+                event.decode_from_bytes(&payload).unwrap();
+                encode(&event)
+            }
+        };
+        assert_eq!(reencoded, payload, "{} does not round trip", name);
+    }
+}
+
+#[test]
+fn check_encoder_quirks() {
+    let schema = parse_schema(PROTO);
+    assert_eq!(schema["SystemEvent"][&11], ("online_event".into(), "OnlineEvent".into()));
+    assert_eq!(schema["UploadEntry"][&4], ("estimated_state_of_charge".into(), "uint32".into()));
+
+    let (_, _, online) = examples().into_iter().find(|(name, _, _)| *name == "online_event").unwrap();
+    let mut annotated = StdString::new();
+    annotate(&schema, "SystemEvent", &online, 0, 0, &mut annotated);
+    // negative int32 are sign extended to 10 bytes, not zigzag encoded
+    assert!(annotated.contains("rssi = -71  [field 3, wire type 0: 18 b9 ff ff ff ff ff ff ff ff 01]"), "{}", annotated);
+    assert!(online.starts_with(&[0x08, 0xc8, 0xf1, 0xb0, 0xc9, 0x06, 0x10, SCHEMA_VERSION as u8, 0x5a]));
+
+    // the fields are written in the order of the proto file, not by field number
+    let (_, _, upload) = examples().into_iter().find(|(name, _, _)| *name == "upload").unwrap();
+    assert_eq!(upload[0], 0x30);
+    assert!(upload.ends_with(&[0x38, SCHEMA_VERSION as u8, 0x40, 0x01]));
+
+    // proto3 zero values are left out, only the `optional` fields are written when set, even to 0
+    let entry = UploadEntry::default().init_offset_in_seconds(0).init_estimated_state_of_charge(0);
+    assert_eq!(encode(&entry), [0x20, 0x00]);
+}
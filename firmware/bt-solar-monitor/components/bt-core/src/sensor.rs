@@ -1 +1,5 @@
+pub mod analog;
+pub mod filter;
+pub mod pulse;
+pub mod temperature;
 pub mod ve_direct;
@@ -0,0 +1,258 @@
+//! Auxiliary analog channels, e.g. a second PV string through a shunt amplifier.
+//!
+//! The board samples up to [`MAX_ANALOG_CHANNELS`] pins with its ADC (the SAADC on the
+//! nRF), an [`AnalogInput`] returns the pin voltages. The [`AnalogCalibration`] of the
+//! config store maps them to the measured value per channel, in V or A, e.g. the divider
+//! ratio or the shunt and amplifier gain. The [`Runner`] averages the samples over the
+//! averaging interval of the VE.Direct readings, the upload runner adds the average to the
+//! reading of the next entry, see `solar_monitor::sensors::SensorSource`.
+
+#![allow(async_fn_in_trait)]
+
+use embassy_sync::{
+    blocking_mutex::raw::NoopRawMutex,
+    channel::{Channel, Receiver, Sender},
+};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+use crate::{
+    storage::{ConfigKey, ConfigValue},
+    watchdog::WatchdogHandle,
+};
+
+pub const MAX_ANALOG_CHANNELS: usize = 2;
+
+/// Like the VE.Direct frames.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const ANALOG_CALIBRATION: ConfigKey<AnalogCalibration> = ConfigKey::new("sensor", "analog");
+
+/// The ADC of the board.
+pub trait AnalogInput {
+    /// Sampled channels, at most [`MAX_ANALOG_CHANNELS`].
+    fn channels(&self) -> usize;
+    /// Samples every channel, the pin voltages in V.
+    async fn sample(&mut self, volts: &mut [f32]);
+}
+
+/// `value = volts * gain + offset`, the default passes the pin voltage.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelCalibration {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl ChannelCalibration {
+    pub fn apply(&self, volts: f32) -> f32 {
+        volts * self.gain + self.offset
+    }
+}
+
+impl Default for ChannelCalibration {
+    fn default() -> Self {
+        Self { gain: 1.0, offset: 0.0 }
+    }
+}
+
+/// Per channel, the channels without one are not calibrated.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogCalibration {
+    channels: Vec<ChannelCalibration, MAX_ANALOG_CHANNELS>,
+}
+
+impl AnalogCalibration {
+    pub const fn new() -> Self {
+        Self { channels: Vec::new() }
+    }
+
+    pub fn channels(&self) -> &[ChannelCalibration] {
+        &self.channels
+    }
+
+    /// Calibrates the next channel, `None` if all are.
+    pub fn push(&mut self, calibration: ChannelCalibration) -> Option<()> {
+        self.channels.push(calibration).ok()
+    }
+
+    pub fn apply(&self, channel: usize, volts: f32) -> f32 {
+        self.channels.get(channel).copied().unwrap_or_default().apply(volts)
+    }
+}
+
+/// Per channel: the gain and the offset, little endian.
+impl ConfigValue for AnalogCalibration {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        for channel in &self.channels {
+            len += channel.gain.encode(buffer.get_mut(len..)?)?;
+            len += channel.offset.encode(buffer.get_mut(len..)?)?;
+        }
+        Some(len)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if !data.len().is_multiple_of(8) {
+            return None;
+        }
+        let mut calibration = AnalogCalibration::new();
+        for channel in data.chunks(8) {
+            calibration.push(ChannelCalibration {
+                gain: f32::decode(&channel[..4])?,
+                offset: f32::decode(&channel[4..])?,
+            })?;
+        }
+        Some(calibration)
+    }
+}
+
+/// The calibrated averages, one value per sampled channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogReading {
+    pub values: Vec<f32, MAX_ANALOG_CHANNELS>,
+}
+
+pub struct Runner<'a, A: AnalogInput, const N: usize> {
+    input: A,
+    calibration: AnalogCalibration,
+    average_interval: Duration,
+    tx: Sender<'a, NoopRawMutex, AnalogReading, N>,
+    watchdog: WatchdogHandle<'a>,
+}
+
+impl<'a, A: AnalogInput, const N: usize> Runner<'a, A, N> {
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    /// The calibration of [`ANALOG_CALIBRATION`], without one the pin voltages are sent.
+    pub fn with_calibration(mut self, calibration: AnalogCalibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            self.averaging_once().await;
+        }
+    }
+
+    pub async fn averaging_once(&mut self) {
+        let channels = self.input.channels().min(MAX_ANALOG_CHANNELS);
+        let end = Instant::now() + self.average_interval;
+        let mut sums = [0.0f32; MAX_ANALOG_CHANNELS];
+        let mut count = 0u32;
+        loop {
+            self.watchdog.feed();
+            let mut volts = [0.0f32; MAX_ANALOG_CHANNELS];
+            self.input.sample(&mut volts[..channels]).await;
+            for (sum, volts) in sums.iter_mut().zip(volts) {
+                *sum += volts;
+            }
+            count += 1;
+            if Instant::now() >= end {
+                break;
+            }
+            Timer::after(SAMPLE_INTERVAL.min(end - Instant::now())).await;
+        }
+        let values = sums[..channels]
+            .iter()
+            .enumerate()
+            .map(|(channel, sum)| self.calibration.apply(channel, sum / count as f32))
+            .collect();
+        let reading = AnalogReading { values };
+        debug!("Analog> Over {} => {:?}", count, reading);
+        self.tx.send(reading).await;
+    }
+}
+
+pub struct State<const N: usize> {
+    channel: Channel<NoopRawMutex, AnalogReading, N>,
+}
+
+impl<const N: usize> State<N> {
+    pub fn new() -> Self {
+        State { channel: Channel::new() }
+    }
+}
+
+impl<const N: usize> Default for State<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn new<'a, A: AnalogInput, const N: usize>(
+    state: &'a mut State<N>,
+    input: A,
+    average_interval: Duration,
+) -> (Runner<'a, A, N>, Receiver<'a, NoopRawMutex, AnalogReading, N>) {
+    (
+        Runner {
+            input,
+            calibration: AnalogCalibration::new(),
+            average_interval,
+            tx: state.channel.sender(),
+            watchdog: WatchdogHandle::default(),
+        },
+        state.channel.receiver(),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    /// Ramps every channel by 0.1 V per sample, the second channel twice the first one.
+    struct Ramp {
+        volts: f32,
+    }
+
+    impl AnalogInput for Ramp {
+        fn channels(&self) -> usize {
+            2
+        }
+
+        async fn sample(&mut self, volts: &mut [f32]) {
+            self.volts += 0.1;
+            volts[0] = self.volts;
+            volts[1] = 2.0 * self.volts;
+        }
+    }
+
+    #[test]
+    fn check_config_value() {
+        let mut calibration = AnalogCalibration::new();
+        calibration.push(ChannelCalibration { gain: 11.0, offset: 0.0 }).unwrap();
+        calibration.push(ChannelCalibration { gain: 20.0, offset: -1.25 }).unwrap();
+        assert!(calibration.push(ChannelCalibration::default()).is_none());
+        let mut buffer = [0u8; crate::storage::CONFIG_VALUE_SIZE];
+        let len = calibration.encode(&mut buffer).unwrap();
+        assert_eq!(len, 16);
+        assert_eq!(AnalogCalibration::decode(&buffer[..len]), Some(calibration));
+        assert_eq!(AnalogCalibration::decode(&buffer[..len - 1]), None);
+        assert_eq!(AnalogCalibration::decode(&[]), Some(AnalogCalibration::new()));
+    }
+
+    #[tokio::test]
+    async fn check_averaging() {
+        let mut state = State::<2>::new();
+        let (runner, rx) = new(&mut state, Ramp { volts: 0.0 }, Duration::from_millis(250));
+        let mut calibration = AnalogCalibration::new();
+        // a 1:10 divider on the first channel, the second one is not calibrated
+        calibration.push(ChannelCalibration { gain: 11.0, offset: 0.0 }).unwrap();
+        let mut runner = runner.with_calibration(calibration);
+        runner.averaging_once().await;
+        // samples at the start and the end of the interval
+        let reading = rx.try_receive().unwrap();
+        assert_eq!(reading.values.len(), 2);
+        assert_relative_eq!(reading.values[0], 0.15 * 11.0, epsilon = 1e-5);
+        assert_relative_eq!(reading.values[1], 0.3, epsilon = 1e-5);
+    }
+}
@@ -0,0 +1,154 @@
+use heapless::Vec;
+
+use crate::sensor::ve_direct::Reading;
+
+/// Filters configured at most, each field can have several filters.
+pub const MAX_FILTERS: usize = 8;
+
+/// The averaged fields of a [`Reading`], counters and states are not filtered.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Field {
+    BatteryVoltage,
+    BatteryCurrent,
+    PanelVoltage,
+    PanelPower,
+    LoadCurrent,
+}
+
+impl Field {
+    fn value_mut(self, reading: &mut Reading) -> &mut f32 {
+        match self {
+            Field::BatteryVoltage => &mut reading.battery_voltage,
+            Field::BatteryCurrent => &mut reading.battery_current,
+            Field::PanelVoltage => &mut reading.panel_voltage,
+            Field::PanelPower => &mut reading.panel_power,
+            Field::LoadCurrent => &mut reading.load_current,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    /// Median of the last three values, rejects single sample spikes.
+    Median3,
+    /// Exponential smoothing, `alpha` (0..=1] is the weight of the new value.
+    Exponential { alpha: f32 },
+}
+
+#[derive(Debug)]
+struct Stage {
+    field: Field,
+    filter: Filter,
+    history: [f32; 2],
+    count: u8,
+}
+
+impl Stage {
+    fn apply(&mut self, value: f32) -> f32 {
+        let filtered = match self.filter {
+            Filter::Median3 if self.count >= 2 => median3(self.history[0], self.history[1], value),
+            Filter::Median3 => value,
+            Filter::Exponential { alpha } if self.count >= 1 => alpha * value + (1.0 - alpha) * self.history[1],
+            Filter::Exponential { .. } => value,
+        };
+        // the median looks at the raw values, the smoothing at its last output
+        let kept = match self.filter {
+            Filter::Median3 => value,
+            Filter::Exponential { .. } => filtered,
+        };
+        self.history = [self.history[1], kept];
+        self.count = self.count.saturating_add(1);
+        filtered
+    }
+}
+
+fn median3(a: f32, b: f32, c: f32) -> f32 {
+    a.max(b).min(a.min(b).max(c))
+}
+
+/// A chain of per field filters applied to every reading before the averaging.
+///
+/// The filters of a field run in the order they were added, e.g. a [`Filter::Median3`]
+/// before a [`Filter::Exponential`] keeps the spikes out of the smoothed value.
+#[derive(Debug, Default)]
+pub struct ReadingFilter {
+    stages: Vec<Stage, MAX_FILTERS>,
+}
+
+impl ReadingFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `filter` for `field`, filters beyond [`MAX_FILTERS`] are ignored.
+    pub fn with(mut self, field: Field, filter: Filter) -> Self {
+        if self
+            .stages
+            .push(Stage {
+                field,
+                filter,
+                history: [0.0; 2],
+                count: 0,
+            })
+            .is_err()
+        {
+            warn!("Too many reading filters => {:?} on {:?} ignored", filter, field);
+        }
+        self
+    }
+
+    pub fn apply(&mut self, reading: &mut Reading) {
+        for stage in self.stages.iter_mut() {
+            let value = stage.field.value_mut(reading);
+            *value = stage.apply(*value);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    fn filter_current(filter: &mut ReadingFilter, values: &[f32]) -> std::vec::Vec<f32> {
+        values
+            .iter()
+            .map(|value| {
+                let mut reading = Reading {
+                    battery_current: *value,
+                    battery_voltage: 12.5,
+                    ..Default::default()
+                };
+                filter.apply(&mut reading);
+                assert_relative_eq!(reading.battery_voltage, 12.5);
+                reading.battery_current
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_median_rejects_spikes() {
+        let mut filter = ReadingFilter::new().with(Field::BatteryCurrent, Filter::Median3);
+        let filtered = filter_current(&mut filter, &[1.0, 1.2, 9.0, 1.1, 1.3, -7.0, 1.2]);
+        assert_eq!(filtered, [1.0, 1.2, 1.2, 1.2, 1.3, 1.1, 1.2]);
+    }
+
+    #[test]
+    fn check_exponential_smoothing() {
+        let mut filter = ReadingFilter::new().with(Field::BatteryCurrent, Filter::Exponential { alpha: 0.5 });
+        let filtered = filter_current(&mut filter, &[2.0, 4.0, 4.0, 0.0]);
+        assert_eq!(filtered, [2.0, 3.0, 3.5, 1.75]);
+    }
+
+    #[test]
+    fn check_chain() {
+        let mut filter = ReadingFilter::new()
+            .with(Field::BatteryCurrent, Filter::Median3)
+            .with(Field::BatteryCurrent, Filter::Exponential { alpha: 0.5 });
+        let filtered = filter_current(&mut filter, &[2.0, 2.0, 20.0, 2.0]);
+        assert_eq!(filtered, [2.0, 2.0, 2.0, 2.0]);
+    }
+}
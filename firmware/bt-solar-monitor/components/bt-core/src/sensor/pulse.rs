@@ -0,0 +1,167 @@
+//! Pulse counter, e.g. the run signal of a water pump or the reed contact of a flow meter.
+//!
+//! The board waits for the edges of the pin (GPIOTE on the nRF), a [`PulseInput`] returns
+//! on every pulse. The [`Runner`] counts them over the averaging interval of the VE.Direct
+//! readings and sends the count, also a zero one, the upload runner adds it to the reading
+//! of the next entry, see `solar_monitor::sensors::SensorSource`.
+
+#![allow(async_fn_in_trait)]
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::raw::NoopRawMutex,
+    channel::{Channel, Receiver, Sender},
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::watchdog::{FEED_INTERVAL, WatchdogHandle};
+
+/// Edges within this time after a pulse are contact bounce, a reed contact settles within a few ms.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(10);
+
+/// The edge detection of the board.
+pub trait PulseInput {
+    /// Returns on the next pulse.
+    async fn wait_pulse(&mut self);
+}
+
+pub struct Runner<'a, P: PulseInput, const N: usize> {
+    input: P,
+    debounce: Duration,
+    average_interval: Duration,
+    tx: Sender<'a, NoopRawMutex, u32, N>,
+    watchdog: WatchdogHandle<'a>,
+}
+
+impl<'a, P: PulseInput, const N: usize> Runner<'a, P, N> {
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    /// Instead of [`DEFAULT_DEBOUNCE`], the shortest time between two pulses.
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            self.counting_once().await;
+        }
+    }
+
+    pub async fn counting_once(&mut self) {
+        let end = Instant::now() + self.average_interval;
+        let mut count = 0u32;
+        loop {
+            self.watchdog.feed();
+            let now = Instant::now();
+            if now >= end {
+                break;
+            }
+            match select(self.input.wait_pulse(), Timer::after(FEED_INTERVAL.min(end - now))).await {
+                Either::First(()) => {
+                    count = count.saturating_add(1);
+                    Timer::after(self.debounce).await;
+                }
+                Either::Second(()) => {}
+            }
+        }
+        debug!("Pulses> {} during {}", count, crate::fmt::FormatableDuration(self.average_interval));
+        self.tx.send(count).await;
+    }
+}
+
+pub struct State<const N: usize> {
+    channel: Channel<NoopRawMutex, u32, N>,
+}
+
+impl<const N: usize> State<N> {
+    pub fn new() -> Self {
+        State { channel: Channel::new() }
+    }
+}
+
+impl<const N: usize> Default for State<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn new<'a, P: PulseInput, const N: usize>(
+    state: &'a mut State<N>,
+    input: P,
+    average_interval: Duration,
+) -> (Runner<'a, P, N>, Receiver<'a, NoopRawMutex, u32, N>) {
+    (
+        Runner {
+            input,
+            debounce: DEFAULT_DEBOUNCE,
+            average_interval,
+            tx: state.channel.sender(),
+            watchdog: WatchdogHandle::default(),
+        },
+        state.channel.receiver(),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A pulse every `period` from `start` on, each one with an edge of contact bounce 5 ms later.
+    struct Bouncing {
+        start: Instant,
+        period: Duration,
+    }
+
+    impl PulseInput for Bouncing {
+        async fn wait_pulse(&mut self) {
+            // the edges before the wait are missed, like with the edge detection of the board
+            let elapsed = (Instant::now() - self.start).as_millis();
+            let period = self.period.as_millis();
+            let pulse = elapsed / period * period;
+            let bounce = if pulse > 0 { pulse + 5 } else { period };
+            let next = [bounce, pulse + period].into_iter().find(|edge| *edge > elapsed).unwrap();
+            Timer::at(self.start + Duration::from_millis(next)).await;
+        }
+    }
+
+    /// No pulses at all, the pump is off.
+    struct Idle;
+
+    impl PulseInput for Idle {
+        async fn wait_pulse(&mut self) {
+            core::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn check_counting() {
+        let mut state = State::<2>::new();
+        let input = Bouncing {
+            start: Instant::now(),
+            period: Duration::from_millis(40),
+        };
+        let (mut runner, rx) = new(&mut state, input, Duration::from_millis(190));
+        runner.counting_once().await;
+        // the bounces are not counted, pulses at 40, 80, 120 and 160 ms
+        assert_eq!(rx.try_receive(), Ok(4));
+
+        // without debouncing every edge counts
+        runner.input.start = Instant::now();
+        runner = runner.with_debounce(Duration::from_ticks(0));
+        runner.counting_once().await;
+        let count = rx.try_receive().unwrap();
+        assert!(count >= 7, "{}", count);
+    }
+
+    #[tokio::test]
+    async fn check_zero_count() {
+        let mut state = State::<2>::new();
+        let (mut runner, rx) = new(&mut state, Idle, Duration::from_millis(20));
+        runner.counting_once().await;
+        assert_eq!(rx.try_receive(), Ok(0));
+    }
+}
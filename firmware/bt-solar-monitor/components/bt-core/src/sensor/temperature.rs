@@ -0,0 +1,272 @@
+//! Battery compartment temperature from an I2C sensor, e.g. a TMP117 or an SHT4x.
+//!
+//! A cold battery takes less charge and sags under load, the charger readings alone do not
+//! tell why. The [`Runner`] samples the sensor every [`SAMPLE_INTERVAL`] and sends the
+//! average over the averaging interval in °C, the upload runner adds it to the reading of
+//! the next entry, see `solar_monitor::sensors::SensorSource`.
+
+use embassy_sync::{
+    blocking_mutex::raw::NoopRawMutex,
+    channel::{Channel, Receiver, Sender},
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+
+use crate::watchdog::{FEED_INTERVAL, WatchdogHandle};
+
+/// Within the feed interval, a temperature changes slower anyway.
+pub const SAMPLE_INTERVAL: Duration = FEED_INTERVAL;
+
+/// Result register of the TMP117, 7.8125 m°C per LSB.
+const TMP117_TEMPERATURE: u8 = 0x00;
+/// Measurement of the SHT4x with high repeatability, temperature and humidity with a CRC each.
+const SHT4X_MEASURE_HIGH: u8 = 0xfd;
+const SHT4X_MEASURE_DURATION: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TemperatureChip {
+    /// Continuous conversion after power on, the result register is read.
+    Tmp117,
+    /// Every sample triggers a measurement.
+    Sht4x,
+}
+
+impl TemperatureChip {
+    /// The address with the address pins at their default.
+    pub fn default_address(&self) -> u8 {
+        match self {
+            TemperatureChip::Tmp117 => 0x48,
+            TemperatureChip::Sht4x => 0x44,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TemperatureError {
+    /// The transfer failed, e.g. no sensor at the address.
+    Bus,
+    /// The CRC of the measurement did not match.
+    Checksum,
+}
+
+pub struct TemperatureSensor<I: I2c> {
+    i2c: I,
+    chip: TemperatureChip,
+    address: u8,
+}
+
+impl<I: I2c> TemperatureSensor<I> {
+    pub fn new(i2c: I, chip: TemperatureChip) -> Self {
+        Self {
+            i2c,
+            chip,
+            address: chip.default_address(),
+        }
+    }
+
+    pub fn with_address(mut self, address: u8) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// The temperature in °C.
+    pub async fn read(&mut self) -> Result<f32, TemperatureError> {
+        match self.chip {
+            TemperatureChip::Tmp117 => {
+                let mut raw = [0u8; 2];
+                self.i2c
+                    .write_read(self.address, &[TMP117_TEMPERATURE], &mut raw)
+                    .await
+                    .map_err(|_| TemperatureError::Bus)?;
+                Ok(i16::from_be_bytes(raw) as f32 * 0.0078125)
+            }
+            TemperatureChip::Sht4x => {
+                self.i2c.write(self.address, &[SHT4X_MEASURE_HIGH]).await.map_err(|_| TemperatureError::Bus)?;
+                Timer::after(SHT4X_MEASURE_DURATION).await;
+                let mut raw = [0u8; 6];
+                self.i2c.read(self.address, &mut raw).await.map_err(|_| TemperatureError::Bus)?;
+                if sht4x_crc(&raw[..2]) != raw[2] {
+                    return Err(TemperatureError::Checksum);
+                }
+                Ok(-45.0 + 175.0 * u16::from_be_bytes([raw[0], raw[1]]) as f32 / 65535.0)
+            }
+        }
+    }
+}
+
+/// CRC-8 of the SHT4x, polynomial 0x31 and 0xff initial.
+fn sht4x_crc(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+pub struct Runner<'a, I: I2c, const N: usize> {
+    sensor: TemperatureSensor<I>,
+    average_interval: Duration,
+    tx: Sender<'a, NoopRawMutex, f32, N>,
+    watchdog: WatchdogHandle<'a>,
+}
+
+impl<'a, I: I2c, const N: usize> Runner<'a, I, N> {
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            self.averaging_once().await;
+        }
+    }
+
+    pub async fn averaging_once(&mut self) {
+        let end = Instant::now() + self.average_interval;
+        let (mut sum, mut count) = (0.0f32, 0u32);
+        loop {
+            self.watchdog.feed();
+            match self.sensor.read().await {
+                Ok(temperature) => {
+                    sum += temperature;
+                    count += 1;
+                }
+                Err(e) => debug!("Temperature> sample failed: {:?}", e),
+            }
+            if Instant::now() >= end {
+                break;
+            }
+            Timer::after(SAMPLE_INTERVAL.min(end - Instant::now())).await;
+        }
+        match count {
+            0 => warn!("Temperature> No samples during interval {}", crate::fmt::FormatableDuration(self.average_interval)),
+            _ => {
+                let average = sum / count as f32;
+                debug!("Temperature> Over {} => {}", count, average);
+                self.tx.send(average).await;
+            }
+        }
+    }
+}
+
+pub struct State<const N: usize> {
+    channel: Channel<NoopRawMutex, f32, N>,
+}
+
+impl<const N: usize> State<N> {
+    pub fn new() -> Self {
+        State { channel: Channel::new() }
+    }
+}
+
+impl<const N: usize> Default for State<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn new<'a, I: I2c, const N: usize>(
+    state: &'a mut State<N>,
+    sensor: TemperatureSensor<I>,
+    average_interval: Duration,
+) -> (Runner<'a, I, N>, Receiver<'a, NoopRawMutex, f32, N>) {
+    (
+        Runner {
+            sensor,
+            average_interval,
+            tx: state.channel.sender(),
+            watchdog: WatchdogHandle::default(),
+        },
+        state.channel.receiver(),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use approx::assert_relative_eq;
+    use embedded_hal_async::i2c::{ErrorKind, ErrorType, Operation};
+    use std::vec::Vec as StdVec;
+
+    use super::*;
+
+    /// Answers every read with `response`, records the written bytes.
+    struct FakeI2c {
+        address: u8,
+        response: StdVec<u8>,
+        written: StdVec<u8>,
+    }
+
+    impl ErrorType for FakeI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for FakeI2c {
+        async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            if address != self.address {
+                return Err(ErrorKind::NoAcknowledge(embedded_hal_async::i2c::NoAcknowledgeSource::Address));
+            }
+            for operation in operations {
+                match operation {
+                    Operation::Write(data) => self.written.extend_from_slice(data),
+                    Operation::Read(buffer) => buffer.copy_from_slice(&self.response[..buffer.len()]),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn fake(address: u8, response: &[u8]) -> FakeI2c {
+        FakeI2c {
+            address,
+            response: response.to_vec(),
+            written: StdVec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_tmp117() {
+        // 0x0c80 => 25 °C, 0xff80 => -1 °C
+        let mut sensor = TemperatureSensor::new(fake(0x48, &[0x0c, 0x80]), TemperatureChip::Tmp117);
+        assert_relative_eq!(sensor.read().await.unwrap(), 25.0);
+        assert_eq!(sensor.i2c.written, [TMP117_TEMPERATURE]);
+        sensor.i2c.response = std::vec![0xff, 0x80];
+        assert_relative_eq!(sensor.read().await.unwrap(), -1.0);
+
+        let mut moved = TemperatureSensor::new(fake(0x49, &[0x0c, 0x80]), TemperatureChip::Tmp117);
+        assert_eq!(moved.read().await, Err(TemperatureError::Bus));
+        moved = moved.with_address(0x49);
+        assert!(moved.read().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn check_sht4x() {
+        // the CRC example of the datasheet, 0xbeef => 0x92
+        assert_eq!(sht4x_crc(&[0xbe, 0xef]), 0x92);
+        let raw = 0x6666u16.to_be_bytes();
+        let mut sensor = TemperatureSensor::new(fake(0x44, &[raw[0], raw[1], sht4x_crc(&raw), 0, 0, 0]), TemperatureChip::Sht4x);
+        assert_relative_eq!(sensor.read().await.unwrap(), 25.0, epsilon = 0.01);
+        assert_eq!(sensor.i2c.written, [SHT4X_MEASURE_HIGH]);
+        sensor.i2c.response[2] ^= 1;
+        assert_eq!(sensor.read().await, Err(TemperatureError::Checksum));
+    }
+
+    #[tokio::test]
+    async fn check_averaging() {
+        let mut state = State::<2>::new();
+        let sensor = TemperatureSensor::new(fake(0x48, &[0x0c, 0x80]), TemperatureChip::Tmp117);
+        let (mut runner, rx) = new(&mut state, sensor, Duration::from_millis(50));
+        runner.averaging_once().await;
+        assert_relative_eq!(rx.try_receive().unwrap(), 25.0);
+
+        // no sensor, nothing to send
+        runner.sensor = runner.sensor.with_address(0x10);
+        runner.averaging_once().await;
+        assert!(rx.try_receive().is_err());
+    }
+}
@@ -1,51 +1,199 @@
+use embassy_futures::select::{Either, select};
 use embassy_sync::{
     blocking_mutex::raw::NoopRawMutex,
     channel::{Channel, Receiver, Sender},
+    watch::DynSender,
 };
-use embassy_time::{Instant, Timer};
+use embassy_time::{Instant, Timer, with_timeout};
 use embedded_hal::digital::OutputPin;
 use embedded_io_async::{Read, Write};
 use heapless::{LinearMap, String};
 
+use crate::{
+    activity::UartBusy,
+    audit::Audit,
+    sensor::{
+        filter::ReadingFilter,
+        ve_direct::{
+            charger_error::{ChargerErrors, ErrorDebounce},
+            hex::{HEX_FRAME_SIZE, HEX_RESPONSE_TIMEOUT, HexError, HexMessage, LOAD_OUTPUT_CONTROL, LoadSwitch, LoadSwitchRequest},
+            history::{DailyHistory, HistoryReader},
+        },
+    },
+    watchdog::{FEED_INTERVAL, WatchdogHandle},
+};
+
+pub mod battery_monitor;
+pub mod charger_error;
+pub mod hex;
+pub mod history;
+
+/// Sum of a value in milli units, an `f32` sum drops the last digits once it is large,
+/// e.g. a day of readings at 1 Hz.
+#[derive(Default, Debug, Copy, Clone)]
+struct MilliSum(i64);
+
+impl MilliSum {
+    /// Adds `value` rounded to milli units, the sum saturates.
+    fn add(&mut self, value: f32) {
+        let milli = value * 1000.0;
+        // `as` truncates towards zero and saturates, NaN becomes 0
+        let milli = if milli < 0.0 { (milli - 0.5) as i64 } else { (milli + 0.5) as i64 };
+        self.0 = self.0.saturating_add(milli);
+    }
+
+    /// The average over `count` values, rounded half away from zero to milli units.
+    fn average(&self, count: u32) -> f32 {
+        let count = count.max(1) as i64;
+        let half = if self.0 < 0 { -count / 2 } else { count / 2 };
+        (self.0.saturating_add(half) / count) as f32 / 1000.0
+    }
+}
+
+/// Sum, min, max and optionally the variance of an averaged channel.
+#[derive(Default, Debug, Copy, Clone)]
+struct ChannelStats {
+    sum: MilliSum,
+    min: f32,
+    max: f32,
+    /// Running mean and sum of the squared deviations (Welford), only with the variance.
+    mean: f64,
+    squares: f64,
+}
+
+impl ChannelStats {
+    /// Adds the `count`th value of the period.
+    fn add(&mut self, value: f32, count: u32, variance: bool) {
+        self.sum.add(value);
+        if count == 1 {
+            (self.min, self.max) = (value, value);
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        if variance {
+            let delta = value as f64 - self.mean;
+            self.mean += delta / count as f64;
+            self.squares += delta * (value as f64 - self.mean);
+        }
+    }
+
+    fn spread(&self, count: u32, variance: bool) -> Spread {
+        // the integer square root of the variance in milli units, core has no float one
+        let stddev = variance
+            .then(|| ((self.squares / count as f64) * 1_000_000.0) as u64)
+            .map(|variance| variance.isqrt() as f32 / 1000.0);
+        Spread {
+            min: self.min,
+            max: self.max,
+            stddev,
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Averaging {
-    sum: Reading,
+    battery_voltage: ChannelStats,
+    battery_current: ChannelStats,
+    panel_voltage: ChannelStats,
+    panel_power: ChannelStats,
+    load_current: ChannelStats,
+    /// The counters and states of the latest reading.
+    latest: Reading,
     count: u32,
+    variance: bool,
 }
 
 impl Averaging {
+    /// Averages with the standard deviation of the channels in the spread.
+    pub fn with_variance() -> Self {
+        Self {
+            variance: true,
+            ..Default::default()
+        }
+    }
+
     pub fn add_reading(&mut self, reading: &Reading) {
-        self.sum.battery_voltage += reading.battery_voltage;
-        self.sum.battery_current += reading.battery_current;
-        self.sum.panel_voltage += reading.panel_voltage;
-        self.sum.panel_power += reading.panel_power;
-        self.sum.load_current += reading.load_current;
-        self.count += 1;
+        self.count = self.count.saturating_add(1);
+        let (count, variance) = (self.count, self.variance);
+        self.battery_voltage.add(reading.battery_voltage, count, variance);
+        self.battery_current.add(reading.battery_current, count, variance);
+        self.panel_voltage.add(reading.panel_voltage, count, variance);
+        self.panel_power.add(reading.panel_power, count, variance);
+        self.load_current.add(reading.load_current, count, variance);
+        // counters and states are not averaged, the latest value wins
+        self.latest.yield_today = reading.yield_today;
+        self.latest.yield_total = reading.yield_total;
+        self.latest.max_power_today = reading.max_power_today;
+        self.latest.charger_state = reading.charger_state;
+        self.latest.mppt_mode = reading.mppt_mode;
+        self.latest.error_code = reading.error_code;
+        self.latest.load_on = reading.load_on;
     }
 
+    /// The average and the number of readings, the spread only for more than one reading.
     pub fn average(&mut self) -> Option<(Reading, u32)> {
         if self.count == 0 {
-            None
-        } else {
-            let count = self.count;
-            let reading = Some((
-                Reading {
-                    battery_voltage: self.sum.battery_voltage / count as f32,
-                    battery_current: self.sum.battery_current / count as f32,
-                    panel_voltage: self.sum.panel_voltage / count as f32,
-                    panel_power: self.sum.panel_power / count as f32,
-                    load_current: self.sum.load_current / count as f32,
-                },
-                count,
-            ));
-            self.sum = Reading::default();
-            self.count = 0;
-            reading
+            return None;
         }
+        let (count, variance) = (self.count, self.variance);
+        let spread = (count > 1).then(|| ReadingSpread {
+            battery_voltage: self.battery_voltage.spread(count, variance),
+            battery_current: self.battery_current.spread(count, variance),
+            panel_voltage: self.panel_voltage.spread(count, variance),
+            panel_power: self.panel_power.spread(count, variance),
+            load_current: self.load_current.spread(count, variance),
+        });
+        let reading = Reading {
+            battery_voltage: self.battery_voltage.sum.average(count),
+            battery_current: self.battery_current.sum.average(count),
+            panel_voltage: self.panel_voltage.sum.average(count),
+            panel_power: self.panel_power.sum.average(count),
+            load_current: self.load_current.sum.average(count),
+            spread,
+            ..core::mem::take(&mut self.latest)
+        };
+        self.reset();
+        Some((reading, count))
+    }
+
+    /// Starts a new period.
+    pub fn reset(&mut self) {
+        *self = Self {
+            variance: self.variance,
+            ..Default::default()
+        };
     }
 }
 
-#[derive(Default, Debug)]
+/// Min, max and standard deviation of a channel over the averaging period.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Spread {
+    pub min: f32,
+    pub max: f32,
+    /// Only with [`Averaging::with_variance`].
+    pub stddev: Option<f32>,
+}
+
+/// The [`Spread`] of the averaged channels of a [`Reading`].
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadingSpread {
+    pub battery_voltage: Spread,
+    pub battery_current: Spread,
+    pub panel_voltage: Spread,
+    pub panel_power: Spread,
+    pub load_current: Spread,
+}
+
+/// The values of a charger in base units, the VE.Direct label and unit per field.
+///
+/// The charger sends at most 16 bit values in mV, mA, W and 0.01 kWh, an `f32` keeps them
+/// exact, the yields up to 167,772 kWh. The currents are negative when the battery or the
+/// load feed back. The proto fields in mV, mA and Wh hold up to ±2,147,483 V, A and kWh,
+/// larger values saturate, see `solar_monitor::scaling`.
+#[derive(Default, Debug, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Reading {
     pub battery_voltage: f32, // V
@@ -53,6 +201,15 @@ pub struct Reading {
     pub panel_voltage: f32,   // VPV
     pub panel_power: f32,     // PPV
     pub load_current: f32,    // IL
+    pub yield_today: f32,     // H20  kWh
+    pub yield_total: f32,     // H19  kWh
+    pub max_power_today: f32, // H21  W
+    pub charger_state: u32,   // CS
+    pub mppt_mode: u32,       // MPPT
+    pub error_code: u32,      // ERR
+    pub load_on: bool,        // LOAD
+    /// Over the averaging period, `None` for a single reading.
+    pub spread: Option<ReadingSpread>,
 }
 
 pub struct Runner<'a, Stream: Read + Write, Output: OutputPin, const N: usize> {
@@ -61,9 +218,91 @@ pub struct Runner<'a, Stream: Read + Write, Output: OutputPin, const N: usize> {
     average_interval: embassy_time::Duration,
     rx: Sender<'a, NoopRawMutex, Reading, N>,
     indicator_pin: Output,
+    watchdog: WatchdogHandle<'a>,
+    filter: ReadingFilter,
+    load_switch: Option<&'a LoadSwitch>,
+    /// The request waiting for the charger's response and its deadline.
+    pending_load_switch: Option<(LoadSwitchRequest, Instant)>,
+    audit: Option<&'a Audit>,
+    charger_errors: Option<&'a ChargerErrors>,
+    error_debounce: ErrorDebounce,
+    history: Option<&'a DailyHistory>,
+    history_reader: HistoryReader,
+    live: Option<LiveReadings<'a>>,
+}
+
+/// Publishes the frames before the averaging at a cadence of its own, see [`Runner::with_live_readings`].
+struct LiveReadings<'a> {
+    sender: DynSender<'a, Reading>,
+    interval: embassy_time::Duration,
+    next: Instant,
+}
+
+impl LiveReadings<'_> {
+    /// Publishes `reading` unless the last one is less than the interval ago.
+    fn update(&mut self, reading: &Reading, now: Instant) {
+        if now < self.next {
+            return;
+        }
+        self.next = now + self.interval;
+        self.sender.send(reading.clone());
+    }
 }
 
-impl<Stream: Read + Write, Output: OutputPin, const N: usize> Runner<'_, Stream, Output, N> {
+impl<'a, Stream: Read + Write, Output: OutputPin, const N: usize> Runner<'a, Stream, Output, N> {
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    /// Adds the standard deviation of the averaged channels to the spread of the readings.
+    pub fn with_variance(mut self) -> Self {
+        self.averaging = Averaging::with_variance();
+        self
+    }
+
+    /// Publishes the filtered frames at most every `interval`, e.g. 1 s for a display or a BLE live
+    /// view. The upload averaging gets every frame regardless, the subscribers of the watch see the
+    /// latest reading only.
+    pub fn with_live_readings(mut self, sender: DynSender<'a, Reading>, interval: embassy_time::Duration) -> Self {
+        self.live = Some(LiveReadings {
+            sender,
+            interval,
+            next: Instant::MIN,
+        });
+        self
+    }
+
+    /// Filters every frame before it is averaged.
+    pub fn with_filter(mut self, filter: ReadingFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Accepts load output requests from [`hex::switch_load`].
+    pub fn with_load_switch(mut self, load_switch: &'a LoadSwitch) -> Self {
+        self.load_switch = Some(load_switch);
+        self
+    }
+
+    /// Records the executed load switches in the audit log.
+    pub fn with_audit(mut self, audit: &'a Audit) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Publishes the debounced `ERR` changes of the frames.
+    pub fn with_charger_errors(mut self, charger_errors: &'a ChargerErrors) -> Self {
+        self.charger_errors = Some(charger_errors);
+        self
+    }
+
+    /// Reads the daily history of the charger once a day and publishes the new days.
+    pub fn with_history(mut self, history: &'a DailyHistory) -> Self {
+        self.history = Some(history);
+        self
+    }
+
     pub async fn run(mut self) {
         loop {
             self.averaging_once().await;
@@ -73,7 +312,40 @@ impl<Stream: Read + Write, Output: OutputPin, const N: usize> Runner<'_, Stream,
     pub async fn averaging_once(&mut self) {
         let end = Instant::now() + self.average_interval;
         loop {
-            let reading = self.frame_handler.read_next().await;
+            self.watchdog.feed();
+            // one HEX command at a time, the responses share `hex_response`
+            let load_switch = self
+                .load_switch
+                .filter(|_| self.pending_load_switch.is_none() && !self.history_reader.is_pending());
+            let load_requested = async {
+                match load_switch {
+                    Some(load_switch) => load_switch.requested.wait().await,
+                    None => core::future::pending().await,
+                }
+            };
+            let next = select(with_timeout(FEED_INTERVAL, self.frame_handler.read_next()), load_requested).await;
+            let reading = match next {
+                Either::First(Ok(reading)) => Some(reading),
+                Either::First(Err(_)) => None,
+                Either::Second(request) => {
+                    self.start_load_switch(request).await;
+                    None
+                }
+            };
+            self.check_load_switch().await;
+            self.check_history().await;
+            let Some(mut reading) = reading else {
+                continue;
+            };
+            self.filter.apply(&mut reading);
+            if let Some(live) = &mut self.live {
+                live.update(&reading, Instant::now());
+            }
+            if let Some(charger_errors) = self.charger_errors
+                && let Some(event) = self.error_debounce.update(reading.error_code, Instant::now())
+            {
+                charger_errors.publish(event);
+            }
             _ = self.indicator_pin.set_low();
             self.averaging.add_reading(&reading);
             Timer::after_millis(1).await;
@@ -83,13 +355,73 @@ impl<Stream: Read + Write, Output: OutputPin, const N: usize> Runner<'_, Stream,
                     debug!("VE.Average> Over {} => {:?}", count, average);
                     self.rx.send(average).await;
                 } else {
-                    warn!("VE.Average> No readings collected during interval {}s", self.average_interval.as_secs());
+                    warn!("VE.Average> No readings collected during interval {}", crate::fmt::FormatableDuration(self.average_interval));
                 }
-                self.averaging = Averaging::default();
+                self.averaging.reset();
                 break;
             }
         }
     }
+
+    async fn start_load_switch(&mut self, request: LoadSwitchRequest) {
+        info!("VE.Hex> Switch load output {:?} ({:?})", request.output, request.origin);
+        self.frame_handler.hex_response = None;
+        match self.frame_handler.write_hex(&hex::encode_set(LOAD_OUTPUT_CONTROL, request.output as u8)).await {
+            Ok(()) => self.pending_load_switch = Some((request, Instant::now() + HEX_RESPONSE_TIMEOUT)),
+            Err(e) => self.finish_load_switch(request, Err(e)).await,
+        }
+    }
+
+    async fn check_load_switch(&mut self) {
+        let Some((request, deadline)) = self.pending_load_switch else {
+            return;
+        };
+        if let Some(result) = self.frame_handler.hex_response.take().and_then(|m| m.set_result(LOAD_OUTPUT_CONTROL)) {
+            self.finish_load_switch(request, result).await;
+        } else if Instant::now() >= deadline {
+            self.finish_load_switch(request, Err(HexError::Timeout)).await;
+        }
+    }
+
+    async fn check_history(&mut self) {
+        let Some(history) = self.history else {
+            return;
+        };
+        let now = Instant::now();
+        let register = if self.history_reader.is_pending() {
+            let response = self.frame_handler.hex_response.take();
+            let (day, register) = self.history_reader.update(response.as_ref(), now);
+            if let Some(day) = day {
+                history.publish(day);
+            }
+            register
+        } else if self.pending_load_switch.is_none() {
+            self.history_reader.start(now)
+        } else {
+            None
+        };
+        let Some(register) = register else {
+            return;
+        };
+        self.frame_handler.hex_response = None;
+        if let Err(e) = self.frame_handler.write_hex(&hex::encode_get(register)).await {
+            warn!("VE.History> Sending the get command failed: {:?}", e);
+            self.history_reader.finish(now);
+        }
+    }
+
+    async fn finish_load_switch(&mut self, request: LoadSwitchRequest, result: Result<(), HexError>) {
+        if let Err(e) = result {
+            warn!("VE.Hex> Switching the load output failed: {:?}", e);
+        }
+        self.pending_load_switch = None;
+        if let Some(audit) = self.audit {
+            audit.record(request.origin, "load", request.output.as_str(), result).await;
+        }
+        if let Some(load_switch) = self.load_switch {
+            load_switch.done.signal(result);
+        }
+    }
 }
 
 pub struct State<const N: usize> {
@@ -121,6 +453,16 @@ pub fn new<'a, Stream: Read + Write, Output: OutputPin, const N: usize>(
             average_interval,
             rx: state.channel.sender(),
             indicator_pin,
+            watchdog: WatchdogHandle::default(),
+            filter: ReadingFilter::default(),
+            load_switch: None,
+            pending_load_switch: None,
+            audit: None,
+            charger_errors: None,
+            error_debounce: ErrorDebounce::default(),
+            history: None,
+            history_reader: HistoryReader::default(),
+            live: None,
         },
         state.channel.receiver(),
     )
@@ -129,9 +471,38 @@ pub fn new<'a, Stream: Read + Write, Output: OutputPin, const N: usize>(
 const STRING_BUFFER_SIZE: usize = 32;
 const MAX_MESSAGES: usize = 20;
 
+/// The label value pairs of a text frame.
+type Values = LinearMap<String<STRING_BUFFER_SIZE>, String<STRING_BUFFER_SIZE>, MAX_MESSAGES>;
+
+/// Where the parser is in the byte stream, see [`FrameHandler::run_once`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+enum FrameState {
+    /// Waiting for the `\r` that opens a text frame.
+    #[default]
+    Idle,
+    /// A HEX message between the text frames, up to its `\n`.
+    Hex,
+    /// The `\n` after the `\r` of a line.
+    LineStart,
+    Label,
+    Value,
+    /// The byte after the `Checksum` label.
+    Checksum,
+}
+
 struct FrameHandler<Stream: Read> {
     stream: Stream,
     checksum: Checksum,
+    state: FrameState,
+    /// The messages, label and value of the frame read so far.
+    messages: Values,
+    label: heapless::Vec<u8, STRING_BUFFER_SIZE>,
+    value: heapless::Vec<u8, STRING_BUFFER_SIZE>,
+    hex_line: String<HEX_FRAME_SIZE>,
+    /// The host links defer their transfers until the frame is complete.
+    frame: Option<UartBusy<'static>>,
+    /// The last HEX message received between the text frames.
+    hex_response: Option<HexMessage>,
 }
 
 impl<Stream: Read> FrameHandler<Stream> {
@@ -139,52 +510,27 @@ impl<Stream: Read> FrameHandler<Stream> {
         FrameHandler {
             stream,
             checksum: Checksum::default(),
+            state: FrameState::Idle,
+            messages: Values::new(),
+            label: heapless::Vec::new(),
+            value: heapless::Vec::new(),
+            hex_line: String::new(),
+            frame: None,
+            hex_response: None,
         }
     }
 
     pub async fn read_next(&mut self) -> Reading {
+        let reading = parse_reading(self.read_values().await);
+        trace!("VE.Reading> Ok");
+        reading
+    }
+
+    /// The next frame with a valid checksum.
+    async fn read_values(&mut self) -> Values {
         loop {
-            let values = self.run_once().await;
-            match values {
-                Ok(values) => {
-                    let mut reading = Reading {
-                        battery_voltage: 0.0,
-                        battery_current: 0.0,
-                        panel_voltage: 0.0,
-                        panel_power: 0.0,
-                        load_current: 0.0,
-                    };
-                    values.into_iter().for_each(|(label, value)| match label.as_str() {
-                        "V" => {
-                            if let Ok(mv) = value.as_str().parse::<u32>() {
-                                reading.battery_voltage = mv as f32 / 1000.0;
-                            }
-                        }
-                        "I" => {
-                            if let Ok(ma) = value.as_str().parse::<i32>() {
-                                reading.battery_current = ma as f32 / 1000.0;
-                            }
-                        }
-                        "VPV" => {
-                            if let Ok(mv) = value.as_str().parse::<u32>() {
-                                reading.panel_voltage = mv as f32 / 1000.0;
-                            }
-                        }
-                        "PPV" => {
-                            if let Ok(w) = value.as_str().parse::<u32>() {
-                                reading.panel_power = w as f32;
-                            }
-                        }
-                        "IL" => {
-                            if let Ok(ma) = value.as_str().parse::<i32>() {
-                                reading.load_current = ma as f32 / 1000.0;
-                            }
-                        }
-                        _ => {}
-                    });
-                    trace!("VE.Reading> Ok");
-                    return reading;
-                }
+            match self.run_once().await {
+                Ok(values) => return values,
                 Err(_) => {
                     warn!("Error reading VE frame");
                 }
@@ -192,89 +538,96 @@ impl<Stream: Read> FrameHandler<Stream> {
         }
     }
 
-    async fn run_once(&mut self) -> Result<LinearMap<String<STRING_BUFFER_SIZE>, String<STRING_BUFFER_SIZE>, MAX_MESSAGES>, ()> {
-        while self.read_byte().await != b'\r' {
-            self.checksum.clear();
-        }
-        self.checksum.add(b'\r');
-        let mut messages = LinearMap::<String<STRING_BUFFER_SIZE>, String<STRING_BUFFER_SIZE>, MAX_MESSAGES>::new();
+    /// The next text frame, `Err` for an invalid checksum.
+    ///
+    /// Cancel safe: the parser state lives in the handler, a read dropped halfway (the watchdog
+    /// timeout, a load switch request) loses no byte and the next call continues the frame.
+    async fn run_once(&mut self) -> Result<Values, ()> {
         loop {
             let byte = self.read_byte().await;
-            self.checksum.add(byte);
+            if let Some(frame) = self.parse(byte) {
+                return frame;
+            }
+        }
+    }
 
-            let label = self.read_label().await;
-            if label == "Checksum" {
-                let checksum_byte = self.read_byte().await;
-                self.checksum.add(checksum_byte);
-                if self.checksum.is_valid() {
-                    trace!("VE.Checksum> Valid => {} messages", messages.len());
+    /// Feeds a byte to the parser, the frame once its checksum byte arrived.
+    fn parse(&mut self, byte: u8) -> Option<Result<Values, ()>> {
+        match self.state {
+            FrameState::Idle => match byte {
+                b'\r' => {
+                    self.frame = Some(crate::activity::ACTIVITY.uart_busy());
                     self.checksum.clear();
-                    return Ok(messages);
-                } else {
-                    error!("VE.Checksum> Invalid ({:?})", self.checksum);
-                    self.checksum.clear();
-                    messages.clear();
-                    return Err(());
+                    self.checksum.add(b'\r');
+                    self.messages.clear();
+                    self.state = FrameState::LineStart;
                 }
-            } else {
-                let value = self.read_value().await;
-                trace!("VE.Message> Label: '{}', Value: '{}'", label, value);
-                match messages.insert(label, value) {
-                    Ok(_) => {}
-                    Err(_) => {
-                        error!("VE> Message map full, cannot insert new message");
+                // the device sends HEX messages only between the text frames
+                b':' => {
+                    self.hex_line.clear();
+                    self.state = FrameState::Hex;
+                }
+                _ => {}
+            },
+            FrameState::Hex => {
+                if byte == b'\n' {
+                    trace!("VE.Hex> :{}", self.hex_line.as_str());
+                    if let Some(message) = hex::decode(&self.hex_line) {
+                        self.hex_response = Some(message);
                     }
+                    self.state = FrameState::Idle;
+                } else if self.hex_line.push(byte as char).is_err() {
+                    warn!("VE.Hex> Message too long");
+                    self.state = FrameState::Idle;
                 }
             }
-        }
-    }
-
-    async fn read_label(&mut self) -> String<STRING_BUFFER_SIZE> {
-        let mut label_buffer: heapless::Vec<u8, STRING_BUFFER_SIZE> = heapless::Vec::new();
-        loop {
-            let byte = self.read_byte().await;
-            self.checksum.add(byte);
-            if byte == b'\t' {
-                trace!("Ve.RX label of lenght {}", label_buffer.len());
-                break;
-            } else {
-                let _ = label_buffer.push(byte);
+            FrameState::LineStart => {
+                self.checksum.add(byte);
+                self.label.clear();
+                self.state = FrameState::Label;
             }
-        }
-        match String::from_utf8(label_buffer) {
-            Ok(label) => {
-                trace!("VE.Label> {}", label.as_str());
-                label
-            }
-            Err(_) => {
-                error!("Invalid UTF-8 sequence");
-                String::new()
-            }
-        }
-    }
-
-    async fn read_value(&mut self) -> String<STRING_BUFFER_SIZE> {
-        let mut value_buffer: heapless::Vec<u8, STRING_BUFFER_SIZE> = heapless::Vec::new();
-        loop {
-            let byte = self.read_byte().await;
-            self.checksum.add(byte);
-            if byte == b'\r' {
-                trace!("Ve.RX value of lenght {}", value_buffer.len());
-                break;
-            } else {
-                let _ = value_buffer.push(byte);
+            FrameState::Label => {
+                self.checksum.add(byte);
+                if byte != b'\t' {
+                    let _ = self.label.push(byte);
+                } else if self.label == b"Checksum" {
+                    self.state = FrameState::Checksum;
+                } else {
+                    self.value.clear();
+                    self.state = FrameState::Value;
+                }
             }
-        }
-        match String::from_utf8(value_buffer) {
-            Ok(value) => {
-                trace!("VE.Value> {}", value.as_str());
-                value
+            FrameState::Value => {
+                self.checksum.add(byte);
+                if byte != b'\r' {
+                    let _ = self.value.push(byte);
+                    return None;
+                }
+                let label = to_string(core::mem::take(&mut self.label));
+                let value = to_string(core::mem::take(&mut self.value));
+                trace!("VE.Message> Label: '{}', Value: '{}'", label, value);
+                if self.messages.insert(label, value).is_err() {
+                    error!("VE> Message map full, cannot insert new message");
+                }
+                self.state = FrameState::LineStart;
             }
-            Err(_) => {
-                error!("Invalid UTF-8 sequence");
-                String::new()
+            FrameState::Checksum => {
+                self.checksum.add(byte);
+                self.state = FrameState::Idle;
+                self.frame = None;
+                if self.checksum.is_valid() {
+                    trace!("VE.Checksum> Valid => {} messages", self.messages.len());
+                    self.checksum.clear();
+                    return Some(Ok(core::mem::replace(&mut self.messages, Values::new())));
+                }
+                error!("VE.Checksum> Invalid ({:?})", self.checksum);
+                crate::health::HEALTH.count(|counters| counters.checksum_errors += 1);
+                self.checksum.clear();
+                self.messages.clear();
+                return Some(Err(()));
             }
         }
+        None
     }
 
     async fn read_byte(&mut self) -> u8 {
@@ -293,6 +646,86 @@ impl<Stream: Read> FrameHandler<Stream> {
     }
 }
 
+impl<Stream: Read + Write> FrameHandler<Stream> {
+    async fn write_hex(&mut self, frame: &str) -> Result<(), HexError> {
+        self.stream.write_all(frame.as_bytes()).await.map_err(|_| HexError::Io)?;
+        self.stream.flush().await.map_err(|_| HexError::Io)
+    }
+}
+
+fn to_string(bytes: heapless::Vec<u8, STRING_BUFFER_SIZE>) -> String<STRING_BUFFER_SIZE> {
+    String::from_utf8(bytes).unwrap_or_else(|_| {
+        error!("Invalid UTF-8 sequence");
+        String::new()
+    })
+}
+
+fn parse_reading(values: Values) -> Reading {
+    let mut reading = Reading::default();
+    values.into_iter().for_each(|(label, value)| match label.as_str() {
+        "V" => {
+            if let Ok(mv) = value.as_str().parse::<u32>() {
+                reading.battery_voltage = mv as f32 / 1000.0;
+            }
+        }
+        "I" => {
+            if let Ok(ma) = value.as_str().parse::<i32>() {
+                reading.battery_current = ma as f32 / 1000.0;
+            }
+        }
+        "VPV" => {
+            if let Ok(mv) = value.as_str().parse::<u32>() {
+                reading.panel_voltage = mv as f32 / 1000.0;
+            }
+        }
+        "PPV" => {
+            if let Ok(w) = value.as_str().parse::<u32>() {
+                reading.panel_power = w as f32;
+            }
+        }
+        "IL" => {
+            if let Ok(ma) = value.as_str().parse::<i32>() {
+                reading.load_current = ma as f32 / 1000.0;
+            }
+        }
+        "H20" => {
+            if let Ok(centi_kwh) = value.as_str().parse::<u32>() {
+                reading.yield_today = centi_kwh as f32 / 100.0;
+            }
+        }
+        "H19" => {
+            if let Ok(centi_kwh) = value.as_str().parse::<u32>() {
+                reading.yield_total = centi_kwh as f32 / 100.0;
+            }
+        }
+        "H21" => {
+            if let Ok(w) = value.as_str().parse::<u32>() {
+                reading.max_power_today = w as f32;
+            }
+        }
+        "CS" => {
+            if let Ok(cs) = value.as_str().parse::<u32>() {
+                reading.charger_state = cs;
+            }
+        }
+        "MPPT" => {
+            if let Ok(mode) = value.as_str().parse::<u32>() {
+                reading.mppt_mode = mode;
+            }
+        }
+        "ERR" => {
+            if let Ok(err) = value.as_str().parse::<u32>() {
+                reading.error_code = err;
+            }
+        }
+        "LOAD" => {
+            reading.load_on = value.as_str() == "ON";
+        }
+        _ => {}
+    });
+    reading
+}
+
 #[derive(Default, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 struct Checksum {
@@ -365,6 +798,39 @@ pub mod tests {
         assert_eq!(values_2.get("P").unwrap().as_str(), "0");
     }
 
+    #[tokio::test]
+    async fn check_hex_between_frames() {
+        let mut raw_data = std::vec::Vec::from(&b":8ABED0004B1\n"[..]);
+        raw_data.extend_from_slice(&[
+            0x0d, 0x0a, 0x50, 0x49, 0x44, 0x09, 0x30, 0x78, 0x32, 0x30, 0x33, 0x0d, 0x0a, 0x56, 0x09, 0x32, 0x36, 0x32, 0x30, 0x31, 0x0d, 0x0a, 0x49, 0x09,
+            0x30, 0x0d, 0x0a, 0x50, 0x09, 0x30, 0x0d, 0x0a, 0x43, 0x45, 0x09, 0x30, 0x0d, 0x0a, 0x53, 0x4f, 0x43, 0x09, 0x31, 0x30, 0x30, 0x30, 0x0d, 0x0a,
+            0x54, 0x54, 0x47, 0x09, 0x2d, 0x31, 0x0d, 0x0a, 0x41, 0x6c, 0x61, 0x72, 0x6d, 0x09, 0x4f, 0x46, 0x46, 0x0d, 0x0a, 0x52, 0x65, 0x6c, 0x61, 0x79,
+            0x09, 0x4f, 0x46, 0x46, 0x0d, 0x0a, 0x41, 0x52, 0x09, 0x30, 0x0d, 0x0a, 0x42, 0x4d, 0x56, 0x09, 0x37, 0x30, 0x30, 0x0d, 0x0a, 0x46, 0x57, 0x09,
+            0x30, 0x33, 0x30, 0x37, 0x0d, 0x0a, 0x43, 0x68, 0x65, 0x63, 0x6b, 0x73, 0x75, 0x6d, 0x09, 0xd8,
+        ]);
+        let slice: &[u8] = &raw_data;
+        let mut frame_handler = super::FrameHandler::new(slice);
+        let values = frame_handler.run_once().await.unwrap();
+        assert_eq!(values.get("V").unwrap().as_str(), "26201");
+        let response = frame_handler.hex_response.take().unwrap();
+        assert_eq!(response.set_result(LOAD_OUTPUT_CONTROL), Some(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn check_read_cancelled_mid_frame() {
+        use tokio::io::AsyncWriteExt;
+        let frame = b"\r\nPID\t0x203\r\nV\t26201\r\nI\t0\r\nP\t0\r\nCE\t0\r\nSOC\t1000\r\nTTG\t-1\r\nAlarm\tOFF\r\nRelay\tOFF\r\nAR\t0\r\nBMV\t700\r\nFW\t0307\r\nChecksum\t\xd8";
+        let (stream, mut device) = crate::tests::mock_stream::mock_stream();
+        let mut frame_handler = super::FrameHandler::new(stream);
+        device.write_all(&frame[..20]).await.unwrap();
+        assert!(with_timeout(embassy_time::Duration::from_millis(20), frame_handler.read_next()).await.is_err());
+        assert_eq!(frame_handler.state, FrameState::Value);
+
+        device.write_all(&frame[20..]).await.unwrap();
+        let reading = frame_handler.read_next().await;
+        assert_relative_eq!(reading.battery_voltage, 26.201);
+    }
+
     #[tokio::test]
     async fn averaging() {
         let mut storage = Averaging::default();
@@ -376,6 +842,7 @@ pub mod tests {
             panel_voltage: 22.0,
             panel_power: 50.0,
             load_current: 0.8,
+            ..Default::default()
         });
         storage.add_reading(&Reading {
             battery_voltage: 12.0,
@@ -383,6 +850,7 @@ pub mod tests {
             panel_voltage: 18.0,
             panel_power: 52.0,
             load_current: 0.2,
+            ..Default::default()
         });
 
         let average = storage.average().unwrap();
@@ -402,6 +870,7 @@ pub mod tests {
                 panel_voltage: 18.0 + i as f32,
                 panel_power: 52.0 + i as f32,
                 load_current: 0.2 + i as f32,
+                ..Default::default()
             });
         }
         let average = storage.average().unwrap();
@@ -414,4 +883,209 @@ pub mod tests {
 
         assert!(storage.average().is_none());
     }
+
+    #[test]
+    fn averaging_keeps_precision_and_sign() {
+        let mut storage = Averaging::default();
+        // a day at 1 Hz, an f32 sum is off by more than 10 mV
+        for _ in 0..86_400 {
+            storage.add_reading(&Reading {
+                battery_voltage: 12.345,
+                battery_current: -1.2,
+                load_current: 0.3,
+                ..Default::default()
+            });
+        }
+        storage.add_reading(&Reading {
+            battery_voltage: 12.345,
+            battery_current: 1.2,
+            load_current: -0.3,
+            ..Default::default()
+        });
+        let (average, count) = storage.average().unwrap();
+        assert_eq!(count, 86_401);
+        assert_relative_eq!(average.battery_voltage, 12.345);
+        assert_relative_eq!(average.battery_current, -1.2);
+        assert_relative_eq!(average.load_current, 0.3);
+    }
+
+    #[test]
+    fn averaging_saturates() {
+        let mut storage = Averaging::default();
+        for value in [f32::MAX, f32::MAX, f32::NAN] {
+            storage.add_reading(&Reading {
+                panel_power: value,
+                battery_current: -value,
+                ..Default::default()
+            });
+        }
+        let (average, count) = storage.average().unwrap();
+        assert_eq!(count, 3);
+        assert_relative_eq!(average.panel_power, (i64::MAX / 3) as f32 / 1000.0);
+        assert_relative_eq!(average.battery_current, (i64::MIN / 3) as f32 / 1000.0);
+        assert!(storage.average().is_none());
+    }
+
+    #[test]
+    fn check_live_readings_decimation() {
+        let watch = embassy_sync::watch::Watch::<NoopRawMutex, Reading, 1>::new();
+        let mut receiver = watch.anon_receiver();
+        let mut live = LiveReadings {
+            sender: watch.dyn_sender(),
+            interval: embassy_time::Duration::from_secs(1),
+            next: Instant::MIN,
+        };
+        let mut published = std::vec::Vec::new();
+        for (millis, panel_power) in [(0, 10.0), (400, 11.0), (1_000, 12.0), (1_200, 13.0), (1_900, 14.0), (2_100, 15.0)] {
+            live.update(
+                &Reading {
+                    panel_power,
+                    ..Default::default()
+                },
+                Instant::from_millis(millis),
+            );
+            if let Some(reading) = receiver.try_changed() {
+                published.push(reading.panel_power);
+            }
+        }
+        assert_eq!(published, [10.0, 12.0, 15.0]);
+    }
+
+    #[test]
+    fn averaging_spread() {
+        let mut storage = Averaging::with_variance();
+        for (battery_voltage, panel_power) in [(12.0, 0.0), (14.0, 100.0), (13.0, 50.0)] {
+            storage.add_reading(&Reading {
+                battery_voltage,
+                panel_power,
+                ..Default::default()
+            });
+        }
+        let (average, _) = storage.average().unwrap();
+        let spread = average.spread.unwrap();
+        assert_eq!((spread.battery_voltage.min, spread.battery_voltage.max), (12.0, 14.0));
+        assert_relative_eq!(spread.battery_voltage.stddev.unwrap(), 0.816);
+        assert_eq!((spread.panel_power.min, spread.panel_power.max), (0.0, 100.0));
+        assert_relative_eq!(spread.panel_power.stddev.unwrap(), 40.824);
+        assert_eq!(
+            spread.load_current,
+            Spread {
+                min: 0.0,
+                max: 0.0,
+                stddev: Some(0.0)
+            }
+        );
+
+        // the variance tracking survives the reset, a single reading has no spread
+        storage.add_reading(&Reading::default());
+        assert_eq!(storage.average().unwrap().0.spread, None);
+        storage.add_reading(&Reading {
+            battery_current: -2.5,
+            ..Default::default()
+        });
+        storage.add_reading(&Reading {
+            battery_current: 1.5,
+            ..Default::default()
+        });
+        let spread = storage.average().unwrap().0.spread.unwrap();
+        assert_eq!(
+            spread.battery_current,
+            Spread {
+                min: -2.5,
+                max: 1.5,
+                stddev: Some(2.0)
+            }
+        );
+
+        let mut storage = Averaging::default();
+        storage.add_reading(&Reading {
+            panel_voltage: 18.0,
+            ..Default::default()
+        });
+        storage.add_reading(&Reading {
+            panel_voltage: 21.0,
+            ..Default::default()
+        });
+        let spread = storage.average().unwrap().0.spread.unwrap();
+        assert_eq!(
+            spread.panel_voltage,
+            Spread {
+                min: 18.0,
+                max: 21.0,
+                stddev: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn averaging_keeps_latest_counters_and_states() {
+        let mut storage = Averaging::default();
+        storage.add_reading(&Reading {
+            panel_power: 40.0,
+            yield_today: 0.12,
+            yield_total: 15.5,
+            max_power_today: 45.0,
+            charger_state: 3,
+            mppt_mode: 2,
+            error_code: 0,
+            load_on: false,
+            ..Default::default()
+        });
+        storage.add_reading(&Reading {
+            panel_power: 60.0,
+            yield_today: 0.14,
+            yield_total: 15.52,
+            max_power_today: 61.0,
+            charger_state: 4,
+            mppt_mode: 1,
+            error_code: 17,
+            load_on: true,
+            ..Default::default()
+        });
+
+        let (average, count) = storage.average().unwrap();
+        assert_eq!(count, 2);
+        assert_relative_eq!(average.panel_power, 50.0);
+        assert_relative_eq!(average.yield_today, 0.14);
+        assert_relative_eq!(average.yield_total, 15.52);
+        assert_relative_eq!(average.max_power_today, 61.0);
+        assert_eq!(average.charger_state, 4);
+        assert_eq!(average.mppt_mode, 1);
+        assert_eq!(average.error_code, 17);
+        assert!(average.load_on);
+    }
+
+    #[test]
+    fn check_parse_reading() {
+        let mut values = Values::new();
+        for (label, value) in [
+            ("V", "12650"),
+            ("I", "-1200"),
+            ("VPV", "18200"),
+            ("PPV", "42"),
+            ("IL", "300"),
+            ("H19", "1552"),
+            ("H20", "14"),
+            ("H21", "61"),
+            ("CS", "3"),
+            ("MPPT", "2"),
+            ("ERR", "0"),
+            ("LOAD", "ON"),
+        ] {
+            values.insert(label.try_into().unwrap(), value.try_into().unwrap()).unwrap();
+        }
+        let reading = parse_reading(values);
+        assert_relative_eq!(reading.battery_voltage, 12.65);
+        assert_relative_eq!(reading.battery_current, -1.2);
+        assert_relative_eq!(reading.panel_voltage, 18.2);
+        assert_relative_eq!(reading.panel_power, 42.0);
+        assert_relative_eq!(reading.load_current, 0.3);
+        assert_relative_eq!(reading.yield_total, 15.52);
+        assert_relative_eq!(reading.yield_today, 0.14);
+        assert_relative_eq!(reading.max_power_today, 61.0);
+        assert_eq!(reading.charger_state, 3);
+        assert_eq!(reading.mppt_mode, 2);
+        assert_eq!(reading.error_code, 0);
+        assert!(reading.load_on);
+    }
 }
@@ -0,0 +1,304 @@
+//! The battery monitor flavor of VE.Direct, e.g. a BMV or a SmartShunt.
+//!
+//! These devices send two blocks with their own checksum each: the live values and
+//! the history (`H1` to `H18`). Both update the same [`BatteryReading`], so every
+//! average carries the latest history.
+
+use embassy_sync::{
+    blocking_mutex::raw::NoopRawMutex,
+    channel::{Channel, Receiver, Sender},
+};
+use embassy_time::{Instant, with_timeout};
+use embedded_io_async::Read;
+
+use super::{FrameHandler, MilliSum, Values};
+use crate::watchdog::{FEED_INTERVAL, WatchdogHandle};
+
+#[derive(Default, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryReading {
+    pub voltage: f32,                   // V    V
+    pub current: f32,                   // I    A
+    pub power: f32,                     // P    W
+    pub consumed: f32,                  // CE   Ah
+    pub state_of_charge: f32,           // SOC  %
+    pub time_to_go: i32,                // TTG  min, -1 while charging
+    pub alarm: bool,                    // Alarm
+    pub alarm_reason: u32,              // AR
+    pub deepest_discharge: f32,         // H1   Ah
+    pub last_discharge: f32,            // H2   Ah
+    pub average_discharge: f32,         // H3   Ah
+    pub charge_cycles: u32,             // H4
+    pub full_discharges: u32,           // H5
+    pub cumulative_drawn: f32,          // H6   Ah
+    pub min_voltage: f32,               // H7   V
+    pub max_voltage: f32,               // H8   V
+    pub seconds_since_full_charge: u32, // H9
+    pub automatic_syncs: u32,           // H10
+    pub low_voltage_alarms: u32,        // H11
+    pub high_voltage_alarms: u32,       // H12
+    pub low_aux_voltage_alarms: u32,    // H13
+    pub high_aux_voltage_alarms: u32,   // H14
+    pub min_aux_voltage: f32,           // H15  V
+    pub max_aux_voltage: f32,           // H16  V
+    pub discharged_energy: f32,         // H17  kWh
+    pub charged_energy: f32,            // H18  kWh
+}
+
+/// Averages the live values, the counters and the history keep their latest value.
+#[derive(Default, Debug)]
+pub struct BatteryAveraging {
+    latest: BatteryReading,
+    voltage_sum: MilliSum,
+    current_sum: MilliSum,
+    power_sum: MilliSum,
+    count: u32,
+}
+
+impl BatteryAveraging {
+    fn add_values(&mut self, values: Values) {
+        if update_battery_reading(values, &mut self.latest) {
+            self.voltage_sum.add(self.latest.voltage);
+            self.current_sum.add(self.latest.current);
+            self.power_sum.add(self.latest.power);
+            self.count = self.count.saturating_add(1);
+        }
+    }
+
+    pub fn average(&mut self) -> Option<(BatteryReading, u32)> {
+        if self.count == 0 {
+            return None;
+        }
+        let count = self.count;
+        let reading = BatteryReading {
+            voltage: self.voltage_sum.average(count),
+            current: self.current_sum.average(count),
+            power: self.power_sum.average(count),
+            ..self.latest.clone()
+        };
+        (self.voltage_sum, self.current_sum, self.power_sum, self.count) = (MilliSum::default(), MilliSum::default(), MilliSum::default(), 0);
+        Some((reading, count))
+    }
+}
+
+pub struct Runner<'a, Stream: Read, const N: usize> {
+    frame_handler: FrameHandler<Stream>,
+    averaging: BatteryAveraging,
+    average_interval: embassy_time::Duration,
+    tx: Sender<'a, NoopRawMutex, BatteryReading, N>,
+    watchdog: WatchdogHandle<'a>,
+}
+
+impl<'a, Stream: Read, const N: usize> Runner<'a, Stream, N> {
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            self.averaging_once().await;
+        }
+    }
+
+    pub async fn averaging_once(&mut self) {
+        let end = Instant::now() + self.average_interval;
+        loop {
+            self.watchdog.feed();
+            if let Ok(values) = with_timeout(FEED_INTERVAL, self.frame_handler.read_values()).await {
+                self.averaging.add_values(values);
+            }
+            if Instant::now() >= end {
+                match self.averaging.average() {
+                    Some((average, count)) => {
+                        debug!("VE.Battery> Over {} => {:?}", count, average);
+                        self.tx.send(average).await;
+                    }
+                    None => warn!("VE.Battery> No readings collected during interval {}", crate::fmt::FormatableDuration(self.average_interval)),
+                }
+                break;
+            }
+        }
+    }
+}
+
+pub struct State<const N: usize> {
+    channel: Channel<NoopRawMutex, BatteryReading, N>,
+}
+
+impl<const N: usize> State<N> {
+    pub fn new() -> Self {
+        State { channel: Channel::new() }
+    }
+}
+
+impl<const N: usize> Default for State<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn new<'a, Stream: Read, const N: usize>(
+    state: &'a mut State<N>,
+    stream: Stream,
+    average_interval: embassy_time::Duration,
+) -> (Runner<'a, Stream, N>, Receiver<'a, NoopRawMutex, BatteryReading, N>) {
+    (
+        Runner {
+            frame_handler: FrameHandler::new(stream),
+            averaging: BatteryAveraging::default(),
+            average_interval,
+            tx: state.channel.sender(),
+            watchdog: WatchdogHandle::default(),
+        },
+        state.channel.receiver(),
+    )
+}
+
+/// Updates `reading` with the values of a block, `true` if it was the block with the live values.
+fn update_battery_reading(values: Values, reading: &mut BatteryReading) -> bool {
+    let milli = |value: &str| value.parse::<i32>().ok().map(|milli| milli as f32 / 1000.0);
+    let count = |value: &str| value.parse::<u32>().ok();
+    let mut live = false;
+    for (label, value) in values.iter() {
+        let value = value.as_str();
+        match label.as_str() {
+            "V" => {
+                if let Some(v) = milli(value) {
+                    reading.voltage = v;
+                    live = true;
+                }
+            }
+            "I" => reading.current = milli(value).unwrap_or(reading.current),
+            "P" => reading.power = value.parse::<i32>().map(|w| w as f32).unwrap_or(reading.power),
+            "CE" => reading.consumed = milli(value).unwrap_or(reading.consumed),
+            "SOC" => {
+                if let Ok(permille) = value.parse::<u32>() {
+                    reading.state_of_charge = permille as f32 / 10.0;
+                }
+            }
+            "TTG" => reading.time_to_go = value.parse().unwrap_or(reading.time_to_go),
+            "Alarm" => reading.alarm = value == "ON",
+            "AR" => reading.alarm_reason = count(value).unwrap_or(reading.alarm_reason),
+            "H1" => reading.deepest_discharge = milli(value).unwrap_or(reading.deepest_discharge),
+            "H2" => reading.last_discharge = milli(value).unwrap_or(reading.last_discharge),
+            "H3" => reading.average_discharge = milli(value).unwrap_or(reading.average_discharge),
+            "H4" => reading.charge_cycles = count(value).unwrap_or(reading.charge_cycles),
+            "H5" => reading.full_discharges = count(value).unwrap_or(reading.full_discharges),
+            "H6" => reading.cumulative_drawn = milli(value).unwrap_or(reading.cumulative_drawn),
+            "H7" => reading.min_voltage = milli(value).unwrap_or(reading.min_voltage),
+            "H8" => reading.max_voltage = milli(value).unwrap_or(reading.max_voltage),
+            "H9" => reading.seconds_since_full_charge = count(value).unwrap_or(reading.seconds_since_full_charge),
+            "H10" => reading.automatic_syncs = count(value).unwrap_or(reading.automatic_syncs),
+            "H11" => reading.low_voltage_alarms = count(value).unwrap_or(reading.low_voltage_alarms),
+            "H12" => reading.high_voltage_alarms = count(value).unwrap_or(reading.high_voltage_alarms),
+            "H13" => reading.low_aux_voltage_alarms = count(value).unwrap_or(reading.low_aux_voltage_alarms),
+            "H14" => reading.high_aux_voltage_alarms = count(value).unwrap_or(reading.high_aux_voltage_alarms),
+            "H15" => reading.min_aux_voltage = milli(value).unwrap_or(reading.min_aux_voltage),
+            "H16" => reading.max_aux_voltage = milli(value).unwrap_or(reading.max_aux_voltage),
+            "H17" => {
+                if let Some(centi_kwh) = count(value) {
+                    reading.discharged_energy = centi_kwh as f32 / 100.0;
+                }
+            }
+            "H18" => {
+                if let Some(centi_kwh) = count(value) {
+                    reading.charged_energy = centi_kwh as f32 / 100.0;
+                }
+            }
+            _ => {}
+        }
+    }
+    live
+}
+
+#[cfg(test)]
+pub mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> Values {
+        let mut values = Values::new();
+        for (label, value) in pairs {
+            values.insert((*label).try_into().unwrap(), (*value).try_into().unwrap()).unwrap();
+        }
+        values
+    }
+
+    #[test]
+    fn check_live_and_history_blocks() {
+        let mut reading = BatteryReading::default();
+        let live = values(&[
+            ("PID", "0xA389"),
+            ("V", "12850"),
+            ("I", "-2340"),
+            ("P", "-30"),
+            ("CE", "-12500"),
+            ("SOC", "876"),
+            ("TTG", "1440"),
+            ("Alarm", "OFF"),
+            ("AR", "0"),
+        ]);
+        assert!(update_battery_reading(live, &mut reading));
+        let history = values(&[
+            ("H1", "-55000"),
+            ("H4", "37"),
+            ("H7", "11420"),
+            ("H9", "86400"),
+            ("H17", "4210"),
+            ("H18", "4987"),
+        ]);
+        assert!(!update_battery_reading(history, &mut reading));
+
+        assert_relative_eq!(reading.voltage, 12.85);
+        assert_relative_eq!(reading.current, -2.34);
+        assert_relative_eq!(reading.power, -30.0);
+        assert_relative_eq!(reading.consumed, -12.5);
+        assert_relative_eq!(reading.state_of_charge, 87.6);
+        assert_eq!(reading.time_to_go, 1440);
+        assert!(!reading.alarm);
+        assert_relative_eq!(reading.deepest_discharge, -55.0);
+        assert_eq!(reading.charge_cycles, 37);
+        assert_relative_eq!(reading.min_voltage, 11.42);
+        assert_eq!(reading.seconds_since_full_charge, 86400);
+        assert_relative_eq!(reading.discharged_energy, 42.1);
+        assert_relative_eq!(reading.charged_energy, 49.87);
+    }
+
+    #[test]
+    fn check_averaging_keeps_history() {
+        let mut averaging = BatteryAveraging::default();
+        averaging.add_values(values(&[("V", "12000"), ("I", "1000"), ("SOC", "500")]));
+        averaging.add_values(values(&[("H4", "12")]));
+        averaging.add_values(values(&[("V", "13000"), ("I", "3000"), ("SOC", "510")]));
+
+        let (average, count) = averaging.average().unwrap();
+        assert_eq!(count, 2);
+        assert_relative_eq!(average.voltage, 12.5);
+        assert_relative_eq!(average.current, 2.0);
+        assert_relative_eq!(average.state_of_charge, 51.0);
+        assert_eq!(average.charge_cycles, 12);
+        assert!(averaging.average().is_none());
+    }
+
+    #[tokio::test]
+    async fn check_runner_sends_average() {
+        // live block of a BMV-700, see the frame handler tests
+        let raw_data: [u8; _] = [
+            0x0d, 0x0a, 0x50, 0x49, 0x44, 0x09, 0x30, 0x78, 0x32, 0x30, 0x33, 0x0d, 0x0a, 0x56, 0x09, 0x32, 0x36, 0x32, 0x30, 0x31, 0x0d, 0x0a, 0x49, 0x09,
+            0x30, 0x0d, 0x0a, 0x50, 0x09, 0x30, 0x0d, 0x0a, 0x43, 0x45, 0x09, 0x30, 0x0d, 0x0a, 0x53, 0x4f, 0x43, 0x09, 0x31, 0x30, 0x30, 0x30, 0x0d, 0x0a,
+            0x54, 0x54, 0x47, 0x09, 0x2d, 0x31, 0x0d, 0x0a, 0x41, 0x6c, 0x61, 0x72, 0x6d, 0x09, 0x4f, 0x46, 0x46, 0x0d, 0x0a, 0x52, 0x65, 0x6c, 0x61, 0x79,
+            0x09, 0x4f, 0x46, 0x46, 0x0d, 0x0a, 0x41, 0x52, 0x09, 0x30, 0x0d, 0x0a, 0x42, 0x4d, 0x56, 0x09, 0x37, 0x30, 0x30, 0x0d, 0x0a, 0x46, 0x57, 0x09,
+            0x30, 0x33, 0x30, 0x37, 0x0d, 0x0a, 0x43, 0x68, 0x65, 0x63, 0x6b, 0x73, 0x75, 0x6d, 0x09, 0xd8,
+        ];
+        let slice: &[u8] = &raw_data;
+        let mut state = State::<1>::new();
+        let (mut runner, rx) = new(&mut state, slice, embassy_time::Duration::from_millis(0));
+        runner.averaging_once().await;
+        let reading = rx.try_receive().unwrap();
+        assert_relative_eq!(reading.voltage, 26.201);
+        assert_relative_eq!(reading.state_of_charge, 100.0);
+        assert_eq!(reading.time_to_go, -1);
+    }
+}
@@ -0,0 +1,251 @@
+//! The `ERR` field of the charger as typed, debounced events.
+//!
+//! A code has to be reported by [`ERROR_DEBOUNCE_FRAMES`] frames in a row before it
+//! counts, a single corrupted frame neither raises nor clears an error.
+
+use core::cell::Cell;
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::Instant;
+
+use crate::proto::bt_::solar_;
+
+pub const ERROR_DEBOUNCE_FRAMES: u8 = 3;
+const CHARGER_ERROR_QUEUE_SIZE: usize = 4;
+
+/// Error codes of the VE.Direct protocol for the MPPT chargers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChargerError {
+    BatteryVoltageTooHigh,
+    ChargerTemperatureTooHigh,
+    ChargerOverCurrent,
+    ChargerCurrentReversed,
+    BulkTimeLimitExceeded,
+    CurrentSensorIssue,
+    TerminalsOverheated,
+    ConverterIssue,
+    /// The panel voltage is above the rating of the charger.
+    InputVoltageTooHigh,
+    InputCurrentTooHigh,
+    InputShutdownBatteryVoltage,
+    InputShutdownCurrentFlow,
+    LostCommunication,
+    SynchronisedChargingConfig,
+    BmsConnectionLost,
+    NetworkMisconfigured,
+    FactoryCalibrationLost,
+    InvalidFirmware,
+    UserSettingsInvalid,
+    Other(u32),
+}
+
+impl ChargerError {
+    /// The error of an `ERR` value, `None` for 0 (no error).
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            0 => return None,
+            2 => Self::BatteryVoltageTooHigh,
+            17 => Self::ChargerTemperatureTooHigh,
+            18 => Self::ChargerOverCurrent,
+            19 => Self::ChargerCurrentReversed,
+            20 => Self::BulkTimeLimitExceeded,
+            21 => Self::CurrentSensorIssue,
+            26 => Self::TerminalsOverheated,
+            28 => Self::ConverterIssue,
+            33 => Self::InputVoltageTooHigh,
+            34 => Self::InputCurrentTooHigh,
+            38 => Self::InputShutdownBatteryVoltage,
+            39 => Self::InputShutdownCurrentFlow,
+            65 => Self::LostCommunication,
+            66 => Self::SynchronisedChargingConfig,
+            67 => Self::BmsConnectionLost,
+            68 => Self::NetworkMisconfigured,
+            116 => Self::FactoryCalibrationLost,
+            117 => Self::InvalidFirmware,
+            119 => Self::UserSettingsInvalid,
+            code => Self::Other(code),
+        })
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::BatteryVoltageTooHigh => 2,
+            Self::ChargerTemperatureTooHigh => 17,
+            Self::ChargerOverCurrent => 18,
+            Self::ChargerCurrentReversed => 19,
+            Self::BulkTimeLimitExceeded => 20,
+            Self::CurrentSensorIssue => 21,
+            Self::TerminalsOverheated => 26,
+            Self::ConverterIssue => 28,
+            Self::InputVoltageTooHigh => 33,
+            Self::InputCurrentTooHigh => 34,
+            Self::InputShutdownBatteryVoltage => 38,
+            Self::InputShutdownCurrentFlow => 39,
+            Self::LostCommunication => 65,
+            Self::SynchronisedChargingConfig => 66,
+            Self::BmsConnectionLost => 67,
+            Self::NetworkMisconfigured => 68,
+            Self::FactoryCalibrationLost => 116,
+            Self::InvalidFirmware => 117,
+            Self::UserSettingsInvalid => 119,
+            Self::Other(code) => *code,
+        }
+    }
+}
+
+/// A change of the debounced charger error.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChargerErrorEvent {
+    /// `None` once the error cleared.
+    pub error: Option<ChargerError>,
+    pub previous: Option<ChargerError>,
+    pub at: Instant,
+}
+
+impl ChargerErrorEvent {
+    pub(crate) fn to_event(self) -> solar_::ChargerErrorEvent {
+        solar_::ChargerErrorEvent {
+            code: self.error.map_or(0, |error| error.code()),
+            previous_code: self.previous.map_or(0, |error| error.code()),
+            uptime_seconds: self.at.as_secs() as u32,
+        }
+    }
+}
+
+/// Debounces the `ERR` values of the frames, see the module documentation.
+#[derive(Debug, Default)]
+pub struct ErrorDebounce {
+    reported: u32,
+    candidate: u32,
+    count: u8,
+}
+
+impl ErrorDebounce {
+    /// Feeds the code of a frame, returns the change once it is stable.
+    pub fn update(&mut self, code: u32, now: Instant) -> Option<ChargerErrorEvent> {
+        if code == self.reported {
+            self.count = 0;
+            return None;
+        }
+        if code != self.candidate {
+            self.candidate = code;
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count < ERROR_DEBOUNCE_FRAMES {
+            return None;
+        }
+        let previous = core::mem::replace(&mut self.reported, code);
+        self.count = 0;
+        Some(ChargerErrorEvent {
+            error: ChargerError::from_code(code),
+            previous: ChargerError::from_code(previous),
+            at: now,
+        })
+    }
+}
+
+/// The debounced charger errors, from the VE.Direct runner to the cloud runner and the status LED.
+pub struct ChargerErrors {
+    events: Channel<NoopRawMutex, ChargerErrorEvent, CHARGER_ERROR_QUEUE_SIZE>,
+    active: Cell<Option<ChargerError>>,
+}
+
+impl ChargerErrors {
+    pub const fn new() -> Self {
+        Self {
+            events: Channel::new(),
+            active: Cell::new(None),
+        }
+    }
+
+    /// The error the charger currently reports.
+    pub fn active(&self) -> Option<ChargerError> {
+        self.active.get()
+    }
+
+    pub(crate) fn publish(&self, event: ChargerErrorEvent) {
+        match event.error {
+            Some(error) => warn!("VE.Error> {:?} (code {})", error, error.code()),
+            None => info!("VE.Error> {:?} cleared", event.previous),
+        }
+        self.active.set(event.error);
+        if self.events.try_send(event).is_err() {
+            warn!("VE.Error> Event queue full => change not uploaded");
+        }
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /// Returns once there is a change to upload, without taking it.
+    pub(crate) async fn wait_pending(&self) {
+        self.events.ready_to_receive().await
+    }
+
+    pub(crate) fn take(&self) -> Option<ChargerErrorEvent> {
+        self.events.try_receive().ok()
+    }
+}
+
+impl Default for ChargerErrors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_codes() {
+        assert_eq!(ChargerError::from_code(0), None);
+        assert_eq!(ChargerError::from_code(33), Some(ChargerError::InputVoltageTooHigh));
+        assert_eq!(ChargerError::from_code(99), Some(ChargerError::Other(99)));
+        for code in [2, 17, 18, 19, 20, 21, 26, 28, 33, 34, 38, 39, 65, 66, 67, 68, 116, 117, 119, 99] {
+            assert_eq!(ChargerError::from_code(code).unwrap().code(), code);
+        }
+    }
+
+    #[test]
+    fn check_debounce() {
+        let now = Instant::from_secs(10);
+        let mut debounce = ErrorDebounce::default();
+        // a single glitch is ignored
+        assert_eq!(debounce.update(17, now), None);
+        assert_eq!(debounce.update(0, now), None);
+        assert_eq!(debounce.update(17, now), None);
+        assert_eq!(debounce.update(17, now), None);
+        let raised = debounce.update(17, now).unwrap();
+        assert_eq!(raised.error, Some(ChargerError::ChargerTemperatureTooHigh));
+        assert_eq!(raised.previous, None);
+        assert_eq!(debounce.update(17, now), None);
+
+        for _ in 1..ERROR_DEBOUNCE_FRAMES {
+            assert_eq!(debounce.update(0, now), None);
+        }
+        let cleared = debounce.update(0, now).unwrap();
+        assert_eq!(cleared.error, None);
+        assert_eq!(cleared.previous, Some(ChargerError::ChargerTemperatureTooHigh));
+        assert_eq!(cleared.to_event().previous_code, 17);
+    }
+
+    #[test]
+    fn check_active_error() {
+        let errors = ChargerErrors::new();
+        let mut debounce = ErrorDebounce::default();
+        for _ in 0..ERROR_DEBOUNCE_FRAMES {
+            if let Some(event) = debounce.update(33, Instant::from_secs(1)) {
+                errors.publish(event);
+            }
+        }
+        assert_eq!(errors.active(), Some(ChargerError::InputVoltageTooHigh));
+        assert!(errors.has_pending());
+        assert_eq!(errors.take().unwrap().to_event().code, 33);
+        assert!(errors.take().is_none());
+    }
+}
@@ -0,0 +1,234 @@
+//! The VE.Direct HEX protocol, interleaved with the text frames on the same line.
+//!
+//! A message is `:` followed by the command nibble, the payload bytes as hex and a
+//! checksum byte that brings the sum of all bytes to `0x55`, terminated by `\n`.
+
+use core::fmt::Write;
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+use crate::audit::CommandOrigin;
+
+/// Register of the load output control (un8).
+pub const LOAD_OUTPUT_CONTROL: u16 = 0xEDAB;
+
+/// Time the device gets to answer a command, the text frames pause while it is in HEX mode.
+pub(crate) const HEX_RESPONSE_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Fits the response of a history day register, the longest message read.
+pub(crate) const HEX_FRAME_SIZE: usize = 96;
+const MAX_PAYLOAD_SIZE: usize = HEX_FRAME_SIZE / 2;
+const COMMAND_GET: u8 = 0x7;
+const COMMAND_SET: u8 = 0x8;
+const CHECKSUM_TARGET: u8 = 0x55;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoadOutput {
+    Off = 0,
+    /// Controlled by the battery life algorithm of the charger.
+    Auto = 1,
+    On = 4,
+}
+
+impl LoadOutput {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LoadOutput::Off => "off",
+            LoadOutput::Auto => "auto",
+            LoadOutput::On => "on",
+        }
+    }
+
+    /// The output named by [`LoadOutput::as_str`].
+    pub fn parse(name: &str) -> Option<Self> {
+        [LoadOutput::Off, LoadOutput::Auto, LoadOutput::On]
+            .into_iter()
+            .find(|output| output.as_str() == name)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HexError {
+    /// No response within [`HEX_RESPONSE_TIMEOUT`].
+    Timeout,
+    UnknownRegister,
+    NotSupported,
+    ParameterError,
+    /// Writing the command failed.
+    Io,
+}
+
+/// A decoded message, the checksum is already verified.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct HexMessage {
+    pub command: u8,
+    pub payload: Vec<u8, MAX_PAYLOAD_SIZE>,
+}
+
+impl HexMessage {
+    /// Result of a set command for `register`, `None` if this is not its response.
+    pub fn set_result(&self, register: u16) -> Option<Result<(), HexError>> {
+        self.response(COMMAND_SET, register).map(|result| result.map(|_| ()))
+    }
+
+    /// Value of a get command for `register`, `None` if this is not its response.
+    pub fn get_value(&self, register: u16) -> Option<Result<&[u8], HexError>> {
+        self.response(COMMAND_GET, register)
+    }
+
+    fn response(&self, command: u8, register: u16) -> Option<Result<&[u8], HexError>> {
+        let [id_low, id_high, flags, value @ ..] = self.payload.as_slice() else {
+            return None;
+        };
+        if self.command != command || u16::from_le_bytes([*id_low, *id_high]) != register {
+            return None;
+        }
+        Some(match flags {
+            0 => Ok(value),
+            0x01 => Err(HexError::UnknownRegister),
+            0x02 => Err(HexError::NotSupported),
+            _ => Err(HexError::ParameterError),
+        })
+    }
+}
+
+/// Encodes a set command of an un8 register, including the leading `:` and the trailing `\n`.
+pub(crate) fn encode_set(register: u16, value: u8) -> String<HEX_FRAME_SIZE> {
+    let [id_low, id_high] = register.to_le_bytes();
+    encode(COMMAND_SET, &[id_low, id_high, 0x00, value])
+}
+
+/// Encodes a get command of a register.
+pub(crate) fn encode_get(register: u16) -> String<HEX_FRAME_SIZE> {
+    let [id_low, id_high] = register.to_le_bytes();
+    encode(COMMAND_GET, &[id_low, id_high, 0x00])
+}
+
+/// Encodes a message, `payload` has to fit into [`MAX_PAYLOAD_SIZE`].
+pub(crate) fn encode(command: u8, payload: &[u8]) -> String<HEX_FRAME_SIZE> {
+    let sum = payload.iter().fold(command, |sum, byte| sum.wrapping_add(*byte));
+    let mut frame = String::new();
+    // the commands sent are far below the capacity
+    let _ = write!(frame, ":{:X}", command);
+    for byte in payload.iter().chain(core::iter::once(&CHECKSUM_TARGET.wrapping_sub(sum))) {
+        let _ = write!(frame, "{:02X}", byte);
+    }
+    let _ = frame.push('\n');
+    frame
+}
+
+/// Decodes a message without the leading `:` and the trailing `\n`.
+pub(crate) fn decode(line: &str) -> Option<HexMessage> {
+    let line = line.trim_end_matches('\r');
+    let (command, data) = line.split_at_checked(1)?;
+    let command = u8::from_str_radix(command, 16).ok()?;
+    if data.len() % 2 != 0 || data.is_empty() {
+        return None;
+    }
+    let mut payload = Vec::new();
+    let mut sum = command;
+    for i in (0..data.len()).step_by(2) {
+        let byte = u8::from_str_radix(data.get(i..i + 2)?, 16).ok()?;
+        sum = sum.wrapping_add(byte);
+        payload.push(byte).ok()?;
+    }
+    if sum != CHECKSUM_TARGET {
+        warn!("VE.Hex> Invalid checksum in ':{}'", line);
+        crate::health::HEALTH.count(|counters| counters.checksum_errors += 1);
+        return None;
+    }
+    payload.pop();
+    Some(HexMessage { command, payload })
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct LoadSwitchRequest {
+    pub output: LoadOutput,
+    pub origin: CommandOrigin,
+}
+
+/// Request to switch the load output of the charger, see [`switch_load`].
+pub struct LoadSwitch {
+    pub(crate) requested: Signal<NoopRawMutex, LoadSwitchRequest>,
+    pub(crate) done: Signal<NoopRawMutex, Result<(), HexError>>,
+}
+
+impl LoadSwitch {
+    pub const fn new() -> Self {
+        Self {
+            requested: Signal::new(),
+            done: Signal::new(),
+        }
+    }
+}
+
+impl Default for LoadSwitch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Switches the load output through the VE.Direct runner, returns once the charger confirmed it.
+///
+/// `origin` is recorded in the audit log, if the runner has one.
+pub async fn switch_load(switch: &LoadSwitch, output: LoadOutput, origin: CommandOrigin) -> Result<(), HexError> {
+    switch.done.reset();
+    switch.requested.signal(LoadSwitchRequest { output, origin });
+    switch.done.wait().await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_encode_set() {
+        assert_eq!(encode_set(LOAD_OUTPUT_CONTROL, LoadOutput::On as u8).as_str(), ":8ABED0004B1\n");
+        assert_eq!(encode_set(LOAD_OUTPUT_CONTROL, LoadOutput::Off as u8).as_str(), ":8ABED0000B5\n");
+    }
+
+    #[test]
+    fn check_load_output_names() {
+        for output in [LoadOutput::Off, LoadOutput::Auto, LoadOutput::On] {
+            assert_eq!(LoadOutput::parse(output.as_str()), Some(output));
+        }
+        assert_eq!(LoadOutput::parse("toggle"), None);
+    }
+
+    #[test]
+    fn check_decode() {
+        let message = decode("8ABED0004B1").unwrap();
+        assert_eq!(message.command, 0x8);
+        assert_eq!(message.payload.as_slice(), &[0xAB, 0xED, 0x00, 0x04]);
+        assert_eq!(message.set_result(LOAD_OUTPUT_CONTROL), Some(Ok(())));
+        assert_eq!(message.set_result(0x0100), None);
+
+        // ping response with the firmware version
+        assert!(decode("51641F9").is_some());
+        assert_eq!(decode("8ABED0004B2"), None);
+        assert_eq!(decode("8ABED0004B"), None);
+        assert_eq!(decode(""), None);
+    }
+
+    #[test]
+    fn check_get_value() {
+        assert_eq!(encode_get(0x1051).as_str(), ":7511000ED\n");
+        let response = encode(COMMAND_GET, &[0x51, 0x10, 0x00, 0x2A, 0x01]);
+        let message = decode(response.trim_start_matches(':').trim_end()).unwrap();
+        assert_eq!(message.get_value(0x1051), Some(Ok(&[0x2A, 0x01][..])));
+        assert_eq!(message.get_value(0x1052), None);
+        assert_eq!(message.set_result(0x1051), None);
+        let message = decode(encode(COMMAND_GET, &[0x51, 0x10, 0x01]).trim_start_matches(':').trim_end()).unwrap();
+        assert_eq!(message.get_value(0x1051), Some(Err(HexError::UnknownRegister)));
+    }
+
+    #[test]
+    fn check_set_result_flags() {
+        let message = decode("8ABED0204AF").unwrap();
+        assert_eq!(message.set_result(LOAD_OUTPUT_CONTROL), Some(Err(HexError::NotSupported)));
+    }
+}
@@ -0,0 +1,299 @@
+//! The daily history of the MPPT charger, read with the HEX protocol.
+//!
+//! The charger keeps a record of the last 30 days in the registers `0x1050 + days ago`, the
+//! yield, the battery extremes and the time in each charge state. The VE.Direct runner reads
+//! the completed days once per [`HISTORY_READ_INTERVAL`], newest first, and stops at the
+//! first day it already published, so after a reset the whole history is uploaded again and
+//! the backend gets the days the monitor was off. The backend keys them by `day_sequence`.
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    proto::bt_::solar_,
+    sensor::ve_direct::hex::{HEX_RESPONSE_TIMEOUT, HexMessage},
+};
+
+/// Register of today, the completed days follow.
+pub const HISTORY_DAY_REGISTER: u16 = 0x1050;
+/// The completed days the charger keeps.
+pub const HISTORY_DAYS: u8 = 30;
+pub const HISTORY_READ_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const HISTORY_RECORD_SIZE: usize = 34;
+
+/// A completed day of the charger, in the units of the proto.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HistoryDay {
+    /// Day number of the charger, increments every day.
+    pub sequence: u16,
+    /// 1 for the day before `at`.
+    pub days_ago: u8,
+    pub solar_yield_wh: u32,
+    pub consumed_wh: u32,
+    pub max_battery_voltage_mv: i32,
+    pub min_battery_voltage_mv: i32,
+    pub max_power_w: u32,
+    pub max_battery_current_ma: i32,
+    pub max_panel_voltage_mv: i32,
+    pub bulk_minutes: u16,
+    pub absorption_minutes: u16,
+    pub float_minutes: u16,
+    /// The `ERR` codes of the day, latest first, 0 for none.
+    pub errors: [u8; 4],
+    /// When the record was read.
+    pub at: Instant,
+}
+
+impl HistoryDay {
+    /// Decodes the value of a history day register, `None` if it is no history record.
+    pub(crate) fn decode(days_ago: u8, value: &[u8], at: Instant) -> Option<Self> {
+        let record: &[u8; HISTORY_RECORD_SIZE] = value.get(..HISTORY_RECORD_SIZE)?.try_into().ok()?;
+        let un16 = |offset: usize| u16::from_le_bytes([record[offset], record[offset + 1]]);
+        let un32 = |offset: usize| u32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+        Some(Self {
+            sequence: un16(32),
+            days_ago,
+            // 0.01 kWh, 0.01 V and 0.1 A
+            solar_yield_wh: un32(1).saturating_mul(10),
+            consumed_wh: un32(5).saturating_mul(10),
+            max_battery_voltage_mv: un16(9) as i32 * 10,
+            min_battery_voltage_mv: un16(11) as i32 * 10,
+            max_power_w: un32(24),
+            max_battery_current_ma: un16(28) as i32 * 100,
+            max_panel_voltage_mv: un16(30) as i32 * 10,
+            bulk_minutes: un16(18),
+            absorption_minutes: un16(20),
+            float_minutes: un16(22),
+            errors: [record[14], record[15], record[16], record[17]],
+            at,
+        })
+    }
+
+    pub(crate) fn to_event(self) -> solar_::HistoryDayEvent {
+        solar_::HistoryDayEvent {
+            day_sequence: self.sequence as u32,
+            days_ago: self.days_ago as u32,
+            solar_yield: self.solar_yield_wh,
+            consumed: self.consumed_wh,
+            max_battery_voltage: self.max_battery_voltage_mv,
+            min_battery_voltage: self.min_battery_voltage_mv,
+            max_power: self.max_power_w,
+            max_battery_current: self.max_battery_current_ma,
+            max_panel_voltage: self.max_panel_voltage_mv,
+            bulk_minutes: self.bulk_minutes as u32,
+            absorption_minutes: self.absorption_minutes as u32,
+            float_minutes: self.float_minutes as u32,
+            errors: self.errors.iter().filter(|code| **code != 0).map(|code| *code as u32).collect(),
+        }
+    }
+}
+
+/// The history days read, from the VE.Direct runner to the cloud runner.
+pub struct DailyHistory {
+    days: Channel<NoopRawMutex, HistoryDay, { HISTORY_DAYS as usize }>,
+}
+
+impl DailyHistory {
+    pub const fn new() -> Self {
+        Self { days: Channel::new() }
+    }
+
+    pub(crate) fn publish(&self, day: HistoryDay) {
+        debug!("VE.History> Day {} ({} days ago): {} Wh", day.sequence, day.days_ago, day.solar_yield_wh);
+        if self.days.try_send(day).is_err() {
+            warn!("VE.History> Queue full => day {} not uploaded", day.sequence);
+        }
+    }
+
+    pub(crate) fn take(&self) -> Option<HistoryDay> {
+        self.days.try_receive().ok()
+    }
+}
+
+impl Default for DailyHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Register of the day `days_ago`.
+pub(crate) fn history_register(days_ago: u8) -> u16 {
+    HISTORY_DAY_REGISTER + days_ago as u16
+}
+
+/// The reads of the history days, one get command at a time, see the module documentation.
+#[derive(Debug)]
+pub(crate) struct HistoryReader {
+    next_read: Instant,
+    /// The day waiting for the charger's response and its deadline.
+    pending: Option<(u8, Instant)>,
+    /// The newest day of the running read.
+    newest: Option<u16>,
+    /// The newest day published, the reads stop at it.
+    published: Option<u16>,
+}
+
+impl Default for HistoryReader {
+    /// The first read is due at once.
+    fn default() -> Self {
+        Self {
+            next_read: Instant::MIN,
+            pending: None,
+            newest: None,
+            published: None,
+        }
+    }
+}
+
+impl HistoryReader {
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Starts a read once it is due, returns the register to get.
+    pub fn start(&mut self, now: Instant) -> Option<u16> {
+        if self.pending.is_some() || now < self.next_read {
+            return None;
+        }
+        self.request(1, now)
+    }
+
+    /// Feeds the HEX message received since the request, returns the day read and the next register to get.
+    pub fn update(&mut self, response: Option<&HexMessage>, now: Instant) -> (Option<HistoryDay>, Option<u16>) {
+        let Some((days_ago, deadline)) = self.pending else {
+            return (None, None);
+        };
+        let value = match response.and_then(|message| message.get_value(history_register(days_ago))) {
+            Some(Ok(value)) => value,
+            Some(Err(e)) => {
+                warn!("VE.History> Reading day -{} failed: {:?}", days_ago, e);
+                self.finish(now);
+                return (None, None);
+            }
+            None if now < deadline => return (None, None),
+            None => {
+                warn!("VE.History> No response for day -{}", days_ago);
+                self.finish(now);
+                return (None, None);
+            }
+        };
+        let Some(day) = HistoryDay::decode(days_ago, value, now) else {
+            warn!("VE.History> Invalid record of day -{}", days_ago);
+            self.finish(now);
+            return (None, None);
+        };
+        if self.published.is_some_and(|published| (day.sequence.wrapping_sub(published) as i16) <= 0) {
+            self.finish(now);
+            return (None, None);
+        }
+        self.newest.get_or_insert(day.sequence);
+        if days_ago < HISTORY_DAYS {
+            (Some(day), self.request(days_ago + 1, now))
+        } else {
+            self.finish(now);
+            (Some(day), None)
+        }
+    }
+
+    fn request(&mut self, days_ago: u8, now: Instant) -> Option<u16> {
+        self.pending = Some((days_ago, now + HEX_RESPONSE_TIMEOUT));
+        Some(history_register(days_ago))
+    }
+
+    /// Ends the running read, also where the command could not be sent.
+    pub fn finish(&mut self, now: Instant) {
+        self.pending = None;
+        self.next_read = now + HISTORY_READ_INTERVAL;
+        if let Some(newest) = self.newest.take() {
+            self.published = Some(newest);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::sensor::ve_direct::hex::{decode, encode};
+
+    fn record(sequence: u16, yield_centi_kwh: u32) -> [u8; HISTORY_RECORD_SIZE] {
+        let mut record = [0u8; HISTORY_RECORD_SIZE];
+        record[1..5].copy_from_slice(&yield_centi_kwh.to_le_bytes());
+        record[5..9].copy_from_slice(&12u32.to_le_bytes());
+        record[9..11].copy_from_slice(&1432u16.to_le_bytes());
+        record[11..13].copy_from_slice(&1218u16.to_le_bytes());
+        record[14] = 17;
+        record[18..20].copy_from_slice(&95u16.to_le_bytes());
+        record[20..22].copy_from_slice(&60u16.to_le_bytes());
+        record[22..24].copy_from_slice(&240u16.to_le_bytes());
+        record[24..28].copy_from_slice(&312u32.to_le_bytes());
+        record[28..30].copy_from_slice(&187u16.to_le_bytes());
+        record[30..32].copy_from_slice(&4321u16.to_le_bytes());
+        record[32..34].copy_from_slice(&sequence.to_le_bytes());
+        record
+    }
+
+    /// The get response of the charger as received, without `:` and `\n`.
+    fn response(days_ago: u8, value: &[u8]) -> HexMessage {
+        let [id_low, id_high] = history_register(days_ago).to_le_bytes();
+        let mut payload = std::vec![id_low, id_high, 0x00];
+        payload.extend_from_slice(value);
+        let frame = encode(0x7, &payload);
+        decode(frame.trim_start_matches(':').trim_end()).unwrap()
+    }
+
+    #[test]
+    fn check_decode_record() {
+        let day = HistoryDay::decode(1, &record(412, 123), Instant::from_secs(5)).unwrap();
+        assert_eq!((day.sequence, day.solar_yield_wh, day.consumed_wh), (412, 1230, 120));
+        assert_eq!((day.max_battery_voltage_mv, day.min_battery_voltage_mv), (14_320, 12_180));
+        assert_eq!((day.max_power_w, day.max_battery_current_ma, day.max_panel_voltage_mv), (312, 18_700, 43_210));
+        assert_eq!((day.bulk_minutes, day.absorption_minutes, day.float_minutes), (95, 60, 240));
+        let event = day.to_event();
+        assert_eq!(event.errors.as_slice(), &[17]);
+        assert_eq!(event.days_ago, 1);
+        assert_eq!(HistoryDay::decode(1, &record(412, 123)[..20], Instant::from_secs(5)), None);
+    }
+
+    #[test]
+    fn check_read_until_published() {
+        let mut reader = HistoryReader::default();
+        let mut now = Instant::from_secs(10);
+        assert_eq!(reader.start(now), Some(0x1051));
+        assert_eq!(reader.start(now), None);
+        // another message, e.g. an async update of the charger
+        assert_eq!(reader.update(Some(&decode("8ABED0004B1").unwrap()), now), (None, None));
+        let (day, next) = reader.update(Some(&response(1, &record(412, 123))), now);
+        assert_eq!((day.unwrap().sequence, next), (412, Some(0x1052)));
+        let (day, next) = reader.update(Some(&response(2, &record(411, 80))), now);
+        assert_eq!((day.unwrap().sequence, next), (411, Some(0x1053)));
+        // the charger keeps fewer days
+        now += Duration::from_secs(1);
+        let unknown = decode(encode(0x7, &[0x53, 0x10, 0x01]).trim_start_matches(':').trim_end()).unwrap();
+        assert_eq!(reader.update(Some(&unknown), now), (None, None));
+        assert!(!reader.is_pending());
+
+        // a day later only the new day
+        assert_eq!(reader.start(now + Duration::from_secs(60)), None);
+        now += HISTORY_READ_INTERVAL;
+        assert_eq!(reader.start(now), Some(0x1051));
+        let (day, next) = reader.update(Some(&response(1, &record(413, 99))), now);
+        assert_eq!((day.unwrap().sequence, next), (413, Some(0x1052)));
+        assert_eq!(reader.update(Some(&response(2, &record(412, 123))), now), (None, None));
+        assert!(!reader.is_pending());
+    }
+
+    #[test]
+    fn check_read_timeout() {
+        let mut reader = HistoryReader::default();
+        let now = Instant::from_secs(10);
+        assert_eq!(reader.start(now), Some(0x1051));
+        assert_eq!(reader.update(None, now + Duration::from_secs(1)), (None, None));
+        assert!(reader.is_pending());
+        assert_eq!(reader.update(None, now + HEX_RESPONSE_TIMEOUT), (None, None));
+        assert!(!reader.is_pending());
+        assert_eq!(reader.start(now + HISTORY_READ_INTERVAL), None);
+        assert_eq!(reader.start(now + HEX_RESPONSE_TIMEOUT + HISTORY_READ_INTERVAL), Some(0x1051));
+    }
+}
@@ -0,0 +1,249 @@
+//! A UART shared by several auxiliary devices, e.g. on the expansion port.
+//!
+//! The devices are told apart by their line discipline: the runner reads the lines
+//! and hands each one to the first [`Port`] whose [`LineDiscipline`] claims it, e.g.
+//! the `$` sentences of a GPS mouse. Writes take turns, a line is never interleaved
+//! with the one of another port.
+
+use core::cell::Cell;
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, mutex::Mutex};
+use embedded_io_async::{Read, Write};
+use heapless::Vec;
+
+pub const MAX_PORTS: usize = 3;
+pub const SHARED_LINE_SIZE: usize = 128;
+const PORT_QUEUE_SIZE: usize = 4;
+
+/// A received line without the line ending.
+pub type SharedLine = Vec<u8, SHARED_LINE_SIZE>;
+
+/// Whether a received line belongs to a port.
+pub type LineDiscipline = fn(&[u8]) -> bool;
+
+/// NMEA 0183 sentences of a GPS receiver.
+pub fn nmea(line: &[u8]) -> bool {
+    line.starts_with(b"$")
+}
+
+/// Every line, for the port registered last, e.g. the console.
+pub fn any_line(_line: &[u8]) -> bool {
+    true
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SharedUartError {
+    Io,
+}
+
+pub struct State<Tx: Write> {
+    ports: [Channel<NoopRawMutex, SharedLine, PORT_QUEUE_SIZE>; MAX_PORTS],
+    disciplines: [Cell<Option<LineDiscipline>>; MAX_PORTS],
+    registered: Cell<usize>,
+    tx: Option<Mutex<NoopRawMutex, Tx>>,
+}
+
+impl<Tx: Write> State<Tx> {
+    pub fn new() -> Self {
+        Self {
+            ports: [const { Channel::new() }; MAX_PORTS],
+            disciplines: [const { Cell::new(None) }; MAX_PORTS],
+            registered: Cell::new(0),
+            tx: None,
+        }
+    }
+}
+
+impl<Tx: Write> Default for State<Tx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn new<'a, Rx: Read, Tx: Write>(state: &'a mut State<Tx>, rx: Rx, tx: Tx) -> (Runner<'a, Rx, Tx>, SharedUart<'a, Tx>) {
+    state.tx = Some(Mutex::new(tx));
+    let state: &'a State<Tx> = state;
+    (
+        Runner {
+            state,
+            rx,
+            line: Vec::new(),
+            overflow: false,
+        },
+        SharedUart { state },
+    )
+}
+
+/// Registers the ports of the shared UART.
+pub struct SharedUart<'a, Tx: Write> {
+    state: &'a State<Tx>,
+}
+
+impl<'a, Tx: Write> SharedUart<'a, Tx> {
+    /// A port for the lines `discipline` claims, `None` once [`MAX_PORTS`] exist.
+    ///
+    /// The disciplines are asked in registration order, so register the catch all port last.
+    pub fn port(&self, discipline: LineDiscipline) -> Option<Port<'a, Tx>> {
+        let index = self.state.registered.get();
+        if index >= MAX_PORTS {
+            return None;
+        }
+        self.state.disciplines[index].set(Some(discipline));
+        self.state.registered.set(index + 1);
+        Some(Port { state: self.state, index })
+    }
+}
+
+pub struct Port<'a, Tx: Write> {
+    state: &'a State<Tx>,
+    index: usize,
+}
+
+impl<Tx: Write> Port<'_, Tx> {
+    pub async fn read_line(&self) -> SharedLine {
+        self.state.ports[self.index].receive().await
+    }
+
+    /// Writes `data` while holding the UART, followed by `\r\n`.
+    pub async fn write_line(&self, data: &[u8]) -> Result<(), SharedUartError> {
+        // set by `new`, a port only exists after it
+        let Some(tx) = &self.state.tx else {
+            return Err(SharedUartError::Io);
+        };
+        let mut tx = tx.lock().await;
+        tx.write_all(data).await.map_err(|_| SharedUartError::Io)?;
+        tx.write_all(b"\r\n").await.map_err(|_| SharedUartError::Io)?;
+        tx.flush().await.map_err(|_| SharedUartError::Io)
+    }
+}
+
+pub struct Runner<'a, Rx: Read, Tx: Write> {
+    state: &'a State<Tx>,
+    rx: Rx,
+    line: SharedLine,
+    /// The current line did not fit, it is dropped up to its end.
+    overflow: bool,
+}
+
+impl<Rx: Read, Tx: Write> Runner<'_, Rx, Tx> {
+    pub async fn run(mut self) {
+        let mut buffer = [0u8; 32];
+        loop {
+            match self.rx.read(&mut buffer).await {
+                Ok(n) => {
+                    for byte in &buffer[..n] {
+                        self.push(*byte);
+                    }
+                }
+                Err(_e) => warn!("Shared UART read error"),
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        match byte {
+            b'\r' => {}
+            b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                if core::mem::take(&mut self.overflow) {
+                    warn!("Shared UART line longer than {} bytes => dropped", SHARED_LINE_SIZE);
+                } else if !line.is_empty() {
+                    self.dispatch(line);
+                }
+            }
+            _ => {
+                if self.line.push(byte).is_err() {
+                    self.overflow = true;
+                }
+            }
+        }
+    }
+
+    fn dispatch(&self, line: SharedLine) {
+        let registered = self.state.registered.get();
+        let claimed = (0..registered).find(|&index| self.state.disciplines[index].get().is_some_and(|claims| claims(&line)));
+        match claimed {
+            Some(index) => {
+                if self.state.ports[index].try_send(line).is_err() {
+                    warn!("Shared UART port {} not reading => line dropped", index);
+                }
+            }
+            None => debug!("Shared UART line of {} bytes not claimed", line.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::tests::mock_stream::{MockStream, mock_stream};
+    use embassy_futures::{
+        join::join,
+        select::{Either, select},
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn console(line: &[u8]) -> bool {
+        !nmea(line)
+    }
+
+    #[tokio::test]
+    async fn check_lines_are_dispatched_by_discipline() {
+        let (rx, mut device) = mock_stream();
+        let (tx, _) = mock_stream();
+        let mut state = State::<MockStream>::new();
+        let (runner, uart) = new(&mut state, rx, tx);
+        let gps = uart.port(nmea).unwrap();
+        let console = uart.port(console).unwrap();
+
+        let test = async {
+            device.write_all(b"$GPGGA,1\r\nhelp\r\n$GPRMC,2\r\n").await.unwrap();
+            let gps_lines = (gps.read_line().await, gps.read_line().await);
+            (gps_lines, console.read_line().await)
+        };
+        let Either::Second(((first, second), command)) = select(runner.run(), test).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(first.as_slice(), b"$GPGGA,1");
+        assert_eq!(second.as_slice(), b"$GPRMC,2");
+        assert_eq!(command.as_slice(), b"help");
+    }
+
+    #[tokio::test]
+    async fn check_overlong_line_is_dropped() {
+        let (rx, mut device) = mock_stream();
+        let (tx, _) = mock_stream();
+        let mut state = State::<MockStream>::new();
+        let (runner, uart) = new(&mut state, rx, tx);
+        let port = uart.port(any_line).unwrap();
+
+        let test = async {
+            device.write_all(&[b'x'; SHARED_LINE_SIZE + 1]).await.unwrap();
+            device.write_all(b"\nok\n").await.unwrap();
+            port.read_line().await
+        };
+        let Either::Second(line) = select(runner.run(), test).await else {
+            unreachable!("runner never returns");
+        };
+        assert_eq!(line.as_slice(), b"ok");
+    }
+
+    #[tokio::test]
+    async fn check_writes_are_not_interleaved() {
+        let (rx, _device) = mock_stream();
+        let (tx, mut wire) = mock_stream();
+        let mut state = State::<MockStream>::new();
+        let (_runner, uart) = new(&mut state, rx, tx);
+        let gps = uart.port(nmea).unwrap();
+        let console = uart.port(any_line).unwrap();
+        assert!(uart.port(any_line).is_some());
+        assert!(uart.port(any_line).is_none());
+
+        let (first, second) = join(gps.write_line(b"$PMTK220,1000*1F"), console.write_line(b"> ready")).await;
+        assert_eq!((first, second), (Ok(()), Ok(())));
+        let mut written = [0u8; 27];
+        wire.read_exact(&mut written).await.unwrap();
+        assert_eq!(&written, b"$PMTK220,1000*1F\r\n> ready\r\n");
+    }
+}
@@ -0,0 +1,223 @@
+//! Access control of the local maintenance shell.
+//!
+//! A session starts locked. It is unlocked with the configured PIN, and locks again
+//! after [`ShellPolicy::session_timeout`] without a command. Wrong PINs lock the shell
+//! out for a while, so the PIN can not be guessed over the USB port. Without a PIN
+//! only the read only commands are available, none of the boards has a button for
+//! a physical unlock.
+//! The [`console::Console`] runs the commands on a serial terminal.
+
+pub mod attrace;
+pub mod console;
+pub mod identify;
+pub mod line;
+
+use embassy_time::{Duration, Instant};
+use heapless::String;
+
+use crate::storage::ConfigKey;
+
+pub const SHELL_PIN_SIZE: usize = 8;
+/// The PIN of [`ShellPolicy::pin`], without one the shell stays read only.
+pub const SHELL_PIN: ConfigKey<String<SHELL_PIN_SIZE>> = ConfigKey::new("shell", "pin");
+
+/// What a shell command can do, the access needed grows with it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandClass {
+    /// Shows status and config, e.g. the last readings.
+    ReadOnly,
+    /// Changes the config.
+    Config,
+    /// Can break the unit, e.g. the AT passthrough or erasing the store.
+    Dangerous,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShellError {
+    /// The session is not unlocked (anymore).
+    Locked,
+    WrongPin,
+    /// Too many wrong PINs, no attempt is accepted until the lockout ended.
+    LockedOut,
+    /// No PIN is configured, the session can not be unlocked.
+    NoPin,
+    /// The command class is disabled in this build.
+    Disabled,
+    /// Writing the output of the command failed.
+    Output,
+    UnknownCommand,
+    /// The arguments of the command are missing or invalid.
+    Usage,
+    /// The board failed to run the command.
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ShellPolicy {
+    pub pin: Option<String<SHELL_PIN_SIZE>>,
+    /// Idle time after which an unlocked session locks again.
+    pub session_timeout: Duration,
+    pub max_failed_attempts: u8,
+    pub lockout: Duration,
+    /// Whether [`CommandClass::Dangerous`] commands are available at all.
+    pub allow_dangerous: bool,
+}
+
+impl Default for ShellPolicy {
+    fn default() -> Self {
+        Self {
+            pin: None,
+            session_timeout: Duration::from_secs(5 * 60),
+            max_failed_attempts: 3,
+            lockout: Duration::from_secs(60),
+            // production units are release builds
+            allow_dangerous: cfg!(debug_assertions),
+        }
+    }
+}
+
+/// Session state of the shell, see the module documentation.
+#[derive(Debug)]
+pub struct ShellAccess {
+    policy: ShellPolicy,
+    unlocked_until: Option<Instant>,
+    failed_attempts: u8,
+    locked_out_until: Option<Instant>,
+}
+
+impl ShellAccess {
+    pub fn new(policy: ShellPolicy) -> Self {
+        Self {
+            policy,
+            unlocked_until: None,
+            failed_attempts: 0,
+            locked_out_until: None,
+        }
+    }
+
+    pub fn is_unlocked(&self, now: Instant) -> bool {
+        self.unlocked_until.is_some_and(|until| now < until)
+    }
+
+    pub fn unlock_with_pin(&mut self, pin: &str, now: Instant) -> Result<(), ShellError> {
+        if self.locked_out_until.is_some_and(|until| now < until) {
+            return Err(ShellError::LockedOut);
+        }
+        let expected = self.policy.pin.as_ref().ok_or(ShellError::NoPin)?;
+        if !pin_matches(expected.as_bytes(), pin.as_bytes()) {
+            self.failed_attempts = self.failed_attempts.saturating_add(1);
+            warn!("Shell> wrong PIN ({} of {})", self.failed_attempts, self.policy.max_failed_attempts);
+            if self.failed_attempts >= self.policy.max_failed_attempts {
+                warn!("Shell> locked out for {} s", self.policy.lockout.as_secs());
+                self.failed_attempts = 0;
+                self.locked_out_until = Some(now + self.policy.lockout);
+                return Err(ShellError::LockedOut);
+            }
+            return Err(ShellError::WrongPin);
+        }
+        self.failed_attempts = 0;
+        self.unlock(now);
+        Ok(())
+    }
+
+    /// Checks whether a command of `class` may run, an allowed command extends the session.
+    ///
+    /// Without a PIN the read only commands need no unlock.
+    pub fn authorize(&mut self, class: CommandClass, now: Instant) -> Result<(), ShellError> {
+        if class == CommandClass::Dangerous && !self.policy.allow_dangerous {
+            return Err(ShellError::Disabled);
+        }
+        if class != CommandClass::ReadOnly || self.policy.pin.is_some() {
+            if !self.is_unlocked(now) {
+                self.unlocked_until = None;
+                return Err(ShellError::Locked);
+            }
+            self.unlocked_until = Some(now + self.policy.session_timeout);
+        }
+        Ok(())
+    }
+
+    pub fn lock(&mut self) {
+        self.unlocked_until = None;
+    }
+
+    fn unlock(&mut self, now: Instant) {
+        info!("Shell> unlocked");
+        self.unlocked_until = Some(now + self.policy.session_timeout);
+    }
+}
+
+/// Compares all bytes, the time does not tell how many leading digits were right.
+fn pin_matches(expected: &[u8], pin: &[u8]) -> bool {
+    let mut difference = (expected.len() != pin.len()) as u8;
+    for (index, byte) in expected.iter().enumerate() {
+        difference |= byte ^ pin.get(index).copied().unwrap_or(0);
+    }
+    difference == 0
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn policy() -> ShellPolicy {
+        ShellPolicy {
+            pin: Some("4711".try_into().unwrap()),
+            allow_dangerous: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_pin_unlock_and_session_timeout() {
+        let start = Instant::from_secs(100);
+        let mut access = ShellAccess::new(policy());
+        assert_eq!(access.authorize(CommandClass::ReadOnly, start), Err(ShellError::Locked));
+        assert_eq!(access.unlock_with_pin("471", start), Err(ShellError::WrongPin));
+        access.unlock_with_pin("4711", start).unwrap();
+
+        // every command extends the session
+        let later = start + Duration::from_secs(4 * 60);
+        access.authorize(CommandClass::Config, later).unwrap();
+        access.authorize(CommandClass::Dangerous, later + Duration::from_secs(4 * 60)).unwrap();
+        assert_eq!(access.authorize(CommandClass::Config, later + Duration::from_secs(10 * 60)), Err(ShellError::Locked));
+    }
+
+    #[test]
+    fn check_lockout_after_wrong_pins() {
+        let start = Instant::from_secs(100);
+        let mut access = ShellAccess::new(policy());
+        assert_eq!(access.unlock_with_pin("0000", start), Err(ShellError::WrongPin));
+        assert_eq!(access.unlock_with_pin("1111", start), Err(ShellError::WrongPin));
+        assert_eq!(access.unlock_with_pin("2222", start), Err(ShellError::LockedOut));
+        assert_eq!(access.unlock_with_pin("4711", start + Duration::from_secs(30)), Err(ShellError::LockedOut));
+        access.unlock_with_pin("4711", start + Duration::from_secs(61)).unwrap();
+    }
+
+    #[test]
+    fn check_without_pin() {
+        let start = Instant::from_secs(100);
+        let mut access = ShellAccess::new(ShellPolicy::default());
+        assert_eq!(access.unlock_with_pin("4711", start), Err(ShellError::NoPin));
+        assert!(!access.is_unlocked(start));
+        access.authorize(CommandClass::ReadOnly, start).unwrap();
+        assert_eq!(access.authorize(CommandClass::Config, start), Err(ShellError::Locked));
+    }
+
+    #[test]
+    fn check_dangerous_disabled() {
+        let start = Instant::from_secs(100);
+        let mut access = ShellAccess::new(ShellPolicy {
+            allow_dangerous: false,
+            ..policy()
+        });
+        access.unlock_with_pin("4711", start).unwrap();
+        assert_eq!(access.authorize(CommandClass::Dangerous, start), Err(ShellError::Disabled));
+        // read only commands need no unlock without a PIN
+        let mut open = ShellAccess::new(ShellPolicy::default());
+        open.authorize(CommandClass::ReadOnly, start).unwrap();
+    }
+}
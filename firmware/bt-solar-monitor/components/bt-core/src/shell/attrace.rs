@@ -0,0 +1,76 @@
+//! `attrace dump` and `attrace clear`, the AT trace over the shell.
+//!
+//! The dump is the CSV of [`crate::at::trace::AT_TRACE`], one line per command with the
+//! timestamp, the command, the result and the duration. Clear it, reproduce the failure
+//! and dump it again to get just the failing sequence.
+
+use core::fmt::Write;
+
+use embassy_time::Instant;
+
+use crate::{
+    at::trace::AT_TRACE,
+    shell::{CommandClass, ShellAccess, ShellError},
+};
+
+pub const ATTRACE_COMMAND: &str = "attrace";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AtTraceCommand {
+    Dump,
+    Clear,
+}
+
+impl AtTraceCommand {
+    /// Parses the arguments after [`ATTRACE_COMMAND`].
+    pub fn parse(args: &str) -> Option<Self> {
+        match args.trim() {
+            "dump" => Some(AtTraceCommand::Dump),
+            "clear" => Some(AtTraceCommand::Clear),
+            _ => None,
+        }
+    }
+
+    pub fn class(self) -> CommandClass {
+        match self {
+            AtTraceCommand::Dump => CommandClass::ReadOnly,
+            // drops what another tech may still want to capture
+            AtTraceCommand::Clear => CommandClass::Config,
+        }
+    }
+
+    pub fn run(self, access: &mut ShellAccess, now: Instant, out: &mut impl Write) -> Result<(), ShellError> {
+        access.authorize(self.class(), now)?;
+        match self {
+            AtTraceCommand::Dump => AT_TRACE.write_csv(out).map_err(|_| ShellError::Output),
+            AtTraceCommand::Clear => {
+                AT_TRACE.clear();
+                writeln!(out, "AT trace cleared").map_err(|_| ShellError::Output)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::shell::ShellPolicy;
+
+    #[test]
+    fn check_parse() {
+        assert_eq!(AtTraceCommand::parse("dump"), Some(AtTraceCommand::Dump));
+        assert_eq!(AtTraceCommand::parse(" clear\r"), Some(AtTraceCommand::Clear));
+        assert_eq!(AtTraceCommand::parse("erase"), None);
+    }
+
+    #[test]
+    fn check_clear_needs_unlock() {
+        let now = Instant::from_secs(100);
+        let mut access = ShellAccess::new(ShellPolicy::default());
+        let mut out = std::string::String::new();
+        AtTraceCommand::Dump.run(&mut access, now, &mut out).unwrap();
+        assert!(out.starts_with(crate::at::trace::CSV_HEADER));
+        assert_eq!(AtTraceCommand::Clear.run(&mut access, now, &mut out), Err(ShellError::Locked));
+    }
+}
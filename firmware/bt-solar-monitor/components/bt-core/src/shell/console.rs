@@ -0,0 +1,304 @@
+//! The maintenance console, the commands of the shell on a serial terminal.
+//!
+//! The board feeds the bytes of the terminal, e.g. of a USB CDC port, to [`Console::input`]
+//! and sends the output back. The console edits the lines, authorizes the commands with
+//! its [`ShellAccess`] and runs them, the [`ShellBackend`] of the board does what needs
+//! its peripherals. `help` lists the commands.
+//!
+//! `bridge` only asks for the bridge to the module, see [`Console::take_bridge_request`],
+//! the port to bridge belongs to the board.
+
+#![allow(async_fn_in_trait)]
+
+use core::fmt::Write;
+
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    activity::ACTIVITY,
+    at::{AtCommandResponse, AtError, bridge::BRIDGE_ESCAPE},
+    health::HEALTH,
+    identify::{IDENTIFY, write_banner},
+    log_ring::LOG_RING,
+    shell::{
+        CommandClass, ShellAccess, ShellError, ShellPolicy,
+        attrace::{ATTRACE_COMMAND, AtTraceCommand},
+        identify::{IDENTIFY_COMMAND, IdentifyCommand},
+        line::LineEditor,
+    },
+    solar_monitor::remote_at::DEFAULT_RUN_AT_TIMEOUT,
+};
+
+pub const SHELL_LINE_SIZE: usize = 80;
+const PROMPT: &str = "> ";
+const HELP: &str = "\
+help                 this list
+unlock <pin>         unlock the session
+lock                 lock the session
+status               uptime, reset reason and health counters
+config               the stored config
+log                  the captured warnings and errors, defmt encoded
+attrace dump|clear   the trace of the AT commands
+identify [min|off]   flash the status LED
+upload               upload the partial batch now (unlocked)
+at <command>         run an AT command (unlocked, dangerous)
+format               erase the store in flash and reset (unlocked, dangerous)
+bridge               the port to the modem UART until Ctrl-] (unlocked, dangerous)
+";
+
+/// What the console needs of the board.
+pub trait ShellBackend {
+    /// Writes the config, one `key value` line each.
+    async fn write_config(&mut self, out: &mut impl Write) -> core::fmt::Result;
+    /// Runs `command` on the cellular module, next to the cloud runner.
+    async fn run_at(&mut self, command: &str, timeout: Duration) -> Result<AtCommandResponse, AtError>;
+    /// Uploads the partial batch, e.g. to check the backend after an installation.
+    async fn trigger_upload(&mut self);
+    /// Erases the store in flash, the board resets once done.
+    async fn format_flash(&mut self) -> Result<(), ShellError>;
+}
+
+pub struct Console<B: ShellBackend> {
+    access: ShellAccess,
+    editor: LineEditor<SHELL_LINE_SIZE>,
+    backend: B,
+    firmware: &'static str,
+    bridge_requested: bool,
+}
+
+impl<B: ShellBackend> Console<B> {
+    pub fn new(policy: ShellPolicy, backend: B, firmware: &'static str) -> Self {
+        Self {
+            access: ShellAccess::new(policy),
+            editor: LineEditor::new(),
+            backend,
+            firmware,
+            bridge_requested: false,
+        }
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Whether the `bridge` command asked the board to bridge the port to the module.
+    pub fn take_bridge_request(&mut self) -> bool {
+        core::mem::take(&mut self.bridge_requested)
+    }
+
+    /// Starts a locked session with the banner and the prompt, e.g. once a terminal connected.
+    pub fn greet(&mut self, out: &mut impl Write) -> core::fmt::Result {
+        self.access.lock();
+        self.editor = LineEditor::new();
+        write_banner(out, self.firmware)?;
+        out.write_str(PROMPT)
+    }
+
+    /// Feeds the received bytes, runs the completed lines and writes their output.
+    ///
+    /// The output of a command is lost where it does not fit into `out`.
+    pub async fn input(&mut self, bytes: &[u8], now: Instant, out: &mut impl Write) {
+        let mut out = Terminal { out, after_cr: false };
+        for byte in bytes {
+            let Some(line) = self.editor.feed(*byte, &mut out) else {
+                continue;
+            };
+            if let Err(error) = self.execute(line.as_str(), now, &mut out).await {
+                let _ = writeln!(out, "error: {:?}", error);
+            }
+            let _ = out.write_str(PROMPT);
+        }
+    }
+
+    /// Runs a command line.
+    pub async fn execute(&mut self, line: &str, now: Instant, out: &mut impl Write) -> Result<(), ShellError> {
+        let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let args = args.trim();
+        match command {
+            "" => Ok(()),
+            "help" => out.write_str(HELP).map_err(|_| ShellError::Output),
+            "unlock" => {
+                self.access.unlock_with_pin(args, now)?;
+                writeln!(out, "unlocked").map_err(|_| ShellError::Output)
+            }
+            "lock" => {
+                self.access.lock();
+                writeln!(out, "locked").map_err(|_| ShellError::Output)
+            }
+            "status" => {
+                self.access.authorize(CommandClass::ReadOnly, now)?;
+                write_status(out, now).map_err(|_| ShellError::Output)
+            }
+            "config" => {
+                self.access.authorize(CommandClass::ReadOnly, now)?;
+                self.backend.write_config(out).await.map_err(|_| ShellError::Output)
+            }
+            "log" => {
+                self.access.authorize(CommandClass::ReadOnly, now)?;
+                LOG_RING.write_hex(out).map_err(|_| ShellError::Output)
+            }
+            ATTRACE_COMMAND => AtTraceCommand::parse(args).ok_or(ShellError::Usage)?.run(&mut self.access, now, out),
+            IDENTIFY_COMMAND => IdentifyCommand::parse(args).ok_or(ShellError::Usage)?.run(&mut self.access, now, out),
+            "upload" => {
+                self.access.authorize(CommandClass::Config, now)?;
+                self.backend.trigger_upload().await;
+                writeln!(out, "upload queued").map_err(|_| ShellError::Output)
+            }
+            "at" => {
+                if !args.get(..2).is_some_and(|prefix| prefix.eq_ignore_ascii_case("AT")) {
+                    return Err(ShellError::Usage);
+                }
+                self.access.authorize(CommandClass::Dangerous, now)?;
+                match self.backend.run_at(args, DEFAULT_RUN_AT_TIMEOUT).await {
+                    Ok(response) => {
+                        for line in response.lines() {
+                            writeln!(out, "{}", line).map_err(|_| ShellError::Output)?;
+                        }
+                        writeln!(out, "OK").map_err(|_| ShellError::Output)
+                    }
+                    Err(error) => writeln!(out, "{:?}", error).map_err(|_| ShellError::Output),
+                }
+            }
+            "format" => {
+                self.access.authorize(CommandClass::Dangerous, now)?;
+                warn!("Shell> formatting the flash");
+                self.backend.format_flash().await?;
+                writeln!(out, "flash formatted").map_err(|_| ShellError::Output)
+            }
+            "bridge" => {
+                self.access.authorize(CommandClass::Dangerous, now)?;
+                self.bridge_requested = true;
+                writeln!(out, "bridge to the modem, 0x{:02x} (Ctrl-]) ends it", BRIDGE_ESCAPE).map_err(|_| ShellError::Output)
+            }
+            _ => Err(ShellError::UnknownCommand),
+        }
+    }
+}
+
+fn write_status(out: &mut impl Write, now: Instant) -> core::fmt::Result {
+    let counters = HEALTH.counters();
+    let device_id = IDENTIFY.device_id();
+    writeln!(out, "up {} s", now.as_secs())?;
+    writeln!(out, "reset {:?}", HEALTH.reset_reason())?;
+    writeln!(out, "id {}", if device_id.is_empty() { "unknown" } else { device_id.as_str() })?;
+    writeln!(out, "identify {}", if IDENTIFY.is_active(now) { "on" } else { "off" })?;
+    writeln!(out, "modem restarts {}", counters.modem_restarts)?;
+    writeln!(out, "checksum errors {}", counters.checksum_errors)?;
+    writeln!(out, "at commands {} errors {} timeouts {} urcs {}", counters.at.commands, counters.at.errors, counters.at.timeouts, counters.at.urcs)?;
+    let overruns = ACTIVITY.overruns();
+    writeln!(
+        out,
+        "uart overruns ve {} ({} host busy) modem {} ({} host busy)",
+        overruns.ve_direct, overruns.ve_direct_host_busy, overruns.modem, overruns.modem_host_busy
+    )
+}
+
+/// Ends the lines with CR LF, the commands write plain LF.
+struct Terminal<'a, W: Write> {
+    out: &'a mut W,
+    after_cr: bool,
+}
+
+impl<W: Write> Write for Terminal<'_, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for (index, part) in s.split('\n').enumerate() {
+            if index > 0 {
+                if !self.after_cr {
+                    self.out.write_char('\r')?;
+                }
+                self.out.write_char('\n')?;
+                self.after_cr = false;
+            }
+            if let Some(last) = part.chars().last() {
+                self.out.write_str(part)?;
+                self.after_cr = last == '\r';
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestBackend {
+        uploads: usize,
+        formats: usize,
+        commands: std::vec::Vec<std::string::String>,
+    }
+
+    impl ShellBackend for TestBackend {
+        async fn write_config(&mut self, out: &mut impl Write) -> core::fmt::Result {
+            writeln!(out, "chemistry Lfp")
+        }
+
+        async fn run_at(&mut self, command: &str, _timeout: Duration) -> Result<AtCommandResponse, AtError> {
+            self.commands.push(command.into());
+            Ok(AtCommandResponse::new(heapless::Vec::from_iter(["+CSQ: 21,3".try_into().unwrap()])))
+        }
+
+        async fn trigger_upload(&mut self) {
+            self.uploads += 1;
+        }
+
+        async fn format_flash(&mut self) -> Result<(), ShellError> {
+            self.formats += 1;
+            Ok(())
+        }
+    }
+
+    fn console() -> Console<TestBackend> {
+        let policy = ShellPolicy {
+            pin: Some("4711".try_into().unwrap()),
+            allow_dangerous: true,
+            ..Default::default()
+        };
+        Console::new(policy, TestBackend::default(), "0.4.2")
+    }
+
+    async fn type_line(console: &mut Console<TestBackend>, line: &str) -> std::string::String {
+        let mut out = std::string::String::new();
+        console.input(line.as_bytes(), Instant::from_secs(100), &mut out).await;
+        out
+    }
+
+    #[tokio::test]
+    async fn check_session() {
+        let mut console = console();
+        let mut out = std::string::String::new();
+        console.greet(&mut out).unwrap();
+        assert!(out.starts_with("bt-solar-monitor 0.4.2\r\n") && out.ends_with(PROMPT), "{}", out);
+
+        assert_eq!(type_line(&mut console, "status\r").await, "status\r\nerror: Locked\r\n> ");
+        assert_eq!(type_line(&mut console, "unlock 4711\r").await, "unlock 4711\r\nunlocked\r\n> ");
+        let out = type_line(&mut console, "status\r").await;
+        assert!(out.contains("\r\nreset Unknown\r\n"), "{}", out);
+        assert!(out.contains("\r\nuart overruns ve 0 (0 host busy) modem 0 (0 host busy)\r\n"), "{}", out);
+        assert_eq!(type_line(&mut console, "config\r").await, "config\r\nchemistry Lfp\r\n> ");
+        assert_eq!(type_line(&mut console, "reboot\r").await, "reboot\r\nerror: UnknownCommand\r\n> ");
+
+        // the banner of a new terminal locks again
+        console.greet(&mut std::string::String::new()).unwrap();
+        assert_eq!(type_line(&mut console, "upload\r").await, "upload\r\nerror: Locked\r\n> ");
+    }
+
+    #[tokio::test]
+    async fn check_backend_commands() {
+        let mut console = console();
+        type_line(&mut console, "unlock 4711\r").await;
+        assert_eq!(type_line(&mut console, "at AT+CSQ\r").await, "at AT+CSQ\r\n+CSQ: 21,3\r\nOK\r\n> ");
+        assert_eq!(type_line(&mut console, "at reboot\r").await, "at reboot\r\nerror: Usage\r\n> ");
+        assert_eq!(console.backend.commands, ["AT+CSQ"]);
+
+        type_line(&mut console, "upload\r").await;
+        type_line(&mut console, "format\r").await;
+        assert_eq!((console.backend.uploads, console.backend.formats), (1, 1));
+
+        assert!(!console.take_bridge_request());
+        type_line(&mut console, "bridge\r").await;
+        assert!(console.take_bridge_request());
+        assert!(!console.take_bridge_request());
+    }
+}
@@ -0,0 +1,60 @@
+//! `identify [minutes]` and `identify off`, flashes the status LED of the device.
+
+use core::fmt::Write;
+
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    identify::{DEFAULT_IDENTIFY_DURATION, IDENTIFY},
+    shell::{CommandClass, ShellAccess, ShellError},
+};
+
+pub const IDENTIFY_COMMAND: &str = "identify";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IdentifyCommand {
+    Start(Duration),
+    Stop,
+}
+
+impl IdentifyCommand {
+    /// Parses the arguments after [`IDENTIFY_COMMAND`], the minutes default to [`DEFAULT_IDENTIFY_DURATION`].
+    pub fn parse(args: &str) -> Option<Self> {
+        match args.trim() {
+            "" => Some(IdentifyCommand::Start(DEFAULT_IDENTIFY_DURATION)),
+            "off" => Some(IdentifyCommand::Stop),
+            minutes => minutes
+                .parse::<u64>()
+                .ok()
+                .map(|minutes| IdentifyCommand::Start(Duration::from_secs(minutes.saturating_mul(60)))),
+        }
+    }
+
+    pub fn run(self, access: &mut ShellAccess, now: Instant, out: &mut impl Write) -> Result<(), ShellError> {
+        access.authorize(CommandClass::ReadOnly, now)?;
+        match self {
+            IdentifyCommand::Start(duration) => {
+                IDENTIFY.start(duration);
+                writeln!(out, "identifying").map_err(|_| ShellError::Output)
+            }
+            IdentifyCommand::Stop => {
+                IDENTIFY.stop();
+                writeln!(out, "identify off").map_err(|_| ShellError::Output)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse() {
+        assert_eq!(IdentifyCommand::parse(""), Some(IdentifyCommand::Start(DEFAULT_IDENTIFY_DURATION)));
+        assert_eq!(IdentifyCommand::parse(" 10\r"), Some(IdentifyCommand::Start(Duration::from_secs(600))));
+        assert_eq!(IdentifyCommand::parse("off"), Some(IdentifyCommand::Stop));
+        assert_eq!(IdentifyCommand::parse("-1"), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Line editing of a serial terminal, the shell sees complete command lines only.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const CTRL_C: u8 = 0x03;
+const CTRL_U: u8 = 0x15;
+
+/// Collects the typed bytes of a line and echoes them, like a terminal in cooked mode.
+///
+/// Backspace and delete remove the last character, Ctrl-C and Ctrl-U drop the line, CR or
+/// LF completes it. Other control bytes are dropped without an echo. A line longer than `N`
+/// completes empty, a truncated command could do something else than typed.
+#[derive(Debug, Default)]
+pub struct LineEditor<const N: usize> {
+    line: String<N>,
+    overflow: bool,
+    /// The CR of a CR LF already completed the line.
+    after_cr: bool,
+}
+
+impl<const N: usize> LineEditor<N> {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            overflow: false,
+            after_cr: false,
+        }
+    }
+
+    /// Feeds a received byte, returns the line once it is complete.
+    pub fn feed(&mut self, byte: u8, echo: &mut impl Write) -> Option<String<N>> {
+        let after_cr = core::mem::take(&mut self.after_cr);
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                self.after_cr = byte == b'\r';
+                let _ = echo.write_str("\r\n");
+                if core::mem::take(&mut self.overflow) {
+                    self.line.clear();
+                    let _ = echo.write_str("line too long\r\n");
+                }
+                Some(core::mem::take(&mut self.line))
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    let _ = echo.write_str("\x08 \x08");
+                }
+                None
+            }
+            CTRL_C | CTRL_U => {
+                self.line.clear();
+                self.overflow = false;
+                let _ = echo.write_str("^C\r\n");
+                Some(String::new())
+            }
+            byte if byte.is_ascii_graphic() || byte == b' ' => {
+                if self.line.push(byte as char).is_ok() {
+                    let _ = echo.write_char(byte as char);
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn feed_all(editor: &mut LineEditor<8>, bytes: &[u8], echo: &mut std::string::String) -> std::vec::Vec<std::string::String> {
+        bytes
+            .iter()
+            .filter_map(|byte| editor.feed(*byte, echo))
+            .map(|line| line.as_str().into())
+            .collect()
+    }
+
+    #[test]
+    fn check_editing() {
+        let mut editor = LineEditor::<8>::new();
+        let mut echo = std::string::String::new();
+        assert_eq!(feed_all(&mut editor, b"stx\x7fat\x1bus\r\n", &mut echo), ["status"]);
+        assert_eq!(echo, "stx\x08 \x08atus\r\n");
+
+        // CR LF completes one line, a lone LF too
+        assert_eq!(feed_all(&mut editor, b"a\r\nb\nc\r", &mut echo), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn check_cancel_and_overflow() {
+        let mut editor = LineEditor::<8>::new();
+        let mut echo = std::string::String::new();
+        assert_eq!(feed_all(&mut editor, b"format\x03", &mut echo), [""]);
+        assert_eq!(feed_all(&mut editor, b"identify off\r", &mut echo), [""]);
+        assert!(echo.ends_with("identify\r\nline too long\r\n"), "{:?}", echo);
+        // nothing to delete on an empty line
+        echo.clear();
+        assert_eq!(feed_all(&mut editor, b"\x08\r", &mut echo), [""]);
+        assert_eq!(echo, "\r\n");
+    }
+}
@@ -1,2 +1,48 @@
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+
+pub mod apn;
+pub mod battery;
+pub mod cbor;
 pub mod cloud;
+pub mod envelope;
+pub mod heartbeat;
+pub mod link_quality;
+pub mod metrics;
+pub mod net_test;
+pub mod remote_at;
+pub mod retry;
+pub mod scaling;
+pub mod scheduler;
+pub mod sensors;
+pub mod soc;
 pub mod upload;
+pub mod upload_queue;
+
+/// Request to hand the partially filled upload batch to the cloud, see [`flush`].
+pub struct Flush {
+    requested: Signal<NoopRawMutex, ()>,
+    done: Signal<NoopRawMutex, ()>,
+}
+
+impl Flush {
+    pub const fn new() -> Self {
+        Self {
+            requested: Signal::new(),
+            done: Signal::new(),
+        }
+    }
+}
+
+impl Default for Flush {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queues the partial upload batch (if any) before a planned power transition,
+/// returns once the upload runner handed it to the upload channel.
+pub async fn flush(flush: &Flush) {
+    flush.done.reset();
+    flush.requested.signal(());
+    flush.done.wait().await;
+}
@@ -0,0 +1,223 @@
+//! APN profiles the cloud client registers with.
+//!
+//! The profiles of [`APN_PROFILES`] are tried first, the built-in [`FALLBACK_APN_PROFILES`]
+//! after them. A failed registration or PDP activation moves the [`ApnSelector`] on to
+//! the next profile, a working one is kept for the following startups.
+//!
+//! A profile may carry the PAP or CHAP credentials its SIM requires, see [`PdpAuth`]. The
+//! credentials of all the profiles share one config value, long ones fit a single profile.
+
+use heapless::{String, Vec};
+
+use crate::{
+    at::packet_domain::{AuthProtocol, PdpAuth, PdpType},
+    storage::{ConfigKey, ConfigValue},
+};
+
+pub const APN_SIZE: usize = 32;
+/// Profiles of the config, they fit one config value.
+pub const MAX_APN_PROFILES: usize = 3;
+const MAX_SELECTOR_PROFILES: usize = MAX_APN_PROFILES + FALLBACK_APN_PROFILES.len();
+
+pub const APN_PROFILES: ConfigKey<ApnProfiles> = ConfigKey::new("cloud", "apn_profiles");
+
+/// Swisscom, with a roaming SIM the carrier default.
+pub const FALLBACK_APN_PROFILES: [(&str, PdpType); 2] = [("gprs.swisscom.ch", PdpType::Ip), ("internet", PdpType::Ip)];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ApnProfile {
+    pub apn: String<APN_SIZE>,
+    pub pdp_type: PdpType,
+    pub auth: PdpAuth,
+}
+
+impl ApnProfile {
+    pub fn new(apn: &str, pdp_type: PdpType) -> Option<Self> {
+        Some(Self {
+            apn: apn.try_into().ok()?,
+            pdp_type,
+            auth: PdpAuth::default(),
+        })
+    }
+
+    pub fn with_auth(mut self, auth: PdpAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+}
+
+/// The configured profiles, in the order they are tried.
+pub type ApnProfiles = Vec<ApnProfile, MAX_APN_PROFILES>;
+
+/// Per profile: PDP type with the authentication protocol in the high nibble, APN length,
+/// APN. With authentication the username and the password follow, each with its length.
+impl ConfigValue for ApnProfiles {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        for profile in self {
+            *buffer.get_mut(len)? = profile.pdp_type as u8 | (profile.auth.protocol as u8) << 4;
+            len += 1;
+            len += encode_str(&profile.apn, buffer.get_mut(len..)?)?;
+            if profile.auth.protocol != AuthProtocol::None {
+                len += encode_str(&profile.auth.username, buffer.get_mut(len..)?)?;
+                len += encode_str(&profile.auth.password, buffer.get_mut(len..)?)?;
+            }
+        }
+        Some(len)
+    }
+
+    fn decode(mut data: &[u8]) -> Option<Self> {
+        let mut profiles = Vec::new();
+        while let [types, rest @ ..] = data {
+            let pdp_type = match types & 0x0f {
+                0 => PdpType::Ip,
+                1 => PdpType::Ipv6,
+                2 => PdpType::Ipv4v6,
+                _ => return None,
+            };
+            let protocol = match types >> 4 {
+                0 => AuthProtocol::None,
+                1 => AuthProtocol::Pap,
+                2 => AuthProtocol::Chap,
+                _ => return None,
+            };
+            let (apn, mut rest) = decode_str(rest)?;
+            let mut profile = ApnProfile::new(apn, pdp_type)?;
+            if protocol != AuthProtocol::None {
+                let (username, after_username) = decode_str(rest)?;
+                let (password, after_password) = decode_str(after_username)?;
+                profile = profile.with_auth(PdpAuth::new(protocol, username, password)?);
+                rest = after_password;
+            }
+            profiles.push(profile).ok()?;
+            data = rest;
+        }
+        Some(profiles)
+    }
+}
+
+fn encode_str(text: &str, buffer: &mut [u8]) -> Option<usize> {
+    let entry = buffer.get_mut(..1 + text.len())?;
+    entry[0] = text.len() as u8;
+    entry[1..].copy_from_slice(text.as_bytes());
+    Some(entry.len())
+}
+
+/// The length prefixed text and the data after it.
+fn decode_str(data: &[u8]) -> Option<(&str, &[u8])> {
+    let (len, rest) = data.split_first()?;
+    let text = core::str::from_utf8(rest.get(..*len as usize)?).ok()?;
+    Some((text, &rest[*len as usize..]))
+}
+
+/// Registration outcomes of a profile since startup.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ApnStats {
+    pub attempts: u32,
+    pub successes: u32,
+}
+
+/// Cycles through the profiles, see the module documentation.
+#[derive(Debug)]
+pub struct ApnSelector {
+    profiles: Vec<(ApnProfile, ApnStats), MAX_SELECTOR_PROFILES>,
+    current: usize,
+}
+
+impl ApnSelector {
+    /// The `configured` profiles followed by the fallbacks that are not configured.
+    pub fn new(configured: &[ApnProfile]) -> Self {
+        let mut profiles = Vec::new();
+        let fallbacks = FALLBACK_APN_PROFILES.iter().filter_map(|(apn, pdp_type)| ApnProfile::new(apn, *pdp_type));
+        for profile in configured.iter().cloned().chain(fallbacks) {
+            if profiles.iter().all(|(known, _): &(ApnProfile, ApnStats)| known.apn != profile.apn) {
+                let _ = profiles.push((profile, ApnStats::default()));
+            }
+        }
+        Self { profiles, current: 0 }
+    }
+
+    pub fn current(&self) -> &ApnProfile {
+        &self.profiles[self.current].0
+    }
+
+    pub fn succeeded(&mut self) {
+        let (profile, stats) = &mut self.profiles[self.current];
+        stats.attempts += 1;
+        stats.successes += 1;
+        info!("APN {} registered ({}/{})", profile.apn.as_str(), stats.successes, stats.attempts);
+    }
+
+    /// Moves on to the next profile.
+    pub fn failed(&mut self) {
+        let (profile, stats) = &mut self.profiles[self.current];
+        stats.attempts += 1;
+        warn!("APN {} failed ({}/{}) => next profile", profile.apn.as_str(), stats.successes, stats.attempts);
+        self.current = (self.current + 1) % self.profiles.len();
+    }
+
+    #[cfg(test)]
+    pub fn stats(&self) -> impl Iterator<Item = (&ApnProfile, ApnStats)> {
+        self.profiles.iter().map(|(profile, stats)| (profile, *stats))
+    }
+}
+
+impl Default for ApnSelector {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn profile(apn: &str, pdp_type: PdpType) -> ApnProfile {
+        ApnProfile::new(apn, pdp_type).unwrap()
+    }
+
+    #[test]
+    fn check_encoding() {
+        let profiles: ApnProfiles = Vec::from_slice(&[profile("iot.1nce.net", PdpType::Ipv4v6), profile("m2m", PdpType::Ip)]).unwrap();
+        let mut buffer = [0u8; 64];
+        let len = profiles.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..4], &[2, 12, b'i', b'o']);
+        assert_eq!(ApnProfiles::decode(&buffer[..len]), Some(profiles));
+        assert_eq!(ApnProfiles::decode(&buffer[..len - 1]), None);
+        assert_eq!(ApnProfiles::decode(&[]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn check_encoding_with_auth() {
+        let auth = PdpAuth::new(AuthProtocol::Chap, "solar", "secret").unwrap();
+        let profiles: ApnProfiles = Vec::from_slice(&[profile("iot.private", PdpType::Ip).with_auth(auth), profile("m2m", PdpType::Ip)]).unwrap();
+        let mut buffer = [0u8; crate::storage::CONFIG_VALUE_SIZE];
+        let len = profiles.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..2], &[0x20, 11]);
+        assert_eq!(&buffer[13..19], &[5, b's', b'o', b'l', b'a', b'r']);
+        assert_eq!(ApnProfiles::decode(&buffer[..len]), Some(profiles));
+        assert_eq!(ApnProfiles::decode(&buffer[..len - 6]), None);
+        assert_eq!(ApnProfiles::decode(&[0x30, 3, b'm', b'2', b'm']), None);
+    }
+
+    #[test]
+    fn check_cycling() {
+        let mut selector = ApnSelector::new(&[profile("iot.1nce.net", PdpType::Ip), profile("internet", PdpType::Ip)]);
+        assert_eq!(selector.stats().count(), 3);
+        assert_eq!(selector.current().apn.as_str(), "iot.1nce.net");
+        selector.failed();
+        assert_eq!(selector.current().apn.as_str(), "internet");
+        selector.failed();
+        assert_eq!(selector.current().apn.as_str(), "gprs.swisscom.ch");
+        selector.succeeded();
+        selector.succeeded();
+        assert_eq!(selector.current().apn.as_str(), "gprs.swisscom.ch");
+        selector.failed();
+        assert_eq!(selector.current().apn.as_str(), "iot.1nce.net");
+
+        let stats: std::vec::Vec<_> = selector.stats().map(|(_, stats)| (stats.successes, stats.attempts)).collect();
+        assert_eq!(stats, [(0, 1), (0, 1), (2, 3)]);
+    }
+}
@@ -0,0 +1,312 @@
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    sensor::ve_direct::Reading,
+    storage::{ConfigKey, ConfigValue},
+};
+
+/// The battery chemistry the installer selected, `battery/chemistry` in the config store.
+pub const CHEMISTRY: ConfigKey<Chemistry> = ConfigKey::new("battery", "chemistry");
+
+/// `CS` of a charger in the equalization stage.
+const CHARGER_STATE_EQUALIZE: u32 = 7;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Chemistry {
+    #[default]
+    Flooded,
+    Agm,
+    LiFePo4,
+}
+
+impl Chemistry {
+    /// The upload throttling thresholds for a 12 V battery of this chemistry.
+    pub fn battery_policy(&self) -> BatteryPolicy {
+        let (low_voltage, resume_voltage) = match self {
+            Chemistry::Flooded => (11.8, 12.4),
+            Chemistry::Agm => (11.9, 12.5),
+            // the voltage is flat over most of the capacity, 12.8 V is about 20 %
+            Chemistry::LiFePo4 => (12.8, 13.1),
+        };
+        BatteryPolicy {
+            low_voltage,
+            resume_voltage,
+            ..BatteryPolicy::default()
+        }
+    }
+
+    /// The alarm thresholds for a 12 V battery of this chemistry.
+    pub fn alarm_preset(&self) -> AlarmPreset {
+        match self {
+            Chemistry::Flooded => AlarmPreset {
+                low_voltage: 11.5,
+                high_voltage: 15.0,
+                equalization_interval: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            },
+            // sealed, equalizing only dries them out
+            Chemistry::Agm => AlarmPreset {
+                low_voltage: 11.6,
+                high_voltage: 14.9,
+                equalization_interval: None,
+            },
+            Chemistry::LiFePo4 => AlarmPreset {
+                low_voltage: 12.0,
+                high_voltage: 14.6,
+                equalization_interval: None,
+            },
+        }
+    }
+}
+
+impl ConfigValue for Chemistry {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        (*self as u8).encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        match u8::decode(data)? {
+            0 => Some(Chemistry::Flooded),
+            1 => Some(Chemistry::Agm),
+            2 => Some(Chemistry::LiFePo4),
+            _ => None,
+        }
+    }
+}
+
+/// Thresholds of the upload throttling, the defaults suit a 12 V lead acid battery.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryPolicy {
+    /// Below this battery voltage the uploads are throttled.
+    pub low_voltage: f32,
+    /// Throttling ends above this voltage, the gap to `low_voltage` keeps it from toggling.
+    pub resume_voltage: f32,
+    /// Minimum time the modem stays asleep while throttled.
+    pub throttled_interval: Duration,
+}
+
+impl Default for BatteryPolicy {
+    fn default() -> Self {
+        Self {
+            low_voltage: 11.8,
+            resume_voltage: 12.4,
+            throttled_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Applies a [`BatteryPolicy`] to the measured battery voltage.
+#[derive(Debug)]
+pub struct BatteryThrottle {
+    policy: BatteryPolicy,
+    throttled: bool,
+}
+
+impl BatteryThrottle {
+    pub fn new(policy: BatteryPolicy) -> Self {
+        Self { policy, throttled: false }
+    }
+
+    /// Updates the state with the latest `voltage`, 0 V (no reading yet) keeps it.
+    pub fn update(&mut self, voltage: f32) {
+        if voltage <= 0.0 {
+            return;
+        }
+        if !self.throttled && voltage < self.policy.low_voltage {
+            warn!("Battery low at {} V => throttle uploads", voltage);
+            self.throttled = true;
+        } else if self.throttled && voltage > self.policy.resume_voltage {
+            info!("Battery recovered at {} V => normal uploads", voltage);
+            self.throttled = false;
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// How long the modem has to stay asleep, `None` without throttling.
+    pub fn min_sleep(&self) -> Option<Duration> {
+        self.throttled.then_some(self.policy.throttled_interval)
+    }
+}
+
+/// Thresholds of the [`BatteryAlarms`], see [`Chemistry::alarm_preset`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmPreset {
+    pub low_voltage: f32,
+    /// Above this voltage outside of the equalization stage.
+    pub high_voltage: f32,
+    /// Longest time without an equalization, `None` for batteries that must not be equalized.
+    pub equalization_interval: Option<Duration>,
+}
+
+/// The active battery alarms.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmState {
+    pub low_voltage: bool,
+    pub high_voltage: bool,
+    /// A battery that needs equalization was not equalized within the interval.
+    pub equalization_overdue: bool,
+    /// The charger equalizes a battery that must not be equalized, its settings are wrong.
+    pub unexpected_equalization: bool,
+}
+
+impl AlarmState {
+    /// Whether an alarm is active that was not in `previous`.
+    pub fn raised_since(&self, previous: AlarmState) -> bool {
+        (self.low_voltage && !previous.low_voltage)
+            || (self.high_voltage && !previous.high_voltage)
+            || (self.equalization_overdue && !previous.equalization_overdue)
+            || (self.unexpected_equalization && !previous.unexpected_equalization)
+    }
+}
+
+/// Checks the readings against an [`AlarmPreset`], every raised alarm is logged once.
+#[derive(Debug)]
+pub struct BatteryAlarms {
+    preset: AlarmPreset,
+    state: AlarmState,
+    last_equalization: Instant,
+}
+
+impl BatteryAlarms {
+    pub fn new(preset: AlarmPreset) -> Self {
+        Self {
+            preset,
+            state: AlarmState::default(),
+            last_equalization: Instant::now(),
+        }
+    }
+
+    pub fn update(&mut self, reading: &Reading, now: Instant) -> AlarmState {
+        let previous = self.state;
+        let equalizing = reading.charger_state == CHARGER_STATE_EQUALIZE;
+        if equalizing {
+            self.last_equalization = now;
+        }
+        // 0 V is no reading yet
+        if reading.battery_voltage > 0.0 {
+            self.state.low_voltage = reading.battery_voltage < self.preset.low_voltage;
+            self.state.high_voltage = !equalizing && reading.battery_voltage > self.preset.high_voltage;
+        }
+        match self.preset.equalization_interval {
+            Some(interval) => {
+                self.state.equalization_overdue = now.saturating_duration_since(self.last_equalization) > interval;
+                self.state.unexpected_equalization = false;
+            }
+            None => {
+                self.state.equalization_overdue = false;
+                self.state.unexpected_equalization = equalizing;
+            }
+        }
+        if self.state.low_voltage && !previous.low_voltage {
+            warn!("Battery alarm> Low voltage {} V", reading.battery_voltage);
+        }
+        if self.state.high_voltage && !previous.high_voltage {
+            warn!("Battery alarm> High voltage {} V", reading.battery_voltage);
+        }
+        if self.state.equalization_overdue && !previous.equalization_overdue {
+            warn!("Battery alarm> Equalization overdue");
+        }
+        if self.state.unexpected_equalization && !previous.unexpected_equalization {
+            warn!("Battery alarm> Charger equalizes a battery that must not be equalized, check its settings");
+        }
+        self.state
+    }
+
+    pub fn state(&self) -> AlarmState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_hysteresis() {
+        let mut throttle = BatteryThrottle::new(BatteryPolicy::default());
+        assert_eq!(throttle.min_sleep(), None);
+
+        throttle.update(12.1);
+        assert!(!throttle.is_throttled());
+        throttle.update(11.7);
+        assert_eq!(throttle.min_sleep(), Some(Duration::from_secs(60 * 60)));
+        throttle.update(12.2);
+        assert!(throttle.is_throttled());
+        throttle.update(12.5);
+        assert!(!throttle.is_throttled());
+    }
+
+    #[test]
+    fn check_missing_reading_keeps_state() {
+        let mut throttle = BatteryThrottle::new(BatteryPolicy::default());
+        throttle.update(11.0);
+        throttle.update(0.0);
+        assert!(throttle.is_throttled());
+    }
+
+    #[test]
+    fn check_chemistry_config_value() {
+        let mut buffer = [0u8; 4];
+        for chemistry in [Chemistry::Flooded, Chemistry::Agm, Chemistry::LiFePo4] {
+            let len = chemistry.encode(&mut buffer).unwrap();
+            assert_eq!(Chemistry::decode(&buffer[..len]), Some(chemistry));
+        }
+        assert_eq!(Chemistry::decode(&[7]), None);
+        assert!(Chemistry::LiFePo4.battery_policy().low_voltage > Chemistry::Flooded.battery_policy().low_voltage);
+    }
+
+    #[test]
+    fn check_lead_acid_alarms() {
+        let start = Instant::from_secs(0);
+        let mut alarms = BatteryAlarms::new(Chemistry::Flooded.alarm_preset());
+        let equalizing = Reading {
+            battery_voltage: 15.8,
+            charger_state: CHARGER_STATE_EQUALIZE,
+            ..Default::default()
+        };
+        // the equalization voltage is no alarm
+        assert_eq!(alarms.update(&equalizing, start), AlarmState::default());
+        let absorption = Reading {
+            charger_state: 4,
+            ..equalizing
+        };
+        assert!(alarms.update(&absorption, start).high_voltage);
+
+        let resting = Reading {
+            battery_voltage: 12.5,
+            ..Default::default()
+        };
+        assert!(!alarms.update(&resting, start + Duration::from_secs(29 * 24 * 60 * 60)).equalization_overdue);
+        assert!(alarms.update(&resting, start + Duration::from_secs(31 * 24 * 60 * 60)).equalization_overdue);
+    }
+
+    #[test]
+    fn check_lifepo4_alarms() {
+        let now = Instant::from_secs(100 * 24 * 60 * 60);
+        let mut alarms = BatteryAlarms::new(Chemistry::LiFePo4.alarm_preset());
+        let resting = Reading {
+            battery_voltage: 13.2,
+            ..Default::default()
+        };
+        // never equalized, but LiFePO4 must not be equalized anyway
+        assert_eq!(alarms.update(&resting, now), AlarmState::default());
+        let equalizing = Reading {
+            battery_voltage: 14.4,
+            charger_state: CHARGER_STATE_EQUALIZE,
+            ..Default::default()
+        };
+        assert!(alarms.update(&equalizing, now).unexpected_equalization);
+        let low = Reading {
+            battery_voltage: 11.9,
+            ..resting
+        };
+        assert!(alarms.update(&low, now).low_voltage);
+    }
+}
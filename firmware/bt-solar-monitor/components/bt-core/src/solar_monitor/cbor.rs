@@ -0,0 +1,460 @@
+//! CBOR encoding of the uploads, for backends in scripting languages without protoc.
+//!
+//! The structure mirrors `Upload` of `readings.proto`: every message is a map keyed by
+//! the proto field numbers, with the integer units of the proto fields. Integer keys keep
+//! the upload as small as the protobuf one, so it fits the same upload buffer.
+//!
+//! ```text
+//! {
+//!   6: int,                  // start_timestamp, Unix timestamp in milliseconds
+//!   7: uint,                 // schema_version
+//!   8: bool,                 // approximate_time, only when set
+//!   1: [{                    // entries
+//!     1: int,                // offset_in_seconds
+//!     2: { 1: int, ... },    // reading, fields 1 to 12, 13 with the deciwatt resolution,
+//!                            // 14 to 18 the spreads { 1: int, 2: int, 3: uint } of an average,
+//!                            // 19 with a temperature sensor, 20 and 21 with analog channels,
+//!                            // 22 with a pulse input
+//!     3: { 1: int, ... },    // battery_monitor, fields 1 to 26, only with a battery monitor
+//!     4: uint,               // estimated_state_of_charge, only with an estimate
+//!   }]
+//! }
+//! ```
+//!
+//! The bool fields are CBOR booleans, arrays and maps have a definite length. minicbor
+//! writes the values, [`CborEncoder`] and the protobuf `PbEncoder` both implement
+//! [`UploadEncoder`] for the upload runner.
+
+use heapless::Vec;
+use micropb::{MessageEncode, PbEncoder, PbWrite};
+use minicbor::{
+    Encoder,
+    encode::{self, Write},
+};
+
+use crate::{
+    proto::bt_::solar_::{BatteryMonitorReading, Reading, Spread, Upload, UploadEntry},
+    storage::{ConfigKey, ConfigValue},
+};
+
+/// The encoding of the readings uploads, `upload/encoding` in the config store.
+pub const UPLOAD_ENCODING: ConfigKey<UploadEncoding> = ConfigKey::new("upload", "encoding");
+
+/// Encoding of the readings uploads, the events stay protobuf.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UploadEncoding {
+    #[default]
+    Protobuf,
+    Cbor,
+}
+
+impl UploadEncoding {
+    /// `Content-Type` of the upload request.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            UploadEncoding::Protobuf => "application/x-protobuf",
+            UploadEncoding::Cbor => "application/cbor",
+        }
+    }
+}
+
+impl ConfigValue for UploadEncoding {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        (*self as u8).encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        match u8::decode(data)? {
+            0 => Some(UploadEncoding::Protobuf),
+            1 => Some(UploadEncoding::Cbor),
+            _ => None,
+        }
+    }
+}
+
+/// A field value of the proto messages.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Value {
+    Int(i64),
+    Bool(bool),
+}
+
+/// Writes an [`Upload`] in one of the [`UploadEncoding`]s.
+pub trait UploadEncoder {
+    type Error;
+
+    fn encode_upload(&mut self, upload: &Upload) -> Result<(), Self::Error>;
+}
+
+/// [`UploadEncoding::Protobuf`], the messages of `readings.proto`.
+impl<W: PbWrite> UploadEncoder for PbEncoder<W> {
+    type Error = W::Error;
+
+    fn encode_upload(&mut self, upload: &Upload) -> Result<(), Self::Error> {
+        upload.encode(self)
+    }
+}
+
+/// The writers of the upload buffer for minicbor.
+struct CborWriter<W>(W);
+
+impl<W: PbWrite> Write for CborWriter<W> {
+    type Error = W::Error;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.pb_write(data)
+    }
+}
+
+type CborResult<W> = Result<(), encode::Error<<W as PbWrite>::Error>>;
+
+/// [`UploadEncoding::Cbor`], the structure of the module documentation.
+pub struct CborEncoder<W: PbWrite> {
+    encoder: Encoder<CborWriter<W>>,
+}
+
+impl<W: PbWrite> UploadEncoder for CborEncoder<W> {
+    type Error = encode::Error<W::Error>;
+
+    fn encode_upload(&mut self, upload: &Upload) -> CborResult<W> {
+        self.map(3 + upload.approximate_time as usize)?;
+        self.int(6)?;
+        self.int(upload.start_timestamp)?;
+        self.int(7)?;
+        self.int(upload.schema_version as i64)?;
+        if upload.approximate_time {
+            self.int(8)?;
+            self.encoder.bool(true)?;
+        }
+        self.int(1)?;
+        self.encoder.array(upload.entries.len() as u64)?;
+        upload.entries.iter().try_for_each(|entry| self.entry(entry))
+    }
+}
+
+impl<W: PbWrite> CborEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            encoder: Encoder::new(CborWriter(writer)),
+        }
+    }
+
+    fn entry(&mut self, entry: &UploadEntry) -> CborResult<W> {
+        let len = 1 + [
+            entry.reading().is_some(),
+            entry.battery_monitor().is_some(),
+            entry.estimated_state_of_charge().is_some(),
+        ]
+        .iter()
+        .filter(|has| **has)
+        .count();
+        self.map(len)?;
+        self.int(1)?;
+        self.int(entry.offset_in_seconds as i64)?;
+        if let Some(reading) = entry.reading() {
+            self.int(2)?;
+            self.reading(reading)?;
+        }
+        if let Some(battery_monitor) = entry.battery_monitor() {
+            self.int(3)?;
+            self.fields(&battery_monitor_fields(battery_monitor))?;
+        }
+        if let Some(soc) = entry.estimated_state_of_charge() {
+            self.int(4)?;
+            self.int(*soc as i64)?;
+        }
+        Ok(())
+    }
+
+    /// The scalar fields numbered from 1, the spreads and the temperature with their field numbers.
+    fn reading(&mut self, reading: &Reading) -> CborResult<W> {
+        let fields = reading_fields(reading);
+        let spreads = [
+            (14, reading.battery_voltage_spread()),
+            (15, reading.battery_current_spread()),
+            (16, reading.panel_voltage_spread()),
+            (17, reading.panel_power_spread()),
+            (18, reading.load_current_spread()),
+        ];
+        let optionals = [
+            (19, reading.battery_temperature().map(|value| *value as i64)),
+            (20, reading.auxiliary_1().map(|value| *value as i64)),
+            (21, reading.auxiliary_2().map(|value| *value as i64)),
+            (22, reading.pulse_count().map(|value| *value as i64)),
+        ];
+        self.map(fields.len() + spreads.iter().filter(|(_, spread)| spread.is_some()).count() + optionals.iter().filter(|(_, value)| value.is_some()).count())?;
+        self.values(&fields)?;
+        for (number, spread) in spreads {
+            if let Some(spread) = spread {
+                self.int(number)?;
+                self.fields(&spread_fields(spread))?;
+            }
+        }
+        for (number, value) in optionals {
+            if let Some(value) = value {
+                self.int(number)?;
+                self.int(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A message with the fields numbered from 1.
+    fn fields(&mut self, fields: &[Value]) -> CborResult<W> {
+        self.map(fields.len())?;
+        self.values(fields)
+    }
+
+    /// The keys and values of fields numbered from 1, without the map head.
+    fn values(&mut self, fields: &[Value]) -> CborResult<W> {
+        fields.iter().zip(1..).try_for_each(|(value, number)| {
+            self.int(number)?;
+            match value {
+                Value::Int(value) => self.int(*value),
+                Value::Bool(value) => self.encoder.bool(*value).map(|_| ()),
+            }
+        })
+    }
+
+    /// In the shortest head, as RFC 8949 deterministic encoding.
+    fn int(&mut self, value: i64) -> CborResult<W> {
+        self.encoder.i64(value).map(|_| ())
+    }
+
+    fn map(&mut self, len: usize) -> CborResult<W> {
+        self.encoder.map(len as u64).map(|_| ())
+    }
+}
+
+fn reading_fields(reading: &Reading) -> Vec<Value, 13> {
+    let mut fields = Vec::from_array([
+        Value::Int(reading.battery_voltage as i64),
+        Value::Int(reading.battery_current as i64),
+        Value::Int(reading.panel_voltage as i64),
+        Value::Int(reading.panel_power as i64),
+        Value::Int(reading.load_current as i64),
+        Value::Int(reading.yield_today as i64),
+        Value::Int(reading.yield_total as i64),
+        Value::Int(reading.max_power_today as i64),
+        Value::Int(reading.charger_state as i64),
+        Value::Int(reading.mppt_mode as i64),
+        Value::Int(reading.error_code as i64),
+        Value::Bool(reading.load_on),
+    ]);
+    if let Some(deciwatt) = reading.panel_power_deciwatt() {
+        // field 13
+        let _ = fields.push(Value::Int(*deciwatt as i64));
+    }
+    fields
+}
+
+fn spread_fields(spread: &Spread) -> Vec<Value, 3> {
+    let mut fields = Vec::from_array([Value::Int(spread.min as i64), Value::Int(spread.max as i64)]);
+    if let Some(stddev) = spread.stddev() {
+        // field 3
+        let _ = fields.push(Value::Int(*stddev as i64));
+    }
+    fields
+}
+
+fn battery_monitor_fields(reading: &BatteryMonitorReading) -> [Value; 26] {
+    [
+        Value::Int(reading.voltage as i64),
+        Value::Int(reading.current as i64),
+        Value::Int(reading.power as i64),
+        Value::Int(reading.consumed as i64),
+        Value::Int(reading.state_of_charge as i64),
+        Value::Int(reading.time_to_go as i64),
+        Value::Bool(reading.alarm),
+        Value::Int(reading.alarm_reason as i64),
+        Value::Int(reading.deepest_discharge as i64),
+        Value::Int(reading.last_discharge as i64),
+        Value::Int(reading.average_discharge as i64),
+        Value::Int(reading.charge_cycles as i64),
+        Value::Int(reading.full_discharges as i64),
+        Value::Int(reading.cumulative_drawn as i64),
+        Value::Int(reading.min_voltage as i64),
+        Value::Int(reading.max_voltage as i64),
+        Value::Int(reading.seconds_since_full_charge as i64),
+        Value::Int(reading.automatic_syncs as i64),
+        Value::Int(reading.low_voltage_alarms as i64),
+        Value::Int(reading.high_voltage_alarms as i64),
+        Value::Int(reading.low_aux_voltage_alarms as i64),
+        Value::Int(reading.high_aux_voltage_alarms as i64),
+        Value::Int(reading.min_aux_voltage as i64),
+        Value::Int(reading.max_aux_voltage as i64),
+        Value::Int(reading.discharged_energy as i64),
+        Value::Int(reading.charged_energy as i64),
+    ]
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    struct Buffer(std::vec::Vec<u8>);
+
+    impl PbWrite for &mut Buffer {
+        type Error = core::convert::Infallible;
+
+        fn pb_write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            self.0.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn encode(write: impl FnOnce(&mut CborEncoder<&mut Buffer>)) -> std::vec::Vec<u8> {
+        let mut buffer = Buffer(std::vec::Vec::new());
+        write(&mut CborEncoder::new(&mut buffer));
+        buffer.0
+    }
+
+    #[test]
+    fn check_integers() {
+        // RFC 8949 Appendix A
+        for (value, expected) in [
+            (0i64, &[0x00u8][..]),
+            (23, &[0x17]),
+            (24, &[0x18, 0x18]),
+            (1000, &[0x19, 0x03, 0xe8]),
+            (1_000_000, &[0x1a, 0x00, 0x0f, 0x42, 0x40]),
+            (1_000_000_000_000, &[0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00]),
+            (-1, &[0x20]),
+            (-100, &[0x38, 0x63]),
+            (-1000, &[0x39, 0x03, 0xe7]),
+            (i64::MIN, &[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        ] {
+            assert_eq!(encode(|encoder| encoder.int(value).unwrap()), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn check_encoding_config_value() {
+        let mut buffer = [0u8; 4];
+        for encoding in [UploadEncoding::Protobuf, UploadEncoding::Cbor] {
+            let len = ConfigValue::encode(&encoding, &mut buffer).unwrap();
+            assert_eq!(UploadEncoding::decode(&buffer[..len]), Some(encoding));
+        }
+        assert_eq!(UploadEncoding::decode(&[2]), None);
+    }
+
+    #[test]
+    fn check_upload() {
+        let mut upload = Upload {
+            start_timestamp: 1_700_000_000_000,
+            schema_version: 8,
+            ..Default::default()
+        };
+        let mut entry = UploadEntry::default().init_offset_in_seconds(60).init_estimated_state_of_charge(815);
+        entry.set_reading(Reading {
+            battery_voltage: 12_840,
+            battery_current: -1_150,
+            load_on: true,
+            ..Default::default()
+        });
+        upload.entries.push(entry).unwrap();
+
+        let cbor = encode(|encoder| encoder.encode_upload(&upload).unwrap());
+        #[rustfmt::skip]
+        let expected = [
+            0xa3,
+            0x06, 0x1b, 0x00, 0x00, 0x01, 0x8b, 0xcf, 0xe5, 0x68, 0x00,
+            0x07, 0x08,
+            0x01, 0x81, 0xa3,
+                0x01, 0x18, 0x3c,
+                0x02, 0xac,
+                    0x01, 0x19, 0x32, 0x28,
+                    0x02, 0x39, 0x04, 0x7d,
+                    0x03, 0x00, 0x04, 0x00, 0x05, 0x00, 0x06, 0x00, 0x07, 0x00, 0x08, 0x00, 0x09, 0x00, 0x0a, 0x00, 0x0b, 0x00,
+                    0x0c, 0xf5,
+                0x04, 0x19, 0x03, 0x2f,
+        ];
+        assert_eq!(cbor, expected);
+    }
+
+    #[test]
+    fn check_reading_spread() {
+        let mut reading = Reading {
+            panel_power: 51,
+            ..Default::default()
+        };
+        reading.set_panel_power_spread(Spread {
+            min: 12,
+            max: -3,
+            ..Default::default()
+        });
+        reading.set_load_current_spread(Spread::default().init_stddev(30));
+        reading.set_battery_temperature(-550);
+        reading.set_auxiliary_2(1_000);
+
+        let cbor = encode(|encoder| encoder.reading(&reading).unwrap());
+        #[rustfmt::skip]
+        let expected = [
+            0xb0,
+                0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x18, 0x33, 0x05, 0x00, 0x06, 0x00, 0x07, 0x00, 0x08, 0x00, 0x09, 0x00,
+                0x0a, 0x00, 0x0b, 0x00, 0x0c, 0xf4,
+                0x11, 0xa2, 0x01, 0x0c, 0x02, 0x22,
+                0x12, 0xa3, 0x01, 0x00, 0x02, 0x00, 0x03, 0x18, 0x1e,
+                0x13, 0x39, 0x02, 0x25,
+                0x15, 0x19, 0x03, 0xe8,
+        ];
+        assert_eq!(cbor, expected);
+    }
+
+    #[test]
+    fn check_max_size() {
+        let reading = Reading {
+            battery_voltage: i32::MIN,
+            battery_current: i32::MIN,
+            panel_voltage: i32::MIN,
+            panel_power: i32::MIN,
+            load_current: i32::MIN,
+            yield_today: i32::MIN,
+            yield_total: i32::MIN,
+            max_power_today: i32::MIN,
+            charger_state: u32::MAX,
+            mppt_mode: u32::MAX,
+            error_code: u32::MAX,
+            load_on: true,
+            ..Default::default()
+        }
+        .init_panel_power_deciwatt(i32::MIN);
+        let spread = Spread {
+            min: i32::MIN,
+            max: i32::MIN,
+            ..Default::default()
+        }
+        .init_stddev(u32::MAX);
+        let mut reading = reading;
+        reading.set_battery_voltage_spread(spread.clone());
+        reading.set_battery_current_spread(spread.clone());
+        reading.set_panel_voltage_spread(spread.clone());
+        reading.set_panel_power_spread(spread.clone());
+        reading.set_load_current_spread(spread);
+        reading.set_battery_temperature(i32::MIN);
+        reading.set_auxiliary_1(i32::MIN);
+        reading.set_auxiliary_2(i32::MIN);
+        reading.set_pulse_count(u32::MAX);
+        let battery_monitor = BatteryMonitorReading {
+            voltage: i32::MIN,
+            state_of_charge: u32::MAX,
+            alarm_reason: u32::MAX,
+            ..Default::default()
+        };
+        let mut entry = UploadEntry::default().init_offset_in_seconds(i32::MIN).init_estimated_state_of_charge(u32::MAX);
+        entry.set_reading(reading);
+        entry.set_battery_monitor(battery_monitor);
+        let mut upload = Upload {
+            start_timestamp: i64::MIN,
+            schema_version: u32::MAX,
+            approximate_time: true,
+            ..Default::default()
+        };
+        while upload.entries.push(entry.clone()).is_ok() {}
+
+        // every CBOR value takes at most as many bytes as its protobuf varint
+        let cbor = encode(|encoder| encoder.encode_upload(&upload).unwrap());
+        assert!(cbor.len() <= Upload::MAX_SIZE.unwrap(), "{}", cbor.len());
+    }
+}
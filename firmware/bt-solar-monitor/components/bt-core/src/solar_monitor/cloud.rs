@@ -1,37 +1,348 @@
-use const_format::concatcp;
-use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Receiver};
-use embassy_time::{Duration, Instant, Timer, with_timeout};
-use embedded_hal::digital::OutputPin;
-use heapless::Vec;
+use chrono::NaiveDateTime;
+use const_format::{concatcp, formatcp};
+use embassy_futures::select::select3;
+use embassy_sync::{
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
+    channel::Receiver,
+    signal::Signal,
+    watch::DynAnonReceiver,
+};
+use embassy_time::{Duration, Instant, Timer, with_deadline, with_timeout};
+use heapless::{String, Vec};
 use micropb::{MessageEncode, PbEncoder};
 
 use crate::{
-    at::AtController,
-    net::cellular::{CellularError, sim_com_a67::SimComCellularModule},
-    proto::bt_::solar_::{OfflineEvent, OnlineEvent, StartupEvent, SystemEvent, SystemEvent_::Event},
-    time::UtcTime,
+    at::{
+        http::HttpStatusCode,
+        identification::IMEI_SIZE,
+        quirks::{AT_QUIRKS, QuirkTable},
+    },
+    audit::{Audit, AuditEntry, CommandOrigin},
+    crash::CrashReport,
+    health::HEALTH,
+    identify::IDENTIFY,
+    log_ring::LOG_RING,
+    net::{
+        cellular::{
+            BufferSink, CellularError, CellularModem,
+            power_cycles::PowerCycleRecord,
+            sleep::{ModemScope, ModemSleep},
+        },
+        http::Url,
+    },
+    ota::{FIRMWARE_MANIFEST_SIZE, FirmwareManifest, ImageSink, Ota, OtaError, OtaMessage},
+    poll_stats::{POLL_STATS, PollStats},
+    power::{PowerHandle, WakeLock},
+    proto::bt_::solar_::{OfflineEvent, OnlineEvent, PositionEvent, StartupEvent, SystemEvent, SystemEvent_::Event},
+    schema::SCHEMA_VERSION,
+    sensor::ve_direct::{
+        charger_error::{ChargerErrorEvent, ChargerErrors},
+        hex::{LoadOutput, LoadSwitch, switch_load},
+        history::{DailyHistory, HistoryDay},
+    },
+    solar_monitor::{
+        Flush,
+        apn::{ApnProfile, ApnSelector},
+        battery::{BatteryPolicy, BatteryThrottle},
+        cbor::UploadEncoding,
+        envelope::{Envelope, EnvelopeKey, KEY_ID_HEADER},
+        heartbeat::Heartbeat,
+        link_quality::LinkQualityStats,
+        net_test::{NetTest, NetTestReport},
+        remote_at::RunAtCommand,
+        retry::{Jitter, RetryPolicy},
+        scheduler::{UploadClass, UploadScheduler, UploadWindow},
+        upload::UploadStatus,
+    },
+    storage::ConfigKey,
+    time::{TimeSource, UtcTime},
+    timeouts::Timeouts,
+    watchdog::{FEED_INTERVAL, WatchdogHandle},
 };
 
-pub struct Runner<'ch, 'a, Output: OutputPin, Ctr: AtController, M: RawMutex, const B: usize, const N: usize> {
-    cloud_controller: CloudController<'ch, 'a, Output, Ctr, M, B, N>,
+/// Strips the location and the SIM and network identifiers from the uploads, `cloud/privacy_mode`
+/// in the config store, see [`Runner::with_privacy_mode`].
+pub const PRIVACY_MODE: ConfigKey<bool> = ConfigKey::new("cloud", "privacy_mode");
+
+pub struct Runner<'a, Modem: CellularModem, M: RawMutex, const B: usize, const N: usize> {
+    cloud_controller: CloudController<'a, Modem, M, B, N>,
 }
 
-pub fn new<'ch, 'a, Output: OutputPin, Ctr: AtController, M: RawMutex, const B: usize, const N: usize>(
-    module: SimComCellularModule<'ch, Output, Ctr>,
+pub fn new<'a, Modem: CellularModem, M: RawMutex, const B: usize, const N: usize>(
+    module: Modem,
     upload_receiver: Receiver<'a, M, Vec<u8, B>, N>,
-) -> Runner<'ch, 'a, Output, Ctr, M, B, N> {
+    timeouts: Timeouts,
+) -> Runner<'a, Modem, M, B, N> {
     Runner {
         cloud_controller: CloudController {
             module,
             state: CloudClientState::Startup,
             upload_receiver,
+            pending_upload: None,
+            upload_failures: 0,
+            retry_policy: RetryPolicy::default(),
+            apn: ApnSelector::default(),
+            jitter: Jitter::new(Instant::now().as_ticks() as u32),
+            timeouts,
+            watchdog: WatchdogHandle::default(),
+            power: PowerHandle::default(),
+            wake_lock: None,
+            scheduler: None,
+            crash_report: None,
+            firmware_update: None,
+            upload_status: None,
+            position_report: PositionReport::Disabled,
+            link_quality: None,
+            audit: None,
+            pending_audit: None,
+            battery: None,
+            slept_at: Instant::now(),
+            power_off: None,
+            flush: None,
+            accepted_version: None,
+            charger_errors: None,
+            pending_charger_error: None,
+            history: None,
+            pending_history_day: None,
+            poll_stats: None,
+            power_cycles: None,
+            net_test: None,
+            health_report: None,
+            upload_encoding: UploadEncoding::Protobuf,
+            heartbeat: None,
+            commands: BackendCommands::default(),
+            load_switch: None,
+            privacy_mode: false,
+            envelope: None,
+            first_upload_pending: false,
+            first_upload: None,
+            modem_sleep: ModemSleep::new(),
         },
     }
 }
 
-impl<'ch, 'a, Output: OutputPin, Ctr: AtController, M: RawMutex, const B: usize, const N: usize> Runner<'ch, 'a, Output, Ctr, M, B, N> {
+const READING_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/reading");
+const EVENT_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/event");
+const METRICS_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/metrics");
+const LOG_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/log");
+const FIRMWARE_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/firmware");
+const HEARTBEAT_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/heartbeat");
+const AT_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/at");
+const HEALTH_URL: &str = concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/healthz");
+const NTP_SERVER: &str = "pool.ntp.org";
+const PROTO_VERSION: &str = formatcp!("{}", SCHEMA_VERSION);
+/// Key of the schema version the backend accepts in its response body.
+const ACCEPTED_VERSION_KEY: &str = "\"accepted_proto_version\":";
+/// Command of the backend in a response body, uploads the captured log.
+const UPLOAD_LOG_COMMAND: &str = "\"upload_log\":true";
+/// Command of the backend in a response body, flashes the LED for the minutes, see [`IDENTIFY`].
+const IDENTIFY_KEY: &str = "\"identify\":";
+/// Command of the backend in a response body, switches the load output of the charger, see [`LoadOutput`].
+const LOAD_KEY: &str = "\"load\":\"";
+/// The VE.Direct runner answers a load switch after its HEX timeout at the latest.
+const LOAD_SWITCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sleep of an unsupervised runner, the wake up time is computed again after it.
+const MAX_SLEEP: Duration = Duration::from_secs(60 * 60);
+/// Time the [`crate::ota::OtaRunner`] gets to verify the written image.
+const OTA_VERIFY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time the audit runner gets to offer the next entry after an acknowledge.
+const AUDIT_OFFER_TIMEOUT: Duration = Duration::from_millis(100);
+
+impl<'a, Modem: CellularModem, M: RawMutex, const B: usize, const N: usize> Runner<'a, Modem, M, B, N> {
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.cloud_controller.retry_policy = retry_policy;
+        self
+    }
+
+    /// Seeds the retry jitter, `seed` from the hardware RNG. The default seed is the uptime at
+    /// startup, about the same on every device, the devices of a fleet would retry in lockstep.
+    pub fn with_jitter_seed(mut self, seed: u32) -> Self {
+        self.cloud_controller.jitter = Jitter::new(seed);
+        self
+    }
+
+    /// Tries the `profiles` before the fallback APNs, see [`ApnSelector`].
+    pub fn with_apn_profiles(mut self, profiles: &[ApnProfile]) -> Self {
+        self.cloud_controller.apn = ApnSelector::new(profiles);
+        self
+    }
+
+    /// Labels the readings uploads with the `Content-Type` of the encoding, see [`crate::solar_monitor::upload::Runner::with_encoding`].
+    pub fn with_upload_encoding(mut self, encoding: UploadEncoding) -> Self {
+        self.cloud_controller.upload_encoding = encoding;
+        self
+    }
+
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.cloud_controller.watchdog = watchdog;
+        self
+    }
+
+    /// Holds a wake lock while the module is awake, so uploads happen in one burst per wake-up.
+    pub fn with_power_manager(mut self, power: PowerHandle<'a>) -> Self {
+        self.cloud_controller.power = power;
+        self
+    }
+
+    /// Deferred low priority uploads are sent with the next wake burst.
+    pub fn with_scheduler(mut self, scheduler: &'a UploadScheduler) -> Self {
+        self.cloud_controller.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Uploads the crash of the previous run after startup, `reported` is signaled once the backend accepted it.
+    pub fn with_crash_report(mut self, report: CrashReport, reported: &'a Signal<NoopRawMutex, ()>) -> Self {
+        self.cloud_controller.crash_report = Some((report, reported));
+        self
+    }
+
+    /// Reports the upload channel overflows with the offline event.
+    pub fn with_upload_status(mut self, upload_status: DynAnonReceiver<'a, UploadStatus>) -> Self {
+        self.cloud_controller.upload_status = Some(upload_status);
+        self
+    }
+
+    /// Reports the GNSS position once per startup, as soon as the module has a fix.
+    pub fn with_position_report(mut self) -> Self {
+        self.cloud_controller.position_report = PositionReport::Pending;
+        self
+    }
+
+    /// Leaves the GNSS position, the IMSI, the ICCID and the operator out of all uploads, for data
+    /// shared with third party dashboards. The IMEI stays, it is the device id of the backend.
+    pub fn with_privacy_mode(mut self, enabled: bool) -> Self {
+        self.cloud_controller.privacy_mode = enabled;
+        self
+    }
+
+    /// Seals the protobuf uploads with the `key`, see [`crate::solar_monitor::envelope`]. `seed`
+    /// from the hardware RNG, the nonces derive from it.
+    pub fn with_envelope(mut self, key: EnvelopeKey, seed: [u8; 32]) -> Self {
+        self.cloud_controller.envelope = Some(Envelope::new(key, seed));
+        self
+    }
+
+    /// Checks for a firmware newer than `current_version` every `check_interval` while the module is
+    /// awake, see [`crate::ota`]. The first successful upload confirms the running firmware.
+    pub fn with_firmware_update(mut self, ota: &'a Ota, current_version: u32, check_interval: Duration) -> Self {
+        self.cloud_controller.firmware_update = Some(FirmwareUpdateCheck {
+            ota,
+            current_version,
+            check_interval,
+            next_check: Instant::now(),
+        });
+        self
+    }
+
+    /// Samples the link quality at most every `sample_interval` while the module is awake,
+    /// the aggregate is reported before the module goes to sleep.
+    pub fn with_link_quality(mut self, sample_interval: Duration) -> Self {
+        self.cloud_controller.link_quality = Some(LinkQualitySampling {
+            sample_interval,
+            next_sample: Instant::now(),
+            stats: LinkQualityStats::default(),
+        });
+        self
+    }
+
+    /// Keeps the module asleep for longer while the battery voltage from `voltage` is low.
+    pub fn with_battery_policy(mut self, policy: BatteryPolicy, voltage: DynAnonReceiver<'a, f32>) -> Self {
+        self.cloud_controller.battery = Some((BatteryThrottle::new(policy), voltage));
+        self
+    }
+
+    /// Uploads the entries of the command audit log while the module is awake.
+    pub fn with_audit(mut self, audit: &'a Audit) -> Self {
+        self.cloud_controller.audit = Some(audit);
+        self
+    }
+
+    /// Uploads the charger error changes right away, they wake the module up.
+    pub fn with_charger_errors(mut self, charger_errors: &'a ChargerErrors) -> Self {
+        self.cloud_controller.charger_errors = Some(charger_errors);
+        self
+    }
+
+    /// Uploads the history days of the charger before the module goes to sleep.
+    pub fn with_daily_history(mut self, history: &'a DailyHistory) -> Self {
+        self.cloud_controller.history = Some(history);
+        self
+    }
+
+    /// Reports the tasks with a poll longer than `limit` before the module goes to sleep.
+    /// The board forwards the executor hooks, see [`crate::poll_stats::task_exec_begin`].
+    pub fn with_poll_stats(mut self, limit: Duration) -> Self {
+        self.cloud_controller.poll_stats = Some((&POLL_STATS, limit));
+        self
+    }
+
+    /// Powers the module off between the upload `window`s instead of keeping it registered in sleep mode.
+    pub fn with_power_off(mut self, window: UploadWindow) -> Self {
+        self.cloud_controller.power_off = Some(window);
+        self
+    }
+
+    /// Switches the load output of the charger on a `"load"` command of the backend, e.g. to
+    /// power cycle a router fed from the load terminals. `load_switch` is the one of the
+    /// VE.Direct runner, see [`crate::sensor::ve_direct::Runner::with_load_switch`].
+    pub fn with_load_switch(mut self, load_switch: &'a LoadSwitch) -> Self {
+        self.cloud_controller.load_switch = Some(load_switch);
+        self
+    }
+
+    /// Flushes the partial upload batch before the module powers off, it goes out with this
+    /// burst instead of waiting for the next upload window, see [`crate::solar_monitor::flush`].
+    pub fn with_flush(mut self, flush: &'a Flush) -> Self {
+        self.cloud_controller.flush = Some(flush);
+        self
+    }
+
+    /// Resumes a module that is still on after a reset of the MCU instead of power cycling it, and
+    /// keeps at least `min_interval` between the power cycles, also across resets.
+    pub fn with_power_cycle_record(mut self, record: &'a mut PowerCycleRecord, min_interval: Duration) -> Self {
+        record.boot();
+        self.cloud_controller.power_cycles = Some(PowerCycleGuard {
+            resume: record.modem_on(),
+            record,
+            min_interval,
+        });
+        self
+    }
+
+    /// Uploads a health event every `interval` while the module is awake, `stack_free` measures the
+    /// lowest free stack of the board, see [`crate::health`].
+    pub fn with_health_report(mut self, interval: Duration, stack_free: fn() -> u32) -> Self {
+        self.cloud_controller.health_report = Some(HealthReport {
+            interval,
+            next_report: Instant::now(),
+            stack_free,
+        });
+        self
+    }
+
+    /// Posts a [`Heartbeat`] every `interval`, a sleeping module is woken up for it. `firmware` is
+    /// the version of the app.
+    pub fn with_heartbeat(mut self, interval: Duration, firmware: &'static str) -> Self {
+        self.cloud_controller.heartbeat = Some(HeartbeatReport {
+            interval,
+            next_beat: Instant::now(),
+            firmware,
+            device_id: String::new(),
+        });
+        self
+    }
+
+    /// Runs the connectivity tests requested with [`NetTest::run`].
+    pub fn with_net_test(mut self, net_test: &'a NetTest) -> Self {
+        self.cloud_controller.net_test = Some(net_test);
+        self
+    }
+
     pub async fn run(mut self) {
         loop {
+            self.cloud_controller.watchdog.feed();
+            self.cloud_controller.update_power_cycles();
             self.cloud_controller.once().await;
         }
     }
@@ -43,103 +354,561 @@ enum CloudClientState {
     Startup,
     Connected,
     Sleeping,
+    PoweredOff,
+}
+
+/// The commands of the backend from the response bodies, run once the module is awake.
+#[derive(Debug, Default)]
+struct BackendCommands {
+    at: Option<RunAtCommand>,
+    /// `"load":"off"`, `"auto"` or `"on"`.
+    load: Option<LoadOutput>,
 }
 
-pub struct CloudController<'ch, 'a, Output: OutputPin, Ctr: AtController, M: RawMutex, const B: usize, const N: usize> {
-    module: SimComCellularModule<'ch, Output, Ctr>,
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PositionReport {
+    Disabled,
+    Pending,
+    Reported,
+}
+
+struct LinkQualitySampling {
+    sample_interval: Duration,
+    next_sample: Instant,
+    stats: LinkQualityStats,
+}
+
+struct PowerCycleGuard<'a> {
+    record: &'a mut PowerCycleRecord,
+    min_interval: Duration,
+    /// The module was on before the reset, the first power on tries to resume it.
+    resume: bool,
+}
+
+struct HealthReport {
+    interval: Duration,
+    next_report: Instant,
+    stack_free: fn() -> u32,
+}
+
+struct HeartbeatReport {
+    interval: Duration,
+    next_beat: Instant,
+    firmware: &'static str,
+    /// The IMEI, from the identity query of the startup.
+    device_id: String<IMEI_SIZE>,
+}
+
+struct FirmwareUpdateCheck<'a> {
+    ota: &'a Ota,
+    current_version: u32,
+    check_interval: Duration,
+    next_check: Instant,
+}
+
+pub struct CloudController<'a, Modem: CellularModem, M: RawMutex, const B: usize, const N: usize> {
+    module: Modem,
     state: CloudClientState,
     upload_receiver: Receiver<'a, M, Vec<u8, B>, N>,
+    pending_upload: Option<Vec<u8, B>>,
+    upload_failures: u32,
+    retry_policy: RetryPolicy,
+    apn: ApnSelector,
+    jitter: Jitter,
+    timeouts: Timeouts,
+    watchdog: WatchdogHandle<'a>,
+    power: PowerHandle<'a>,
+    wake_lock: Option<WakeLock<'a>>,
+    scheduler: Option<&'a UploadScheduler>,
+    crash_report: Option<(CrashReport, &'a Signal<NoopRawMutex, ()>)>,
+    firmware_update: Option<FirmwareUpdateCheck<'a>>,
+    upload_status: Option<DynAnonReceiver<'a, UploadStatus>>,
+    position_report: PositionReport,
+    link_quality: Option<LinkQualitySampling>,
+    audit: Option<&'a Audit>,
+    /// Taken from the audit runner but not yet uploaded.
+    pending_audit: Option<AuditEntry>,
+    battery: Option<(BatteryThrottle, DynAnonReceiver<'a, f32>)>,
+    slept_at: Instant,
+    power_off: Option<UploadWindow>,
+    flush: Option<&'a Flush>,
+    /// Schema version the backend reported last, see [`accepted_version`].
+    accepted_version: Option<u32>,
+    charger_errors: Option<&'a ChargerErrors>,
+    /// Taken from the charger errors but not yet uploaded.
+    pending_charger_error: Option<ChargerErrorEvent>,
+    history: Option<&'a DailyHistory>,
+    /// Taken from the daily history but not yet uploaded.
+    pending_history_day: Option<HistoryDay>,
+    poll_stats: Option<(&'a PollStats, Duration)>,
+    power_cycles: Option<PowerCycleGuard<'a>>,
+    net_test: Option<&'a NetTest>,
+    health_report: Option<HealthReport>,
+    upload_encoding: UploadEncoding,
+    heartbeat: Option<HeartbeatReport>,
+    /// Asked for by the backend but not yet run.
+    commands: BackendCommands,
+    load_switch: Option<&'a LoadSwitch>,
+    privacy_mode: bool,
+    envelope: Option<Envelope>,
+    /// The next reading upload is the first one after a startup.
+    first_upload_pending: bool,
+    /// How long the first reading upload after the last startup took, reported with the next [`StartupEvent`].
+    first_upload: Option<Duration>,
+    modem_sleep: ModemSleep,
 }
-impl<'ch, 'a, Output: OutputPin, Ctr: AtController, M: RawMutex, const B: usize, const N: usize> CloudController<'ch, 'a, Output, Ctr, M, B, N> {
-    pub async fn sleep(&mut self) -> Result<(), CellularError> {
-        //self.module.set_sleep_mode(SleepMode::Enabled).await?;
-        self.state = CloudClientState::Sleeping;
-        Ok(())
+
+impl<'a, Modem: CellularModem, M: RawMutex, const B: usize, const N: usize> ModemScope for CloudController<'a, Modem, M, B, N> {
+    type Modem = Modem;
+
+    fn modem_sleep(&mut self) -> (&mut Modem, &mut ModemSleep) {
+        (&mut self.module, &mut self.modem_sleep)
     }
+}
 
+impl<'a, Modem: CellularModem, M: RawMutex, const B: usize, const N: usize> CloudController<'a, Modem, M, B, N> {
     async fn once(&mut self) {
+        if matches!(self.state, CloudClientState::Sleeping | CloudClientState::PoweredOff) {
+            self.wake_lock = None;
+        } else if self.wake_lock.is_none() {
+            self.wake_lock = Some(self.power.acquire());
+        }
         let result = match self.state {
             CloudClientState::Startup => self.handle_startup().await,
             CloudClientState::Connected => self.handle_connected().await,
             CloudClientState::Sleeping => self.handle_sleeping().await,
+            CloudClientState::PoweredOff => self.handle_powered_off().await,
         };
         if let Err(e) = result {
             warn!("CloudClient error: {:?} => resetting module", e);
+            HEALTH.count(|counters| counters.modem_restarts += 1);
             while self.module.reset().await.is_err() {
                 warn!("CloudClient reset error, retrying...");
-                Timer::after_secs(30).await;
+                Timer::after(self.timeouts.modem_reset_retry).await;
             }
+            self.modem_sleep.powered_on();
             self.state = CloudClientState::Startup;
+            if let Some(net_test) = self.net_test
+                && net_test.take_request()
+            {
+                net_test.complete(NetTestReport::failed(e));
+            }
         }
     }
 
     async fn handle_startup(&mut self) -> Result<(), CellularError> {
-        self.module.power_cycle().await?;
-        self.module.startup_network("gprs.swisscom.ch").await?;
-        let now = self.module.query_real_time_clock().await?;
-        UtcTime::time_sync(now).await;
+        let start = Instant::now();
+        self.power_on().await?;
+        let booted = Instant::now();
+        self.startup_network().await?;
+        let registered = Instant::now();
+        let now = self.sync_time().await?;
+        let synced = Instant::now();
         self.state = CloudClientState::Connected;
+        if self.position_report == PositionReport::Reported {
+            self.position_report = PositionReport::Pending;
+        }
         info!("CloudClient connected at {}", crate::fmt::FormatableNaiveDateTime(&now));
         let rssi = self.module.query_signal_quality().await?;
+        let mut startup = StartupEvent {
+            uptime_seconds: Instant::now().as_secs() as u32,
+            rssi: rssi.into(),
+            reset_reason: HEALTH.reset_reason() as u32,
+            ..Default::default()
+        };
+        self.startup_phases(&mut startup, booted - start, registered - booted, synced - registered);
+        // the identity is for the inventory only, the event goes out without it
+        match self.module.query_identity().await {
+            Ok(identity) => {
+                let _ = startup.imei.push_str(identity.imei.as_str());
+                if !self.privacy_mode {
+                    let _ = startup.imsi.push_str(identity.imsi.as_str());
+                    let _ = startup.iccid.push_str(identity.iccid.as_str());
+                }
+                let _ = startup.firmware_revision.push_str(identity.firmware_revision.as_str());
+                IDENTIFY.set_device_id(&identity.imei);
+                if let Some(heartbeat) = self.heartbeat.as_mut() {
+                    heartbeat.device_id = identity.imei;
+                }
+            }
+            Err(e) => warn!("CloudClient identity query failed: {:?}", e),
+        }
         self.upload_event(SystemEvent {
+            schema_version: SCHEMA_VERSION,
             timestamp: now.and_utc().timestamp(),
-            event: Some(Event::StartupEvent(StartupEvent {
-                uptime_seconds: Instant::now().as_secs() as u32,
-                rssi: rssi.into(),
-            })),
+            event: Some(Event::StartupEvent(startup)),
         })
         .await?;
+        self.upload_crash_report(now).await?;
+        Ok(())
+    }
+
+    /// The durations of the startup sequence, a module that does not split the network startup reports it as registration.
+    fn startup_phases(&mut self, startup: &mut StartupEvent, modem_boot: Duration, network: Duration, time_sync: Duration) {
+        let phases = self.module.network_phases();
+        startup.modem_boot_ms = millis(modem_boot);
+        startup.sim_ready_ms = phases.sim_ready.map_or(0, millis);
+        startup.registration_ms = millis(phases.registration.unwrap_or(network));
+        startup.context_activation_ms = phases.context_activation.map_or(0, millis);
+        startup.time_sync_ms = millis(time_sync);
+        startup.first_upload_ms = self.first_upload.take().map_or(0, millis);
+        self.first_upload_pending = true;
+        info!(
+            "Startup phases [ms]: boot {}, SIM {}, registration {}, context {}, time {}",
+            startup.modem_boot_ms, startup.sim_ready_ms, startup.registration_ms, startup.context_activation_ms, startup.time_sync_ms
+        );
+    }
+
+    /// Power cycles the module, with a [`PowerCycleRecord`] a module still on from before the reset is resumed instead.
+    async fn power_on(&mut self) -> Result<(), CellularError> {
+        self.modem_sleep.powered_on();
+        let Some(guard) = self.power_cycles.as_mut() else {
+            return self.module.power_cycle().await;
+        };
+        if core::mem::take(&mut guard.resume) && self.module.resume().await? {
+            info!("Module still on from before the reset => resumed");
+            return Ok(());
+        }
+        let wait = guard.record.wait_before_cycle(uptime_seconds(), guard.min_interval);
+        if wait > Duration::from_ticks(0) {
+            info!("Last module power cycle too recent => wait {} before the next", crate::fmt::FormatableDuration(wait));
+            let until = Instant::now() + wait;
+            while Instant::now() < until {
+                self.watchdog.feed();
+                Timer::after(FEED_INTERVAL.min(until - Instant::now())).await;
+            }
+        }
+        self.module.power_cycle().await?;
+        if let Some(guard) = self.power_cycles.as_mut() {
+            guard.record.cycled(uptime_seconds());
+        }
+        Ok(())
+    }
+
+    fn update_power_cycles(&mut self) {
+        if let Some(guard) = self.power_cycles.as_mut() {
+            guard.record.update(uptime_seconds());
+        }
+    }
+
+    /// Registers with the current APN profile, a failure moves on to the next one.
+    async fn startup_network(&mut self) -> Result<(), CellularError> {
+        let profile = self.apn.current();
+        info!("Registering with APN {} ({:?}, {:?})", profile.apn.as_str(), profile.pdp_type, profile.auth.protocol);
+        let result = with_timeout(self.timeouts.network_registration, self.module.startup_network(&profile.apn, profile.pdp_type, &profile.auth)).await;
+        match result {
+            Ok(Ok(())) => {
+                self.apn.succeeded();
+                Ok(())
+            }
+            Ok(Err(e)) => {
+                self.apn.failed();
+                Err(e)
+            }
+            Err(e) => {
+                self.apn.failed();
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn upload_crash_report(&mut self, now: NaiveDateTime) -> Result<(), CellularError> {
+        let Some((report, _)) = &self.crash_report else {
+            return Ok(());
+        };
+        warn!("Uploading crash of previous run: {}", report.message.as_str());
+        let event = SystemEvent {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now.and_utc().timestamp(),
+            event: Some(Event::CrashEvent(report.to_event())),
+        };
+        if self.send_event(event).await?.is_ok()
+            && let Some((_, reported)) = self.crash_report.take()
+        {
+            reported.signal(());
+        }
         Ok(())
     }
 
+    /// The partial batch of the upload runner before the module powers off, `None` without one.
+    ///
+    /// The batch may go through the flash queue first, it is waited for like any other batch.
+    async fn flush_before_power_off(&mut self) -> Option<Vec<u8, B>> {
+        let flush = self.flush.filter(|_| self.power_off.is_some())?;
+        self.power.prepare_power_down(flush).await;
+        with_timeout(self.timeouts.upload_idle, self.upload_receiver.receive()).await.ok()
+    }
+
     async fn handle_connected(&mut self) -> Result<(), CellularError> {
-        match with_timeout(Duration::from_secs(4), self.upload_receiver.receive()).await {
-            Ok(data) => {
-                info!("Uploading {} bytes to cloud...", data.len());
-                let request = self.module.request().await?;
-                request.set_header("X-Token", crate::config::SOLAR_BACKEND_TOKEN).await?;
-                let mut response = request
-                    .post(concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/reading"), data.as_slice())
-                    .await?;
-                if response.status().is_ok() {
-                    info!("Upload successful");
-                } else {
-                    warn!("Upload failed with status {}", response.status());
+        self.sample_link_quality().await;
+        self.run_net_test().await;
+        self.send_heartbeat().await?;
+        self.run_at_command().await?;
+        self.switch_load().await;
+        self.upload_charger_errors().await?;
+        if self.pending_upload.is_none() {
+            match with_timeout(self.timeouts.upload_idle, self.upload_receiver.receive()).await {
+                Ok(data) => {
+                    if let Some(window) = self.power_off.as_mut() {
+                        window.batch_received(Instant::now());
+                    }
+                    self.pending_upload = Some(data);
+                }
+                Err(_) if let Some(data) = self.flush_before_power_off().await => {
+                    info!("Partial batch flushed => upload before powering off");
+                    self.pending_upload = Some(data);
                 }
-                let body = response.body();
-                if body.is_empty() {
-                    info!("No response body");
-                } else {
-                    let mut body_buffer = [0u8; 1024];
-                    info!("Response body [{}]: {}", body.len(), body.read_as_str(&mut body_buffer).await?);
+                Err(_) => {
+                    self.upload_deferred().await;
+                    self.check_firmware_update().await;
+                    self.report_position().await?;
+                    self.report_link_quality().await?;
+                    self.report_poll_stats().await?;
+                    self.report_health().await?;
+                    self.upload_audit().await?;
+                    self.upload_history().await?;
+                    if let Some(now) = UtcTime::now().await {
+                        let rssi = self.module.query_signal_quality().await?;
+                        let upload_overflows = self.upload_overflows();
+                        self.upload_event(SystemEvent {
+                            schema_version: SCHEMA_VERSION,
+                            timestamp: now.and_utc().timestamp(),
+                            event: Some(Event::OfflineEvent(OfflineEvent {
+                                uptime_seconds: Instant::now().as_secs() as u32,
+                                rssi: rssi.into(),
+                                upload_overflows,
+                            })),
+                        })
+                        .await?;
+                    }
+                    if self.power_off.is_some() {
+                        info!("No data to upload, powering off until the next upload window...");
+                        self.module.power_down().await?;
+                        if let Some(guard) = self.power_cycles.as_mut() {
+                            guard.record.powered_down();
+                        }
+                        self.state = CloudClientState::PoweredOff;
+                    } else {
+                        info!("No data to upload, going to sleep...");
+                        self.modem_sleep.sleep(&mut self.module).await?;
+                        self.state = CloudClientState::Sleeping;
+                    }
+                    self.slept_at = Instant::now();
+                    return Ok(());
                 }
             }
-            Err(_) => {
-                if let Some(now) = UtcTime::now().await {
-                    let rssi = self.module.query_signal_quality().await?;
-                    self.upload_event(SystemEvent {
-                        timestamp: now.and_utc().timestamp(),
-                        event: Some(Event::OfflineEvent(OfflineEvent {
-                            uptime_seconds: Instant::now().as_secs() as u32,
-                            rssi: rssi.into(),
-                        })),
-                    })
-                    .await?;
+        }
+        if let Some(data) = &self.pending_upload {
+            info!("Uploading {} bytes to cloud...", data.len());
+            let started = Instant::now();
+            match Self::post(
+                &mut self.module,
+                &mut self.accepted_version,
+                &mut self.commands,
+                self.envelope.as_mut(),
+                READING_URL,
+                data.as_slice(),
+                self.upload_encoding,
+            )
+            .await
+            {
+                Ok(status) if status.is_ok() => {
+                    info!("Upload successful");
+                    if core::mem::take(&mut self.first_upload_pending) {
+                        self.first_upload = Some(started.elapsed());
+                    }
+                    if let Some(check) = &self.firmware_update {
+                        check.ota.confirm();
+                    }
+                    self.pending_upload = None;
+                    self.upload_failures = 0;
+                }
+                Ok(status) if status.is_client_error() => {
+                    warn!("Upload rejected with status {} => dropping batch", status);
+                    self.pending_upload = None;
+                    self.upload_failures = 0;
+                }
+                Ok(status) => {
+                    warn!("Upload failed with status {}", status);
+                    self.upload_failed(CellularError::HttpStatus(status)).await?;
+                }
+                Err(e) => {
+                    warn!("Upload failed with error {:?}", e);
+                    self.upload_failed(e).await?;
                 }
-                info!("No data to upload, going to sleep...");
-                self.module.set_sleep_mode(crate::at::serial_interface::SleepMode::RxSleep).await?;
-                self.state = CloudClientState::Sleeping;
             }
         }
         Ok(())
     }
 
+    async fn upload_failed(&mut self, error: CellularError) -> Result<(), CellularError> {
+        self.upload_failures += 1;
+        if !self.retry_policy.should_retry(self.upload_failures) {
+            warn!("Upload failed {} times => keep batch and reset module", self.upload_failures);
+            self.upload_failures = 0;
+            return Err(error);
+        }
+        let backoff = self.retry_policy.backoff(self.upload_failures, self.jitter.next());
+        info!("Retry upload in {} ({}/{})", crate::fmt::FormatableDuration(backoff), self.upload_failures, self.retry_policy.max_attempts);
+        Timer::after(backoff).await;
+        Ok(())
+    }
+
     async fn handle_sleeping(&mut self) -> Result<(), CellularError> {
-        self.upload_receiver.ready_to_receive().await;
-        self.module.wake_up().await?;
+        // a scope cancelled halfway left the module awake
+        self.modem_sleep.restore(&mut self.module).await?;
+        self.wait_for_wake_up(None).await;
+        self.wake_lock = Some(self.power.acquire());
+        if self.heartbeat_only() {
+            info!("Heartbeat only => back to sleep afterwards");
+            return self
+                .with_modem_awake(async |controller| {
+                    controller.send_heartbeat().await?;
+                    controller.run_at_command().await?;
+                    controller.switch_load().await;
+                    Ok(())
+                })
+                .await;
+        }
+        self.modem_sleep.wake_up(&mut self.module).await?;
+        self.upload_online_event().await?;
+        self.state = CloudClientState::Connected;
+        Ok(())
+    }
+
+    /// Powers the module on for the upload window, or earlier for anything that would also wake it from sleep.
+    async fn handle_powered_off(&mut self) -> Result<(), CellularError> {
+        let window_opens = self.power_off.and_then(|window| window.next_power_on(self.slept_at));
+        self.wait_for_wake_up(window_opens).await;
+        self.wake_lock = Some(self.power.acquire());
+        self.power_on().await?;
+        self.startup_network().await?;
+        self.sync_time().await?;
+        self.upload_online_event().await?;
+        self.state = CloudClientState::Connected;
+        Ok(())
+    }
+
+    /// Woken by the heartbeat alone, nothing else needs the module connected.
+    fn heartbeat_only(&self) -> bool {
+        self.heartbeat.as_ref().is_some_and(|heartbeat| Instant::now() >= heartbeat.next_beat)
+            && self.upload_receiver.is_empty()
+            && !self.scheduler.is_some_and(|scheduler| scheduler.is_overdue(Instant::now()))
+            && !self.charger_errors.is_some_and(|errors| errors.has_pending())
+            && !self.net_test.is_some_and(|net_test| net_test.is_requested())
+    }
+
+    /// Waits until there is something to upload, `window_opens` wakes the module up in any case.
+    ///
+    /// Between the checks it sleeps until [`CloudController::next_wake_up`], unless an upload,
+    /// a charger error or a net test arrives before.
+    async fn wait_for_wake_up(&mut self, window_opens: Option<Instant>) {
+        let throttled_until = self.battery_min_sleep().map(|min_sleep| self.slept_at + min_sleep);
+        loop {
+            self.watchdog.feed();
+            self.update_power_cycles();
+            if let Some(until) = throttled_until
+                && Instant::now() < until
+            {
+                // a full queue blocks the readings, that costs more than an early upload
+                if self.upload_receiver.is_full() {
+                    info!("Upload queue full => wake up despite the low battery");
+                    break;
+                }
+                Timer::after(FEED_INTERVAL.min(until - Instant::now())).await;
+                continue;
+            }
+            if let Some(opens) = window_opens
+                && Instant::now() >= opens
+            {
+                info!("Upload window opens => power on");
+                break;
+            }
+            if !self.upload_receiver.is_empty() {
+                break;
+            }
+            if self.scheduler.is_some_and(|scheduler| scheduler.is_overdue(Instant::now())) {
+                info!("Deferred upload overdue => wake up");
+                break;
+            }
+            if self.charger_errors.is_some_and(|errors| errors.has_pending()) {
+                info!("Charger error changed => wake up");
+                break;
+            }
+            if self.net_test.is_some_and(|net_test| net_test.is_requested()) {
+                info!("Net test requested => wake up");
+                break;
+            }
+            // a powered off module waits for its upload window, the backend knows the schedule
+            if self.state == CloudClientState::Sleeping && self.heartbeat.as_ref().is_some_and(|heartbeat| Instant::now() >= heartbeat.next_beat) {
+                info!("Heartbeat due => wake up");
+                break;
+            }
+            let wake_up = self.next_wake_up(window_opens);
+            debug!("Sleep for {} ms", wake_up.saturating_duration_since(Instant::now()).as_millis());
+            let (charger_errors, net_test) = (self.charger_errors, self.net_test);
+            let arrived = select3(
+                self.upload_receiver.ready_to_receive(),
+                async {
+                    match charger_errors {
+                        Some(errors) => errors.wait_pending().await,
+                        None => core::future::pending().await,
+                    }
+                },
+                async {
+                    match net_test {
+                        Some(net_test) => net_test.wait_request().await,
+                        None => core::future::pending().await,
+                    }
+                },
+            );
+            let _ = with_deadline(wake_up, arrived).await;
+        }
+    }
+
+    /// The next deadline of the waiting module: the upload window, the first deferred upload
+    /// getting overdue or the heartbeat of a sleeping module, at the latest the next feed of
+    /// the watchdog. Deferred later, an upload is due after the next wake up at the earliest.
+    fn next_wake_up(&self, window_opens: Option<Instant>) -> Instant {
+        let heartbeat = self
+            .heartbeat
+            .as_ref()
+            .filter(|_| self.state == CloudClientState::Sleeping)
+            .map(|heartbeat| heartbeat.next_beat);
+        let deferred = self.scheduler.and_then(|scheduler| scheduler.next_deadline());
+        let feed = Instant::now() + self.watchdog.max_idle().unwrap_or(MAX_SLEEP);
+        [window_opens, deferred, heartbeat].into_iter().flatten().fold(feed, Instant::min)
+    }
+
+    /// Registration, DNS and health endpoint of a requested [`NetTest`], the failures go into the report.
+    async fn run_net_test(&mut self) {
+        let Some(net_test) = self.net_test else {
+            return;
+        };
+        if !net_test.take_request() {
+            return;
+        }
+        info!("NetTest> running");
+        let mut report = NetTestReport {
+            link: self.module.query_link_quality().await,
+            dns: None,
+            http: None,
+        };
+        if report.is_registered() {
+            let host = Url::parse(HEALTH_URL).map(|url| url.host).unwrap_or_default();
+            let started = Instant::now();
+            report.dns = Some(self.module.resolve(host).await.map(|_| started.elapsed()));
+            let started = Instant::now();
+            report.http = Some(self.module.http_head(HEALTH_URL, &[]).await.map(|status| (status, started.elapsed())));
+        }
+        net_test.complete(report);
+    }
+
+    async fn upload_online_event(&mut self) -> Result<(), CellularError> {
         if let Some(now) = UtcTime::now().await {
             let rssi = self.module.query_signal_quality().await?;
             self.upload_event(SystemEvent {
+                schema_version: SCHEMA_VERSION,
                 timestamp: now.and_utc().timestamp(),
                 event: Some(Event::OnlineEvent(OnlineEvent {
                     uptime_seconds: Instant::now().as_secs() as u32,
@@ -148,43 +917,1736 @@ impl<'ch, 'a, Output: OutputPin, Ctr: AtController, M: RawMutex, const B: usize,
             })
             .await?;
         }
-        self.state = CloudClientState::Connected;
         Ok(())
     }
 
-    async fn upload_event(&mut self, event: SystemEvent) -> Result<(), CellularError> {
-        const BUFFER_SIZE: usize = SystemEvent::MAX_SIZE.expect("Size known at compile time");
-        let mut buffer = micropb::heapless::Vec::<u8, BUFFER_SIZE>::new();
-        let mut encoder = PbEncoder::new(&mut buffer);
-        event.encode(&mut encoder).map_err(|_| CellularError::Encoding())?;
-        let request = self.module.request().await?;
-        request.set_header("X-Token", crate::config::SOLAR_BACKEND_TOKEN).await?;
-        let mut response = request
-            .post(concatcp!(crate::config::SOLAR_BACKEND_BASE_URL, "/api/v2/solar/event"), buffer.as_slice())
-            .await?;
-        if response.status().is_ok() {
-            info!("Event sent successful");
-        } else {
-            warn!("Event send failed with status {}", response.status());
+    /// The minimum sleep of the battery policy for the latest battery voltage.
+    fn battery_min_sleep(&mut self) -> Option<Duration> {
+        let (throttle, voltage) = self.battery.as_mut()?;
+        if let Some(voltage) = voltage.try_get() {
+            throttle.update(voltage);
+        }
+        throttle.min_sleep()
+    }
+
+    /// Prefers NTP over the module RTC, which is only as good as the network time of the carrier.
+    async fn sync_time(&mut self) -> Result<NaiveDateTime, CellularError> {
+        let (now, source) = match self.module.sync_network_time(NTP_SERVER).await {
+            Ok(now) => (now, TimeSource::Ntp),
+            Err(e) => {
+                warn!("NTP sync failed: {:?} => use module RTC", e);
+                (self.module.query_real_time_clock().await?, TimeSource::ModemRtc)
+            }
+        };
+        self.time_synced(now, source).await;
+        Ok(now)
+    }
+
+    fn upload_overflows(&mut self) -> u32 {
+        self.upload_status
+            .as_mut()
+            .and_then(|receiver| receiver.try_get())
+            .map_or(0, |status| status.overflows)
+    }
+
+    /// Synchronizes the system time, external time is also written back into the module RTC
+    /// so it survives MCU resets while the module stays powered.
+    async fn time_synced(&mut self, now: NaiveDateTime, source: TimeSource) {
+        UtcTime::time_sync(now).await;
+        if source.is_external()
+            && let Err(e) = self.module.set_real_time_clock(&now).await
+        {
+            warn!("Failed to write {:?} time back to module RTC: {:?}", source, e);
+        }
+    }
+
+    /// Sends the deferred low priority uploads while the module is awake anyway.
+    ///
+    /// Failures keep the upload queued for the next burst instead of resetting the module.
+    async fn upload_deferred(&mut self) {
+        let Some(scheduler) = self.scheduler else {
+            return;
+        };
+        while let Some(upload) = scheduler.take() {
+            let url = match upload.class {
+                UploadClass::Metrics => METRICS_URL,
+                UploadClass::Log => LOG_URL,
+            };
+            match Self::post(
+                &mut self.module,
+                &mut self.accepted_version,
+                &mut self.commands,
+                self.envelope.as_mut(),
+                url,
+                upload.data.as_slice(),
+                UploadEncoding::Protobuf,
+            )
+            .await
+            {
+                Ok(status) if status.is_ok() || status.is_client_error() => {
+                    if !status.is_ok() {
+                        warn!("Deferred {:?} upload rejected with status {} => dropping", upload.class, status);
+                    }
+                }
+                Ok(status) => {
+                    warn!("Deferred {:?} upload failed with status {} => keep for next burst", upload.class, status);
+                    scheduler.requeue(upload);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Deferred {:?} upload failed with error {:?} => keep for next burst", upload.class, e);
+                    scheduler.requeue(upload);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Uploads the position once the module has a fix, a failed query is retried with the next idle period.
+    async fn report_position(&mut self) -> Result<(), CellularError> {
+        if self.position_report != PositionReport::Pending || self.privacy_mode {
+            return Ok(());
+        }
+        let position = match self.module.query_position().await {
+            Ok(Some(position)) => position,
+            Ok(None) => {
+                info!("No GNSS fix yet");
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("GNSS position query failed: {:?}", e);
+                return Ok(());
+            }
+        };
+        info!("GNSS position {}, {}", position.latitude, position.longitude);
+        let event = SystemEvent {
+            schema_version: SCHEMA_VERSION,
+            timestamp: position.fix_time.and_utc().timestamp(),
+            event: Some(Event::PositionEvent(PositionEvent {
+                latitude: position.latitude,
+                longitude: position.longitude,
+                altitude: position.altitude,
+                fix_timestamp: position.fix_time.and_utc().timestamp(),
+            })),
+        };
+        if self.send_event(event).await?.is_ok() {
+            self.position_report = PositionReport::Reported;
+        }
+        Ok(())
+    }
+
+    async fn sample_link_quality(&mut self) {
+        let Some(sampling) = &mut self.link_quality else {
+            return;
+        };
+        let now = Instant::now();
+        if now < sampling.next_sample {
+            return;
+        }
+        sampling.next_sample = now + sampling.sample_interval;
+        match self.module.query_link_quality().await {
+            Ok(sample) => sampling.stats.add(sample, now),
+            Err(e) => {
+                warn!("Link quality query failed: {:?}", e);
+                sampling.stats.add_failure(now);
+            }
+        }
+    }
+
+    /// Uploads the link quality aggregate of the period, the samples are kept if the backend did not take it.
+    async fn report_link_quality(&mut self) -> Result<(), CellularError> {
+        let Some(mut event) = self.link_quality.as_ref().and_then(|sampling| sampling.stats.to_event(Instant::now())) else {
+            return Ok(());
+        };
+        if self.privacy_mode {
+            event.operator.clear();
+        }
+        let Some(now) = UtcTime::now().await else {
+            return Ok(());
+        };
+        let event = SystemEvent {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now.and_utc().timestamp(),
+            event: Some(Event::LinkQualityEvent(event)),
+        };
+        if self.send_event(event).await?.is_ok()
+            && let Some(sampling) = &mut self.link_quality
+        {
+            sampling.stats.clear();
         }
-        let body = response.body();
-        if body.is_empty() {
-            info!("No response body");
+        Ok(())
+    }
+
+    async fn report_poll_stats(&mut self) -> Result<(), CellularError> {
+        let Some(event) = self.poll_stats.and_then(|(stats, limit)| stats.take_report(limit, Instant::now())) else {
+            return Ok(());
+        };
+        let Some(now) = UtcTime::now().await else {
+            return Ok(());
+        };
+        self.upload_event(SystemEvent {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now.and_utc().timestamp(),
+            event: Some(Event::PollStatsEvent(event)),
+        })
+        .await
+    }
+
+    /// Best effort, a rejected heartbeat is not retried before the next one is due.
+    async fn send_heartbeat(&mut self) -> Result<(), CellularError> {
+        let Some(heartbeat) = self.heartbeat.as_mut().filter(|heartbeat| Instant::now() >= heartbeat.next_beat) else {
+            return Ok(());
+        };
+        heartbeat.next_beat = Instant::now() + heartbeat.interval;
+        let queue_depth = self.upload_receiver.len() + self.pending_upload.is_some() as usize;
+        let Some(json) = (Heartbeat {
+            device_id: heartbeat.device_id.as_str(),
+            uptime_seconds: uptime_seconds(),
+            firmware: heartbeat.firmware,
+            queue_depth: queue_depth as u32,
+        })
+        .to_json() else {
+            warn!("Heartbeat does not fit => skipped");
+            return Ok(());
+        };
+        let headers = [("X-Token", crate::config::SOLAR_BACKEND_TOKEN), ("Content-Type", "application/json")];
+        // the backend answers with the commands for this device
+        let mut response = [0u8; 256];
+        let (status, len) = self.module.http_post(HEARTBEAT_URL, &headers, json.as_bytes(), &mut response).await?;
+        if status.is_ok() {
+            info!("Heartbeat sent");
+            handle_response(&response[..len], &mut self.accepted_version, &mut self.commands);
         } else {
-            let mut body_buffer = [0u8; 1024];
-            info!("Response body [{}]: {}", body.len(), body.read_as_str(&mut body_buffer).await?);
+            warn!("Heartbeat failed with status {}", status);
         }
         Ok(())
     }
-}
 
-#[cfg(test)]
-pub mod tests {
-    use chrono::NaiveDateTime;
-    use serial_test::serial;
-    use std::fs;
+    /// Runs the AT command asked for by the backend and uploads the response, once: a failed upload is not retried.
+    async fn run_at_command(&mut self) -> Result<(), CellularError> {
+        let Some(command) = self.commands.at.take() else {
+            return Ok(());
+        };
+        info!("Backend runs '{}'", command.command.as_str());
+        let result = self.module.run_at_command(&command.command, command.timeout).await;
+        if let Some(audit) = self.audit {
+            audit.record(CommandOrigin::Backend, "at", &command.command, result.as_ref().map(|_| ())).await;
+        }
+        let json = command.report(result.as_ref());
+        let headers = [("X-Token", crate::config::SOLAR_BACKEND_TOKEN), ("Content-Type", "application/json")];
+        let (status, _) = self.module.http_post(AT_URL, &headers, json.as_bytes(), &mut []).await?;
+        if !status.is_ok() {
+            warn!("AT response upload failed with status {}", status);
+        }
+        Ok(())
+    }
 
-    use super::*;
+    /// Switches the load output asked for by the backend, the VE.Direct runner records it in the audit log.
+    async fn switch_load(&mut self) {
+        let Some(output) = self.commands.load.take() else {
+            return;
+        };
+        let Some(load_switch) = self.load_switch else {
+            warn!("Backend switches the load output {} => no load switch", output.as_str());
+            return;
+        };
+        info!("Backend switches the load output {}", output.as_str());
+        match with_timeout(LOAD_SWITCH_TIMEOUT, switch_load(load_switch, output, CommandOrigin::Backend)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Switching the load output failed: {:?}", e),
+            Err(_) => warn!("Switching the load output timed out"),
+        }
+    }
+
+    async fn report_health(&mut self) -> Result<(), CellularError> {
+        let Some(report) = self.health_report.as_mut().filter(|report| Instant::now() >= report.next_report) else {
+            return Ok(());
+        };
+        let Some(now) = UtcTime::now().await else {
+            return Ok(());
+        };
+        report.next_report = Instant::now() + report.interval;
+        info!("CloudClient health {:?}", HEALTH.counters());
+        let event = HEALTH.to_event(Instant::now().as_secs() as u32, (report.stack_free)());
+        self.upload_event(SystemEvent {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now.and_utc().timestamp(),
+            event: Some(Event::HealthEvent(event)),
+        })
+        .await
+    }
+
+    /// Uploads the charger error changes in order, a change that failed stays pending for the next attempt.
+    async fn upload_charger_errors(&mut self) -> Result<(), CellularError> {
+        let Some(errors) = self.charger_errors else {
+            return Ok(());
+        };
+        let Some(now) = UtcTime::now().await else {
+            return Ok(());
+        };
+        while let Some(change) = self.pending_charger_error.take().or_else(|| errors.take()) {
+            let event = SystemEvent {
+                schema_version: SCHEMA_VERSION,
+                timestamp: (now - chrono::Duration::seconds(change.at.elapsed().as_secs() as i64)).and_utc().timestamp(),
+                event: Some(Event::ChargerErrorEvent(change.to_event())),
+            };
+            self.pending_charger_error = Some(change);
+            let status = self.send_event(event).await?;
+            if !status.is_ok() && !status.is_client_error() {
+                return Ok(());
+            }
+            self.pending_charger_error = None;
+        }
+        Ok(())
+    }
+
+    /// Uploads the history days one by one, a day that failed stays pending for the next idle period.
+    async fn upload_history(&mut self) -> Result<(), CellularError> {
+        let Some(history) = self.history else {
+            return Ok(());
+        };
+        let Some(now) = UtcTime::now().await else {
+            return Ok(());
+        };
+        while let Some(day) = self.pending_history_day.take().or_else(|| history.take()) {
+            let event = SystemEvent {
+                schema_version: SCHEMA_VERSION,
+                timestamp: (now - chrono::Duration::seconds(day.at.elapsed().as_secs() as i64)).and_utc().timestamp(),
+                event: Some(Event::HistoryDayEvent(day.to_event())),
+            };
+            self.pending_history_day = Some(day);
+            let status = self.send_event(event).await?;
+            if !status.is_ok() && !status.is_client_error() {
+                return Ok(());
+            }
+            self.pending_history_day = None;
+        }
+        Ok(())
+    }
+
+    /// Uploads the offered audit entries one by one, an entry that failed stays pending for the next idle period.
+    async fn upload_audit(&mut self) -> Result<(), CellularError> {
+        let Some(audit) = self.audit else {
+            return Ok(());
+        };
+        while let Some(entry) = self.pending_audit.take().or_else(|| audit.take_offered()) {
+            let sequence = entry.sequence;
+            let event = SystemEvent {
+                schema_version: SCHEMA_VERSION,
+                timestamp: entry.timestamp,
+                event: Some(Event::CommandAuditEvent(entry.to_event())),
+            };
+            self.pending_audit = Some(entry);
+            let status = self.send_event(event).await?;
+            if !status.is_ok() && !status.is_client_error() {
+                return Ok(());
+            }
+            if status.is_client_error() {
+                warn!("Audit entry #{} rejected with status {} => dropping", sequence, status);
+            }
+            self.pending_audit = None;
+            audit.acknowledge(sequence);
+            match with_timeout(AUDIT_OFFER_TIMEOUT, audit.wait_offered()).await {
+                Ok(entry) => self.pending_audit = Some(entry),
+                Err(_) => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks for a firmware update once the check interval elapsed and the running firmware is confirmed.
+    ///
+    /// Failures are only logged, the running firmware stays in place and the check is repeated with the next interval.
+    async fn check_firmware_update(&mut self) {
+        let Some(check) = &mut self.firmware_update else {
+            return;
+        };
+        if Instant::now() < check.next_check || !check.ota.booted() {
+            return;
+        }
+        check.next_check = Instant::now() + check.check_interval;
+        let (ota, current_version) = (check.ota, check.current_version);
+        match self.update_firmware(ota, current_version).await {
+            Ok(Some(version)) => info!("Firmware {} ready, swap on next reset", version),
+            Ok(None) => info!("Firmware {} is up to date", current_version),
+            Err(e) => warn!("Firmware update failed: {:?}", e),
+        }
+    }
+
+    /// Fetches the manifest and streams a newer image to the OTA runner, returns the version of the verified image.
+    async fn update_firmware(&mut self, ota: &'a Ota, current_version: u32) -> Result<Option<u32>, OtaError> {
+        let headers = [("X-Token", crate::config::SOLAR_BACKEND_TOKEN)];
+        let mut buffer = [0u8; FIRMWARE_MANIFEST_SIZE];
+        let (status, len) = self.module.http_get(FIRMWARE_URL, &headers, &mut BufferSink::new(&mut buffer)).await?;
+        if !status.is_ok() {
+            info!("No firmware manifest, status {}", status);
+            return Ok(None);
+        }
+        let manifest = core::str::from_utf8(&buffer[..len])
+            .ok()
+            .and_then(FirmwareManifest::parse)
+            .ok_or(OtaError::InvalidManifest)?;
+        if manifest.version <= current_version {
+            return Ok(None);
+        }
+        info!("Downloading firmware {} ({} bytes)", manifest.version, manifest.size);
+        let version = manifest.version;
+        let url = manifest.url.clone();
+        ota.reset_result();
+        ota.send(OtaMessage::Begin(manifest)).await;
+        let mut image_sink = ImageSink::new(ota, self.watchdog);
+        match self.module.http_get(url.as_str(), &headers, &mut image_sink).await {
+            Ok((status, _)) if status.is_ok() => {
+                image_sink.flush().await;
+                ota.send(OtaMessage::End).await;
+            }
+            Ok((status, _)) => {
+                ota.send(OtaMessage::Abort).await;
+                return Err(OtaError::Download(CellularError::HttpStatus(status)));
+            }
+            Err(e) => {
+                ota.send(OtaMessage::Abort).await;
+                return Err(e.into());
+            }
+        }
+        with_timeout(OTA_VERIFY_TIMEOUT, ota.wait_result())
+            .await
+            .map_err(|_| OtaError::Timeout)?
+            .map(|_| Some(version))
+    }
+
+    async fn upload_event(&mut self, event: SystemEvent) -> Result<(), CellularError> {
+        self.send_event(event).await?;
+        Ok(())
+    }
+
+    async fn send_event(&mut self, event: SystemEvent) -> Result<HttpStatusCode, CellularError> {
+        const BUFFER_SIZE: usize = SystemEvent::MAX_SIZE.expect("Size known at compile time");
+        let mut buffer = micropb::heapless::Vec::<u8, BUFFER_SIZE>::new();
+        let mut encoder = PbEncoder::new(&mut buffer);
+        event.encode(&mut encoder).map_err(|_| CellularError::Encoding())?;
+        let status = Self::post(
+            &mut self.module,
+            &mut self.accepted_version,
+            &mut self.commands,
+            self.envelope.as_mut(),
+            EVENT_URL,
+            buffer.as_slice(),
+            UploadEncoding::Protobuf,
+        )
+        .await?;
+        if status.is_ok() {
+            info!("Event sent successful");
+        } else {
+            warn!("Event send failed with status {}", status);
+        }
+        Ok(status)
+    }
+
+    async fn post(
+        module: &mut Modem,
+        accepted: &mut Option<u32>,
+        commands: &mut BackendCommands,
+        envelope: Option<&mut Envelope>,
+        url: &str,
+        body: &[u8],
+        encoding: UploadEncoding,
+    ) -> Result<HttpStatusCode, CellularError> {
+        let mut body_buffer = [0u8; 1024];
+        let mut headers = Vec::<(&str, &str), 4>::new();
+        let _ = headers.push(("X-Token", crate::config::SOLAR_BACKEND_TOKEN));
+        let _ = headers.push(("X-Proto-Version", PROTO_VERSION));
+        // protobuf is what the backend expects without a content type
+        if encoding == UploadEncoding::Cbor {
+            let _ = headers.push(("Content-Type", encoding.content_type()));
+        }
+        let body = match envelope {
+            Some(envelope) => {
+                let sealed = envelope.seal(body).ok_or(CellularError::Encoding())?;
+                let _ = headers.push((KEY_ID_HEADER, sealed.key_id));
+                sealed.body
+            }
+            None => body,
+        };
+        let (status, len) = module.http_post(url, &headers, body, &mut body_buffer).await?;
+        handle_response(&body_buffer[..len], accepted, commands);
+        Ok(status)
+    }
+}
+
+/// The schema version and the commands of the backend in a response body.
+fn handle_response(body: &[u8], accepted: &mut Option<u32>, commands: &mut BackendCommands) {
+    if body.is_empty() {
+        info!("No response body");
+        return;
+    }
+    let Ok(body) = core::str::from_utf8(body) else {
+        warn!("Response body [{}] not utf8", body.len());
+        return;
+    };
+    info!("Response body [{}]: {}", body.len(), body);
+    if let Some(version) = accepted_version(body) {
+        check_accepted_version(accepted, version);
+    }
+    if body.contains(UPLOAD_LOG_COMMAND) {
+        info!("Backend requests the log");
+        LOG_RING.request_upload();
+    }
+    if let Some(minutes) = number_after(body, IDENTIFY_KEY) {
+        IDENTIFY.start(Duration::from_secs(60 * minutes as u64));
+    }
+    if let Some(command) = RunAtCommand::parse(body) {
+        commands.at = Some(command);
+    }
+    if let Some(output) = string_after(body, LOAD_KEY).and_then(LoadOutput::parse) {
+        commands.load = Some(output);
+    }
+    if let Some(table) = QuirkTable::from_response(body) {
+        AT_QUIRKS.download(table);
+    }
+}
+
+/// The `"accepted_proto_version": <n>` of a backend response, `None` from backends before versioning.
+fn accepted_version(body: &str) -> Option<u32> {
+    number_after(body, ACCEPTED_VERSION_KEY)
+}
+
+/// The string value after `key` (which ends with the opening quote) in a response body, without escapes.
+pub(crate) fn string_after<'b>(body: &'b str, key: &str) -> Option<&'b str> {
+    let start = body.find(key)? + key.len();
+    let end = body[start..].find('"')?;
+    Some(&body[start..start + end])
+}
+
+/// The number after `key` in a response body.
+pub(crate) fn number_after(body: &str, key: &str) -> Option<u32> {
+    let start = body.find(key)? + key.len();
+    let value = body[start..].trim_start();
+    let end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+fn uptime_seconds() -> u32 {
+    Instant::now().as_secs() as u32
+}
+
+fn millis(duration: Duration) -> u32 {
+    duration.as_millis().min(u32::MAX as u64) as u32
+}
+
+/// Warns once per change, an older backend drops the fields it does not know.
+fn check_accepted_version(accepted: &mut Option<u32>, version: u32) {
+    if *accepted == Some(version) {
+        return;
+    }
+    *accepted = Some(version);
+    if version < SCHEMA_VERSION {
+        warn!("Backend accepts schema version {}, firmware sends {} => newer fields are ignored", version, SCHEMA_VERSION);
+    } else {
+        info!("Backend accepts schema version {} (firmware {})", version, SCHEMA_VERSION);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chrono::NaiveDateTime;
+    use core::cell::RefCell;
+    use embassy_futures::join::join;
+    use embassy_sync::channel::Channel;
+    use micropb::{MessageDecode, PbDecoder};
+    use serial_test::serial;
+    use std::{collections::VecDeque, fs};
+
+    use super::*;
+    use crate::{
+        at::{
+            AtCommandResponse,
+            gnss::GnssPosition,
+            identification::ModuleIdentity,
+            network::NetworkRegistrationState,
+            packet_domain::{PdpAuth, PdpType},
+            status_control::Rssi,
+        },
+        audit::tests::RamStore,
+        health::ResetReason,
+        net::cellular::{HttpBodySink, LinkQuality, NetworkPhases},
+        solar_monitor::scheduler::StalenessLimits,
+    };
+
+    type TestChannel = Channel<NoopRawMutex, Vec<u8, 16>, 4>;
+    type TestController<'a> = CloudController<'a, MockModem, NoopRawMutex, 16, 4>;
+
+    const RTC_TIME: &str = "2025-12-30 15:22:22";
+
+    #[derive(Default)]
+    struct MockModem {
+        calls: RefCell<std::vec::Vec<&'static str>>,
+        posts: std::vec::Vec<(std::string::String, std::vec::Vec<u8>)>,
+        post_results: VecDeque<Result<HttpStatusCode, CellularError>>,
+        startup_failures: u32,
+        /// APNs of the network startups.
+        startup_apns: std::vec::Vec<std::string::String>,
+        /// Time of a successful NTP sync, the sync fails without one.
+        ntp_time: Option<NaiveDateTime>,
+        /// Answers of the position queries, `None` once they are used up.
+        positions: VecDeque<Option<GnssPosition>>,
+        /// Headers of the last post.
+        post_headers: std::vec::Vec<(std::string::String, std::string::String)>,
+        /// Response body of every post.
+        response_body: &'static str,
+        /// Whether the module answers a resume.
+        resumable: bool,
+        network_phases: NetworkPhases,
+        /// Time every post takes.
+        post_delay: Duration,
+    }
+
+    impl MockModem {
+        fn record(&self, call: &'static str) {
+            self.calls.borrow_mut().push(call);
+        }
+
+        fn take_calls(&self) -> std::vec::Vec<&'static str> {
+            self.calls.take()
+        }
+
+        fn take_posts(&mut self) -> std::vec::Vec<(std::string::String, std::vec::Vec<u8>)> {
+            core::mem::take(&mut self.posts)
+        }
+    }
+
+    impl CellularModem for MockModem {
+        async fn power_cycle(&mut self) -> Result<(), CellularError> {
+            self.record("power_cycle");
+            Ok(())
+        }
+
+        async fn resume(&mut self) -> Result<bool, CellularError> {
+            self.record("resume");
+            Ok(self.resumable)
+        }
+
+        async fn power_down(&mut self) -> Result<(), CellularError> {
+            self.record("power_down");
+            Ok(())
+        }
+
+        async fn reset(&mut self) -> Result<(), CellularError> {
+            self.record("reset");
+            Ok(())
+        }
+
+        async fn startup_network(&mut self, apn: &str, _pdp_type: PdpType, _auth: &PdpAuth) -> Result<(), CellularError> {
+            self.record("startup_network");
+            self.startup_apns.push(apn.into());
+            if self.startup_failures > 0 {
+                self.startup_failures -= 1;
+                return Err(CellularError::Timeout);
+            }
+            Ok(())
+        }
+
+        async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError> {
+            self.record("query_real_time_clock");
+            Ok(NaiveDateTime::parse_from_str(RTC_TIME, "%Y-%m-%d %H:%M:%S").unwrap())
+        }
+
+        async fn sync_network_time(&mut self, _server: &str) -> Result<NaiveDateTime, CellularError> {
+            self.record("sync_network_time");
+            self.ntp_time.ok_or(CellularError::Timeout)
+        }
+
+        async fn set_real_time_clock(&self, _utc: &NaiveDateTime) -> Result<(), CellularError> {
+            self.record("set_real_time_clock");
+            Ok(())
+        }
+
+        async fn query_signal_quality(&self) -> Result<Rssi, CellularError> {
+            self.record("query_signal_quality");
+            Ok(Rssi::from_dbm(-71))
+        }
+
+        async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+            self.record("query_link_quality");
+            Ok(LinkQuality {
+                rssi: Rssi::from_dbm(-71),
+                ber: 0,
+                registration: NetworkRegistrationState::Registered,
+                operator: "Swisscom".try_into().unwrap(),
+            })
+        }
+
+        async fn query_identity(&self) -> Result<ModuleIdentity, CellularError> {
+            self.record("query_identity");
+            Ok(ModuleIdentity {
+                imei: "864663060123456".try_into().unwrap(),
+                imsi: "228012345678901".try_into().unwrap(),
+                iccid: "89410123456789012345".try_into().unwrap(),
+                firmware_revision: "A011B07A7670M7".try_into().unwrap(),
+            })
+        }
+
+        async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError> {
+            self.record("query_position");
+            Ok(self.positions.pop_front().flatten())
+        }
+
+        async fn sleep(&mut self) -> Result<(), CellularError> {
+            self.record("sleep");
+            Ok(())
+        }
+
+        async fn wake_up(&mut self) -> Result<(), CellularError> {
+            self.record("wake_up");
+            Ok(())
+        }
+
+        async fn http_post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError> {
+            self.record("http_post");
+            Timer::after(self.post_delay).await;
+            self.posts.push((url.into(), body.into()));
+            self.post_headers = headers.iter().map(|(name, value)| ((*name).into(), (*value).into())).collect();
+            let status = self.post_results.pop_front().unwrap_or(Ok(HttpStatusCode::new(200)))?;
+            let len = self.response_body.len().min(response.len());
+            response[..len].copy_from_slice(&self.response_body.as_bytes()[..len]);
+            Ok((status, len))
+        }
+
+        fn network_phases(&self) -> NetworkPhases {
+            self.network_phases
+        }
+
+        async fn http_get(&mut self, _url: &str, _headers: &[(&str, &str)], _sink: &mut impl HttpBodySink) -> Result<(HttpStatusCode, usize), CellularError> {
+            self.record("http_get");
+            Ok((HttpStatusCode::new(404), 0))
+        }
+
+        async fn http_head(&mut self, _url: &str, _headers: &[(&str, &str)]) -> Result<HttpStatusCode, CellularError> {
+            self.record("http_head");
+            Ok(HttpStatusCode::new(200))
+        }
+
+        async fn resolve(&mut self, _host: &str) -> Result<core::net::IpAddr, CellularError> {
+            self.record("resolve");
+            Ok(core::net::IpAddr::V4(core::net::Ipv4Addr::new(192, 0, 2, 1)))
+        }
+
+        async fn run_at_command(&mut self, _command: &str, _timeout: Duration) -> Result<AtCommandResponse, CellularError> {
+            self.record("run_at_command");
+            Ok(AtCommandResponse::new(Vec::from_iter(["+CSQ: 21,3".try_into().unwrap()])))
+        }
+    }
+
+    fn controller<'a>(channel: &'a TestChannel, modem: MockModem) -> TestController<'a> {
+        let timeouts = Timeouts {
+            upload_idle: Duration::from_millis(10),
+            modem_reset_retry: Duration::from_millis(1),
+            ..Default::default()
+        };
+        new(modem, channel.receiver(), timeouts)
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                jitter_percent: 0,
+            })
+            .cloud_controller
+    }
+
+    /// A controller that went through a successful startup, with the startup calls and posts already taken.
+    async fn connected_controller<'a>(channel: &'a TestChannel, modem: MockModem) -> TestController<'a> {
+        let mut controller = controller(channel, modem);
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+        controller.module.take_calls();
+        controller.module.take_posts();
+        controller
+    }
+
+    fn batch(data: &[u8]) -> Vec<u8, 16> {
+        Vec::from_slice(data).unwrap()
+    }
+
+    fn decode_event(body: &[u8]) -> Event {
+        let mut event = SystemEvent::default();
+        let mut decoder = PbDecoder::new(body);
+        event.decode(&mut decoder, body.len()).unwrap();
+        event.event.unwrap()
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_startup_connects_and_sends_startup_event() {
+        let channel = TestChannel::new();
+        let mut controller = controller(&channel, MockModem::default());
+        HEALTH.set_reset_reason(ResetReason::Watchdog);
+
+        controller.once().await;
+
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert_eq!(
+            controller.module.take_calls(),
+            [
+                "power_cycle",
+                "startup_network",
+                "sync_network_time",
+                "query_real_time_clock",
+                "query_signal_quality",
+                "query_identity",
+                "http_post"
+            ]
+        );
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].0, EVENT_URL);
+        let Event::StartupEvent(startup) = decode_event(&posts[0].1) else {
+            panic!("startup event expected");
+        };
+        assert_eq!(startup.rssi, -71);
+        assert_eq!(startup.imei.as_str(), "864663060123456");
+        assert_eq!(startup.iccid.as_str(), "89410123456789012345");
+        assert_eq!(startup.reset_reason, ResetReason::Watchdog as u32);
+        let now = UtcTime::now().await.unwrap();
+        assert_eq!(now, NaiveDateTime::parse_from_str(RTC_TIME, "%Y-%m-%d %H:%M:%S").unwrap());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_startup_phases() {
+        let channel = TestChannel::new();
+        let modem = MockModem {
+            network_phases: NetworkPhases {
+                sim_ready: Some(Duration::from_millis(800)),
+                registration: Some(Duration::from_secs(12)),
+                context_activation: None,
+            },
+            post_delay: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let mut controller = connected_controller(&channel, modem).await;
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        assert_eq!(controller.module.take_posts()[0].0, READING_URL);
+
+        controller.state = CloudClientState::Startup;
+        controller.once().await;
+        let Event::StartupEvent(startup) = decode_event(&controller.module.take_posts()[0].1) else {
+            panic!("startup event expected");
+        };
+        assert_eq!((startup.sim_ready_ms, startup.registration_ms, startup.context_activation_ms), (800, 12_000, 0));
+        // the first reading upload after the previous startup
+        assert!(startup.first_upload_ms >= 20, "{}", startup.first_upload_ms);
+        assert!(controller.first_upload.is_none());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_startup_prefers_ntp_time() {
+        let ntp_time = NaiveDateTime::parse_from_str("2025-12-30 15:22:25", "%Y-%m-%d %H:%M:%S").unwrap();
+        let channel = TestChannel::new();
+        let mut controller = controller(
+            &channel,
+            MockModem {
+                ntp_time: Some(ntp_time),
+                ..Default::default()
+            },
+        );
+
+        controller.once().await;
+
+        assert_eq!(controller.state, CloudClientState::Connected);
+        let calls = controller.module.take_calls();
+        assert!(calls.contains(&"sync_network_time"));
+        assert!(!calls.contains(&"query_real_time_clock"));
+        assert_eq!(UtcTime::now().await, Some(ntp_time));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_startup_failure_resets_module() {
+        let channel = TestChannel::new();
+        let mut controller = controller(
+            &channel,
+            MockModem {
+                startup_failures: 1,
+                ..Default::default()
+            },
+        );
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Startup);
+        assert_eq!(controller.module.take_calls(), ["power_cycle", "startup_network", "reset"]);
+        assert!(controller.module.take_posts().is_empty());
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_startup_resumes_module() {
+        let channel = TestChannel::new();
+        let mut record = PowerCycleRecord::new();
+        record.cycled(0);
+        let mut controller = new(
+            MockModem {
+                resumable: true,
+                ..Default::default()
+            },
+            channel.receiver(),
+            Timeouts::default(),
+        )
+        .with_power_cycle_record(&mut record, Duration::from_secs(60))
+        .cloud_controller;
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert_eq!(controller.module.take_calls()[..2], ["resume", "startup_network"]);
+
+        // only the first startup after the reset resumes
+        controller.state = CloudClientState::Startup;
+        controller.power_cycles.as_mut().unwrap().min_interval = Duration::from_secs(0);
+        controller.once().await;
+        assert_eq!(controller.module.take_calls()[..2], ["power_cycle", "startup_network"]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_upload_idle_timeout() {
+        let channel = TestChannel::new();
+        let timeouts = Timeouts {
+            upload_idle: Duration::from_millis(60),
+            ..Default::default()
+        };
+        let mut controller = new(MockModem::default(), channel.receiver(), timeouts).cloud_controller;
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+
+        let started = Instant::now();
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_power_cycle_interval() {
+        let channel = TestChannel::new();
+        let mut record = PowerCycleRecord::new();
+        let mut controller = new(MockModem::default(), channel.receiver(), Timeouts::default())
+            .with_power_cycle_record(&mut record, Duration::from_secs(2))
+            .cloud_controller;
+        let guard = controller.power_cycles.as_mut().unwrap();
+        guard.record.cycled(uptime_seconds());
+        guard.record.powered_down();
+
+        let start = Instant::now();
+        controller.power_on().await.unwrap();
+        // the record counts whole seconds
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(controller.module.take_calls(), ["power_cycle"]);
+        assert!(controller.power_cycles.as_ref().unwrap().record.modem_on());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_startup_failure_cycles_apn_profiles() {
+        let channel = TestChannel::new();
+        let mut controller = controller(
+            &channel,
+            MockModem {
+                startup_failures: 2,
+                ..Default::default()
+            },
+        );
+        controller.apn = ApnSelector::new(&[ApnProfile::new("iot.1nce.net", PdpType::Ipv4v6).unwrap()]);
+
+        for _ in 0..3 {
+            controller.once().await;
+        }
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert_eq!(controller.module.startup_apns, ["iot.1nce.net", "gprs.swisscom.ch", "internet"]);
+        let stats: std::vec::Vec<_> = controller.apn.stats().map(|(_, stats)| (stats.successes, stats.attempts)).collect();
+        assert_eq!(stats, [(0, 1), (0, 1), (1, 1)]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_upload_retry_on_server_error() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.module.post_results = [Ok(HttpStatusCode::new(500)), Ok(HttpStatusCode::new(200))].into();
+        channel.send(batch(&[1, 2, 3])).await;
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert_eq!(controller.upload_failures, 1);
+        assert!(controller.pending_upload.is_some());
+
+        controller.once().await;
+        assert_eq!(controller.upload_failures, 0);
+        assert!(controller.pending_upload.is_none());
+
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        for (url, body) in posts {
+            assert_eq!(url, READING_URL);
+            assert_eq!(body, [1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn check_jitter_seed() {
+        let channel = TestChannel::new();
+        let jitter = |seed| {
+            let mut controller = new(MockModem::default(), channel.receiver(), Timeouts::default())
+                .with_jitter_seed(seed)
+                .cloud_controller;
+            [controller.jitter.next(), controller.jitter.next()]
+        };
+        assert_eq!(jitter(0x1234_5678), jitter(0x1234_5678));
+        assert_ne!(jitter(0x1234_5678), jitter(0x1234_5679));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_upload_retries_exhausted_resets_module_and_keeps_batch() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.module.post_results = [Ok(HttpStatusCode::new(500)), Err(CellularError::Timeout)].into();
+        channel.send(batch(&[7])).await;
+
+        controller.once().await;
+        controller.once().await;
+
+        assert_eq!(controller.state, CloudClientState::Startup);
+        assert_eq!(controller.module.take_calls(), ["http_post", "http_post", "reset"]);
+        assert_eq!(controller.pending_upload.as_deref(), Some([7].as_slice()));
+
+        controller.once().await;
+        controller.once().await;
+        assert!(controller.pending_upload.is_none());
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.last().unwrap(), &(READING_URL.into(), std::vec![7]));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_upload_client_error_drops_batch() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.module.post_results = [Ok(HttpStatusCode::new(400))].into();
+        channel.send(batch(&[1])).await;
+
+        controller.once().await;
+
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert_eq!(controller.upload_failures, 0);
+        assert!(controller.pending_upload.is_none());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_upload_content_type() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        assert!(!controller.module.post_headers.iter().any(|(name, _)| name == "Content-Type"));
+
+        controller.upload_encoding = UploadEncoding::Cbor;
+        channel.send(batch(&[2])).await;
+        controller.once().await;
+        assert!(controller.module.post_headers.contains(&("Content-Type".into(), "application/cbor".into())));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_sealed_upload() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.envelope = Some(Envelope::new(EnvelopeKey { id: 7, key: [0x11; 32] }, [0x22; 32]));
+        channel.send(batch(&[1, 2, 3])).await;
+        controller.once().await;
+        assert!(controller.module.post_headers.contains(&(KEY_ID_HEADER.into(), "7".into())));
+        let (url, body) = controller.module.take_posts().pop().unwrap();
+        assert_eq!(url, READING_URL);
+        assert_eq!(body.len(), 3 + crate::solar_monitor::envelope::ENVELOPE_OVERHEAD);
+        assert_ne!(body[crate::solar_monitor::envelope::NONCE_SIZE..][..3], [1, 2, 3]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_heartbeat() {
+        let channel = TestChannel::new();
+        let mut controller = controller(&channel, MockModem::default());
+        controller.heartbeat = Some(HeartbeatReport {
+            interval: Duration::from_secs(3600),
+            next_beat: Instant::now(),
+            firmware: "0.4.2",
+            device_id: String::new(),
+        });
+        controller.once().await;
+        controller.module.take_posts();
+
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].0, HEARTBEAT_URL);
+        let json = std::str::from_utf8(&posts[0].1).unwrap();
+        assert!(json.starts_with(r#"{"id":"864663060123456","up":"#), "{}", json);
+        assert!(json.ends_with(r#","fw":"0.4.2","q":1}"#), "{}", json);
+        assert_eq!(posts[1].0, READING_URL);
+
+        // not due before the interval passed
+        channel.send(batch(&[2])).await;
+        controller.once().await;
+        assert_eq!(controller.module.take_posts().len(), 1);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_proto_version_negotiation() {
+        let channel = TestChannel::new();
+        let modem = MockModem {
+            response_body: "{\"accepted_proto_version\": 1}",
+            ..Default::default()
+        };
+        let mut controller = connected_controller(&channel, modem).await;
+        assert_eq!(controller.accepted_version, Some(1));
+        channel.send(batch(&[1])).await;
+
+        controller.once().await;
+
+        let version = std::string::String::from(PROTO_VERSION);
+        assert!(controller.module.post_headers.contains(&("X-Proto-Version".into(), version)));
+        assert!(controller.pending_upload.is_none());
+        assert_eq!(accepted_version("{\"accepted_proto_version\":12,\"ok\":true}"), Some(12));
+        assert_eq!(accepted_version(""), None);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_log_upload_command() {
+        let channel = TestChannel::new();
+        let modem = MockModem {
+            response_body: "{\"accepted_proto_version\":10,\"upload_log\":true}",
+            ..Default::default()
+        };
+        LOG_RING.take_upload_request();
+        connected_controller(&channel, modem).await;
+        assert!(LOG_RING.take_upload_request());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_remote_at_command() {
+        let channel = TestChannel::new();
+        let modem = MockModem {
+            response_body: r#"{"accepted_proto_version":10,"run_at":"AT+CSQ"}"#,
+            ..Default::default()
+        };
+        let mut controller = connected_controller(&channel, modem).await;
+        assert_eq!(controller.commands.at.as_ref().map(|command| command.command.as_str()), Some("AT+CSQ"));
+
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        assert!(controller.module.take_calls().contains(&"run_at_command"));
+        let posts = controller.module.take_posts();
+        assert_eq!(posts[0].0, AT_URL);
+        assert_eq!(std::str::from_utf8(&posts[0].1).unwrap(), r#"{"command":"AT+CSQ","result":"OK","lines":["+CSQ: 21,3"]}"#);
+        assert_eq!(posts[1].0, READING_URL);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_remote_load_switch() {
+        let channel = TestChannel::new();
+        let load_switch = LoadSwitch::new();
+        let modem = MockModem {
+            response_body: r#"{"accepted_proto_version":10,"load":"off"}"#,
+            ..Default::default()
+        };
+        let mut controller = connected_controller(&channel, modem).await;
+        controller.load_switch = Some(&load_switch);
+        assert_eq!(controller.commands.load, Some(LoadOutput::Off));
+
+        // stands in for the VE.Direct runner
+        let ve_direct = async {
+            let request = load_switch.requested.wait().await;
+            load_switch.done.signal(Ok(()));
+            request
+        };
+        channel.send(batch(&[1])).await;
+        let ((), request) = embassy_futures::join::join(controller.once(), ve_direct).await;
+        assert_eq!((request.output, request.origin), (LoadOutput::Off, CommandOrigin::Backend));
+        assert_eq!(controller.module.take_posts().last().map(|post| post.0.as_str()), Some(READING_URL));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_identify_command() {
+        let channel = TestChannel::new();
+        let modem = MockModem {
+            response_body: r#"{"accepted_proto_version":10,"identify":10}"#,
+            ..Default::default()
+        };
+        IDENTIFY.stop();
+        connected_controller(&channel, modem).await;
+        assert!(IDENTIFY.is_active(Instant::now() + Duration::from_secs(9 * 60)));
+        assert!(!IDENTIFY.is_active(Instant::now() + Duration::from_secs(11 * 60)));
+        assert_eq!(IDENTIFY.device_id().as_str(), "864663060123456");
+        IDENTIFY.stop();
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_queue_drains_in_order() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        for i in 1..=3 {
+            channel.send(batch(&[i])).await;
+        }
+
+        for _ in 1..=3 {
+            controller.once().await;
+        }
+
+        let bodies: std::vec::Vec<_> = controller
+            .module
+            .take_posts()
+            .into_iter()
+            .map(|(url, body)| (url == READING_URL, body))
+            .collect();
+        assert_eq!(bodies, [(true, std::vec![1]), (true, std::vec![2]), (true, std::vec![3])]);
+        assert_eq!(controller.state, CloudClientState::Connected);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_privacy_mode() {
+        let channel = TestChannel::new();
+        let mut controller = controller(&channel, MockModem::default());
+        controller.privacy_mode = true;
+        controller.position_report = PositionReport::Pending;
+        controller.link_quality = Some(LinkQualitySampling {
+            sample_interval: Duration::from_millis(0),
+            next_sample: Instant::now(),
+            stats: LinkQualityStats::default(),
+        });
+
+        controller.once().await;
+        let Event::StartupEvent(startup) = decode_event(&controller.module.take_posts()[0].1) else {
+            panic!("startup event expected");
+        };
+        assert_eq!(startup.imei.as_str(), "864663060123456");
+        assert!(startup.imsi.is_empty() && startup.iccid.is_empty());
+
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        assert!(!controller.module.take_calls().contains(&"query_position"));
+        let events: std::vec::Vec<_> = controller.module.take_posts().iter().skip(1).map(|(_, body)| decode_event(body)).collect();
+        assert!(!events.iter().any(|event| matches!(event, Event::PositionEvent(_))));
+        let Some(Event::LinkQualityEvent(link)) = events.first() else {
+            panic!("link quality event expected");
+        };
+        assert!(link.operator.is_empty());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_position_reported_once() {
+        let fix_time = NaiveDateTime::parse_from_str("2025-12-30 15:20:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let position = GnssPosition {
+            latitude: 47.3769,
+            longitude: 8.5417,
+            altitude: 412.0,
+            fix_time,
+        };
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.position_report = PositionReport::Pending;
+        controller.module.positions = [None, Some(position)].into();
+
+        controller.once().await;
+        assert_eq!(controller.module.take_posts().len(), 1);
+        assert_eq!(controller.position_report, PositionReport::Pending);
+
+        controller.state = CloudClientState::Connected;
+        controller.once().await;
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        let Event::PositionEvent(event) = decode_event(&posts[0].1) else {
+            panic!("position event expected");
+        };
+        assert_eq!(event.latitude, 47.3769);
+        assert_eq!(event.fix_timestamp, fix_time.and_utc().timestamp());
+        assert_eq!(controller.position_report, PositionReport::Reported);
+
+        controller.module.take_calls();
+        controller.state = CloudClientState::Connected;
+        controller.once().await;
+        assert!(!controller.module.take_calls().contains(&"query_position"));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_audit_entries_uploaded() {
+        let audit = Audit::new();
+        let mut store = RamStore::new(8);
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.audit = Some(&audit);
+        audit.record::<()>(CommandOrigin::Backend, "load", "on", Ok(())).await;
+        audit.record(CommandOrigin::Rule, "load", "off", Err("Timeout")).await;
+
+        embassy_futures::select::select(audit.runner(&mut store).run(), async {
+            Timer::after_millis(10).await;
+            controller.once().await;
+        })
+        .await;
+
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 3);
+        let audited: std::vec::Vec<_> = posts[..2]
+            .iter()
+            .map(|(_, body)| match decode_event(body) {
+                Event::CommandAuditEvent(event) => (event.sequence, event.parameters.as_str().to_owned(), event.success),
+                _ => panic!("audit event expected"),
+            })
+            .collect();
+        assert_eq!(audited, [(0, "on".to_owned(), true), (1, "off".to_owned(), false)]);
+        assert!(controller.pending_audit.is_none());
+        assert_eq!(store.cursors.upload, 2);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_history_days_uploaded() {
+        let history = DailyHistory::new();
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.history = Some(&history);
+        for (sequence, days_ago) in [(412, 1), (411, 2)] {
+            history.publish(HistoryDay {
+                sequence,
+                days_ago,
+                solar_yield_wh: 1_230,
+                consumed_wh: 120,
+                max_battery_voltage_mv: 14_320,
+                min_battery_voltage_mv: 12_180,
+                max_power_w: 312,
+                max_battery_current_ma: 18_700,
+                max_panel_voltage_mv: 43_210,
+                bulk_minutes: 95,
+                absorption_minutes: 60,
+                float_minutes: 240,
+                errors: [0; 4],
+                at: Instant::now(),
+            });
+        }
+
+        controller.once().await;
+
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 3);
+        let days: std::vec::Vec<_> = posts[..2]
+            .iter()
+            .map(|(_, body)| match decode_event(body) {
+                Event::HistoryDayEvent(event) => (event.day_sequence, event.days_ago, event.solar_yield),
+                _ => panic!("history day event expected"),
+            })
+            .collect();
+        assert_eq!(days, [(412, 1, 1_230), (411, 2, 1_230)]);
+        assert!(controller.pending_history_day.is_none());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_charger_error_uploaded_first() {
+        use crate::sensor::ve_direct::charger_error::ErrorDebounce;
+
+        let errors = ChargerErrors::new();
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.charger_errors = Some(&errors);
+        let mut debounce = ErrorDebounce::default();
+        for _ in 0..3 {
+            if let Some(event) = debounce.update(2, Instant::now()) {
+                errors.publish(event);
+            }
+        }
+        channel.send(batch(&[1])).await;
+
+        controller.once().await;
+
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        let Event::ChargerErrorEvent(event) = decode_event(&posts[0].1) else {
+            panic!("charger error event expected");
+        };
+        assert_eq!((event.code, event.previous_code), (2, 0));
+        assert_eq!(posts[1].0, READING_URL);
+        assert!(controller.pending_charger_error.is_none());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_net_test() {
+        let net_test = NetTest::new();
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.net_test = Some(&net_test);
+
+        let (report, _) = join(net_test.run(), controller.once()).await;
+        assert!(report.passed());
+        assert_eq!(report.link.unwrap().operator.as_str(), "Swisscom");
+        assert!(matches!(report.dns, Some(Ok(_))));
+        assert_eq!(report.http.unwrap().unwrap().0, HttpStatusCode::new(200));
+        assert_eq!(controller.module.take_calls()[..3], ["query_link_quality", "resolve", "http_head"]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_net_test_startup_failure() {
+        let net_test = NetTest::new();
+        let channel = TestChannel::new();
+        let mut controller = controller(
+            &channel,
+            MockModem {
+                startup_failures: 1,
+                ..Default::default()
+            },
+        );
+        controller.net_test = Some(&net_test);
+
+        let (report, _) = join(net_test.run(), controller.once()).await;
+        assert!(!report.passed());
+        assert_eq!(report.link, Err(CellularError::Timeout));
+        assert_eq!(report.http, None);
+        assert_eq!(controller.state, CloudClientState::Startup);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_link_quality_reported_before_sleep() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.link_quality = Some(LinkQualitySampling {
+            sample_interval: Duration::from_millis(0),
+            next_sample: Instant::now(),
+            stats: LinkQualityStats::default(),
+        });
+
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        assert_eq!(controller.module.take_calls(), ["query_link_quality", "http_post"]);
+        controller.module.take_posts();
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        let Event::LinkQualityEvent(event) = decode_event(&posts[0].1) else {
+            panic!("link quality event expected");
+        };
+        assert_eq!(event.samples, 2);
+        assert_eq!((event.rssi_min, event.rssi_avg, event.rssi_max), (-71, -71, -71));
+        assert_eq!(event.operator.as_str(), "Swisscom");
+        assert!(controller.link_quality.as_ref().unwrap().stats.is_empty());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_poll_stats_reported_before_sleep() {
+        let stats = PollStats::new();
+        stats.take_report(Duration::from_millis(5), Instant::from_millis(0));
+        stats.exec_begin(0x2000_0200, Instant::from_millis(10));
+        stats.exec_end(0x2000_0200, Instant::from_millis(30));
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.poll_stats = Some((&stats, Duration::from_millis(5)));
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        let Event::PollStatsEvent(event) = decode_event(&posts[0].1) else {
+            panic!("poll stats event expected");
+        };
+        assert_eq!(event.limit_us, 5_000);
+        assert_eq!(event.offenders[0].task_id, 0x2000_0200);
+        assert_eq!(event.offenders[0].longest_poll_us, 20_000);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_health_report_cadence() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.health_report = Some(HealthReport {
+            interval: Duration::from_secs(3_600),
+            next_report: Instant::now(),
+            stack_free: || 2_048,
+        });
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 2);
+        let Event::HealthEvent(event) = decode_event(&posts[0].1) else {
+            panic!("health event expected");
+        };
+        assert_eq!(event.stack_free_bytes, 2_048);
+
+        // not due again before the interval
+        controller.state = CloudClientState::Connected;
+        controller.once().await;
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 1);
+        assert!(matches!(decode_event(&posts[0].1), Event::OfflineEvent(_)));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_low_battery_delays_wake_up() {
+        let voltage = embassy_sync::watch::Watch::<NoopRawMutex, f32, 1>::new();
+        voltage.sender().send(11.2);
+        let policy = BatteryPolicy {
+            throttled_interval: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.battery = Some((BatteryThrottle::new(policy), voltage.dyn_anon_receiver()));
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+
+        channel.send(batch(&[1])).await;
+        let started = Instant::now();
+        controller.once().await;
+        assert!(started.elapsed() >= Duration::from_millis(45));
+        assert_eq!(controller.state, CloudClientState::Connected);
+
+        // a full queue wakes the module right away
+        controller.state = CloudClientState::Sleeping;
+        controller.slept_at = Instant::now();
+        controller.battery.as_mut().unwrap().0 = BatteryThrottle::new(BatteryPolicy {
+            throttled_interval: Duration::from_secs(60),
+            ..Default::default()
+        });
+        for i in 0..4 {
+            channel.try_send(batch(&[i])).ok();
+        }
+        let started = Instant::now();
+        controller.once().await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(controller.state, CloudClientState::Connected);
+
+        // recovered battery, no throttling
+        voltage.sender().send(12.8);
+        assert_eq!(controller.battery_min_sleep(), None);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_sleep_wake_cycle() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        assert_eq!(controller.module.take_calls(), ["query_signal_quality", "http_post", "sleep"]);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].0, EVENT_URL);
+        assert!(matches!(decode_event(&posts[0].1), Event::OfflineEvent(OfflineEvent { rssi: -71, .. })));
+
+        channel.send(batch(&[9])).await;
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert!(controller.wake_lock.is_some());
+        assert_eq!(controller.module.take_calls(), ["wake_up", "query_signal_quality", "http_post"]);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 1);
+        assert!(matches!(decode_event(&posts[0].1), Event::OnlineEvent(OnlineEvent { rssi: -71, .. })));
+
+        controller.once().await;
+        assert_eq!(controller.module.take_posts(), [(READING_URL.into(), std::vec![9])]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_heartbeat_while_sleeping() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        controller.module.take_calls();
+        controller.module.take_posts();
+
+        controller.heartbeat = Some(HeartbeatReport {
+            interval: Duration::from_secs(3600),
+            next_beat: Instant::now(),
+            firmware: "0.4.2",
+            device_id: String::new(),
+        });
+        controller.once().await;
+        // no online and offline events, the module sleeps again right away
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        assert_eq!(controller.module.take_calls(), ["wake_up", "http_post", "sleep"]);
+        assert_eq!(controller.module.take_posts()[0].0, HEARTBEAT_URL);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_modem_awake_scope() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        controller.module.take_calls();
+
+        // a failed scope restores the sleep as well
+        controller.module.post_results = [Err(CellularError::Timeout)].into();
+        let result = controller
+            .with_modem_awake(async |controller| controller.module.http_post(EVENT_URL, &[], &[], &mut []).await)
+            .await;
+        assert_eq!(result, Err(CellularError::Timeout));
+        assert_eq!(controller.module.take_calls(), ["wake_up", "http_post", "sleep"]);
+
+        // a cancelled scope leaves the module awake until the next restore
+        let cancelled = embassy_futures::select::select(
+            controller.with_modem_awake(async |_| core::future::pending::<Result<(), CellularError>>().await),
+            Timer::after_millis(10),
+        );
+        cancelled.await;
+        assert_eq!(controller.module.take_calls(), ["wake_up"]);
+        channel.send(batch(&[1])).await;
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert_eq!(controller.module.take_calls(), ["sleep", "wake_up", "query_signal_quality", "http_post"]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_sleep_until_next_deadline() {
+        let channel = TestChannel::new();
+        let scheduler = UploadScheduler::new(StalenessLimits {
+            metrics: Duration::from_millis(300),
+            log: Duration::from_secs(3600),
+        });
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.scheduler = Some(&scheduler);
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::Sleeping);
+        let slept_at = Instant::now();
+        assert!(controller.next_wake_up(None) >= slept_at + MAX_SLEEP - Duration::from_secs(1));
+
+        // the heartbeat is due before the deferred metrics
+        controller.heartbeat = Some(HeartbeatReport {
+            interval: Duration::from_secs(3600),
+            next_beat: slept_at + Duration::from_millis(100),
+            firmware: "0.4.2",
+            device_id: String::new(),
+        });
+        scheduler.defer(UploadClass::Metrics, b"m").unwrap();
+        assert_eq!(controller.next_wake_up(None), slept_at + Duration::from_millis(100));
+        assert_eq!(controller.next_wake_up(Some(slept_at)), slept_at);
+
+        // woken by the deadline, not by the feed interval of the watchdog
+        controller.wait_for_wake_up(None).await;
+        let elapsed = slept_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(100) && elapsed < FEED_INTERVAL, "{:?}", elapsed);
+        controller.heartbeat = None;
+        controller.wait_for_wake_up(None).await;
+        let elapsed = slept_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < FEED_INTERVAL, "{:?}", elapsed);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_power_off_until_upload_window() {
+        let channel = TestChannel::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.power_off = Some(UploadWindow::new(Duration::from_millis(100), Duration::from_millis(40)));
+
+        channel.send(batch(&[1])).await;
+        let received = Instant::now();
+        controller.once().await;
+        assert_eq!(controller.module.take_calls(), ["http_post"]);
+        controller.module.take_posts();
+
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::PoweredOff);
+        assert_eq!(controller.module.take_calls(), ["query_signal_quality", "http_post", "power_down"]);
+        controller.module.take_posts();
+
+        controller.once().await;
+        assert!(received.elapsed() >= Duration::from_millis(55));
+        assert_eq!(controller.state, CloudClientState::Connected);
+        assert!(controller.wake_lock.is_some());
+        assert_eq!(
+            controller.module.take_calls(),
+            [
+                "power_cycle",
+                "startup_network",
+                "sync_network_time",
+                "query_real_time_clock",
+                "query_signal_quality",
+                "http_post"
+            ]
+        );
+        let posts = controller.module.take_posts();
+        assert!(matches!(decode_event(&posts[0].1), Event::OnlineEvent(OnlineEvent { rssi: -71, .. })));
+
+        // a batch arriving before the window powers the module on right away
+        controller.once().await;
+        assert_eq!(controller.state, CloudClientState::PoweredOff);
+        controller.module.take_calls();
+        channel.send(batch(&[2])).await;
+        let started = Instant::now();
+        controller.once().await;
+        assert!(started.elapsed() < Duration::from_millis(40));
+        assert_eq!(controller.state, CloudClientState::Connected);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_power_off_flushes_partial_batch() {
+        let channel = TestChannel::new();
+        let flush = Flush::new();
+        let mut controller = connected_controller(&channel, MockModem::default()).await;
+        controller.power_off = Some(UploadWindow::new(Duration::from_secs(60), Duration::from_secs(10)));
+        controller.flush = Some(&flush);
+
+        // stands in for the upload runner with a partial batch
+        let upload_runner = async {
+            flush.requested.wait().await;
+            channel.send(batch(&[7])).await;
+            flush.done.signal(());
+        };
+        embassy_futures::join::join(controller.once(), upload_runner).await;
+        assert_eq!(controller.state, CloudClientState::Connected);
+        let posts = controller.module.take_posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].0, READING_URL);
+        controller.module.take_calls();
+
+        // nothing left to flush => power off
+        let upload_runner = async {
+            flush.requested.wait().await;
+            flush.done.signal(());
+        };
+        embassy_futures::join::join(controller.once(), upload_runner).await;
+        assert_eq!(controller.state, CloudClientState::PoweredOff);
+        assert_eq!(controller.module.take_calls(), ["query_signal_quality", "http_post", "power_down"]);
+    }
 
     #[serial(bt_time)]
     #[tokio::test]
@@ -197,13 +2659,16 @@ pub mod tests {
     #[serial(bt_time)]
     #[tokio::test]
     #[ignore]
+    #[allow(clippy::field_reassign_with_default)]
     async fn check_startup_event() {
         let startup = NaiveDateTime::parse_from_str("2025-12-30 15:22:22", "%Y-%m-%d %H:%M:%S").unwrap();
         let mut event = SystemEvent::default();
+        event.schema_version = SCHEMA_VERSION;
         event.timestamp = startup.and_utc().timestamp();
         event.event = Some(Event::StartupEvent(StartupEvent {
             uptime_seconds: 123,
             rssi: -65,
+            ..Default::default()
         }));
         let mut body_data = std::vec::Vec::default();
         let mut encoder = PbEncoder::new(&mut body_data);
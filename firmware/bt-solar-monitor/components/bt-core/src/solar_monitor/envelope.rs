@@ -0,0 +1,209 @@
+//! End-to-end encryption of the protobuf uploads, for backends behind third party relays.
+//!
+//! With an [`EnvelopeKey`] in the config store under [`ENVELOPE_KEY`] the cloud client seals
+//! the readings, the events and the deferred uploads with XChaCha20-Poly1305 before the POST,
+//! see `solar_monitor::cloud::Runner::with_envelope`. The body is the random nonce, the
+//! ciphertext and the tag, the [`KEY_ID_HEADER`] names the key, so a relay or a plain
+//! `http://` hop sees neither the readings nor can it alter them unnoticed. The JSON of the
+//! heartbeat and the remote AT results stay readable.
+//!
+//! The AEAD is the `chacha20poly1305` crate, this module only frames the body and derives the
+//! nonces: the ChaCha20 keystream of a seed from the hardware RNG at boot, the large nonce of
+//! XChaCha20 makes a collision of random nonces negligible.
+
+use core::fmt::Write as _;
+
+use chacha20::{
+    ChaCha20,
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, aead::AeadInPlace};
+use heapless::{String, Vec};
+use micropb::MessageEncode;
+
+use crate::{
+    proto::bt_::solar_::{SystemEvent, Upload},
+    solar_monitor::scheduler::LOW_PRIORITY_PAYLOAD_SIZE,
+    storage::{ConfigKey, ConfigValue},
+};
+
+pub const ENVELOPE_KEY: ConfigKey<EnvelopeKey> = ConfigKey::new("cloud", "envelope_key");
+
+/// Header with the [`EnvelopeKey::id`] of a sealed body, in decimal.
+pub const KEY_ID_HEADER: &str = "X-Key-Id";
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 24;
+pub const TAG_SIZE: usize = 16;
+/// Nonce before and tag after the ciphertext.
+pub const ENVELOPE_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// The largest body the cloud client seals, a readings batch or an event.
+pub const SEALED_BODY_SIZE: usize =
+    max(max(Upload::MAX_SIZE.expect("Size known at compile time"), SystemEvent::MAX_SIZE.expect("Size known at compile time")), LOW_PRIORITY_PAYLOAD_SIZE)
+        + ENVELOPE_OVERHEAD;
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// The per device key, the backend knows it under its id.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EnvelopeKey {
+    pub id: u32,
+    pub key: [u8; KEY_SIZE],
+}
+
+/// Only the id, the key stays out of the log.
+impl core::fmt::Debug for EnvelopeKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EnvelopeKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for EnvelopeKey {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "EnvelopeKey {{ id: {}, .. }}", self.id)
+    }
+}
+
+/// The id little endian, the key.
+impl ConfigValue for EnvelopeKey {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let entry = buffer.get_mut(..4 + KEY_SIZE)?;
+        entry[..4].copy_from_slice(&self.id.to_le_bytes());
+        entry[4..].copy_from_slice(&self.key);
+        Some(entry.len())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (id, key) = data.split_first_chunk::<4>()?;
+        Some(Self {
+            id: u32::from_le_bytes(*id),
+            key: key.try_into().ok()?,
+        })
+    }
+}
+
+/// A sealed body and the value of its [`KEY_ID_HEADER`].
+pub struct Sealed<'a> {
+    pub key_id: &'a str,
+    pub body: &'a [u8],
+}
+
+/// Seals the bodies of the cloud client, see the module documentation.
+pub struct Envelope {
+    cipher: XChaCha20Poly1305,
+    key_id: String<10>,
+    nonces: ChaCha20,
+    buffer: Vec<u8, SEALED_BODY_SIZE>,
+}
+
+impl Envelope {
+    /// `seed` from the hardware RNG, a new one every boot.
+    pub fn new(key: EnvelopeKey, seed: [u8; KEY_SIZE]) -> Self {
+        let mut key_id = String::new();
+        let _ = write!(key_id, "{}", key.id);
+        Self {
+            cipher: XChaCha20Poly1305::new(&key.key.into()),
+            key_id,
+            nonces: ChaCha20::new(&seed.into(), &[0; 12].into()),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// `None` if the body is larger than [`SEALED_BODY_SIZE`] without the overhead.
+    pub fn seal(&mut self, body: &[u8]) -> Option<Sealed<'_>> {
+        let nonce = self.next_nonce();
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&nonce).ok()?;
+        self.buffer.extend_from_slice(body).ok()?;
+        let tag = self.cipher.encrypt_in_place_detached(&nonce.into(), &[], &mut self.buffer[NONCE_SIZE..]).ok()?;
+        self.buffer.extend_from_slice(&tag).ok()?;
+        Some(Sealed {
+            key_id: &self.key_id,
+            body: &self.buffer,
+        })
+    }
+
+    /// The start of the next keystream block, the nonces of a boot never repeat.
+    fn next_nonce(&mut self) -> [u8; NONCE_SIZE] {
+        let mut nonce = [0u8; NONCE_SIZE];
+        self.nonces.apply_keystream(&mut nonce);
+        let next_block = self.nonces.current_pos::<u64>().next_multiple_of(64);
+        self.nonces.seek(next_block);
+        nonce
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// The opening of the backend, `None` if the tag does not match.
+    fn open(key: &[u8; KEY_SIZE], sealed: &[u8]) -> Option<std::vec::Vec<u8>> {
+        let (nonce, rest) = sealed.split_first_chunk::<NONCE_SIZE>()?;
+        let (ciphertext, tag) = rest.split_at(rest.len().checked_sub(TAG_SIZE)?);
+        let mut data = ciphertext.to_vec();
+        XChaCha20Poly1305::new(&(*key).into())
+            .decrypt_in_place_detached(&(*nonce).into(), &[], &mut data, tag.into())
+            .ok()?;
+        Some(data)
+    }
+
+    fn hex(text: &str) -> std::vec::Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn check_nonces() {
+        // RFC 8439, A.1 test vectors 1 and 2: the all zero key from block 0 on
+        let mut envelope = Envelope::new(EnvelopeKey { id: 1, key: [0; KEY_SIZE] }, [0; KEY_SIZE]);
+        let first = envelope.seal(&[]).unwrap().body[..NONCE_SIZE].to_vec();
+        assert_eq!(first, hex("76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1a"));
+        let second = envelope.seal(&[]).unwrap().body[..NONCE_SIZE].to_vec();
+        assert_eq!(second, hex("9f07e7be5551387a98ba977c732d080dcb0f29a048e36569"));
+    }
+
+    #[test]
+    fn check_seal() {
+        let key = EnvelopeKey {
+            id: 7,
+            key: core::array::from_fn(|i| i as u8),
+        };
+        let mut envelope = Envelope::new(key.clone(), [0x55; KEY_SIZE]);
+        let sealed = envelope.seal(&[0x08, 0x01]).unwrap();
+        assert_eq!(sealed.key_id, "7");
+        assert_eq!(sealed.body.len(), 2 + ENVELOPE_OVERHEAD);
+        let first = sealed.body.to_vec();
+        // a new nonce every body
+        let second = envelope.seal(&[0x08, 0x01]).unwrap().body.to_vec();
+        assert_ne!(first[..NONCE_SIZE], second[..NONCE_SIZE]);
+        assert_ne!(first[NONCE_SIZE..], second[NONCE_SIZE..]);
+
+        // the backend opens it with the key, tampering fails the tag
+        assert_eq!(open(&key.key, &first).as_deref(), Some([0x08, 0x01].as_slice()));
+        assert_eq!(open(&key.key, &second).as_deref(), Some([0x08, 0x01].as_slice()));
+        let mut tampered = first.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert_eq!(open(&key.key, &tampered), None);
+        assert_eq!(open(&[0; KEY_SIZE], &first), None);
+
+        assert!(envelope.seal(&[0; SEALED_BODY_SIZE]).is_none());
+    }
+
+    #[test]
+    fn check_config_value() {
+        let key = EnvelopeKey {
+            id: 0x0102,
+            key: [0xab; KEY_SIZE],
+        };
+        let mut buffer = [0u8; crate::storage::CONFIG_VALUE_SIZE];
+        let len = key.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..5], &[0x02, 0x01, 0, 0, 0xab]);
+        assert_eq!(EnvelopeKey::decode(&buffer[..len]), Some(key));
+        assert_eq!(EnvelopeKey::decode(&buffer[..len - 1]), None);
+        assert_eq!(std::format!("{:?}", EnvelopeKey::decode(&buffer[..len]).unwrap()), "EnvelopeKey { id: 258, .. }");
+    }
+}
@@ -0,0 +1,67 @@
+//! Heartbeat of the device, independent of the readings.
+//!
+//! The readings may be batched for hours, the heartbeat lets the backend mark a device
+//! offline long before. It is a small self-describing JSON object, e.g.
+//! `{"id":"869951031234567","up":86400,"fw":"0.4.2","q":3}`, so the backend needs no
+//! schema for it: the IMEI of the module, the uptime in seconds, the firmware version
+//! and the batches waiting for the upload.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+/// Fits the longest IMEI, uptime and queue depth with a 16 character version.
+pub const HEARTBEAT_SIZE: usize = 96;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Heartbeat<'a> {
+    /// Empty until the identity of the module is known.
+    pub device_id: &'a str,
+    pub uptime_seconds: u32,
+    pub firmware: &'a str,
+    pub queue_depth: u32,
+}
+
+impl Heartbeat<'_> {
+    /// The JSON body, `None` if the strings do not fit.
+    pub fn to_json(&self) -> Option<String<HEARTBEAT_SIZE>> {
+        let mut json = String::new();
+        write!(json, "{{\"id\":\"{}\",\"up\":{},\"fw\":\"{}\",\"q\":{}}}", self.device_id, self.uptime_seconds, self.firmware, self.queue_depth).ok()?;
+        Some(json)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_json() {
+        let heartbeat = Heartbeat {
+            device_id: "869951031234567",
+            uptime_seconds: 86_400,
+            firmware: "0.4.2",
+            queue_depth: 3,
+        };
+        let json = heartbeat.to_json().unwrap();
+        assert_eq!(json.as_str(), r#"{"id":"869951031234567","up":86400,"fw":"0.4.2","q":3}"#);
+        assert!(json.len() <= 56);
+
+        let longest = Heartbeat {
+            device_id: "8699510312345678",
+            uptime_seconds: u32::MAX,
+            firmware: "0.4.2-rc.1+g1234",
+            queue_depth: u32::MAX,
+        };
+        assert!(longest.to_json().is_some());
+        assert_eq!(
+            Heartbeat {
+                firmware: &"x".repeat(64),
+                ..longest
+            }
+            .to_json(),
+            None
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use embassy_time::Instant;
+
+use crate::{net::cellular::LinkQuality, proto::bt_::solar_::LinkQualityEvent};
+
+/// BER value of the modules when it is not known.
+const BER_UNKNOWN: u32 = 99;
+
+/// Aggregates the link quality samples of an upload period into a [`LinkQualityEvent`].
+#[derive(Debug, Default)]
+pub struct LinkQualityStats {
+    since: Option<Instant>,
+    samples: u32,
+    failed_samples: u32,
+    rssi_min: i32,
+    rssi_max: i32,
+    rssi_sum: i64,
+    ber_samples: u32,
+    ber_min: u32,
+    ber_max: u32,
+    ber_sum: u32,
+    last: Option<LinkQuality>,
+}
+
+impl LinkQualityStats {
+    pub fn add(&mut self, sample: LinkQuality, now: Instant) {
+        self.since.get_or_insert(now);
+        let rssi: i32 = sample.rssi.into();
+        if self.samples == 0 {
+            (self.rssi_min, self.rssi_max) = (rssi, rssi);
+        }
+        self.samples += 1;
+        self.rssi_min = self.rssi_min.min(rssi);
+        self.rssi_max = self.rssi_max.max(rssi);
+        self.rssi_sum += rssi as i64;
+        if sample.ber != BER_UNKNOWN {
+            if self.ber_samples == 0 {
+                (self.ber_min, self.ber_max) = (sample.ber, sample.ber);
+            }
+            self.ber_samples += 1;
+            self.ber_min = self.ber_min.min(sample.ber);
+            self.ber_max = self.ber_max.max(sample.ber);
+            self.ber_sum += sample.ber;
+        }
+        self.last = Some(sample);
+    }
+
+    /// A query that failed, e.g. the module reports no signal at all.
+    pub fn add_failure(&mut self, now: Instant) {
+        self.since.get_or_insert(now);
+        self.failed_samples += 1;
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.samples == 0 && self.failed_samples == 0
+    }
+
+    /// The aggregate since the first sample, `None` without any.
+    pub fn to_event(&self, now: Instant) -> Option<LinkQualityEvent> {
+        let since = self.since?;
+        let mut event = LinkQualityEvent {
+            period_seconds: now.saturating_duration_since(since).as_secs() as u32,
+            samples: self.samples,
+            failed_samples: self.failed_samples,
+            ber_min: BER_UNKNOWN,
+            ber_max: BER_UNKNOWN,
+            ..Default::default()
+        };
+        if self.samples > 0 {
+            event.rssi_min = self.rssi_min;
+            event.rssi_max = self.rssi_max;
+            event.rssi_avg = (self.rssi_sum / self.samples as i64) as i32;
+        }
+        if self.ber_samples > 0 {
+            event.ber_min = self.ber_min;
+            event.ber_max = self.ber_max;
+            event.ber_avg = self.ber_sum as f32 / self.ber_samples as f32;
+        }
+        if let Some(last) = &self.last {
+            event.registration_state = last.registration as u32;
+            // both are limited to OPERATOR_SIZE bytes
+            let _ = event.operator.push_str(last.operator.as_str());
+        }
+        Some(event)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::at::{network::NetworkRegistrationState, status_control::Rssi};
+
+    fn sample(rssi: i32, ber: u32) -> LinkQuality {
+        LinkQuality {
+            rssi: Rssi::from_dbm(rssi),
+            ber,
+            registration: NetworkRegistrationState::Registered,
+            operator: "Swisscom".try_into().unwrap(),
+        }
+    }
+
+    #[test]
+    fn check_aggregation() {
+        let start = Instant::from_secs(100);
+        let mut stats = LinkQualityStats::default();
+        assert!(stats.to_event(start).is_none());
+
+        stats.add(sample(-71, 0), start);
+        stats.add(sample(-95, 99), start + Duration::from_secs(60));
+        stats.add_failure(start + Duration::from_secs(120));
+        stats.add(sample(-80, 3), start + Duration::from_secs(180));
+
+        let event = stats.to_event(start + Duration::from_secs(300)).unwrap();
+        assert_eq!(event.period_seconds, 300);
+        assert_eq!(event.samples, 3);
+        assert_eq!(event.failed_samples, 1);
+        assert_eq!((event.rssi_min, event.rssi_avg, event.rssi_max), (-95, -82, -71));
+        assert_eq!((event.ber_min, event.ber_max), (0, 3));
+        assert_eq!(event.ber_avg, 1.5);
+        assert_eq!(event.registration_state, NetworkRegistrationState::Registered as u32);
+        assert_eq!(event.operator.as_str(), "Swisscom");
+
+        stats.clear();
+        assert!(stats.is_empty());
+    }
+
+    #[test]
+    fn check_only_failures() {
+        let mut stats = LinkQualityStats::default();
+        stats.add_failure(Instant::from_secs(10));
+        let event = stats.to_event(Instant::from_secs(20)).unwrap();
+        assert_eq!(event.samples, 0);
+        assert_eq!(event.failed_samples, 1);
+        assert_eq!(event.ber_max, BER_UNKNOWN);
+    }
+}
@@ -0,0 +1,94 @@
+//! The health counters as a low priority metrics upload.
+//!
+//! Every interval the runner defers a [`HealthEvent`](crate::proto::bt_::solar_::HealthEvent)
+//! to the [`UploadScheduler`], it piggy-backs on the next wake burst of the readings instead
+//! of waking the modem for itself, see [`crate::solar_monitor::scheduler`]. The backend takes
+//! it at `/api/v2/solar/metrics`.
+
+use embassy_time::{Duration, Instant, Timer};
+use micropb::{MessageEncode, PbEncoder};
+
+use crate::{
+    health::HEALTH,
+    proto::bt_::solar_::{SystemEvent, SystemEvent_::Event},
+    schema::SCHEMA_VERSION,
+    solar_monitor::scheduler::{LOW_PRIORITY_PAYLOAD_SIZE, UploadClass, UploadScheduler},
+    time::UtcTime,
+};
+
+pub struct Runner<'a> {
+    scheduler: &'a UploadScheduler,
+    interval: Duration,
+    stack_free: fn() -> u32,
+}
+
+/// Defers the health counters every `interval`, `stack_free` reports the unused stack bytes.
+pub fn new(scheduler: &UploadScheduler, interval: Duration, stack_free: fn() -> u32) -> Runner<'_> {
+    Runner {
+        scheduler,
+        interval,
+        stack_free,
+    }
+}
+
+impl Runner<'_> {
+    pub async fn run(self) {
+        loop {
+            Timer::after(self.interval).await;
+            self.once().await;
+        }
+    }
+
+    /// Without a time yet there is no timestamp for the event, the counters wait for the next interval.
+    async fn once(&self) {
+        let Some(now) = UtcTime::now().await else {
+            debug!("Metrics> no time yet => skipped");
+            return;
+        };
+        let event = SystemEvent {
+            schema_version: SCHEMA_VERSION,
+            timestamp: now.and_utc().timestamp(),
+            event: Some(Event::HealthEvent(HEALTH.to_event(Instant::now().as_secs() as u32, (self.stack_free)()))),
+        };
+        let mut buffer = micropb::heapless::Vec::<u8, LOW_PRIORITY_PAYLOAD_SIZE>::new();
+        if event.encode(&mut PbEncoder::new(&mut buffer)).is_err() {
+            warn!("Metrics> encoding the health event failed");
+            return;
+        }
+        if self.scheduler.defer(UploadClass::Metrics, &buffer).is_err() {
+            warn!("Metrics> health event too large to defer");
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chrono::NaiveDateTime;
+    use micropb::{MessageDecode, PbDecoder};
+    use serial_test::serial;
+
+    use super::*;
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_metrics_deferred() {
+        let scheduler = UploadScheduler::default();
+        let runner = new(&scheduler, Duration::from_secs(60), || 1024);
+        UtcTime::time_sync(NaiveDateTime::parse_from_str("2025-12-30 15:22:22", "%Y-%m-%d %H:%M:%S").unwrap()).await;
+
+        runner.once().await;
+        let upload = scheduler.take().unwrap();
+        assert_eq!(upload.class, UploadClass::Metrics);
+        // piggy-backs on the next wake burst
+        scheduler.requeue(upload);
+        assert!(!scheduler.is_overdue(Instant::now()));
+        let upload = scheduler.take().unwrap();
+        let mut event = SystemEvent::default();
+        event.decode(&mut PbDecoder::new(upload.data.as_slice()), upload.data.len()).unwrap();
+        assert_eq!(event.timestamp, 1_767_108_142);
+        let Some(Event::HealthEvent(health)) = event.event else {
+            panic!("health event expected");
+        };
+        assert_eq!(health.stack_free_bytes, 1024);
+    }
+}
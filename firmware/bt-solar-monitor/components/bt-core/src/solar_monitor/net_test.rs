@@ -0,0 +1,145 @@
+//! Pre-flight connectivity test, the `nettest` command of the installation.
+//!
+//! The installer runs it from the shell or the backend before leaving the site. The
+//! cloud runner owns the module, so it runs the steps once it is connected: the
+//! registration, a DNS lookup of the backend host and a HEAD request to `/healthz`,
+//! each with its latency. A failed startup completes the test with its error.
+
+use core::fmt;
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
+use embassy_time::Duration;
+
+use crate::{
+    at::{http::HttpStatusCode, network::NetworkRegistrationState},
+    net::cellular::{CellularError, LinkQuality},
+};
+
+/// Result of the steps, a step is `None` when an earlier one failed.
+#[derive(Debug, PartialEq)]
+pub struct NetTestReport {
+    /// Signal, registration and operator.
+    pub link: Result<LinkQuality, CellularError>,
+    /// Time to resolve the backend host.
+    pub dns: Option<Result<Duration, CellularError>>,
+    /// Status and round trip of the HEAD request to the health endpoint.
+    pub http: Option<Result<(HttpStatusCode, Duration), CellularError>>,
+}
+
+impl NetTestReport {
+    /// The module could not be brought up, e.g. no registration within the timeout.
+    pub fn failed(error: CellularError) -> Self {
+        Self {
+            link: Err(error),
+            dns: None,
+            http: None,
+        }
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.link
+            .as_ref()
+            .is_ok_and(|link| matches!(link.registration, NetworkRegistrationState::Registered | NetworkRegistrationState::RegisteredRoaming))
+    }
+
+    /// Registered and the backend answered with a success status.
+    pub fn passed(&self) -> bool {
+        self.is_registered() && matches!(self.http, Some(Ok((status, _))) if status.is_ok())
+    }
+}
+
+/// One line per step for the shell.
+impl fmt::Display for NetTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nettest: {}", if self.passed() { "PASS" } else { "FAIL" })?;
+        match &self.link {
+            Ok(link) => writeln!(f, "registration: {:?} {} {}", link.registration, link.operator.as_str(), link.rssi)?,
+            Err(e) => writeln!(f, "registration: {:?}", e)?,
+        }
+        match &self.dns {
+            Some(Ok(latency)) => writeln!(f, "dns: {} ms", latency.as_millis())?,
+            Some(Err(e)) => writeln!(f, "dns: {:?}", e)?,
+            None => writeln!(f, "dns: skipped")?,
+        }
+        match &self.http {
+            Some(Ok((status, latency))) => writeln!(f, "http: {} in {} ms", status, latency.as_millis()),
+            Some(Err(e)) => writeln!(f, "http: {:?}", e),
+            None => writeln!(f, "http: skipped"),
+        }
+    }
+}
+
+/// Request of a test to the cloud runner, see [`NetTest::run`].
+pub struct NetTest {
+    requested: Signal<NoopRawMutex, ()>,
+    report: Signal<NoopRawMutex, NetTestReport>,
+}
+
+impl NetTest {
+    pub const fn new() -> Self {
+        Self {
+            requested: Signal::new(),
+            report: Signal::new(),
+        }
+    }
+
+    /// Asks the cloud runner for a test and waits for the report, a sleeping module is woken up.
+    pub async fn run(&self) -> NetTestReport {
+        self.report.reset();
+        self.requested.signal(());
+        self.report.wait().await
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.signaled()
+    }
+
+    /// Returns once a test is requested, the request stays for [`NetTest::take_request`].
+    pub(crate) async fn wait_request(&self) {
+        self.requested.wait().await;
+        self.requested.signal(());
+    }
+
+    pub(crate) fn take_request(&self) -> bool {
+        self.requested.try_take().is_some()
+    }
+
+    pub(crate) fn complete(&self, report: NetTestReport) {
+        info!("NetTest> {}", if report.passed() { "passed" } else { "failed" });
+        self.report.signal(report);
+    }
+}
+
+impl Default for NetTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::string::ToString;
+
+    use super::*;
+    use crate::at::status_control::Rssi;
+
+    #[test]
+    fn check_report() {
+        let report = NetTestReport {
+            link: Ok(LinkQuality {
+                rssi: Rssi::from_dbm(-71),
+                ber: 0,
+                registration: NetworkRegistrationState::RegisteredRoaming,
+                operator: "Swisscom".try_into().unwrap(),
+            }),
+            dns: Some(Ok(Duration::from_millis(120))),
+            http: Some(Ok((HttpStatusCode::new(200), Duration::from_millis(850)))),
+        };
+        assert!(report.passed());
+        assert_eq!(report.to_string(), "nettest: PASS\nregistration: RegisteredRoaming Swisscom -71 dBm\ndns: 120 ms\nhttp: 200 in 850 ms\n");
+
+        let report = NetTestReport::failed(CellularError::Timeout);
+        assert!(!report.passed());
+        assert_eq!(report.to_string(), "nettest: FAIL\nregistration: Timeout\ndns: skipped\nhttp: skipped\n");
+    }
+}
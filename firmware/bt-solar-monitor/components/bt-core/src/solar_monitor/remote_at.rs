@@ -0,0 +1,166 @@
+//! Remote AT passthrough, for diagnosing the modem of a device without physical access.
+//!
+//! The backend asks for a command with `"run_at":"<command>"` in a response body, e.g. of
+//! the heartbeat, and optionally with `"run_at_timeout":<seconds>`. The cloud runner runs
+//! it while the module is awake, records it in the audit log and uploads the response
+//! lines, e.g. `{"command":"AT+CSQ","result":"OK","lines":["+CSQ: 21,3"]}`.
+
+use core::fmt::Write;
+
+use embassy_time::Duration;
+use heapless::String;
+
+use crate::{at::AtCommandResponse, net::cellular::CellularError};
+
+pub const RUN_AT_COMMAND_SIZE: usize = 64;
+/// Fits the four response lines of the AT controller, as long as they need little escaping.
+pub const RUN_AT_REPORT_SIZE: usize = 1152;
+pub const DEFAULT_RUN_AT_TIMEOUT: Duration = Duration::from_secs(5);
+/// A longer timeout asked for by the backend is cut to this one.
+pub const MAX_RUN_AT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const RUN_AT_KEY: &str = "\"run_at\":\"";
+const RUN_AT_TIMEOUT_KEY: &str = "\"run_at_timeout\":";
+const TRUNCATED_END: &str = "],\"truncated\":true}";
+const OVERFLOW_REPORT: &str = "{\"result\":\"overflow\"}";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RunAtCommand {
+    pub command: String<RUN_AT_COMMAND_SIZE>,
+    /// Bounded by [`MAX_RUN_AT_TIMEOUT`].
+    pub timeout: Duration,
+}
+
+impl RunAtCommand {
+    /// The command asked for in a response body, `None` if there is none or it is not a single AT command.
+    pub fn parse(body: &str) -> Option<Self> {
+        let start = body.find(RUN_AT_KEY)? + RUN_AT_KEY.len();
+        let mut command = String::new();
+        let mut chars = body[start..].chars();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    c @ ('"' | '\\') => command.push(c).ok()?,
+                    _ => return None,
+                },
+                // one command per request, no line breaks
+                c if c.is_control() => return None,
+                c => command.push(c).ok()?,
+            }
+        }
+        if !command.get(..2).is_some_and(|prefix| prefix.eq_ignore_ascii_case("AT")) {
+            return None;
+        }
+        let timeout = body
+            .find(RUN_AT_TIMEOUT_KEY)
+            .and_then(|start| {
+                let value = body[start + RUN_AT_TIMEOUT_KEY.len()..].trim_start();
+                let end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+                value[..end].parse().ok()
+            })
+            .map_or(DEFAULT_RUN_AT_TIMEOUT, |seconds| Duration::from_secs(seconds).min(MAX_RUN_AT_TIMEOUT));
+        Some(Self { command, timeout })
+    }
+
+    /// The JSON of the upload. The response lines that do not fit are left out and the report
+    /// ends with `"truncated":true`.
+    pub fn report(&self, result: Result<&AtCommandResponse, &CellularError>) -> String<RUN_AT_REPORT_SIZE> {
+        let mut json = String::new();
+        if self.write_report(&mut json, result).is_none() {
+            // only the lines can overflow, this is a safety net
+            json.clear();
+            let _ = json.push_str(OVERFLOW_REPORT);
+        }
+        json
+    }
+
+    fn write_report(&self, json: &mut String<RUN_AT_REPORT_SIZE>, result: Result<&AtCommandResponse, &CellularError>) -> Option<()> {
+        json.push_str("{\"command\":").ok()?;
+        push_json_str(json, &self.command)?;
+        json.push_str(",\"result\":").ok()?;
+        match result {
+            Ok(response) => {
+                push_json_str(json, "OK")?;
+                json.push_str(",\"lines\":[").ok()?;
+                let mut truncated = false;
+                for (index, line) in response.lines().enumerate() {
+                    let start = json.len();
+                    let pushed =
+                        (index == 0 || json.push(',').is_ok()) && push_json_str(json, line).is_some() && json.len() + TRUNCATED_END.len() <= RUN_AT_REPORT_SIZE;
+                    if !pushed {
+                        json.truncate(start);
+                        truncated = true;
+                        break;
+                    }
+                }
+                json.push_str(if truncated { TRUNCATED_END } else { "]}" }).ok()?;
+            }
+            Err(error) => {
+                let mut name = String::<64>::new();
+                let _ = write!(name, "{:?}", error);
+                push_json_str(json, &name)?;
+                json.push('}').ok()?;
+            }
+        }
+        Some(())
+    }
+}
+
+fn push_json_str<const N: usize>(json: &mut String<N>, value: &str) -> Option<()> {
+    json.push('"').ok()?;
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                json.push('\\').ok()?;
+                json.push(c).ok()?;
+            }
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).ok()?,
+            c => json.push(c).ok()?,
+        }
+    }
+    json.push('"').ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::at::AtError;
+
+    #[test]
+    fn check_parse() {
+        let command = RunAtCommand::parse(r#"{"accepted_proto_version":10,"run_at":"AT+CGDCONT?"}"#).unwrap();
+        assert_eq!(command.command.as_str(), "AT+CGDCONT?");
+        assert_eq!(command.timeout, DEFAULT_RUN_AT_TIMEOUT);
+
+        let command = RunAtCommand::parse(r#"{"run_at":"at+cgdcont=1,\"IP\",\"gprs.swisscom.ch\"","run_at_timeout":10}"#).unwrap();
+        assert_eq!(command.command.as_str(), r#"at+cgdcont=1,"IP","gprs.swisscom.ch""#);
+        assert_eq!(command.timeout, Duration::from_secs(10));
+
+        assert_eq!(RunAtCommand::parse(r#"{"run_at":"AT+CSQ","run_at_timeout":600}"#).unwrap().timeout, MAX_RUN_AT_TIMEOUT);
+        assert_eq!(RunAtCommand::parse(r#"{"accepted_proto_version":10}"#), None);
+        assert_eq!(RunAtCommand::parse(r#"{"run_at":"rm -rf"}"#), None);
+        assert_eq!(RunAtCommand::parse(r#"{"run_at":"AT\r\nAT+CFUN=0"}"#), None);
+        assert_eq!(RunAtCommand::parse(r#"{"run_at":"AT+CSQ"#), None);
+    }
+
+    #[test]
+    fn check_report() {
+        let command = RunAtCommand::parse(r#"{"run_at":"AT+COPS?"}"#).unwrap();
+        let response = AtCommandResponse::new(heapless::Vec::from_iter(["+COPS: 0,0,\"Swisscom\",7".try_into().unwrap()]));
+        assert_eq!(command.report(Ok(&response)).as_str(), r#"{"command":"AT+COPS?","result":"OK","lines":["+COPS: 0,0,\"Swisscom\",7"]}"#);
+        assert_eq!(command.report(Err(&CellularError::AtError(AtError::Timeout))).as_str(), r#"{"command":"AT+COPS?","result":"AtError(Timeout)"}"#);
+    }
+
+    #[test]
+    fn check_report_truncated() {
+        let command = RunAtCommand::parse(r#"{"run_at":"AT+CMGL=\"ALL\""}"#).unwrap();
+        let line = heapless::String::try_from("\"".repeat(200).as_str()).unwrap();
+        let response = AtCommandResponse::new(heapless::Vec::from_iter([line.clone(), line.clone(), line.clone(), line]));
+        let report = command.report(Ok(&response));
+        // two of the escaped lines fit
+        let escaped = std::format!("\"{}\"", "\\\"".repeat(200));
+        assert_eq!(report.as_str(), std::format!(r#"{{"command":"AT+CMGL=\"ALL\"","result":"OK","lines":[{},{}],"truncated":true}}"#, escaped, escaped));
+    }
+}
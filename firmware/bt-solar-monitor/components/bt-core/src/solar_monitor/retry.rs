@@ -0,0 +1,102 @@
+use embassy_time::Duration;
+
+/// Retry policy with exponential backoff and random jitter.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetryPolicy {
+    /// Attempts before giving up (and resetting the module), including the first one.
+    pub max_attempts: u32,
+    /// Backoff after the first failed attempt, doubled for every further failure.
+    pub initial_backoff: Duration,
+    /// Upper bound of the backoff (without jitter).
+    pub max_backoff: Duration,
+    /// Random extra delay of up to this percentage of the backoff.
+    pub jitter_percent: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+            jitter_percent: 25,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff after `failures` failed attempts (`failures >= 1`), `random` selects the jitter.
+    pub fn backoff(&self, failures: u32, random: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(31);
+        let backoff = self.initial_backoff.as_millis().saturating_mul(1 << exponent).min(self.max_backoff.as_millis());
+        let jitter = backoff * self.jitter_percent as u64 / 100 * (random % 1001) as u64 / 1000;
+        Duration::from_millis(backoff + jitter)
+    }
+
+    pub fn should_retry(&self, failures: u32) -> bool {
+        failures < self.max_attempts
+    }
+}
+
+/// Tiny xorshift generator, good enough to spread retries of a fleet.
+pub(crate) struct Jitter(u32);
+
+impl Jitter {
+    pub fn new(seed: u32) -> Self {
+        Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    pub fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn check_backoff_without_jitter() {
+        let policy = RetryPolicy {
+            jitter_percent: 0,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1, 1000), Duration::from_secs(2));
+        assert_eq!(policy.backoff(2, 1000), Duration::from_secs(4));
+        assert_eq!(policy.backoff(3, 1000), Duration::from_secs(8));
+        assert_eq!(policy.backoff(5, 1000), Duration::from_secs(32));
+        assert_eq!(policy.backoff(6, 1000), Duration::from_secs(60));
+        assert_eq!(policy.backoff(100, 1000), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn check_backoff_jitter_bounds() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1, 0), Duration::from_secs(2));
+        assert_eq!(policy.backoff(1, 1000), Duration::from_millis(2500));
+        let mut jitter = Jitter::new(42);
+        for failures in 1..10 {
+            let backoff = policy.backoff(failures, jitter.next());
+            let base = policy.backoff(failures, 0);
+            assert!(backoff >= base);
+            assert!(backoff.as_millis() <= base.as_millis() * 125 / 100);
+        }
+    }
+
+    #[test]
+    fn check_should_retry() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            ..Default::default()
+        };
+        assert!(policy.should_retry(1));
+        assert!(policy.should_retry(2));
+        assert!(!policy.should_retry(3));
+    }
+}
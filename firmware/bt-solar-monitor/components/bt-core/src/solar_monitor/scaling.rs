@@ -0,0 +1,233 @@
+//! Scaling of the readings to the integer fields of the proto messages.
+//!
+//! The VE.Direct values are floats in base units (V, A, W, kWh, %), averaged over the
+//! reading interval. The proto fields are integers in the units noted in
+//! `readings.proto`. The values are rounded to the nearest unit, a truncation would
+//! bias every average towards zero.
+
+use crate::{
+    proto::bt_::solar_::{BatteryMonitorReading, Reading as ProtoReading, Spread as ProtoSpread},
+    sensor::ve_direct::{Reading, Spread, battery_monitor::BatteryReading},
+};
+
+/// V => mV, A => mA, Ah => mAh, kWh => Wh.
+pub const MILLI: f32 = 1000.0;
+/// W => dW.
+pub const DECI: f32 = 10.0;
+/// Fields already in the proto unit, e.g. W.
+pub const UNIT: f32 = 1.0;
+/// % => per mille.
+pub const PER_MILLE: f32 = 10.0;
+/// °C => c°C.
+pub const CENTI: f32 = 100.0;
+
+/// `value * factor` rounded half away from zero, saturated to the `i32` range.
+pub fn scale(value: f32, factor: f32) -> i32 {
+    let scaled = value * factor;
+    // `as` truncates towards zero and saturates, NaN becomes 0
+    if scaled < 0.0 { (scaled - 0.5) as i32 } else { (scaled + 0.5) as i32 }
+}
+
+/// Like [`scale`], negative values become 0.
+pub fn scale_unsigned(value: f32, factor: f32) -> u32 {
+    (value * factor + 0.5) as u32
+}
+
+/// Resolution options of the [`Reading`] conversion, the default is the one of the
+/// `From` conversion.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadingScaling {
+    /// Also sends the panel power in dW, `panel_power` stays in W for older backends.
+    pub panel_power_deciwatt: bool,
+}
+
+impl ReadingScaling {
+    pub fn reading(&self, reading: &Reading) -> ProtoReading {
+        let mut proto = ProtoReading {
+            battery_voltage: scale(reading.battery_voltage, MILLI),
+            battery_current: scale(reading.battery_current, MILLI),
+            panel_voltage: scale(reading.panel_voltage, MILLI),
+            panel_power: scale(reading.panel_power, UNIT),
+            load_current: scale(reading.load_current, MILLI),
+            yield_today: scale(reading.yield_today, MILLI),
+            yield_total: scale(reading.yield_total, MILLI),
+            max_power_today: scale(reading.max_power_today, UNIT),
+            charger_state: reading.charger_state,
+            mppt_mode: reading.mppt_mode,
+            error_code: reading.error_code,
+            load_on: reading.load_on,
+            ..Default::default()
+        };
+        if self.panel_power_deciwatt {
+            proto.set_panel_power_deciwatt(scale(reading.panel_power, DECI));
+        }
+        if let Some(spread) = &reading.spread {
+            proto.set_battery_voltage_spread(scale_spread(&spread.battery_voltage, MILLI));
+            proto.set_battery_current_spread(scale_spread(&spread.battery_current, MILLI));
+            proto.set_panel_voltage_spread(scale_spread(&spread.panel_voltage, MILLI));
+            proto.set_panel_power_spread(scale_spread(&spread.panel_power, UNIT));
+            proto.set_load_current_spread(scale_spread(&spread.load_current, MILLI));
+        }
+        proto
+    }
+}
+
+fn scale_spread(spread: &Spread, factor: f32) -> ProtoSpread {
+    let mut proto = ProtoSpread {
+        min: scale(spread.min, factor),
+        max: scale(spread.max, factor),
+        ..Default::default()
+    };
+    if let Some(stddev) = spread.stddev {
+        proto.set_stddev(scale_unsigned(stddev, factor));
+    }
+    proto
+}
+
+impl From<Reading> for ProtoReading {
+    fn from(reading: Reading) -> Self {
+        ReadingScaling::default().reading(&reading)
+    }
+}
+
+impl From<BatteryReading> for BatteryMonitorReading {
+    fn from(reading: BatteryReading) -> Self {
+        Self {
+            voltage: scale(reading.voltage, MILLI),
+            current: scale(reading.current, MILLI),
+            power: scale(reading.power, UNIT),
+            consumed: scale(reading.consumed, MILLI),
+            state_of_charge: scale_unsigned(reading.state_of_charge, PER_MILLE),
+            time_to_go: reading.time_to_go,
+            alarm: reading.alarm,
+            alarm_reason: reading.alarm_reason,
+            deepest_discharge: scale(reading.deepest_discharge, MILLI),
+            last_discharge: scale(reading.last_discharge, MILLI),
+            average_discharge: scale(reading.average_discharge, MILLI),
+            charge_cycles: reading.charge_cycles,
+            full_discharges: reading.full_discharges,
+            cumulative_drawn: scale(reading.cumulative_drawn, MILLI),
+            min_voltage: scale(reading.min_voltage, MILLI),
+            max_voltage: scale(reading.max_voltage, MILLI),
+            seconds_since_full_charge: reading.seconds_since_full_charge,
+            automatic_syncs: reading.automatic_syncs,
+            low_voltage_alarms: reading.low_voltage_alarms,
+            high_voltage_alarms: reading.high_voltage_alarms,
+            low_aux_voltage_alarms: reading.low_aux_voltage_alarms,
+            high_aux_voltage_alarms: reading.high_aux_voltage_alarms,
+            min_aux_voltage: scale(reading.min_aux_voltage, MILLI),
+            max_aux_voltage: scale(reading.max_aux_voltage, MILLI),
+            discharged_energy: scale(reading.discharged_energy, MILLI),
+            charged_energy: scale(reading.charged_energy, MILLI),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::sensor::ve_direct::ReadingSpread;
+
+    #[test]
+    fn check_rounding() {
+        assert_eq!(scale(12.8456, MILLI), 12_846);
+        assert_eq!(scale(0.0005, MILLI), 1);
+        assert_eq!(scale(-1.1505, MILLI), -1_151);
+        assert_eq!(scale(-0.0004, MILLI), 0);
+        assert_eq!(scale(f32::NAN, MILLI), 0);
+        assert_eq!(scale(1e9, MILLI), i32::MAX);
+        assert_eq!(scale(-1e9, MILLI), i32::MIN);
+        assert_eq!(scale_unsigned(81.56, PER_MILLE), 816);
+        assert_eq!(scale_unsigned(-1.0, PER_MILLE), 0);
+        assert_eq!(scale_unsigned(f32::MAX, PER_MILLE), u32::MAX);
+        // largest yield with the full 0.01 kWh resolution
+        assert_eq!(scale(167_772.16, MILLI), 167_772_160);
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            battery_voltage: 12.8399,
+            battery_current: -1.2345,
+            panel_voltage: 18.5,
+            panel_power: 42.76,
+            load_current: 0.3996,
+            yield_today: 0.12,
+            yield_total: 123.45,
+            max_power_today: 99.5,
+            charger_state: 3,
+            mppt_mode: 2,
+            error_code: 0,
+            load_on: true,
+            spread: None,
+        }
+    }
+
+    #[test]
+    fn check_reading() {
+        let proto = ProtoReading::from(reading());
+        assert_eq!(proto.battery_voltage, 12_840);
+        assert_eq!(proto.battery_current, -1_235);
+        assert_eq!(proto.panel_voltage, 18_500);
+        assert_eq!(proto.panel_power, 43);
+        assert_eq!(proto.load_current, 400);
+        assert_eq!(proto.yield_today, 120);
+        assert_eq!(proto.yield_total, 123_450);
+        assert_eq!(proto.max_power_today, 100);
+        assert_eq!(proto.panel_power_deciwatt(), None);
+        assert!(proto.load_on);
+    }
+
+    #[test]
+    fn check_panel_power_deciwatt() {
+        let scaling = ReadingScaling { panel_power_deciwatt: true };
+        let proto = scaling.reading(&reading());
+        assert_eq!(proto.panel_power, 43);
+        assert_eq!(proto.panel_power_deciwatt(), Some(&428));
+    }
+
+    #[test]
+    fn check_spread() {
+        assert!(ProtoReading::from(reading()).battery_voltage_spread().is_none());
+        let spread = Spread {
+            min: 12.7996,
+            max: 12.9004,
+            stddev: Some(0.0304),
+        };
+        let reading = Reading {
+            spread: Some(ReadingSpread {
+                panel_power: Spread {
+                    min: 12.4,
+                    max: 61.5,
+                    stddev: None,
+                },
+                battery_voltage: spread,
+                ..Default::default()
+            }),
+            ..reading()
+        };
+        let proto = ProtoReading::from(reading);
+        let battery_voltage = proto.battery_voltage_spread().unwrap();
+        assert_eq!((battery_voltage.min, battery_voltage.max, battery_voltage.stddev()), (12_800, 12_900, Some(&30)));
+        let panel_power = proto.panel_power_spread().unwrap();
+        assert_eq!((panel_power.min, panel_power.max, panel_power.stddev()), (12, 62, None));
+        assert_eq!(proto.load_current_spread().unwrap().max, 0);
+    }
+
+    #[test]
+    fn check_battery_monitor_reading() {
+        let proto = BatteryMonitorReading::from(BatteryReading {
+            voltage: 12.84,
+            current: -1.15,
+            power: -14.6,
+            consumed: -12.3456,
+            state_of_charge: 81.56,
+            ..Default::default()
+        });
+        assert_eq!(proto.voltage, 12_840);
+        assert_eq!(proto.current, -1_150);
+        assert_eq!(proto.power, -15);
+        assert_eq!(proto.consumed, -12_346);
+        assert_eq!(proto.state_of_charge, 816);
+    }
+}
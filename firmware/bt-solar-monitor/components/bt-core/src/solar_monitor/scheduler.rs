@@ -0,0 +1,283 @@
+//! Deferral of low priority uploads until the modem is awake anyway.
+//!
+//! Readings (and alarms) wake the modem on their own. Metrics and logs are
+//! only queued here and piggy-back on the next wake burst, unless one of them
+//! gets older than the staleness limit of its class, then it wakes the modem
+//! itself. An upload queued without the piggy-back flag wakes the modem right
+//! away, e.g. the log asked for by the backend.
+//!
+//! A modem that is powered off between uploads instead follows the [`UploadWindow`]
+//! of the averaging runner, it is powered on just before the next batch is due.
+
+use core::cell::RefCell;
+
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+
+pub const LOW_PRIORITY_PAYLOAD_SIZE: usize = 256;
+const LOW_PRIORITY_QUEUE_SIZE: usize = 4;
+/// Time to power on the module, register to the network and sync the time.
+const DEFAULT_POWER_ON_LEAD_TIME: Duration = Duration::from_secs(90);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UploadClass {
+    Metrics,
+    Log,
+}
+
+/// Maximum time a deferred upload waits for a wake burst before it wakes the modem itself.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StalenessLimits {
+    pub metrics: Duration,
+    pub log: Duration,
+}
+
+impl StalenessLimits {
+    pub fn of(&self, class: UploadClass) -> Duration {
+        match class {
+            UploadClass::Metrics => self.metrics,
+            UploadClass::Log => self.log,
+        }
+    }
+}
+
+impl Default for StalenessLimits {
+    fn default() -> Self {
+        Self {
+            metrics: Duration::from_secs(60 * 60),
+            log: Duration::from_secs(6 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DeferredUpload {
+    pub class: UploadClass,
+    pub data: Vec<u8, LOW_PRIORITY_PAYLOAD_SIZE>,
+    queued_at: Instant,
+    /// Waits for the next wake burst, up to the staleness limit of the class.
+    piggy_back: bool,
+}
+
+impl DeferredUpload {
+    fn due_at(&self, limits: &StalenessLimits) -> Instant {
+        if self.piggy_back {
+            self.queued_at + limits.of(self.class)
+        } else {
+            self.queued_at
+        }
+    }
+}
+
+pub struct UploadScheduler {
+    queue: RefCell<Deque<DeferredUpload, LOW_PRIORITY_QUEUE_SIZE>>,
+    limits: StalenessLimits,
+}
+
+impl UploadScheduler {
+    pub fn new(limits: StalenessLimits) -> Self {
+        Self {
+            queue: RefCell::new(Deque::new()),
+            limits,
+        }
+    }
+
+    /// Queues `data` to piggy-back on the next wake burst, see [`UploadScheduler::enqueue`].
+    pub fn defer(&self, class: UploadClass, data: &[u8]) -> Result<(), heapless::CapacityError> {
+        self.enqueue(class, data, true)
+    }
+
+    /// Queues `data`, with `piggy_back` for the next wake burst, without it wakes the modem
+    /// right away. Drops the oldest deferred upload if the queue is full.
+    pub fn enqueue(&self, class: UploadClass, data: &[u8], piggy_back: bool) -> Result<(), heapless::CapacityError> {
+        let upload = DeferredUpload {
+            class,
+            data: Vec::from_slice(data)?,
+            queued_at: Instant::now(),
+            piggy_back,
+        };
+        let mut queue = self.queue.borrow_mut();
+        if queue.is_full()
+            && let Some(dropped) = queue.pop_front()
+        {
+            warn!("Deferred upload queue full => dropping oldest {:?} upload", dropped.class);
+        }
+        let _ = queue.push_back(upload);
+        Ok(())
+    }
+
+    /// Whether a deferred upload waited longer than its class allows or does not piggy-back.
+    pub fn is_overdue(&self, now: Instant) -> bool {
+        self.queue.borrow().iter().any(|upload| now >= upload.due_at(&self.limits))
+    }
+
+    /// When the first deferred upload gets overdue, `None` while nothing is deferred.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.borrow().iter().map(|upload| upload.due_at(&self.limits)).min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.borrow().is_empty()
+    }
+
+    /// Whether [`UploadScheduler::defer`] would not drop an upload.
+    pub fn has_room(&self) -> bool {
+        !self.queue.borrow().is_full()
+    }
+
+    pub fn take(&self) -> Option<DeferredUpload> {
+        self.queue.borrow_mut().pop_front()
+    }
+
+    /// Puts back an upload that failed. It piggy-backs on the next wake burst and its staleness
+    /// limit starts over, a failing upload does not keep waking the modem.
+    pub fn requeue(&self, mut upload: DeferredUpload) {
+        upload.queued_at = Instant::now();
+        upload.piggy_back = true;
+        let mut queue = self.queue.borrow_mut();
+        if queue.push_front(upload).is_err() {
+            warn!("Deferred upload queue full => dropping failed upload");
+        }
+    }
+}
+
+impl Default for UploadScheduler {
+    fn default() -> Self {
+        Self::new(StalenessLimits::default())
+    }
+}
+
+/// The batches of the upload runner arrive once per `interval`, the window opens
+/// `lead_time` before the next one is due.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UploadWindow {
+    pub interval: Duration,
+    pub lead_time: Duration,
+    last_batch: Option<Instant>,
+}
+
+impl UploadWindow {
+    pub fn new(interval: Duration, lead_time: Duration) -> Self {
+        Self {
+            interval,
+            lead_time,
+            last_batch: None,
+        }
+    }
+
+    /// A batch after every `entries` averaging periods of `averaging`.
+    pub fn after_averaging(averaging: Duration, entries: u32) -> Self {
+        Self::new(averaging * entries, DEFAULT_POWER_ON_LEAD_TIME)
+    }
+
+    pub fn batch_received(&mut self, at: Instant) {
+        self.last_batch = Some(at);
+    }
+
+    /// The first window opening after `after`, `None` until the first batch arrived.
+    pub fn next_power_on(&self, after: Instant) -> Option<Instant> {
+        let interval = self.interval.as_ticks().max(1);
+        let opens = self.last_batch? + Duration::from_ticks(interval.saturating_sub(self.lead_time.as_ticks()));
+        if opens > after {
+            return Some(opens);
+        }
+        let missed = (after - opens).as_ticks() / interval + 1;
+        Some(opens + Duration::from_ticks(missed * interval))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defer_and_take() {
+        let scheduler = UploadScheduler::default();
+        scheduler.defer(UploadClass::Metrics, b"m1").unwrap();
+        scheduler.defer(UploadClass::Log, b"l1").unwrap();
+        let first = scheduler.take().unwrap();
+        assert_eq!(first.class, UploadClass::Metrics);
+        scheduler.requeue(first);
+        assert_eq!(scheduler.take().unwrap().data.as_slice(), b"m1");
+        assert_eq!(scheduler.take().unwrap().data.as_slice(), b"l1");
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn test_full_queue_drops_oldest() {
+        let scheduler = UploadScheduler::default();
+        for i in 0..=LOW_PRIORITY_QUEUE_SIZE as u8 {
+            scheduler.defer(UploadClass::Log, &[i]).unwrap();
+        }
+        assert_eq!(scheduler.take().unwrap().data.as_slice(), &[1]);
+        assert!(scheduler.defer(UploadClass::Log, &[0; LOW_PRIORITY_PAYLOAD_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_overdue() {
+        let scheduler = UploadScheduler::new(StalenessLimits {
+            metrics: Duration::from_secs(10),
+            log: Duration::from_secs(100),
+        });
+        let now = Instant::now();
+        assert!(!scheduler.is_overdue(now));
+        assert_eq!(scheduler.next_deadline(), None);
+        scheduler.defer(UploadClass::Log, b"log").unwrap();
+        assert!(!scheduler.is_overdue(now + Duration::from_secs(50)));
+        scheduler.defer(UploadClass::Metrics, b"metrics").unwrap();
+        assert!(scheduler.is_overdue(Instant::now() + Duration::from_secs(11)));
+        // the metrics are due first, although deferred later
+        let deadline = scheduler.next_deadline().unwrap();
+        assert!(deadline < now + Duration::from_secs(100));
+        assert!(scheduler.is_overdue(deadline));
+    }
+
+    #[test]
+    fn test_without_piggy_back() {
+        let scheduler = UploadScheduler::default();
+        scheduler.defer(UploadClass::Metrics, b"metrics").unwrap();
+        assert!(!scheduler.is_overdue(Instant::now()));
+        scheduler.enqueue(UploadClass::Log, b"log", false).unwrap();
+        let now = Instant::now();
+        assert!(scheduler.is_overdue(now));
+        assert!(scheduler.next_deadline().unwrap() <= now);
+    }
+
+    #[test]
+    fn test_requeue_restarts_staleness() {
+        let scheduler = UploadScheduler::new(StalenessLimits {
+            metrics: Duration::from_secs(10),
+            log: Duration::from_secs(100),
+        });
+        scheduler.defer(UploadClass::Metrics, b"metrics").unwrap();
+        scheduler.enqueue(UploadClass::Log, b"log", false).unwrap();
+        let later = Instant::now() + Duration::from_secs(11);
+        assert!(scheduler.is_overdue(later));
+
+        // both fail to upload
+        let metrics = scheduler.take().unwrap();
+        let log = scheduler.take().unwrap();
+        scheduler.requeue(log);
+        scheduler.requeue(metrics);
+        let now = Instant::now();
+        assert!(!scheduler.is_overdue(now));
+        assert!(scheduler.next_deadline().unwrap() >= now + Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_upload_window() {
+        let mut window = UploadWindow::after_averaging(Duration::from_secs(5 * 60), 12);
+        assert_eq!(window.interval, Duration::from_secs(60 * 60));
+        assert_eq!(window.next_power_on(Instant::from_secs(0)), None);
+
+        window.batch_received(Instant::from_secs(1000));
+        let opens = Instant::from_secs(1000 + 60 * 60 - 90);
+        assert_eq!(window.next_power_on(Instant::from_secs(1100)), Some(opens));
+        // a missed window opens again one interval later
+        assert_eq!(window.next_power_on(opens), Some(opens + Duration::from_secs(60 * 60)));
+        assert_eq!(window.next_power_on(opens + Duration::from_secs(3 * 60 * 60)), Some(opens + Duration::from_secs(4 * 60 * 60)));
+    }
+}
@@ -0,0 +1,131 @@
+//! The sensors beside the charger, merged into the upload entries.
+//!
+//! The VE.Direct charger readings open the entries, see [`crate::solar_monitor::upload`].
+//! Every other sensor registers its channel as a [`SensorSource`] with
+//! `upload::Runner::with_sensor`, per entry the runner takes a [`SensorReading`] from each
+//! source and applies it. A new sensor adds a variant to both enums, the aggregation and the
+//! upload stay as they are.
+
+use embassy_sync::channel::DynamicReceiver;
+
+use crate::{
+    proto::bt_::solar_::{Reading as ProtoReading, UploadEntry},
+    sensor::{analog::AnalogReading, ve_direct::battery_monitor::BatteryReading},
+    solar_monitor::scaling::{CENTI, MILLI, scale},
+};
+
+/// Battery monitor, temperature, analog channels and pulse counter.
+pub const MAX_SENSORS: usize = 4;
+
+/// The channel of a sensor runner.
+pub enum SensorSource<'a> {
+    /// A battery monitor (BMV, SmartShunt), see [`crate::sensor::ve_direct::battery_monitor`].
+    BatteryMonitor(DynamicReceiver<'a, BatteryReading>),
+    /// The battery compartment temperature in °C, see [`crate::sensor::temperature`].
+    Temperature(DynamicReceiver<'a, f32>),
+    /// The averages of the auxiliary analog channels, see [`crate::sensor::analog`].
+    Analog(DynamicReceiver<'a, AnalogReading>),
+    /// The pulse counts, see [`crate::sensor::pulse`].
+    Pulses(DynamicReceiver<'a, u32>),
+}
+
+impl SensorSource<'_> {
+    /// The reading since the last entry, each one is uploaded once. The newest value of a sensor,
+    /// only the counts of several intervals add up.
+    pub fn take(&self) -> Option<SensorReading> {
+        match self {
+            SensorSource::BatteryMonitor(receiver) => latest(receiver).map(SensorReading::BatteryMonitor),
+            SensorSource::Temperature(receiver) => latest(receiver).map(SensorReading::Temperature),
+            SensorSource::Analog(receiver) => latest(receiver).map(SensorReading::Analog),
+            SensorSource::Pulses(receiver) => {
+                let mut pulses = None;
+                while let Ok(count) = receiver.try_receive() {
+                    pulses = Some(pulses.unwrap_or(0u32).saturating_add(count));
+                }
+                pulses.map(SensorReading::Pulses)
+            }
+        }
+    }
+}
+
+fn latest<T>(receiver: &DynamicReceiver<'_, T>) -> Option<T> {
+    let mut latest = None;
+    while let Ok(value) = receiver.try_receive() {
+        latest = Some(value);
+    }
+    latest
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorReading {
+    BatteryMonitor(BatteryReading),
+    Temperature(f32),
+    Analog(AnalogReading),
+    Pulses(u32),
+}
+
+impl SensorReading {
+    /// Sets the fields of the sensor in the charger reading or the entry.
+    pub fn apply(self, reading: &mut ProtoReading, entry: &mut UploadEntry) {
+        match self {
+            SensorReading::BatteryMonitor(battery) => {
+                entry.set_battery_monitor(battery.into());
+            }
+            SensorReading::Temperature(temperature) => {
+                reading.set_battery_temperature(scale(temperature, CENTI));
+            }
+            SensorReading::Analog(analog) => {
+                if let Some(value) = analog.values.first() {
+                    reading.set_auxiliary_1(scale(*value, MILLI));
+                }
+                if let Some(value) = analog.values.get(1) {
+                    reading.set_auxiliary_2(scale(*value, MILLI));
+                }
+            }
+            SensorReading::Pulses(pulses) => {
+                reading.set_pulse_count(pulses);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+
+    use super::*;
+
+    #[test]
+    fn check_take() {
+        let temperature_channel = Channel::<NoopRawMutex, f32, 2>::new();
+        let temperature = SensorSource::Temperature(temperature_channel.dyn_receiver());
+        assert_eq!(temperature.take(), None);
+        temperature_channel.try_send(21.0).unwrap();
+        temperature_channel.try_send(22.5).unwrap();
+        assert_eq!(temperature.take(), Some(SensorReading::Temperature(22.5)));
+        assert_eq!(temperature.take(), None);
+
+        let pulse_channel = Channel::<NoopRawMutex, u32, 2>::new();
+        let pulses = SensorSource::Pulses(pulse_channel.dyn_receiver());
+        pulse_channel.try_send(4).unwrap();
+        pulse_channel.try_send(u32::MAX).unwrap();
+        assert_eq!(pulses.take(), Some(SensorReading::Pulses(u32::MAX)));
+    }
+
+    #[test]
+    fn check_apply() {
+        let mut reading = ProtoReading::default();
+        let mut entry = UploadEntry::default();
+        SensorReading::Temperature(-5.504).apply(&mut reading, &mut entry);
+        SensorReading::Pulses(7).apply(&mut reading, &mut entry);
+        SensorReading::BatteryMonitor(BatteryReading {
+            state_of_charge: 81.5,
+            ..Default::default()
+        })
+        .apply(&mut reading, &mut entry);
+        assert_eq!(reading.battery_temperature(), Some(&-550));
+        assert_eq!(reading.pulse_count(), Some(&7));
+        assert_eq!(entry.battery_monitor().map(|battery| battery.state_of_charge), Some(815));
+    }
+}
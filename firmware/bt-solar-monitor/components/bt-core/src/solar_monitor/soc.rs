@@ -0,0 +1,173 @@
+//! State of charge estimation from the battery voltage, for systems without a battery monitor.
+//!
+//! The open circuit voltage of a battery follows its state of charge once it rested for a
+//! while, i.e. neither charged nor discharged. [`SocEstimator`] looks for such rested windows
+//! in the averaged readings and maps the voltage with the [`SocCurve`] of the [`Chemistry`].
+//! The LiFePO4 curve is flat in the middle, its estimates are rough between 20 and 90 %.
+
+use embassy_time::Duration;
+
+use crate::{sensor::ve_direct::Reading, solar_monitor::battery::Chemistry};
+
+impl Chemistry {
+    pub fn soc_curve(&self) -> SocCurve {
+        match self {
+            Chemistry::Flooded => SocCurve::new(&[(11.89, 0.0), (12.06, 25.0), (12.24, 50.0), (12.45, 75.0), (12.65, 100.0)]),
+            Chemistry::Agm => SocCurve::new(&[(11.80, 0.0), (12.00, 25.0), (12.30, 50.0), (12.60, 75.0), (12.85, 100.0)]),
+            Chemistry::LiFePo4 => SocCurve::new(&[
+                (10.00, 0.0),
+                (12.00, 9.0),
+                (12.80, 17.0),
+                (12.90, 20.0),
+                (13.00, 30.0),
+                (13.10, 40.0),
+                (13.20, 70.0),
+                (13.30, 90.0),
+                (13.40, 99.0),
+                (13.60, 100.0),
+            ]),
+        }
+    }
+}
+
+/// Rested voltage of a 12 V battery to state of charge in %, the points ascending by voltage.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SocCurve {
+    points: &'static [(f32, f32)],
+}
+
+impl SocCurve {
+    pub const fn new(points: &'static [(f32, f32)]) -> Self {
+        Self { points }
+    }
+
+    /// Interpolates linearly between the points, clamped to the first and last one.
+    pub fn state_of_charge(&self, voltage: f32) -> Option<f32> {
+        let (first, last) = (self.points.first()?, self.points.last()?);
+        if voltage <= first.0 {
+            return Some(first.1);
+        }
+        if voltage >= last.0 {
+            return Some(last.1);
+        }
+        self.points.windows(2).find(|pair| voltage <= pair[1].0).map(|pair| {
+            let ((v0, soc0), (v1, soc1)) = (pair[0], pair[1]);
+            soc0 + (soc1 - soc0) * (voltage - v0) / (v1 - v0)
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SocConfig {
+    pub curve: SocCurve,
+    /// Nominal voltage of the battery bank, the curves are for 12 V.
+    pub nominal_voltage: f32,
+    /// Battery and load current below which the battery counts as resting.
+    pub rest_current: f32,
+    /// How long the battery has to rest before its voltage is used.
+    pub rest_time: Duration,
+}
+
+impl SocConfig {
+    pub fn new(chemistry: Chemistry) -> Self {
+        Self {
+            curve: chemistry.soc_curve(),
+            nominal_voltage: 12.0,
+            rest_current: 0.2,
+            rest_time: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl Default for SocConfig {
+    fn default() -> Self {
+        Self::new(Chemistry::default())
+    }
+}
+
+/// Tracks the rested windows of the averaged readings.
+#[derive(Debug)]
+pub struct SocEstimator {
+    config: SocConfig,
+    /// Timestamp (s) of the first reading of the current rested window.
+    rested_since: Option<i64>,
+}
+
+impl SocEstimator {
+    pub fn new(config: SocConfig) -> Self {
+        Self { config, rested_since: None }
+    }
+
+    /// The estimated state of charge in % for the `reading` at `timestamp` (Unix seconds),
+    /// `None` unless the battery rested for the configured time.
+    pub fn update(&mut self, reading: &Reading, timestamp: i64) -> Option<f32> {
+        let resting = reading.battery_voltage > 0.0
+            && reading.battery_current.abs() <= self.config.rest_current
+            && reading.load_current.abs() <= self.config.rest_current;
+        if !resting {
+            self.rested_since = None;
+            return None;
+        }
+        let since = *self.rested_since.get_or_insert(timestamp);
+        if timestamp - since < self.config.rest_time.as_secs() as i64 {
+            return None;
+        }
+        let voltage = reading.battery_voltage * 12.0 / self.config.nominal_voltage;
+        let soc = self.config.curve.state_of_charge(voltage)?;
+        debug!("Battery rested at {} V => SOC ~{} %", reading.battery_voltage, soc);
+        Some(soc)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    fn rested(voltage: f32) -> Reading {
+        Reading {
+            battery_voltage: voltage,
+            battery_current: 0.05,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_curve() {
+        let curve = Chemistry::Flooded.soc_curve();
+        assert_relative_eq!(curve.state_of_charge(11.0).unwrap(), 0.0);
+        assert_relative_eq!(curve.state_of_charge(12.24).unwrap(), 50.0);
+        assert_relative_eq!(curve.state_of_charge(12.345).unwrap(), 62.5, epsilon = 0.01);
+        assert_relative_eq!(curve.state_of_charge(13.5).unwrap(), 100.0);
+        assert_eq!(SocCurve::new(&[]).state_of_charge(12.0), None);
+    }
+
+    #[test]
+    fn check_rest_time() {
+        let mut estimator = SocEstimator::new(SocConfig::new(Chemistry::Agm));
+        assert_eq!(estimator.update(&rested(12.3), 0), None);
+        assert_eq!(estimator.update(&rested(12.3), 25 * 60), None);
+        assert_relative_eq!(estimator.update(&rested(12.3), 30 * 60).unwrap(), 50.0);
+
+        // a charge current ends the rested window
+        let charging = Reading {
+            battery_current: 3.0,
+            ..rested(13.2)
+        };
+        assert_eq!(estimator.update(&charging, 35 * 60), None);
+        assert_eq!(estimator.update(&rested(12.3), 40 * 60), None);
+    }
+
+    #[test]
+    fn check_nominal_voltage() {
+        let mut estimator = SocEstimator::new(SocConfig {
+            nominal_voltage: 24.0,
+            rest_time: Duration::from_secs(0),
+            ..SocConfig::new(Chemistry::LiFePo4)
+        });
+        assert_relative_eq!(estimator.update(&rested(26.6), 0).unwrap(), 90.0, epsilon = 0.01);
+        // no reading yet
+        assert_eq!(estimator.update(&rested(0.0), 0), None);
+    }
+}
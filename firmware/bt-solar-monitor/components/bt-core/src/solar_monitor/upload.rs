@@ -1,16 +1,103 @@
-use embassy_futures::yield_now;
-use embassy_sync::channel::Sender;
+use chrono::NaiveDateTime;
+use embassy_futures::select::{Either, select};
+use embassy_sync::channel::{Sender, TrySendError};
+use embassy_sync::watch::DynSender;
 use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Receiver};
+use embassy_time::{Duration, Instant, with_timeout};
 use heapless::Vec;
 use micropb::{MessageEncode, PbEncoder, PbWrite};
 
 use crate::proto::bt_::solar_::UploadEntry;
-use crate::{proto::bt_::solar_::Upload, sensor::ve_direct::Reading, time::UtcTime};
+use crate::{
+    proto::bt_::solar_::Upload,
+    schema::SCHEMA_VERSION,
+    sensor::ve_direct::Reading,
+    solar_monitor::{
+        Flush,
+        battery::BatteryAlarms,
+        cbor::{CborEncoder, UploadEncoder, UploadEncoding},
+        scaling::{PER_MILLE, ReadingScaling, scale_unsigned},
+        sensors::{MAX_SENSORS, SensorSource},
+        soc::SocEstimator,
+        upload_queue::{UploadQueue, UploadQueueError},
+    },
+    storage::{ConfigKey, ConfigValue},
+    time::{TimeQuality, UtcTime},
+    watchdog::{FEED_INTERVAL, WatchdogHandle},
+};
 
 const UPLOAD_MAX_MESSAGE_SIZE: usize = Upload::MAX_SIZE.expect("Size known at compile time");
 type UploadVec = Vec<u8, UPLOAD_MAX_MESSAGE_SIZE>;
 struct UploadBuffer(UploadVec);
 
+/// The batching of the installation, `upload/batch_policy` in the config store.
+pub const BATCH_POLICY: ConfigKey<BatchPolicy> = ConfigKey::new("upload", "batch_policy");
+
+/// When the runner closes the batch and uploads it, whichever trigger comes first.
+///
+/// The default uploads full `Upload` messages only.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatchPolicy {
+    /// Readings per batch, at most the capacity of the `Upload` message is used.
+    pub max_entries: u16,
+    /// Uploads a partial batch once its first reading is this old.
+    pub max_age: Option<Duration>,
+    /// Uploads the partial batch with the reading that raised a battery alarm.
+    pub flush_on_alarm: bool,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        Self {
+            max_entries: u16::MAX,
+            max_age: None,
+            flush_on_alarm: false,
+        }
+    }
+}
+
+impl BatchPolicy {
+    /// Whether a batch of `entries` readings, the first one `age` old, is due.
+    pub fn is_due(&self, entries: usize, age: Duration, alarm_raised: bool) -> bool {
+        entries >= self.max_entries as usize || self.max_age.is_some_and(|max_age| age >= max_age) || (self.flush_on_alarm && alarm_raised)
+    }
+}
+
+/// Max entries, max age in seconds (0 without), flush on alarm.
+impl ConfigValue for BatchPolicy {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let max_age = self.max_age.map_or(0, |max_age| max_age.as_secs() as u32);
+        let data = buffer.get_mut(..7)?;
+        data[..2].copy_from_slice(&self.max_entries.to_le_bytes());
+        data[2..6].copy_from_slice(&max_age.to_le_bytes());
+        data[6] = self.flush_on_alarm as u8;
+        Some(data.len())
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let [e0, e1, a0, a1, a2, a3, flush_on_alarm] = data.try_into().ok()?;
+        let max_age = u32::from_le_bytes([a0, a1, a2, a3]);
+        Some(Self {
+            max_entries: u16::from_le_bytes([e0, e1]).max(1),
+            max_age: (max_age > 0).then(|| Duration::from_secs(max_age as u64)),
+            flush_on_alarm: bool::decode(&[flush_on_alarm])?,
+        })
+    }
+}
+
+/// Backpressure of the upload channel, published whenever it changes.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UploadStatus {
+    /// Number of uploads that found the channel full since startup.
+    pub overflows: u32,
+    /// The last upload found the channel full, the cloud does not keep up.
+    pub overrun: bool,
+    /// Number of loop iterations that missed the loop deadline since startup.
+    pub late_loops: u32,
+}
+
 impl UploadBuffer {
     pub fn new() -> Self {
         UploadBuffer(Vec::new())
@@ -30,6 +117,21 @@ pub struct Runner<'a, 'b, M: RawMutex, const NRECEIVER: usize, const NSENDER: us
     reading_receiver: Receiver<'a, M, Reading, NRECEIVER>,
     upload_sender: Sender<'b, M, UploadVec, NSENDER>,
     upload: Option<Upload>,
+    watchdog: WatchdogHandle<'a>,
+    status: UploadStatus,
+    status_sender: Option<DynSender<'a, UploadStatus>>,
+    policy: BatchPolicy,
+    /// A battery alarm was raised since the batch was taken, see [`BatchPolicy::flush_on_alarm`].
+    alarm_raised: bool,
+    flush: Option<&'a Flush>,
+    loop_deadline: Option<Duration>,
+    battery_voltage: Option<DynSender<'a, f32>>,
+    sensors: Vec<SensorSource<'a>, MAX_SENSORS>,
+    soc_estimator: Option<SocEstimator>,
+    battery_alarms: Option<BatteryAlarms>,
+    queue: Option<&'a UploadQueue>,
+    scaling: ReadingScaling,
+    encoding: UploadEncoding,
 }
 
 pub fn new<'a, 'b, M: RawMutex, const NRECEIVER: usize, const NSENDER: usize>(
@@ -40,83 +142,313 @@ pub fn new<'a, 'b, M: RawMutex, const NRECEIVER: usize, const NSENDER: usize>(
         reading_receiver,
         upload_sender,
         upload: None,
+        watchdog: WatchdogHandle::default(),
+        status: UploadStatus::default(),
+        status_sender: None,
+        policy: BatchPolicy::default(),
+        alarm_raised: false,
+        flush: None,
+        loop_deadline: None,
+        battery_voltage: None,
+        sensors: Vec::new(),
+        soc_estimator: None,
+        battery_alarms: None,
+        queue: None,
+        scaling: ReadingScaling::default(),
+        encoding: UploadEncoding::Protobuf,
     }
 }
 
 impl<'a, 'b, M: RawMutex, const NRECEIVER: usize, const NSENDER: usize> Runner<'a, 'b, M, NRECEIVER, NSENDER> {
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    /// When to upload the batch, e.g. the [`BATCH_POLICY`] of the config store.
+    pub fn with_batch_policy(mut self, policy: BatchPolicy) -> Self {
+        self.policy = BatchPolicy {
+            max_entries: policy.max_entries.max(1),
+            ..policy
+        };
+        self
+    }
+
+    /// Uploads once the batch has `entries` readings, at most the capacity of the `Upload` message.
+    pub fn with_entries_per_upload(mut self, entries: usize) -> Self {
+        self.policy.max_entries = entries.clamp(1, u16::MAX as usize) as u16;
+        self
+    }
+
+    /// Uploads a partial batch once its first reading is `max_latency` old.
+    pub fn with_max_latency(mut self, max_latency: Duration) -> Self {
+        self.policy.max_age = Some(max_latency);
+        self
+    }
+
+    /// Answers [`crate::solar_monitor::flush`] requests.
+    pub fn with_flush(mut self, flush: &'a Flush) -> Self {
+        self.flush = Some(flush);
+        self
+    }
+
+    /// Counts the iterations that take longer than `deadline` from waking up to having handled the reading,
+    /// a hint that the executor is starved by other tasks.
+    pub fn with_loop_deadline(mut self, deadline: Duration) -> Self {
+        self.loop_deadline = Some(deadline);
+        self
+    }
+
+    /// Publishes the battery voltage of every reading, e.g. for the battery policy of the cloud runner.
+    pub fn with_battery_voltage(mut self, sender: DynSender<'a, f32>) -> Self {
+        self.battery_voltage = Some(sender);
+        self
+    }
+
+    /// Adds the readings of another sensor, e.g. a battery monitor (BMV, SmartShunt), to the entry of every charger reading.
+    pub fn with_sensor(mut self, source: SensorSource<'a>) -> Self {
+        if self.sensors.push(source).is_err() {
+            warn!("Upload> more than {} sensors, ignored", MAX_SENSORS);
+        }
+        self
+    }
+
+    /// Adds the state of charge estimated from the rested battery voltage to the entries without a battery monitor reading.
+    pub fn with_soc_estimator(mut self, estimator: SocEstimator) -> Self {
+        self.soc_estimator = Some(estimator);
+        self
+    }
+
+    /// Checks every reading against the battery alarm thresholds.
+    pub fn with_battery_alarms(mut self, alarms: BatteryAlarms) -> Self {
+        self.battery_alarms = Some(alarms);
+        self
+    }
+
+    /// Streams the batches record by record into the flash queue instead of encoding them into the upload channel.
+    pub fn with_queue(mut self, queue: &'a UploadQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Resolution of the uploaded readings, see [`ReadingScaling`].
+    pub fn with_reading_scaling(mut self, scaling: ReadingScaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    /// Encoding of the uploads, the cloud runner needs the same one for the `Content-Type`.
+    ///
+    /// The flash queue stores protobuf records, CBOR uploads go to the upload channel directly.
+    pub fn with_encoding(mut self, encoding: UploadEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn with_status(mut self, status_sender: DynSender<'a, UploadStatus>) -> Self {
+        status_sender.send(self.status);
+        self.status_sender = Some(status_sender);
+        self
+    }
+
+    /// Runs the batching forever.
+    ///
+    /// The runner only awaits, it never polls: it needs the executor to poll it when a reading
+    /// arrives, a flush is requested or the [`FEED_INTERVAL`] elapses. Run it on the same
+    /// executor as the other runners (spawned or joined), with [`Runner::with_loop_deadline`]
+    /// to detect tasks that block the executor for too long.
     pub async fn run(mut self) {
         loop {
-            yield_now().await;
+            self.watchdog.feed();
             self.run_once().await;
         }
     }
 
     async fn run_once(&mut self) {
-        let reading = self.reading_receiver.receive().await;
-        info!("VE.Reading> {:?}", reading);
-        if let Some(upload) = self.handle_reading(reading).await {
-            self.upload_sender.send(upload).await;
+        let flush_requested = async {
+            match self.flush {
+                Some(flush) => flush.requested.wait().await,
+                None => core::future::pending().await,
+            }
+        };
+        let event = select(with_timeout(FEED_INTERVAL, self.reading_receiver.receive()), flush_requested).await;
+        let started = Instant::now();
+        match event {
+            Either::First(Ok(reading)) => {
+                info!("VE.Reading> {:?}", reading);
+                if let Some(sender) = &self.battery_voltage {
+                    sender.send(reading.battery_voltage);
+                }
+                if let Some(alarms) = &mut self.battery_alarms {
+                    let previous = alarms.state();
+                    self.alarm_raised |= alarms.update(&reading, started).raised_since(previous);
+                }
+                if let Some(upload) = self.handle_reading(reading).await {
+                    self.send_upload(upload).await;
+                }
+            }
+            Either::First(Err(_)) => {
+                if let Some(upload) = self.flush_overdue().await {
+                    self.send_upload(upload).await;
+                }
+            }
+            Either::Second(()) => {
+                info!("Flush requested => upload partial batch");
+                if let Some(upload) = self.take_batch().await {
+                    self.send_upload(upload).await;
+                }
+                if let Some(flush) = self.flush {
+                    flush.done.signal(());
+                }
+            }
+        }
+        self.check_loop_deadline(started.elapsed());
+    }
+
+    fn check_loop_deadline(&mut self, elapsed: Duration) {
+        let Some(deadline) = self.loop_deadline else {
+            return;
+        };
+        if elapsed > deadline {
+            self.status.late_loops += 1;
+            warn!(
+                "Upload loop took {} (deadline {}, {} late loops)",
+                crate::fmt::FormatableDuration(elapsed),
+                crate::fmt::FormatableDuration(deadline),
+                self.status.late_loops
+            );
+            self.publish_status();
+        }
+    }
+
+    /// Sends the upload, a full channel is counted and flagged before waiting for the cloud.
+    async fn send_upload(&mut self, upload: UploadVec) {
+        match self.upload_sender.try_send(upload) {
+            Ok(()) => self.set_overrun(false),
+            Err(TrySendError::Full(upload)) => {
+                self.status.overflows += 1;
+                warn!("Upload channel full ({} overflows) => waiting for the cloud", self.status.overflows);
+                self.set_overrun(true);
+                self.upload_sender.send(upload).await;
+            }
+        }
+    }
+
+    fn set_overrun(&mut self, overrun: bool) {
+        // every overflow is published, the recovery only once
+        if !overrun && !self.status.overrun {
+            return;
+        }
+        self.status.overrun = overrun;
+        self.publish_status();
+    }
+
+    fn publish_status(&self) {
+        if let Some(sender) = &self.status_sender {
+            sender.send(self.status);
         }
     }
 
     async fn handle_reading(&mut self, reading: Reading) -> Option<UploadVec> {
-        match UtcTime::now().await {
+        let approximate = UtcTime::quality().await == Some(TimeQuality::Approximate);
+        let now = match UtcTime::now().await {
             Some(timestamp) => {
-                let mut entry = UploadEntry::default().init_offset_in_seconds(0).init_reading(reading.into());
+                let estimate = self
+                    .soc_estimator
+                    .as_mut()
+                    .and_then(|estimator| estimator.update(&reading, timestamp.and_utc().timestamp()));
+                let mut proto_reading = self.scaling.reading(&reading);
+                let mut entry = UploadEntry::default().init_offset_in_seconds(0);
+                for sensor in &self.sensors {
+                    if let Some(sensor_reading) = sensor.take() {
+                        sensor_reading.apply(&mut proto_reading, &mut entry);
+                    }
+                }
+                entry.set_reading(proto_reading);
+                if entry.battery_monitor().is_none()
+                    && let Some(soc) = estimate
+                {
+                    entry.set_estimated_state_of_charge(scale_unsigned(soc, PER_MILLE));
+                }
                 match self.upload {
                     Some(ref mut upload) => {
                         let offest = (timestamp.and_utc().timestamp() - upload.start_timestamp) as i32;
                         entry.set_offset_in_seconds(offest);
+                        upload.approximate_time |= approximate;
                         let _ = upload.entries.push(entry);
                         debug!("Added reading [+{}s] to upload, total entries: {}", offest, upload.entries.len());
                     }
                     None => {
                         let mut new_upload = Upload {
+                            schema_version: SCHEMA_VERSION,
                             start_timestamp: timestamp.and_utc().timestamp(),
                             entries: micropb::heapless::Vec::new(),
+                            approximate_time: approximate,
                         };
                         let _ = new_upload.entries.push(entry);
                         debug!("New Upload started @{}", new_upload.start_timestamp);
                         self.upload = Some(new_upload);
                     }
                 }
+                timestamp
             }
             None => {
                 warn!("Skipping reading upload: system time not synchronized yet");
                 return None;
             }
         };
-        if let Some(ref mut upload) = self.upload
-            && upload.entries.is_full()
-        {
-            let upload = self.upload.take().unwrap();
-            info!("Uploading {} readings", upload.entries.len());
-            let mut upload_buffer = UploadBuffer::new();
-            let mut encoder = PbEncoder::new(&mut upload_buffer);
-            match upload.encode(&mut encoder) {
-                Ok(_) => {
-                    info!("Upload encoded ({} bytes)", upload_buffer.0.len());
-                    return Some(upload_buffer.0);
-                }
-                Err(e) => {
-                    error!("Failed to encode upload: {:?}", e);
-                }
+        if self.is_batch_due(now) { self.take_batch().await } else { None }
+    }
+
+    /// Takes a partial batch that exceeded the maximum latency while no readings arrive.
+    async fn flush_overdue(&mut self) -> Option<UploadVec> {
+        let now = UtcTime::now().await?;
+        if self.is_batch_due(now) { self.take_batch().await } else { None }
+    }
+
+    fn is_batch_due(&self, now: NaiveDateTime) -> bool {
+        let Some(upload) = &self.upload else {
+            return false;
+        };
+        let age = Duration::from_secs((now.and_utc().timestamp() - upload.start_timestamp).max(0) as u64);
+        upload.entries.is_full() || self.policy.is_due(upload.entries.len(), age, self.alarm_raised)
+    }
+
+    /// Takes the batch, with a queue it is written to the queue and `None` returned.
+    ///
+    /// A batch the queue cannot hand over is returned for the upload channel instead.
+    async fn take_batch(&mut self) -> Option<UploadVec> {
+        self.alarm_raised = false;
+        let upload = self.upload.take()?;
+        let (Some(queue), UploadEncoding::Protobuf) = (self.queue, self.encoding) else {
+            return self.encode_upload(upload);
+        };
+        info!("Queueing {} readings", upload.entries.len());
+        match queue.write(&upload).await {
+            Ok(len) => info!("Upload queued ({} bytes)", len),
+            Err(UploadQueueError::Overflow) => {
+                warn!("Upload too large for the queue => sent directly");
+                return self.encode_upload(upload);
             }
+            Err(e) => error!("Failed to queue upload: {:?}", e),
         }
         None
     }
-}
 
-impl From<Reading> for crate::proto::bt_::solar_::Reading {
-    fn from(reading: Reading) -> Self {
-        const MILLI_FACTOR: f32 = 1000.0;
-        Self {
-            battery_voltage: (reading.battery_voltage * MILLI_FACTOR) as i32,
-            battery_current: (reading.battery_current * MILLI_FACTOR) as i32,
-            panel_voltage: (reading.panel_voltage * MILLI_FACTOR) as i32,
-            panel_power: reading.panel_power as i32,
-            load_current: (reading.load_current * MILLI_FACTOR) as i32,
+    fn encode_upload(&self, upload: Upload) -> Option<UploadVec> {
+        info!("Uploading {} readings", upload.entries.len());
+        let mut upload_buffer = UploadBuffer::new();
+        // both fail only on the capacity of the buffer
+        let encoded = match self.encoding {
+            UploadEncoding::Protobuf => PbEncoder::new(&mut upload_buffer).encode_upload(&upload).is_ok(),
+            UploadEncoding::Cbor => CborEncoder::new(&mut upload_buffer).encode_upload(&upload).is_ok(),
+        };
+        if !encoded {
+            error!("Failed to encode upload: {} readings do not fit {} bytes", upload.entries.len(), UPLOAD_MAX_MESSAGE_SIZE);
+            return None;
         }
+        info!("Upload encoded ({} bytes)", upload_buffer.0.len());
+        Some(upload_buffer.0)
     }
 }
 
@@ -129,6 +461,10 @@ pub mod tests {
     use std::fs;
 
     use super::*;
+    use crate::{
+        sensor::{analog::AnalogReading, ve_direct::battery_monitor::BatteryReading},
+        solar_monitor::{battery::Chemistry, soc::SocConfig, upload_queue::tests::RamUploadStore},
+    };
 
     #[serial(bt_time)]
     #[tokio::test]
@@ -144,6 +480,7 @@ pub mod tests {
         let mut first = Upload::default();
         first.decode_from_bytes(&uploads[0]).unwrap();
         assert_eq!(first.start_timestamp, startup.and_utc().timestamp());
+        assert_eq!(first.schema_version, SCHEMA_VERSION);
         assert_eq!(first.entries.len(), 12);
         assert_eq!(first.entries[0].offset_in_seconds, 0);
         assert_eq!(first.entries[1].offset_in_seconds, 60 * 5);
@@ -197,11 +534,431 @@ pub mod tests {
         assert!(success);
     }
 
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_entries_per_upload() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender()).with_entries_per_upload(4);
+        let uploads = create_uploads(&mut runner, startup).await;
+        assert_eq!(uploads.len(), 6);
+        let mut first = Upload::default();
+        first.decode_from_bytes(&uploads[0]).unwrap();
+        assert_eq!(first.entries.len(), 4);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_max_latency() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender()).with_max_latency(embassy_time::Duration::from_secs(20 * 60));
+        let uploads = create_uploads(&mut runner, startup).await;
+        assert_eq!(uploads.len(), 4);
+        let mut second = Upload::default();
+        second.decode_from_bytes(&uploads[1]).unwrap();
+        assert_eq!(second.start_timestamp, (startup + Duration::minutes(25)).and_utc().timestamp());
+        assert_eq!(second.entries.len(), 5);
+
+        // the partial batch is flushed without a new reading once it is overdue
+        UtcTime::time_sync(startup + Duration::minutes(115)).await;
+        assert_eq!(runner.flush_overdue().await, None);
+        UtcTime::time_sync(startup + Duration::minutes(120)).await;
+        let mut last = Upload::default();
+        last.decode_from_bytes(&runner.flush_overdue().await.unwrap()).unwrap();
+        assert_eq!(last.entries.len(), 4);
+    }
+
+    #[test]
+    fn check_batch_policy() {
+        let policy = BatchPolicy {
+            max_entries: 12,
+            max_age: Some(embassy_time::Duration::from_secs(60 * 60)),
+            flush_on_alarm: true,
+        };
+        let minutes = |minutes: u64| embassy_time::Duration::from_secs(minutes * 60);
+        assert!(!policy.is_due(11, minutes(59), false));
+        assert!(policy.is_due(12, minutes(5), false));
+        assert!(policy.is_due(3, minutes(60), false));
+        assert!(policy.is_due(1, minutes(0), true));
+        assert!(
+            !BatchPolicy {
+                flush_on_alarm: false,
+                ..policy
+            }
+            .is_due(1, minutes(0), true)
+        );
+        assert!(!BatchPolicy::default().is_due(1_000, minutes(24 * 60), true));
+
+        let mut buffer = [0u8; 16];
+        let len = policy.encode(&mut buffer).unwrap();
+        assert_eq!(BatchPolicy::decode(&buffer[..len]), Some(policy));
+        let len = BatchPolicy::default().encode(&mut buffer).unwrap();
+        assert_eq!(BatchPolicy::decode(&buffer[..len]), Some(BatchPolicy::default()));
+        assert_eq!(BatchPolicy::decode(&buffer[..len - 1]), None);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_flush_on_alarm() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let policy = BatchPolicy {
+            max_entries: 8,
+            max_age: Some(embassy_time::Duration::from_secs(15 * 60)),
+            flush_on_alarm: true,
+        };
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_batch_policy(policy)
+            .with_battery_alarms(BatteryAlarms::new(Chemistry::LiFePo4.alarm_preset()));
+        let mut entries = std::vec::Vec::new();
+        for (minute, battery_voltage) in [(0, 13.2), (5, 12.9), (10, 11.8), (15, 11.7), (20, 11.6), (25, 12.6), (30, 12.6)] {
+            UtcTime::time_sync(startup + Duration::minutes(minute)).await;
+            sensor_channel
+                .send(Reading {
+                    battery_voltage,
+                    ..Default::default()
+                })
+                .await;
+            runner.run_once().await;
+            if let Ok(data) = upload_channel.try_receive() {
+                let mut upload = Upload::default();
+                upload.decode_from_bytes(&data).unwrap();
+                entries.push(upload.entries.len());
+            }
+        }
+        // the alarm flushes once when raised, the next batch is closed by its age
+        assert_eq!(entries, [3, 4]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_flush() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let flush = Flush::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender()).with_flush(&flush);
+        sensor_channel.send(Reading::default()).await;
+        runner.run_once().await;
+        assert!(upload_channel.is_empty());
+
+        embassy_futures::join::join(runner.run_once(), crate::solar_monitor::flush(&flush)).await;
+        let mut upload = Upload::default();
+        upload.decode_from_bytes(&upload_channel.receive().await).unwrap();
+        assert_eq!(upload.entries.len(), 1);
+
+        // nothing left to flush
+        embassy_futures::join::join(runner.run_once(), crate::solar_monitor::flush(&flush)).await;
+        assert!(upload_channel.is_empty());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_approximate_time() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::reset().await;
+        UtcTime::restore(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender());
+        assert!(runner.handle_reading(Reading::default()).await.is_none());
+        UtcTime::time_sync(startup + Duration::minutes(95)).await;
+        assert!(runner.handle_reading(Reading::default()).await.is_none());
+        let mut upload = Upload::default();
+        upload.decode_from_bytes(&runner.take_batch().await.unwrap()).unwrap();
+        // one entry before the sync is enough
+        assert!(upload.approximate_time);
+        assert_eq!(upload.entries.len(), 2);
+
+        assert!(runner.handle_reading(Reading::default()).await.is_none());
+        let mut upload = Upload::default();
+        upload.decode_from_bytes(&runner.take_batch().await.unwrap()).unwrap();
+        assert!(!upload.approximate_time);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_battery_monitor_merge() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let battery_channel = embassy_sync::channel::Channel::<NoopRawMutex, BatteryReading, 2>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_sensor(SensorSource::BatteryMonitor(battery_channel.dyn_receiver()))
+            .with_entries_per_upload(2);
+        battery_channel
+            .send(BatteryReading {
+                state_of_charge: 80.0,
+                ..Default::default()
+            })
+            .await;
+        battery_channel
+            .send(BatteryReading {
+                state_of_charge: 81.5,
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(runner.handle_reading(Reading::default()).await, None);
+        let upload = runner.handle_reading(Reading::default()).await.unwrap();
+
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload).unwrap();
+        assert_eq!(decoded.entries[0].battery_monitor().map(|battery| battery.state_of_charge), Some(815));
+        // no new battery reading in between
+        assert!(decoded.entries[1].battery_monitor().is_none());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_temperature() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let temperature_channel = embassy_sync::channel::Channel::<NoopRawMutex, f32, 2>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_sensor(SensorSource::Temperature(temperature_channel.dyn_receiver()))
+            .with_entries_per_upload(2);
+        temperature_channel.send(-4.0).await;
+        temperature_channel.send(-5.504).await;
+        assert_eq!(runner.handle_reading(Reading::default()).await, None);
+        let upload = runner.handle_reading(Reading::default()).await.unwrap();
+
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload).unwrap();
+        assert_eq!(decoded.entries[0].reading().and_then(|reading| reading.battery_temperature()), Some(&-550));
+        // no new temperature in between
+        assert_eq!(decoded.entries[1].reading().and_then(|reading| reading.battery_temperature()), None);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_analog() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let analog_channel = embassy_sync::channel::Channel::<NoopRawMutex, AnalogReading, 2>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_sensor(SensorSource::Analog(analog_channel.dyn_receiver()))
+            .with_entries_per_upload(2);
+        analog_channel
+            .send(AnalogReading {
+                values: heapless::Vec::from_slice(&[24.31]).unwrap(),
+            })
+            .await;
+        assert_eq!(runner.handle_reading(Reading::default()).await, None);
+        analog_channel
+            .send(AnalogReading {
+                values: heapless::Vec::from_slice(&[24.0, -0.5]).unwrap(),
+            })
+            .await;
+        let upload = runner.handle_reading(Reading::default()).await.unwrap();
+
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload).unwrap();
+        let first = decoded.entries[0].reading().unwrap();
+        assert_eq!((first.auxiliary_1(), first.auxiliary_2()), (Some(&24_310), None));
+        let second = decoded.entries[1].reading().unwrap();
+        assert_eq!((second.auxiliary_1(), second.auxiliary_2()), (Some(&24_000), Some(&-500)));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_pulses() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let pulse_channel = embassy_sync::channel::Channel::<NoopRawMutex, u32, 2>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_sensor(SensorSource::Pulses(pulse_channel.dyn_receiver()))
+            .with_entries_per_upload(3);
+        pulse_channel.send(12).await;
+        pulse_channel.send(3).await;
+        assert_eq!(runner.handle_reading(Reading::default()).await, None);
+        pulse_channel.send(0).await;
+        assert_eq!(runner.handle_reading(Reading::default()).await, None);
+        let upload = runner.handle_reading(Reading::default()).await.unwrap();
+
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload).unwrap();
+        let pulses: std::vec::Vec<_> = decoded.entries.iter().map(|entry| entry.reading().unwrap().pulse_count().copied()).collect();
+        // a pump that stopped reports zero, without a count in between none
+        assert_eq!(pulses, [Some(15), Some(0), None]);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_queue() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 1>::new();
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_queue(&queue)
+            .with_entries_per_upload(2);
+        let queue_runner = queue.runner(&mut store, upload_channel.sender());
+        let batch = async {
+            assert_eq!(runner.handle_reading(Reading::default()).await, None);
+            // the batch goes to the queue, not into the upload channel
+            assert_eq!(runner.handle_reading(Reading::default()).await, None);
+            upload_channel.ready_to_receive().await;
+        };
+        embassy_futures::select::select(batch, queue_runner.run()).await;
+
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload_channel.try_receive().unwrap()).unwrap();
+        assert_eq!(decoded.start_timestamp, startup.and_utc().timestamp());
+        assert_eq!(decoded.schema_version, SCHEMA_VERSION);
+        assert_eq!(decoded.entries.len(), 2);
+        assert_eq!(queue.stored(), 0);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_queue_overflow() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 1>::new();
+        let small_upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, Vec<u8, 16>, 1>::new();
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_queue(&queue)
+            .with_entries_per_upload(4);
+        let queue_runner = queue.runner(&mut store, small_upload_channel.sender());
+        let batch = async {
+            for _ in 0..3 {
+                assert_eq!(runner.handle_reading(Reading::default()).await, None);
+            }
+            // too large for the hand over, the batch is not stored but returned
+            runner.handle_reading(Reading::default()).await
+        };
+        let Either::First(upload) = embassy_futures::select::select(batch, queue_runner.run()).await else {
+            unreachable!("queue runner never returns");
+        };
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload.unwrap()).unwrap();
+        assert_eq!(decoded.entries.len(), 4);
+        assert_eq!(queue.stored(), 0);
+        assert!(store.batches.is_empty());
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_cbor_encoding() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(startup).await;
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 1>::new();
+        let queue = UploadQueue::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_queue(&queue)
+            .with_encoding(UploadEncoding::Cbor)
+            .with_entries_per_upload(2);
+        assert_eq!(runner.handle_reading(Reading::default()).await, None);
+        // CBOR bypasses the protobuf queue
+        let upload = runner.handle_reading(Reading::default()).await.unwrap();
+        assert_eq!(queue.stored(), 0);
+        // map of 3: the start timestamp (5 bytes), the schema version and 2 entries with an offset and a reading each
+        assert_eq!(upload[..2], [0xa3, 0x06]);
+        assert_eq!(upload[7..12], [0x07, SCHEMA_VERSION as u8, 0x01, 0x82, 0xa2]);
+        assert_eq!(upload.len(), 11 + 2 * 29);
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn check_estimated_state_of_charge() {
+        let startup = NaiveDateTime::parse_from_str("2025-11-30 22:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 10>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_soc_estimator(SocEstimator::new(SocConfig::new(Chemistry::Agm)))
+            .with_entries_per_upload(8);
+        let mut upload = None;
+        for i in 0..8 {
+            UtcTime::time_sync(startup + Duration::minutes(5) * i).await;
+            let reading = Reading {
+                battery_voltage: 12.3,
+                ..Default::default()
+            };
+            upload = runner.handle_reading(reading).await;
+        }
+
+        let mut decoded = Upload::default();
+        decoded.decode_from_bytes(&upload.unwrap()).unwrap();
+        // rested for 30 minutes from the first reading on
+        assert_eq!(decoded.entries[5].estimated_state_of_charge(), None);
+        assert_eq!(decoded.entries[6].estimated_state_of_charge(), Some(&500));
+        assert_eq!(decoded.entries[7].estimated_state_of_charge(), Some(&500));
+    }
+
+    #[tokio::test]
+    async fn check_upload_overflow() {
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, Reading, 1>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 1>::new();
+        let status = embassy_sync::watch::Watch::<NoopRawMutex, UploadStatus, 1>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender()).with_status(status.dyn_sender());
+        let mut status_receiver = status.anon_receiver();
+        assert_eq!(status_receiver.try_get(), Some(UploadStatus::default()));
+
+        runner.send_upload(Vec::from_slice(&[1]).unwrap()).await;
+        embassy_futures::join::join(runner.send_upload(Vec::from_slice(&[2]).unwrap()), async {
+            assert_eq!(
+                status_receiver.try_get(),
+                Some(UploadStatus {
+                    overflows: 1,
+                    overrun: true,
+                    ..Default::default()
+                })
+            );
+            assert_eq!(upload_channel.receive().await.as_slice(), &[1]);
+        })
+        .await;
+        assert_eq!(upload_channel.receive().await.as_slice(), &[2]);
+
+        runner.send_upload(Vec::from_slice(&[3]).unwrap()).await;
+        assert_eq!(
+            status_receiver.try_get(),
+            Some(UploadStatus {
+                overflows: 1,
+                overrun: false,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn check_loop_deadline() {
+        let sensor_channel = embassy_sync::channel::Channel::<NoopRawMutex, Reading, 1>::new();
+        let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 1>::new();
+        let status = embassy_sync::watch::Watch::<NoopRawMutex, UploadStatus, 1>::new();
+        let mut runner = super::new(sensor_channel.receiver(), upload_channel.sender())
+            .with_status(status.dyn_sender())
+            .with_loop_deadline(embassy_time::Duration::from_millis(100));
+        let mut status_receiver = status.anon_receiver();
+
+        runner.check_loop_deadline(embassy_time::Duration::from_millis(100));
+        assert_eq!(status_receiver.try_get().unwrap().late_loops, 0);
+        runner.check_loop_deadline(embassy_time::Duration::from_millis(250));
+        assert_eq!(status_receiver.try_get().unwrap().late_loops, 1);
+    }
+
     async fn create_uploads<'a, 'b, M: RawMutex, const NRECEIVER: usize, const NSENDER: usize>(
         runner: &mut Runner<'a, 'b, M, NRECEIVER, NSENDER>,
         startup: NaiveDateTime,
-    ) -> Vec<UploadVec, 4> {
-        let mut uploads = Vec::<UploadVec, 4>::new();
+    ) -> std::vec::Vec<UploadVec> {
+        let mut uploads = std::vec::Vec::new();
         for i in 0..24 {
             let f = i as f32 / 10.0;
             let reading = Reading {
@@ -210,10 +967,11 @@ pub mod tests {
                 panel_voltage: (18.0 + f),
                 panel_power: (50.0 + f * 10.0),
                 load_current: (1.0 + f),
+                ..Default::default()
             };
             UtcTime::time_sync(startup + Duration::minutes(5) * i).await;
             if let Some(upload) = runner.handle_reading(reading).await {
-                uploads.push(upload).unwrap();
+                uploads.push(upload);
             }
         }
         uploads
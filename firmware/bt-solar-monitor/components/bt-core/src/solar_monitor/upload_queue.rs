@@ -0,0 +1,548 @@
+//! Flash queue of the upload batches.
+//!
+//! With a queue the upload runner does not encode a batch into one `Upload::MAX_SIZE`
+//! buffer, it streams it record by record to the [`UploadQueueRunner`]: the start timestamp,
+//! every entry and the schema version with the time quality are encoded as separate `Upload` messages. The
+//! concatenated records decode as the whole batch (the repeated entries of concatenated
+//! messages are merged) and are byte by byte the encoding of the whole `Upload`.
+//!
+//! The runner appends the records of a batch to the [`UploadStore`] and commits them
+//! together, only complete batches are stored. Once the upload channel has room it hands
+//! the oldest stored batch to the cloud runner and removes it from the store.
+//!
+//! The queue saves the encode buffer of the upload runner, not the one of the upload: the
+//! hand over still reads the whole batch into the `Vec<u8, B>` of the upload channel, the
+//! records go straight into it. [`UploadQueue::write`] rejects a batch larger than `B`
+//! before it is stored, the upload runner sends it directly then, so no stored batch is
+//! too large for the hand over.
+//!
+//! The removal deletes the records of the batch, the writes that make the flash database
+//! compact its pages sooner or later. With [`UploadQueueRunner::with_maintenance_window`]
+//! the runner defers the removals of the handed over batches to the idle windows of the
+//! [`PowerManager`], the modem asleep between the upload bursts, and the flash maintenance
+//! does not delay the burst in progress. A reset before the removal uploads the batch again.
+
+#![allow(async_fn_in_trait)]
+
+use core::{cell::Cell, future::poll_fn};
+
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
+    channel::{Channel, Sender},
+};
+use heapless::Vec;
+use micropb::{MessageEncode, PbEncoder, PbWrite};
+
+use crate::{
+    power::PowerManager,
+    proto::bt_::solar_::{Upload, UploadEntry},
+};
+
+/// Longest record, an entry with its tag and two byte length.
+pub const UPLOAD_RECORD_SIZE: usize = UploadEntry::MAX_SIZE.expect("Size known at compile time") + 3;
+const UPLOAD_RECORD_QUEUE_SIZE: usize = 2;
+
+/// Tags of the `Upload` fields, see `readings.proto`.
+const START_TIMESTAMP_TAG: u32 = 6 << 3;
+const ENTRIES_TAG: u32 = (1 << 3) | 2;
+const SCHEMA_VERSION_TAG: u32 = 7 << 3;
+const APPROXIMATE_TIME_TAG: u32 = 8 << 3;
+
+pub type UploadRecord = Vec<u8, UPLOAD_RECORD_SIZE>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UploadQueueError {
+    /// Reading or writing the flash failed.
+    Storage,
+    /// A record or the stored batch does not fit its buffer.
+    Overflow,
+}
+
+/// Flash store of the batches, the app implements it over its flash database (ekv on the nRF).
+pub trait UploadStore {
+    /// Number of stored batches.
+    async fn batches(&mut self) -> Result<u32, UploadQueueError>;
+    /// Appends `record` to the batch being written, it is not stored before [`UploadStore::commit`].
+    async fn append(&mut self, record: &[u8]) -> Result<(), UploadQueueError>;
+    /// Stores the batch being written as the newest one.
+    async fn commit(&mut self) -> Result<(), UploadQueueError>;
+    /// Drops the records appended since the last commit.
+    async fn discard(&mut self);
+    /// Reads record `index` of batch `batch`, counted from the oldest one, into `buffer`, `None`
+    /// past its last record or the newest batch.
+    async fn read(&mut self, batch: u32, index: u16, buffer: &mut [u8]) -> Result<Option<usize>, UploadQueueError>;
+    /// Removes the oldest batch.
+    async fn remove_oldest(&mut self) -> Result<(), UploadQueueError>;
+}
+
+// the channel holds the records by value anyway
+#[allow(clippy::large_enum_variant)]
+enum QueueMessage {
+    Record(UploadRecord),
+    Commit,
+    Discard,
+}
+
+/// Encodes into one record, output beyond [`UPLOAD_RECORD_SIZE`] fails the encoding.
+struct RecordWriter(UploadRecord);
+
+impl PbWrite for RecordWriter {
+    type Error = heapless::CapacityError;
+
+    #[inline]
+    fn pb_write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.extend_from_slice(data)
+    }
+}
+
+/// Link between the upload runner and the [`UploadQueueRunner`].
+pub struct UploadQueue {
+    messages: Channel<NoopRawMutex, QueueMessage, UPLOAD_RECORD_QUEUE_SIZE>,
+    stored: Cell<u32>,
+    /// The upload buffer `B` of the runner, the largest batch it hands over.
+    max_batch_size: Cell<usize>,
+}
+
+impl UploadQueue {
+    pub fn new() -> Self {
+        Self {
+            messages: Channel::new(),
+            stored: Cell::new(0),
+            max_batch_size: Cell::new(usize::MAX),
+        }
+    }
+
+    pub fn runner<'a, S: UploadStore, M: RawMutex, const B: usize, const N: usize>(
+        &'a self,
+        store: S,
+        upload_sender: Sender<'a, M, Vec<u8, B>, N>,
+    ) -> UploadQueueRunner<'a, S, M, B, N> {
+        self.max_batch_size.set(B);
+        UploadQueueRunner {
+            queue: self,
+            store,
+            upload_sender,
+            failed: false,
+            power: None,
+            handed_over: 0,
+        }
+    }
+
+    /// Number of stored batches not handed over yet.
+    pub fn stored(&self) -> u32 {
+        self.stored.get()
+    }
+
+    /// Streams `upload` record by record to the store, returns the encoded size.
+    ///
+    /// A batch larger than the upload buffer of the runner is discarded before the commit,
+    /// an [`UploadQueueError::Overflow`].
+    pub async fn write(&self, upload: &Upload) -> Result<usize, UploadQueueError> {
+        let mut len = 0;
+        let header = self.encode(|encoder| {
+            if upload.start_timestamp != 0 {
+                encoder.encode_varint32(START_TIMESTAMP_TAG)?;
+                encoder.encode_int64(upload.start_timestamp)?;
+            }
+            Ok(())
+        });
+        len += self.send(header, len).await?;
+        for entry in upload.entries.iter() {
+            let record = self.encode(|encoder| {
+                encoder.encode_varint32(ENTRIES_TAG)?;
+                entry.encode_len_delimited(encoder)
+            });
+            len += self.send(record, len).await?;
+        }
+        let trailer = self.encode(|encoder| {
+            if upload.schema_version != 0 {
+                encoder.encode_varint32(SCHEMA_VERSION_TAG)?;
+                encoder.encode_varint32(upload.schema_version)?;
+            }
+            if upload.approximate_time {
+                encoder.encode_varint32(APPROXIMATE_TIME_TAG)?;
+                encoder.encode_bool(true)?;
+            }
+            Ok(())
+        });
+        len += self.send(trailer, len).await?;
+        self.messages.send(QueueMessage::Commit).await;
+        Ok(len)
+    }
+
+    fn encode(&self, encode: impl FnOnce(&mut PbEncoder<&mut RecordWriter>) -> Result<(), heapless::CapacityError>) -> Result<UploadRecord, UploadQueueError> {
+        let mut writer = RecordWriter(UploadRecord::new());
+        encode(&mut PbEncoder::new(&mut writer)).map_err(|_| UploadQueueError::Overflow)?;
+        Ok(writer.0)
+    }
+
+    /// Sends `record` to the runner, `len` bytes of the batch are sent already.
+    async fn send(&self, record: Result<UploadRecord, UploadQueueError>, len: usize) -> Result<usize, UploadQueueError> {
+        let record = record.and_then(|record| {
+            if len + record.len() > self.max_batch_size.get() {
+                warn!("UploadQueue> batch larger than the upload buffer ({} bytes)", self.max_batch_size.get());
+                return Err(UploadQueueError::Overflow);
+            }
+            Ok(record)
+        });
+        match record {
+            // the header and trailer of a batch without timestamp and version are empty
+            Ok(record) if record.is_empty() => Ok(0),
+            Ok(record) => {
+                let len = record.len();
+                self.messages.send(QueueMessage::Record(record)).await;
+                Ok(len)
+            }
+            Err(e) => {
+                self.messages.send(QueueMessage::Discard).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Default for UploadQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct UploadQueueRunner<'a, S: UploadStore, M: RawMutex, const B: usize, const N: usize> {
+    queue: &'a UploadQueue,
+    store: S,
+    upload_sender: Sender<'a, M, Vec<u8, B>, N>,
+    /// The batch being written lost a record, it is not committed.
+    failed: bool,
+    power: Option<&'a PowerManager>,
+    /// The oldest batches, handed over but not removed from the store yet.
+    handed_over: u32,
+}
+
+impl<'a, S: UploadStore, M: RawMutex, const B: usize, const N: usize> UploadQueueRunner<'a, S, M, B, N> {
+    /// Removes the handed over batches only while `power` is idle.
+    pub fn with_maintenance_window(self, power: &'a PowerManager) -> Self {
+        Self { power: Some(power), ..self }
+    }
+
+    pub async fn run(mut self) {
+        self.load().await;
+        loop {
+            self.once().await;
+        }
+    }
+
+    async fn load(&mut self) {
+        match self.store.batches().await {
+            Ok(stored) => self.queue.stored.set(stored),
+            Err(e) => warn!("UploadQueue> reading the store failed: {:?}", e),
+        }
+        info!("UploadQueue> {} stored batches", self.queue.stored());
+    }
+
+    async fn once(&mut self) {
+        let upload_channel_ready = async {
+            if self.queue.stored() == 0 {
+                core::future::pending::<()>().await;
+            }
+            poll_fn(|cx| self.upload_sender.poll_ready_to_send(cx)).await
+        };
+        let maintenance_window = async {
+            match self.power {
+                Some(power) if self.handed_over > 0 => power.wait_idle().await,
+                _ => core::future::pending::<()>().await,
+            }
+        };
+        match select3(self.queue.messages.receive(), upload_channel_ready, maintenance_window).await {
+            Either3::First(QueueMessage::Record(record)) => {
+                if self.failed {
+                    return;
+                }
+                if let Err(e) = self.store.append(&record).await {
+                    warn!("UploadQueue> appending a record failed: {:?} => batch discarded", e);
+                    self.store.discard().await;
+                    self.failed = true;
+                }
+            }
+            Either3::First(QueueMessage::Commit) if core::mem::take(&mut self.failed) => {}
+            Either3::First(QueueMessage::Commit) => match self.store.commit().await {
+                Ok(()) => {
+                    self.queue.stored.set(self.queue.stored() + 1);
+                    debug!("UploadQueue> batch stored, {} stored batches", self.queue.stored());
+                }
+                Err(e) => warn!("UploadQueue> storing the batch failed: {:?}", e),
+            },
+            Either3::First(QueueMessage::Discard) => {
+                warn!("UploadQueue> batch not encodable => discarded");
+                if !core::mem::take(&mut self.failed) {
+                    self.store.discard().await;
+                }
+            }
+            Either3::Second(()) => self.hand_over().await,
+            Either3::Third(()) => self.remove_handed_over().await,
+        }
+    }
+
+    /// Moves the oldest batch not handed over yet into the upload channel.
+    async fn hand_over(&mut self) {
+        match self.read_next().await {
+            Ok(upload) => {
+                info!("UploadQueue> handing over a batch ({} bytes)", upload.len());
+                if self.upload_sender.try_send(upload).is_err() {
+                    return;
+                }
+            }
+            // only a batch stored with a larger upload buffer, `write` rejects them
+            Err(UploadQueueError::Overflow) => warn!("UploadQueue> stored batch too large => dropped"),
+            Err(e) => {
+                warn!("UploadQueue> reading the oldest batch failed: {:?}", e);
+                return;
+            }
+        }
+        self.queue.stored.set(self.queue.stored().saturating_sub(1));
+        self.handed_over += 1;
+        if self.power.is_none() {
+            self.remove_handed_over().await;
+        }
+    }
+
+    /// Removes the oldest handed over batch from the store.
+    async fn remove_handed_over(&mut self) {
+        match self.store.remove_oldest().await {
+            Ok(()) => {
+                self.handed_over -= 1;
+                debug!("UploadQueue> batch removed, {} to remove", self.handed_over);
+            }
+            Err(e) => warn!("UploadQueue> removing the oldest batch failed: {:?}", e),
+        }
+    }
+
+    /// Reads the records straight into the buffer handed over, a record that does not fit
+    /// the rest of it is an [`UploadQueueError::Overflow`] of the store.
+    async fn read_next(&mut self) -> Result<Vec<u8, B>, UploadQueueError> {
+        let mut upload = Vec::new();
+        let _ = upload.resize(B, 0);
+        let mut len = 0;
+        for index in 0.. {
+            let Some(read) = self.store.read(self.handed_over, index, &mut upload[len..]).await? else {
+                break;
+            };
+            len += read;
+        }
+        upload.truncate(len);
+        Ok(upload)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use embassy_futures::{join::join, select::select};
+    use embassy_sync::channel::Channel;
+    use embassy_time::{Duration, with_timeout};
+    use micropb::MessageDecode;
+    use std::{collections::VecDeque, vec::Vec as StdVec};
+
+    use super::*;
+    use crate::{proto::bt_::solar_::Reading, schema::SCHEMA_VERSION};
+
+    #[derive(Default)]
+    pub(crate) struct RamUploadStore {
+        pub(crate) batches: VecDeque<StdVec<StdVec<u8>>>,
+        writing: StdVec<StdVec<u8>>,
+    }
+
+    impl UploadStore for &mut RamUploadStore {
+        async fn batches(&mut self) -> Result<u32, UploadQueueError> {
+            Ok(self.batches.len() as u32)
+        }
+
+        async fn append(&mut self, record: &[u8]) -> Result<(), UploadQueueError> {
+            self.writing.push(record.to_vec());
+            Ok(())
+        }
+
+        async fn commit(&mut self) -> Result<(), UploadQueueError> {
+            let batch = core::mem::take(&mut self.writing);
+            self.batches.push_back(batch);
+            Ok(())
+        }
+
+        async fn discard(&mut self) {
+            self.writing.clear();
+        }
+
+        async fn read(&mut self, batch: u32, index: u16, buffer: &mut [u8]) -> Result<Option<usize>, UploadQueueError> {
+            let Some(record) = self.batches.get(batch as usize).and_then(|batch| batch.get(index as usize)) else {
+                return Ok(None);
+            };
+            buffer.get_mut(..record.len()).ok_or(UploadQueueError::Overflow)?.copy_from_slice(record);
+            Ok(Some(record.len()))
+        }
+
+        async fn remove_oldest(&mut self) -> Result<(), UploadQueueError> {
+            self.batches.pop_front();
+            Ok(())
+        }
+    }
+
+    fn upload(start_timestamp: i64, entries: i32) -> Upload {
+        let mut upload = Upload {
+            start_timestamp,
+            schema_version: SCHEMA_VERSION,
+            ..Default::default()
+        };
+        for offset in 0..entries {
+            let reading = Reading {
+                battery_voltage: 12_800 + offset,
+                battery_current: -1_200,
+                ..Default::default()
+            };
+            let _ = upload
+                .entries
+                .push(UploadEntry::default().init_offset_in_seconds(offset * 300).init_reading(reading));
+        }
+        upload
+    }
+
+    fn encode(upload: &Upload) -> StdVec<u8> {
+        let mut buffer = StdVec::new();
+        upload.encode(&mut PbEncoder::new(&mut buffer)).unwrap();
+        buffer
+    }
+
+    /// Writes the `batches` while the runner handles the records, then lets it hand over until the channel is full.
+    async fn write_all<S: UploadStore>(queue: &UploadQueue, runner: &mut UploadQueueRunner<'_, S, NoopRawMutex, 4096, 1>, batches: &[Upload]) {
+        let write = async {
+            for batch in batches {
+                queue.write(batch).await.unwrap();
+            }
+        };
+        select(write, async {
+            loop {
+                runner.once().await;
+            }
+        })
+        .await;
+        while with_timeout(Duration::from_millis(10), runner.once()).await.is_ok() {}
+    }
+
+    #[tokio::test]
+    async fn check_records_are_the_upload_encoding() {
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        let uploads = Channel::<NoopRawMutex, Vec<u8, 4096>, 1>::new();
+        let mut batch = upload(1_764_505_800, 12);
+        batch.approximate_time = true;
+        let mut runner = queue.runner(&mut store, uploads.sender());
+        let (written, _) = join(queue.write(&batch), async {
+            // header, entries, trailer and commit
+            for _ in 0..15 {
+                runner.once().await;
+            }
+        })
+        .await;
+        assert_eq!(written, Ok(encode(&batch).len()));
+        assert_eq!(queue.stored(), 1);
+        assert_eq!(store.batches[0].len(), 14);
+        assert!(store.batches[0].iter().all(|record| record.len() <= UPLOAD_RECORD_SIZE));
+        assert_eq!(store.batches[0].concat(), encode(&batch));
+    }
+
+    #[tokio::test]
+    async fn check_hand_over_when_channel_has_room() {
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        let uploads = Channel::<NoopRawMutex, Vec<u8, 4096>, 1>::new();
+        let first = upload(1_764_505_800, 2);
+        let second = upload(1_764_509_400, 3);
+        let mut runner = queue.runner(&mut store, uploads.sender());
+        write_all(&queue, &mut runner, &[first.clone(), second.clone()]).await;
+        // the channel is full, the second batch stays in the store
+        assert_eq!(queue.stored(), 1);
+        let mut received = Upload::default();
+        received.decode_from_bytes(&uploads.try_receive().unwrap()).unwrap();
+        assert_eq!(received.start_timestamp, first.start_timestamp);
+        assert_eq!(received.entries.len(), 2);
+
+        runner.once().await;
+        assert_eq!(queue.stored(), 0);
+        assert!(store.batches.is_empty());
+        assert_eq!(uploads.try_receive().unwrap().as_slice(), encode(&second));
+    }
+
+    #[tokio::test]
+    async fn check_removal_waits_for_maintenance_window() {
+        let queue = UploadQueue::new();
+        let power = PowerManager::new();
+        let mut store = RamUploadStore::default();
+        let uploads = Channel::<NoopRawMutex, Vec<u8, 4096>, 1>::new();
+        let first = upload(1_764_505_800, 2);
+        let second = upload(1_764_509_400, 3);
+        let mut runner = queue.runner(&mut store, uploads.sender()).with_maintenance_window(&power);
+        let burst = power.handle().acquire();
+        write_all(&queue, &mut runner, &[first.clone(), second.clone()]).await;
+        assert_eq!(uploads.try_receive().unwrap().as_slice(), encode(&first));
+        // the burst goes on with the next batch, the handed over one stays until the modem sleeps
+        runner.once().await;
+        assert_eq!(queue.stored(), 0);
+        assert_eq!(uploads.try_receive().unwrap().as_slice(), encode(&second));
+        assert!(with_timeout(Duration::from_millis(10), runner.once()).await.is_err());
+        assert_eq!(runner.handed_over, 2);
+
+        drop(burst);
+        runner.once().await;
+        runner.once().await;
+        assert_eq!(runner.handed_over, 0);
+        assert!(store.batches.is_empty());
+    }
+
+    #[tokio::test]
+    async fn check_batch_larger_than_upload_buffer_is_not_stored() {
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        let uploads = Channel::<NoopRawMutex, Vec<u8, 256>, 1>::new();
+        let runner = queue.runner(&mut store, uploads.sender());
+        let write = async {
+            assert_eq!(queue.write(&upload(1_764_505_800, 12)).await, Err(UploadQueueError::Overflow));
+            assert_eq!(queue.write(&upload(1_764_509_400, 1)).await, Ok(encode(&upload(1_764_509_400, 1)).len()));
+            uploads.ready_to_receive().await;
+        };
+        select(write, runner.run()).await;
+        assert_eq!(uploads.try_receive().unwrap().as_slice(), encode(&upload(1_764_509_400, 1)));
+        assert_eq!(queue.stored(), 0);
+        assert!(store.batches.is_empty());
+    }
+
+    /// A batch stored by a firmware with a larger upload buffer.
+    #[tokio::test]
+    async fn check_batch_larger_than_upload_buffer_is_dropped() {
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        store.batches.push_back(std::vec![encode(&upload(1_764_505_800, 12))]);
+        store.batches.push_back(std::vec![encode(&upload(1_764_509_400, 1))]);
+        let uploads = Channel::<NoopRawMutex, Vec<u8, 256>, 1>::new();
+        let mut runner = queue.runner(&mut store, uploads.sender());
+        runner.load().await;
+        runner.once().await;
+        assert_eq!(queue.stored(), 1);
+        assert!(uploads.try_receive().is_err());
+        runner.once().await;
+        assert_eq!(queue.stored(), 0);
+        assert_eq!(uploads.try_receive().unwrap().as_slice(), encode(&upload(1_764_509_400, 1)));
+    }
+
+    #[tokio::test]
+    async fn check_stored_batches_survive_restart() {
+        let queue = UploadQueue::new();
+        let mut store = RamUploadStore::default();
+        store.batches.push_back(std::vec![encode(&upload(1_764_505_800, 1))]);
+        let uploads = Channel::<NoopRawMutex, Vec<u8, 4096>, 1>::new();
+        let mut runner = queue.runner(&mut store, uploads.sender());
+        runner.load().await;
+        assert_eq!(queue.stored(), 1);
+        runner.once().await;
+        assert_eq!(queue.stored(), 0);
+        assert!(uploads.try_receive().is_ok());
+    }
+}
@@ -0,0 +1,632 @@
+//! Persistent settings of the application modules.
+//!
+//! A [`ConfigStore`] keeps typed values under namespaced keys (`cloud/retries`) in a
+//! [`KeyValueStore`], the app implements it over its flash database (ekv on the nRF).
+//! The values are encoded little endian, strings as their UTF-8 bytes. The layout
+//! version is stored along, [`ConfigStore::migrate`] moves the values of older
+//! firmware to the keys of the running one.
+//!
+//! The flash database only writes single keys atomically. A migration is therefore
+//! planned first and its writes and deletes are stored as one journal value, then
+//! applied. A journal left behind by a reset is applied again at the next start, a
+//! migration takes effect completely (version included) or not at all.
+
+#![allow(async_fn_in_trait)]
+
+use core::{fmt::Write, marker::PhantomData};
+
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+/// Longest `namespace/name`.
+pub const CONFIG_KEY_SIZE: usize = 32;
+/// Largest encoded value.
+pub const CONFIG_VALUE_SIZE: usize = 128;
+
+/// Largest journal of a [`Migration`], see the module documentation.
+pub const CONFIG_JOURNAL_SIZE: usize = 512;
+
+const VERSION_KEY: Key = Key::new("config", "version");
+const JOURNAL_KEY: Key = Key::new("config", "journal");
+const JOURNAL_WRITE: u8 = 0;
+const JOURNAL_DELETE: u8 = 1;
+
+/// The layout versions of the bt-core settings, for [`ConfigStore::migrate`] at boot.
+///
+/// 1: `battery/chemistry` and `cloud/apn_profiles`.
+pub const CONFIG_MIGRATIONS: &[Migration] = &[Migration { version: 1, steps: &[] }];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StorageError {
+    /// The flash database failed.
+    Storage,
+    /// The stored value does not decode as the type of the key.
+    Corrupt,
+    KeyTooLong,
+    ValueTooLarge,
+}
+
+/// The flash database below the [`ConfigStore`].
+pub trait KeyValueStore {
+    /// Reads the value of `key` into `buffer`, `None` if the key does not exist.
+    async fn read(&mut self, key: &[u8], buffer: &mut [u8]) -> Result<Option<usize>, StorageError>;
+    async fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
+    /// Removes `key`, a key that does not exist is no error.
+    async fn delete(&mut self, key: &[u8]) -> Result<(), StorageError>;
+}
+
+/// A value that can be stored in the [`ConfigStore`].
+pub trait ConfigValue: Sized {
+    /// Encodes into `buffer`, `None` if it does not fit.
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize>;
+    fn decode(data: &[u8]) -> Option<Self>;
+}
+
+macro_rules! le_config_value {
+    ($($t:ty),*) => {$(
+        impl ConfigValue for $t {
+            fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+                let bytes = self.to_le_bytes();
+                buffer.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+                Some(bytes.len())
+            }
+
+            fn decode(data: &[u8]) -> Option<Self> {
+                Some(<$t>::from_le_bytes(data.try_into().ok()?))
+            }
+        }
+    )*};
+}
+
+le_config_value!(u8, u16, u32, u64, i8, i16, i32, i64, f32);
+
+impl ConfigValue for bool {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        (*self as u8).encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        match u8::decode(data)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// In milliseconds.
+impl ConfigValue for Duration {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.as_millis().encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        u64::decode(data).map(Duration::from_millis)
+    }
+}
+
+impl<const N: usize> ConfigValue for String<N> {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.as_bytes().encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        String::from_utf8(Vec::from_slice(data).ok()?).ok()
+    }
+}
+
+impl<const N: usize> ConfigValue for Vec<u8, N> {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.as_slice().encode(buffer)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        Vec::from_slice(data).ok()
+    }
+}
+
+impl ConfigValue for &[u8] {
+    fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        buffer.get_mut(..self.len())?.copy_from_slice(self);
+        Some(self.len())
+    }
+
+    fn decode(_data: &[u8]) -> Option<Self> {
+        // borrowed values can only be written
+        None
+    }
+}
+
+/// Untyped `namespace/name` of a setting, e.g. for the [`Migration`] steps.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Key {
+    pub namespace: &'static str,
+    pub name: &'static str,
+}
+
+impl Key {
+    pub const fn new(namespace: &'static str, name: &'static str) -> Self {
+        Self { namespace, name }
+    }
+
+    fn path(&self) -> Result<String<CONFIG_KEY_SIZE>, StorageError> {
+        let mut path = String::new();
+        write!(path, "{}/{}", self.namespace, self.name).map_err(|_| StorageError::KeyTooLong)?;
+        Ok(path)
+    }
+}
+
+/// The key of a setting of type `T`, declared as a constant by the module that owns it.
+pub struct ConfigKey<T> {
+    key: Key,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> ConfigKey<T> {
+    pub const fn new(namespace: &'static str, name: &'static str) -> Self {
+        Self {
+            key: Key::new(namespace, name),
+            value: PhantomData,
+        }
+    }
+
+    pub const fn key(&self) -> Key {
+        self.key
+    }
+}
+
+impl<T> Clone for ConfigKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ConfigKey<T> {}
+
+/// A change of the key layout, applied by [`ConfigStore::migrate`].
+#[derive(Debug, Copy, Clone)]
+pub enum MigrationStep {
+    Rename {
+        from: Key,
+        to: Key,
+    },
+    Remove(Key),
+    /// Re-encodes the value in place, a `None` of the conversion removes it.
+    Convert {
+        key: Key,
+        convert: fn(&[u8], &mut [u8]) -> Option<usize>,
+    },
+}
+
+/// The steps that lead from `version - 1` to `version`.
+#[derive(Debug, Copy, Clone)]
+pub struct Migration {
+    pub version: u32,
+    pub steps: &'static [MigrationStep],
+}
+
+/// Typed settings in a [`KeyValueStore`], see the module documentation.
+pub struct ConfigStore<S: KeyValueStore> {
+    store: S,
+}
+
+impl<S: KeyValueStore> ConfigStore<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    pub async fn get<T: ConfigValue>(&mut self, key: ConfigKey<T>) -> Result<Option<T>, StorageError> {
+        let mut buffer = [0u8; CONFIG_VALUE_SIZE];
+        let Some(len) = self.read(key.key, &mut buffer).await? else {
+            return Ok(None);
+        };
+        T::decode(&buffer[..len]).map(Some).ok_or(StorageError::Corrupt)
+    }
+
+    /// The stored value or `default` if there is none or it can not be read.
+    pub async fn get_or<T: ConfigValue>(&mut self, key: ConfigKey<T>, default: T) -> T {
+        match self.get(key).await {
+            Ok(Some(value)) => value,
+            Ok(None) => default,
+            Err(e) => {
+                warn!("Config {}/{} not readable ({:?}) => default", key.key.namespace, key.key.name, e);
+                default
+            }
+        }
+    }
+
+    pub async fn set<T: ConfigValue>(&mut self, key: ConfigKey<T>, value: &T) -> Result<(), StorageError> {
+        let mut buffer = [0u8; CONFIG_VALUE_SIZE];
+        let len = value.encode(&mut buffer).ok_or(StorageError::ValueTooLarge)?;
+        self.store.write(key.key.path()?.as_bytes(), &buffer[..len]).await
+    }
+
+    pub async fn remove<T>(&mut self, key: ConfigKey<T>) -> Result<(), StorageError> {
+        self.store.delete(key.key.path()?.as_bytes()).await
+    }
+
+    /// The layout version of the stored settings, 0 before the first [`ConfigStore::migrate`].
+    pub async fn version(&mut self) -> Result<u32, StorageError> {
+        let mut buffer = [0u8; 4];
+        match self.read(VERSION_KEY, &mut buffer).await? {
+            Some(len) => u32::decode(&buffer[..len]).ok_or(StorageError::Corrupt),
+            None => Ok(0),
+        }
+    }
+
+    /// Applies the `migrations` newer than the stored version in order, returns the resulting version.
+    ///
+    /// Every migration is applied atomically, see the module documentation. Settings of a newer
+    /// firmware (after a downgrade) are left as they are.
+    pub async fn migrate(&mut self, migrations: &[Migration]) -> Result<u32, StorageError> {
+        self.recover().await?;
+        let stored = self.version().await?;
+        if let Some(latest) = migrations.last()
+            && stored > latest.version
+        {
+            warn!("Config> version {} is newer than the firmware ({})", stored, latest.version);
+        }
+        let mut version = stored;
+        for migration in migrations.iter().filter(|migration| migration.version > stored) {
+            info!("Config> migrate {} => {}", version, migration.version);
+            let mut journal = Journal::default();
+            for step in migration.steps {
+                self.plan(&mut journal, step).await?;
+            }
+            journal.write(VERSION_KEY, &migration.version)?;
+            self.store.write(JOURNAL_KEY.path()?.as_bytes(), &journal.0).await?;
+            self.commit(&journal).await?;
+            version = migration.version;
+        }
+        Ok(version)
+    }
+
+    /// Completes a migration interrupted after its journal was stored.
+    async fn recover(&mut self) -> Result<(), StorageError> {
+        let mut journal = Journal::default();
+        journal.0.resize_default(CONFIG_JOURNAL_SIZE).map_err(|_| StorageError::ValueTooLarge)?;
+        match self.store.read(JOURNAL_KEY.path()?.as_bytes(), &mut journal.0).await? {
+            Some(len) => {
+                journal.0.truncate(len);
+                warn!("Config> completing an interrupted migration");
+                self.commit(&journal).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn commit(&mut self, journal: &Journal) -> Result<(), StorageError> {
+        for entry in journal.entries() {
+            let (key, value) = entry?;
+            match value {
+                Some(value) => self.store.write(key, value).await?,
+                None => self.store.delete(key).await?,
+            }
+        }
+        self.store.delete(JOURNAL_KEY.path()?.as_bytes()).await
+    }
+
+    /// Adds the changes of `step` to the `journal`, on top of the changes already planned.
+    async fn plan(&mut self, journal: &mut Journal, step: &MigrationStep) -> Result<(), StorageError> {
+        let mut buffer = [0u8; CONFIG_VALUE_SIZE];
+        match *step {
+            MigrationStep::Rename { from, to } => {
+                if let Some(len) = self.planned_read(journal, from, &mut buffer).await? {
+                    journal.write(to, &&buffer[..len])?;
+                    journal.delete(from)?;
+                }
+            }
+            MigrationStep::Remove(key) => journal.delete(key)?,
+            MigrationStep::Convert { key, convert } => {
+                if let Some(len) = self.planned_read(journal, key, &mut buffer).await? {
+                    let mut converted = [0u8; CONFIG_VALUE_SIZE];
+                    match convert(&buffer[..len], &mut converted) {
+                        Some(len) => journal.write(key, &&converted[..len])?,
+                        None => journal.delete(key)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn planned_read(&mut self, journal: &Journal, key: Key, buffer: &mut [u8]) -> Result<Option<usize>, StorageError> {
+        let path = key.path()?;
+        match journal.latest(path.as_bytes())? {
+            Some(Some(value)) => {
+                buffer.get_mut(..value.len()).ok_or(StorageError::ValueTooLarge)?.copy_from_slice(value);
+                Ok(Some(value.len()))
+            }
+            Some(None) => Ok(None),
+            None => self.store.read(path.as_bytes(), buffer).await,
+        }
+    }
+
+    async fn read(&mut self, key: Key, buffer: &mut [u8]) -> Result<Option<usize>, StorageError> {
+        self.store.read(key.path()?.as_bytes(), buffer).await
+    }
+}
+
+/// The writes and deletes of a migration, each `kind, key length, key[, value length, value]`.
+#[derive(Default)]
+struct Journal(Vec<u8, CONFIG_JOURNAL_SIZE>);
+
+impl Journal {
+    fn write(&mut self, key: Key, value: &impl ConfigValue) -> Result<(), StorageError> {
+        let mut buffer = [0u8; CONFIG_VALUE_SIZE];
+        let len = value.encode(&mut buffer).ok_or(StorageError::ValueTooLarge)?;
+        self.push(JOURNAL_WRITE, key)?;
+        self.extend(&[len as u8])?;
+        self.extend(&buffer[..len])
+    }
+
+    fn delete(&mut self, key: Key) -> Result<(), StorageError> {
+        self.push(JOURNAL_DELETE, key)
+    }
+
+    fn push(&mut self, kind: u8, key: Key) -> Result<(), StorageError> {
+        let path = key.path()?;
+        self.extend(&[kind, path.len() as u8])?;
+        self.extend(path.as_bytes())
+    }
+
+    fn extend(&mut self, data: &[u8]) -> Result<(), StorageError> {
+        self.0.extend_from_slice(data).map_err(|_| StorageError::ValueTooLarge)
+    }
+
+    /// The last change of `key`, `Some(None)` if it is deleted.
+    fn latest(&self, key: &[u8]) -> Result<Option<Option<&[u8]>>, StorageError> {
+        let mut latest = None;
+        for entry in self.entries() {
+            let (path, value) = entry?;
+            if path == key {
+                latest = Some(value);
+            }
+        }
+        Ok(latest)
+    }
+
+    fn entries(&self) -> JournalEntries<'_> {
+        JournalEntries(&self.0)
+    }
+}
+
+struct JournalEntries<'j>(&'j [u8]);
+
+impl<'j> Iterator for JournalEntries<'j> {
+    /// The key and the value written, `None` for a delete.
+    type Item = Result<(&'j [u8], Option<&'j [u8]>), StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&[kind, key_len], rest) = self.0.split_first_chunk()?;
+        let entry = rest.split_at_checked(key_len as usize).and_then(|(key, rest)| match (kind, rest) {
+            (JOURNAL_DELETE, rest) => Some((key, None, rest)),
+            (JOURNAL_WRITE, [len, rest @ ..]) => rest.split_at_checked(*len as usize).map(|(value, rest)| (key, Some(value), rest)),
+            _ => None,
+        });
+        match entry {
+            Some((key, value, rest)) => {
+                self.0 = rest;
+                Some(Ok((key, value)))
+            }
+            None => {
+                self.0 = &[];
+                Some(Err(StorageError::Corrupt))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{collections::BTreeMap, vec::Vec as StdVec};
+
+    #[derive(Default)]
+    pub(crate) struct RamKeyValueStore {
+        pub(crate) values: BTreeMap<StdVec<u8>, StdVec<u8>>,
+        /// Writes and deletes until the store fails, like a reset in the middle.
+        pub(crate) fail_after: Option<usize>,
+    }
+
+    impl RamKeyValueStore {
+        /// A flash image of an older firmware.
+        fn image(entries: &[(&[u8], &[u8])]) -> Self {
+            Self {
+                values: entries.iter().map(|(key, value)| (key.to_vec(), value.to_vec())).collect(),
+                fail_after: None,
+            }
+        }
+
+        fn modify(&mut self) -> Result<(), StorageError> {
+            match self.fail_after.as_mut() {
+                Some(0) => Err(StorageError::Storage),
+                Some(left) => {
+                    *left -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl KeyValueStore for &mut RamKeyValueStore {
+        async fn read(&mut self, key: &[u8], buffer: &mut [u8]) -> Result<Option<usize>, StorageError> {
+            let Some(value) = self.values.get(key) else {
+                return Ok(None);
+            };
+            buffer.get_mut(..value.len()).ok_or(StorageError::ValueTooLarge)?.copy_from_slice(value);
+            Ok(Some(value.len()))
+        }
+
+        async fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+            self.modify()?;
+            self.values.insert(key.into(), value.into());
+            Ok(())
+        }
+
+        async fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+            self.modify()?;
+            self.values.remove(key);
+            Ok(())
+        }
+    }
+
+    const RETRIES: ConfigKey<u32> = ConfigKey::new("cloud", "retries");
+    const APN: ConfigKey<String<24>> = ConfigKey::new("cloud", "apn");
+    const INTERVAL: ConfigKey<Duration> = ConfigKey::new("solar_monitor", "interval");
+
+    #[tokio::test]
+    async fn check_typed_values() {
+        let mut ram = RamKeyValueStore::default();
+        let mut config = ConfigStore::new(&mut ram);
+        assert_eq!(config.get(RETRIES).await, Ok(None));
+        assert_eq!(config.get_or(RETRIES, 5).await, 5);
+
+        config.set(RETRIES, &3).await.unwrap();
+        config.set(APN, &"gprs.swisscom.ch".try_into().unwrap()).await.unwrap();
+        config.set(INTERVAL, &Duration::from_secs(300)).await.unwrap();
+        assert_eq!(config.get(RETRIES).await, Ok(Some(3)));
+        assert_eq!(config.get(APN).await.unwrap().unwrap().as_str(), "gprs.swisscom.ch");
+        assert_eq!(config.get(INTERVAL).await, Ok(Some(Duration::from_secs(300))));
+
+        config.remove(RETRIES).await.unwrap();
+        assert_eq!(config.get(RETRIES).await, Ok(None));
+        assert!(ram.values.contains_key(&b"solar_monitor/interval"[..]));
+    }
+
+    #[tokio::test]
+    async fn check_corrupt_value_falls_back_to_default() {
+        let mut ram = RamKeyValueStore::default();
+        ram.values.insert(b"cloud/retries".to_vec(), std::vec![1, 2]);
+        let mut config = ConfigStore::new(&mut ram);
+        assert_eq!(config.get(RETRIES).await, Err(StorageError::Corrupt));
+        assert_eq!(config.get_or(RETRIES, 5).await, 5);
+    }
+
+    fn seconds_to_millis(data: &[u8], buffer: &mut [u8]) -> Option<usize> {
+        (u32::decode(data)? as u64 * 1000).encode(buffer)
+    }
+
+    #[tokio::test]
+    async fn check_migration() {
+        const MIGRATIONS: &[Migration] = &[
+            Migration {
+                version: 1,
+                steps: &[MigrationStep::Rename {
+                    from: Key::new("cloud", "retry_count"),
+                    to: Key::new("cloud", "retries"),
+                }],
+            },
+            Migration {
+                version: 2,
+                steps: &[
+                    MigrationStep::Convert {
+                        key: Key::new("solar_monitor", "interval"),
+                        convert: seconds_to_millis,
+                    },
+                    MigrationStep::Remove(Key::new("cloud", "legacy")),
+                ],
+            },
+        ];
+
+        let mut ram = RamKeyValueStore::default();
+        ram.values.insert(b"cloud/retry_count".to_vec(), 7u32.to_le_bytes().to_vec());
+        ram.values.insert(b"cloud/legacy".to_vec(), std::vec![1]);
+        ram.values.insert(b"solar_monitor/interval".to_vec(), 60u32.to_le_bytes().to_vec());
+        let mut config = ConfigStore::new(&mut ram);
+        assert_eq!(config.version().await, Ok(0));
+        assert_eq!(config.migrate(MIGRATIONS).await, Ok(2));
+        assert_eq!(config.get(RETRIES).await, Ok(Some(7)));
+        assert_eq!(config.get(INTERVAL).await, Ok(Some(Duration::from_secs(60))));
+        // applied migrations are not repeated
+        config.set(RETRIES, &8).await.unwrap();
+        assert_eq!(config.migrate(MIGRATIONS).await, Ok(2));
+        assert_eq!(config.get(RETRIES).await, Ok(Some(8)));
+        assert!(!ram.values.contains_key(&b"cloud/legacy"[..]));
+        assert!(!ram.values.contains_key(&b"cloud/retry_count"[..]));
+    }
+
+    /// Renames and converts the same key, converting twice would scale the value again.
+    const REWORK: &[Migration] = &[Migration {
+        version: 3,
+        steps: &[
+            MigrationStep::Rename {
+                from: Key::new("solar", "interval_s"),
+                to: Key::new("solar_monitor", "interval"),
+            },
+            MigrationStep::Convert {
+                key: Key::new("solar_monitor", "interval"),
+                convert: seconds_to_millis,
+            },
+            MigrationStep::Remove(Key::new("cloud", "legacy")),
+        ],
+    }];
+
+    const V2_IMAGE: &[(&[u8], &[u8])] = &[
+        (b"config/version", &[2, 0, 0, 0]),
+        (b"solar/interval_s", &[60, 0, 0, 0]),
+        (b"cloud/legacy", &[1]),
+        (b"cloud/retries", &[3, 0, 0, 0]),
+    ];
+
+    #[tokio::test]
+    async fn check_interrupted_migration() {
+        let mut expected = RamKeyValueStore::image(V2_IMAGE);
+        assert_eq!(ConfigStore::new(&mut expected).migrate(REWORK).await, Ok(3));
+        assert_eq!(ConfigStore::new(&mut expected).get(INTERVAL).await, Ok(Some(Duration::from_secs(60))));
+
+        // a reset after every write and delete of the migration
+        for fail_after in 0.. {
+            let mut ram = RamKeyValueStore::image(V2_IMAGE);
+            ram.fail_after = Some(fail_after);
+            if ConfigStore::new(&mut ram).migrate(REWORK).await.is_ok() {
+                break;
+            }
+            let journaled = ram.values.contains_key(&b"config/journal"[..]);
+            if !journaled {
+                // nothing changed until the journal is stored
+                assert_eq!(ram.values, RamKeyValueStore::image(V2_IMAGE).values, "reset after {} writes", fail_after);
+            }
+            ram.fail_after = None;
+            assert_eq!(ConfigStore::new(&mut ram).migrate(REWORK).await, Ok(3));
+            assert_eq!(ram.values, expected.values, "reset after {} writes", fail_after);
+        }
+    }
+
+    #[tokio::test]
+    async fn check_fixture_images() {
+        // before the version key, written by the first firmware with settings
+        let mut ram = RamKeyValueStore::image(&[
+            (b"battery/chemistry", &[2]),
+            (b"cloud/apn_profiles", b"\x02\x10gprs.swisscom.ch\x00\x0binternet.ch"),
+        ]);
+        let mut config = ConfigStore::new(&mut ram);
+        assert_eq!(config.migrate(CONFIG_MIGRATIONS).await, Ok(1));
+        assert_eq!(config.version().await, Ok(1));
+        assert_eq!(config.get(crate::solar_monitor::battery::CHEMISTRY).await, Ok(Some(crate::solar_monitor::battery::Chemistry::LiFePo4)));
+        let profiles = config.get(crate::solar_monitor::apn::APN_PROFILES).await.unwrap().unwrap();
+        let apns: StdVec<_> = profiles.iter().map(|profile| (profile.apn.as_str(), profile.pdp_type)).collect();
+        assert_eq!(
+            apns,
+            [
+                ("gprs.swisscom.ch", crate::at::packet_domain::PdpType::Ipv4v6),
+                ("internet.ch", crate::at::packet_domain::PdpType::Ip)
+            ]
+        );
+
+        // written by a newer firmware, kept for the upgrade again
+        let mut ram = RamKeyValueStore::image(&[(b"config/version", &[7, 0, 0, 0]), (b"battery/chemistry", &[1]), (b"battery/new_setting", &[1])]);
+        let before = ram.values.clone();
+        assert_eq!(ConfigStore::new(&mut ram).migrate(CONFIG_MIGRATIONS).await, Ok(7));
+        assert_eq!(ram.values, before);
+    }
+
+    #[tokio::test]
+    async fn check_corrupt_journal() {
+        let mut ram = RamKeyValueStore::image(&[(b"config/journal", &[0, 40, b'c'])]);
+        assert_eq!(ConfigStore::new(&mut ram).migrate(CONFIG_MIGRATIONS).await, Err(StorageError::Corrupt));
+    }
+}
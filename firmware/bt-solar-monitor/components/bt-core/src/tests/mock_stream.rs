@@ -0,0 +1,179 @@
+//! Host side test harness for the AT stack.
+//!
+//! [`mock_stream`] returns the UART of the controller and the other end of it, on
+//! which a [`ModemSimulator`] plays the module: it expects the commands of the
+//! script and answers with the scripted response lines and URCs.
+
+use std::{string::String as StdString, vec::Vec as StdVec};
+
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+const MOCK_STREAM_BUFFER_SIZE: usize = 4096;
+/// Time the simulator waits for an expected command before it fails the test.
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The UART end of the controller, reads pend once the modem end has nothing more to say.
+pub struct MockStream {
+    inner: DuplexStream,
+}
+
+impl ErrorType for MockStream {
+    type Error = ErrorKind;
+}
+
+impl Read for MockStream {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.inner.read(buf).await {
+            // the modem end is closed, a real UART would stay silent
+            Ok(0) if !buf.is_empty() => core::future::pending().await,
+            Ok(n) => Ok(n),
+            Err(_) => Err(ErrorKind::Other),
+        }
+    }
+}
+
+impl Write for MockStream {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.inner.write(buf).await.map_err(|_| ErrorKind::Other)
+    }
+}
+
+/// A connected pair of the controller UART and the modem end.
+pub fn mock_stream() -> (MockStream, DuplexStream) {
+    let (controller, modem) = tokio::io::duplex(MOCK_STREAM_BUFFER_SIZE);
+    (MockStream { inner: controller }, modem)
+}
+
+#[derive(Debug)]
+enum Step {
+    Expect(StdString),
+    ExpectData(StdVec<u8>),
+    Emit(StdString),
+    Delay(Duration),
+}
+
+/// Scripted modem, see the module documentation.
+///
+/// The commands are echoed like a module with `ATE1` does, unless [`ModemSimulator::without_echo`].
+pub struct ModemSimulator {
+    steps: StdVec<Step>,
+    echo: bool,
+}
+
+impl ModemSimulator {
+    pub fn new() -> Self {
+        Self {
+            steps: StdVec::new(),
+            echo: true,
+        }
+    }
+
+    pub fn without_echo(mut self) -> Self {
+        self.echo = false;
+        self
+    }
+
+    /// Waits for `command` (without the `\r\n`), anything else fails the test.
+    pub fn expect(mut self, command: &str) -> Self {
+        self.steps.push(Step::Expect(command.into()));
+        self
+    }
+
+    /// Waits for raw `data`, e.g. the body after a `DOWNLOAD` prompt.
+    pub fn expect_data(mut self, data: &[u8]) -> Self {
+        self.steps.push(Step::ExpectData(data.into()));
+        self
+    }
+
+    /// Sends the response lines, including the final result code.
+    pub fn respond(mut self, lines: &[&str]) -> Self {
+        for line in lines {
+            self.steps.push(Step::Emit(std::format!("{}\r\n", line)));
+        }
+        self
+    }
+
+    /// Sends the `>` prompt of the SIMCom file writes, it has no line end.
+    pub fn prompt(mut self) -> Self {
+        self.steps.push(Step::Emit("\r\n>".into()));
+        self
+    }
+
+    pub fn ok(self) -> Self {
+        self.respond(&["OK"])
+    }
+
+    /// Sends an unsolicited result code, with the blank line the modules put in front.
+    pub fn urc(mut self, line: &str) -> Self {
+        self.steps.push(Step::Emit(std::format!("\r\n{}\r\n", line)));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push(Step::Delay(delay));
+        self
+    }
+
+    /// Plays the script, returns the modem end so the line stays open afterwards.
+    pub async fn run(self, mut modem: DuplexStream) -> DuplexStream {
+        for step in self.steps {
+            match step {
+                Step::Expect(command) => {
+                    let received = with_timeout(EXPECT_TIMEOUT, read_command(&mut modem))
+                        .await
+                        .unwrap_or_else(|_| panic!("timeout waiting for '{}'", command));
+                    assert_eq!(received, command, "unexpected command");
+                    if self.echo {
+                        modem.write_all(std::format!("{}\r\r\n", command).as_bytes()).await.unwrap();
+                    }
+                }
+                Step::ExpectData(data) => {
+                    let mut received = std::vec![0u8; data.len()];
+                    with_timeout(EXPECT_TIMEOUT, modem.read_exact(&mut received))
+                        .await
+                        .unwrap_or_else(|_| panic!("timeout waiting for {} data bytes", data.len()))
+                        .unwrap();
+                    assert_eq!(received, data, "unexpected data");
+                }
+                Step::Emit(line) => modem.write_all(line.as_bytes()).await.unwrap(),
+                Step::Delay(delay) => Timer::after(delay).await,
+            }
+        }
+        modem
+    }
+}
+
+impl Default for ModemSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn read_command(modem: &mut DuplexStream) -> StdString {
+    let mut line = StdVec::new();
+    while !line.ends_with(b"\r\n") {
+        line.push(modem.read_u8().await.unwrap());
+    }
+    line.truncate(line.len() - 2);
+    StdString::from_utf8(line).unwrap()
+}
+
+/// Power key and reset line of the module, the simulator ignores them.
+#[derive(Debug, Default)]
+pub struct MockPin;
+
+impl embedded_hal::digital::ErrorType for MockPin {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_hal::digital::OutputPin for MockPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -1,55 +1,191 @@
-use crate::fmt::FormatableNaiveDateTime;
-use chrono::{Duration, NaiveDateTime};
+use crate::{
+    fmt::FormatableNaiveDateTime,
+    storage::{ConfigKey, ConfigStore, KeyValueStore, StorageError},
+};
+use chrono::{DateTime, Duration, NaiveDateTime};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
-use embassy_time::Instant;
+use embassy_time::{Instant, Timer};
 
-static SYSTEM_BOOT_TIME: Mutex<CriticalSectionRawMutex, Option<NaiveDateTime>> = Mutex::new(None);
+/// The synced time in Unix seconds, restored at boot by [`restore_time`].
+pub const LAST_KNOWN_TIME: ConfigKey<i64> = ConfigKey::new("time", "last_known");
+/// How often [`persist_time`] writes [`LAST_KNOWN_TIME`], the restored time is behind by up to this and the reset.
+pub const TIME_PERSIST_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(30 * 60);
+
+/// A larger drift is no crystal but a step of the time, e.g. a wrong module RTC corrected by NTP.
+const MAX_DRIFT_PPM: f32 = 500.0;
+/// The seconds resolution of the sources, an error within it is no step.
+const RESOLUTION_MS: i64 = 2000;
+/// The resolution of the drift estimate, the error of the sources spread over [`MIN_DRIFT_INTERVAL`].
+const DRIFT_RESOLUTION_PPM: i64 = 50;
+/// Syncs closer than this only correct the time, their error is mostly the resolution of the source.
+const MIN_DRIFT_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_millis((RESOLUTION_MS * 1_000_000 / DRIFT_RESOLUTION_PPM) as u64);
+
+static SYSTEM_TIME: Mutex<CriticalSectionRawMutex, Option<Discipline>> = Mutex::new(None);
+
+/// Where a time synchronization came from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimeSource {
+    /// The RTC of the cellular module (`AT+CCLK?`).
+    ModemRtc,
+    /// An NTP server.
+    Ntp,
+}
+
+/// How good [`UtcTime::now`] is.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimeQuality {
+    Synced,
+    /// Restored from [`LAST_KNOWN_TIME`] after a reset, behind by the time the board was off.
+    Approximate,
+}
+
+impl TimeSource {
+    /// Time from outside the module, worth writing back into the module RTC.
+    pub fn is_external(&self) -> bool {
+        *self != TimeSource::ModemRtc
+    }
+}
+
+/// The UTC time of the last sync and how fast the uptime runs against it.
+///
+/// The drift is estimated over the span since the `anchor`, the first sync after a step, so
+/// the seconds resolution of the sources shrinks the longer the board runs. `now` applies it
+/// since the last sync, the time stays accurate while the modem sleeps for hours.
+#[derive(Debug, Copy, Clone)]
+struct Discipline {
+    anchor: (Instant, NaiveDateTime),
+    last_sync: (Instant, NaiveDateTime),
+    /// Positive where the uptime runs slow, UTC advances more.
+    drift_ppm: Option<f32>,
+    quality: TimeQuality,
+}
+
+impl Discipline {
+    fn new(at: Instant, now: NaiveDateTime, quality: TimeQuality) -> Self {
+        Self {
+            anchor: (at, now),
+            last_sync: (at, now),
+            drift_ppm: None,
+            quality,
+        }
+    }
+
+    fn now_at(&self, at: Instant) -> NaiveDateTime {
+        let (synced, synced_time) = self.last_sync;
+        let elapsed = at.saturating_duration_since(synced).as_micros() as i64;
+        let correction = self.drift_ppm.map_or(0, |ppm| (elapsed as f32 * ppm / 1_000_000.0) as i64);
+        synced_time + Duration::seconds((elapsed + correction) / 1_000_000)
+    }
+
+    /// Takes the synced time, returns the error of the disciplined time.
+    fn sync_at(&mut self, at: Instant, now: NaiveDateTime) -> Duration {
+        let error = now - self.now_at(at);
+        let (anchored, anchor_time) = self.anchor;
+        let span = at.saturating_duration_since(anchored);
+        let span_ms = span.as_millis() as i64;
+        let anchor_error_ms = (now - anchor_time).num_milliseconds() - span_ms;
+        self.last_sync = (at, now);
+        if anchor_error_ms.abs() > RESOLUTION_MS + (span_ms as f32 * MAX_DRIFT_PPM / 1_000_000.0) as i64 {
+            self.anchor = (at, now);
+            self.drift_ppm = None;
+        } else if span >= MIN_DRIFT_INTERVAL {
+            self.drift_ppm = Some((anchor_error_ms as f32 * 1_000_000.0 / span_ms as f32).clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM));
+        }
+        error
+    }
+}
 
 pub struct UtcTime {}
 
 impl UtcTime {
     pub async fn time_sync(now: NaiveDateTime) {
-        let since_system_boot = Instant::now();
-        let new_system_boot_time = now - Duration::seconds(since_system_boot.as_secs() as i64);
-        let mut guard = SYSTEM_BOOT_TIME.lock().await;
-        match *guard {
-            Some(current_system_boot_time) => {
-                if current_system_boot_time != new_system_boot_time {
-                    *guard = Some(new_system_boot_time);
-                    let drift = new_system_boot_time - current_system_boot_time;
-                    info!("System time re-synchronized: {} (drift: {} seconds)", FormatableNaiveDateTime(&now), drift.num_seconds());
+        let mut guard = SYSTEM_TIME.lock().await;
+        match guard.as_mut().filter(|discipline| discipline.quality == TimeQuality::Synced) {
+            Some(discipline) => {
+                let error = discipline.sync_at(Instant::now(), now);
+                if error != Duration::zero() {
+                    info!(
+                        "System time re-synchronized: {} (drift: {} seconds, {} ppm)",
+                        FormatableNaiveDateTime(&now),
+                        error.num_seconds(),
+                        discipline.drift_ppm.unwrap_or(0.0)
+                    );
                 }
             }
             None => {
-                *guard = Some(new_system_boot_time);
+                *guard = Some(Discipline::new(Instant::now(), now, TimeQuality::Synced));
                 info!("System time initially synchronized: {}", FormatableNaiveDateTime(&now));
             }
         };
     }
 
-    pub async fn now() -> Option<NaiveDateTime> {
-        let guard = SYSTEM_BOOT_TIME.lock().await;
-        match *guard {
-            Some(system_boot_time) => {
-                let since_system_boot = Instant::now();
-                Some(system_boot_time + Duration::seconds(since_system_boot.as_secs() as i64))
-            }
-            None => None,
+    /// Starts with an approximate time until the first sync, e.g. the [`LAST_KNOWN_TIME`] at boot.
+    ///
+    /// The upload runner marks the batches with readings timed meanwhile as `approximate_time`.
+    pub async fn restore(last_known: NaiveDateTime) {
+        let mut guard = SYSTEM_TIME.lock().await;
+        if guard.is_none() {
+            *guard = Some(Discipline::new(Instant::now(), last_known, TimeQuality::Approximate));
+            info!("System time restored approximately: {}", FormatableNaiveDateTime(&last_known));
         }
     }
 
+    pub async fn quality() -> Option<TimeQuality> {
+        SYSTEM_TIME.lock().await.as_ref().map(|discipline| discipline.quality)
+    }
+
+    pub async fn now() -> Option<NaiveDateTime> {
+        let guard = SYSTEM_TIME.lock().await;
+        guard.as_ref().map(|discipline| discipline.now_at(Instant::now()))
+    }
+
+    /// The estimated drift of the uptime against UTC, once synced over [`MIN_DRIFT_INTERVAL`].
+    pub async fn drift_ppm() -> Option<f32> {
+        SYSTEM_TIME.lock().await.as_ref().and_then(|discipline| discipline.drift_ppm)
+    }
+
     #[cfg(test)]
-    async fn reset() {
-        let mut guard = SYSTEM_BOOT_TIME.lock().await;
+    pub(crate) async fn reset() {
+        let mut guard = SYSTEM_TIME.lock().await;
         *guard = None;
     }
 }
 
+/// Restores the [`LAST_KNOWN_TIME`] as [`TimeQuality::Approximate`], at boot before the readings start.
+pub async fn restore_time<S: KeyValueStore>(config: &mut ConfigStore<S>) -> Option<NaiveDateTime> {
+    let timestamp = config.get(LAST_KNOWN_TIME).await.ok().flatten()?;
+    let last_known = DateTime::from_timestamp(timestamp, 0)?.naive_utc();
+    UtcTime::restore(last_known).await;
+    Some(last_known)
+}
+
+/// Writes the synced time to [`LAST_KNOWN_TIME`], an approximate one is not worth it.
+pub async fn store_time<S: KeyValueStore>(config: &mut ConfigStore<S>) -> Result<bool, StorageError> {
+    let (Some(now), Some(TimeQuality::Synced)) = (UtcTime::now().await, UtcTime::quality().await) else {
+        return Ok(false);
+    };
+    config.set(LAST_KNOWN_TIME, &now.and_utc().timestamp()).await?;
+    Ok(true)
+}
+
+/// Stores the time every [`TIME_PERSIST_INTERVAL`], runs forever.
+pub async fn persist_time<S: KeyValueStore>(mut config: ConfigStore<S>) {
+    loop {
+        Timer::after(TIME_PERSIST_INTERVAL).await;
+        if let Err(e) = store_time(&mut config).await {
+            warn!("Failed to persist the time: {:?}", e);
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use serial_test::serial;
 
     use super::*;
+    use crate::storage::tests::RamKeyValueStore;
 
     #[serial(bt_time)]
     #[tokio::test]
@@ -96,4 +232,75 @@ pub mod tests {
         let now_two = super::UtcTime::now().await;
         std::assert_eq!(now_two.unwrap(), sync_two);
     }
+
+    #[test]
+    fn check_drift_estimation() {
+        let time = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut discipline = Discipline::new(Instant::from_secs(100), time("2025-11-30 12:00:00"), TimeQuality::Synced);
+        assert_eq!(discipline.now_at(Instant::from_secs(200)), time("2025-11-30 12:01:40"));
+
+        // too short to tell drift from the resolution of the source
+        discipline.sync_at(Instant::from_secs(700), time("2025-11-30 12:10:01"));
+        assert_eq!(discipline.drift_ppm, None);
+        // a second over 30 min would be 555 ppm
+        discipline.sync_at(Instant::from_secs(1_900), time("2025-11-30 12:30:01"));
+        assert_eq!(discipline.drift_ppm, None);
+        assert_eq!(discipline.anchor.0, Instant::from_secs(100));
+
+        // the uptime ran 36 s slow over 10 h, 1000 ppm => a step of the time
+        discipline.sync_at(Instant::from_secs(36_100), time("2025-11-30 22:00:36"));
+        assert_eq!(discipline.drift_ppm, None);
+        assert_eq!(discipline.anchor.0, Instant::from_secs(36_100));
+
+        // 5 s slow over 100000 s, 50 ppm
+        discipline.sync_at(Instant::from_secs(136_100), time("2025-12-02 01:47:21"));
+        let ppm = discipline.drift_ppm.unwrap();
+        assert!((ppm - 50.0).abs() < 0.5, "{}", ppm);
+        // corrected until the next sync, 10 h later by 1.8 s
+        assert_eq!(discipline.now_at(Instant::from_secs(172_100)), time("2025-12-02 11:47:22"));
+        assert_eq!(discipline.sync_at(Instant::from_secs(172_100), time("2025-12-02 11:47:23")), Duration::seconds(1));
+
+        // 21.9 s over 40000 s is within the resolution of a step, clamped
+        let mut discipline = Discipline::new(Instant::from_secs(0), time("2025-11-30 12:00:00"), TimeQuality::Synced);
+        discipline.sync_at(Instant::from_millis(40_000_000), time("2025-11-30 23:07:01") + Duration::milliseconds(900));
+        assert_eq!(discipline.drift_ppm, Some(MAX_DRIFT_PPM));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn test_restore_last_known_time() {
+        let mut store = RamKeyValueStore::default();
+        let mut config = ConfigStore::new(&mut store);
+        let synced = NaiveDateTime::parse_from_str("2025-11-30 12:30:21", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::reset().await;
+        assert_eq!(store_time(&mut config).await, Ok(false));
+        UtcTime::time_sync(synced).await;
+        assert_eq!(store_time(&mut config).await, Ok(true));
+
+        // after a reset
+        UtcTime::reset().await;
+        assert_eq!(restore_time(&mut config).await, Some(synced));
+        assert_eq!(UtcTime::now().await, Some(synced));
+        assert_eq!(UtcTime::quality().await, Some(TimeQuality::Approximate));
+        assert_eq!(store_time(&mut config).await, Ok(false));
+
+        // the first sync replaces it, no drift from the time the board was off
+        let now = synced + Duration::hours(3);
+        UtcTime::time_sync(now).await;
+        assert_eq!(UtcTime::now().await, Some(now));
+        assert_eq!(UtcTime::quality().await, Some(TimeQuality::Synced));
+        // a restore once synced is too late
+        UtcTime::restore(synced).await;
+        assert_eq!(UtcTime::now().await, Some(now));
+    }
+
+    #[serial(bt_time)]
+    #[tokio::test]
+    async fn test_drift_not_estimated_yet() {
+        UtcTime::reset().await;
+        let sync = NaiveDateTime::parse_from_str("2025-11-30 12:30:21", "%Y-%m-%d %H:%M:%S").unwrap();
+        UtcTime::time_sync(sync).await;
+        UtcTime::time_sync(sync).await;
+        std::assert!(UtcTime::drift_ppm().await.is_none());
+    }
 }
@@ -0,0 +1,50 @@
+use embassy_time::Duration;
+
+/// Timeouts and waits used across the AT, cellular and cloud layers.
+///
+/// The defaults match the SIMCom A67 on a reasonable LTE link. Slow NB-IoT
+/// deployments may want to lengthen them, bench tests to shorten them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeouts {
+    /// Default response timeout of an AT command.
+    pub at_command: Duration,
+    /// Response timeout of the `AT+HTTPREAD` / `AT+HTTPDATA` commands.
+    pub http_command: Duration,
+    /// Timeout waiting for the `+HTTPREAD` data tags.
+    pub http_read: Duration,
+    /// Wait after the power key pulse before talking to the module.
+    pub modem_boot: Duration,
+    /// Timeout for the module to answer `AT` after power on.
+    pub modem_at_ready: Duration,
+    /// Timeout for the module to wake up and re-register after sleep.
+    pub modem_wake_up: Duration,
+    /// Timeout for the network registration and PDP activation of an APN profile.
+    pub network_registration: Duration,
+    /// Delay between failed module reset attempts.
+    pub modem_reset_retry: Duration,
+    /// Timeout of a DNS lookup by the module.
+    pub dns_lookup: Duration,
+    /// Timeout of an NTP synchronization by the module.
+    pub ntp_sync: Duration,
+    /// Wait for new upload data before the cloud client goes to sleep.
+    pub upload_idle: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            at_command: Duration::from_secs(5),
+            http_command: Duration::from_secs(10),
+            http_read: Duration::from_secs(120),
+            modem_boot: Duration::from_secs(8),
+            modem_at_ready: Duration::from_secs(10),
+            modem_wake_up: Duration::from_secs(30),
+            network_registration: Duration::from_secs(3 * 60),
+            modem_reset_retry: Duration::from_secs(30),
+            dns_lookup: Duration::from_secs(30),
+            ntp_sync: Duration::from_secs(30),
+            upload_idle: Duration::from_secs(4),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Software supervision of the long running runners.
+//!
+//! Every runner gets a [`WatchdogHandle`] and feeds it from its loop. The board
+//! task runs [`Watchdog::run`] and only pets the hardware watchdog while all
+//! registered runners have fed within their deadline, so a stuck runner leads
+//! to a hardware reset instead of a silently dead device.
+//!
+//! Maintenance work that runs on demand, e.g. an erase of the flash store, registers with
+//! [`Watchdog::register_maintenance`]: it is only supervised while it holds a [`Busy`]
+//! and feeds at its pet points, e.g. between the sectors. The runners blocked by it, e.g.
+//! waiting for the store, are credited with its progress.
+
+use core::cell::Cell;
+
+use embassy_time::{Duration, Instant, Timer};
+
+/// Upper bound for idle waits inside supervised runners, so they keep feeding while there is nothing to do.
+pub const FEED_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    name: &'static str,
+    deadline: Duration,
+    last_feed: Instant,
+    /// Only supervised while busy, see [`Watchdog::register_maintenance`].
+    on_demand: bool,
+    busy: bool,
+}
+
+pub struct Watchdog<const N: usize> {
+    slots: [Cell<Option<Slot>>; N],
+}
+
+impl<const N: usize> Watchdog<N> {
+    pub fn new() -> Self {
+        Self {
+            slots: [const { Cell::new(None) }; N],
+        }
+    }
+
+    /// Registers a runner that has to feed at least every `deadline`, `None` if all slots are taken.
+    pub fn register(&self, name: &'static str, deadline: Duration) -> Option<WatchdogHandle<'_>> {
+        self.register_slot(name, deadline, false)
+    }
+
+    /// Registers maintenance work that has to feed at least every `deadline` while busy, see [`WatchdogHandle::busy`].
+    pub fn register_maintenance(&self, name: &'static str, deadline: Duration) -> Option<WatchdogHandle<'_>> {
+        self.register_slot(name, deadline, true)
+    }
+
+    fn register_slot(&self, name: &'static str, deadline: Duration, on_demand: bool) -> Option<WatchdogHandle<'_>> {
+        let slot = self.slots.iter().find(|slot| slot.get().is_none())?;
+        slot.set(Some(Slot {
+            name,
+            deadline,
+            last_feed: Instant::now(),
+            on_demand,
+            busy: false,
+        }));
+        Some(WatchdogHandle { slot: Some(slot) })
+    }
+
+    /// Name of the first runner that missed its deadline at `now`.
+    pub fn stale(&self, now: Instant) -> Option<&'static str> {
+        let slots = || self.slots.iter().filter_map(|slot| slot.get());
+        // the last progress of the maintenance counts for the runners waiting for it
+        let maintenance = slots().filter(|slot| slot.on_demand).map(|slot| slot.last_feed).max();
+        slots()
+            .filter(|slot| !slot.on_demand || slot.busy)
+            .find(|slot| {
+                let last_feed = match maintenance {
+                    Some(progress) if !slot.on_demand => slot.last_feed.max(progress),
+                    _ => slot.last_feed,
+                };
+                now.saturating_duration_since(last_feed) > slot.deadline
+            })
+            .map(|slot| slot.name)
+    }
+
+    /// Calls `pet` every `interval` as long as no runner is stale.
+    ///
+    /// Once a runner is stale the hardware watchdog is starved and resets the device.
+    pub async fn run(&self, interval: Duration, mut pet: impl FnMut()) {
+        let mut reported = false;
+        loop {
+            match self.stale(Instant::now()) {
+                None => pet(),
+                Some(name) if !reported => {
+                    error!("Watchdog> runner '{}' missed its deadline => stop petting", name);
+                    reported = true;
+                }
+                Some(_) => {}
+            }
+            Timer::after(interval).await;
+        }
+    }
+}
+
+impl<const N: usize> Default for Watchdog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Liveness reporting of a single runner, the default handle is not supervised.
+#[derive(Copy, Clone, Default)]
+pub struct WatchdogHandle<'a> {
+    slot: Option<&'a Cell<Option<Slot>>>,
+}
+
+impl<'a> WatchdogHandle<'a> {
+    pub fn feed(&self) {
+        self.feed_at(Instant::now());
+    }
+
+    /// Longest idle wait that still feeds in time, `None` for the unsupervised handle.
+    pub fn max_idle(&self) -> Option<Duration> {
+        let slot = self.slot?.get()?;
+        Some(slot.deadline / 2)
+    }
+
+    /// Supervises a maintenance until the guard is dropped, it feeds at its pet points meanwhile.
+    pub fn busy(&self) -> Busy<'a> {
+        self.set_busy(true, Instant::now());
+        Busy { handle: *self }
+    }
+
+    fn feed_at(&self, now: Instant) {
+        if let Some(cell) = self.slot
+            && let Some(mut slot) = cell.get()
+        {
+            slot.last_feed = now;
+            cell.set(Some(slot));
+        }
+    }
+
+    /// Feeds as well, the runners waiting for the maintenance get a full deadline from its end.
+    fn set_busy(&self, busy: bool, now: Instant) {
+        if let Some(cell) = self.slot
+            && let Some(mut slot) = cell.get()
+        {
+            slot.busy = busy;
+            slot.last_feed = now;
+            cell.set(Some(slot));
+        }
+    }
+}
+
+/// A maintenance is supervised until dropped.
+pub struct Busy<'a> {
+    handle: WatchdogHandle<'a>,
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.handle.set_busy(false, Instant::now());
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_runner() {
+        let watchdog = Watchdog::<2>::new();
+        let start = Instant::now();
+        let at = watchdog.register("at", Duration::from_secs(10)).unwrap();
+        let cloud = watchdog.register("cloud", Duration::from_secs(60)).unwrap();
+        assert!(watchdog.register("full", Duration::from_secs(1)).is_none());
+        assert_eq!(cloud.max_idle(), Some(Duration::from_secs(30)));
+
+        at.feed_at(start);
+        cloud.feed_at(start);
+        assert_eq!(watchdog.stale(start + Duration::from_secs(5)), None);
+        assert_eq!(watchdog.stale(start + Duration::from_secs(11)), Some("at"));
+
+        at.feed_at(start + Duration::from_secs(11));
+        assert_eq!(watchdog.stale(start + Duration::from_secs(15)), None);
+        assert_eq!(watchdog.stale(start + Duration::from_secs(61)), Some("at"));
+        at.feed_at(start + Duration::from_secs(61));
+        assert_eq!(watchdog.stale(start + Duration::from_secs(61)), Some("cloud"));
+    }
+
+    #[test]
+    fn test_maintenance() {
+        let watchdog = Watchdog::<2>::new();
+        let start = Instant::now();
+        let upload = watchdog.register("upload", Duration::from_secs(10)).unwrap();
+        let flash = watchdog.register_maintenance("flash", Duration::from_secs(2)).unwrap();
+        upload.feed_at(start);
+        flash.feed_at(start);
+        // idle, not supervised
+        assert_eq!(watchdog.stale(start + Duration::from_secs(9)), None);
+
+        // the upload waits for the store, the erase progresses sector by sector
+        flash.set_busy(true, start + Duration::from_secs(9));
+        for second in 10..30 {
+            flash.feed_at(start + Duration::from_secs(second));
+            assert_eq!(watchdog.stale(start + Duration::from_secs(second + 1)), None);
+        }
+        assert_eq!(watchdog.stale(start + Duration::from_secs(32)), Some("flash"));
+
+        // the end counts as progress, the upload gets its full deadline from there
+        flash.set_busy(false, start + Duration::from_secs(30));
+        assert_eq!(watchdog.stale(start + Duration::from_secs(40)), None);
+        assert_eq!(watchdog.stale(start + Duration::from_secs(41)), Some("upload"));
+    }
+
+    #[test]
+    fn test_unsupervised_handle() {
+        WatchdogHandle::default().feed();
+        drop(WatchdogHandle::default().busy());
+        assert_eq!(WatchdogHandle::default().max_idle(), None);
+    }
+}
@@ -0,0 +1,72 @@
+//! Compiles bt-core with each combination of its features.
+//!
+//! The `defmt::Format` derives and the logging macros are gated per feature, a combination
+//! nobody builds drifts unnoticed. `check_feature_matrix` runs `cargo check --all-targets`
+//! for each entry of [`FEATURE_MATRIX`], the tests included, it takes minutes and is ignored by `cargo test`, see
+//! `just features_components`. `check_matrix_covers_features` keeps the matrix in sync with
+//! the manifest and runs always.
+
+use std::{path::Path, process::Command};
+
+/// The combinations to build, `defmt` and `log` exclude each other.
+const FEATURE_MATRIX: &[&[&str]] = &[&[], &["log"], &["defmt"], &["release-log"], &["log", "release-log"], &["defmt", "release-log"]];
+/// Combinations that must not compile.
+const REJECTED: &[&[&str]] = &[&["defmt", "log"]];
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// The features of the `[features]` table of the manifest.
+fn declared_features() -> Vec<String> {
+    let manifest = std::fs::read_to_string(manifest_dir().join("Cargo.toml")).unwrap();
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[features]")
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .filter_map(|line| line.split_once('=').map(|(name, _)| name.trim()))
+        .filter(|name| !name.is_empty() && !name.starts_with('#') && !name.starts_with('"'))
+        .map(String::from)
+        .collect()
+}
+
+fn cargo_check(features: &[&str]) -> Command {
+    // an own target dir, the one of the workspace is locked by the running `cargo test`
+    let target_dir = manifest_dir().join("../../target/feature-matrix");
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .current_dir(manifest_dir())
+        .args(["check", "--quiet", "--all-targets", "--no-default-features"])
+        .arg("--target-dir")
+        .arg(target_dir);
+    if !features.is_empty() {
+        command.arg("--features").arg(features.join(","));
+    }
+    command
+}
+
+#[test]
+fn check_matrix_covers_features() {
+    let features = declared_features();
+    assert!(features.contains(&"defmt".into()), "{:?}", features);
+    for feature in features {
+        assert!(FEATURE_MATRIX.iter().any(|combination| combination.contains(&feature.as_str())), "feature {} is missing in the matrix", feature);
+    }
+}
+
+#[test]
+#[ignore]
+fn check_feature_matrix() {
+    let failed: Vec<_> = FEATURE_MATRIX
+        .iter()
+        .filter(|features| !cargo_check(features).status().unwrap().success())
+        .collect();
+    assert!(failed.is_empty(), "failed to build with {:?}", failed);
+    // their compile errors are expected, not worth the output
+    let built: Vec<_> = REJECTED
+        .iter()
+        .filter(|features| cargo_check(features).output().unwrap().status.success())
+        .collect();
+    assert!(built.is_empty(), "built with {:?}", built);
+}
@@ -0,0 +1,19 @@
+MEMORY
+{
+    /* The layout of embassy-boot, the same as in the memory.x of the nrf-solar-monitor */
+    FLASH            : ORIGIN = 0x00000000, LENGTH = 32K
+    BOOTLOADER_STATE : ORIGIN = 0x00008000, LENGTH = 4K
+    ACTIVE           : ORIGIN = 0x00009000, LENGTH = 988K
+    /* In the QSPI flash above the ekv database, see bt_nrf::driver::qspi_flash::DFU_OFFSET */
+    DFU              : ORIGIN = 0x00300000, LENGTH = 1024K
+    RAM              : ORIGIN = 0x20000000, LENGTH = 256K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE);
+
+__bootloader_active_start = ORIGIN(ACTIVE);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE);
+
+__bootloader_dfu_start = ORIGIN(DFU);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU);
@@ -0,0 +1,67 @@
+//! The embassy-boot bootloader of the nrf-solar-monitor.
+//!
+//! After a verified OTA update it swaps the DFU partition in the QSPI flash with the app in
+//! the internal flash, and swaps the previous app back if the new one resets before it marked
+//! itself booted, see `bt_core::prelude::Ota`. The partitions are in `memory.x`.
+//!
+//! The watchdog is started with the config of the app, which keeps it running, and is fed at
+//! every page of the swap.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use embassy_boot_nrf::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_nrf::{bind_interrupts, nvmc::Nvmc, peripherals, qspi, wdt};
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+
+bind_interrupts!(struct Irqs {
+    QSPI => qspi::InterruptHandler<peripherals::QSPI>;
+});
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_nrf::init(Default::default());
+
+    // the same as in the app, it can not reconfigure a running watchdog
+    let mut wdt_config = wdt::Config::default();
+    wdt_config.timeout_ticks = 32768 * 10; // 10 seconds
+    wdt_config.action_during_debug_halt = wdt::HaltConfig::PAUSE;
+    let internal = Mutex::<NoopRawMutex, _>::new(RefCell::new(WatchdogFlash::start(Nvmc::new(p.NVMC), p.WDT, wdt_config)));
+
+    // Config for the MX25L3233F (32 Mbit = 4 MB), the same as in the app
+    let mut qspi_config = qspi::Config::default();
+    qspi_config.read_opcode = qspi::ReadOpcode::READ2O;
+    qspi_config.write_opcode = qspi::WriteOpcode::PP;
+    qspi_config.write_page_size = qspi::WritePageSize::_256BYTES;
+    qspi_config.frequency = qspi::Frequency::M8;
+    qspi_config.capacity = 4 * 1024 * 1024;
+    let qspi = qspi::Qspi::new(p.QSPI, Irqs, p.P0_19, p.P0_17, p.P0_20, p.P0_21, p.P0_22, p.P0_23, qspi_config);
+    let dfu = Mutex::<NoopRawMutex, _>::new(RefCell::new(qspi));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&internal, &dfu, &internal);
+    let active_offset = config.active.offset();
+    let bootloader: BootLoader = BootLoader::prepare(config);
+
+    // the app configures the QSPI again
+    drop(dfu);
+    unsafe { bootloader.load(active_offset) }
+}
+
+#[exception]
+unsafe fn HardFault(_frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_irqn: i16) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// A failed swap is retried after the reset, the state is only advanced page by page.
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,78 @@
+//! The command audit log in the key value store.
+//!
+//! The entries rotate through [`AUDIT_SLOTS`] keys by their sequence number,
+//! so the store keeps the last entries even when the backend is out of reach.
+
+use bt_core::prelude::{AUDIT_ENTRY_SIZE, AuditCursors, AuditEntry, AuditError, AuditStore};
+use bt_core::warn;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+const AUDIT_SLOTS: u32 = 32;
+const CURSORS_KEY: &[u8] = b"audit/cursors";
+
+pub struct EkvAuditStore<'a, F: ekv::flash::Flash> {
+    db: &'a ekv::Database<F, NoopRawMutex>,
+}
+
+impl<'a, F: ekv::flash::Flash> EkvAuditStore<'a, F> {
+    pub fn new(db: &'a ekv::Database<F, NoopRawMutex>) -> Self {
+        Self { db }
+    }
+
+    async fn write(&mut self, key: &[u8], data: &[u8]) -> Result<(), AuditError> {
+        let mut wtx = self.db.write_transaction().await;
+        if wtx.write(key, data).await.is_err() || wtx.commit().await.is_err() {
+            return Err(AuditError::Storage);
+        }
+        Ok(())
+    }
+}
+
+fn entry_key(sequence: u32) -> [u8; 7] {
+    let mut key = *b"audit/\0";
+    key[6] = (sequence % AUDIT_SLOTS) as u8;
+    key
+}
+
+impl<F: ekv::flash::Flash> AuditStore for EkvAuditStore<'_, F> {
+    async fn cursors(&mut self) -> Result<AuditCursors, AuditError> {
+        let mut buffer = [0u8; 8];
+        let rtx = self.db.read_transaction().await;
+        match rtx.read(CURSORS_KEY, &mut buffer).await {
+            Ok(8) => Ok(AuditCursors {
+                next: u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
+                upload: u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            }),
+            // not written yet
+            _ => Ok(AuditCursors::default()),
+        }
+    }
+
+    async fn store_cursors(&mut self, cursors: &AuditCursors) -> Result<(), AuditError> {
+        let mut buffer = [0u8; 8];
+        buffer[..4].copy_from_slice(&cursors.next.to_le_bytes());
+        buffer[4..].copy_from_slice(&cursors.upload.to_le_bytes());
+        self.write(CURSORS_KEY, &buffer).await
+    }
+
+    async fn append(&mut self, entry: &AuditEntry) -> Result<(), AuditError> {
+        let encoded = entry.encode().ok_or(AuditError::Corrupt)?;
+        self.write(&entry_key(entry.sequence), &encoded).await
+    }
+
+    async fn read(&mut self, sequence: u32) -> Result<Option<AuditEntry>, AuditError> {
+        let mut buffer = [0u8; AUDIT_ENTRY_SIZE];
+        let rtx = self.db.read_transaction().await;
+        let Ok(len) = rtx.read(&entry_key(sequence), &mut buffer).await else {
+            return Ok(None);
+        };
+        match AuditEntry::decode(&buffer[..len]) {
+            // the slot holds a newer entry once the log wrapped
+            Some(entry) => Ok(Some(entry).filter(|entry| entry.sequence == sequence)),
+            None => {
+                warn!("Audit entry #{} does not decode", sequence);
+                Err(AuditError::Corrupt)
+            }
+        }
+    }
+}
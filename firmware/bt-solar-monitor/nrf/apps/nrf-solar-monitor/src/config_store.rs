@@ -0,0 +1,42 @@
+//! The settings of the [`ConfigStore`](bt_core::prelude::ConfigStore) in the key value store.
+
+use bt_core::prelude::{KeyValueStore, StorageError};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+pub struct EkvKeyValueStore<'a, F: ekv::flash::Flash> {
+    db: &'a ekv::Database<F, NoopRawMutex>,
+}
+
+impl<'a, F: ekv::flash::Flash> EkvKeyValueStore<'a, F> {
+    pub fn new(db: &'a ekv::Database<F, NoopRawMutex>) -> Self {
+        Self { db }
+    }
+}
+
+impl<F: ekv::flash::Flash> KeyValueStore for EkvKeyValueStore<'_, F> {
+    async fn read(&mut self, key: &[u8], buffer: &mut [u8]) -> Result<Option<usize>, StorageError> {
+        let rtx = self.db.read_transaction().await;
+        match rtx.read(key, buffer).await {
+            Ok(len) => Ok(Some(len)),
+            Err(ekv::ReadError::KeyNotFound) => Ok(None),
+            Err(ekv::ReadError::BufferTooSmall) => Err(StorageError::ValueTooLarge),
+            Err(_) => Err(StorageError::Storage),
+        }
+    }
+
+    async fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let mut wtx = self.db.write_transaction().await;
+        if wtx.write(key, value).await.is_err() || wtx.commit().await.is_err() {
+            return Err(StorageError::Storage);
+        }
+        Ok(())
+    }
+
+    async fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        let mut wtx = self.db.write_transaction().await;
+        if wtx.delete(key).await.is_err() || wtx.commit().await.is_err() {
+            return Err(StorageError::Storage);
+        }
+        Ok(())
+    }
+}
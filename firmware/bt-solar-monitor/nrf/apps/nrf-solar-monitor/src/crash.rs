@@ -0,0 +1,70 @@
+//! Panic and hard fault handling.
+//!
+//! The handlers only fill the crash record in retained RAM and reset. After the
+//! reset [`persist`] moves the record into the key value store, so it survives
+//! power loss until the cloud runner reported it.
+
+use core::mem::MaybeUninit;
+
+use bt_core::prelude::{CRASH_REPORT_SIZE, CrashRecord, CrashReport};
+use bt_core::{info, warn};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+const CRASH_KEY: &[u8] = b"crash";
+
+#[unsafe(link_section = ".uninit.CRASH_RECORD")]
+static mut CRASH_RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+
+fn record() -> &'static mut CrashRecord {
+    // SAFETY: any bit pattern is a valid CrashRecord; only accessed during startup and from the fault handlers.
+    unsafe { &mut *(&raw mut CRASH_RECORD).cast::<CrashRecord>() }
+}
+
+fn uptime_seconds() -> u32 {
+    embassy_time::Instant::now().as_secs() as u32
+}
+
+#[panic_handler]
+fn panic(panic_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    #[cfg(feature = "defmt")]
+    defmt::error!("{}", defmt::Display2Format(panic_info));
+    record().record(uptime_seconds(), 0, format_args!("{}", panic_info));
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[cortex_m_rt::exception]
+unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    record().record(uptime_seconds(), frame.pc(), format_args!("HardFault"));
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Moves a crash recorded before the reset into the store and returns the not yet reported crash, if any.
+pub async fn persist<F: ekv::flash::Flash>(db: &ekv::Database<F, NoopRawMutex>) -> Option<CrashReport> {
+    if let Some(report) = record().take() {
+        warn!("Crashed in previous run after {}s: {}", report.uptime_seconds, report.message.as_str());
+        if let Some(encoded) = report.encode() {
+            let mut wtx = db.write_transaction().await;
+            if wtx.write(CRASH_KEY, &encoded).await.is_err() || wtx.commit().await.is_err() {
+                warn!("Failed to persist crash report");
+                return Some(report);
+            }
+        }
+    }
+    let mut buffer = [0u8; CRASH_REPORT_SIZE];
+    let rtx = db.read_transaction().await;
+    match rtx.read(CRASH_KEY, &mut buffer).await {
+        Ok(len) => CrashReport::decode(&buffer[..len]),
+        Err(_) => None,
+    }
+}
+
+/// Removes the reported crash from the store.
+pub async fn clear<F: ekv::flash::Flash>(db: &ekv::Database<F, NoopRawMutex>) {
+    let mut wtx = db.write_transaction().await;
+    if wtx.delete(CRASH_KEY).await.is_err() || wtx.commit().await.is_err() {
+        warn!("Failed to clear crash report");
+    } else {
+        info!("Crash report cleared");
+    }
+}
@@ -0,0 +1,171 @@
+//! HTTP and HTTPS with reqwless on the PPP stack, see [`bt_core::prelude::HttpTransport`].
+//!
+//! Unlike the AT HTTP client of the A67 there is no limit on the response lines and the
+//! headers are there. TLS is embedded-tls, reqwless does not verify the server certificate.
+//! With a [`bt_core::prelude::TlsPin`] the requests go through the
+//! [`bt_core::prelude::PinnedTlsTransport`] instead, it only talks to the server presenting the
+//! pinned key.
+
+use bt_core::{
+    prelude::{CellularError, HttpBodySink, HttpStatusCode, HttpTransport, PinnedTlsTransport, TlsPin},
+    warn,
+};
+use embassy_net::{
+    Stack,
+    dns::DnsSocket,
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embedded_io_async::Read;
+use heapless::String;
+use reqwless::{
+    client::{HttpClient, TlsConfig, TlsVerify},
+    request::{Method, RequestBuilder},
+    response::Response,
+};
+use static_cell::StaticCell;
+
+use crate::ppp::Connector;
+
+const SOCKET_BUFFER_SIZE: usize = 1024;
+/// A full TLS record, embedded-tls does not negotiate a smaller one.
+const TLS_READ_BUFFER_SIZE: usize = 16640;
+const TLS_WRITE_BUFFER_SIZE: usize = 4096;
+/// Status line and headers of the response.
+const RESPONSE_HEAD_SIZE: usize = 1024;
+const BODY_CHUNK_SIZE: usize = 256;
+const URL_SIZE: usize = 256;
+
+pub struct ReqwlessTransport {
+    stack: Stack<'static>,
+    tcp_state: &'static TcpClientState<1, SOCKET_BUFFER_SIZE, SOCKET_BUFFER_SIZE>,
+    tls_read: &'static mut [u8; TLS_READ_BUFFER_SIZE],
+    tls_write: &'static mut [u8; TLS_WRITE_BUFFER_SIZE],
+    head: [u8; RESPONSE_HEAD_SIZE],
+    seed: u64,
+    pin: Option<TlsPin>,
+}
+
+impl ReqwlessTransport {
+    pub fn new(stack: Stack<'static>, seed: u64) -> Self {
+        static TCP_STATE: StaticCell<TcpClientState<1, SOCKET_BUFFER_SIZE, SOCKET_BUFFER_SIZE>> = StaticCell::new();
+        static TLS_READ: StaticCell<[u8; TLS_READ_BUFFER_SIZE]> = StaticCell::new();
+        static TLS_WRITE: StaticCell<[u8; TLS_WRITE_BUFFER_SIZE]> = StaticCell::new();
+        Self {
+            stack,
+            tcp_state: TCP_STATE.init(TcpClientState::new()),
+            tls_read: TLS_READ.init([0; TLS_READ_BUFFER_SIZE]),
+            tls_write: TLS_WRITE.init([0; TLS_WRITE_BUFFER_SIZE]),
+            head: [0; RESPONSE_HEAD_SIZE],
+            seed,
+            pin: None,
+        }
+    }
+
+    /// Only talks HTTPS to the server presenting the pinned key, see the module documentation.
+    pub fn with_tls_pin(mut self, pin: &TlsPin) -> Self {
+        self.pin = Some(pin.clone());
+        self
+    }
+
+    /// A different seed for the TLS random of every connection.
+    fn next_seed(&mut self) -> u64 {
+        // splitmix64
+        self.seed = self.seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// The seed of the ChaCha RNG of the [`PinnedTlsTransport`].
+    fn next_seed_bytes(&mut self) -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        for chunk in seed.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_seed().to_le_bytes());
+        }
+        seed
+    }
+}
+
+impl HttpTransport for ReqwlessTransport {
+    async fn wait_up(&self) {
+        self.stack.wait_config_up().await
+    }
+
+    async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+        sink: &mut impl HttpBodySink,
+        body_on_error: bool,
+    ) -> Result<(HttpStatusCode, usize), CellularError> {
+        if let Some(pin) = self.pin.clone() {
+            let seed = self.next_seed_bytes();
+            let mut pinned = PinnedTlsTransport::new(Connector::new(self.stack), pin, seed, &mut self.tls_read[..], &mut self.tls_write[..]);
+            return pinned.request(method, url, headers, body, sink, body_on_error).await;
+        }
+        let method = match method {
+            "GET" => Method::GET,
+            "POST" => Method::POST,
+            _ => return Err(CellularError::Unsupported),
+        };
+        // the backend URLs come without a scheme, reqwless needs one
+        let mut full_url = String::<URL_SIZE>::new();
+        if !url.contains("://") {
+            full_url.push_str("http://").map_err(|_| CellularError::BufferOverflow)?;
+        }
+        full_url.push_str(url).map_err(|_| CellularError::BufferOverflow)?;
+
+        let seed = self.next_seed();
+        let tcp = TcpClient::new(self.stack, self.tcp_state);
+        let dns = DnsSocket::new(self.stack);
+        let tls = TlsConfig::new(seed, &mut self.tls_read[..], &mut self.tls_write[..], TlsVerify::None);
+        let mut client = HttpClient::new_with_tls(&tcp, &dns, tls);
+        let mut handle = client.request(method, &full_url).await.map_err(http_error)?.headers(headers);
+        match body {
+            Some(body) => {
+                let mut handle = handle.body(body);
+                let response = handle.send(&mut self.head).await.map_err(http_error)?;
+                read_body(response, sink, body_on_error).await
+            }
+            None => {
+                let response = handle.send(&mut self.head).await.map_err(http_error)?;
+                read_body(response, sink, body_on_error).await
+            }
+        }
+    }
+}
+
+async fn read_body<C: Read>(
+    response: Response<'_, '_, C>,
+    sink: &mut impl HttpBodySink,
+    body_on_error: bool,
+) -> Result<(HttpStatusCode, usize), CellularError> {
+    let status = HttpStatusCode::new(response.status.0 as u32);
+    if !status.is_ok() && !body_on_error {
+        return Ok((status, 0));
+    }
+    let mut reader = response.body().reader();
+    let mut chunk = [0u8; BODY_CHUNK_SIZE];
+    let mut len = 0;
+    loop {
+        match reader.read(&mut chunk).await.map_err(http_error)? {
+            0 => return Ok((status, len)),
+            n => {
+                sink.write(&chunk[..n]).await?;
+                len += n;
+            }
+        }
+    }
+}
+
+fn http_error(e: reqwless::Error) -> CellularError {
+    warn!("HTTP request failed: {:?}", e);
+    match e {
+        reqwless::Error::Dns => CellularError::HostNotFound,
+        reqwless::Error::BufferTooSmall => CellularError::BufferOverflow,
+        _ => CellularError::Connection,
+    }
+}
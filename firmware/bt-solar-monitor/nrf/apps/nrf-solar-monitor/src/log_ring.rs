@@ -0,0 +1,134 @@
+//! The captured log chunks in the key value store, and the global logger of the field builds.
+//!
+//! The chunks rotate through [`LOG_SLOTS`] keys by their sequence number, every value
+//! starts with the sequence number so a slot of a wrapped region is told apart.
+//!
+//! With the `log-ring` feature the defmt frames go to the log ring of bt-core
+//! instead of RTT, an installed device has no probe attached.
+
+use bt_core::prelude::{LOG_CHUNK_SIZE, LogCursors, LogError, LogStore};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+const LOG_SLOTS: u32 = 16;
+const CURSORS_KEY: &[u8] = b"log/cursors";
+
+pub struct EkvLogStore<'a, F: ekv::flash::Flash> {
+    db: &'a ekv::Database<F, NoopRawMutex>,
+}
+
+impl<'a, F: ekv::flash::Flash> EkvLogStore<'a, F> {
+    pub fn new(db: &'a ekv::Database<F, NoopRawMutex>) -> Self {
+        Self { db }
+    }
+
+    async fn write(&mut self, key: &[u8], data: &[u8]) -> Result<(), LogError> {
+        let mut wtx = self.db.write_transaction().await;
+        if wtx.write(key, data).await.is_err() || wtx.commit().await.is_err() {
+            return Err(LogError::Storage);
+        }
+        Ok(())
+    }
+}
+
+fn chunk_key(sequence: u32) -> [u8; 5] {
+    let mut key = *b"log/\0";
+    key[4] = (sequence % LOG_SLOTS) as u8;
+    key
+}
+
+impl<F: ekv::flash::Flash> LogStore for EkvLogStore<'_, F> {
+    async fn cursors(&mut self) -> Result<LogCursors, LogError> {
+        let mut buffer = [0u8; 8];
+        let rtx = self.db.read_transaction().await;
+        match rtx.read(CURSORS_KEY, &mut buffer).await {
+            Ok(8) => Ok(LogCursors {
+                next: u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
+                upload: u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            }),
+            // not written yet
+            _ => Ok(LogCursors::default()),
+        }
+    }
+
+    async fn store_cursors(&mut self, cursors: &LogCursors) -> Result<(), LogError> {
+        let mut buffer = [0u8; 8];
+        buffer[..4].copy_from_slice(&cursors.next.to_le_bytes());
+        buffer[4..].copy_from_slice(&cursors.upload.to_le_bytes());
+        self.write(CURSORS_KEY, &buffer).await
+    }
+
+    async fn append(&mut self, sequence: u32, chunk: &[u8]) -> Result<(), LogError> {
+        let mut buffer = [0u8; 4 + LOG_CHUNK_SIZE];
+        let value = buffer.get_mut(..4 + chunk.len()).ok_or(LogError::Corrupt)?;
+        value[..4].copy_from_slice(&sequence.to_le_bytes());
+        value[4..].copy_from_slice(chunk);
+        self.write(&chunk_key(sequence), value).await
+    }
+
+    async fn read(&mut self, sequence: u32, buffer: &mut [u8]) -> Result<Option<usize>, LogError> {
+        let mut value = [0u8; 4 + LOG_CHUNK_SIZE];
+        let rtx = self.db.read_transaction().await;
+        let Ok(len) = rtx.read(&chunk_key(sequence), &mut value).await else {
+            return Ok(None);
+        };
+        if len < 4 || value[..4] != sequence.to_le_bytes() {
+            // the slot holds a newer chunk once the region wrapped
+            return Ok(None);
+        }
+        let chunk = &value[4..len];
+        buffer.get_mut(..chunk.len()).ok_or(LogError::Corrupt)?.copy_from_slice(chunk);
+        Ok(Some(chunk.len()))
+    }
+}
+
+#[cfg(feature = "log-ring")]
+mod logger {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use bt_core::prelude::driver::log_frame;
+
+    #[defmt::global_logger]
+    struct RingLogger;
+
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    static mut CS_RESTORE: critical_section::RestoreState = critical_section::RestoreState::invalid();
+    static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+    fn write(bytes: &[u8]) {
+        log_frame(bytes);
+    }
+
+    // SAFETY: like defmt-rtt, the frame is written within one critical section
+    unsafe impl defmt::Logger for RingLogger {
+        fn acquire() {
+            // SAFETY: released in `release`
+            let restore = unsafe { critical_section::acquire() };
+            if TAKEN.load(Ordering::Relaxed) {
+                panic!("defmt logger taken reentrantly")
+            }
+            TAKEN.store(true, Ordering::Relaxed);
+            // SAFETY: only accessed within the critical section
+            unsafe {
+                CS_RESTORE = restore;
+                (*(&raw mut ENCODER)).start_frame(write);
+            }
+        }
+
+        unsafe fn flush() {}
+
+        unsafe fn release() {
+            // SAFETY: only accessed within the critical section of `acquire`
+            unsafe {
+                (*(&raw mut ENCODER)).end_frame(write);
+                TAKEN.store(false, Ordering::Relaxed);
+                let restore = CS_RESTORE;
+                critical_section::release(restore);
+            }
+        }
+
+        unsafe fn write(bytes: &[u8]) {
+            // SAFETY: only accessed within the critical section of `acquire`
+            unsafe { (*(&raw mut ENCODER)).write(bytes, write) }
+        }
+    }
+}
@@ -1,33 +1,96 @@
 #![no_std]
 #![no_main]
 
-use bt_core::{info, net::cellular::sim_com_a67::SimComCellularModule};
+mod audit;
+mod config_store;
+mod crash;
+#[cfg(feature = "reqwless")]
+mod https;
+mod log_ring;
+mod ota;
+#[cfg(feature = "poll-stats")]
+mod poll_stats;
+mod power_cycles;
+#[cfg(feature = "ppp")]
+mod ppp;
+mod reset_reason;
+mod stack;
+mod upload_store;
+#[cfg(feature = "usb-shell")]
+mod usb_shell;
+
+use bt_core::{
+    info,
+    prelude::{
+        APN_PROFILES, ApnProfiles, Audit, BATCH_POLICY, BLINK_PATTERN, BatchPolicy, BatteryAlarms, CHEMISTRY, CONFIG_MIGRATIONS, ChargerErrors, Chemistry,
+        ConfigStore, DailyHistory, ENVELOPE_KEY, FIRMWARE_KEY, Field, Filter, FirmwareSlot, Flush, HEALTH, IDENTIFY, LoadSwitch, Monitored, Ota, PRIVACY_MODE,
+        PowerManager, PowerState, ReadingFilter, SimComCellularModule, SocConfig, SocEstimator, TLS_PIN, Timeouts, UPLOAD_ENCODING, UartPath, UploadEncoding,
+        UploadQueue, UploadScheduler, UploadStatus, UploadWindow, Watchdog, load_quirks, restore_time,
+        tasks::{at, cloud, log_ring as log_runner, metrics, persist_quirks, persist_time, upload, ve_direct},
+    },
+    warn,
+};
+use bt_nrf::driver::qspi_flash::{DFU_OFFSET, EkvFlash, QspiFlashDriver};
+#[cfg(not(feature = "log-ring"))]
+use defmt_rtt as _;
+use embassy_embedded_hal::adapter::BlockingAsync;
 use embassy_executor::Spawner;
 use embassy_futures::join::*;
 use embassy_nrf::{
     bind_interrupts,
     buffered_uarte::{self, BufferedUarte},
     gpio::{Input, Level, Output, OutputDrive, Pull},
-    peripherals,
+    nvmc::Nvmc,
+    peripherals, qspi,
+    rng::{self, Rng},
     uarte::{self, Uarte},
 };
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex, signal::Signal};
 use embassy_time::Timer;
-use {defmt_rtt as _, panic_probe as _};
+use rand_core::RngCore;
 
 const CONFIG_SOLAR_SENSOR_AVERAGING_DURATION: embassy_time::Duration = embassy_time::Duration::from_secs(5 * 60);
 //const CONFIG_SOLAR_SENSOR_AVERAGING_DURATION: embassy_time::Duration = embassy_time::Duration::from_secs(20);
+/// One batch per hour, the modem is powered off in between.
+const CONFIG_READINGS_PER_UPLOAD: u16 = 12;
+/// A reset loop must not pulse PWRKEY more often.
+const CONFIG_MIN_POWER_CYCLE_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(2 * 60);
+/// Health events at most every 6 hours, sent while the modem is on anyway.
+const CONFIG_HEALTH_REPORT_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(6 * 60 * 60);
+/// Wakes the sleeping modem, the backend marks a device offline after two missed heartbeats.
+const CONFIG_HEARTBEAT_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(30 * 60);
+/// The captured warnings and errors once a day, the backend requests them sooner if needed.
+const CONFIG_LOG_UPLOAD_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(24 * 60 * 60);
+/// Compared with the version of the OTA manifest, raise it with every release signed by `just sign_ota_nrf`.
+const CONFIG_FIRMWARE_VERSION: u32 = 1;
+/// Looks for a new firmware once a day, while the modem is on anyway.
+const CONFIG_FIRMWARE_CHECK_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(24 * 60 * 60);
+/// The sensor in the battery compartment, an SHT4x works at its default address too.
+#[cfg(feature = "temperature")]
+const CONFIG_TEMPERATURE_CHIP: bt_core::prelude::TemperatureChip = bt_core::prelude::TemperatureChip::Tmp117;
 
 bind_interrupts!(struct Irqs {
     UARTE0 => buffered_uarte::InterruptHandler<peripherals::UARTE0>;
     UARTE1 => uarte::InterruptHandler<peripherals::UARTE1>;
+    QSPI => qspi::InterruptHandler<peripherals::QSPI>;
+    RNG => rng::InterruptHandler<peripherals::RNG>;
+    #[cfg(feature = "usb-shell")]
+    USBD => embassy_nrf::usb::InterruptHandler<peripherals::USBD>;
+    #[cfg(feature = "usb-shell")]
+    CLOCK_POWER => embassy_nrf::usb::vbus_detect::InterruptHandler;
+    #[cfg(feature = "analog")]
+    SAADC => embassy_nrf::saadc::InterruptHandler;
+    #[cfg(feature = "temperature")]
+    TWISPI0 => embassy_nrf::twim::InterruptHandler<peripherals::TWISPI0>;
 });
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     let p = embassy_nrf::init(Default::default());
     info!("nRF Solar Monitor starting up...");
+    HEALTH.set_reset_reason(reset_reason::take());
     info!("Using backend URL: {}", bt_core::config::SOLAR_BACKEND_BASE_URL);
-    info!("Using averaging duration: {}", CONFIG_SOLAR_SENSOR_AVERAGING_DURATION.as_secs());
+    info!("Using averaging duration: {}", bt_core::prelude::FormatableDuration(CONFIG_SOLAR_SENSOR_AVERAGING_DURATION));
 
     let mut led = Output::new(p.P1_12, Level::Low, OutputDrive::Standard);
 
@@ -38,6 +101,9 @@ async fn main(_spawner: Spawner) {
     let reset = Output::new(p.P0_03, Level::Low, OutputDrive::Standard);
     let pwrkey = Output::new(p.P0_04, Level::Low, OutputDrive::Standard);
     let mut netlight = Input::new(p.P0_28, Pull::None);
+    // strapped to GND: every USB terminal is bridged to the modem UART first
+    #[cfg(feature = "usb-shell")]
+    let bridge_strap = Input::new(p.P0_29, Pull::Up).is_low();
 
     let mut uart_lte_config = uarte::Config::default();
     uart_lte_config.parity = uarte::Parity::EXCLUDED;
@@ -57,21 +123,238 @@ async fn main(_spawner: Spawner) {
         &mut uart_lte_rx_buffer,
         &mut uart_lte_tx_buffer,
     );
+    // the buffered UART has no error kinds to tell apart, every read error counts as overrun
+    let uart_lte = Monitored::new(uart_lte, UartPath::Modem, |_| true);
 
-    let mut at_state = bt_core::at::State::new();
-    let (at_runner, at_client) = bt_core::at::new(&mut at_state, uart_lte);
-    let module = SimComCellularModule::new(at_client, pwrkey, reset);
+    // Config for the MX25L3233F (32 Mbit = 4 MB), see the flash sketch
+    let mut qspi_config = qspi::Config::default();
+    qspi_config.read_opcode = qspi::ReadOpcode::READ2O;
+    qspi_config.write_opcode = qspi::WriteOpcode::PP;
+    qspi_config.write_page_size = qspi::WritePageSize::_256BYTES;
+    qspi_config.frequency = qspi::Frequency::M8;
+    qspi_config.capacity = 4 * 1024 * 1024;
+    let qspi = qspi::Qspi::new(p.QSPI, Irqs, p.P0_19, p.P0_17, p.P0_20, p.P0_21, p.P0_22, p.P0_23, qspi_config);
+    let supervisor = Watchdog::<8>::new();
+    // a sector erase takes 200 ms at most, the maintenance only needs to outlast a few
+    let flash = Mutex::<NoopRawMutex, _>::new(
+        QspiFlashDriver::new(qspi).with_watchdog(supervisor.register_maintenance("flash", embassy_time::Duration::from_secs(2)).unwrap()),
+    );
+    // the DFU partition of the bootloader is above the database, see memory.x
+    let mut ekv_flash = EkvFlash::new(&flash, 0, DFU_OFFSET);
+    let mut ekv_config = ekv::Config::default();
+    let mut rng = Rng::new(p.RNG, Irqs);
+    ekv_config.random_seed = rng.next_u32();
+    let db = ekv::Database::<_, NoopRawMutex>::new(&mut ekv_flash, ekv_config);
+    if db.mount().await.is_err() {
+        info!("Flash database not mounted => formatting...");
+        if db.format().await.is_err() {
+            warn!("Flash database format failed");
+        }
+    }
+    let crash_report = crash::persist(&db).await;
+    let crash_reported = Signal::<NoopRawMutex, ()>::new();
+    let audit_log = Audit::new();
+    let audit_runner = audit_log.runner(audit::EkvAuditStore::new(&db));
+    let mut config = ConfigStore::new(config_store::EkvKeyValueStore::new(&db));
+    match config.migrate(CONFIG_MIGRATIONS).await {
+        Ok(version) => info!("Config version {}", version),
+        Err(e) => warn!("Config migration failed: {:?}", e),
+    }
+    // readings before the first sync get the time of the last run
+    restore_time(&mut config).await;
+    let chemistry = config.get_or(CHEMISTRY, Chemistry::default()).await;
+    let apn_profiles = config.get_or(APN_PROFILES, ApnProfiles::new()).await;
+    info!("Battery chemistry {:?}", chemistry);
+    let upload_encoding = config.get_or(UPLOAD_ENCODING, UploadEncoding::default()).await;
+    let default_batch_policy = BatchPolicy {
+        max_entries: CONFIG_READINGS_PER_UPLOAD,
+        flush_on_alarm: true,
+        ..BatchPolicy::default()
+    };
+    let batch_policy = config.get_or(BATCH_POLICY, default_batch_policy).await;
+    let tls_pin = config.get(TLS_PIN).await.ok().flatten();
+    let privacy_mode = config.get_or(PRIVACY_MODE, false).await;
+    let envelope_key = config.get(ENVELOPE_KEY).await.ok().flatten();
+    let firmware_key = config.get(FIRMWARE_KEY).await.ok().flatten();
+    load_quirks(&mut config).await;
+    #[cfg(feature = "usb-shell")]
+    let shell_pin = config.get(bt_core::prelude::SHELL_PIN).await.ok().flatten();
+    #[cfg(feature = "analog")]
+    let analog_calibration = config
+        .get_or(bt_core::prelude::ANALOG_CALIBRATION, bt_core::prelude::AnalogCalibration::new())
+        .await;
+
+    let internal_flash = Mutex::<NoopRawMutex, _>::new(BlockingAsync::new(Nvmc::new(p.NVMC)));
+    let mut dfu_magic = ota::DfuSlot::magic();
+    let mut dfu_slot = ota::DfuSlot::new(&flash, &internal_flash, &mut dfu_magic);
+    let firmware_ota = firmware_key.map(Ota::new);
+
+    let timeouts = Timeouts::default();
+    let power = PowerManager::new();
+    let scheduler = UploadScheduler::default();
+    let log_runner = log_runner::new(log_ring::EkvLogStore::new(&db), &scheduler).with_upload_interval(CONFIG_LOG_UPLOAD_INTERVAL);
+    // the health counters piggy-back on the reading uploads
+    let metrics_runner = metrics::new(&scheduler, CONFIG_HEALTH_REPORT_INTERVAL, stack::free);
+    let mut at_state = at::State::new().with_recorder();
+    let (at_runner, at_client) = at::new(&mut at_state, uart_lte, timeouts);
+    #[cfg(feature = "usb-shell")]
+    let shell_at_client = at_client.try_clone().unwrap();
+    let at_runner = at_runner.with_watchdog(supervisor.register("at", embassy_time::Duration::from_secs(30)).unwrap());
+    let mut module = SimComCellularModule::new(at_client, pwrkey, reset, timeouts);
+    if let Some(pin) = tls_pin.clone() {
+        module = module.with_tls_pin(pin);
+    }
+    #[cfg(feature = "ppp")]
+    let module = {
+        let (link, stack, net_runner) = ppp::new(rng.next_u64());
+        _spawner.must_spawn(ppp::net_task(net_runner));
+        #[cfg(not(feature = "reqwless"))]
+        let transport = bt_core::prelude::SocketTransport::new(ppp::Connector::new(stack));
+        #[cfg(not(feature = "reqwless"))]
+        if tls_pin.is_some() {
+            warn!("TLS pin set but the socket transport is plain HTTP, pinning needs the reqwless feature");
+        }
+        #[cfg(feature = "reqwless")]
+        let mut transport = https::ReqwlessTransport::new(stack, rng.next_u64());
+        #[cfg(feature = "reqwless")]
+        if let Some(pin) = &tls_pin {
+            transport = transport.with_tls_pin(pin);
+        }
+        bt_core::prelude::PppCellularModule::new(module, link, transport, timeouts)
+    };
 
     let mut uart_ve_config = uarte::Config::default();
     uart_ve_config.parity = uarte::Parity::EXCLUDED;
     uart_ve_config.baudrate = uarte::Baudrate::BAUD19200;
-    let uart_ve = UartWrapper(Uarte::new(p.UARTE1, p.P1_10, p.P1_08, Irqs, uart_ve_config));
+    let uart_ve = Monitored::new(UartWrapper(Uarte::new(p.UARTE1, p.P1_10, p.P1_08, Irqs, uart_ve_config)), UartPath::VeDirect, |error| {
+        matches!(error, uarte::Error::Overrun)
+    });
 
-    let mut ve_state = bt_core::sensor::ve_direct::State::<8>::new();
-    let (ve_direct_runner, ve_rx) = bt_core::sensor::ve_direct::new(&mut ve_state, uart_ve, CONFIG_SOLAR_SENSOR_AVERAGING_DURATION, green);
-    let upload_channel = embassy_sync::channel::Channel::<embassy_sync::blocking_mutex::raw::NoopRawMutex, _, 4>::new();
-    let solar_runner = bt_core::solar_monitor::upload::new(ve_rx, upload_channel.sender());
-    let cloud_runner = bt_core::solar_monitor::cloud::new(module, upload_channel.receiver());
+    let charger_errors = ChargerErrors::new();
+    let daily_history = DailyHistory::new();
+    let load_switch = LoadSwitch::new();
+    let mut ve_state = ve_direct::State::<8>::new();
+    let (ve_direct_runner, ve_rx) = ve_direct::new(&mut ve_state, uart_ve, CONFIG_SOLAR_SENSOR_AVERAGING_DURATION, green);
+    // the currents glitch by amps on the long VE.Direct cable
+    let ve_filter = ReadingFilter::new()
+        .with(Field::BatteryCurrent, Filter::Median3)
+        .with(Field::LoadCurrent, Filter::Median3);
+    let ve_direct_runner = ve_direct_runner
+        .with_watchdog(supervisor.register("ve_direct", embassy_time::Duration::from_secs(30)).unwrap())
+        .with_filter(ve_filter)
+        .with_variance()
+        .with_audit(&audit_log)
+        .with_charger_errors(&charger_errors)
+        .with_history(&daily_history)
+        .with_load_switch(&load_switch);
+    #[cfg(feature = "analog")]
+    let mut analog_state = bt_core::prelude::tasks::analog::State::<2>::new();
+    #[cfg(feature = "analog")]
+    let (analog_runner, analog_rx) = {
+        let channels = [
+            embassy_nrf::saadc::ChannelConfig::single_ended(p.P0_02),
+            embassy_nrf::saadc::ChannelConfig::single_ended(p.P0_31),
+        ];
+        let input = bt_nrf::driver::saadc::SaadcInput::new(embassy_nrf::saadc::Saadc::new(p.SAADC, Irqs, embassy_nrf::saadc::Config::default(), channels));
+        input.calibrate().await;
+        // averaged like the VE.Direct readings, the upload runner merges them per entry
+        let (runner, rx) = bt_core::prelude::tasks::analog::new(&mut analog_state, input, CONFIG_SOLAR_SENSOR_AVERAGING_DURATION);
+        let runner = runner
+            .with_watchdog(supervisor.register("analog", embassy_time::Duration::from_secs(30)).unwrap())
+            .with_calibration(analog_calibration);
+        (runner, rx)
+    };
+    #[cfg(feature = "pulse")]
+    let mut pulse_state = bt_core::prelude::tasks::pulse::State::<2>::new();
+    #[cfg(feature = "pulse")]
+    let (pulse_runner, pulse_rx) = {
+        let input = bt_nrf::driver::pulse::PulsePin::new(Input::new(p.P1_02, Pull::Up));
+        // counted over the averaging interval, the upload runner merges the counts per entry
+        let (runner, rx) = bt_core::prelude::tasks::pulse::new(&mut pulse_state, input, CONFIG_SOLAR_SENSOR_AVERAGING_DURATION);
+        let runner = runner.with_watchdog(supervisor.register("pulse", embassy_time::Duration::from_secs(30)).unwrap());
+        (runner, rx)
+    };
+    #[cfg(feature = "temperature")]
+    let mut temperature_state = bt_core::prelude::tasks::temperature::State::<2>::new();
+    // the command bytes of the sensor are constants in flash, the TWIM only sends from RAM
+    #[cfg(feature = "temperature")]
+    let mut twim_tx_buffer = [0u8; 4];
+    #[cfg(feature = "temperature")]
+    let (temperature_runner, temperature_rx) = {
+        let twim = embassy_nrf::twim::Twim::new(p.TWISPI0, Irqs, p.P0_26, p.P0_27, embassy_nrf::twim::Config::default(), &mut twim_tx_buffer);
+        let sensor = bt_core::prelude::TemperatureSensor::new(twim, CONFIG_TEMPERATURE_CHIP);
+        // averaged like the VE.Direct readings, the upload runner merges them per entry
+        let (runner, rx) = bt_core::prelude::tasks::temperature::new(&mut temperature_state, sensor, CONFIG_SOLAR_SENSOR_AVERAGING_DURATION);
+        let runner = runner.with_watchdog(supervisor.register("temperature", embassy_time::Duration::from_secs(30)).unwrap());
+        (runner, rx)
+    };
+    // the batches wait in the flash queue, the channel only holds the one handed to the cloud
+    let upload_channel = embassy_sync::channel::Channel::<embassy_sync::blocking_mutex::raw::NoopRawMutex, _, 1>::new();
+    let upload_queue = UploadQueue::new();
+    // the flash deletions of the uploaded batches wait for the modem to sleep
+    let upload_queue_runner = upload_queue
+        .runner(upload_store::EkvUploadStore::new(&db), upload_channel.sender())
+        .with_maintenance_window(&power);
+    let upload_status = embassy_sync::watch::Watch::<NoopRawMutex, UploadStatus, 1>::new();
+    let battery_voltage = embassy_sync::watch::Watch::<NoopRawMutex, f32, 1>::new();
+    let flush = Flush::new();
+    let solar_runner = upload::new(ve_rx, upload_channel.sender())
+        .with_watchdog(supervisor.register("upload", CONFIG_SOLAR_SENSOR_AVERAGING_DURATION * 2).unwrap())
+        .with_batch_policy(batch_policy)
+        .with_queue(&upload_queue)
+        .with_flush(&flush)
+        .with_encoding(upload_encoding)
+        .with_status(upload_status.dyn_sender())
+        .with_battery_voltage(battery_voltage.dyn_sender())
+        .with_battery_alarms(BatteryAlarms::new(chemistry.alarm_preset()))
+        // MPPT only, no battery monitor to report the state of charge
+        .with_soc_estimator(SocEstimator::new(SocConfig::new(chemistry)))
+        .with_loop_deadline(embassy_time::Duration::from_millis(500));
+    #[cfg(feature = "temperature")]
+    let solar_runner = solar_runner.with_sensor(bt_core::prelude::SensorSource::Temperature(temperature_rx.into()));
+    #[cfg(feature = "analog")]
+    let solar_runner = solar_runner.with_sensor(bt_core::prelude::SensorSource::Analog(analog_rx.into()));
+    #[cfg(feature = "pulse")]
+    let solar_runner = solar_runner.with_sensor(bt_core::prelude::SensorSource::Pulses(pulse_rx.into()));
+    // module startup waits for the network registration and retries uploads, give it plenty of time
+    let cloud_runner = cloud::new(module, upload_channel.receiver(), timeouts)
+        .with_watchdog(supervisor.register("cloud", embassy_time::Duration::from_secs(10 * 60)).unwrap())
+        .with_power_manager(power.handle())
+        .with_jitter_seed(rng.next_u32())
+        .with_apn_profiles(&apn_profiles)
+        .with_upload_encoding(upload_encoding)
+        .with_scheduler(&scheduler)
+        .with_upload_status(upload_status.dyn_anon_receiver())
+        .with_link_quality(embassy_time::Duration::from_secs(60))
+        .with_power_cycle_record(power_cycles::record(), CONFIG_MIN_POWER_CYCLE_INTERVAL)
+        .with_heartbeat(CONFIG_HEARTBEAT_INTERVAL, env!("CARGO_PKG_VERSION"))
+        .with_audit(&audit_log)
+        .with_charger_errors(&charger_errors)
+        .with_daily_history(&daily_history)
+        .with_load_switch(&load_switch)
+        .with_privacy_mode(privacy_mode)
+        .with_battery_policy(chemistry.battery_policy(), battery_voltage.dyn_anon_receiver())
+        .with_power_off(UploadWindow::after_averaging(CONFIG_SOLAR_SENSOR_AVERAGING_DURATION, batch_policy.max_entries as u32))
+        .with_flush(&flush);
+    let cloud_runner = match crash_report {
+        Some(report) => cloud_runner.with_crash_report(report, &crash_reported),
+        None => cloud_runner,
+    };
+    let cloud_runner = match envelope_key {
+        Some(key) => {
+            info!("Uploads sealed with key {}", key.id);
+            let mut seed = [0u8; 32];
+            rng.fill_bytes(&mut seed);
+            cloud_runner.with_envelope(key, seed)
+        }
+        None => cloud_runner,
+    };
+    let cloud_runner = match &firmware_ota {
+        Some(ota) => cloud_runner.with_firmware_update(ota, CONFIG_FIRMWARE_VERSION, CONFIG_FIRMWARE_CHECK_INTERVAL),
+        None => cloud_runner,
+    };
+    #[cfg(feature = "poll-stats")]
+    let cloud_runner = cloud_runner.with_poll_stats(embassy_time::Duration::from_millis(10));
 
     let mut wdt_config = embassy_nrf::wdt::Config::default();
     wdt_config.timeout_ticks = 32768 * 10; // 10 seconds
@@ -91,31 +374,127 @@ async fn main(_spawner: Spawner) {
     info!("nRF Solar Monitor starting up...");
     blue.set_high();
 
+    let watchdog = supervisor.run(embassy_time::Duration::from_secs(1), || watchdog_handle.pet());
+
     let blinky = async {
+        let mut upload_status = upload_status.anon_receiver();
         loop {
-            watchdog_handle.pet();
-            led.set_high();
-            Timer::after_millis(100).await;
+            // the identify pattern on request, also while asleep
+            if IDENTIFY.is_active(embassy_time::Instant::now()) {
+                for (index, millis) in BLINK_PATTERN.iter().enumerate() {
+                    led.set_level(if index % 2 == 0 { Level::High } else { Level::Low });
+                    Timer::after_millis(*millis).await;
+                }
+                led.set_low();
+                continue;
+            }
+            // three short blinks while the charger reports an error, also while asleep
+            if charger_errors.active().is_some() {
+                for _ in 0..3 {
+                    led.set_high();
+                    Timer::after_millis(100).await;
+                    led.set_low();
+                    Timer::after_millis(100).await;
+                }
+                Timer::after_millis(400).await;
+                continue;
+            }
+            // long blinks while the upload channel overruns, the cloud does not keep up
+            let on = if upload_status.try_get().is_some_and(|status| status.overrun) {
+                500
+            } else {
+                100
+            };
+            // status LEDs only while awake, they draw more than the idle nRF
+            if power.state() == PowerState::Active {
+                led.set_high();
+            }
+            Timer::after_millis(on).await;
             led.set_low();
-            Timer::after_millis(900).await;
+            Timer::after_millis(1000 - on).await;
         }
     };
 
-    let mut follow = |netlight: &Input<'_>| {
-        let level = if netlight.is_high() { Level::Low } else { Level::High };
+    let mut follow = |netlight: &Input<'_>, state: PowerState| {
+        let level = if netlight.is_high() && state == PowerState::Active {
+            Level::Low
+        } else {
+            Level::High
+        };
         blue.set_level(level);
         red.set_level(level);
     };
 
     let netlight_loop = async {
-        follow(&netlight);
         loop {
-            netlight.wait_for_any_edge().await;
-            follow(&netlight);
+            follow(&netlight, power.state());
+            embassy_futures::select::select(netlight.wait_for_any_edge(), power.wait_for_change()).await;
+        }
+    };
+
+    #[cfg(feature = "usb-shell")]
+    let usb_shell = {
+        let policy = bt_core::prelude::ShellPolicy {
+            pin: shell_pin,
+            ..Default::default()
+        };
+        let backend = usb_shell::Backend::new(&db, shell_at_client, &flush);
+        usb_shell::run(p.USBD, bt_core::prelude::Console::new(policy, backend, env!("CARGO_PKG_VERSION")), bridge_strap, power.handle())
+    };
+    #[cfg(not(feature = "usb-shell"))]
+    let usb_shell = async {};
+
+    #[cfg(feature = "analog")]
+    let analog = analog_runner.run();
+    #[cfg(not(feature = "analog"))]
+    let analog = async {};
+    #[cfg(feature = "pulse")]
+    let pulse = pulse_runner.run();
+    #[cfg(not(feature = "pulse"))]
+    let pulse = async {};
+    #[cfg(feature = "temperature")]
+    let temperature = temperature_runner.run();
+    #[cfg(not(feature = "temperature"))]
+    let temperature = async {};
+
+    let crash_clear = async {
+        crash_reported.wait().await;
+        crash::clear(&db).await;
+    };
+
+    let ota_loop = async {
+        match &firmware_ota {
+            Some(ota) => {
+                ota.runner(dfu_slot).run().await;
+                info!("Firmware update verified => reset into the bootloader");
+                Timer::after_millis(100).await;
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            // without a key there are no updates, a swapped in firmware is kept nevertheless
+            None => {
+                if let Err(e) = dfu_slot.mark_booted().await {
+                    warn!("Marking the firmware booted failed: {:?}", e);
+                }
+            }
         }
     };
 
-    join(join(blinky, netlight_loop), join4(at_runner.run(), ve_direct_runner.run(), cloud_runner.run(), solar_runner.run())).await;
+    join4(
+        watchdog,
+        join5(
+            crash_clear,
+            audit_runner.run(),
+            upload_queue_runner.run(),
+            join(log_runner.run(), metrics_runner.run()),
+            join(
+                persist_time(ConfigStore::new(config_store::EkvKeyValueStore::new(&db))),
+                persist_quirks(ConfigStore::new(config_store::EkvKeyValueStore::new(&db))),
+            ),
+        ),
+        join4(blinky, netlight_loop, usb_shell, ota_loop),
+        join5(at_runner.run(), ve_direct_runner.run(), cloud_runner.run(), solar_runner.run(), join3(analog, pulse, temperature)),
+    )
+    .await;
 }
 
 struct UartWrapper<'d>(Uarte<'d>);
@@ -0,0 +1,65 @@
+//! The [`FirmwareSlot`] of the OTA update, see [`bt_core::prelude::Ota`].
+//!
+//! The DFU partition of embassy-boot is the last MB of the QSPI flash (above the ekv
+//! database), the bootloader state is in the internal flash, both as in `memory.x`. The
+//! nrf-bootloader app swaps the DFU partition with the app in the internal flash after a
+//! reset and swaps it back if the new firmware resets before it marked itself booted.
+
+use bt_core::prelude::{FirmwareSlot, OtaError};
+use bt_nrf::driver::qspi_flash::QspiFlashDriver;
+use embassy_boot_nrf::{AlignedBuffer, FirmwareUpdater, FirmwareUpdaterConfig};
+use embassy_embedded_hal::{adapter::BlockingAsync, flash::partition::Partition};
+use embassy_nrf::nvmc::Nvmc;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+
+pub type QspiFlash<'a> = Mutex<NoopRawMutex, QspiFlashDriver<'a>>;
+pub type InternalFlash<'a> = Mutex<NoopRawMutex, BlockingAsync<Nvmc<'a>>>;
+
+/// Write size of the internal flash, the state magic is written in one word.
+const STATE_WRITE_SIZE: usize = 4;
+
+pub struct DfuSlot<'a> {
+    updater: FirmwareUpdater<'a, Partition<'a, NoopRawMutex, QspiFlashDriver<'a>>, Partition<'a, NoopRawMutex, BlockingAsync<Nvmc<'a>>>>,
+}
+
+impl<'a> DfuSlot<'a> {
+    pub fn new(qspi: &'a QspiFlash<'a>, internal: &'a InternalFlash<'a>, magic: &'a mut AlignedBuffer<STATE_WRITE_SIZE>) -> Self {
+        let config = FirmwareUpdaterConfig::from_linkerfile(qspi, internal);
+        Self {
+            updater: FirmwareUpdater::new(config, &mut magic.0),
+        }
+    }
+
+    /// The buffer of the state magic for [`DfuSlot::new`].
+    pub fn magic() -> AlignedBuffer<STATE_WRITE_SIZE> {
+        AlignedBuffer([0; STATE_WRITE_SIZE])
+    }
+}
+
+impl FirmwareSlot for DfuSlot<'_> {
+    /// The app partition in the internal flash.
+    fn capacity(&self) -> u32 {
+        unsafe extern "C" {
+            static __bootloader_active_start: u32;
+            static __bootloader_active_end: u32;
+        }
+        // SAFETY: only the addresses of the linker symbols are used
+        unsafe { &__bootloader_active_end as *const u32 as u32 - &__bootloader_active_start as *const u32 as u32 }
+    }
+
+    async fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), OtaError> {
+        self.updater.write_firmware(offset as usize, data).await.map_err(|_| OtaError::Flash)
+    }
+
+    async fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), OtaError> {
+        self.updater.read_dfu(offset, buf).await.map_err(|_| OtaError::Flash)
+    }
+
+    async fn mark_updated(&mut self) -> Result<(), OtaError> {
+        self.updater.mark_updated().await.map_err(|_| OtaError::Flash)
+    }
+
+    async fn mark_booted(&mut self) -> Result<(), OtaError> {
+        self.updater.mark_booted().await.map_err(|_| OtaError::Flash)
+    }
+}
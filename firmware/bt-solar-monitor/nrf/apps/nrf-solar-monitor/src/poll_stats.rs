@@ -0,0 +1,30 @@
+//! Hooks of the embassy-executor `trace` feature.
+//!
+//! Only the task polls are measured, the cloud runner reports the long ones.
+
+use bt_core::prelude::driver::{task_exec_begin, task_exec_end};
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_task_exec_begin(_executor_id: u32, task_id: u32) {
+    task_exec_begin(task_id);
+}
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_task_exec_end(_executor_id: u32, task_id: u32) {
+    task_exec_end(task_id);
+}
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_poll_start(_executor_id: u32) {}
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_task_new(_executor_id: u32, _task_id: u32) {}
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_task_end(_executor_id: u32, _task_id: u32) {}
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_task_ready_begin(_executor_id: u32, _task_id: u32) {}
+
+#[unsafe(no_mangle)]
+fn _embassy_trace_executor_idle(_executor_id: u32) {}
@@ -0,0 +1,14 @@
+//! The module power cycles in retained RAM, see [`PowerCycleRecord`].
+
+use core::mem::MaybeUninit;
+
+use bt_core::prelude::PowerCycleRecord;
+
+#[unsafe(link_section = ".uninit.POWER_CYCLE_RECORD")]
+static mut POWER_CYCLE_RECORD: MaybeUninit<PowerCycleRecord> = MaybeUninit::uninit();
+
+/// The record, to be taken once at startup.
+pub fn record() -> &'static mut PowerCycleRecord {
+    // SAFETY: any bit pattern is a valid PowerCycleRecord; only handed out once, to the cloud runner.
+    unsafe { &mut *(&raw mut POWER_CYCLE_RECORD).cast::<PowerCycleRecord>() }
+}
@@ -0,0 +1,108 @@
+//! The embassy-net glue of the PPP data mode, see [`bt_core::prelude::PppCellularModule`].
+//!
+//! PPP runs on the LTE UART while the A67 is in the data mode, embassy-net on top of it.
+//! The stack runner is its own task, it idles while there is no link.
+
+use bt_core::{
+    info,
+    prelude::{CellularError, PppLink, TcpConnector},
+    warn,
+};
+use embassy_net::{Config, ConfigV4, Ipv4Cidr, Stack, StackResources, StaticConfigV4, dns::DnsQueryType, tcp::TcpSocket};
+use embassy_net_ppp::{Device, Runner, State};
+use embassy_nrf::buffered_uarte::BufferedUarte;
+use embassy_time::Duration;
+use static_cell::StaticCell;
+
+const SOCKET_BUFFER_SIZE: usize = 1024;
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Ppp {
+    runner: Runner<'static>,
+    stack: Stack<'static>,
+}
+
+/// Creates the PPP device and the IP stack on it, `net_runner` has to be spawned.
+pub fn new(seed: u64) -> (Ppp, Stack<'static>, embassy_net::Runner<'static, Device<'static>>) {
+    static STATE: StaticCell<State<4, 4>> = StaticCell::new();
+    // a TCP and a DNS socket per request
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (device, runner) = embassy_net_ppp::new(STATE.init(State::new()));
+    let (stack, net_runner) = embassy_net::new(device, Config::default(), RESOURCES.init(StackResources::new()), seed);
+    (Ppp { runner, stack }, stack, net_runner)
+}
+
+#[embassy_executor::task]
+pub async fn net_task(mut runner: embassy_net::Runner<'static, Device<'static>>) -> ! {
+    runner.run().await
+}
+
+impl<'d> PppLink<BufferedUarte<'d>> for Ppp {
+    async fn run(&mut self, stream: &mut BufferedUarte<'d>) -> CellularError {
+        let config = embassy_net_ppp::Config { username: b"", password: b"" };
+        let stack = self.stack;
+        let result = self
+            .runner
+            .run(stream, config, |ipv4| {
+                let Some(address) = ipv4.address else {
+                    warn!("PPP up without an IPv4 address");
+                    return;
+                };
+                info!("PPP up with {}", address);
+                let mut dns_servers = heapless::Vec::new();
+                for server in ipv4.dns_servers.iter().flatten() {
+                    let _ = dns_servers.push(*server);
+                }
+                stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+                    address: Ipv4Cidr::new(address, 0),
+                    gateway: None,
+                    dns_servers,
+                }));
+            })
+            .await;
+        // the link is gone, the next session negotiates a new address
+        stack.set_config_v4(ConfigV4::None);
+        let Err(e) = result;
+        warn!("PPP failed: {:?}", e);
+        CellularError::Connection
+    }
+}
+
+pub struct Connector {
+    stack: Stack<'static>,
+    rx: [u8; SOCKET_BUFFER_SIZE],
+    tx: [u8; SOCKET_BUFFER_SIZE],
+}
+
+impl Connector {
+    pub fn new(stack: Stack<'static>) -> Self {
+        Self {
+            stack,
+            rx: [0; SOCKET_BUFFER_SIZE],
+            tx: [0; SOCKET_BUFFER_SIZE],
+        }
+    }
+}
+
+impl TcpConnector for Connector {
+    type Socket<'s> = TcpSocket<'s>;
+
+    async fn wait_up(&self) {
+        self.stack.wait_config_up().await
+    }
+
+    async fn connect(&mut self, host: &str, port: u16) -> Result<Self::Socket<'_>, CellularError> {
+        let addresses = self.stack.dns_query(host, DnsQueryType::A).await.map_err(|e| {
+            warn!("DNS query for {} failed: {:?}", host, e);
+            CellularError::HostNotFound
+        })?;
+        let address = *addresses.first().ok_or(CellularError::HostNotFound)?;
+        let mut socket = TcpSocket::new(self.stack, &mut self.rx, &mut self.tx);
+        socket.set_timeout(Some(SOCKET_TIMEOUT));
+        socket.connect((address, port)).await.map_err(|e| {
+            warn!("TCP connect to {}:{} failed: {:?}", host, port, e);
+            CellularError::Connection
+        })?;
+        Ok(socket)
+    }
+}
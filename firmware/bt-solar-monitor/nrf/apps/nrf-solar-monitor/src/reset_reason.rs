@@ -0,0 +1,29 @@
+//! Cause of the last reset from `POWER.RESETREAS`, see [`bt_core::prelude::ResetReason`].
+
+use bt_core::{info, prelude::ResetReason};
+use embassy_nrf::pac;
+
+/// Reads and clears RESETREAS, called once at boot.
+///
+/// The bits accumulate until cleared and none is set after a power-on or brown-out reset.
+pub fn take() -> ResetReason {
+    let reasons = pac::POWER.resetreas().read();
+    // write 1 to clear, otherwise the next reset reports them again
+    pac::POWER.resetreas().write_value(reasons);
+    let reason = if reasons.dog() {
+        ResetReason::Watchdog
+    } else if reasons.lockup() {
+        ResetReason::Lockup
+    } else if reasons.sreq() {
+        ResetReason::SoftReset
+    } else if reasons.resetpin() {
+        ResetReason::Pin
+    } else if reasons.0 == 0 {
+        ResetReason::PowerOn
+    } else {
+        // wake up from System OFF
+        ResetReason::Unknown
+    };
+    info!("Reset reason {:?} (RESETREAS {:#010x})", reason, reasons.0);
+    reason
+}
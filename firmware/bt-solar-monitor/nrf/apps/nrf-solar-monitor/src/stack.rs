@@ -0,0 +1,31 @@
+//! The stack watermark for the health event, see [`bt_core::prelude::HEALTH`].
+//!
+//! cortex-m-rt paints the stack at boot (feature `paint-stack`), the lowest word that
+//! still has the paint is the deepest the stack ever reached.
+
+/// `STACK_PAINT_VALUE` of cortex-m-rt.
+const STACK_PAINT: u32 = 0xcccc_cccc;
+
+unsafe extern "C" {
+    /// Lowest address of the stack.
+    static _stack_end: u32;
+    /// Initial stack pointer, the stack grows down from here.
+    static _stack_start: u32;
+}
+
+/// Bytes of the stack that were never used since the reset.
+pub fn free() -> u32 {
+    let end = &raw const _stack_end;
+    let start = &raw const _stack_start;
+    let mut free = 0;
+    let mut word = end;
+    while word < start {
+        // SAFETY: the words between _stack_end and _stack_start are the stack, the painted ones are unused.
+        if unsafe { word.read_volatile() } != STACK_PAINT {
+            break;
+        }
+        free += 4;
+        word = word.wrapping_add(1);
+    }
+    free
+}
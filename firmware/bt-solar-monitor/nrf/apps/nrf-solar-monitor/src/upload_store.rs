@@ -0,0 +1,145 @@
+//! The upload batches in the key value store.
+//!
+//! Every record is a key of its own, the batch sequence number and the record index big
+//! endian, so the records of a batch are written in key order within one write transaction.
+//! The cursors are written last in the same transaction, a batch is stored completely or not.
+
+use bt_core::prelude::{UploadQueueError, UploadStore};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+/// Sorts after the record keys.
+const CURSORS_KEY: &[u8] = b"upload_cursors";
+
+fn record_key(batch: u32, index: u16) -> [u8; 13] {
+    let mut key = *b"upload/\0\0\0\0\0\0";
+    key[7..11].copy_from_slice(&batch.to_be_bytes());
+    key[11..].copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Sequence numbers of the oldest and the next batch.
+#[derive(Clone, Copy, Default)]
+struct Cursors {
+    oldest: u32,
+    next: u32,
+}
+
+impl Cursors {
+    fn encode(&self) -> [u8; 8] {
+        let mut buffer = [0u8; 8];
+        buffer[..4].copy_from_slice(&self.oldest.to_le_bytes());
+        buffer[4..].copy_from_slice(&self.next.to_le_bytes());
+        buffer
+    }
+}
+
+pub struct EkvUploadStore<'a, F: ekv::flash::Flash> {
+    db: &'a ekv::Database<F, NoopRawMutex>,
+    cursors: Option<Cursors>,
+    /// The transaction of the batch being written and its number of records.
+    writing: Option<(ekv::WriteTransaction<'a, F, NoopRawMutex>, u16)>,
+}
+
+impl<'a, F: ekv::flash::Flash> EkvUploadStore<'a, F> {
+    pub fn new(db: &'a ekv::Database<F, NoopRawMutex>) -> Self {
+        Self {
+            db,
+            cursors: None,
+            writing: None,
+        }
+    }
+
+    async fn cursors(&mut self) -> Cursors {
+        if let Some(cursors) = self.cursors {
+            return cursors;
+        }
+        let mut buffer = [0u8; 8];
+        let rtx = self.db.read_transaction().await;
+        let cursors = match rtx.read(CURSORS_KEY, &mut buffer).await {
+            Ok(8) => Cursors {
+                oldest: u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
+                next: u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            },
+            // not written yet
+            _ => Cursors::default(),
+        };
+        self.cursors = Some(cursors);
+        cursors
+    }
+}
+
+impl<F: ekv::flash::Flash> UploadStore for EkvUploadStore<'_, F> {
+    async fn batches(&mut self) -> Result<u32, UploadQueueError> {
+        let cursors = self.cursors().await;
+        Ok(cursors.next.wrapping_sub(cursors.oldest))
+    }
+
+    async fn append(&mut self, record: &[u8]) -> Result<(), UploadQueueError> {
+        let batch = self.cursors().await.next;
+        if self.writing.is_none() {
+            self.writing = Some((self.db.write_transaction().await, 0));
+        }
+        let Some((wtx, records)) = self.writing.as_mut() else {
+            return Err(UploadQueueError::Storage);
+        };
+        wtx.write(&record_key(batch, *records), record).await.map_err(|_| UploadQueueError::Storage)?;
+        *records += 1;
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), UploadQueueError> {
+        let mut cursors = self.cursors().await;
+        let Some((mut wtx, _)) = self.writing.take() else {
+            return Err(UploadQueueError::Storage);
+        };
+        cursors.next = cursors.next.wrapping_add(1);
+        if wtx.write(CURSORS_KEY, &cursors.encode()).await.is_err() || wtx.commit().await.is_err() {
+            return Err(UploadQueueError::Storage);
+        }
+        self.cursors = Some(cursors);
+        Ok(())
+    }
+
+    async fn discard(&mut self) {
+        // a transaction dropped without commit leaves the store unchanged
+        self.writing = None;
+    }
+
+    async fn read(&mut self, batch: u32, index: u16, buffer: &mut [u8]) -> Result<Option<usize>, UploadQueueError> {
+        let cursors = self.cursors().await;
+        if batch >= cursors.next.wrapping_sub(cursors.oldest) {
+            return Ok(None);
+        }
+        let rtx = self.db.read_transaction().await;
+        match rtx.read(&record_key(cursors.oldest.wrapping_add(batch), index), buffer).await {
+            Ok(len) => Ok(Some(len)),
+            Err(ekv::ReadError::KeyNotFound) => Ok(None),
+            Err(ekv::ReadError::BufferTooSmall) => Err(UploadQueueError::Overflow),
+            Err(_) => Err(UploadQueueError::Storage),
+        }
+    }
+
+    async fn remove_oldest(&mut self) -> Result<(), UploadQueueError> {
+        let mut cursors = self.cursors().await;
+        if cursors.oldest == cursors.next {
+            return Ok(());
+        }
+        let mut records = 0;
+        {
+            let rtx = self.db.read_transaction().await;
+            while let Err(ekv::ReadError::BufferTooSmall) = rtx.read(&record_key(cursors.oldest, records), &mut []).await {
+                records += 1;
+            }
+        }
+        let mut wtx = self.db.write_transaction().await;
+        for index in 0..records {
+            wtx.delete(&record_key(cursors.oldest, index)).await.map_err(|_| UploadQueueError::Storage)?;
+        }
+        cursors.oldest = cursors.oldest.wrapping_add(1);
+        if wtx.write(CURSORS_KEY, &cursors.encode()).await.is_err() || wtx.commit().await.is_err() {
+            return Err(UploadQueueError::Storage);
+        }
+        self.cursors = Some(cursors);
+        Ok(())
+    }
+}
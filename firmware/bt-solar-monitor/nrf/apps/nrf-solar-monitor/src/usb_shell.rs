@@ -0,0 +1,256 @@
+//! The maintenance console on the USB port of the nRF52840, see [`bt_core::prelude::Console`].
+//!
+//! The board enumerates as a CDC ACM serial port, a terminal that opens it (sets DTR) gets
+//! the banner and a locked session. The output of a line is collected and sent once the
+//! command completed, the console writes into RAM and not into the USB endpoint.
+//!
+//! The `bridge` command, or the bridge strap at boot for every terminal, pipes the port to
+//! the modem UART instead, see [`bt_core::prelude::tasks::at::bridge`]. Ctrl-] returns to the console.
+//!
+//! A connected terminal keeps the board awake, the handling of a packet marks the shell as
+//! busy host link and the output waits for the UART frame in progress, see
+//! [`bt_core::prelude::ACTIVITY`].
+
+use core::fmt::Write;
+
+use bt_core::{
+    info,
+    prelude::{
+        ACTIVITY, ANALOG_CALIBRATION, APN_PROFILES, AnalogCalibration, ApnProfiles, AtClientImpl, AtCommandResponse, AtControllerImpl, AtError, AuthProtocol,
+        BATCH_POLICY, BridgeEnd, CHEMISTRY, Chemistry, ConfigStore, Console, ENVELOPE_KEY, FIRMWARE_KEY, Flush, PRIVACY_MODE, PowerHandle, SHELL_PIN,
+        ShellBackend, ShellError, TLS_PIN, UPLOAD_ENCODING, UploadEncoding, active_quirks, flush, tasks::at,
+    },
+    warn,
+};
+use embassy_futures::join::join;
+use embassy_nrf::{
+    Peri, pac, peripherals,
+    usb::{Driver, vbus_detect::HardwareVbusDetect},
+};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{Duration, Instant};
+use embassy_usb::{
+    Builder,
+    class::cdc_acm::{CdcAcmClass, State},
+    driver::EndpointError,
+};
+use embedded_io_async::Read;
+use heapless::String;
+
+use crate::{Irqs, config_store::EkvKeyValueStore};
+
+const MAX_PACKET_SIZE: u16 = 64;
+/// Fits the AT trace dump, the longest output.
+const OUTPUT_SIZE: usize = 4096;
+
+pub struct Backend<'a, F: ekv::flash::Flash, S: Read + embedded_io_async::Write> {
+    db: &'a ekv::Database<F, NoopRawMutex>,
+    config: ConfigStore<EkvKeyValueStore<'a, F>>,
+    at_client: AtClientImpl<'a, AtControllerImpl<S>>,
+    flush: &'a Flush,
+}
+
+impl<'a, F: ekv::flash::Flash, S: Read + embedded_io_async::Write> Backend<'a, F, S> {
+    pub fn new(db: &'a ekv::Database<F, NoopRawMutex>, at_client: AtClientImpl<'a, AtControllerImpl<S>>, flush: &'a Flush) -> Self {
+        Self {
+            db,
+            config: ConfigStore::new(EkvKeyValueStore::new(db)),
+            at_client,
+            flush,
+        }
+    }
+}
+
+impl<F: ekv::flash::Flash, S: Read + embedded_io_async::Write> ShellBackend for Backend<'_, F, S> {
+    async fn write_config(&mut self, out: &mut impl Write) -> core::fmt::Result {
+        writeln!(out, "battery/chemistry {:?}", self.config.get_or(CHEMISTRY, Chemistry::default()).await)?;
+        writeln!(out, "upload/encoding {:?}", self.config.get_or(UPLOAD_ENCODING, UploadEncoding::default()).await)?;
+        match self.config.get(BATCH_POLICY).await.ok().flatten() {
+            Some(policy) => writeln!(out, "upload/batch_policy {:?}", policy)?,
+            None => writeln!(out, "upload/batch_policy default")?,
+        }
+        write!(out, "cloud/apn_profiles")?;
+        for profile in self.config.get_or(APN_PROFILES, ApnProfiles::new()).await {
+            write!(out, " {}:{:?}", profile.apn, profile.pdp_type)?;
+            // the credentials stay in the store
+            if profile.auth.protocol != AuthProtocol::None {
+                write!(out, ":{:?}", profile.auth.protocol)?;
+            }
+        }
+        writeln!(out)?;
+        writeln!(out, "cloud/privacy_mode {}", self.config.get_or(PRIVACY_MODE, false).await)?;
+        write!(out, "sensor/analog")?;
+        for channel in self.config.get_or(ANALOG_CALIBRATION, AnalogCalibration::new()).await.channels() {
+            write!(out, " {}:{}", channel.gain, channel.offset)?;
+        }
+        writeln!(out)?;
+        write!(out, "at/quirks")?;
+        for rule in active_quirks().rules() {
+            write!(out, " {}={}", rule.prefix, rule.quirks.bits())?;
+        }
+        writeln!(out)?;
+        match self.config.get(ENVELOPE_KEY).await.ok().flatten() {
+            Some(key) => writeln!(out, "cloud/envelope_key id {}", key.id)?,
+            None => writeln!(out, "cloud/envelope_key none")?,
+        }
+        let tls_pin = self.config.get(TLS_PIN).await.ok().flatten();
+        writeln!(out, "cloud/tls_pin {}", if tls_pin.is_some() { "set" } else { "none" })?;
+        let firmware_key = self.config.get(FIRMWARE_KEY).await.ok().flatten();
+        writeln!(out, "ota/firmware_key {}", if firmware_key.is_some() { "set" } else { "none" })?;
+        let shell_pin = self.config.get(SHELL_PIN).await.ok().flatten();
+        writeln!(out, "shell/pin {}", if shell_pin.is_some() { "set" } else { "none" })
+    }
+
+    async fn run_at(&mut self, command: &str, timeout: Duration) -> Result<AtCommandResponse, AtError> {
+        at::passthrough(&self.at_client, command, timeout).await
+    }
+
+    async fn trigger_upload(&mut self) {
+        flush(self.flush).await;
+    }
+
+    async fn format_flash(&mut self) -> Result<(), ShellError> {
+        self.db.format().await.map_err(|_| ShellError::Failed)?;
+        info!("Flash formatted => reset");
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+}
+
+/// Runs the USB device and the console until the end, `bridge_strap` bridges every terminal first.
+pub async fn run<F: ekv::flash::Flash, S: Read + embedded_io_async::Write>(
+    usbd: Peri<'_, peripherals::USBD>,
+    mut console: Console<Backend<'_, F, S>>,
+    bridge_strap: bool,
+    power: PowerHandle<'_>,
+) {
+    // the USB peripheral needs the crystal
+    pac::CLOCK.tasks_hfclkstart().write_value(1);
+    while pac::CLOCK.events_hfclkstarted().read() != 1 {}
+
+    let driver = Driver::new(usbd, Irqs, HardwareVbusDetect::new(Irqs));
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("bittailor");
+    config.product = Some("bt-solar-monitor");
+    config.max_power = 100;
+    config.max_packet_size_0 = MAX_PACKET_SIZE as u8;
+
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut msos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+    let mut state = State::new();
+    let mut builder = Builder::new(driver, config, &mut config_descriptor, &mut bos_descriptor, &mut msos_descriptor, &mut control_buf);
+    let mut class = CdcAcmClass::new(&mut builder, &mut state, MAX_PACKET_SIZE);
+    let mut usb = builder.build();
+
+    let shell = async {
+        let mut out = String::<OUTPUT_SIZE>::new();
+        loop {
+            class.wait_connection().await;
+            info!("Shell> terminal connected");
+            let _awake = power.acquire();
+            let _ = session(&mut class, &mut console, &mut out, bridge_strap).await;
+            info!("Shell> terminal disconnected");
+        }
+    };
+    join(usb.run(), shell).await;
+}
+
+async fn session<F: ekv::flash::Flash, S: Read + embedded_io_async::Write>(
+    class: &mut CdcAcmClass<'_, Driver<'_, HardwareVbusDetect>>,
+    console: &mut Console<Backend<'_, F, S>>,
+    out: &mut String<OUTPUT_SIZE>,
+    bridge_strap: bool,
+) -> Result<(), EndpointError> {
+    if bridge_strap {
+        run_bridge(class, console).await?;
+    }
+    out.clear();
+    let _ = console.greet(out);
+    send(class, out).await?;
+    let mut packet = [0u8; MAX_PACKET_SIZE as usize];
+    loop {
+        let len = class.read_packet(&mut packet).await?;
+        let _busy = ACTIVITY.host_busy();
+        console.input(&packet[..len], Instant::now(), out).await;
+        send(class, out).await?;
+        if console.take_bridge_request() {
+            run_bridge(class, console).await?;
+            let _ = console.greet(out);
+            send(class, out).await?;
+        }
+    }
+}
+
+async fn run_bridge<F: ekv::flash::Flash, S: Read + embedded_io_async::Write>(
+    class: &mut CdcAcmClass<'_, Driver<'_, HardwareVbusDetect>>,
+    console: &mut Console<Backend<'_, F, S>>,
+) -> Result<(), EndpointError> {
+    let mut port = CdcPort {
+        class,
+        packet: [0; MAX_PACKET_SIZE as usize],
+        pending: 0..0,
+    };
+    match at::bridge(&console.backend_mut().at_client, &mut port).await {
+        Ok(BridgeEnd::Host) => Err(EndpointError::Disabled),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!("Shell> bridge failed: {:?}", e);
+            Ok(())
+        }
+    }
+}
+
+async fn send(class: &mut CdcAcmClass<'_, Driver<'_, HardwareVbusDetect>>, out: &mut String<OUTPUT_SIZE>) -> Result<(), EndpointError> {
+    for packet in out.as_bytes().chunks(MAX_PACKET_SIZE as usize) {
+        ACTIVITY.wait_uart_idle().await;
+        class.write_packet(packet).await?;
+    }
+    // a full last packet needs a zero length one, otherwise the host waits for more
+    if !out.is_empty() && out.len() % MAX_PACKET_SIZE as usize == 0 {
+        class.write_packet(&[]).await?;
+    }
+    out.clear();
+    Ok(())
+}
+
+/// The CDC port as a byte stream for the bridge.
+struct CdcPort<'a, 'd> {
+    class: &'a mut CdcAcmClass<'d, Driver<'d, HardwareVbusDetect>>,
+    packet: [u8; MAX_PACKET_SIZE as usize],
+    /// Bytes of the last packet not read yet.
+    pending: core::ops::Range<usize>,
+}
+
+impl embedded_io_async::ErrorType for CdcPort<'_, '_> {
+    type Error = embedded_io_async::ErrorKind;
+}
+
+impl Read for CdcPort<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        while self.pending.is_empty() {
+            let len = self
+                .class
+                .read_packet(&mut self.packet)
+                .await
+                .map_err(|_| embedded_io_async::ErrorKind::BrokenPipe)?;
+            self.pending = 0..len;
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.packet[self.pending.start..self.pending.start + len]);
+        self.pending.start += len;
+        Ok(len)
+    }
+}
+
+impl embedded_io_async::Write for CdcPort<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = buf.len().min(MAX_PACKET_SIZE as usize - 1);
+        // shorter than a full packet, the host needs no zero length packet
+        self.class
+            .write_packet(&buf[..len])
+            .await
+            .map_err(|_| embedded_io_async::ErrorKind::BrokenPipe)?;
+        Ok(len)
+    }
+}
@@ -0,0 +1,7 @@
+/* Non-secure part behind the secure partition manager (SPM / TF-M) of the nRF9160. */
+/* nrf-modem keeps its buffers shared with the modem in the first 128K of the RAM. */
+MEMORY
+{
+    FLASH : ORIGIN = 0x00050000, LENGTH = 768K
+    RAM   : ORIGIN = 0x20018000, LENGTH = 160K
+}
@@ -0,0 +1,161 @@
+#![no_std]
+#![no_main]
+
+mod modem;
+
+use bt_core::{
+    info,
+    prelude::{
+        BatteryAlarms, Chemistry, Field, Filter, Flush, Monitored, PowerManager, ReadingFilter, SocConfig, SocEstimator, Timeouts, UartPath, UploadStatus,
+        UploadWindow, Watchdog,
+        tasks::{cloud, upload, ve_direct},
+    },
+    warn,
+};
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_futures::join::*;
+use embassy_nrf::{
+    bind_interrupts,
+    gpio::{Level, Output, OutputDrive},
+    interrupt::{self, InterruptExt, Priority},
+    peripherals,
+    uarte::{self, Uarte},
+};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::Timer;
+use nrf_modem::{ConnectionPreference, SystemMode};
+
+const CONFIG_SOLAR_SENSOR_AVERAGING_DURATION: embassy_time::Duration = embassy_time::Duration::from_secs(5 * 60);
+/// One batch per hour, the modem is offline in between.
+const CONFIG_READINGS_PER_UPLOAD: usize = 12;
+
+bind_interrupts!(struct Irqs {
+    SERIAL0 => uarte::InterruptHandler<peripherals::SERIAL0>;
+});
+
+#[interrupt]
+fn IPC() {
+    nrf_modem::ipc_irq_handler();
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_nrf::init(Default::default());
+    info!("nRF9160 Solar Monitor starting up...");
+    info!("Using backend URL: {}", bt_core::config::SOLAR_BACKEND_BASE_URL);
+    info!("Using averaging duration: {}", bt_core::prelude::FormatableDuration(CONFIG_SOLAR_SENSOR_AVERAGING_DURATION));
+
+    let mut led = Output::new(p.P0_02, Level::Low, OutputDrive::Standard);
+    let green = Output::new(p.P0_03, Level::Low, OutputDrive::Standard);
+
+    interrupt::IPC.set_priority(Priority::P0);
+    interrupt::IPC.unpend();
+    unsafe { interrupt::IPC.enable() };
+    if let Err(e) = nrf_modem::init(SystemMode {
+        lte_support: true,
+        lte_psm_support: true,
+        nbiot_support: false,
+        gnss_support: false,
+        preference: ConnectionPreference::Lte,
+    })
+    .await
+    {
+        warn!("Modem init failed: {:?}", e);
+    }
+
+    // no external flash, the configuration defaults are used
+    let chemistry = Chemistry::default();
+    info!("Battery chemistry {:?}", chemistry);
+
+    let timeouts = Timeouts::default();
+    let supervisor = Watchdog::<3>::new();
+    let power = PowerManager::new();
+    let module = modem::NrfModem::new(timeouts);
+
+    let mut uart_ve_config = uarte::Config::default();
+    uart_ve_config.parity = uarte::Parity::EXCLUDED;
+    uart_ve_config.baudrate = uarte::Baudrate::BAUD19200;
+    let uart_ve = Monitored::new(UartWrapper(Uarte::new(p.SERIAL0, p.P0_10, p.P0_11, Irqs, uart_ve_config)), UartPath::VeDirect, |error| {
+        matches!(error, uarte::Error::Overrun)
+    });
+
+    let mut ve_state = ve_direct::State::<8>::new();
+    let (ve_direct_runner, ve_rx) = ve_direct::new(&mut ve_state, uart_ve, CONFIG_SOLAR_SENSOR_AVERAGING_DURATION, green);
+    let ve_filter = ReadingFilter::new()
+        .with(Field::BatteryCurrent, Filter::Median3)
+        .with(Field::LoadCurrent, Filter::Median3);
+    let ve_direct_runner = ve_direct_runner
+        .with_watchdog(supervisor.register("ve_direct", embassy_time::Duration::from_secs(30)).unwrap())
+        .with_filter(ve_filter);
+    let upload_channel = embassy_sync::channel::Channel::<NoopRawMutex, _, 4>::new();
+    let upload_status = embassy_sync::watch::Watch::<NoopRawMutex, UploadStatus, 1>::new();
+    let battery_voltage = embassy_sync::watch::Watch::<NoopRawMutex, f32, 1>::new();
+    let flush = Flush::new();
+    let solar_runner = upload::new(ve_rx, upload_channel.sender())
+        .with_watchdog(supervisor.register("upload", CONFIG_SOLAR_SENSOR_AVERAGING_DURATION * 2).unwrap())
+        .with_entries_per_upload(CONFIG_READINGS_PER_UPLOAD)
+        .with_flush(&flush)
+        .with_status(upload_status.dyn_sender())
+        .with_battery_voltage(battery_voltage.dyn_sender())
+        .with_battery_alarms(BatteryAlarms::new(chemistry.alarm_preset()))
+        .with_soc_estimator(SocEstimator::new(SocConfig::new(chemistry)))
+        .with_loop_deadline(embassy_time::Duration::from_millis(500));
+    let cloud_runner = cloud::new(module, upload_channel.receiver(), timeouts)
+        .with_watchdog(supervisor.register("cloud", embassy_time::Duration::from_secs(10 * 60)).unwrap())
+        .with_power_manager(power.handle())
+        .with_upload_status(upload_status.dyn_anon_receiver())
+        .with_link_quality(embassy_time::Duration::from_secs(60))
+        .with_battery_policy(chemistry.battery_policy(), battery_voltage.dyn_anon_receiver())
+        .with_power_off(UploadWindow::after_averaging(CONFIG_SOLAR_SENSOR_AVERAGING_DURATION, CONFIG_READINGS_PER_UPLOAD as u32))
+        .with_flush(&flush);
+
+    let mut wdt_config = embassy_nrf::wdt::Config::default();
+    wdt_config.timeout_ticks = 32768 * 10; // 10 seconds
+    wdt_config.action_during_debug_halt = embassy_nrf::wdt::HaltConfig::PAUSE;
+    let (_watchdog, [mut watchdog_handle]) = match embassy_nrf::wdt::Watchdog::try_new(p.WDT, wdt_config) {
+        Ok(x) => x,
+        Err(_) => {
+            info!("Watchdog already active with wrong config, waiting for it to timeout...");
+            loop {
+                Timer::after_millis(250).await;
+            }
+        }
+    };
+
+    let watchdog = supervisor.run(embassy_time::Duration::from_secs(1), || watchdog_handle.pet());
+
+    let blinky = async {
+        loop {
+            led.set_high();
+            Timer::after_millis(100).await;
+            led.set_low();
+            Timer::after_millis(900).await;
+        }
+    };
+
+    join3(watchdog, blinky, join3(ve_direct_runner.run(), cloud_runner.run(), solar_runner.run())).await;
+}
+
+struct UartWrapper<'d>(Uarte<'d>);
+
+impl embedded_io::ErrorType for UartWrapper<'_> {
+    type Error = embassy_nrf::uarte::Error;
+}
+
+impl embedded_io_async::Read for UartWrapper<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.read(buf).await?;
+        Ok(buf.len())
+    }
+}
+
+impl embedded_io_async::Write for UartWrapper<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        embedded_io_async::Write::write(&mut self.0, buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        embedded_io_async::Write::flush(&mut self.0).await
+    }
+}
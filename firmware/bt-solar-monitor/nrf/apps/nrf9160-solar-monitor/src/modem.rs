@@ -0,0 +1,294 @@
+//! The integrated LTE-M modem of the nRF9160 behind [`CellularModem`].
+//!
+//! The modem firmware runs the IP stack, the AT commands and the sockets go through
+//! nrf-modem. It has no HTTP client, the requests are written to a TCP or TLS socket
+//! with the HTTP helper of bt-core.
+
+use core::fmt::Write as _;
+
+use bt_core::{
+    info,
+    prelude::{
+        AtError, AuthProtocol, CellularError, CellularModem, GnssPosition, HttpBodySink, HttpResponse, HttpStatusCode, LinkQuality, NetworkRegistrationState,
+        PdpAuth, PdpType, Rssi, Timeouts, Url,
+        driver::{parse_eps_network_registration, parse_operator_selection, parse_real_time_clock, write_request},
+    },
+    warn,
+};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use embassy_time::{Duration, Timer, with_timeout};
+use heapless::String;
+use nrf_modem::{PeerVerification, TcpStream, TlsStream};
+
+/// Response of the AT commands used here, the longest is `+COPS?`.
+const AT_RESPONSE_SIZE: usize = 128;
+const AT_COMMAND_SIZE: usize = 96;
+/// Security tag with the CA certificate of the backend, provisioned with the nRF Connect tools.
+const SECURITY_TAG: u32 = 42;
+/// Chunks of the GET body streamed to the sink.
+const BODY_CHUNK_SIZE: usize = 256;
+/// CID 0 is the default context of the modem.
+const CONTEXT_ID: u8 = 0;
+
+pub struct NrfModem {
+    timeouts: Timeouts,
+}
+
+impl NrfModem {
+    pub fn new(timeouts: Timeouts) -> Self {
+        Self { timeouts }
+    }
+
+    async fn send(&self, command: &str) -> Result<arrayvec::ArrayString<AT_RESPONSE_SIZE>, CellularError> {
+        match with_timeout(self.timeouts.at_command, nrf_modem::send_at::<AT_RESPONSE_SIZE>(command)).await? {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                warn!("{} failed: {:?}", command, e);
+                Err(CellularError::AtError(AtError::Error))
+            }
+        }
+    }
+
+    /// The first line of the response, the one with the information.
+    async fn query(&self, command: &str) -> Result<String<AT_RESPONSE_SIZE>, CellularError> {
+        let response = self.send(command).await?;
+        let line = response.lines().next().unwrap_or_default();
+        String::try_from(line).map_err(|_| CellularError::BufferOverflow)
+    }
+
+    async fn is_registered(&self) -> Result<bool, CellularError> {
+        let (_, state) = parse_eps_network_registration(&self.query("AT+CEREG?").await?)?;
+        Ok(matches!(state, NetworkRegistrationState::Registered | NetworkRegistrationState::RegisteredRoaming))
+    }
+
+    async fn wait_for_registration(&self, timeout: Duration) -> Result<(), CellularError> {
+        with_timeout(timeout, async {
+            while !self.is_registered().await? {
+                warn!("Not registered to network yet, waiting...");
+                Timer::after_secs(2).await;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn connect(&self, url: &Url<'_>) -> Result<Socket, CellularError> {
+        let socket = if url.https {
+            Socket::Tls(
+                TlsStream::connect(url.host, url.port, PeerVerification::Enabled, &[SECURITY_TAG], None, false)
+                    .await
+                    .map_err(connection_error)?,
+            )
+        } else {
+            let address = nrf_modem::get_host_by_name(url.host).await.map_err(|_| CellularError::HostNotFound)?;
+            Socket::Tcp(
+                TcpStream::connect(core::net::SocketAddr::new(address, url.port))
+                    .await
+                    .map_err(connection_error)?,
+            )
+        };
+        Ok(socket)
+    }
+}
+
+impl CellularModem for NrfModem {
+    /// The modem is part of the SoC, a power cycle is a round trip through the offline mode.
+    async fn power_cycle(&mut self) -> Result<(), CellularError> {
+        self.send("AT+CFUN=0").await?;
+        Timer::after_secs(1).await;
+        Ok(())
+    }
+
+    async fn power_down(&mut self) -> Result<(), CellularError> {
+        self.send("AT+CFUN=0").await?;
+        Ok(())
+    }
+
+    /// No reset line, the same as [`CellularModem::power_cycle`].
+    async fn reset(&mut self) -> Result<(), CellularError> {
+        info!("reset via offline mode ...");
+        self.power_cycle().await
+    }
+
+    async fn startup_network(&mut self, apn: &str, pdp_type: PdpType, auth: &PdpAuth) -> Result<(), CellularError> {
+        // the context can only be changed while offline
+        self.send("AT+CFUN=0").await?;
+        let mut command = String::<AT_COMMAND_SIZE>::new();
+        write!(command, "AT+CGDCONT={},\"{}\",\"{}\"", CONTEXT_ID, pdp_type.as_str(), apn).map_err(|_| CellularError::BufferOverflow)?;
+        self.send(&command).await?;
+        // the 3GPP order, the username before the password
+        command.clear();
+        match auth.protocol {
+            AuthProtocol::None => write!(command, "AT+CGAUTH={},0", CONTEXT_ID),
+            protocol => write!(command, "AT+CGAUTH={},{},\"{}\",\"{}\"", CONTEXT_ID, protocol as u8, auth.username, auth.password),
+        }
+        .map_err(|_| CellularError::BufferOverflow)?;
+        self.send(&command).await?;
+        self.send("AT+CFUN=1").await?;
+        self.wait_for_registration(self.timeouts.network_registration).await?;
+        let _rtc = self.query_real_time_clock().await?;
+        Ok(())
+    }
+
+    async fn query_real_time_clock(&self) -> Result<NaiveDateTime, CellularError> {
+        Ok(parse_real_time_clock(&self.query("AT+CCLK?").await?)?)
+    }
+
+    async fn sync_network_time(&mut self, _server: &str) -> Result<NaiveDateTime, CellularError> {
+        warn!("NTP sync not supported by the nRF91 modem");
+        Err(CellularError::Unsupported)
+    }
+
+    async fn set_real_time_clock(&self, utc: &NaiveDateTime) -> Result<(), CellularError> {
+        if !(2000..2100).contains(&utc.year()) {
+            return Err(CellularError::Encoding());
+        }
+        let mut command = String::<AT_COMMAND_SIZE>::new();
+        write!(
+            command,
+            "AT+CCLK=\"{:02}/{:02}/{:02},{:02}:{:02}:{:02}+00\"",
+            utc.year() - 2000,
+            utc.month(),
+            utc.day(),
+            utc.hour(),
+            utc.minute(),
+            utc.second()
+        )
+        .map_err(|_| CellularError::BufferOverflow)?;
+        self.send(&command).await?;
+        Ok(())
+    }
+
+    /// The RSRP of `AT+CESQ`, LTE-M has no RSSI.
+    async fn query_signal_quality(&self) -> Result<Rssi, CellularError> {
+        // +CESQ: <rxlev>,<ber>,<rscp>,<ecno>,<rsrq>,<rsrp>
+        let line = self.query("AT+CESQ").await?;
+        let rsrp = line
+            .strip_prefix("+CESQ: ")
+            .and_then(|fields| fields.split(',').nth(5))
+            .and_then(|rsrp| rsrp.trim().parse::<i32>().ok())
+            .ok_or(CellularError::Encoding())?;
+        match rsrp {
+            0..=97 => Ok(Rssi::from_dbm(rsrp - 141)),
+            // 255: not known or not detectable, like +CSQ: 99
+            _ => Err(CellularError::AtError(AtError::Error)),
+        }
+    }
+
+    async fn query_link_quality(&self) -> Result<LinkQuality, CellularError> {
+        let rssi = self.query_signal_quality().await?;
+        let (_, registration) = parse_eps_network_registration(&self.query("AT+CEREG?").await?)?;
+        let operator = parse_operator_selection(&self.query("AT+COPS?").await?)?.operator.unwrap_or_default();
+        Ok(LinkQuality {
+            rssi,
+            // not reported for LTE
+            ber: 99,
+            registration,
+            operator,
+        })
+    }
+
+    async fn query_position(&mut self) -> Result<Option<GnssPosition>, CellularError> {
+        warn!("GNSS not supported by the nRF91 driver");
+        Err(CellularError::Unsupported)
+    }
+
+    /// Requests PSM, the modem enters it by itself when the sockets are idle.
+    async fn sleep(&mut self) -> Result<(), CellularError> {
+        self.send("AT+CPSMS=1").await?;
+        Ok(())
+    }
+
+    async fn wake_up(&mut self) -> Result<(), CellularError> {
+        self.wait_for_registration(self.timeouts.modem_wake_up).await
+    }
+
+    async fn http_post(&mut self, url: &str, headers: &[(&str, &str)], body: &[u8], response: &mut [u8]) -> Result<(HttpStatusCode, usize), CellularError> {
+        let url = Url::parse(url).ok_or(CellularError::Encoding())?;
+        let mut socket = self.connect(&url).await?;
+        let result = with_timeout(self.timeouts.http_read, async {
+            write_request(&mut socket, "POST", &url, headers, Some(body)).await?;
+            let mut http_response = HttpResponse::read(&mut socket).await?;
+            let len = http_response.read_to_end(response).await?;
+            Ok((http_response.status(), len))
+        })
+        .await;
+        socket.close().await;
+        result?
+    }
+
+    async fn http_get(&mut self, url: &str, headers: &[(&str, &str)], sink: &mut impl HttpBodySink) -> Result<(HttpStatusCode, usize), CellularError> {
+        let url = Url::parse(url).ok_or(CellularError::Encoding())?;
+        let mut socket = self.connect(&url).await?;
+        let result = with_timeout(self.timeouts.http_read, async {
+            write_request(&mut socket, "GET", &url, headers, None).await?;
+            let mut http_response = HttpResponse::read(&mut socket).await?;
+            let status = http_response.status();
+            if !status.is_ok() {
+                return Ok((status, 0));
+            }
+            let mut chunk = [0u8; BODY_CHUNK_SIZE];
+            let mut len = 0;
+            loop {
+                match http_response.read_body(&mut chunk).await? {
+                    0 => return Ok((status, len)),
+                    n => {
+                        sink.write(&chunk[..n]).await?;
+                        len += n;
+                    }
+                }
+            }
+        })
+        .await;
+        socket.close().await;
+        result?
+    }
+}
+
+fn connection_error(e: nrf_modem::Error) -> CellularError {
+    warn!("socket failed: {:?}", e);
+    CellularError::Connection
+}
+
+/// An nrf-modem socket as `embedded-io-async` stream.
+enum Socket {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+}
+
+impl Socket {
+    async fn close(self) {
+        let result = match self {
+            Socket::Tcp(stream) => stream.deactivate().await,
+            Socket::Tls(stream) => stream.deactivate().await,
+        };
+        if let Err(e) = result {
+            warn!("closing the socket failed: {:?}", e);
+        }
+    }
+}
+
+impl embedded_io_async::ErrorType for Socket {
+    type Error = CellularError;
+}
+
+impl embedded_io_async::Read for Socket {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let received = match self {
+            Socket::Tcp(stream) => stream.receive(buf).await,
+            Socket::Tls(stream) => stream.receive(buf).await,
+        };
+        received.map(|data| data.len()).map_err(connection_error)
+    }
+}
+
+impl embedded_io_async::Write for Socket {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf).await,
+            Socket::Tls(stream) => stream.write(buf).await,
+        }
+        .map_err(connection_error)?;
+        Ok(buf.len())
+    }
+}
@@ -2,13 +2,13 @@
 #![no_main]
 
 use bt_core::{info, unwrap};
-use bt_nrf::driver::qspi_flash::QspiFlashDriver;
+use bt_nrf::driver::qspi_flash::{EkvFlash, FLASH_SIZE, QspiFlashDriver};
 #[cfg(feature = "defmt")]
 use defmt_rtt as _;
 use embassy_executor::Spawner;
 use embassy_nrf::rng::Rng;
 use embassy_nrf::{bind_interrupts, pac, peripherals, qspi, rng};
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
 use panic_probe as _;
 use rand_core::RngCore;
 
@@ -77,7 +77,8 @@ async fn main(_spawner: Spawner) -> ! {
         info!("enabled quad in status");
     }
 
-    let mut f = QspiFlashDriver::new(q);
+    let q = Mutex::<NoopRawMutex, _>::new(QspiFlashDriver::new(q));
+    let mut f = EkvFlash::new(&q, 0, FLASH_SIZE as u32);
 
     let mut config = ekv::Config::default();
     config.random_seed = random_seed;
@@ -1,9 +1,7 @@
 #![no_std]
 #![no_main]
 
-use bt_core::at::AtController;
-use bt_core::net::cellular::CellularError;
-use bt_core::net::cellular::sim_com_a67::SimComCellularModule;
+use bt_core::prelude::{AtController, CellularError, NetworkRegistrationState, PdpType, SimComCellularModule, SleepMode, Timeouts, tasks::at};
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_futures::join::*;
@@ -47,9 +45,9 @@ async fn main(_spawner: Spawner) {
         &mut uart_lte_tx_buffer,
     );
 
-    let mut at_state = bt_core::at::State::new();
-    let (at_runner, at_client) = bt_core::at::new(&mut at_state, uart_lte);
-    let mut lte = SimComCellularModule::new(at_client, pwrkey, reset);
+    let mut at_state = at::State::new();
+    let (at_runner, at_client) = at::new(&mut at_state, uart_lte, Timeouts::default());
+    let mut lte = SimComCellularModule::new(at_client, pwrkey, reset, Timeouts::default());
 
     let sequence = async {
         match lte_sequence(&mut lte).await {
@@ -71,14 +69,14 @@ async fn main(_spawner: Spawner) {
     join3(at_runner.run(), blinky, sequence).await;
 }
 
-async fn lte_sequence(lte: &mut bt_core::net::cellular::sim_com_a67::SimComCellularModule<'_, impl OutputPin, impl AtController>) -> Result<(), CellularError> {
+async fn lte_sequence(lte: &mut SimComCellularModule<'_, impl OutputPin, impl AtController>) -> Result<(), CellularError> {
     info!("start LTE sequence");
 
     lte.power_cycle().await?;
 
-    lte.set_apn("gprs.swisscom.ch").await?;
+    lte.set_apn("gprs.swisscom.ch", PdpType::Ip).await?;
 
-    while lte.read_network_registration().await?.1 != bt_core::at::network::NetworkRegistrationState::Registered {
+    while lte.read_network_registration().await?.1 != NetworkRegistrationState::Registered {
         warn!("Not registered to network yet, waiting...");
         Timer::after_secs(2).await;
         info!("... retrying ...");
@@ -124,14 +122,14 @@ async fn lte_sequence(lte: &mut bt_core::net::cellular::sim_com_a67::SimComCellu
         Timer::after_secs(10).await;
 
         info!("Set sleep mode");
-        lte.set_sleep_mode(bt_core::at::serial_interface::SleepMode::RxSleep).await?;
+        lte.set_sleep_mode(SleepMode::RxSleep).await?;
         info!("... wait a bit in sleep mode ...");
         Timer::after_secs(30).await;
-        while !lte.is_alive().await {
+        while lte.is_alive().await.is_err() {
             error!("LTE module not alive, retrying...");
         }
         info!("check network registration again");
-        while lte.read_network_registration().await?.1 != bt_core::at::network::NetworkRegistrationState::Registered {
+        while lte.read_network_registration().await?.1 != NetworkRegistrationState::Registered {
             warn!("Not registered to network yet, waiting...");
             Timer::after_secs(2).await;
             info!("... retrying ...");
@@ -41,8 +41,8 @@ async fn main(_spawner: Spawner) {
         &mut uart_ve_rx_buffer,
         &mut uart_ve_tx_buffer,
     );
-    let mut ve_state = bt_core::sensor::ve_direct::State::<8>::default();
-    let (ve_direct_runner, ve_rx) = bt_core::sensor::ve_direct::new(&mut ve_state, uart_ve, embassy_time::Duration::from_secs(10), green);
+    let mut ve_state = bt_core::prelude::tasks::ve_direct::State::<8>::default();
+    let (ve_direct_runner, ve_rx) = bt_core::prelude::tasks::ve_direct::new(&mut ve_state, uart_ve, embassy_time::Duration::from_secs(10), green);
 
     let blinky = async {
         loop {
@@ -1 +1,3 @@
+pub mod pulse;
 pub mod qspi_flash;
+pub mod saadc;
@@ -0,0 +1,25 @@
+//! A GPIO pin as the [`PulseInput`] of `bt_core::prelude::tasks::pulse`.
+//!
+//! The edge detection runs on the GPIOTE port event, the CPU sleeps between the pulses.
+//! The pin is pulled up, the pump relay or the reed contact of the flow meter pulls it to
+//! ground, every falling edge is a pulse.
+
+use bt_core::prelude::PulseInput;
+use embassy_nrf::gpio::Input;
+
+pub struct PulsePin<'d> {
+    input: Input<'d>,
+}
+
+impl<'d> PulsePin<'d> {
+    /// `input` configured with the pull-up, unless the source drives the pin.
+    pub fn new(input: Input<'d>) -> Self {
+        Self { input }
+    }
+}
+
+impl PulseInput for PulsePin<'_> {
+    async fn wait_pulse(&mut self) {
+        self.input.wait_for_falling_edge().await;
+    }
+}
@@ -1,20 +1,32 @@
 //! QSPI Flash driver for MX25L3233F (32Mbit/4MB) flash chip
 //!
-//! This driver implements the `ekv::flash::Flash` trait for use with the ekv
-//! embedded key-value database. It handles alignment requirements for the QSPI
-//! peripheral by automatically copying unaligned buffers to an aligned temporary buffer.
+//! This driver implements the async `NorFlash` trait of embedded-storage. It handles
+//! alignment requirements for the QSPI peripheral by automatically copying unaligned
+//! buffers to an aligned temporary buffer. The flash is shared behind a mutex: the ekv
+//! embedded key-value database uses the pages below [`DFU_OFFSET`] through [`EkvFlash`],
+//! the DFU slot of the bootloader the rest, see the `memory.x` of the apps.
+//!
+//! A sector erase takes up to 200 ms and a compaction of ekv erases many in a row. The
+//! driver waits for the flash with a timer between the status polls, so the executor
+//! keeps running the other tasks, and feeds its maintenance watchdog at every poll, see
+//! [`QspiFlashDriver::with_watchdog`].
 
 use core::convert::Infallible;
 
-use bt_core::info;
+use bt_core::{debug, info, prelude::WatchdogHandle};
 use ekv::flash::PageID;
+use embassy_embedded_hal::flash::partition::Partition;
 use embassy_nrf::qspi;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Timer};
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
 
 // MX25L3233F => https://www.macronix.com/Lists/Datasheet/Attachments/8933/MX25L3233F,%203V,%2032Mb,%20v1.7.pdf
 // 32 Mbit = 4 MB total, organized as 4KB sectors
-const FLASH_SIZE: usize = 4 * 1024 * 1024; // 4 MB
+pub const FLASH_SIZE: usize = 4 * 1024 * 1024; // 4 MB
 const PAGE_SIZE: usize = 4 * 1024; // 4 KB (sector size, minimum erase unit)
-const PAGE_COUNT: usize = FLASH_SIZE / PAGE_SIZE; // 1024 pages
+/// Start of the DFU slot of the bootloader, the last MB, `__bootloader_dfu_start` in the `memory.x` of the apps
+pub const DFU_OFFSET: u32 = 3 * 1024 * 1024;
 const ALIGN: usize = 4; // QSPI requires 4-byte alignment
 const PROGRAM_SIZE: usize = 256; // MX25L3233F page program size
 
@@ -22,6 +34,11 @@ const PROGRAM_SIZE: usize = 256; // MX25L3233F page program size
 const CMD_READ_STATUS: u8 = 0x05;
 const CMD_WRITE_ENABLE: u8 = 0x06;
 
+/// A page program takes 0.3 ms typically, a sector erase 40 ms.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_micros(250);
+/// Erases between two progress reports.
+const ERASE_PROGRESS_PAGES: u32 = 64;
+
 /// Aligned buffer wrapper for QSPI operations
 #[repr(align(4))]
 struct AlignedBuffer {
@@ -30,13 +47,17 @@ struct AlignedBuffer {
 
 /// QSPI Flash driver for MX25L3233F
 ///
-/// Implements the `ekv::flash::Flash` trait for the MX25L3233F flash chip.
+/// Implements the async `NorFlash` trait for the MX25L3233F flash chip.
 /// Automatically handles alignment requirements by copying data to/from an
-/// aligned buffer when necessary.
+/// aligned buffer when necessary, writes are padded with 0xFF to whole words.
 pub struct QspiFlashDriver<'a> {
     qspi: qspi::Qspi<'a>,
     /// Aligned buffer for QSPI operations when ekv provides unaligned buffers
     aligned_buffer: AlignedBuffer,
+    /// Supervises the erases and writes, see [`bt_core::prelude::Watchdog`]
+    watchdog: WatchdogHandle<'a>,
+    /// Pages erased since the start, for the progress reports
+    erased: u32,
 }
 
 impl<'a> QspiFlashDriver<'a> {
@@ -45,28 +66,45 @@ impl<'a> QspiFlashDriver<'a> {
         Self {
             qspi,
             aligned_buffer: AlignedBuffer { data: [0u8; 512] },
+            watchdog: WatchdogHandle::default(),
+            erased: 0,
         }
     }
 
+    /// Supervise the erases and writes with a handle of `Watchdog::register_maintenance`
+    pub fn with_watchdog(mut self, watchdog: WatchdogHandle<'a>) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
     /// Check if address and buffer are properly aligned for QSPI
     fn is_aligned(addr: u32, buffer: &[u8]) -> bool {
         let ptr_addr = buffer.as_ptr() as usize;
         addr.is_multiple_of(ALIGN as u32) && ptr_addr.is_multiple_of(ALIGN) && buffer.len().is_multiple_of(ALIGN)
     }
 
+    fn check_bounds(addr: u32, len: usize) -> Result<(), NorFlashErrorKind> {
+        if addr as usize + len > FLASH_SIZE {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        Ok(())
+    }
+
     /// Round up size to alignment boundary
     fn align_up(size: usize) -> usize {
         size.div_ceil(ALIGN) * ALIGN
     }
 
-    /// Wait for the flash to be ready (WIP bit cleared)
+    /// Wait for the flash to be ready (WIP bit cleared), every poll is a pet point of the watchdog
     async fn wait_ready(&mut self) -> Result<(), Infallible> {
         loop {
             let mut status = [0u8; 1];
             self.qspi.custom_instruction(CMD_READ_STATUS, &[], &mut status).await.unwrap();
+            self.watchdog.feed();
             if status[0] & 0x01 == 0 {
                 break;
             }
+            Timer::after(STATUS_POLL_INTERVAL).await;
         }
         Ok(())
     }
@@ -112,63 +150,118 @@ impl<'a> QspiFlashDriver<'a> {
     }
 }
 
-impl<'a> ekv::flash::Flash for QspiFlashDriver<'a> {
-    type Error = Infallible;
-
-    fn page_count(&self) -> usize {
-        PAGE_COUNT
-    }
+impl ErrorType for QspiFlashDriver<'_> {
+    type Error = NorFlashErrorKind;
+}
 
-    async fn erase(&mut self, page_id: PageID) -> Result<(), Self::Error> {
-        let addr = (page_id.index() * PAGE_SIZE) as u32;
+impl ReadNorFlash for QspiFlashDriver<'_> {
+    const READ_SIZE: usize = ALIGN;
 
-        info!("Erasing page {} at addr 0x{:x}", page_id.index(), addr);
+    async fn read(&mut self, addr: u32, data: &mut [u8]) -> Result<(), Self::Error> {
+        Self::check_bounds(addr, data.len())?;
+        self.wait_ready().await.unwrap();
 
-        self.wait_ready().await?;
-        self.write_enable().await?;
-        self.qspi.erase(addr).await.unwrap();
-        self.wait_ready().await?;
+        if Self::is_aligned(addr, data) {
+            self.qspi.read(addr, data).await.unwrap();
+        } else {
+            self.read_unaligned(addr, data).await.unwrap();
+        }
 
         Ok(())
     }
 
-    async fn read(&mut self, page_id: PageID, offset: usize, data: &mut [u8]) -> Result<(), Self::Error> {
-        let addr = (page_id.index() * PAGE_SIZE + offset) as u32;
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
 
-        self.wait_ready().await?;
+impl NorFlash for QspiFlashDriver<'_> {
+    const WRITE_SIZE: usize = ALIGN;
+    const ERASE_SIZE: usize = PAGE_SIZE;
 
-        if Self::is_aligned(addr, data) {
-            self.qspi.read(addr, data).await.unwrap();
-        } else {
-            self.read_unaligned(addr, data).await?;
-        }
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        Self::check_bounds(from, (to - from) as usize)?;
+        let _busy = self.watchdog.busy();
+
+        for addr in (from..to).step_by(PAGE_SIZE) {
+            debug!("Erasing sector at addr 0x{:x}", addr);
 
+            self.wait_ready().await.unwrap();
+            self.write_enable().await.unwrap();
+            self.qspi.erase(addr).await.unwrap();
+            self.wait_ready().await.unwrap();
+
+            self.erased = self.erased.wrapping_add(1);
+            if self.erased.is_multiple_of(ERASE_PROGRESS_PAGES) {
+                info!("Flash> {} pages erased", self.erased);
+            }
+        }
         Ok(())
     }
 
-    async fn write(&mut self, page_id: PageID, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
-        let addr = (page_id.index() * PAGE_SIZE + offset) as u32;
+    async fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error> {
+        Self::check_bounds(addr, data.len())?;
         let len = data.len();
         let mut offset_in_data = 0;
+        let _busy = self.watchdog.busy();
 
         while offset_in_data < len {
             let chunk_size = (len - offset_in_data).min(PROGRAM_SIZE);
             let chunk_addr = addr + offset_in_data as u32;
             let chunk = &data[offset_in_data..offset_in_data + chunk_size];
 
-            self.wait_ready().await?;
-            self.write_enable().await?;
+            self.wait_ready().await.unwrap();
+            self.write_enable().await.unwrap();
 
             if Self::is_aligned(chunk_addr, chunk) {
                 self.qspi.write(chunk_addr, chunk).await.unwrap();
             } else {
-                self.write_unaligned(chunk_addr, chunk).await?;
+                self.write_unaligned(chunk_addr, chunk).await.unwrap();
             }
 
             offset_in_data += chunk_size;
         }
 
-        self.wait_ready().await?;
+        self.wait_ready().await.unwrap();
+        Ok(())
+    }
+}
+
+/// The ekv database on a partition of the shared [`QspiFlashDriver`], the pages below [`DFU_OFFSET`] on the apps
+pub struct EkvFlash<'a> {
+    partition: Partition<'a, NoopRawMutex, QspiFlashDriver<'a>>,
+}
+
+impl<'a> EkvFlash<'a> {
+    pub fn new(flash: &'a Mutex<NoopRawMutex, QspiFlashDriver<'a>>, offset: u32, size: u32) -> Self {
+        Self {
+            partition: Partition::new(flash, offset, size),
+        }
+    }
+}
+
+impl ekv::flash::Flash for EkvFlash<'_> {
+    type Error = Infallible;
+
+    fn page_count(&self) -> usize {
+        self.partition.size() as usize / PAGE_SIZE
+    }
+
+    async fn erase(&mut self, page_id: PageID) -> Result<(), Self::Error> {
+        let addr = (page_id.index() * PAGE_SIZE) as u32;
+        self.partition.erase(addr, addr + PAGE_SIZE as u32).await.unwrap();
+        Ok(())
+    }
+
+    async fn read(&mut self, page_id: PageID, offset: usize, data: &mut [u8]) -> Result<(), Self::Error> {
+        let addr = (page_id.index() * PAGE_SIZE + offset) as u32;
+        self.partition.read(addr, data).await.unwrap();
+        Ok(())
+    }
+
+    async fn write(&mut self, page_id: PageID, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+        let addr = (page_id.index() * PAGE_SIZE + offset) as u32;
+        self.partition.write(addr, data).await.unwrap();
         Ok(())
     }
 }
@@ -0,0 +1,42 @@
+//! The SAADC of the nRF52840 as the [`AnalogInput`] of `bt_core::prelude::tasks::analog`.
+//!
+//! The channels keep the default configuration of embassy-nrf: gain 1/6 with the internal
+//! 0.6 V reference and 12 bit resolution, 3.6 V full scale. The pin voltage must stay
+//! below VDD, a divider or an amplifier scales the measured source, its factor goes into
+//! the calibration of the channel.
+
+use bt_core::prelude::AnalogInput;
+use embassy_nrf::saadc::Saadc;
+
+/// Full scale over the 12 bit range.
+const VOLTS_PER_COUNT: f32 = 3.6 / 4096.0;
+
+pub struct SaadcInput<'d, const N: usize> {
+    saadc: Saadc<'d, N>,
+}
+
+impl<'d, const N: usize> SaadcInput<'d, N> {
+    pub fn new(saadc: Saadc<'d, N>) -> Self {
+        Self { saadc }
+    }
+
+    /// Calibrates the offset of the ADC, once at startup and after a large temperature change.
+    pub async fn calibrate(&self) {
+        self.saadc.calibrate().await;
+    }
+}
+
+impl<const N: usize> AnalogInput for SaadcInput<'_, N> {
+    fn channels(&self) -> usize {
+        N
+    }
+
+    async fn sample(&mut self, volts: &mut [f32]) {
+        let mut counts = [0i16; N];
+        self.saadc.sample(&mut counts).await;
+        for (volts, counts) in volts.iter_mut().zip(counts) {
+            // single ended samples go slightly negative around 0 V
+            *volts = counts.max(0) as f32 * VOLTS_PER_COUNT;
+        }
+    }
+}
@@ -8,19 +8,67 @@
 use DateTimeImmutable;
 use Bt\Solar\SystemEvent;
 use App\Models\SolarReading;
+use Google\Protobuf\DescriptorPool;
+use Google\Protobuf\Internal\GPBLabel;
+use Google\Protobuf\Internal\GPBType;
 use Illuminate\Http\Request;
+use Illuminate\Support\Facades\Cache;
 use Illuminate\Support\Facades\Log;
+use Illuminate\Support\Facades\Storage;
 
 class SolarReadingController extends Controller
 {
-    public function reading(Request $request)
+    // newest SCHEMA_VERSION of the firmware (bt-core/src/schema.rs) this backend decodes
+    const ACCEPTED_PROTO_VERSION = 17;
+
+    // the body of the request, opened if the device sealed it (bt-core/src/solar_monitor/envelope.rs):
+    // nonce (24 bytes), ciphertext, tag (16 bytes) of XChaCha20-Poly1305 with the key of the X-Key-Id,
+    // the keys are set hex encoded as SOLAR_ENVELOPE_KEY_<id>, null if it can not be opened
+    private function content(Request $request): ?string
     {
         $content = $request->getContent();
-        $upload = new Upload();
-        $upload->mergeFromString($content);
+        $keyId = $request->header('X-Key-Id');
+        if ($keyId === null) {
+            return $content;
+        }
+        $key = env('SOLAR_ENVELOPE_KEY_' . (int) $keyId);
+        if ($key === null || strlen($content) < SODIUM_CRYPTO_AEAD_XCHACHA20POLY1305_IETF_NPUBBYTES + SODIUM_CRYPTO_AEAD_XCHACHA20POLY1305_IETF_ABYTES) {
+            Log::warning("Sealed body not opened", ['key_id' => $keyId, 'size' => strlen($content)]);
+            return null;
+        }
+        $nonce = substr($content, 0, SODIUM_CRYPTO_AEAD_XCHACHA20POLY1305_IETF_NPUBBYTES);
+        $ciphertext = substr($content, SODIUM_CRYPTO_AEAD_XCHACHA20POLY1305_IETF_NPUBBYTES);
+        $opened = sodium_crypto_aead_xchacha20poly1305_ietf_decrypt($ciphertext, '', $nonce, hex2bin($key));
+        if ($opened === false) {
+            Log::warning("Sealed body rejected", ['key_id' => $keyId]);
+            return null;
+        }
+        return $opened;
+    }
+
+    public function reading(Request $request)
+    {
+        $content = $this->content($request);
+        if ($content === null) {
+            return response('envelope not opened', 400);
+        }
+        // upload/encoding of the device, CBOR maps keyed by the field numbers (bt-core/src/solar_monitor/cbor.rs)
+        if ($request->header('Content-Type') === 'application/cbor') {
+            $offset = 0;
+            $fields = $this->cbor($content, $offset);
+            if (!is_array($fields) || $offset !== strlen($content)) {
+                return response('invalid CBOR', 400);
+            }
+            $upload = $this->messageFromCbor(Upload::class, $fields);
+        } else {
+            $upload = new Upload();
+            $upload->mergeFromString($content);
+        }
         $n = $upload->getEntries()->count();
         Log::info("Upload received ", ['entries' => $n]);
         $startTimestamp = $upload->getStartTimestamp();
+        // timed with the last known time after a reset, the batch is behind by the time the device was off
+        $timeApproximate = $upload->getApproximateTime();
         foreach ($upload->getEntries() as $entry) {
             $reading = $entry->getReading();
             $timestamp = Carbon::createFromTimestampUTC($startTimestamp + $entry->getOffsetInSeconds());
@@ -29,17 +77,121 @@ public function reading(Request $request)
             $solarReading->battery_voltage = $reading->getBatteryVoltage() / $factor;
             $solarReading->battery_current = $reading->getBatteryCurrent() / $factor;
             $solarReading->panel_voltage = $reading->getPanelVoltage() / $factor;
-            $solarReading->panel_power = $reading->getPanelPower();
+            // the deciwatt field is only sent by units configured for the finer resolution
+            $solarReading->panel_power = $reading->hasPanelPowerDeciwatt() ? $reading->getPanelPowerDeciwatt() / 10.0 : $reading->getPanelPower();
             $solarReading->load_current = $reading->getLoadCurrent()  / $factor;
+            // min, max and stddev of an average of several readings
+            $this->spread($solarReading, 'battery_voltage', $reading->getBatteryVoltageSpread(), $factor);
+            $this->spread($solarReading, 'battery_current', $reading->getBatteryCurrentSpread(), $factor);
+            $this->spread($solarReading, 'panel_voltage', $reading->getPanelVoltageSpread(), $factor);
+            $this->spread($solarReading, 'panel_power', $reading->getPanelPowerSpread(), 1.0);
+            $this->spread($solarReading, 'load_current', $reading->getLoadCurrentSpread(), $factor);
+            // the sensors beside the charger, c°C, mV or mA by the calibration of the channel and a count
+            $solarReading->battery_temperature = $reading->hasBatteryTemperature() ? $reading->getBatteryTemperature() / 100.0 : null;
+            $solarReading->auxiliary_1 = $reading->hasAuxiliary1() ? $reading->getAuxiliary1() / $factor : null;
+            $solarReading->auxiliary_2 = $reading->hasAuxiliary2() ? $reading->getAuxiliary2() / $factor : null;
+            $solarReading->pulse_count = $reading->hasPulseCount() ? $reading->getPulseCount() : null;
             $solarReading->recorded_at = $timestamp;
+            $solarReading->time_approximate = $timeApproximate;
             $solarReading->save();
         }
-        return response('', 200)->withHeaders([]);
+        return response()->json(['accepted_proto_version' => self::ACCEPTED_PROTO_VERSION]);
+    }
+
+    // the <column>_min, _max and _stddev of a Spread, left null without one
+    private function spread(SolarReading $solarReading, string $column, $spread, float $factor): void
+    {
+        if ($spread === null) {
+            return;
+        }
+        $solarReading->{"{$column}_min"} = $spread->getMin() / $factor;
+        $solarReading->{"{$column}_max"} = $spread->getMax() / $factor;
+        $solarReading->{"{$column}_stddev"} = $spread->hasStddev() ? $spread->getStddev() / $factor : null;
+    }
+
+    // decodes the CBOR value at $offset, the integers, booleans, arrays and maps the device sends, null for anything else
+    private function cbor(string $data, int &$offset)
+    {
+        if ($offset >= strlen($data)) {
+            return null;
+        }
+        $initial = ord($data[$offset++]);
+        $major = $initial >> 5;
+        $info = $initial & 0x1f;
+        if ($major === 7) {
+            return match ($info) {
+                20 => false,
+                21 => true,
+                default => null,
+            };
+        }
+        if ($info < 24) {
+            $value = $info;
+        } elseif ($info <= 27) {
+            $size = 1 << ($info - 24);
+            if ($offset + $size > strlen($data)) {
+                return null;
+            }
+            $value = 0;
+            foreach (str_split(substr($data, $offset, $size)) as $byte) {
+                $value = ($value << 8) | ord($byte);
+            }
+            $offset += $size;
+        } else {
+            return null;
+        }
+        switch ($major) {
+            case 0:
+                return $value;
+            case 1:
+                return -1 - $value;
+            case 4:
+                $items = [];
+                for ($i = 0; $i < $value; $i++) {
+                    $items[] = $this->cbor($data, $offset);
+                }
+                return $items;
+            case 5:
+                $items = [];
+                for ($i = 0; $i < $value; $i++) {
+                    $key = $this->cbor($data, $offset);
+                    $items[$key] = $this->cbor($data, $offset);
+                }
+                return $items;
+            default:
+                return null;
+        }
+    }
+
+    // the proto message $class from a CBOR map keyed by its field numbers, unknown numbers are skipped
+    private function messageFromCbor(string $class, array $fields)
+    {
+        $message = new $class();
+        $descriptor = DescriptorPool::getGeneratedPool()->getDescriptorByClassName($class);
+        for ($i = 0; $i < $descriptor->getFieldCount(); $i++) {
+            $field = $descriptor->getField($i);
+            if (!array_key_exists($field->getNumber(), $fields)) {
+                continue;
+            }
+            $value = $fields[$field->getNumber()];
+            if ($field->getType() === GPBType::MESSAGE) {
+                $messageClass = $field->getMessageType()->getClass();
+                $value = $field->getLabel() === GPBLabel::REPEATED
+                    ? array_map(fn ($item) => $this->messageFromCbor($messageClass, $item), $value)
+                    : $this->messageFromCbor($messageClass, $value);
+            }
+            $setter = 'set' . str_replace('_', '', ucwords($field->getName(), '_'));
+            $message->$setter($value);
+        }
+        return $message;
     }
 
     public function event(Request $request)
     {
-        $content = $request->getContent();
+        $content = $this->content($request);
+        if ($content === null) {
+            return response('envelope not opened', 400);
+        }
         $event = new SystemEvent();
         $event->mergeFromString($content);
         $json = $event->serializeToJsonString();
@@ -48,6 +200,105 @@ public function event(Request $request)
         $dbEvent->timestamp = Carbon::createFromTimestampUTC($event->getTimestamp());
         $dbEvent->event = $event;
         $dbEvent->save();
-        return response('', 200)->withHeaders([]); 
+        return response()->json(['accepted_proto_version' => self::ACCEPTED_PROTO_VERSION]);
+    }
+
+    // a SystemEvent with the health counters, deferred by the device until a reading upload woke the modem
+    public function metrics(Request $request)
+    {
+        $content = $this->content($request);
+        if ($content === null) {
+            return response('envelope not opened', 400);
+        }
+        $event = new SystemEvent();
+        $event->mergeFromString($content);
+        Log::info("Metrics received ", ['event' => $event->serializeToJsonString()]);
+        $dbEvent = new Event;
+        $dbEvent->timestamp = Carbon::createFromTimestampUTC($event->getTimestamp());
+        $dbEvent->event = $event;
+        $dbEvent->save();
+        return response()->json(['accepted_proto_version' => self::ACCEPTED_PROTO_VERSION]);
+    }
+
+    // {"id":"<imei>","up":<uptime s>,"fw":"<version>","q":<queued batches>}, the last one per device is kept
+    public function heartbeat(Request $request)
+    {
+        $heartbeat = $request->json()->all();
+        $id = $heartbeat['id'] ?? '';
+        Log::info("Heartbeat received ", ['heartbeat' => $heartbeat]);
+        Cache::put("heartbeat.{$id}", [
+            'seen_at' => Carbon::now(),
+            'uptime' => $heartbeat['up'] ?? null,
+            'firmware' => $heartbeat['fw'] ?? null,
+            'queue_depth' => $heartbeat['q'] ?? null,
+        ]);
+        $response = ['accepted_proto_version' => self::ACCEPTED_PROTO_VERSION];
+        // set with Cache::put("upload_log.<imei>", true) to get the log ring of a device
+        if (Cache::pull("upload_log.{$id}", false)) {
+            $response['upload_log'] = true;
+        }
+        // set with Cache::put("identify.<imei>", <minutes>) to flash the LED of a device on site
+        $minutes = Cache::pull("identify.{$id}");
+        if ($minutes !== null) {
+            $response['identify'] = (int) $minutes;
+        }
+        // set with Cache::put("run_at.<imei>", "AT+CSQ") for a remote diagnosis, the answer is posted to /at
+        $command = Cache::pull("run_at.{$id}");
+        if ($command !== null) {
+            $response['run_at'] = $command;
+        }
+        // set with Cache::put("load.<imei>", "off") to switch the load output of the charger (off, auto or on)
+        $load = Cache::pull("load.{$id}");
+        if ($load !== null) {
+            $response['load'] = $load;
+        }
+        // set with Cache::put("at_quirks.<imei>", "A011B07=2,A110=0") to replace the modem quirk table of a device
+        $quirks = Cache::pull("at_quirks.{$id}");
+        if ($quirks !== null) {
+            $response['at_quirks'] = $quirks;
+        }
+        return response()->json($response);
+    }
+
+    // {"command":"AT+CSQ","result":"OK","lines":["+CSQ: 21,3"]}, the answer to a run_at of the heartbeat response
+    public function at(Request $request)
+    {
+        $result = $request->json()->all();
+        Log::info("AT command result received ", ['result' => $result]);
+        Cache::put('at_result', array_merge($result, ['received_at' => Carbon::now()]));
+        return response()->json(['accepted_proto_version' => self::ACCEPTED_PROTO_VERSION]);
+    }
+
+    // encoded defmt frames with a one byte length prefix each, decoded with the ELF of the firmware
+    public function log(Request $request)
+    {
+        $chunk = $this->content($request);
+        if ($chunk === null) {
+            return response('envelope not opened', 400);
+        }
+        Log::info("Log chunk received ", ['size' => strlen($chunk)]);
+        $length = pack('V', strlen($chunk));
+        Storage::append('log/' . Carbon::now()->format('Y-m-d') . '.bin', $length . $chunk, '');
+        return response()->json(['accepted_proto_version' => self::ACCEPTED_PROTO_VERSION]);
+    }
+
+    // the signed manifest of `just sign_ota_nrf`, copied to firmware/manifest.json with the image
+    public function firmware(Request $request)
+    {
+        if (!Storage::exists('firmware/manifest.json')) {
+            return response('no firmware', 404);
+        }
+        return response(Storage::get('firmware/manifest.json'), 200)->header('Content-Type', 'application/json');
+    }
+
+    // the image of the manifest, the firmware checks its hash and signature
+    public function firmwareImage(Request $request, int $version)
+    {
+        $path = "firmware/{$version}.bin";
+        if (!Storage::exists($path)) {
+            return response('no firmware', 404);
+        }
+        Log::info("Firmware image requested ", ['version' => $version]);
+        return response(Storage::get($path), 200)->header('Content-Type', 'application/octet-stream');
     }
 }
@@ -0,0 +1,29 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('solar_readings', function (Blueprint $table) {
+            // recorded_at is the last known time of the device after a reset, before its first sync
+            $table->boolean('time_approximate')->default(false)->after('recorded_at');
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('solar_readings', function (Blueprint $table) {
+            $table->dropColumn('time_approximate');
+        });
+    }
+};
@@ -0,0 +1,44 @@
+<?php
+
+use Illuminate\Database\Migrations\Migration;
+use Illuminate\Database\Schema\Blueprint;
+use Illuminate\Support\Facades\Schema;
+
+return new class extends Migration
+{
+    // the averaged fields of the Reading with a spread (readings.proto), in the unit of their column
+    const SPREAD_COLUMNS = ['battery_voltage', 'battery_current', 'panel_voltage', 'panel_power', 'load_current'];
+
+    /**
+     * Run the migrations.
+     */
+    public function up(): void
+    {
+        Schema::table('solar_readings', function (Blueprint $table) {
+            // null for a single reading, the stddev also without the variance tracking
+            foreach (self::SPREAD_COLUMNS as $column) {
+                $table->float("{$column}_min")->nullable();
+                $table->float("{$column}_max")->nullable();
+                $table->float("{$column}_stddev")->nullable();
+            }
+            // null without the sensor
+            $table->float('battery_temperature')->nullable();
+            $table->float('auxiliary_1')->nullable();
+            $table->float('auxiliary_2')->nullable();
+            $table->unsignedInteger('pulse_count')->nullable();
+        });
+    }
+
+    /**
+     * Reverse the migrations.
+     */
+    public function down(): void
+    {
+        Schema::table('solar_readings', function (Blueprint $table) {
+            foreach (self::SPREAD_COLUMNS as $column) {
+                $table->dropColumn(["{$column}_min", "{$column}_max", "{$column}_stddev"]);
+            }
+            $table->dropColumn(['battery_temperature', 'auxiliary_1', 'auxiliary_2', 'pulse_count']);
+        });
+    }
+};
@@ -29,9 +29,20 @@
     return response('solar api v2', 200)->header('Content-Type', 'text/plain');
 });
 
+// connectivity test of the firmware at installation time, answers HEAD as well
+Route::get('/v2/healthz', function (Request $request) {
+    return response('ok', 200)->header('Content-Type', 'text/plain');
+});
+
 Route::middleware([ApiToken::class])->prefix('/v2/solar')->group(function () {
     Route::post('/reading', [SolarReadingController::class, 'reading'])->middleware([StripToMinimalHeaders::class]);
     Route::post('/event', [SolarReadingController::class, 'event'])->middleware([StripToMinimalHeaders::class]);
+    Route::post('/metrics', [SolarReadingController::class, 'metrics'])->middleware([StripToMinimalHeaders::class]);
+    Route::post('/heartbeat', [SolarReadingController::class, 'heartbeat'])->middleware([StripToMinimalHeaders::class]);
+    Route::post('/log', [SolarReadingController::class, 'log'])->middleware([StripToMinimalHeaders::class]);
+    Route::post('/at', [SolarReadingController::class, 'at'])->middleware([StripToMinimalHeaders::class]);
+    Route::get('/firmware', [SolarReadingController::class, 'firmware'])->middleware([StripToMinimalHeaders::class]);
+    Route::get('/firmware/{version}', [SolarReadingController::class, 'firmwareImage'])->whereNumber('version')->middleware([StripToMinimalHeaders::class]);
 });
 
 